@@ -0,0 +1,57 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::regex::Regex;
+use std::time::Instant;
+
+const ROUTES: usize = 200;
+const ITERATIONS: usize = 20_000;
+
+fn request(uri: &str) -> SyncRequest {
+    let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+    SyncRequest::new(parts, Vec::new())
+}
+
+fn report(name: &str, started: Instant) {
+    let elapsed = started.elapsed();
+    let nanos = elapsed.as_secs() as f64 * 1e9 + f64::from(elapsed.subsec_nanos());
+    println!("{:<32} {:>10.0} ns/dispatch", name, nanos / ITERATIONS as f64);
+}
+
+/// Compare the dispatch of the last of `ROUTES` static routes, plus a regex route, against a linear scan of the same
+/// regular expressions, which is how delegates used to be matched
+fn main() {
+    let mut controller = BasicController::new(());
+    let mut patterns = Vec::new();
+
+    for i in 0..ROUTES {
+        let pattern = format!("^/resources/{}/items$", i);
+        controller.add(Method::GET, pattern.as_str(), |_, _, res| { res.status(StatusCode::OK); });
+        patterns.push(Regex::new(&pattern).unwrap());
+    }
+
+    controller.add(Method::GET, "^/users/(\\d+)$", |_, _, res| { res.status(StatusCode::OK); });
+    patterns.push(Regex::new("^/users/(\\d+)$").unwrap());
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    for uri in &[format!("/resources/{}/items", ROUTES - 1), "/users/42".to_string()] {
+        let req = request(uri);
+
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            let path = req.uri().path();
+            assert!(patterns.iter().any(|p| p.is_match(path)));
+        }
+        report(&format!("linear scan {}", uri), started);
+
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            let mut res = SyncResponse::new();
+            router.dispatch(&req, &mut res);
+            assert_eq!(res.get_status(), StatusCode::OK);
+        }
+        report(&format!("router {}", uri), started);
+    }
+}
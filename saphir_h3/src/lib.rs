@@ -0,0 +1,284 @@
+//! # saphir_h3
+//!
+//! The HTTP/3 listener of saphir, accepting QUIC connections with `quinn` and serving their requests with `h3`. Requests
+//! are handed, with their whole body, to a blocking handler which saphir plugs its router into, see the `http3` feature of
+//! saphir. This crate is experimental, like the crates it is built upon.
+
+#![deny(missing_docs)]
+
+pub use http;
+
+use bytes::Buf;
+use bytes::Bytes;
+use http::Request;
+use http::Response;
+use log::debug;
+use log::warn;
+use quinn::crypto::rustls::HandshakeData;
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::pki_types::PrivateKeyDer;
+use rustls::server::NoServerSessionStorage;
+use rustls::server::ProducesTickets;
+use rustls::server::ServerSessionMemoryCache;
+use rustls::crypto::GetRandomFailed;
+use rustls::crypto::ring::Ticketer;
+use rustls::TicketRotator;
+use std::io;
+use std::net::SocketAddr;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Waker;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// What is known about the QUIC connection a request was received over
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// The address of the client
+    pub peer_addr: SocketAddr,
+    /// The server name the client asked for with SNI
+    pub server_name: Option<String>,
+    /// Whether the request was received as 0-RTT early data, before the handshake completed, which an attacker may replay
+    pub early_data: bool,
+}
+
+/// How clients resume their TLS sessions, sparing a full handshake to the connections following their first one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resumption {
+    /// Every connection goes through a full handshake
+    Disabled,
+    /// The server keeps up to `capacity` sessions in memory, which clients resume with the id they were given
+    Cache {
+        /// The most sessions kept, the least recently used ones being forgotten first
+        capacity: usize,
+    },
+    /// The server keeps no session, clients are given tickets holding their session, encrypted with a key of the server
+    Tickets {
+        /// How long a key encrypts the new tickets before being replaced, tickets being accepted for twice as long
+        rotation: Duration,
+    },
+}
+
+/// The TLS settings of a `Listener`, see `Listener::bind_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsSettings {
+    /// How clients resume their sessions, a cache of 256 sessions by default
+    pub resumption: Resumption,
+    /// Whether clients resuming a session from the cache may send requests as 0-RTT early data, along with their handshake,
+    /// disabled by default. Such requests are flagged by `ConnectionInfo::early_data`, since they can be replayed. Sessions
+    /// resumed with a ticket never accept early data: a cached session can only be resumed once, a ticket any number of
+    /// times.
+    pub early_data: bool,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        TlsSettings {
+            resumption: Resumption::Cache { capacity: 256 },
+            early_data: false,
+        }
+    }
+}
+
+/// A ticket key of its own, replaced as a whole by the `TicketRotator` of the listener
+#[derive(Debug)]
+struct TicketKey(Arc<dyn ProducesTickets>);
+
+impl ProducesTickets for TicketKey {
+    fn enabled(&self) -> bool {
+        self.0.enabled()
+    }
+
+    fn lifetime(&self) -> u32 {
+        self.0.lifetime()
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        self.0.encrypt(plain)
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        self.0.decrypt(cipher)
+    }
+}
+
+fn new_ticket_key() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
+    Ticketer::new().map(|ticketer| Box::new(TicketKey(ticketer)) as Box<dyn ProducesTickets>).map_err(|_| GetRandomFailed)
+}
+
+type Handler = dyn Fn(Request<Vec<u8>>, &ConnectionInfo) -> Response<Vec<u8>> + Send + Sync;
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// A QUIC endpoint serving HTTP/3 requests
+pub struct Listener {
+    runtime: Runtime,
+    endpoint: quinn::Endpoint,
+    early_data: bool,
+}
+
+impl Listener {
+    /// Listen on the UDP `addr`, presenting the PEM encoded certificate chain and private key to clients
+    pub fn bind(addr: SocketAddr, cert_chain_pem: &[u8], private_key_pem: &[u8]) -> io::Result<Self> {
+        Listener::bind_with(addr, cert_chain_pem, private_key_pem, TlsSettings::default())
+    }
+
+    /// Listen on the UDP `addr` like `bind`, resuming the TLS sessions and accepting early data according to `settings`
+    pub fn bind_with(addr: SocketAddr, cert_chain_pem: &[u8], private_key_pem: &[u8], settings: TlsSettings) -> io::Result<Self> {
+        let cert_chain = CertificateDer::pem_slice_iter(cert_chain_pem).collect::<Result<Vec<_>, _>>().map_err(invalid_data)?;
+        let private_key = PrivateKeyDer::from_pem_slice(private_key_pem).map_err(invalid_data)?;
+
+        let mut tls = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(invalid_data)?
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, private_key)
+            .map_err(invalid_data)?;
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+        match settings.resumption {
+            Resumption::Disabled => {
+                tls.session_storage = Arc::new(NoServerSessionStorage {});
+                tls.send_tls13_tickets = 0;
+            }
+            Resumption::Cache { capacity } => tls.session_storage = ServerSessionMemoryCache::new(capacity),
+            Resumption::Tickets { rotation } => {
+                let rotation = u32::try_from(rotation.as_secs()).unwrap_or(u32::MAX).max(1);
+                tls.ticketer = Arc::new(TicketRotator::new(rotation, new_ticket_key).map_err(invalid_data)?);
+            }
+        }
+        // QUIC only allows no early data at all, or as much as the transport allows
+        if settings.early_data && matches!(settings.resumption, Resumption::Cache { .. }) {
+            tls.max_early_data_size = u32::MAX;
+        }
+        let early_data = tls.max_early_data_size > 0;
+        let crypto = QuicServerConfig::try_from(tls).map_err(invalid_data)?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .thread_name("saphir-h3")
+            .enable_all()
+            .build()?;
+        let endpoint = {
+            let _guard = runtime.enter();
+            quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)?
+        };
+
+        Ok(Listener { runtime, endpoint, early_data })
+    }
+
+    /// Returns the address the listener is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Returns a handle closing the listener, which makes `serve` return
+    pub fn closer(&self) -> Closer {
+        Closer(self.endpoint.clone())
+    }
+
+    /// Serve the requests of every accepted connection with `handler` until the listener is closed. The handler is invoked
+    /// on a thread where blocking is allowed.
+    pub fn serve<H>(self, handler: H)
+        where H: 'static + Fn(Request<Vec<u8>>, &ConnectionInfo) -> Response<Vec<u8>> + Send + Sync {
+        let handler: Arc<Handler> = Arc::new(handler);
+        let endpoint = self.endpoint.clone();
+        let early_data = self.early_data;
+
+        self.runtime.block_on(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(incoming, early_data, handler).await {
+                        debug!("An HTTP/3 connection failed: {}", e);
+                    }
+                });
+            }
+
+            endpoint.wait_idle().await;
+        });
+    }
+}
+
+/// Closes a `Listener`, see `Listener::closer`
+#[derive(Clone)]
+pub struct Closer(quinn::Endpoint);
+
+impl Closer {
+    /// Close the listener and every connection it accepted
+    pub fn close(&self) {
+        self.0.close(0u32.into(), b"shutdown");
+    }
+}
+
+async fn serve_connection(incoming: quinn::Incoming, early_data: bool, handler: Arc<Handler>)
+                          -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // The requests of a connection accepting early data are served before its handshake completes, which a replayed
+    // handshake never does
+    let (connection, mut handshake) = if early_data {
+        match incoming.accept()?.into_0rtt() {
+            Ok((connection, accepted)) => (connection, Some(accepted)),
+            Err(connecting) => (connecting.await?, None),
+        }
+    } else {
+        (incoming.await?, None)
+    };
+
+    let info = ConnectionInfo {
+        peer_addr: connection.remote_address(),
+        server_name: connection.handshake_data()
+            .and_then(|data| data.downcast::<HandshakeData>().ok())
+            .and_then(|data| data.server_name),
+        early_data: false,
+    };
+
+    let mut h3_connection = h3::server::builder().build::<_, Bytes>(h3_quinn::Connection::new(connection)).await?;
+
+    while let Some(resolver) = h3_connection.accept().await? {
+        let handler = handler.clone();
+        let early_data = handshake.as_mut().is_some_and(|accepted| Pin::new(accepted).poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        if !early_data {
+            handshake = None;
+        }
+        let info = ConnectionInfo { early_data, ..info.clone() };
+        tokio::spawn(async move {
+            if let Err(e) = serve_request(resolver, handler, info).await {
+                warn!("Unable to answer an HTTP/3 request: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+async fn serve_request(resolver: h3::server::RequestResolver<h3_quinn::Connection, Bytes>, handler: Arc<Handler>, info: ConnectionInfo)
+                       -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (request, mut stream) = resolver.resolve_request().await?;
+
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await? {
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            body.extend_from_slice(bytes);
+            let len = bytes.len();
+            chunk.advance(len);
+        }
+    }
+
+    let (parts, _) = request.into_parts();
+    let request = Request::from_parts(parts, body);
+    let response = tokio::task::spawn_blocking(move || handler(request, &info)).await?;
+
+    let (parts, body) = response.into_parts();
+    stream.send_response(Response::from_parts(parts, ())).await?;
+    if !body.is_empty() {
+        stream.send_data(Bytes::from(body)).await?;
+    }
+    stream.finish().await?;
+
+    Ok(())
+}
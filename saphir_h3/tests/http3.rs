@@ -0,0 +1,151 @@
+use bytes::Buf;
+use bytes::Bytes;
+use quinn::crypto::rustls::QuicClientConfig;
+use rustls::pki_types::CertificateDer;
+use saphir::*;
+use saphir::test::TestClient;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+fn certificate() -> (CertificateDer<'static>, String, String) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    (certified.cert.der().clone(), certified.cert.pem(), certified.signing_key.serialize_pem())
+}
+
+/// The configuration of a client trusting `root`, which resumes its sessions and sends early data when `early_data` is set
+fn client_config(root: CertificateDer<'static>, early_data: bool) -> quinn::ClientConfig {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(root).unwrap();
+    let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    tls.enable_early_data = early_data;
+
+    quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls).unwrap()))
+}
+
+/// Send a request over an HTTP/3 `connection`, returning the status, the headers and the body of the response
+async fn exchange(connection: quinn::Connection, request: saphir_h3::http::Request<()>, body: &'static str)
+                  -> (u16, saphir_h3::http::HeaderMap, Vec<u8>) {
+    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(connection)).await.unwrap();
+    tokio::spawn(async move { std::future::poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let mut stream = send_request.send_request(request).await.unwrap();
+    if !body.is_empty() {
+        stream.send_data(Bytes::from(body)).await.unwrap();
+    }
+    stream.finish().await.unwrap();
+
+    let response = stream.recv_response().await.unwrap();
+    let mut received = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+        while chunk.has_remaining() {
+            let len = chunk.chunk().len();
+            received.extend_from_slice(chunk.chunk());
+            chunk.advance(len);
+        }
+    }
+
+    (response.status().as_u16(), response.headers().clone(), received)
+}
+
+/// Send a request over HTTP/3, returning the status, the headers and the body of the response
+async fn send(addr: SocketAddr, root: CertificateDer<'static>, request: saphir_h3::http::Request<()>, body: &'static str)
+              -> (u16, saphir_h3::http::HeaderMap, Vec<u8>) {
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(client_config(root, false));
+    let connection = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+    let response = exchange(connection, request, body).await;
+    endpoint.close(0u32.into(), b"done");
+    response
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http3_listener() {
+    let (root, cert_pem, key_pem) = certificate();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        let server_name = req.tls_info().and_then(|tls| tls.server_name).unwrap_or_default();
+        res.status(StatusCode::CREATED)
+            .header("x-http3", req.is_http3().to_string())
+            .header("x-server-name", server_name)
+            .body(format!("{} {}", req.uri().path(), String::from_utf8_lossy(req.body())));
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).default_header("x-served-by", "saphir").build();
+    let http3 = server.spawn_http3(&Http3Config::new("127.0.0.1:0", cert_pem, key_pem)).unwrap();
+    let addr = http3.addr();
+
+    let request = saphir_h3::http::Request::post("https://localhost/echo").body(()).unwrap();
+    let (status, headers, body) = send(addr, root.clone(), request, "hello").await;
+    assert_eq!(status, 201);
+    assert_eq!(body, b"/echo hello".to_vec());
+    assert_eq!(headers["x-http3"], "true");
+    assert_eq!(headers["x-server-name"], "localhost");
+    assert_eq!(headers["x-served-by"], "saphir");
+
+    let request = saphir_h3::http::Request::get("https://localhost/missing").body(()).unwrap();
+    let (status, headers, _) = send(addr, root, request, "").await;
+    assert_eq!(status, 405);
+    assert_eq!(headers["content-type"], PROBLEM_CONTENT_TYPE);
+
+    // Responses received over TCP advertise the HTTP/3 listener
+    let client = TestClient::new(server);
+    let res = client.post("/echo").send();
+    assert_eq!(res.headers_map()[header::ALT_SVC], format!("h3=\":{}\"; ma=86400", addr.port()).as_str());
+    assert_eq!(res.headers_map()["x-http3"], "false");
+
+    tokio::task::spawn_blocking(move || http3.shutdown()).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn early_data() {
+    let (root, cert_pem, key_pem) = certificate();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/orders$", |_, req, res| { res.status(StatusCode::OK).body(req.is_early_data().to_string()); });
+    controller.add(Method::POST, "^/orders$", |_, _, res| { res.status(StatusCode::CREATED); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build();
+    let config = Http3Config::new("127.0.0.1:0", cert_pem, key_pem).session_cache(16).early_data();
+    let http3 = server.spawn_http3(&config).unwrap();
+    let addr = http3.addr();
+
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(client_config(root, true));
+
+    // The first connection gets a ticket, there is no session to resume yet
+    let connection = match endpoint.connect(addr, "localhost").unwrap().into_0rtt() {
+        Ok(_) => panic!("no session to resume yet"),
+        Err(connecting) => connecting.await.unwrap(),
+    };
+    let request = saphir_h3::http::Request::get("https://localhost/orders").body(()).unwrap();
+    assert_eq!(exchange(connection, request, "").await.2, b"false".to_vec());
+
+    // The requests of the next connection are sent along with its handshake
+    let connection = match endpoint.connect(addr, "localhost").unwrap().into_0rtt() {
+        Ok((connection, _)) => connection,
+        Err(_) => panic!("a resumed session"),
+    };
+    let request = saphir_h3::http::Request::post("https://localhost/orders").body(()).unwrap();
+    let (status, _, _) = exchange(connection, request, "").await;
+    assert_eq!(status, 425);
+
+    endpoint.close(0u32.into(), b"done");
+    tokio::task::spawn_blocking(move || http3.shutdown()).await.unwrap();
+}
+
+#[test]
+fn invalid_certificates() {
+    let config = Http3Config::new("127.0.0.1:0", "not a certificate", "not a key");
+    assert!(Server::builder().build().spawn_http3(&config).is_err());
+}
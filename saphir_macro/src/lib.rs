@@ -0,0 +1,175 @@
+#![deny(missing_docs)]
+
+//! # Saphir macro
+//!
+//! Attribute macros generating the routing code of saphir controllers. Route definitions are checked at compile time and
+//! live next to the function handling them.
+//!
+//! ```rust,ignore
+//! #[macro_use]
+//! extern crate saphir;
+//!
+//! use saphir::*;
+//!
+//! struct UserController {
+//!     users: Vec<String>,
+//! }
+//!
+//! #[controller(prefix = "/users")]
+//! impl UserController {
+//!     #[get("/")]
+//!     fn list(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+//!         res.status(StatusCode::OK).json(&self.users);
+//!     }
+//!
+//!     #[get("/<id>")]
+//!     fn get(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+//!         res.status(StatusCode::OK);
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let mut router = Router::new();
+//!     UserController { users: vec![] }.register(&mut router);
+//! }
+//! ```
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+#[macro_use]
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::ImplItem;
+use syn::ItemImpl;
+use syn::LitStr;
+
+const METHODS: &[(&str, &str)] = &[
+    ("get", "GET"),
+    ("post", "POST"),
+    ("put", "PUT"),
+    ("patch", "PATCH"),
+    ("delete", "DELETE"),
+    ("head", "HEAD"),
+    ("options", "OPTIONS"),
+];
+
+/// Convert a route path like `/<id>/posts` to an anchored regex where every `<name>` segment becomes a named capture group
+fn route_to_regex(prefix: &str, path: &str) -> String {
+    let full = format!("{}/{}", prefix.trim_end_matches('/'), path.trim_start_matches('/'));
+    let full = if full.len() > 1 { full.trim_end_matches('/').to_string() } else { full };
+    let mut regex = String::from("^");
+
+    for (i, segment) in full.split('/').enumerate() {
+        if i > 0 {
+            regex.push('/');
+        }
+
+        if segment.starts_with('<') && segment.ends_with('>') && segment.len() > 2 {
+            regex.push_str(&format!("(?P<{}>[^/]+)", &segment[1..segment.len() - 1]));
+        } else {
+            regex.push_str(&escape(segment));
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+fn escape(segment: &str) -> String {
+    let mut escaped = String::new();
+
+    for c in segment.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Generate the routing code of a controller from the route attributes of the methods of an `impl` block
+///
+/// Every method annotated with `#[get("/path")]`, `#[post("/path")]`, `#[put("/path")]`, `#[patch("/path")]`,
+/// `#[delete("/path")]`, `#[head("/path")]` or `#[options("/path")]` must have the delegate signature
+/// `fn(&self, &SyncRequest, &mut SyncResponse)`. Path segments written as `<name>` match any non-empty segment and are
+/// exposed as named capture groups of the route regex.
+///
+/// The macro generates two methods on the type:
+///  - `into_controller(self) -> BasicController<Self>` registering every annotated method on a new controller
+///  - `register(self, router: &mut Router)` adding the controller to a router under the `prefix` of the controller
+#[proc_macro_attribute]
+pub fn controller(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut prefix = String::new();
+    let attr_parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("prefix") {
+            prefix = meta.value()?.parse::<LitStr>()?.value();
+            Ok(())
+        } else {
+            Err(meta.error("unsupported controller attribute, expected `prefix`"))
+        }
+    });
+    parse_macro_input!(attr with attr_parser);
+
+    let mut item_impl = parse_macro_input!(item as ItemImpl);
+    let self_ty = item_impl.self_ty.clone();
+    let mut registrations = Vec::new();
+
+    for impl_item in item_impl.items.iter_mut() {
+        let method = match *impl_item {
+            ImplItem::Fn(ref mut method) => method,
+            _ => continue,
+        };
+
+        let mut retained_attrs = Vec::new();
+
+        for attr in method.attrs.drain(..) {
+            let http_method = METHODS.iter().find(|&&(name, _)| attr.path().is_ident(name)).map(|&(_, m)| m);
+
+            match http_method {
+                Some(http_method) => {
+                    let path = match attr.parse_args::<LitStr>() {
+                        Ok(path) => path.value(),
+                        Err(e) => return e.to_compile_error().into(),
+                    };
+
+                    let regex = route_to_regex(&prefix, &path);
+                    let method_ident = syn::Ident::new(http_method, Span::call_site());
+                    let fn_ident = method.sig.ident.clone();
+                    registrations.push(quote! {
+                        controller.add(::saphir::Method::#method_ident, #regex, #self_ty::#fn_ident);
+                    });
+                }
+                None => retained_attrs.push(attr),
+            }
+        }
+
+        method.attrs = retained_attrs;
+    }
+
+    let route_prefix = format!("^{}", prefix.trim_end_matches('/'));
+
+    let expanded = quote! {
+        #item_impl
+
+        impl #self_ty {
+            /// Create a controller handling every route defined on this type
+            pub fn into_controller(self) -> ::saphir::BasicController<#self_ty> {
+                let mut controller = ::saphir::BasicController::new(self);
+                #(#registrations)*
+                controller
+            }
+
+            /// Add the controller handling every route defined on this type to `router`
+            pub fn register(self, router: &mut ::saphir::Router) {
+                router.add(#route_prefix, self.into_controller());
+            }
+        }
+    };
+
+    expanded.into()
+}
@@ -0,0 +1,318 @@
+use http::*;
+use audit::AuditEvent;
+use config_reload::ReloadTrigger;
+use controller::Controller;
+use controller::RequestGuardCollection;
+use controller::RouteInfo;
+use debug::RouteReport;
+use debug::route_reports;
+use drain::DrainReport;
+use drain::InFlight;
+use logging::*;
+use maintenance::MaintenanceMode;
+use metrics::Metrics;
+use profiling::Profiler;
+use router::Router;
+use utils::RequestContinuation;
+use log::Level;
+use log::LevelFilter;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Whether the server is draining its connections, switched by the `AdminController`
+#[derive(Clone, Default)]
+pub(crate) struct DrainSwitch(Arc<AtomicBool>);
+
+impl DrainSwitch {
+    pub(crate) fn is_draining(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Marks the requests received while the server is draining its connections
+#[derive(Debug, Clone, Copy)]
+struct Draining;
+
+impl SyncRequest {
+    /// Returns true when the request was received while the server drains its connections, see `AdminController`
+    pub fn is_draining(&self) -> bool {
+        self.extensions().get::<Draining>().is_some()
+    }
+}
+
+/// Mark `req` as received while draining, for `close_drained` to close its connection once answered
+pub(crate) fn mark_draining(req: &mut SyncRequest) {
+    req.extensions_mut().insert(Draining);
+}
+
+/// Tell the client of a request received while draining to close its connection, HTTP/2 connections being closed by the
+/// server once it stops
+pub(crate) fn close_drained(req: &SyncRequest, res: &mut SyncResponse) {
+    if req.is_draining() && req.version() != Version::HTTP_2 {
+        res.headers_map_mut().insert(header::CONNECTION, header::HeaderValue::from_static("close"));
+    }
+}
+
+/// What the server hands to the `AdminController` once built
+#[derive(Default)]
+struct Controls {
+    prefix: String,
+    routes: Vec<RouteReport>,
+    maintenance: Option<MaintenanceMode>,
+    in_flight: Option<Arc<InFlight>>,
+}
+
+/// A controller exposing runtime controls of the server to its operators: the maximum log level, the maintenance mode, the
+/// draining of the connections, the metrics, the profile of the routes, the routes and the reload of the configuration
+///
+/// The controller is opt-in, it is registered with `ServerBuilder::admin_endpoint` under a path prefix and answers, as JSON:
+///
+/// * `GET <prefix>` with the log level, and whether the server is in maintenance and draining
+/// * `GET <prefix>/log-level` with the maximum log level, which `PUT <prefix>/log-level` sets to the level of its body,
+///   like `debug`
+/// * `GET <prefix>/maintenance` with whether the server is in maintenance, `PUT` enables the `MaintenanceMode` of the
+///   server and `DELETE` disables it. The controller keeps answering during maintenance.
+/// * `GET <prefix>/drain` with whether the server is draining its connections, `PUT` starts draining and `DELETE` stops.
+///   While draining, every response closes its connection and the readiness probe of the `HealthController` fails, so
+///   that load balancers stop sending traffic before the server is stopped. The progress of the draining is answered
+///   along, see `DrainReport`: the number of requests and streams in flight, and the age of the oldest request.
+/// * `GET <prefix>/metrics` with the metrics set with `metrics`, in the Prometheus text format
+/// * `GET <prefix>/profile` with where the requests of each route spend their time, hot spots first, see `Profiler::report`,
+///   which `DELETE` resets
+/// * `GET <prefix>/routes` with the route table
+/// * `POST <prefix>/reload` triggers the reload of the configuration set with `config_reload`
+///
+/// Since it controls the whole server, every request is validated by the guards the controller is created with before being
+/// answered, and every change is logged and recorded in the audit log of the server, see `AuditLog`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+/// let metrics = Metrics::new();
+///
+/// let server = Server::builder()
+///     .router(Router::new())
+///     .maintenance(MaintenanceMode::new())
+///     .admin_endpoint("/_admin", AdminController::new(loopback).metrics(metrics))
+///     .build();
+/// ```
+pub struct AdminController {
+    guards: RequestGuardCollection,
+    metrics: Option<Metrics>,
+    profiler: Option<Profiler>,
+    reload: Option<ReloadTrigger>,
+    draining: DrainSwitch,
+    controls: Arc<Mutex<Controls>>,
+}
+
+impl AdminController {
+    /// Create a controller answering the requests validated by `guards`
+    pub fn new<G: Into<RequestGuardCollection>>(guards: G) -> Self {
+        AdminController {
+            guards: guards.into(),
+            metrics: None,
+            profiler: None,
+            reload: None,
+            draining: DrainSwitch::default(),
+            controls: Arc::new(Mutex::new(Controls::default())),
+        }
+    }
+
+    /// Expose `metrics` under `<prefix>/metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Expose the report of `profiler` under `<prefix>/profile`, see `ServerBuilder::profiling`
+    pub fn profiler(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Reload the configuration through `trigger` on `POST <prefix>/reload`, see `ConfigReloader::trigger`
+    pub fn config_reload(mut self, trigger: ReloadTrigger) -> Self {
+        self.reload = Some(trigger);
+        self
+    }
+
+    /// Register the controller under `prefix` in `router`, controlling `maintenance` and listing the routes of the router
+    /// once it is part of it. Returns the switch telling the server whether it drains its connections.
+    pub(crate) fn register(self, prefix: &str, router: &mut Router, maintenance: Option<MaintenanceMode>, in_flight: Arc<InFlight>) -> DrainSwitch {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let route = format!("^{}(/|$)", ::regex::escape(&prefix));
+        let draining = self.draining.clone();
+        let controls = self.controls.clone();
+        router.add(route.as_str(), self);
+
+        let mut controls = controls.lock().unwrap_or_else(|e| e.into_inner());
+        controls.prefix = prefix;
+        controls.routes = route_reports(router);
+        // The controller keeps answering during maintenance, to be able to end it
+        controls.maintenance = maintenance.and_then(|maintenance| maintenance.allow_path(route.as_str()).ok());
+        controls.in_flight = Some(in_flight);
+
+        draining
+    }
+
+    /// Log the change `action` made by `req`, and record it in the audit log
+    fn record(req: &SyncRequest, action: &str, value: &dyn fmt::Display) {
+        let actor = req.client_ip().map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_string());
+        log_event(Level::Warn, SERVER_LOG_TARGET, "admin action", format_args!("{} set {} to {}", actor, action, value),
+                  &[("actor", &actor), ("action", &action), ("value", value)]);
+        req.audit(AuditEvent::new(&actor, &format!("admin.{}", action), req.uri().path()).detail("value", value));
+    }
+
+    fn log_level(req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() == Method::PUT {
+            let level = String::from_utf8_lossy(req.body());
+            match level.trim().trim_matches('"').parse::<LevelFilter>() {
+                Ok(level) => {
+                    ::log::set_max_level(level);
+                    Self::record(req, "log_level", &level);
+                }
+                Err(_) => {
+                    res.status(StatusCode::BAD_REQUEST).body(format!("invalid log level {}", level.trim()));
+                    return;
+                }
+            }
+        }
+
+        res.status(StatusCode::OK).json(&json!({ "level": ::log::max_level().to_string().to_lowercase() }));
+    }
+
+    fn maintenance(req: &SyncRequest, res: &mut SyncResponse, maintenance: Option<&MaintenanceMode>) {
+        let maintenance = match maintenance {
+            Some(maintenance) => maintenance,
+            None => {
+                res.status(StatusCode::NOT_FOUND);
+                return;
+            }
+        };
+
+        match *req.method() {
+            Method::PUT => maintenance.enable(),
+            Method::DELETE => maintenance.disable(),
+            _ => {}
+        }
+
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            Self::record(req, "maintenance", &maintenance.is_enabled());
+        }
+        res.status(StatusCode::OK).json(&json!({ "enabled": maintenance.is_enabled() }));
+    }
+
+    fn drain(&self, req: &SyncRequest, res: &mut SyncResponse, report: Option<DrainReport>) {
+        match *req.method() {
+            Method::PUT => self.draining.0.store(true, Ordering::SeqCst),
+            Method::DELETE => self.draining.0.store(false, Ordering::SeqCst),
+            _ => {}
+        }
+
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            Self::record(req, "draining", &self.draining.is_draining());
+        }
+        let millis = |duration: Option<::std::time::Duration>| duration.map(|duration| duration.as_millis() as u64);
+        res.status(StatusCode::OK).json(&json!({
+            "draining": self.draining.is_draining(),
+            "shutting_down": report.as_ref().is_some_and(|report| report.is_shutting_down()),
+            "shutdown_age_ms": report.as_ref().and_then(|report| millis(report.shutdown_age())),
+            "in_flight": report.as_ref().map(|report| report.in_flight()),
+            "streams": report.as_ref().map(|report| report.streams()),
+            "oldest_request_age_ms": report.as_ref().and_then(|report| millis(report.oldest_request_age())),
+        }));
+    }
+}
+
+/// Returns the methods `resource` of an `AdminController` answers to
+fn allowed_methods(resource: &str) -> &'static [Method] {
+    match resource {
+        "/log-level" => &[Method::GET, Method::HEAD, Method::PUT],
+        "/maintenance" | "/drain" => &[Method::GET, Method::HEAD, Method::PUT, Method::DELETE],
+        "/profile" => &[Method::GET, Method::HEAD, Method::DELETE],
+        "/reload" => &[Method::POST],
+        _ => &[Method::GET, Method::HEAD],
+    }
+}
+
+impl Controller for AdminController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        for guard in &self.guards {
+            if let RequestContinuation::None = guard.validate(req, res) {
+                return;
+            }
+        }
+
+        let controls = self.controls.lock().unwrap_or_else(|e| e.into_inner());
+        let resource = req.uri().path().get(controls.prefix.len()..).unwrap_or("").trim_end_matches('/');
+        res.header(header::CACHE_CONTROL, "no-store");
+
+        let allowed = allowed_methods(resource);
+        if !allowed.contains(req.method()) {
+            let allow: Vec<&str> = allowed.iter().map(|method| method.as_str()).collect();
+            res.status(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, allow.join(", "));
+            return;
+        }
+
+        match resource {
+            "" => {
+                res.status(StatusCode::OK).json(&json!({
+                    "log_level": ::log::max_level().to_string().to_lowercase(),
+                    "maintenance": controls.maintenance.as_ref().map(|maintenance| maintenance.is_enabled()),
+                    "draining": self.draining.is_draining(),
+                }));
+            }
+            "/log-level" => Self::log_level(req, res),
+            "/maintenance" => Self::maintenance(req, res, controls.maintenance.as_ref()),
+            "/drain" => self.drain(req, res, controls.in_flight.as_ref().map(|in_flight| in_flight.report())),
+            "/metrics" => match self.metrics {
+                Some(ref metrics) => {
+                    res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8").body(metrics.render());
+                }
+                None => {
+                    res.status(StatusCode::NOT_FOUND);
+                }
+            },
+            "/profile" => match self.profiler {
+                Some(ref profiler) => {
+                    if *req.method() == Method::DELETE {
+                        profiler.reset();
+                        Self::record(req, "profile", &"reset");
+                    }
+                    res.status(StatusCode::OK).json(&profiler.report());
+                }
+                None => {
+                    res.status(StatusCode::NOT_FOUND);
+                }
+            },
+            "/routes" => {
+                res.status(StatusCode::OK).json(&controls.routes);
+            }
+            "/reload" => match self.reload {
+                Some(ref trigger) => {
+                    trigger.reload();
+                    Self::record(req, "config_reload", &"requested");
+                    res.status(StatusCode::ACCEPTED);
+                }
+                None => {
+                    res.status(StatusCode::NOT_FOUND);
+                }
+            },
+            _ => {
+                res.status(StatusCode::NOT_FOUND);
+            }
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let guards: Vec<String> = (&self.guards).into_iter().map(|guard| guard.name()).collect();
+        vec![Method::GET, Method::HEAD, Method::PUT, Method::DELETE, Method::POST].into_iter()
+            .map(|method| RouteInfo::new("AdminController", Some(method), None, guards.clone()))
+            .collect()
+    }
+}
@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// The metadata of an entry of an `AssetSource`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssetMetadata {
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+    /// The size of the file, in bytes
+    pub len: u64,
+    /// The time of the last modification of the entry, if known
+    pub modified: Option<SystemTime>,
+}
+
+/// A trait representing where an `AssetController` reads the assets it serves from
+///
+/// Names are decoded, `/` separated and relative to the root of the source, which is the empty name. They never contain
+/// `.` or `..` segments.
+pub trait AssetSource: Send + Sync {
+    /// Return the metadata of the entry `name`, failing with `NotFound` when it does not exist
+    fn metadata(&self, name: &str) -> io::Result<AssetMetadata>;
+
+    /// List the names of the entries of the directory `name`
+    fn read_dir(&self, name: &str) -> io::Result<Vec<String>>;
+
+    /// Read the content of the file `name`
+    fn read(&self, name: &str) -> io::Result<Cow<'static, [u8]>>;
+}
+
+/// An `AssetSource` reading the files of a directory of the local file system
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    /// Read the assets from the `root` directory
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DirectorySource {
+            root: root.into(),
+        }
+    }
+
+    fn resolve(&self, name: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        for segment in name.split('/').filter(|segment| !segment.is_empty()) {
+            path.push(segment);
+        }
+        path
+    }
+}
+
+impl AssetSource for DirectorySource {
+    fn metadata(&self, name: &str) -> io::Result<AssetMetadata> {
+        let metadata = fs::metadata(self.resolve(name))?;
+
+        Ok(AssetMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn read_dir(&self, name: &str) -> io::Result<Vec<String>> {
+        fs::read_dir(self.resolve(name))?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+            .collect()
+    }
+
+    fn read(&self, name: &str) -> io::Result<Cow<'static, [u8]>> {
+        fs::read(self.resolve(name)).map(Cow::Owned)
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no asset named {}", name))
+}
+
+/// The entries of the directory `name` amongst the files `names`, directories being implied by the names of their files
+fn list_names<'a, I: Iterator<Item=&'a str>>(names: I, name: &str) -> Option<Vec<String>> {
+    let prefix = if name.is_empty() { String::new() } else { format!("{}/", name) };
+    let mut found = name.is_empty();
+    let mut entries = BTreeSet::new();
+
+    for file in names {
+        if file.starts_with(&prefix) {
+            let rest = &file[prefix.len()..];
+            if rest.is_empty() {
+                continue;
+            }
+            found = true;
+            entries.insert(rest.split('/').next().unwrap_or(rest).to_string());
+        }
+    }
+
+    if found { Some(entries.into_iter().collect()) } else { None }
+}
+
+/// An `AssetSource` serving files held in memory, like the ones included in the binary with `include_bytes!` or the
+/// `include_dir` crate, for the application to be deployed as a single binary
+///
+/// Directories are implied by the names of the files.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut assets = MemoryAssets::new();
+/// assets.add("index.html", &b"<div id=\"app\"></div>"[..]);
+/// assets.add("css/app.css", &b"body { margin: 0 }"[..]);
+///
+/// let manifest = AssetManifest::scan_source(&assets).unwrap();
+/// let mut router = Router::new();
+/// router.add("^/", AssetController::with_source(assets, manifest));
+/// ```
+#[derive(Default)]
+pub struct MemoryAssets {
+    files: BTreeMap<String, Cow<'static, [u8]>>,
+    modified: Option<SystemTime>,
+}
+
+impl MemoryAssets {
+    /// Create a source without any file
+    pub fn new() -> Self {
+        MemoryAssets::default()
+    }
+
+    /// Add the file `name`, replacing any previous file of the same name
+    pub fn add<N: Into<String>, C: Into<Cow<'static, [u8]>>>(&mut self, name: N, content: C) -> &mut Self {
+        let name = name.into();
+        self.files.insert(name.trim_matches('/').to_string(), content.into());
+        self
+    }
+
+    /// Set the modification time reported for every file, like the time the binary was built. Unknown by default.
+    pub fn modified(&mut self, modified: SystemTime) -> &mut Self {
+        self.modified = Some(modified);
+        self
+    }
+}
+
+impl AssetSource for MemoryAssets {
+    fn metadata(&self, name: &str) -> io::Result<AssetMetadata> {
+        if let Some(content) = self.files.get(name) {
+            return Ok(AssetMetadata { is_dir: false, len: content.len() as u64, modified: self.modified });
+        }
+
+        list_names(self.files.keys().map(|file| file.as_str()), name)
+            .map(|_| AssetMetadata { is_dir: true, len: 0, modified: self.modified })
+            .ok_or_else(|| not_found(name))
+    }
+
+    fn read_dir(&self, name: &str) -> io::Result<Vec<String>> {
+        list_names(self.files.keys().map(|file| file.as_str()), name).ok_or_else(|| not_found(name))
+    }
+
+    fn read(&self, name: &str) -> io::Result<Cow<'static, [u8]>> {
+        self.files.get(name).cloned().ok_or_else(|| not_found(name))
+    }
+}
+
+#[cfg(feature = "rust-embed")]
+mod embedded {
+    use super::*;
+    use rust_embed::RustEmbed;
+    use std::marker::PhantomData;
+    use std::time::Duration;
+    use std::time::UNIX_EPOCH;
+
+    /// An `AssetSource` serving the files embedded in the binary by `rust-embed`, for the application to be deployed as a
+    /// single binary
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// #[derive(rust_embed::RustEmbed)]
+    /// #[folder = "public/"]
+    /// struct Public;
+    ///
+    /// let manifest = AssetManifest::scan_source(&EmbeddedAssets::<Public>::new()).unwrap();
+    /// let mut router = Router::new();
+    /// router.add("^/", AssetController::with_source(EmbeddedAssets::<Public>::new(), manifest));
+    /// ```
+    pub struct EmbeddedAssets<E: RustEmbed> {
+        embed: PhantomData<fn() -> E>,
+    }
+
+    impl<E: RustEmbed> EmbeddedAssets<E> {
+        /// Serve the files embedded by `E`
+        pub fn new() -> Self {
+            EmbeddedAssets {
+                embed: PhantomData,
+            }
+        }
+    }
+
+    impl<E: RustEmbed> Default for EmbeddedAssets<E> {
+        fn default() -> Self {
+            EmbeddedAssets::new()
+        }
+    }
+
+    impl<E: RustEmbed> AssetSource for EmbeddedAssets<E> {
+        fn metadata(&self, name: &str) -> io::Result<AssetMetadata> {
+            if let Some(file) = E::get(name) {
+                return Ok(AssetMetadata {
+                    is_dir: false,
+                    len: file.data.len() as u64,
+                    modified: file.metadata.last_modified().map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                });
+            }
+
+            let names = E::iter().collect::<Vec<_>>();
+            list_names(names.iter().map(|file| file.as_ref()), name)
+                .map(|_| AssetMetadata { is_dir: true, len: 0, modified: None })
+                .ok_or_else(|| not_found(name))
+        }
+
+        fn read_dir(&self, name: &str) -> io::Result<Vec<String>> {
+            let names = E::iter().collect::<Vec<_>>();
+            list_names(names.iter().map(|file| file.as_ref()), name).ok_or_else(|| not_found(name))
+        }
+
+        fn read(&self, name: &str) -> io::Result<Cow<'static, [u8]>> {
+            E::get(name).map(|file| file.data).ok_or_else(|| not_found(name))
+        }
+    }
+}
+
+#[cfg(feature = "rust-embed")]
+pub use self::embedded::EmbeddedAssets;
@@ -0,0 +1,468 @@
+use http::*;
+use http::header::HttpDate;
+use controller::Controller;
+use controller::RouteInfo;
+use config_reload::ConfigError;
+use asset_source::AssetSource;
+use asset_source::DirectorySource;
+use etag::fnv1a;
+use etag::strong_etag;
+use etag::is_not_modified;
+use form::html_escape;
+use query::percent_decode;
+use query::percent_encode;
+use utils::ToRegex;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// The `Cache-Control` of fingerprinted assets: their content never changes under the same name, so clients may keep them
+/// for a year without revalidating them
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The content codings of the precompressed siblings of the assets along with their extension, in order of preference
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// The file disabling the listing of the directory it is in, see `AssetController::directory_listing`
+pub const NO_INDEX_MARKER: &str = ".noindex";
+
+#[derive(Debug, Default)]
+struct Manifest {
+    prefix: String,
+    assets: BTreeMap<String, String>,
+}
+
+/// The fingerprinted names of static assets, like `css/app.3f2a9c1d.css` for `css/app.css`, for their URLs to change along
+/// with their content
+///
+/// A manifest is either computed at startup by hashing the files of a directory with `AssetManifest::scan`, or read from the
+/// JSON manifest of a bundler mapping each asset to its fingerprinted name with `AssetManifest::from_file`. The URLs of the
+/// assets are given by `AssetManifest::url`, by `SyncResponse::asset_url` for handlers, and by the `asset_url` helper of the
+/// template engines once the manifest is registered with `ServerBuilder::asset_manifest`. The assets themselves are served
+/// by an `AssetController`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let manifest = AssetManifest::scan("public").unwrap().prefix("/assets");
+/// assert!(manifest.url("app.css").starts_with("/assets/app."));
+///
+/// let mut router = Router::new();
+/// router.add("^/assets/", AssetController::new("public", manifest.clone()));
+///
+/// let server = Server::builder().router(router).asset_manifest(manifest).build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AssetManifest {
+    manifest: Arc<Manifest>,
+}
+
+impl AssetManifest {
+    /// Fingerprint every file under `dir`, recursively, with a hash of its content
+    pub fn scan<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        AssetManifest::scan_source(&DirectorySource::new(dir))
+    }
+
+    /// Fingerprint every file of `source`, like the assets embedded in the binary, see `scan`
+    pub fn scan_source<S: AssetSource + ?Sized>(source: &S) -> io::Result<Self> {
+        let mut assets = BTreeMap::new();
+        scan_dir(source, "", &mut assets)?;
+        Ok(AssetManifest::from_assets(assets))
+    }
+
+    /// Read the manifest of a bundler, a JSON object mapping the name of each asset to its fingerprinted name, like
+    /// `{"app.css": "app.3f2a9c1d.css"}`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)?;
+        let assets: BTreeMap<String, String> = ::serde_json::from_str(&content)?;
+
+        Ok(AssetManifest::from_assets(assets.into_iter()
+            .map(|(name, fingerprinted)| (name.trim_start_matches('/').to_string(), fingerprinted.trim_start_matches('/').to_string()))
+            .collect()))
+    }
+
+    fn from_assets(assets: BTreeMap<String, String>) -> Self {
+        AssetManifest {
+            manifest: Arc::new(Manifest {
+                prefix: "/".to_string(),
+                assets,
+            }),
+        }
+    }
+
+    /// Set the path the assets are served under, `/` by default
+    pub fn prefix(self, prefix: &str) -> Self {
+        let assets = Arc::try_unwrap(self.manifest).map(|manifest| manifest.assets).unwrap_or_else(|manifest| manifest.assets.clone());
+
+        AssetManifest {
+            manifest: Arc::new(Manifest {
+                prefix: format!("{}/", prefix.trim_end_matches('/')),
+                assets,
+            }),
+        }
+    }
+
+    /// Returns the fingerprinted name of the asset `name`, `None` when it is not part of the manifest
+    pub fn fingerprinted(&self, name: &str) -> Option<&str> {
+        self.manifest.assets.get(name.trim_start_matches('/')).map(|fingerprinted| fingerprinted.as_str())
+    }
+
+    /// Returns the URL of the asset `name`, fingerprinted when it is part of the manifest
+    pub fn url(&self, name: &str) -> String {
+        let name = name.trim_start_matches('/');
+        format!("{}{}", self.manifest.prefix, self.fingerprinted(name).unwrap_or(name))
+    }
+
+    fn path<'a>(&self, uri_path: &'a str) -> Option<&'a str> {
+        let prefix = &self.manifest.prefix;
+        if uri_path.starts_with(prefix.as_str()) {
+            Some(&uri_path[prefix.len()..])
+        } else if uri_path.len() + 1 == prefix.len() && prefix.starts_with(uri_path) {
+            Some("")
+        } else {
+            None
+        }
+    }
+}
+
+fn scan_dir<S: AssetSource + ?Sized>(source: &S, parent: &str, assets: &mut BTreeMap<String, String>) -> io::Result<()> {
+    for file_name in source.read_dir(parent.trim_end_matches('/'))? {
+        let name = format!("{}{}", parent, file_name);
+
+        if source.metadata(&name)?.is_dir {
+            scan_dir(source, &format!("{}/", name), assets)?;
+        } else {
+            let hash = format!("{:016x}", fnv1a(&source.read(&name)?));
+            let fingerprinted = match file_name.find('.') {
+                Some(dot) if dot > 0 => format!("{}{}.{}{}", parent, &file_name[..dot], &hash[..8], &file_name[dot..]),
+                _ => format!("{}.{}", name, &hash[..8]),
+            };
+            assets.insert(name, fingerprinted);
+        }
+    }
+
+    Ok(())
+}
+
+/// Guess the content type of a file from the extension of its name
+pub(crate) fn guess_content_type(name: &str) -> &'static str {
+    match Path::new(name).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") | Some("map") => "application/json",
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("mp4") => "video/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// A controller serving the static assets of a directory, under the prefix of an `AssetManifest`
+///
+/// Assets requested by their fingerprinted name are sent with `Cache-Control: public, max-age=31536000, immutable`, since
+/// a new version of an asset gets a new name. The files of a bundler manifest are expected on disk under their fingerprinted
+/// name, the ones of a scanned manifest under their own name. Assets requested by their own name are sent with an `ETag`
+/// and `Cache-Control: no-cache`, for clients to revalidate them.
+///
+/// Clients may ask for parts of the files with the `Range` header, see `SyncResponse::ranged_body`. When the client accepts
+/// it, a file is sent from its precompressed sibling, `app.css.br` or `app.css.gz` for `app.css`,
+/// along with the matching `Content-Encoding`.
+///
+/// Directories are not listed unless `AssetController::directory_listing` is enabled, and missing files are answered with
+/// `404 Not Found` unless `AssetController::spa_fallback` is enabled.
+pub struct AssetController {
+    source: Box<dyn AssetSource>,
+    manifest: AssetManifest,
+    names: HashMap<String, String>,
+    listing: bool,
+    spa_fallback: Option<SpaFallback>,
+}
+
+/// The page of a single-page application, served in place of the files which don't exist
+struct SpaFallback {
+    index: String,
+    excluded_paths: Vec<Regex>,
+}
+
+#[derive(Serialize)]
+struct ListingEntry {
+    name: String,
+    directory: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Listing {
+    path: String,
+    entries: Vec<ListingEntry>,
+}
+
+impl AssetController {
+    /// Serve the files of `dir` under the prefix of `manifest`
+    pub fn new<P: Into<PathBuf>>(dir: P, manifest: AssetManifest) -> Self {
+        AssetController::with_source(DirectorySource::new(dir), manifest)
+    }
+
+    /// Serve the files of `source` under the prefix of `manifest`, like the assets embedded in the binary, see
+    /// `MemoryAssets`
+    pub fn with_source<S: 'static + AssetSource>(source: S, manifest: AssetManifest) -> Self {
+        let names = manifest.manifest.assets.iter().map(|(name, fingerprinted)| (fingerprinted.clone(), name.clone())).collect();
+
+        AssetController {
+            source: Box::new(source),
+            manifest,
+            names,
+            listing: false,
+            spa_fallback: None,
+        }
+    }
+
+    /// Serve a single-page application: requests for files which don't exist are answered with `index`, like `index.html`,
+    /// for the application to route them itself. Paths matching one of `excluded_paths`, like `^/api/`, and paths whose last
+    /// segment has an extension, like a missing `app.js`, are still answered with `404 Not Found`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut router = Router::new();
+    /// router.add("^/", AssetController::new("dist", AssetManifest::default()).spa_fallback("index.html", vec!["^/api/"]));
+    /// ```
+    pub fn spa_fallback<R: ToRegex>(mut self, index: &str, excluded_paths: Vec<R>) -> Self {
+        self.spa_fallback = Some(SpaFallback {
+            index: index.trim_start_matches('/').to_string(),
+            excluded_paths: excluded_paths.iter().map(|path| reg!(path)).collect(),
+        });
+        self
+    }
+
+    /// Set whether directories are answered with a listing of their files and subdirectories, along with their sizes and
+    /// modification times, as HTML or as JSON for clients preferring it. Hidden files are not listed, and directories
+    /// containing a `.noindex` file are not listed either. Disabled by default.
+    pub fn directory_listing(mut self, enabled: bool) -> Self {
+        self.listing = enabled;
+        self
+    }
+
+    /// Decode the name of an entry of the source from the path of a request
+    fn decode(&self, name: &str) -> Option<String> {
+        let mut segments = Vec::new();
+        for segment in name.split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode(segment);
+            if segment == "." || segment == ".." || segment.contains('/') || segment.contains('\\') || segment.contains('\0') {
+                return None;
+            }
+            segments.push(segment);
+        }
+
+        Some(segments.join("/"))
+    }
+
+    fn file(&self, name: &str) -> Option<String> {
+        self.decode(name).filter(|name| self.source.metadata(name).map(|metadata| !metadata.is_dir).unwrap_or(false))
+    }
+
+    fn dir(&self, name: &str) -> Option<String> {
+        self.decode(name).filter(|name| self.source.metadata(name).map(|metadata| metadata.is_dir).unwrap_or(false))
+    }
+
+    /// Read the file `name`, or its precompressed sibling preferred by the client
+    fn read(&self, req: &SyncRequest, res: &mut SyncResponse, name: &str) -> io::Result<Vec<u8>> {
+        let siblings = PRECOMPRESSED.iter()
+            .map(|&(coding, extension)| (coding, format!("{}.{}", name, extension)))
+            .filter(|(_, sibling)| self.source.metadata(sibling).map(|metadata| !metadata.is_dir).unwrap_or(false))
+            .collect::<Vec<_>>();
+        if siblings.is_empty() {
+            return self.source.read(name).map(|content| content.into_owned());
+        }
+
+        res.header(header::VARY, "accept-encoding");
+        let offered = siblings.iter().map(|&(coding, _)| coding).collect::<Vec<_>>();
+        match req.accepts_encoding(&offered).and_then(|coding| siblings.iter().find(|&&(c, _)| c == coding)) {
+            Some(&(coding, ref sibling)) => {
+                let content = self.source.read(sibling)?;
+                res.header(header::CONTENT_ENCODING, coding);
+                Ok(content.into_owned())
+            }
+            None => self.source.read(name).map(|content| content.into_owned()),
+        }
+    }
+
+    fn spa_index(&self, uri_path: &str) -> Option<String> {
+        let spa = self.spa_fallback.as_ref()?;
+        let last_segment = uri_path.rsplit('/').next().unwrap_or("");
+        if last_segment.contains('.') || spa.excluded_paths.iter().any(|path| path.is_match(uri_path)) {
+            return None;
+        }
+
+        self.file(&spa.index)
+    }
+
+    fn list(&self, req: &SyncRequest, res: &mut SyncResponse, name: &str, dir: &str) {
+        let mut entries = match self.source.read_dir(dir) {
+            Ok(entries) => entries.into_iter().filter(|entry| !entry.starts_with('.')).filter_map(|entry| {
+                let metadata = self.source.metadata(&if dir.is_empty() { entry.clone() } else { format!("{}/{}", dir, entry) }).ok()?;
+                Some(ListingEntry {
+                    name: entry,
+                    directory: metadata.is_dir,
+                    size: if metadata.is_dir { None } else { Some(metadata.len) },
+                    modified: metadata.modified.map(|modified| HttpDate::from(modified).to_string()),
+                })
+            }).collect::<Vec<_>>(),
+            Err(e) => {
+                error!("Unable to list the directory {}: {}", dir, e);
+                res.status(StatusCode::INTERNAL_SERVER_ERROR);
+                return;
+            }
+        };
+        entries.sort_by(|a, b| b.directory.cmp(&a.directory).then_with(|| a.name.cmp(&b.name)));
+
+        let mut path = self.manifest.manifest.prefix.clone();
+        for segment in name.split('/').filter(|segment| !segment.is_empty()) {
+            path.push_str(segment);
+            path.push('/');
+        }
+        let root = path == self.manifest.manifest.prefix;
+        let listing = Listing { path, entries };
+
+        res.status(StatusCode::OK).header(header::CACHE_CONTROL, "no-cache");
+        if req.accepts(&["text/html", "application/json"]) == Some("application/json") {
+            res.json(&listing);
+        } else {
+            res.header(header::CONTENT_TYPE, "text/html; charset=utf-8").body(listing_page(&listing, root));
+        }
+    }
+}
+
+fn listing_page(listing: &Listing, root: bool) -> String {
+    let title = format!("Index of {}", percent_decode(&listing.path));
+    let mut page = String::new();
+
+    let _ = write!(page, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n", html_escape(&title));
+    let _ = writeln!(page, "<h1>{}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>", html_escape(&title));
+    if !root {
+        page.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in &listing.entries {
+        let suffix = if entry.directory { "/" } else { "" };
+        let size = entry.size.map(|size| size.to_string()).unwrap_or_else(|| "-".to_string());
+        let _ = writeln!(page, "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+                         html_escape(&listing.path), percent_encode(&entry.name), suffix, html_escape(&entry.name), suffix, size,
+                         html_escape(entry.modified.as_deref().unwrap_or("")));
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
+
+impl Controller for AssetController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, "GET, HEAD");
+            return;
+        }
+
+        let name = match self.manifest.path(req.uri().path()) {
+            Some(name) => name,
+            None => {
+                res.status(StatusCode::NOT_FOUND);
+                return;
+            }
+        };
+
+        let (mut path, immutable) = match self.names.get(name) {
+            Some(original) => (self.file(name).or_else(|| self.file(original)), true),
+            None => (self.file(name), false),
+        };
+
+        if path.is_none() {
+            let listed = if self.listing { self.dir(name) } else { None };
+            if let Some(dir) = listed.filter(|dir| self.source.metadata(format!("{}/{}", dir, NO_INDEX_MARKER).trim_start_matches('/')).is_err()) {
+                self.list(req, res, name, &dir);
+                return;
+            }
+            path = self.spa_index(req.uri().path());
+        }
+
+        let content = match path.as_ref().map(|path| (path, self.read(req, res, path))) {
+            Some((path, Ok(content))) => {
+                res.header(header::CONTENT_TYPE, guess_content_type(path));
+                content
+            }
+            Some((path, Err(e))) => {
+                error!("Unable to read the asset {}: {}", path, e);
+                res.status(StatusCode::INTERNAL_SERVER_ERROR);
+                return;
+            }
+            None => {
+                res.status(StatusCode::NOT_FOUND);
+                return;
+            }
+        };
+
+        if immutable {
+            res.header(header::CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL);
+        } else {
+            let etag = strong_etag(&content);
+            res.header(header::CACHE_CONTROL, "no-cache").header(header::ETAG, etag.to_string());
+            let modified: Option<SystemTime> = path.and_then(|path| self.source.metadata(&path).ok()).and_then(|metadata| metadata.modified);
+            if let Some(modified) = modified {
+                res.header(header::LAST_MODIFIED, HttpDate::from(modified).to_string());
+            }
+            if is_not_modified(req, Some(&etag), None) {
+                res.status(StatusCode::NOT_MODIFIED);
+                return;
+            }
+        }
+
+        res.status(StatusCode::OK);
+        if *req.method() == Method::GET {
+            res.ranged_body(req, content);
+        } else {
+            res.header(header::ACCEPT_RANGES, "bytes").header(header::CONTENT_LENGTH, content.len().to_string());
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        vec![Method::GET, Method::HEAD].into_iter()
+            .map(|method| RouteInfo::new("AssetController", Some(method), None, Vec::new()))
+            .collect()
+    }
+}
+
+impl SyncResponse {
+    /// Returns the URL of the asset `name`, fingerprinted according to the manifest registered on the server, see
+    /// `ServerBuilder::asset_manifest`. Without a manifest, `name` is returned as an absolute path.
+    pub fn asset_url(&self, name: &str) -> String {
+        match self.get_extensions().get::<AssetManifest>() {
+            Some(manifest) => manifest.url(name),
+            None => format!("/{}", name.trim_start_matches('/')),
+        }
+    }
+}
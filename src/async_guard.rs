@@ -0,0 +1,132 @@
+use http::*;
+use controller::RequestGuard;
+use controller::short_type_name;
+use problem::Problem;
+use utils::RequestContinuation;
+use futures::Future;
+use futures::future;
+use std::any::type_name;
+
+/// The outcome of an `AsyncRequestGuard`, completing successfully when the request is allowed through
+pub type GuardFuture = Box<dyn Future<Item=(), Error=GuardRejection> + Send>;
+
+/// Why an `AsyncRequestGuard` rejected a request
+#[derive(Debug, Clone)]
+pub enum GuardRejection {
+    /// The guard already answered the request before returning its future
+    Answered,
+    /// The request is answered with this problem document
+    Problem(Problem),
+}
+
+impl From<Problem> for GuardRejection {
+    fn from(problem: Problem) -> Self {
+        GuardRejection::Problem(problem)
+    }
+}
+
+impl From<StatusCode> for GuardRejection {
+    fn from(status: StatusCode) -> Self {
+        GuardRejection::Problem(Problem::new(status))
+    }
+}
+
+/// A guard validating requests with I/O, like a token introspection or a database lookup, completing a future instead of
+/// answering right away
+///
+/// The request is borrowed only until `validate` returns: the future must own whatever it needs from it, like the value of
+/// the `Authorization` header. Delegates are still invoked synchronously, an async guard is registered on a route wrapped
+/// in an `AwaitGuard`, which waits for it on the handler thread, never on the threads serving the connections. Existing
+/// guards become async guards when wrapped in a `SyncGuard`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate futures;
+/// # extern crate saphir;
+/// # use futures::Future;
+/// # use saphir::*;
+/// # fn introspect(_token: String) -> Box<Future<Item=bool, Error=()> + Send> { Box::new(futures::future::ok(true)) }
+/// # fn main() {
+/// struct TokenGuard;
+///
+/// impl AsyncRequestGuard for TokenGuard {
+///     fn validate(&self, req: &SyncRequest, _res: &mut SyncResponse) -> GuardFuture {
+///         let token = match req.headers_map().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+///             Some(token) => token.to_string(),
+///             None => return Box::new(futures::future::err(StatusCode::UNAUTHORIZED.into())),
+///         };
+///
+///         Box::new(introspect(token).then(|active| match active {
+///             Ok(true) => Ok(()),
+///             _ => Err(StatusCode::UNAUTHORIZED.into()),
+///         }))
+///     }
+/// }
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::GET, "^/account$", AwaitGuard(TokenGuard).into(), |_, _, res| {
+///     res.status(StatusCode::OK);
+/// });
+/// # }
+/// ```
+pub trait AsyncRequestGuard: Send + Sync {
+    /// Start validating the request, the returned future completes once the request is allowed or rejected
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> GuardFuture;
+
+    /// Name of the guard, used for introspection only
+    fn name(&self) -> String {
+        short_type_name(type_name::<Self>())
+    }
+
+    /// The key under which a `CachedGuard` remembers the validation of `req`, like its credentials, `None` to validate it
+    /// every time. The default implementation returns `None`.
+    fn cache_key(&self, _req: &SyncRequest) -> Option<String> {
+        None
+    }
+}
+
+/// Wrap an existing `RequestGuard` into an `AsyncRequestGuard` completing right away
+pub struct SyncGuard<G>(pub G);
+
+impl<G: RequestGuard> AsyncRequestGuard for SyncGuard<G> {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> GuardFuture {
+        match self.0.validate(req, res) {
+            RequestContinuation::Next => Box::new(future::ok(())),
+            RequestContinuation::None => Box::new(future::err(GuardRejection::Answered)),
+        }
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn cache_key(&self, req: &SyncRequest) -> Option<String> {
+        self.0.cache_key(req)
+    }
+}
+
+/// Register an `AsyncRequestGuard` on a route, waiting for its future on the handler thread processing the request
+pub struct AwaitGuard<G>(pub G);
+
+impl<G: AsyncRequestGuard> RequestGuard for AwaitGuard<G> {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.0.validate(req, res).wait() {
+            Ok(()) => RequestContinuation::Next,
+            Err(GuardRejection::Answered) => RequestContinuation::None,
+            Err(GuardRejection::Problem(problem)) => {
+                res.problem(&problem);
+                RequestContinuation::None
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        self.0.name()
+    }
+
+    fn cache_key(&self, req: &SyncRequest) -> Option<String> {
+        self.0.cache_key(req)
+    }
+}
+
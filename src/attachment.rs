@@ -0,0 +1,157 @@
+use http::*;
+use assets::guess_content_type;
+use query::percent_encode;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+
+/// The size of the chunks files are streamed in by `SyncResponse::attachment`
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The content of a file downloaded by the client, see `SyncResponse::attachment`
+///
+/// Bytes and strings are sent as they are, paths are read from disk.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attachment {
+    /// Content held in memory
+    Bytes(Vec<u8>),
+    /// A file streamed from disk
+    File(PathBuf),
+}
+
+impl From<Vec<u8>> for Attachment {
+    fn from(bytes: Vec<u8>) -> Self {
+        Attachment::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Attachment {
+    fn from(bytes: &'a [u8]) -> Self {
+        Attachment::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Attachment {
+    fn from(content: String) -> Self {
+        Attachment::Bytes(content.into_bytes())
+    }
+}
+
+impl<'a> From<&'a str> for Attachment {
+    fn from(content: &'a str) -> Self {
+        Attachment::Bytes(content.as_bytes().to_vec())
+    }
+}
+
+impl From<PathBuf> for Attachment {
+    fn from(path: PathBuf) -> Self {
+        Attachment::File(path)
+    }
+}
+
+impl<'a> From<&'a Path> for Attachment {
+    fn from(path: &'a Path) -> Self {
+        Attachment::File(path.to_path_buf())
+    }
+}
+
+/// Format the `Content-Disposition` of an attachment named `filename`
+///
+/// Names which are not made of printable ASCII characters only are given both as an ASCII approximation, for older
+/// clients, and encoded as UTF-8 in the `filename*` parameter as specified by RFC 6266 and RFC 5987.
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename.chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+
+    if fallback == filename {
+        format!("attachment; filename=\"{}\"", fallback)
+    } else {
+        format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, percent_encode(filename))
+    }
+}
+
+impl SyncResponse {
+    /// Send `content` as a file downloaded by the client under the name `filename`, for exports like CSV or PDF files
+    ///
+    /// The `Content-Disposition` of the response is set to `attachment`, see `content_disposition`, and its content type is
+    /// guessed from the extension of `filename` unless the response already has one. Files are streamed from disk on a
+    /// thread of their own, see `SyncResponse::stream`; when they can't be opened, the response is turned into
+    /// `404 Not Found` or `500 Internal Server Error`.
+    ///
+    /// Files are read into userspace rather than sent with `sendfile`: hyper owns the connection and frames the body itself,
+    /// counting its bytes against `Content-Length` or writing the chunk sizes, so nothing can be written to the socket around
+    /// it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use std::path::Path;
+    /// fn export(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).attachment("id,name\n1,Ada\n", "users.csv");
+    /// }
+    ///
+    /// fn report(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).attachment(Path::new("reports/2024.pdf"), "Rapport annuel 2024.pdf");
+    /// }
+    /// ```
+    pub fn attachment<A: Into<Attachment>>(&mut self, content: A, filename: &str) -> &mut SyncResponse {
+        let file = match content.into() {
+            Attachment::Bytes(bytes) => {
+                self.describe_attachment(filename);
+                return self.body(bytes);
+            }
+            Attachment::File(path) => match File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    error!("Unable to open the attachment {}: {}", path.display(), e);
+                    let status = if e.kind() == io::ErrorKind::NotFound { StatusCode::NOT_FOUND } else { StatusCode::INTERNAL_SERVER_ERROR };
+                    return self.status(status).body(Vec::<u8>::new());
+                }
+            },
+        };
+
+        self.describe_attachment(filename);
+        let mut sender = self.stream();
+        let spawned = thread::Builder::new().name("saphir-attachment".to_string()).spawn(move || {
+            let mut file = file;
+            loop {
+                // Each chunk is read into the buffer handed over to hyper, rather than copied out of a reused one
+                let mut chunk = vec![0; CHUNK_SIZE];
+                match file.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        if sender.send(chunk).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("Unable to read an attachment: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        if let Err(e) = spawned {
+            error!("Unable to stream an attachment: {}", e);
+            self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+        }
+
+        self
+    }
+
+    fn describe_attachment(&mut self, filename: &str) {
+        if let Ok(disposition) = header::HeaderValue::from_str(&content_disposition(filename)) {
+            self.headers_map_mut().insert(header::CONTENT_DISPOSITION, disposition);
+        }
+        if !self.headers_map().contains_key(header::CONTENT_TYPE) {
+            self.headers_map_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(guess_content_type(filename)));
+        }
+    }
+}
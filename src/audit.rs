@@ -0,0 +1,367 @@
+use http::*;
+use logging::*;
+use log::Level;
+use futures::Future;
+use futures::Stream;
+use futures::sync::oneshot;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use tokio::runtime::Runtime;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How the action of an `AuditEvent` ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// The action was performed
+    Success,
+    /// The action was attempted but failed
+    Failure,
+    /// The actor wasn't allowed to perform the action
+    Denied,
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuditOutcome::Success => write!(f, "success"),
+            AuditOutcome::Failure => write!(f, "failure"),
+            AuditOutcome::Denied => write!(f, "denied"),
+        }
+    }
+}
+
+/// Something an actor did, or attempted to do, to a resource, emitted with `SyncRequest::audit`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let event = AuditEvent::new("alice", "user.delete", "users/42")
+///     .outcome(AuditOutcome::Denied)
+///     .detail("reason", "not an administrator");
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    /// Who performed the action, like a user name or the name of a service
+    pub actor: String,
+    /// What was done, like `user.delete`
+    pub action: String,
+    /// What it was done to, like `users/42`
+    pub resource: String,
+    /// How it ended
+    pub outcome: AuditOutcome,
+    /// Anything else worth keeping, like the reason of a denial
+    pub details: BTreeMap<String, String>,
+}
+
+impl AuditEvent {
+    /// Create the event of `actor` successfully performing `action` on `resource`
+    pub fn new(actor: &str, action: &str, resource: &str) -> Self {
+        AuditEvent {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            resource: resource.to_string(),
+            outcome: AuditOutcome::Success,
+            details: BTreeMap::new(),
+        }
+    }
+
+    /// Set how the action ended
+    pub fn outcome(mut self, outcome: AuditOutcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    /// Set the detail `name`
+    pub fn detail<V: ToString>(mut self, name: &str, value: V) -> Self {
+        self.details.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// An `AuditEvent` along with the request it was emitted for, as handed to the `AuditSink`s
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// When the event was emitted, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    /// The event
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// Method of the request
+    pub method: String,
+    /// Path of the request
+    pub path: String,
+    /// Address of the client, see `SyncRequest::client_ip`
+    pub client_ip: Option<String>,
+    /// The `X-Request-Id` header of the request, correlating the event with the logs of the request
+    pub request_id: Option<String>,
+}
+
+impl AuditRecord {
+    fn new(req: &SyncRequest, event: AuditEvent) -> Self {
+        AuditRecord {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+            event,
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            client_ip: req.client_ip().map(|ip| ip.to_string()),
+            request_id: req.header_list("x-request-id").first().map(|id| id.to_string()),
+        }
+    }
+
+    /// Returns the record as a JSON document on a single line
+    pub fn to_json(&self) -> String {
+        ::serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// A trait representing where audit records are kept, like a file or a log collector, see `AuditLog::sink`
+///
+/// The trait is implemented by any function taking a record and returning an `io::Result<()>`.
+pub trait AuditSink: Send + Sync {
+    /// Keep `record`, or buffer it until the next flush
+    fn record(&self, record: &AuditRecord) -> io::Result<()>;
+
+    /// Write the buffered records, called when the server shuts down
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<F> AuditSink for F where F: Fn(&AuditRecord) -> io::Result<()> + Send + Sync {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        (*self)(record)
+    }
+}
+
+/// An `AuditSink` appending the records to a file, one JSON document per line
+///
+/// Records are buffered, and written along with a sync of the file on every flush.
+pub struct FileAuditSink {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl FileAuditSink {
+    /// Append the records to the file at `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileAuditSink { file: Mutex::new(BufWriter::new(file)) })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut file = self.file.lock().map_err(|_| io::Error::other("the audit file lock is poisoned"))?;
+        writeln!(file, "{}", record.to_json())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut file = self.file.lock().map_err(|_| io::Error::other("the audit file lock is poisoned"))?;
+        file.flush()?;
+        file.get_ref().sync_data()
+    }
+}
+
+/// An `AuditSink` sending each record to a syslog server over UDP, as an RFC 5424 message of the `authpriv` facility
+/// whose message is the record in JSON
+///
+/// Successes are sent with the `notice` severity, failures and denials with `warning`.
+pub struct SyslogAuditSink {
+    socket: UdpSocket,
+    app_name: String,
+}
+
+impl SyslogAuditSink {
+    /// Send the records to the syslog server at `addr`, like `127.0.0.1:514`, as sent by the application `app_name`
+    pub fn connect<A: ToSocketAddrs>(addr: A, app_name: &str) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no syslog address"))?;
+        let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.connect(addr)?;
+
+        let app_name: String = app_name.chars().filter(|c| c.is_ascii_graphic()).take(48).collect();
+        Ok(SyslogAuditSink { socket, app_name: if app_name.is_empty() { "-".to_string() } else { app_name } })
+    }
+}
+
+impl AuditSink for SyslogAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        // authpriv is the facility 10, notice and warning the severities 5 and 4
+        let priority = 10 * 8 + if record.event.outcome == AuditOutcome::Success { 5 } else { 4 };
+        let message = format!("<{}>1 - - {} - audit - {}", priority, self.app_name, record.to_json());
+        self.socket.send(message.as_bytes()).map(|_| ())
+    }
+}
+
+/// An `AuditSink` posting the records to an http collector in batches, as `application/x-ndjson`
+///
+/// A batch is posted once it holds `batch_size` records, 100 by default, and on every flush. A batch which couldn't be
+/// posted is kept, and posted again with the next one.
+pub struct HttpAuditSink {
+    url: Uri,
+    batch_size: usize,
+    pending: Mutex<Vec<String>>,
+    client: Client<HttpConnector, Body>,
+    runtime: Runtime,
+}
+
+impl HttpAuditSink {
+    /// Post the records to `url`, like `http://audit.internal/v1/events`
+    pub fn new(url: &str) -> io::Result<Self> {
+        let url = url.parse::<Uri>().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let runtime = Runtime::new()?;
+        let client = Client::builder().executor(runtime.executor()).build_http::<Body>();
+
+        Ok(HttpAuditSink {
+            url,
+            batch_size: 100,
+            pending: Mutex::new(Vec::new()),
+            client,
+            runtime,
+        })
+    }
+
+    /// Post the records once `size` of them are pending
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    /// Post the pending records
+    fn post(&self, pending: &mut Vec<String>) -> io::Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut body = pending.join("\n");
+        body.push('\n');
+        let request = Request::post(self.url.clone()).header(header::CONTENT_TYPE, "application/x-ndjson").body(Body::from(body))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let response = self.client.request(request).and_then(|res| {
+            let status = res.status();
+            res.into_body().concat2().map(move |_| status)
+        });
+
+        let status = oneshot::spawn(response, &self.runtime.executor()).wait().map_err(io::Error::other)?;
+        if !status.is_success() {
+            return Err(io::Error::other(format!("the audit collector answered {}", status)));
+        }
+
+        pending.clear();
+        Ok(())
+    }
+}
+
+impl AuditSink for HttpAuditSink {
+    fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut pending = self.pending.lock().map_err(|_| io::Error::other("the audit batch lock is poisoned"))?;
+        pending.push(record.to_json());
+
+        if pending.len() >= self.batch_size {
+            self.post(&mut pending)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut pending = self.pending.lock().map_err(|_| io::Error::other("the audit batch lock is poisoned"))?;
+        self.post(&mut pending)
+    }
+}
+
+/// The audit log of the server, handing the events emitted with `SyncRequest::audit` to its sinks, see
+/// `ServerBuilder::audit_log`
+///
+/// Each event is recorded along with the request it was emitted for. Sinks are flushed once the server stopped serving
+/// the connections in progress, and when the log is dropped, so that buffered records aren't lost on shutdown. Records
+/// which a sink fails to keep are logged as errors.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let audit = AuditLog::new()
+///     .sink(FileAuditSink::open("audit.jsonl").unwrap())
+///     .sink(SyslogAuditSink::connect("127.0.0.1:514", "billing").unwrap());
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::DELETE, "^/invoices/(?P<id>\\d+)$", |_, req, res| {
+///     req.audit(AuditEvent::new("alice", "invoice.delete", req.uri().path()));
+///     res.status(StatusCode::NO_CONTENT);
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/", controller);
+/// let server = Server::builder().router(router).audit_log(audit).build();
+/// ```
+#[derive(Default)]
+pub struct AuditLog {
+    sinks: Vec<Box<dyn AuditSink>>,
+}
+
+impl AuditLog {
+    /// Create a log without any sink
+    pub fn new() -> Self {
+        AuditLog::default()
+    }
+
+    /// Hand the events to `sink`, along with the other sinks
+    pub fn sink<S: 'static + AuditSink>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    fn record(&self, record: &AuditRecord) {
+        for sink in &self.sinks {
+            if let Err(e) = sink.record(record) {
+                log_event(Level::Error, SERVER_LOG_TARGET, "audit record lost",
+                          format_args!("Unable to record the audit event {} of {}: {}", record.event.action, record.event.actor, e),
+                          &[("action", &record.event.action), ("actor", &record.event.actor), ("error", &e)]);
+            }
+        }
+    }
+
+    /// Flush every sink
+    pub fn flush(&self) {
+        for sink in &self.sinks {
+            if let Err(e) = sink.flush() {
+                log_event(Level::Error, SERVER_LOG_TARGET, "audit flush failed", format_args!("Unable to flush the audit log: {}", e),
+                          &[("error", &e)]);
+            }
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// The audit log registered on the server, inserted in the extensions of every request
+#[derive(Clone)]
+pub(crate) struct SharedAuditLog(pub(crate) Arc<AuditLog>);
+
+impl SyncRequest {
+    /// Record `event` in the audit log of the server, along with the method, path, client address and `X-Request-Id` of the
+    /// request. The event is discarded when the server has no audit log.
+    pub fn audit(&self, event: AuditEvent) {
+        if let Some(log) = self.extensions().get::<SharedAuditLog>() {
+            log.0.record(&AuditRecord::new(self, event));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use http::*;
+use futures_cpupool::Builder as CpuPoolBuilder;
+use futures_cpupool::CpuPool;
+use problem::Problem;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A pool of threads running the blocking or CPU heavy work of handlers, see `SyncRequest::run_blocking`
+///
+/// The pool accepts as much work as it has threads plus a bounded queue, and rejects the rest right away instead of
+/// piling it up, so a burst of expensive requests can't hold every thread processing requests. Pools are cheap handles
+/// which can be cloned, their threads being started with the first work they run.
+#[derive(Clone)]
+pub struct BlockingPool {
+    threads: usize,
+    max_queued: usize,
+    timeout: Option<Duration>,
+    name_prefix: String,
+    pool: Arc<OnceLock<CpuPool>>,
+    in_use: Arc<AtomicUsize>,
+}
+
+impl BlockingPool {
+    /// Create a pool of `threads` threads, queuing up to as many works once they are all busy
+    pub fn new(threads: usize) -> Self {
+        BlockingPool {
+            threads: threads.max(1),
+            max_queued: threads.max(1),
+            timeout: None,
+            name_prefix: "saphir-blocking-".to_string(),
+            pool: Arc::new(OnceLock::new()),
+            in_use: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Set the number of works waiting for a thread at most, the others being rejected with `BlockingError::Saturated`
+    pub fn queue_limit(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
+
+    /// Stop waiting for works which didn't complete within `timeout`, besides the deadline of the request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Prefix the name of the threads of the pool with `prefix`, `saphir-blocking-` by default
+    pub fn name_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.name_prefix = prefix.into();
+        self
+    }
+
+    /// Returns the number of works running or waiting for a thread
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Acquire)
+    }
+
+    /// Run `work` on the pool and wait for its result until `deadline`, if any, or the timeout of the pool
+    ///
+    /// Work which timed out keeps running to completion, its result being dropped, since a thread can't be interrupted.
+    /// Long works should watch the `CancellationToken` of their request to stop early.
+    pub fn run<F, T>(&self, deadline: Option<Instant>, work: F) -> Result<T, BlockingError>
+        where F: 'static + FnOnce() -> T + Send, T: 'static + Send {
+        if self.in_use.fetch_add(1, Ordering::AcqRel) >= self.threads + self.max_queued {
+            self.in_use.fetch_sub(1, Ordering::AcqRel);
+            return Err(BlockingError::Saturated);
+        }
+
+        let deadline = match (deadline, self.timeout.map(|timeout| Instant::now() + timeout)) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+            (deadline, timeout) => deadline.or(timeout),
+        };
+
+        let (tx, rx) = channel();
+        let in_use = InUse(self.in_use.clone());
+        self.pool().spawn_fn(move || {
+            let _in_use = in_use;
+            let _ = tx.send(work());
+            Ok::<(), ()>(())
+        }).forget();
+
+        match deadline {
+            Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())).map_err(|e| match e {
+                RecvTimeoutError::Timeout => BlockingError::TimedOut,
+                RecvTimeoutError::Disconnected => BlockingError::Panicked,
+            }),
+            None => rx.recv().map_err(|_| BlockingError::Panicked),
+        }
+    }
+
+    fn pool(&self) -> &CpuPool {
+        self.pool.get_or_init(|| {
+            CpuPoolBuilder::new().pool_size(self.threads).name_prefix(self.name_prefix.clone()).create()
+        })
+    }
+}
+
+/// A work accepted by a pool, released once it completed or panicked
+struct InUse(Arc<AtomicUsize>);
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Why a work offloaded with `SyncRequest::run_blocking` didn't produce its result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockingError {
+    /// Every thread of the pool was busy and its queue full
+    Saturated,
+    /// The work didn't complete before the deadline of the request or the timeout of the pool
+    TimedOut,
+    /// The work panicked
+    Panicked,
+}
+
+impl BlockingError {
+    /// Returns the status answering the request, `503 Service Unavailable` for a saturated pool, `504 Gateway Timeout` for
+    /// a work which timed out and `500 Internal Server Error` for one which panicked
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            BlockingError::Saturated => StatusCode::SERVICE_UNAVAILABLE,
+            BlockingError::TimedOut => StatusCode::GATEWAY_TIMEOUT,
+            BlockingError::Panicked => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Describe the error as a problem document of its status
+    pub fn problem(&self) -> Problem {
+        Problem::new(self.status()).with_detail(self.to_string())
+    }
+}
+
+impl fmt::Display for BlockingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockingError::Saturated => f.write_str("The server is too busy to process the request"),
+            BlockingError::TimedOut => f.write_str("The request couldn't be processed in time"),
+            BlockingError::Panicked => f.write_str("The request couldn't be processed"),
+        }
+    }
+}
+
+impl Error for BlockingError {}
+
+impl SyncRequest {
+    /// Run `work` on the `BlockingPool` of the server, see `ServerBuilder::blocking_pool`, and wait for its result until
+    /// the deadline of the request, if any. Without a pool, `work` runs right away on the current thread.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use std::time::Duration;
+    /// let mut controller = BasicController::new(());
+    /// controller.add(Method::POST, "^/thumbnails$", |_, req, res| {
+    ///     let image = req.body().clone();
+    ///     match req.run_blocking(move || image.iter().map(|&byte| byte / 2).collect::<Vec<u8>>()) {
+    ///         Ok(thumbnail) => res.status(StatusCode::OK).body(thumbnail),
+    ///         Err(e) => res.problem(&e.problem()),
+    ///     };
+    /// });
+    ///
+    /// let mut router = Router::new();
+    /// router.add("^/thumbnails$", controller);
+    ///
+    /// let server = Server::builder()
+    ///     .router(router)
+    ///     .blocking_pool(BlockingPool::new(4).queue_limit(16).timeout(Duration::from_secs(5)))
+    ///     .build();
+    /// ```
+    pub fn run_blocking<F, T>(&self, work: F) -> Result<T, BlockingError>
+        where F: 'static + FnOnce() -> T + Send, T: 'static + Send {
+        match self.state::<BlockingPool>() {
+            Some(pool) => pool.run(self.deadline(), work),
+            None => Ok(work()),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use http::*;
+use problem::Problem;
+use server_config::LimitConfig;
+use server_config::LimitsConfig;
+use regex::Regex;
+use utils::ToRegex;
+use std::time::Duration;
+
+/// The limits applied to the body of a request while it is received, see `BodyLimits`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BodyLimit {
+    max_size: Option<usize>,
+    timeout: Option<Duration>,
+    buffered: Option<bool>,
+}
+
+impl BodyLimit {
+    /// Create a limit which doesn't restrict anything, falling back to the less specific limits
+    pub fn new() -> Self {
+        BodyLimit::default()
+    }
+
+    /// Answer requests whose body is larger than `bytes` with `413 Payload Too Large`
+    pub fn max_size(mut self, bytes: usize) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Answer requests whose body isn't received within `timeout` with `408 Request Timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Load the body before the request is dispatched when `buffered` is true, the default, or leave it unread for the
+    /// handler to stream with `SyncRequest::body_reader` otherwise, like uploads written to disk as they are received or
+    /// requests forwarded by a `ProxyController`
+    ///
+    /// The body of an unbuffered request is rejected when its `Content-Length` exceeds the maximum size, and fails to be
+    /// read once it grows past it. The timeout only applies to buffered bodies.
+    pub fn buffered(mut self, buffered: bool) -> Self {
+        self.buffered = Some(buffered);
+        self
+    }
+
+    /// Returns the maximum size of the body, in bytes, `None` when unlimited
+    pub fn get_max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Returns how long the body may take to be received, `None` when unlimited
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns whether the body is loaded before the request is dispatched
+    pub fn is_buffered(&self) -> bool {
+        self.buffered.unwrap_or(true)
+    }
+
+    /// Fill the limits left unset with the ones of `fallback`
+    fn or(self, fallback: BodyLimit) -> BodyLimit {
+        BodyLimit {
+            max_size: self.max_size.or(fallback.max_size),
+            timeout: self.timeout.or(fallback.timeout),
+            buffered: self.buffered.or(fallback.buffered),
+        }
+    }
+}
+
+/// The limits of the request bodies accepted by a server, by route and by content type, see `ServerBuilder::body_limits`
+///
+/// The limits of a request are those of the first route matching its path, then those of the first content type matching
+/// its `Content-Type`, then the default ones: a route only setting a maximum size keeps the timeout of its content type.
+/// Bodies announcing a larger `Content-Length` are rejected before a single byte is read, the others as soon as they grow
+/// past the limit. Routes streaming their bodies leave them unread until the handler reads them, see `BodyLimit::buffered`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// use std::time::Duration;
+///
+/// let limits = BodyLimits::new(BodyLimit::new().max_size(64 * 1024).timeout(Duration::from_secs(10)))
+///     .content_type("application/json", BodyLimit::new().max_size(1024 * 1024))
+///     .content_type("multipart/*", BodyLimit::new().max_size(10 * 1024 * 1024))
+///     .route("^/videos$", BodyLimit::new().max_size(100 * 1024 * 1024).timeout(Duration::from_secs(300)))
+///     .route("^/backups$", BodyLimit::new().max_size(10 * 1024 * 1024 * 1024).buffered(false));
+///
+/// let server = Server::builder().router(Router::new()).body_limits(limits).build();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BodyLimits {
+    default: BodyLimit,
+    content_types: Vec<(String, BodyLimit)>,
+    routes: Vec<(Regex, BodyLimit)>,
+}
+
+impl BodyLimits {
+    /// Create limits applying `default` to every request
+    pub fn new(default: BodyLimit) -> Self {
+        BodyLimits {
+            default,
+            content_types: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Apply `limit` to the requests of `content_type`, like `application/json`, or of a whole type, like `multipart/*`.
+    /// Parameters of the `Content-Type` of the requests are ignored.
+    pub fn content_type(mut self, content_type: &str, limit: BodyLimit) -> Self {
+        self.content_types.push((content_type.trim().to_ascii_lowercase(), limit));
+        self
+    }
+
+    /// Apply `limit` to the requests whose path matches the regex `path`
+    pub fn route<R: ToRegex>(mut self, path: R, limit: BodyLimit) -> Self {
+        self.routes.push((reg!(path), limit));
+        self
+    }
+
+    /// Returns the limits of a request to `path` with the given headers
+    pub(crate) fn resolve(&self, path: &str, headers: &header::HeaderMap) -> BodyLimit {
+        let route = self.routes.iter().find(|(route, _)| route.is_match(path)).map(|(_, limit)| *limit).unwrap_or_default();
+
+        let content_type = headers.get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase());
+        let content_type = content_type
+            .and_then(|content_type| self.content_types.iter().find(|(pattern, _)| media_type_matches(pattern, &content_type)))
+            .map(|(_, limit)| *limit)
+            .unwrap_or_default();
+
+        route.or(content_type).or(self.default)
+    }
+
+    /// Describe the limits as the `limits` section of a `ServerConfig`
+    pub(crate) fn config(&self) -> LimitsConfig {
+        let limit = |limit: &BodyLimit| LimitConfig {
+            max_body_size: limit.max_size,
+            body_timeout_ms: limit.timeout.map(|timeout| timeout.as_millis() as u64),
+            buffered: limit.buffered,
+            ..LimitConfig::default()
+        };
+
+        LimitsConfig {
+            max_body_size: self.default.max_size,
+            body_timeout_ms: self.default.timeout.map(|timeout| timeout.as_millis() as u64),
+            content_types: self.content_types.iter()
+                .map(|(content_type, l)| LimitConfig { content_type: Some(content_type.clone()), ..limit(l) })
+                .collect(),
+            routes: self.routes.iter()
+                .map(|(path, l)| LimitConfig { path: Some(path.as_str().to_string()), ..limit(l) })
+                .collect(),
+        }
+    }
+}
+
+fn media_type_matches(pattern: &str, media_type: &str) -> bool {
+    if pattern == "*/*" {
+        return true;
+    }
+
+    match pattern.strip_suffix("/*") {
+        Some(type_) => media_type.split('/').next() == Some(type_),
+        None => pattern == media_type,
+    }
+}
+
+/// Why the body of a request was rejected, kept in the extensions of the request, which is left without a body
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BodyRejection {
+    TooLarge(usize),
+    Timeout(Duration),
+}
+
+impl BodyRejection {
+    /// Returns why the body of `request` was rejected while it was received, or whether it exceeds `limit` once received
+    pub(crate) fn of(request: &SyncRequest, limit: BodyLimit) -> Option<BodyRejection> {
+        if let Some(rejection) = request.extensions().get::<BodyRejection>() {
+            return Some(*rejection);
+        }
+
+        match limit.max_size {
+            Some(max_size) if request.body().len() > max_size => Some(BodyRejection::TooLarge(max_size)),
+            _ => None,
+        }
+    }
+
+    /// Answer the request with a problem document
+    pub(crate) fn describe(&self, res: &mut SyncResponse) {
+        let problem = match *self {
+            BodyRejection::TooLarge(max_size) => Problem::new(StatusCode::PAYLOAD_TOO_LARGE)
+                .with_detail(format!("The request body exceeds the limit of {} bytes", max_size)),
+            BodyRejection::Timeout(timeout) => Problem::new(StatusCode::REQUEST_TIMEOUT)
+                .with_detail(format!("The request body wasn't received within {:?}", timeout)),
+        };
+
+        res.problem(&problem);
+    }
+}
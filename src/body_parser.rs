@@ -0,0 +1,214 @@
+use http::*;
+use problem::Problem;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// A decoder of the request bodies of a media type the server doesn't decode by itself, see `ServerBuilder::body_decoder`
+///
+/// Bodies are decoded to a JSON value, which is then deserialized to the type asked by `SyncRequest::parse_body`.
+pub trait BodyDecoder: Send + Sync {
+    /// Decode `body`, or describe why it is invalid
+    fn decode(&self, body: &[u8]) -> Result<Value, String>;
+}
+
+impl<F> BodyDecoder for F where F: Fn(&[u8]) -> Result<Value, String> + Send + Sync {
+    fn decode(&self, body: &[u8]) -> Result<Value, String> {
+        self(body)
+    }
+}
+
+/// The decoders registered on the server, by media type, inserted in the extensions of every request
+#[derive(Clone, Default)]
+pub(crate) struct BodyDecoders(pub(crate) Arc<HashMap<String, Arc<dyn BodyDecoder>>>);
+
+/// Why the body of a request couldn't be parsed by `SyncRequest::parse_body`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyParseError {
+    /// The request has no `Content-Type`, or one no decoder is registered for
+    UnsupportedMediaType {
+        /// The media type of the request, if it has one
+        media_type: Option<String>,
+        /// The media types which can be decoded
+        supported: Vec<String>,
+    },
+    /// The body isn't valid in its media type, or doesn't match the expected type
+    Invalid {
+        /// The media type of the request
+        media_type: String,
+        /// The error of the decoder
+        message: String,
+    },
+}
+
+impl BodyParseError {
+    /// Returns the status answering the request, `415 Unsupported Media Type` for a media type which can't be decoded and
+    /// `400 Bad Request` for an invalid body
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            BodyParseError::UnsupportedMediaType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BodyParseError::Invalid { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Describe the error as a problem document of its status, listing the supported media types when the one of the
+    /// request isn't
+    pub fn problem(&self) -> Problem {
+        let problem = Problem::new(self.status()).with_detail(self.to_string());
+        match *self {
+            BodyParseError::UnsupportedMediaType { ref supported, .. } => problem.with_extension("supported", supported),
+            BodyParseError::Invalid { .. } => problem,
+        }
+    }
+
+    /// Answer `res` with the problem describing the error, along with the `Accept-Post` and `Accept-Patch` headers listing
+    /// the supported media types when the one of the request isn't
+    pub fn answer<'r>(&self, req: &SyncRequest, res: &'r mut SyncResponse) -> &'r mut SyncResponse {
+        if let BodyParseError::UnsupportedMediaType { ref supported, .. } = *self {
+            match *req.method() {
+                Method::POST => { res.header("accept-post", supported.join(", ")); }
+                Method::PATCH => { res.header("accept-patch", supported.join(", ")); }
+                _ => {}
+            }
+        }
+
+        res.problem(&self.problem())
+    }
+}
+
+impl fmt::Display for BodyParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyParseError::UnsupportedMediaType { media_type: Some(ref media_type), .. } => write!(f, "The media type {} isn't supported", media_type),
+            BodyParseError::UnsupportedMediaType { media_type: None, .. } => f.write_str("The request has no Content-Type"),
+            BodyParseError::Invalid { ref media_type, ref message } => write!(f, "The {} body is invalid: {}", media_type, message),
+        }
+    }
+}
+
+impl Error for BodyParseError {}
+
+/// The formats decoded by the server, without any decoder registered
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Form,
+    #[cfg(feature = "xml")]
+    Xml,
+    #[cfg(feature = "msgpack")]
+    MsgPack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+/// Returns the format of `media_type`, including the structured syntax suffixes like `application/problem+json`
+fn builtin_format(media_type: &str) -> Option<Format> {
+    match media_type {
+        "application/json" => Some(Format::Json),
+        "application/x-www-form-urlencoded" => Some(Format::Form),
+        #[cfg(feature = "xml")]
+        "application/xml" | "text/xml" => Some(Format::Xml),
+        #[cfg(feature = "msgpack")]
+        "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Format::MsgPack),
+        #[cfg(feature = "cbor")]
+        "application/cbor" => Some(Format::Cbor),
+        _ if media_type.ends_with("+json") => Some(Format::Json),
+        #[cfg(feature = "xml")]
+        _ if media_type.ends_with("+xml") => Some(Format::Xml),
+        #[cfg(feature = "cbor")]
+        _ if media_type.ends_with("+cbor") => Some(Format::Cbor),
+        _ => None,
+    }
+}
+
+/// Returns the media types decoded by the server, without any decoder registered
+fn builtin_media_types() -> Vec<&'static str> {
+    let mut media_types = vec!["application/json", "application/x-www-form-urlencoded"];
+
+    if cfg!(feature = "xml") {
+        media_types.push("application/xml");
+    }
+
+    if cfg!(feature = "msgpack") {
+        media_types.push("application/msgpack");
+    }
+
+    if cfg!(feature = "cbor") {
+        media_types.push("application/cbor");
+    }
+
+    media_types
+}
+
+impl SyncRequest {
+    /// Deserialize the body of the request according to its `Content-Type`: JSON, including the `+json` media types, and
+    /// forms are always decoded, XML, MessagePack and CBOR are with their features, and the other media types are decoded by
+    /// the decoders registered with `ServerBuilder::body_decoder`, which take precedence.
+    ///
+    /// A request without `Content-Type` or of a media type which can't be decoded fails with
+    /// `BodyParseError::UnsupportedMediaType`, answered with `415 Unsupported Media Type`, see `BodyParseError::answer`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate saphir;
+    /// # use saphir::*;
+    /// #[derive(Deserialize)]
+    /// struct Order {
+    ///     item: String,
+    ///     quantity: u32,
+    /// }
+    ///
+    /// fn create(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     match req.parse_body::<Order>() {
+    ///         Ok(order) => { res.status(StatusCode::CREATED).body(format!("{} {}", order.quantity, order.item)); }
+    ///         Err(e) => { e.answer(req, res); }
+    ///     }
+    /// }
+    /// # fn main() {}
+    /// ```
+    pub fn parse_body<T: DeserializeOwned>(&self) -> Result<T, BodyParseError> {
+        let decoders = self.extensions().get::<BodyDecoders>();
+        let media_type = self.headers_map().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok())
+            .map(|c| c.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+            .filter(|media_type| !media_type.is_empty());
+
+        let unsupported = |media_type: Option<String>| {
+            let mut supported = decoders.map(|decoders| decoders.0.keys().cloned().collect::<Vec<_>>()).unwrap_or_default();
+            supported.sort();
+            for builtin in builtin_media_types() {
+                if !supported.iter().any(|media_type| media_type == builtin) {
+                    supported.push(builtin.to_string());
+                }
+            }
+            BodyParseError::UnsupportedMediaType { media_type, supported }
+        };
+
+        let media_type = match media_type {
+            Some(media_type) => media_type,
+            None => return Err(unsupported(None)),
+        };
+        let invalid = |message: String| BodyParseError::Invalid { media_type: media_type.clone(), message };
+
+        if let Some(decoder) = decoders.and_then(|decoders| decoders.0.get(&media_type)) {
+            let value = decoder.decode(self.body()).map_err(&invalid)?;
+            return ::serde_json::from_value(value).map_err(|e| invalid(e.to_string()));
+        }
+
+        match builtin_format(&media_type) {
+            Some(Format::Json) => self.body_json().map_err(|e| invalid(e.to_string())),
+            Some(Format::Form) => self.body_form().map_err(|e| invalid(e.to_string())),
+            #[cfg(feature = "xml")]
+            Some(Format::Xml) => self.body_xml().map_err(|e| invalid(e.to_string())),
+            #[cfg(feature = "msgpack")]
+            Some(Format::MsgPack) => self.body_msgpack().map_err(|e| invalid(e.to_string())),
+            #[cfg(feature = "cbor")]
+            Some(Format::Cbor) => self.body_cbor().map_err(|e| invalid(e.to_string())),
+            None => Err(unsupported(Some(media_type.clone()))),
+        }
+    }
+}
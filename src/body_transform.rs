@@ -0,0 +1,230 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use futures::Async;
+use futures::Poll;
+use futures::Stream;
+use hyper::Chunk;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+/// A transformation of the bytes of a body, like its encryption, fed with the chunks of the body in order
+///
+/// The trait is implemented by any function taking a chunk and returning its transformation, for stateless ones.
+pub trait BodyTransform: Send {
+    /// Transform the next chunk of the body, returning the bytes to send in its place, which may be empty
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Returns the bytes ending the transformed body once every chunk went through `chunk`, nothing by default
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+
+impl<F> BodyTransform for F where F: FnMut(&[u8]) -> io::Result<Vec<u8>> + Send {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        (*self)(chunk)
+    }
+}
+
+/// A trait for the transformations `BodyTransformMiddleware` applies to the bodies of requests and responses
+pub trait BodyTransformer: Send + Sync {
+    /// Returns the transformation of the body of `req`, `None` to leave it untouched, which is the default
+    fn request(&self, _req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+        None
+    }
+
+    /// Returns the transformation of the body of `res`, `None` to leave it untouched, which is the default. The headers of
+    /// the response may be altered to describe the transformed body, like its `Content-Type`.
+    fn response(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+        None
+    }
+}
+
+/// Run `transform` over the whole of `body`
+fn transform_all(mut transform: Box<dyn BodyTransform>, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut transformed = transform.chunk(body)?;
+    transformed.extend(transform.finish()?);
+    Ok(transformed)
+}
+
+/// A middleware replacing the bodies of requests and responses with the transformations of a `BodyTransformer`, for
+/// gateways encrypting or signing bodies, or transcoding them on the fly
+///
+/// The body of a request is transformed at once in `Middleware::prepare`, before the following middlewares and the
+/// handler see it, a failing transformation answers the request with `400 Bad Request`. The body as received stays
+/// available with `SyncRequest::raw_body`. The body of a response is
+/// transformed once the handler and the following middlewares are done with it: at once, a failing transformation
+/// answering with `500 Internal Server Error`, or chunk by chunk as they are sent when the body is streamed, a failing
+/// transformation aborting the stream. The `Content-Length` header is updated or removed accordingly.
+///
+/// Several transformers compose like any middlewares: request bodies go through them in the order they were applied to
+/// the stack, and response bodies in the reverse order, so the first one applied always handles the body as the client
+/// sees it.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::io;
+/// struct Rot13;
+///
+/// fn rot13(chunk: &[u8]) -> io::Result<Vec<u8>> {
+///     Ok(chunk.iter().map(|&b| match b {
+///         b'a'..=b'z' => (b - b'a' + 13) % 26 + b'a',
+///         b'A'..=b'Z' => (b - b'A' + 13) % 26 + b'A',
+///         _ => b,
+///     }).collect())
+/// }
+///
+/// impl BodyTransformer for Rot13 {
+///     fn request(&self, _req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+///         Some(Box::new(rot13))
+///     }
+///
+///     fn response(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+///         Some(Box::new(rot13))
+///     }
+/// }
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(BodyTransformMiddleware::new(Rot13), vec!("/"), None);
+/// ```
+pub struct BodyTransformMiddleware {
+    transformer: Box<dyn BodyTransformer>,
+}
+
+impl BodyTransformMiddleware {
+    /// Create a middleware applying the transformations of `transformer`
+    pub fn new<T: 'static + BodyTransformer>(transformer: T) -> Self {
+        BodyTransformMiddleware {
+            transformer: Box::new(transformer),
+        }
+    }
+}
+
+impl Middleware for BodyTransformMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let transform = match self.transformer.request(req) {
+            Some(transform) => transform,
+            None => return RequestContinuation::Next,
+        };
+
+        match transform_all(transform, req.body()) {
+            Ok(body) => {
+                if req.headers_map().contains_key(header::CONTENT_LENGTH) {
+                    req.headers_map_mut().insert(header::CONTENT_LENGTH, header::HeaderValue::from(body.len()));
+                }
+                req.replace_body(body);
+                RequestContinuation::Next
+            }
+            Err(e) => {
+                warn!("Unable to transform the body of {} {}: {}", req.method(), req.uri(), e);
+                res.status(StatusCode::BAD_REQUEST);
+                RequestContinuation::None
+            }
+        }
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let transform = match self.transformer.response(req, res) {
+            Some(transform) => transform,
+            None => return,
+        };
+
+        res.headers_map_mut().remove(header::CONTENT_LENGTH);
+
+        if res.is_streamed() {
+            return transform_streamed(res, transform);
+        }
+
+        match transform_all(transform, &res.get_body()) {
+            Ok(body) => {
+                res.body(body);
+            }
+            Err(e) => {
+                warn!("Unable to transform the response body of {} {}: {}", req.method(), req.uri(), e);
+                res.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+            }
+        }
+    }
+}
+
+/// Transform the streamed body of `res` with `transform`, chunk by chunk as they are sent
+pub(crate) fn transform_streamed(res: &mut SyncResponse, transform: Box<dyn BodyTransform>) {
+    let inner = res.take_body();
+    res.body(TransformedBody {
+        inner,
+        transform: Mutex::new(Some(transform)),
+        sent: Arc::new(Mutex::new(Vec::new())),
+    });
+}
+
+/// A streamed body transformed chunk by chunk, keeping a copy of the transformed chunks like the body it wraps
+struct TransformedBody {
+    inner: Box<dyn ToBody>,
+    transform: Mutex<Option<Box<dyn BodyTransform>>>,
+    sent: Arc<Mutex<Vec<u8>>>,
+}
+
+impl ToBody for TransformedBody {
+    fn to_body(&self) -> Body {
+        match self.transform.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(transform) => Body::wrap_stream(TransformedStream {
+                inner: self.inner.to_body(),
+                transform,
+                sent: Arc::downgrade(&self.sent),
+                finished: false,
+            }),
+            None => self.sent.lock().unwrap_or_else(|e| e.into_inner()).clone().into(),
+        }
+    }
+}
+
+struct TransformedStream {
+    inner: Body,
+    transform: Box<dyn BodyTransform>,
+    sent: Weak<Mutex<Vec<u8>>>,
+    finished: bool,
+}
+
+impl TransformedStream {
+    fn send(&self, transformed: Vec<u8>) -> Chunk {
+        if let Some(sent) = self.sent.upgrade() {
+            sent.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(&transformed);
+        }
+
+        Chunk::from(transformed)
+    }
+}
+
+impl Stream for TransformedStream {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        while !self.finished {
+            let transformed = match self.inner.poll() {
+                Ok(Async::Ready(Some(chunk))) => self.transform.chunk(&chunk)?,
+                Ok(Async::Ready(None)) => {
+                    self.finished = true;
+                    self.transform.finish()?
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => return Err(io::Error::other(e)),
+            };
+
+            if !transformed.is_empty() {
+                return Ok(Async::Ready(Some(self.send(transformed))));
+            }
+        }
+
+        Ok(Async::Ready(None))
+    }
+}
@@ -0,0 +1,171 @@
+use http::*;
+use controller::RequestGuard;
+use middleware::Middleware;
+use query::query_pairs;
+use utils::RequestContinuation;
+use logging::*;
+use log::Level;
+use regex::Regex;
+
+/// A service telling how likely a request is to come from a bot, like a reputation database or a CAPTCHA provider, see
+/// `BotGuard::scorer`
+///
+/// The trait is implemented by any function taking a request and returning its score.
+pub trait BotScorer: Send + Sync {
+    /// Returns the likelihood of `req` coming from a bot, from `0.0` for a human to `1.0` for a bot, `None` when it can't
+    /// be told, like when the service is unavailable, in which case the request is let through
+    fn score(&self, req: &SyncRequest) -> Option<f32>;
+}
+
+impl<F> BotScorer for F where F: Fn(&SyncRequest) -> Option<f32> + Send + Sync {
+    fn score(&self, req: &SyncRequest) -> Option<f32> {
+        (*self)(req)
+    }
+}
+
+/// A guard rejecting the requests which look like they come from bots, with `403 Forbidden` by default, before the handler
+/// sees them
+///
+/// A request is rejected when its `User-Agent` matches a denied pattern, when it lacks one of the headers every browser
+/// sends, when the hidden honeypot field of its form was filled, which humans never see, or when an external scorer finds
+/// it likely enough to come from a bot. The rules are checked in this order, the cheapest first, and the reason of a
+/// rejection is logged but not told to the client.
+///
+/// The guard can also be applied to a `MiddlewareStack`, to protect every route under a path.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let guard = BotGuard::new()
+///     .deny_user_agent("curl|python-requests|scrapy")
+///     .browser_headers()
+///     .honeypot("website")
+///     .scorer(|req: &SyncRequest| if req.header_list("x-bot-score").is_empty() { None } else { Some(0.95) }, 0.9);
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::POST, "^/signup$", guard.into(), |_, _, res| { res.status(StatusCode::CREATED); });
+/// ```
+pub struct BotGuard {
+    user_agents: Vec<Regex>,
+    required_headers: Vec<header::HeaderName>,
+    honeypot: Option<String>,
+    scorers: Vec<(Box<dyn BotScorer>, f32)>,
+    status: StatusCode,
+}
+
+impl BotGuard {
+    /// Create a guard without any rule, rejecting with `403 Forbidden`
+    pub fn new() -> Self {
+        BotGuard {
+            user_agents: Vec::new(),
+            required_headers: Vec::new(),
+            honeypot: None,
+            scorers: Vec::new(),
+            status: StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// Reject the requests whose `User-Agent` matches the regular expression `pattern`, regardless of its case
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` isn't a valid regular expression.
+    pub fn deny_user_agent(mut self, pattern: &str) -> Self {
+        self.user_agents.push(Regex::new(&format!("(?i){}", pattern)).expect("valid user agent pattern"));
+        self
+    }
+
+    /// Reject the requests without the header `name`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn require_header(mut self, name: &str) -> Self {
+        let name = header::HeaderName::from_bytes(name.as_bytes()).expect("valid required header name");
+        if !self.required_headers.contains(&name) {
+            self.required_headers.push(name);
+        }
+        self
+    }
+
+    /// Reject the requests without the `User-Agent`, `Accept` and `Accept-Language` headers, which browsers always send
+    /// but simple scripts usually don't
+    pub fn browser_headers(self) -> Self {
+        self.require_header("user-agent").require_header("accept").require_header("accept-language")
+    }
+
+    /// Reject the forms whose field `name` isn't empty, a field hidden from humans by the page but filled by bots
+    pub fn honeypot(mut self, name: &str) -> Self {
+        self.honeypot = Some(name.to_string());
+        self
+    }
+
+    /// Reject the requests `scorer` scores at `threshold` or more
+    pub fn scorer<S: 'static + BotScorer>(mut self, scorer: S, threshold: f32) -> Self {
+        self.scorers.push((Box::new(scorer), threshold));
+        self
+    }
+
+    /// Reject the requests with `status` rather than `403 Forbidden`, like `404 Not Found` to hide the route from bots
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Returns why `req` looks like it comes from a bot, `None` when it doesn't
+    fn rejection(&self, req: &SyncRequest) -> Option<String> {
+        let user_agent = req.headers_map().get(header::USER_AGENT).and_then(|v| v.to_str().ok()).unwrap_or("");
+        if let Some(pattern) = self.user_agents.iter().find(|pattern| pattern.is_match(user_agent)) {
+            return Some(format!("the user agent matches {}", pattern.as_str().trim_start_matches("(?i)")));
+        }
+
+        if let Some(name) = self.required_headers.iter().find(|name| !req.headers_map().contains_key(*name)) {
+            return Some(format!("the {} header is missing", name));
+        }
+
+        if let Some(ref field) = self.honeypot {
+            if req.is_form() {
+                let body = String::from_utf8_lossy(req.body());
+                if query_pairs(&body).iter().any(|(name, value)| name == field && !value.is_empty()) {
+                    return Some(format!("the honeypot field {} is filled", field));
+                }
+            }
+        }
+
+        for (scorer, threshold) in &self.scorers {
+            if let Some(score) = scorer.score(req).filter(|score| score >= threshold) {
+                return Some(format!("its score of {} reaches {}", score, threshold));
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for BotGuard {
+    fn default() -> Self {
+        BotGuard::new()
+    }
+}
+
+impl RequestGuard for BotGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.rejection(req) {
+            Some(reason) => {
+                log_event(Level::Info, GUARD_LOG_TARGET, "bot rejected",
+                          format_args!("Rejected {} {} as a bot: {}", req.method(), req.uri().path(), reason),
+                          &[("method", req.method()), ("path", &req.uri().path()), ("reason", &reason)]);
+                res.status(self.status);
+                RequestContinuation::None
+            }
+            None => RequestContinuation::Next,
+        }
+    }
+}
+
+impl Middleware for BotGuard {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        self.validate(req, res)
+    }
+}
@@ -0,0 +1,124 @@
+use http::*;
+use futures::Future;
+use futures::future::ok;
+use trailers::LoadPooledBody;
+use body_limits::BodyLimit;
+use body_limits::BodyRejection;
+use request_body::UnreadBody;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// Number of buffers kept by the pool of a connection, requests of a connection are mostly received one after the other
+const POOLED_BUFFERS: usize = 2;
+
+/// Buffers growing larger than this are not kept, so a single large upload doesn't pin its memory for the whole connection
+const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+/// Counters of the body buffers pools of a server, shared by every connection
+///
+/// A hit is a request body loaded into a buffer reused from a previous request of the same connection, a miss is a request
+/// body for which a new buffer was allocated. Requests without a body are not counted.
+#[derive(Clone, Default)]
+pub struct BufferPoolStats {
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+}
+
+impl BufferPoolStats {
+    /// Returns the number of request bodies loaded into a reused buffer
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of request bodies for which a buffer was allocated
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the proportion of request bodies loaded into a reused buffer, between 0 and 1
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.hits();
+        let total = hits + self.misses();
+
+        if total == 0 {
+            0.0
+        } else {
+            hits as f64 / total as f64
+        }
+    }
+}
+
+/// A pool of buffers reused to load the bodies of the requests received on a connection
+///
+/// Response bodies are not pooled, they are handed over to hyper which releases them once written.
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    stats: BufferPoolStats,
+}
+
+impl BufferPool {
+    /// Create an empty pool reporting to `stats`
+    pub fn new(stats: BufferPoolStats) -> Self {
+        BufferPool {
+            buffers: Mutex::new(Vec::with_capacity(POOLED_BUFFERS)),
+            stats,
+        }
+    }
+
+    /// Take an empty buffer from the pool, or allocate one holding at least `capacity` bytes
+    pub fn take(&self, capacity: usize) -> Vec<u8> {
+        let pooled = self.buffers.lock().unwrap_or_else(|e| e.into_inner()).pop();
+
+        match pooled {
+            Some(mut buffer) => {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                buffer.reserve(capacity);
+                buffer
+            }
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(capacity)
+            }
+        }
+    }
+
+    /// Give a buffer back to the pool once the request it holds is processed
+    pub fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < POOLED_BUFFERS {
+            buffer.clear();
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// Load the body of `req` into a buffer of `pool`, sized from the `Content-Length` of the request, along with its trailers.
+/// A body exceeding `limit` is not loaded, the request is completed without it. A body `limit` doesn't buffer is left
+/// unread, for the handler to stream.
+pub fn load_pooled_body(req: Request<Body>, pool: Arc<BufferPool>, limit: BodyLimit) -> Box<dyn Future<Item=SyncRequest, Error=::hyper::Error> + Send> {
+    let content_length = req.headers().get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+
+    if !limit.is_buffered() {
+        let (parts, body) = req.into_parts();
+        let mut request = SyncRequest::new(parts, Vec::new());
+        match limit.get_max_size() {
+            Some(max_size) if content_length.is_some_and(|len| len > max_size) => {
+                request.extensions_mut().insert(BodyRejection::TooLarge(max_size));
+            }
+            max_size => {
+                request.extensions_mut().insert(UnreadBody::new(body, max_size));
+            }
+        }
+        return Box::new(ok(request));
+    }
+
+    Box::new(LoadPooledBody::new(req, pool, content_length.map_or(0, |len| len.min(MAX_POOLED_CAPACITY)), limit))
+}
@@ -0,0 +1,159 @@
+use http::*;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    client_gone: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+}
+
+/// Tells whether the response to a request can still be delivered, for handlers to abort expensive work when it can't
+///
+/// Every request processed by the server has one, see `SyncRequest::cancellation_token`. It is cancelled when the
+/// client is gone before the response is sent, including while a streamed body is sent, and its deadline is set by the
+/// timeout of the `RoutePolicy` of the route. Tokens are cheap handles which can be cloned, like into a thread working
+/// for the handler.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl CancellationToken {
+    /// Create a token which is neither cancelled nor has a deadline
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Cancel the token, the response won't be delivered
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Cancel the token because the client is gone, closing the connection before the response was sent
+    pub(crate) fn disconnect(&self) {
+        self.state.client_gone.store(true, Ordering::Release);
+        self.cancel();
+    }
+
+    /// Returns true once the client closed the connection before receiving the whole response
+    pub fn is_client_gone(&self) -> bool {
+        self.state.client_gone.load(Ordering::Acquire)
+    }
+
+    /// Returns true once the token is cancelled or its deadline has passed
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire) || self.deadline().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns the instant after which the response won't be delivered, if any
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.state.deadline.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set the deadline of the token to `deadline`, unless it already has an earlier one
+    pub fn set_deadline(&self, deadline: Instant) {
+        let mut current = self.state.deadline.lock().unwrap_or_else(|e| e.into_inner());
+        if current.is_none_or(|current| deadline < current) {
+            *current = Some(deadline);
+        }
+    }
+
+    /// Returns the time left until the deadline, `None` without a deadline
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+}
+
+impl SyncRequest {
+    /// Returns the cancellation token of the request, a new one when the request isn't processed by a server
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn report(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let mut rows = Vec::new();
+    ///     for page in 0..1000 {
+    ///         if req.is_cancelled() {
+    ///             return;
+    ///         }
+    ///         rows.push(page.to_string());
+    ///     }
+    ///     res.status(StatusCode::OK).body(rows.join("\n"));
+    /// }
+    /// ```
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.extensions().get::<CancellationToken>().cloned().unwrap_or_default()
+    }
+
+    /// Returns the instant after which the response to the request won't be delivered, if any, see `CancellationToken`
+    pub fn deadline(&self) -> Option<Instant> {
+        self.extensions().get::<CancellationToken>().and_then(|token| token.deadline())
+    }
+
+    /// Returns true once the response to the request can no longer be delivered, see `CancellationToken`
+    pub fn is_cancelled(&self) -> bool {
+        self.extensions().get::<CancellationToken>().is_some_and(|token| token.is_cancelled())
+    }
+}
+
+impl SyncResponse {
+    /// Returns true once the client closed the connection, the response being built won't be delivered
+    ///
+    /// The client is watched while the handler runs, and while the body of a response streamed with `stream` is sent,
+    /// so the producer of a stream can stop through the `CancellationToken` of the request. A response which isn't sent
+    /// by a server never has its client gone.
+    pub fn is_client_gone(&self) -> bool {
+        self.get_extensions().get::<CancellationToken>().is_some_and(|token| token.is_client_gone())
+    }
+}
+
+/// A future cancelling a token when dropped before completing, like the future of a response dropped by hyper once the
+/// client is gone
+pub(crate) struct CancelOnDrop<F> {
+    future: F,
+    token: CancellationToken,
+    done: bool,
+}
+
+impl<F> CancelOnDrop<F> {
+    pub(crate) fn new(future: F, token: CancellationToken) -> Self {
+        CancelOnDrop {
+            future,
+            token,
+            done: false,
+        }
+    }
+}
+
+impl<F: Future> Future for CancelOnDrop<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let polled = self.future.poll();
+        if let Ok(Async::NotReady) = polled {
+            return polled;
+        }
+
+        self.done = true;
+        polled
+    }
+}
+
+impl<F> Drop for CancelOnDrop<F> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.token.disconnect();
+        }
+    }
+}
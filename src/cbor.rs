@@ -0,0 +1,26 @@
+use http::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use profiling::Phase;
+use profiling::profiled;
+
+impl SyncRequest {
+    /// Deserialize the body of the request from CBOR
+    pub fn body_cbor<T: DeserializeOwned>(&self) -> Result<T, ::serde_cbor::Error> {
+        ::serde_cbor::from_slice(self.body())
+    }
+}
+
+impl SyncResponse {
+    /// Serialize `value` as CBOR and set it as the body of the response, along with the `application/cbor` content type.
+    ///
+    /// If the value cannot be serialized, the server answers with a `500 Internal Server Error`, see `SerializationError`.
+    pub fn cbor<T: Serialize>(&mut self, value: &T) -> &mut SyncResponse {
+        match profiled(self, || Phase::Serialization, |_| ::serde_cbor::to_vec(value)) {
+            Ok(body) => {
+                self.header(header::CONTENT_TYPE, "application/cbor").body(body)
+            }
+            Err(e) => self.serialization_failed("cbor", e),
+        }
+    }
+}
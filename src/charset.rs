@@ -0,0 +1,92 @@
+use http::*;
+use encoding_rs::Encoding;
+use encoding_rs::UTF_8;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+
+/// Why the body of a request couldn't be decoded as text, see `SyncRequest::body_text`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextError {
+    /// The charset of the `Content-Type` header isn't known
+    UnsupportedCharset(String),
+    /// The body isn't valid in its charset, named here
+    Malformed(&'static str),
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TextError::UnsupportedCharset(ref charset) => write!(f, "unsupported charset {}", charset),
+            TextError::Malformed(charset) => write!(f, "the body isn't valid {}", charset),
+        }
+    }
+}
+
+impl Error for TextError {}
+
+/// Returns the value of the `charset` parameter of the media type `content_type`, unquoted
+fn charset_param(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") { Some(value.trim().trim_matches('"')) } else { None }
+    })
+}
+
+impl SyncRequest {
+    /// Returns the `charset` parameter of the `Content-Type` header of the request, if any
+    pub fn charset(&self) -> Option<&str> {
+        self.headers_map().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).and_then(charset_param)
+    }
+
+    /// Decode the body of the request as text, in the charset of its `Content-Type` header, like `ISO-8859-1` or
+    /// `Shift_JIS`, or in UTF-8 when it has none
+    ///
+    /// A byte order mark prevails over the charset of the header, and is removed from the text. Charsets are the ones of
+    /// the WHATWG Encoding Standard, which web clients send: `ISO-8859-1` is decoded as its `windows-1252` superset.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn comment(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     match req.body_text() {
+    ///         Ok(text) => { res.status(StatusCode::OK).body(format!("{} characters", text.chars().count())); }
+    ///         Err(e) => { res.status(StatusCode::UNSUPPORTED_MEDIA_TYPE).body(e.to_string()); }
+    ///     }
+    /// }
+    /// ```
+    pub fn body_text(&self) -> Result<Cow<'_, str>, TextError> {
+        let encoding = match self.charset() {
+            Some(charset) => Encoding::for_label(charset.as_bytes()).ok_or_else(|| TextError::UnsupportedCharset(charset.to_string()))?,
+            None => UTF_8,
+        };
+
+        let body = self.body();
+        let (encoding, body) = match Encoding::for_bom(body) {
+            Some((encoding, bom_length)) => (encoding, &body[bom_length..]),
+            None => (encoding, &body[..]),
+        };
+
+        encoding.decode_without_bom_handling_and_without_replacement(body).ok_or(TextError::Malformed(encoding.name()))
+    }
+}
+
+/// Add `; charset=utf-8` to the `text/*` content type of `res` when it has no charset, and its body is valid UTF-8
+///
+/// Streamed bodies, which can't be checked, are left untouched.
+pub(crate) fn default_charset(res: &mut SyncResponse) {
+    let content_type = match res.headers_map().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(content_type) if content_type.trim_start().get(..5).is_some_and(|t| t.eq_ignore_ascii_case("text/"))
+            && charset_param(content_type).is_none() => content_type.to_string(),
+        _ => return,
+    };
+
+    if res.is_streamed() || ::std::str::from_utf8(&res.get_body()).is_err() {
+        return;
+    }
+
+    if let Ok(value) = header::HeaderValue::from_str(&format!("{}; charset=utf-8", content_type.trim_end().trim_end_matches(';'))) {
+        res.headers_map_mut().insert(header::CONTENT_TYPE, value);
+    }
+}
@@ -0,0 +1,244 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use utils::ToRegex;
+use regex::Regex;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The state of a circuit of a `CircuitBreakerMiddleware`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through, their outcome is tracked
+    Closed,
+    /// Requests are rejected with `503 Service Unavailable` without reaching the upstream
+    Open,
+    /// A limited number of probe requests go through, their outcome closes or opens the circuit again
+    HalfOpen,
+}
+
+struct Circuit {
+    state: CircuitState,
+    outcomes: VecDeque<bool>,
+    opened_at: Instant,
+    probes: usize,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Circuit {
+            state: CircuitState::Closed,
+            outcomes: VecDeque::new(),
+            opened_at: Instant::now(),
+            probes: 0,
+        }
+    }
+
+    fn open(&mut self) {
+        self.state = CircuitState::Open;
+        self.opened_at = Instant::now();
+        self.outcomes.clear();
+        self.probes = 0;
+    }
+
+    fn close(&mut self) {
+        self.state = CircuitState::Closed;
+        self.outcomes.clear();
+        self.probes = 0;
+    }
+}
+
+/// Marks a response whose outcome must be recorded in the circuit `key`
+struct Admitted {
+    key: String,
+    probe: bool,
+}
+
+/// A middleware protecting failing upstreams, typically reached through a `ProxyController`, by rejecting the requests
+/// routed to them until they recover
+///
+/// Every route has its own circuit tracking the outcome of its last requests, a response with a server error status being a
+/// failure. Once the failure rate of a closed circuit reaches the threshold, over at least the minimum number of requests,
+/// the circuit opens: requests are answered with `503 Service Unavailable` and a `Retry-After` header until the open
+/// duration elapses. The circuit then becomes half-open, letting a limited number of probe requests through: the circuit
+/// closes when they succeed, and opens again as soon as one fails.
+///
+/// Requests share the circuit of the first route they match, requests matching no route, or all of them when no route is
+/// set, have a circuit per path.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let breaker = CircuitBreakerMiddleware::new()
+///     .route("^/api/search")
+///     .route("^/api/users")
+///     .failure_threshold(0.5, 10)
+///     .open_duration(Duration::from_secs(30));
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(breaker, vec!("^/api"), None);
+/// ```
+pub struct CircuitBreakerMiddleware {
+    routes: Vec<Regex>,
+    circuits: Mutex<HashMap<String, Circuit>>,
+    window: usize,
+    threshold: f64,
+    min_requests: usize,
+    open_duration: Duration,
+    probes: usize,
+}
+
+impl CircuitBreakerMiddleware {
+    /// Create a breaker opening circuits when half of the last 20 requests failed, over at least 10 requests, for 30 seconds,
+    /// and probing them with a single request
+    pub fn new() -> Self {
+        CircuitBreakerMiddleware {
+            routes: Vec::new(),
+            circuits: Mutex::new(HashMap::new()),
+            window: 20,
+            threshold: 0.5,
+            min_requests: 10,
+            open_duration: Duration::from_secs(30),
+            probes: 1,
+        }
+    }
+
+    /// Give the requests matching `route` a circuit of their own
+    pub fn route<R: ToRegex>(mut self, route: R) -> Self {
+        self.routes.push(reg!(route));
+        self
+    }
+
+    /// Open circuits when the rate of failed requests reaches `threshold`, between 0 and 1, over at least `min_requests`
+    pub fn failure_threshold(mut self, threshold: f64, min_requests: usize) -> Self {
+        self.threshold = threshold;
+        self.min_requests = min_requests.max(1);
+        self.window = self.window.max(self.min_requests);
+        self
+    }
+
+    /// Compute the failure rate over the last `window` requests of a circuit
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self.min_requests = self.min_requests.min(self.window);
+        self
+    }
+
+    /// Set for how long circuits stay open before probe requests are let through
+    pub fn open_duration(mut self, duration: Duration) -> Self {
+        self.open_duration = duration;
+        self
+    }
+
+    /// Set how many probe requests a half-open circuit lets through at the same time
+    pub fn probes(mut self, probes: usize) -> Self {
+        self.probes = probes.max(1);
+        self
+    }
+
+    /// Returns the state of the circuit of the requests to `path`, as it would be for a request received now
+    pub fn state(&self, path: &str) -> CircuitState {
+        let circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        match circuits.get(&self.key_for(path)) {
+            Some(circuit) if circuit.state == CircuitState::Open && circuit.opened_at.elapsed() >= self.open_duration => {
+                CircuitState::HalfOpen
+            }
+            Some(circuit) => circuit.state,
+            None => CircuitState::Closed,
+        }
+    }
+
+    fn key_for(&self, path: &str) -> String {
+        self.routes.iter()
+            .find(|route| route.is_match(path))
+            .map(|route| route.as_str().to_string())
+            .unwrap_or_else(|| path.to_string())
+    }
+
+    fn record(&self, key: &str, probe: bool, success: bool) {
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits.entry(key.to_string()).or_insert_with(Circuit::new);
+
+        if probe {
+            circuit.probes = circuit.probes.saturating_sub(1);
+            if circuit.state != CircuitState::HalfOpen {
+                return;
+            }
+
+            if success {
+                info!("Circuit {} closed, its probe request succeeded", key);
+                circuit.close();
+            } else {
+                warn!("Circuit {} opened again for {:?}, its probe request failed", key, self.open_duration);
+                circuit.open();
+            }
+            return;
+        }
+
+        if circuit.state != CircuitState::Closed {
+            return;
+        }
+
+        circuit.outcomes.push_back(success);
+        while circuit.outcomes.len() > self.window {
+            circuit.outcomes.pop_front();
+        }
+
+        let failures = circuit.outcomes.iter().filter(|success| !**success).count();
+        let requests = circuit.outcomes.len();
+        if requests >= self.min_requests && failures as f64 >= self.threshold * requests as f64 {
+            warn!("Circuit {} opened for {:?}, {} of its last {} requests failed", key, self.open_duration, failures, requests);
+            circuit.open();
+        }
+    }
+}
+
+impl Default for CircuitBreakerMiddleware {
+    fn default() -> Self {
+        CircuitBreakerMiddleware::new()
+    }
+}
+
+impl Middleware for CircuitBreakerMiddleware {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let key = self.key_for(req.uri().path());
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let circuit = circuits.entry(key.clone()).or_insert_with(Circuit::new);
+
+        if circuit.state == CircuitState::Open && circuit.opened_at.elapsed() >= self.open_duration {
+            circuit.state = CircuitState::HalfOpen;
+        }
+
+        let probe = match circuit.state {
+            CircuitState::Closed => false,
+            CircuitState::HalfOpen if circuit.probes < self.probes => {
+                circuit.probes += 1;
+                true
+            }
+            _ => {
+                let retry_after = self.open_duration.checked_sub(circuit.opened_at.elapsed()).unwrap_or_default();
+                res.status(StatusCode::SERVICE_UNAVAILABLE).header(header::RETRY_AFTER, retry_after.as_secs().max(1).to_string());
+                return RequestContinuation::None;
+            }
+        };
+
+        res.extension(Admitted {
+            key,
+            probe,
+        });
+        RequestContinuation::Next
+    }
+
+    fn after(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+        let success = !res.get_status().is_server_error();
+
+        if let Some(admitted) = res.get_extensions_mut().remove::<Admitted>() {
+            self.record(&admitted.key, admitted.probe, success);
+        }
+    }
+}
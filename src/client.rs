@@ -0,0 +1,177 @@
+use http::*;
+use futures::Future;
+use futures::Stream;
+use futures::sync::oneshot;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use tokio::runtime::Builder as RuntimeBuilder;
+use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
+use std::fmt;
+use std::time::Duration;
+
+/// The headers correlating a request with the calls made to serve it, propagated by default by `HttpClient`
+const PROPAGATED_HEADERS: &[&str] = &[
+    "x-request-id",
+    "x-correlation-id",
+    "traceparent",
+    "tracestate",
+    "baggage",
+];
+
+/// Why a call made by an `HttpClient` failed
+#[derive(Debug)]
+pub enum ClientError {
+    /// The request couldn't be built, like when its url is invalid
+    InvalidRequest(String),
+    /// The request being served was cancelled or past its deadline, the call wasn't made
+    Cancelled,
+    /// No response was received before the timeout of the client or the deadline of the request being served
+    Timeout,
+    /// The server couldn't be reached, or its response couldn't be read
+    Failed(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientError::InvalidRequest(ref e) => write!(f, "invalid request: {}", e),
+            ClientError::Cancelled => write!(f, "the request being served is cancelled"),
+            ClientError::Timeout => write!(f, "no response was received in time"),
+            ClientError::Failed(ref e) => write!(f, "request failed: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for ClientError {}
+
+/// A client for the `http` services called by handlers, correlating and bounding each call with the request it serves
+///
+/// Every call is made on behalf of the request being served, the context: the headers correlating the context with the
+/// calls made to serve it, `X-Request-Id`, `X-Correlation-Id` and the W3C `traceparent`, `tracestate` and `baggage`, are
+/// copied to the outbound request unless it sets them itself, more can be added with `propagate`. The call is bounded
+/// by the deadline of the context, see `CancellationToken`, along with the timeout of the client, and isn't made at all
+/// once the context is cancelled. Responses are read entirely before being returned.
+///
+/// Calls block the handler making them, like `ProxyController` does, while the connections are handled by a runtime
+/// owned by the client, which should be created once and shared, like as a state of the server.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// fn profile(client: &HttpClient, req: &SyncRequest, res: &mut SyncResponse) {
+///     match client.get(req, "http://accounts.internal/v1/profile") {
+///         Ok(profile) => res.status(profile.status()).body(profile.into_body()),
+///         Err(ClientError::Timeout) => res.status(StatusCode::GATEWAY_TIMEOUT),
+///         Err(_) => res.status(StatusCode::BAD_GATEWAY),
+///     };
+/// }
+///
+/// let client = HttpClient::new().timeout(Duration::from_secs(2)).propagate("x-tenant-id");
+/// let mut controller = BasicController::new(client);
+/// controller.add(Method::GET, "^/profile$", profile);
+/// ```
+pub struct HttpClient {
+    client: Client<HttpConnector, Body>,
+    runtime: Runtime,
+    propagated: Vec<header::HeaderName>,
+    timeout: Option<Duration>,
+}
+
+impl HttpClient {
+    /// Create a client propagating the default correlation headers, without a timeout of its own
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime handling the connections can't be started.
+    pub fn new() -> Self {
+        let runtime = RuntimeBuilder::new().name_prefix("saphir-client-").build().expect("Unable to start the client runtime");
+        let client = Client::builder().executor(runtime.executor()).build_http();
+
+        HttpClient {
+            client,
+            runtime,
+            propagated: PROPAGATED_HEADERS.iter().map(|name| header::HeaderName::from_static(name)).collect(),
+            timeout: None,
+        }
+    }
+
+    /// Propagate the header `name` of the context to the calls too
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn propagate(mut self, name: &str) -> Self {
+        let name = header::HeaderName::from_bytes(name.as_bytes()).expect("valid propagated header name");
+        if !self.propagated.contains(&name) {
+            self.propagated.push(name);
+        }
+        self
+    }
+
+    /// Wait at most `timeout` for the response to each call, on top of the deadline of the context
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Send a `GET` request to `uri` on behalf of `context`
+    pub fn get(&self, context: &SyncRequest, uri: &str) -> Result<Response<Vec<u8>>, ClientError> {
+        let request = Request::get(uri).body(Vec::new()).map_err(|e| ClientError::InvalidRequest(e.to_string()))?;
+        self.send(context, request)
+    }
+
+    /// Send a `POST` request to `uri` with `body` on behalf of `context`
+    pub fn post<B: Into<Vec<u8>>>(&self, context: &SyncRequest, uri: &str, body: B) -> Result<Response<Vec<u8>>, ClientError> {
+        let request = Request::post(uri).body(body.into()).map_err(|e| ClientError::InvalidRequest(e.to_string()))?;
+        self.send(context, request)
+    }
+
+    /// Send `request` on behalf of `context`, returning its response once read entirely
+    pub fn send(&self, context: &SyncRequest, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, ClientError> {
+        if context.is_cancelled() {
+            return Err(ClientError::Cancelled);
+        }
+
+        let (mut parts, body) = request.into_parts();
+        for name in &self.propagated {
+            if parts.headers.contains_key(name) {
+                continue;
+            }
+            for value in context.headers_map().get_all(name) {
+                parts.headers.append(name.clone(), value.clone());
+            }
+        }
+
+        let response = self.client.request(Request::from_parts(parts, Body::from(body))).and_then(|res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(move |body| Response::from_parts(parts, body.to_vec()))
+        });
+
+        let timeout = match (self.timeout, context.cancellation_token().remaining()) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        };
+
+        match timeout {
+            Some(timeout) => {
+                oneshot::spawn(Timeout::new(response, timeout), &self.runtime.executor()).wait().map_err(|e| {
+                    if e.is_elapsed() {
+                        ClientError::Timeout
+                    } else {
+                        ClientError::Failed(e.to_string())
+                    }
+                })
+            }
+            None => oneshot::spawn(response, &self.runtime.executor()).wait().map_err(|e| ClientError::Failed(e.to_string())),
+        }
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        HttpClient::new()
+    }
+}
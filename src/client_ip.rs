@@ -0,0 +1,264 @@
+use http::*;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// An error raised when an IP network can't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpNetworkError(String);
+
+impl fmt::Display for IpNetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid IP network `{}`", self.0)
+    }
+}
+
+impl ::std::error::Error for IpNetworkError {}
+
+/// A range of IP addresses in CIDR notation, like `10.0.0.0/8` or `2001:db8::/32`
+///
+/// A single address, without a prefix length, is parsed as a network containing only itself. IPv4 networks also contain the
+/// IPv4-mapped IPv6 form of their addresses, like `::ffff:10.0.0.1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Create the network of the addresses sharing the first `prefix` bits of `addr`, `None` if `prefix` is too long
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return None;
+        }
+
+        Some(IpNetwork { addr, prefix })
+    }
+
+    /// The first address of the network, as given when it was created
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The number of leading bits shared by the addresses of the network
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether `ip` belongs to the network
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) };
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) => self.contains(IpAddr::V4(ip)),
+                None => {
+                    let mask = if self.prefix == 0 { 0 } else { u128::MAX << (128 - self.prefix) };
+                    u128::from(network) & mask == u128::from(ip) & mask
+                }
+            },
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip.to_ipv4_mapped().is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = IpNetworkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || IpNetworkError(s.to_string());
+        let mut parts = s.trim().splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap_or("").parse().map_err(|_| invalid())?;
+
+        let prefix = match parts.next() {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None if addr.is_ipv4() => 32,
+            None => 128,
+        };
+
+        IpNetwork::new(addr, prefix).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// A header through which proxies report the address of the client they received a request from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardedHeader {
+    /// The standard `Forwarded` header of RFC 7239, using its `for` parameters
+    Forwarded,
+    /// The `X-Forwarded-For` header, a list of addresses each proxy appends to
+    XForwardedFor,
+    /// The `X-Real-IP` header, holding the single address seen by the proxy
+    XRealIp,
+}
+
+impl ForwardedHeader {
+    /// The addresses listed by the header in `req`, from the farthest to the nearest hop, `None` when the header is absent
+    fn addresses(&self, req: &SyncRequest) -> Option<Vec<Option<IpAddr>>> {
+        let values = match *self {
+            ForwardedHeader::Forwarded => req.header_list(header::FORWARDED),
+            ForwardedHeader::XForwardedFor => req.header_list("x-forwarded-for"),
+            ForwardedHeader::XRealIp => req.header_list("x-real-ip"),
+        };
+
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(match *self {
+            ForwardedHeader::Forwarded => values.iter()
+                .filter_map(|element| element.split(';')
+                    .filter_map(|pair| {
+                        let mut pair = pair.splitn(2, '=');
+                        match (pair.next(), pair.next()) {
+                            (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("for") => Some(value),
+                            _ => None,
+                        }
+                    })
+                    .next())
+                .map(parse_node)
+                .collect(),
+            _ => values.into_iter().map(parse_node).collect(),
+        })
+    }
+}
+
+/// Parse a node of a forwarding header, like `192.0.2.1`, `"[2001:db8::1]:4711"` or `203.0.113.7:8080`. Obfuscated and
+/// `unknown` nodes are `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split(']').next().and_then(|ip| ip.parse().ok());
+    }
+
+    node.parse().ok().or_else(|| {
+        let mut parts = node.rsplitn(2, ':');
+        let _port = parts.next();
+        parts.next().and_then(|ip| ip.parse::<::std::net::Ipv4Addr>().ok()).map(IpAddr::V4)
+    })
+}
+
+/// Which peers are trusted to report the address of the client in forwarding headers, see `SyncRequest::client_ip`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let proxies = TrustedProxies::new()
+///     .trust("10.0.0.0/8").unwrap()
+///     .trust("fd00::/8").unwrap()
+///     .header(ForwardedHeader::XForwardedFor);
+///
+/// let server = Server::builder().trusted_proxies(proxies).build();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustedProxies {
+    networks: Vec<IpNetwork>,
+    headers: Vec<ForwardedHeader>,
+}
+
+impl TrustedProxies {
+    /// Trust no proxy, the client address is the address of the peer. The `Forwarded` header is consulted, then
+    /// `X-Forwarded-For`, once proxies are trusted.
+    pub fn new() -> Self {
+        TrustedProxies {
+            networks: Vec::new(),
+            headers: vec![ForwardedHeader::Forwarded, ForwardedHeader::XForwardedFor],
+        }
+    }
+
+    /// Trust the peers whose address belongs to `network`, written in CIDR notation
+    pub fn trust(mut self, network: &str) -> Result<Self, IpNetworkError> {
+        self.networks.push(network.parse()?);
+        Ok(self)
+    }
+
+    /// Trust the peers whose address belongs to `network`
+    pub fn trust_network(mut self, network: IpNetwork) -> Self {
+        self.networks.push(network);
+        self
+    }
+
+    /// Only consult `header` to find the client address, rather than `Forwarded` and `X-Forwarded-For`
+    pub fn header(mut self, header: ForwardedHeader) -> Self {
+        self.headers = vec![header];
+        self
+    }
+
+    /// Returns the trusted networks
+    pub(crate) fn networks(&self) -> &[IpNetwork] {
+        &self.networks
+    }
+
+    /// Whether `ip` belongs to a trusted network
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// Find the address of the client which sent `req` through `peer`
+    ///
+    /// The forwarding chain is walked from the nearest hop, the first address which is not trusted is the client. When every
+    /// hop is trusted, the farthest one is. A hop which can't be parsed ends the walk, since nothing it reports can be
+    /// believed.
+    pub fn client_ip(&self, req: &SyncRequest, peer: IpAddr) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let addresses = match self.headers.iter().filter_map(|header| header.addresses(req)).next() {
+            Some(addresses) => addresses,
+            None => return peer,
+        };
+
+        let mut client = peer;
+        for address in addresses.into_iter().rev() {
+            match address {
+                Some(ip) => {
+                    client = ip;
+                    if !self.is_trusted(ip) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        client
+    }
+}
+
+impl Default for TrustedProxies {
+    fn default() -> Self {
+        TrustedProxies::new()
+    }
+}
+
+/// The trusted proxies configured on the server, inserted in the extensions of every request
+#[derive(Clone)]
+pub(crate) struct SharedTrustedProxies(pub(crate) Arc<TrustedProxies>);
+
+impl SyncRequest {
+    /// Returns the address of the client which sent the request, `None` if the request was not received by a `Server`
+    ///
+    /// The address reported by forwarding headers is only used when the peer is a proxy trusted by the `TrustedProxies` set
+    /// on the server builder, otherwise the address of the peer is returned.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        let peer = self.peer_addr()?.ip();
+
+        match self.extensions().get::<SharedTrustedProxies>() {
+            Some(proxies) => Some(proxies.0.client_ip(self, peer)),
+            None => Some(peer),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use http::*;
+use middleware::Middleware;
+use response_cache::CachedResponse;
+use utils::RequestContinuation;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+type Flights = Arc<Mutex<HashMap<String, Arc<Flight>>>>;
+
+enum FlightState {
+    Pending,
+    Done(Option<CachedResponse>),
+}
+
+/// A request being processed, which identical requests wait for
+struct Flight {
+    state: Mutex<FlightState>,
+    done: Condvar,
+}
+
+impl Flight {
+    /// Wait for the response of the flight, `None` if the leader failed to produce one or if it took longer than `timeout`
+    fn wait(&self, timeout: Duration) -> Option<CachedResponse> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            match *state {
+                FlightState::Done(ref response) => return response.clone(),
+                FlightState::Pending => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return None;
+                    }
+                    state = self.done.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+                }
+            }
+        }
+    }
+}
+
+/// Marks the request leading a flight. The flight is landed when the response is known, or when the request is dropped
+/// without one, so that waiting requests never outlive a failed leader.
+struct Leader {
+    key: String,
+    flight: Arc<Flight>,
+    flights: Flights,
+}
+
+impl Leader {
+    fn land(&self, response: Option<CachedResponse>) {
+        {
+            let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+            if flights.get(&self.key).is_some_and(|flight| Arc::ptr_eq(flight, &self.flight)) {
+                flights.remove(&self.key);
+            }
+        }
+
+        let mut state = self.flight.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let FlightState::Pending = *state {
+            *state = FlightState::Done(response);
+        }
+        self.flight.done.notify_all();
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.land(None);
+    }
+}
+
+/// A middleware coalescing concurrent identical `GET` requests: the first one is processed while the others wait for its
+/// response, which is then replayed to all of them, shielding expensive read endpoints from stampedes
+///
+/// Requests are identical when they share their path, query and the values of the headers the response may depend on,
+/// `Authorization`, `Cookie`, `Accept`, `Accept-Encoding` and `Accept-Language` by default, so that responses are never
+/// shared between users. Requests waiting for longer than the timeout, or whose leader failed or streamed its response, are
+/// processed normally.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(CoalescingMiddleware::new(), vec!("^/reports"), None);
+/// ```
+pub struct CoalescingMiddleware {
+    flights: Flights,
+    headers: Vec<header::HeaderName>,
+    timeout: Duration,
+}
+
+impl CoalescingMiddleware {
+    /// Create a middleware waiting at most 30 seconds for the response of identical requests
+    pub fn new() -> Self {
+        CoalescingMiddleware {
+            flights: Arc::new(Mutex::new(HashMap::new())),
+            headers: vec![header::AUTHORIZATION, header::COOKIE, header::ACCEPT, header::ACCEPT_ENCODING, header::ACCEPT_LANGUAGE],
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the headers whose values tell requests apart, replacing the default ones
+    pub fn vary(mut self, headers: Vec<header::HeaderName>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Set for how long a request waits for the response of an identical one before being processed on its own
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn key_for(&self, req: &SyncRequest) -> String {
+        let mut key = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or_else(|| req.uri().path()).to_string();
+
+        for name in &self.headers {
+            key.push('\n');
+            for value in req.headers_map().get_all(name).iter() {
+                key.push_str(value.to_str().unwrap_or(""));
+                key.push(',');
+            }
+        }
+
+        key
+    }
+}
+
+impl Default for CoalescingMiddleware {
+    fn default() -> Self {
+        CoalescingMiddleware::new()
+    }
+}
+
+impl Middleware for CoalescingMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if *req.method() != Method::GET {
+            return RequestContinuation::Next;
+        }
+
+        let key = self.key_for(req);
+
+        let follow = {
+            let mut flights = self.flights.lock().unwrap_or_else(|e| e.into_inner());
+            match flights.get(&key) {
+                Some(flight) => Some(flight.clone()),
+                None => {
+                    let flight = Arc::new(Flight {
+                        state: Mutex::new(FlightState::Pending),
+                        done: Condvar::new(),
+                    });
+                    flights.insert(key.clone(), flight.clone());
+                    req.extensions_mut().insert(Leader {
+                        key,
+                        flight,
+                        flights: self.flights.clone(),
+                    });
+                    None
+                }
+            }
+        };
+
+        let cached = match follow.and_then(|flight| flight.wait(self.timeout)) {
+            Some(cached) => cached,
+            None => return RequestContinuation::Next,
+        };
+
+        res.status(cached.status);
+        for (name, value) in cached.headers.iter() {
+            res.headers_map_mut().append(name.clone(), value.clone());
+        }
+        res.body(cached.body);
+        RequestContinuation::None
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let leader = match req.extensions().get::<Leader>() {
+            Some(leader) => leader,
+            None => return,
+        };
+
+        // A streamed body can only be sent once, the followers compute their own response
+        if res.is_streamed() {
+            leader.land(None);
+            return;
+        }
+
+        leader.land(Some(CachedResponse {
+            status: res.get_status(),
+            headers: res.headers_map().clone(),
+            body: res.get_body(),
+            stored_at: SystemTime::now(),
+            max_age: Duration::from_secs(0),
+        }));
+    }
+}
@@ -0,0 +1,176 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+struct LimiterState {
+    in_flight: usize,
+    queued: usize,
+}
+
+/// The slots of a `ConcurrencyLimitMiddleware` or of a `RoutePolicy`, shared with the permits holding them
+pub(crate) struct Limiter {
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+impl Limiter {
+    pub(crate) fn new() -> Arc<Limiter> {
+        Arc::new(Limiter {
+            state: Mutex::new(LimiterState {
+                in_flight: 0,
+                queued: 0,
+            }),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Returns the number of slots currently held
+    pub(crate) fn in_flight(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).in_flight
+    }
+
+    /// Hold one of `max_in_flight` slots, waiting for at most `max_wait` for one to be released when they are all held,
+    /// unless `max_queued` requests are already waiting
+    pub(crate) fn acquire(self: &Arc<Self>, max_in_flight: usize, max_queued: usize, max_wait: Duration) -> Option<ConcurrencyPermit> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut acquired = state.in_flight < max_in_flight;
+
+        if !acquired && state.queued < max_queued {
+            state.queued += 1;
+            let deadline = Instant::now() + max_wait;
+
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+
+                state = self.released.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+
+                if state.in_flight < max_in_flight {
+                    acquired = true;
+                    break;
+                }
+            }
+
+            state.queued -= 1;
+        }
+
+        if !acquired {
+            return None;
+        }
+
+        state.in_flight += 1;
+        Some(ConcurrencyPermit { limiter: self.clone() })
+    }
+}
+
+/// A slot held by a request, released when dropped so that a request whose handler panicked, and whose response is
+/// replaced without running the `after` of the middleware, doesn't hold it forever
+pub(crate) struct ConcurrencyPermit {
+    limiter: Arc<Limiter>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_flight -= 1;
+        self.limiter.released.notify_one();
+    }
+}
+
+/// The slots a request holds, inserted in the extensions of the response
+struct ConcurrencyPermits(Vec<ConcurrencyPermit>);
+
+/// A middleware capping the number of requests processed concurrently, shedding the excess with `503 Service Unavailable`
+///
+/// Once `max_in_flight` requests are being processed, up to `max_queued` requests wait for at most `max_wait` for one of
+/// them to complete. Requests exceeding the queue, or waiting for too long, are answered right away with a `Retry-After`
+/// header instead of piling up, which keeps the latency of accepted requests under control during an overload.
+///
+/// Queued requests block the thread they would be processed on, see `ServerBuilder::handler_threads`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(ConcurrencyLimitMiddleware::new(256).queue(512, Duration::from_millis(200)), vec!("/"), None);
+/// ```
+pub struct ConcurrencyLimitMiddleware {
+    max_in_flight: usize,
+    max_queued: usize,
+    max_wait: Duration,
+    retry_after: Duration,
+    limiter: Arc<Limiter>,
+}
+
+impl ConcurrencyLimitMiddleware {
+    /// Create a middleware processing at most `max_in_flight` requests at once, without queue
+    pub fn new(max_in_flight: usize) -> Self {
+        ConcurrencyLimitMiddleware {
+            max_in_flight,
+            max_queued: 0,
+            max_wait: Duration::from_secs(0),
+            retry_after: Duration::from_secs(1),
+            limiter: Limiter::new(),
+        }
+    }
+
+    /// Let up to `max_queued` requests wait for at most `max_wait` when the limit is reached
+    pub fn queue(mut self, max_queued: usize, max_wait: Duration) -> Self {
+        self.max_queued = max_queued;
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Set the delay advertised by the `Retry-After` header of shed requests, rounded up to the second. Defaults to 1 second.
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = delay;
+        self
+    }
+
+    /// Returns the number of requests currently processed
+    pub fn in_flight(&self) -> usize {
+        self.limiter.in_flight()
+    }
+
+    fn acquire(&self) -> Option<ConcurrencyPermit> {
+        self.limiter.acquire(self.max_in_flight, self.max_queued, self.max_wait)
+    }
+}
+
+impl Middleware for ConcurrencyLimitMiddleware {
+    fn resolve(&self, _req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let permit = match self.acquire() {
+            Some(permit) => permit,
+            None => {
+                let retry_after = self.retry_after.as_secs() + if self.retry_after.subsec_nanos() > 0 { 1 } else { 0 };
+                res.status(StatusCode::SERVICE_UNAVAILABLE)
+                    .header(header::RETRY_AFTER, retry_after.to_string())
+                    .body(Vec::<u8>::new());
+                return RequestContinuation::None;
+            }
+        };
+
+        match res.get_extensions_mut().get_mut::<ConcurrencyPermits>() {
+            Some(permits) => permits.0.push(permit),
+            None => { res.extension(ConcurrencyPermits(vec![permit])); }
+        }
+
+        RequestContinuation::Next
+    }
+
+    fn after(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+        // Dropping the permit releases the slot
+        if let Some(permits) = res.get_extensions_mut().get_mut::<ConcurrencyPermits>() {
+            permits.0.retain(|permit| !Arc::ptr_eq(&permit.limiter, &self.limiter));
+        }
+    }
+}
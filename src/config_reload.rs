@@ -0,0 +1,260 @@
+use arc_swap::ArcSwap;
+use log::LevelFilter;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// An error raised while loading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not a valid JSON representation of the configuration
+    Parse(::serde_json::Error),
+    /// The file is not a valid TOML or YAML representation of the configuration, or its format is not supported
+    Syntax(String),
+    /// A setting of the configuration has an invalid value
+    Invalid(String),
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<::serde_json::Error> for ConfigError {
+    fn from(e: ::serde_json::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "unable to read the configuration: {}", e),
+            ConfigError::Parse(e) => write!(f, "unable to parse the configuration: {}", e),
+            ConfigError::Syntax(e) => write!(f, "unable to parse the configuration: {}", e),
+            ConfigError::Invalid(e) => write!(f, "invalid configuration: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for ConfigError {}
+
+/// A handle to the current version of a configuration, updated atomically by a `ConfigReloader`
+///
+/// Handles are cheap to clone, they are typically registered as a state on the server so middlewares and delegates can read
+/// `req.state::<Reloadable<MyConfig>>()`.
+pub struct Reloadable<T> {
+    current: Arc<ArcSwap<T>>,
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Reloadable {
+            current: self.current.clone(),
+        }
+    }
+}
+
+impl<T> Reloadable<T> {
+    /// Create a handle holding `value`
+    pub fn new(value: T) -> Self {
+        Reloadable {
+            current: Arc::new(ArcSwap::from_pointee(value)),
+        }
+    }
+
+    /// Returns the current version of the configuration. The returned value is never altered, a reload replaces it.
+    pub fn get(&self) -> Arc<T> {
+        self.current.load_full()
+    }
+
+    /// Replace the current version of the configuration
+    pub fn set(&self, value: T) {
+        self.current.store(Arc::new(value));
+    }
+}
+
+type ReloadListener<T> = Box<dyn Fn(&T) + Send + Sync>;
+type LogLevelSelector<T> = Box<dyn Fn(&T) -> Option<LevelFilter> + Send + Sync>;
+
+/// Reload a JSON configuration file whenever it is modified, or when the process receives `SIGHUP` on unix platforms
+///
+/// Each reload parses the whole file, atomically replaces the configuration held by `Reloadable` handles and invokes the
+/// registered listeners, which is where settings like rate limits or served directories are re-applied. When the file can't be
+/// loaded, the error is logged and the previous configuration is kept. The server is never restarted, so connections are
+/// not dropped.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate saphir;
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate log;
+/// # use saphir::*;
+/// #[derive(Deserialize)]
+/// struct AppConfig {
+///     log_level: String,
+///     max_upload_size: usize,
+/// }
+///
+/// # fn main() {
+/// let reloader = ConfigReloader::<AppConfig>::new("config.json").unwrap()
+///     .on_sighup()
+///     .log_level(|c| c.log_level.parse().ok())
+///     .on_reload(|c| println!("max upload size is now {}", c.max_upload_size));
+///
+/// let server = Server::builder().state(reloader.config()).build();
+/// reloader.spawn();
+/// # }
+/// ```
+pub struct ConfigReloader<T> {
+    path: PathBuf,
+    config: Reloadable<T>,
+    poll_interval: Duration,
+    sighup: bool,
+    log_level: Option<LogLevelSelector<T>>,
+    listeners: Vec<ReloadListener<T>>,
+    signaled: Arc<AtomicBool>,
+}
+
+impl<T: 'static + DeserializeOwned + Send + Sync> ConfigReloader<T> {
+    /// Load the configuration file at `path`
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.into();
+        let config = Reloadable::new(Self::load(&path)?);
+
+        Ok(ConfigReloader {
+            path,
+            config,
+            poll_interval: Duration::from_secs(2),
+            sighup: false,
+            log_level: None,
+            listeners: Vec::new(),
+            signaled: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn load(path: &PathBuf) -> Result<T, ConfigError> {
+        let content = fs::read(path)?;
+        Ok(::serde_json::from_slice(&content)?)
+    }
+
+    /// Returns a handle to the current configuration
+    pub fn config(&self) -> Reloadable<T> {
+        self.config.clone()
+    }
+
+    /// Set how often the modification time of the file is checked, every 2 seconds by default
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Also reload the configuration when the process receives `SIGHUP`. This has no effect on non unix platforms.
+    pub fn on_sighup(mut self) -> Self {
+        self.sighup = true;
+        self
+    }
+
+    /// Apply the maximum log level returned by `level` on every reload, and once at startup
+    pub fn log_level<F: 'static + Fn(&T) -> Option<LevelFilter> + Send + Sync>(mut self, level: F) -> Self {
+        self.log_level = Some(Box::new(level));
+        self
+    }
+
+    /// Invoke `listener` with the new configuration on every reload
+    pub fn on_reload<F: 'static + Fn(&T) + Send + Sync>(mut self, listener: F) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    /// Returns a handle making the reloader reload the configuration once spawned, like `AdminController::config_reload`
+    pub fn trigger(&self) -> ReloadTrigger {
+        ReloadTrigger(self.signaled.clone())
+    }
+
+    /// Reload the configuration right away
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        self.config.set(Self::load(&self.path)?);
+        self.apply();
+        Ok(())
+    }
+
+    fn apply(&self) {
+        let config = self.config.get();
+
+        if let Some(level) = self.log_level.as_ref().and_then(|level| level(&config)) {
+            ::log::set_max_level(level);
+        }
+
+        for listener in &self.listeners {
+            listener(&config);
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    /// Apply the current configuration and start watching for changes on a background thread
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        let signaled = self.signaled.clone();
+
+        if self.sighup {
+            watch_sighup(&signaled);
+        }
+
+        self.apply();
+
+        thread::spawn(move || {
+            let mut last_modified = self.modified();
+
+            loop {
+                thread::sleep(self.poll_interval);
+
+                let modified = self.modified();
+                if !signaled.swap(false, Ordering::SeqCst) && modified == last_modified {
+                    continue;
+                }
+
+                last_modified = modified;
+                match self.reload() {
+                    Ok(_) => info!("Configuration reloaded from {}", self.path.display()),
+                    Err(e) => error!("Unable to reload the configuration from {}, keeping the previous one: {}", self.path.display(), e),
+                }
+            }
+        })
+    }
+}
+
+/// A handle making a spawned `ConfigReloader` reload its configuration, see `ConfigReloader::trigger`
+#[derive(Clone)]
+pub struct ReloadTrigger(Arc<AtomicBool>);
+
+impl ReloadTrigger {
+    /// Make the reloader reload the configuration at its next poll, even if the file wasn't modified
+    pub fn reload(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+fn watch_sighup(signaled: &Arc<AtomicBool>) {
+    if let Err(e) = ::signal_hook::flag::register(::signal_hook::consts::SIGHUP, signaled.clone()) {
+        error!("Unable to watch SIGHUP to reload the configuration: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn watch_sighup(_signaled: &Arc<AtomicBool>) {}
@@ -0,0 +1,153 @@
+use http::*;
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+type Factory = Box<dyn Fn(&Container) -> Arc<dyn Any + Send + Sync> + Send + Sync>;
+
+enum Registration {
+    Singleton(Arc<dyn Any + Send + Sync>),
+    Factory(Factory),
+}
+
+/// A lightweight typed service container
+///
+/// Services are registered either as singletons, shared by every consumer, or as factories invoked on every resolution.
+/// Registering a service replaces any previous registration of the same type, which makes swapping real services for
+/// mocks in tests trivial.
+///
+/// Once registered on the server as a state, services can be resolved within a request through `SyncRequest::resolve`,
+/// where factories are invoked at most once per request.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// struct DbPool;
+/// struct UserRepository {
+///     pool: ::std::sync::Arc<DbPool>,
+/// }
+///
+/// let mut container = Container::new();
+/// container.register_singleton(DbPool);
+/// container.register_factory(|c: &Container| UserRepository { pool: c.resolve::<DbPool>().unwrap() });
+///
+/// let repository = container.resolve::<UserRepository>();
+/// assert!(repository.is_some());
+///
+/// let server = Server::builder().state(container).build();
+/// ```
+#[derive(Default)]
+pub struct Container {
+    registrations: HashMap<TypeId, Registration>,
+}
+
+impl Container {
+    /// Create an empty container
+    pub fn new() -> Self {
+        Container {
+            registrations: HashMap::new(),
+        }
+    }
+
+    /// Register a service shared by every consumer
+    pub fn register_singleton<T: Any + Send + Sync>(&mut self, service: T) -> &mut Self {
+        self.registrations.insert(TypeId::of::<T>(), Registration::Singleton(Arc::new(service)));
+        self
+    }
+
+    /// Register a factory creating a new service on every resolution. The factory can resolve its own dependencies
+    /// from the container.
+    pub fn register_factory<T, F>(&mut self, factory: F) -> &mut Self
+        where T: Any + Send + Sync,
+              F: 'static + Fn(&Container) -> T + Send + Sync {
+        let factory: Factory = Box::new(move |c| Arc::new(factory(c)));
+        self.registrations.insert(TypeId::of::<T>(), Registration::Factory(factory));
+        self
+    }
+
+    /// Returns whether or not a service of type `T` is registered
+    pub fn is_registered<T: Any + Send + Sync>(&self) -> bool {
+        self.registrations.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Resolve the service of type `T`, returns `None` when it is not registered
+    pub fn resolve<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.resolve_any(TypeId::of::<T>()).and_then(|s| s.downcast::<T>().ok())
+    }
+
+    /// Create a value whose dependencies are resolved from the container, typically a controller context
+    pub fn build<T: FromContainer>(&self) -> T {
+        T::from_container(self)
+    }
+
+    fn resolve_any(&self, type_id: TypeId) -> Option<Arc<dyn Any + Send + Sync>> {
+        match self.registrations.get(&type_id) {
+            Some(Registration::Singleton(service)) => Some(service.clone()),
+            Some(Registration::Factory(factory)) => Some(factory(self)),
+            None => None,
+        }
+    }
+}
+
+/// A trait for types which can be created from the services of a `Container`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::sync::Arc;
+/// struct DbPool;
+///
+/// struct UserControllerContext {
+///     pool: Arc<DbPool>,
+/// }
+///
+/// impl FromContainer for UserControllerContext {
+///     fn from_container(container: &Container) -> Self {
+///         UserControllerContext {
+///             pool: container.resolve().expect("DbPool is registered"),
+///         }
+///     }
+/// }
+///
+/// let mut container = Container::new();
+/// container.register_singleton(DbPool);
+///
+/// let controller = BasicController::new(container.build::<UserControllerContext>());
+/// ```
+pub trait FromContainer {
+    /// Create the value from the services of `container`
+    fn from_container(container: &Container) -> Self;
+}
+
+/// Services resolved during a request, inserted in the extensions of every request when a `Container` is registered on
+/// the server
+#[derive(Default)]
+pub struct RequestScope {
+    resolved: Mutex<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl SyncRequest {
+    /// Resolve the service of type `T` from the `Container` registered as a state on the server. Services created by a
+    /// factory are cached for the remainder of the request.
+    pub fn resolve<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        let container = self.state::<Container>()?;
+        let type_id = TypeId::of::<T>();
+
+        let scope = match self.extensions().get::<RequestScope>() {
+            Some(scope) => scope,
+            None => return container.resolve::<T>(),
+        };
+
+        if let Some(service) = scope.resolved.lock().unwrap().get(&type_id) {
+            return service.clone().downcast::<T>().ok();
+        }
+
+        let service = container.resolve_any(type_id)?;
+        scope.resolved.lock().unwrap().insert(type_id, service.clone());
+        service.downcast::<T>().ok()
+    }
+}
@@ -1,14 +1,129 @@
 use http::*;
+use router::routing_path;
 use utils::ToRegex;
 use utils::RequestContinuation;
 use regex::Regex;
-use std::sync::RwLock;
+use std::any::type_name;
+use std::collections::HashMap;
+use std::sync::Arc;
+use route_index::RouteIndex;
+use route_index::literal_path;
+use route_policy::RoutePolicy;
+use logging::*;
+use metrics::RouteLabel;
+use deprecation::Deprecation;
+use sitemap::SitemapEntry;
+use route_metadata::RouteMetadata;
+use predicate::RequestPredicate;
+use profiling::Phase;
+use profiling::profiled;
+use profiling::record_since;
+use log::Level;
+use std::time::Instant;
+
+/// The alias of a route which matched a request, see `ControllerDispatch::add_aliases`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteAlias {
+    index: usize,
+    pattern: String,
+    primary: String,
+}
+
+impl RouteAlias {
+    /// Returns the position of the alias in the paths it was registered with
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the pattern of the alias
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl SyncResponse {
+    /// Returns the alias of the delegate route which handled the request, `None` when it wasn't registered under aliases
+    pub fn route_alias(&self) -> Option<&RouteAlias> {
+        self.get_extensions().get::<RouteAlias>()
+    }
+}
 
 /// Trait representing a controller
 pub trait Controller: Send + Sync {
     /// Method invoked if the request gets routed to this controller. Nothing will be processed after a controller `handling` a request.
     /// When returning from this function, the `res` param is the response returned to the client.
     fn handle(&self, req: &SyncRequest, res: &mut SyncResponse);
+
+    /// Describe the routes handled by this controller, used for introspection only. The default implementation returns
+    /// a single entry without method nor pattern, meaning that the controller handles every request routed to it.
+    fn routes(&self) -> Vec<RouteInfo> {
+        vec![RouteInfo::new(short_type_name(type_name::<Self>()), None, None, Vec::new())]
+    }
+}
+
+/// Description of a route, as returned by `Controller::routes` and `Router::routes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    /// The regular expression the controller is registered under in the router, empty until listed by a `Router`
+    pub controller_route: String,
+    /// The host pattern the controller is registered under in the router, `None` when it handles requests to any host
+    pub host: Option<String>,
+    /// Name of the controller handling the route
+    pub controller: String,
+    /// The method of the route, `None` when any method can be handled
+    pub method: Option<Method>,
+    /// The regular expression the request path is matched against, `None` when any path can be handled
+    pub pattern: Option<String>,
+    /// Names of the guards validating requests before they reach the route
+    pub guards: Vec<String>,
+    /// The name given to the route, see `ControllerDispatch::label`
+    pub name: Option<String>,
+    /// How the route is listed in the sitemap, see `ControllerDispatch::sitemap`
+    pub sitemap: Option<SitemapEntry>,
+    /// The description of the predicate requests must match besides the path, see `ControllerDispatch::add_when`
+    pub predicate: Option<String>,
+    /// The media type of the responses of the route, see `ControllerDispatch::add_producing`
+    pub produces: Option<String>,
+    /// The documentation of the route, see `ControllerDispatch::add_documented`
+    pub metadata: Option<RouteMetadata>,
+}
+
+impl RouteInfo {
+    /// Describe a route handled by `controller`
+    pub fn new<C: Into<String>>(controller: C, method: Option<Method>, pattern: Option<String>, guards: Vec<String>) -> Self {
+        RouteInfo {
+            controller_route: String::new(),
+            host: None,
+            controller: controller.into(),
+            method,
+            pattern,
+            guards,
+            name: None,
+            sitemap: None,
+            predicate: None,
+            produces: None,
+            metadata: None,
+        }
+    }
+}
+
+/// Strip the module paths from a type name, `saphir::BasicController<app::Context>` becomes `BasicController<Context>`
+pub(crate) fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut segment_start = 0;
+
+    for c in name.chars() {
+        if c == ':' {
+            short.truncate(segment_start);
+        } else {
+            short.push(c);
+            if !c.is_alphanumeric() && c != '_' {
+                segment_start = short.len();
+            }
+        }
+    }
+
+    short
 }
 
 ///
@@ -70,20 +185,123 @@ impl<'a> IntoIterator for &'a RequestGuardCollection {
 }
 
 /// A trait to provide an other layer of validation before allowing a request into a controller
-pub trait RequestGuard {
+pub trait RequestGuard: Send + Sync {
     ///
     fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation;
+
+    /// Name of the guard, used for introspection only
+    fn name(&self) -> String {
+        short_type_name(type_name::<Self>())
+    }
+
+    /// The key under which a `CachedGuard` remembers the validation of `req`, like its credentials, `None` to validate it
+    /// every time. The default implementation returns `None`.
+    fn cache_key(&self, _req: &SyncRequest) -> Option<String> {
+        None
+    }
 }
 
-type DelegateFunction<T> = Fn(&T, &SyncRequest, &mut SyncResponse);
-type ControllerDelegate<T> = (Method, Regex, Option<RequestGuardCollection>, Box<DelegateFunction<T>>);
+/// A trait deriving a value scoped to a request from the context of a controller, like a database connection checked out
+/// of a pool held by the context, for the delegates registered with `ControllerDispatch::add_scoped`
+///
+/// The value is created before the delegate runs and released once it returned, which is where a transaction is committed
+/// or rolled back according to the response. A value whose delegate panicked is dropped without being released.
+pub trait ContextFactory<T>: Send + Sync {
+    /// The value handed to the delegates along with the context
+    type Scoped;
+
+    /// Create the value of the request, or answer it in `res` and return `None` to skip the delegate
+    fn create(&self, context: &T, req: &SyncRequest, res: &mut SyncResponse) -> Option<Self::Scoped>;
+
+    /// Release the value once the delegate returned, the default implementation drops it
+    fn release(&self, _context: &T, _scoped: Self::Scoped, _req: &SyncRequest, _res: &mut SyncResponse) {}
+}
+
+impl<T, F: ContextFactory<T>> ContextFactory<T> for Arc<F> {
+    type Scoped = F::Scoped;
+
+    fn create(&self, context: &T, req: &SyncRequest, res: &mut SyncResponse) -> Option<Self::Scoped> {
+        (**self).create(context, req, res)
+    }
+
+    fn release(&self, context: &T, scoped: Self::Scoped, req: &SyncRequest, res: &mut SyncResponse) {
+        (**self).release(context, scoped, req, res)
+    }
+}
+
+/// What to do when a route is registered after a route which shadows it, see `is_shadowed_by`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RouteConflictPolicy {
+    /// Silently register the route
+    Ignore,
+    /// Log a warning and register the route, the default
+    #[default]
+    Warn,
+    /// Panic, the same way an invalid route regex does
+    Panic,
+}
+
+impl RouteConflictPolicy {
+    /// Report a conflict according to the policy
+    pub fn report(self, conflict: &str) {
+        match self {
+            RouteConflictPolicy::Ignore => {}
+            RouteConflictPolicy::Warn => warn!(target: ROUTING_LOG_TARGET, "{}", conflict),
+            RouteConflictPolicy::Panic => panic!("{}", conflict),
+        }
+    }
+}
+
+/// Returns whether `previous`, matched before `route`, leaves no request for `route` to match.
+///
+/// Overlapping regular expressions cannot be detected in general, only two cases are caught: identical patterns, and patterns
+/// anchored on both ends without any meta character (i.e. a single path) which `previous` already matches.
+pub fn is_shadowed_by(route: &Regex, previous: &Regex) -> bool {
+    let pattern = route.as_str();
+
+    if pattern == previous.as_str() {
+        return true;
+    }
+
+    match literal_path(pattern) {
+        Some((path, true)) => previous.is_match(path),
+        _ => false,
+    }
+}
+
+type DelegateFunction<T> = dyn Fn(&T, &SyncRequest, &mut SyncResponse) + Send + Sync;
+type ControllerDelegate<T> = (Method, Regex, Option<Arc<RequestGuardCollection>>, Arc<DelegateFunction<T>>, Option<RequestPredicate>, Option<String>);
+
+/// The delegates of a `ControllerDispatch`, in their matching order, indexed by method
+struct DelegateTable<T> {
+    delegates: Vec<ControllerDelegate<T>>,
+    priorities: Vec<i32>,
+    index: HashMap<Method, RouteIndex>,
+}
 
 /// Struct to delegate a request to a registered function matching booth a `method` and a `path`
+///
+/// Delegates are added while building the controller, which then becomes immutable once moved into a `Router`: dispatching
+/// a request doesn't take any lock.
 pub struct ControllerDispatch<T> {
     /// The context sent with the request to the function
     delegate_context: T,
     /// List of delegates
-    delegates: RwLock<Vec<ControllerDelegate<T>>>,
+    delegates: DelegateTable<T>,
+    /// What to do when a delegate is shadowed by a previous one
+    conflict_policy: RouteConflictPolicy,
+    /// The function handling the requests matching no delegate
+    fallback: Option<Box<DelegateFunction<T>>>,
+    /// The labels given to delegates, by route pattern and method
+    labels: HashMap<String, HashMap<Method, String>>,
+    /// The deprecations of delegates, by route pattern and method
+    deprecations: HashMap<String, HashMap<Method, Deprecation>>,
+    /// The aliases of delegates registered under several patterns, by route pattern and method
+    aliases: HashMap<String, HashMap<Method, RouteAlias>>,
+    /// How delegates are listed in the sitemap, by route pattern and method
+    sitemap: HashMap<String, HashMap<Method, SitemapEntry>>,
+    /// The documentation of delegates, by route pattern and method
+    metadata: HashMap<String, HashMap<Method, RouteMetadata>>,
 }
 
 impl<T: Send + Sync> ControllerDispatch<T> {
@@ -91,7 +309,49 @@ impl<T: Send + Sync> ControllerDispatch<T> {
     pub fn new(delegate_context: T) -> Self {
         ControllerDispatch {
             delegate_context,
-            delegates: RwLock::new(Vec::new()),
+            delegates: DelegateTable {
+                delegates: Vec::new(),
+                priorities: Vec::new(),
+                index: HashMap::new(),
+            },
+            conflict_policy: RouteConflictPolicy::default(),
+            fallback: None,
+            labels: HashMap::new(),
+            deprecations: HashMap::new(),
+            aliases: HashMap::new(),
+            sitemap: HashMap::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Set what to do when a delegate is added after a delegate of the same method shadowing it
+    pub fn conflict_policy(mut self, policy: RouteConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    fn push(&mut self, delegate: ControllerDelegate<T>, priority: i32) {
+        let table = &mut self.delegates;
+        let position = table.priorities.iter().position(|&p| p < priority).unwrap_or(table.priorities.len());
+
+        if let Some(previous) = table.delegates[..position].iter().find(|d| d.0 == delegate.0 && d.4.is_none() && d.5.is_none() && is_shadowed_by(&delegate.1, &d.1)) {
+            self.conflict_policy.report(&format!("The route {} {} is shadowed by the route {} {} matched before it and will never be reached",
+                                                 delegate.0, delegate.1.as_str(), previous.0, previous.1.as_str()));
+        }
+
+        if position == table.delegates.len() {
+            table.index.entry(delegate.0.clone()).or_default().insert(position, &delegate.1);
+            table.delegates.push(delegate);
+            table.priorities.push(priority);
+            return;
+        }
+
+        // Every following delegate moves down, the positions they are indexed under must be updated
+        table.delegates.insert(position, delegate);
+        table.priorities.insert(position, priority);
+        table.index.clear();
+        for (position, delegate) in table.delegates.iter().enumerate() {
+            table.index.entry(delegate.0.clone()).or_default().insert(position, &delegate.1);
         }
     }
 
@@ -100,12 +360,12 @@ impl<T: Send + Sync> ControllerDispatch<T> {
     ///
     /// ```rust,no_run
     /// let u8_context = 1;
-    /// let dispatch = ControllerDispatch::new(u8_context);
+    /// let mut dispatch = ControllerDispatch::new(u8_context);
     /// dispatch.add(Method::Get, "^/test$", |ctx, req, res| { println!("this will handle Get request done on <your_host>/test")});
     /// ```
-    pub fn add<F, R: ToRegex>(&self, method: Method, path: R, delegate_func: F)
-        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) {
-        self.delegates.write().unwrap().push((method, reg!(path), None, Box::new(delegate_func)));
+    pub fn add<F, R: ToRegex>(&mut self, method: Method, path: R, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), None, Arc::new(delegate_func), None, None), 0);
     }
 
     /// Add a delegate function to handle a particular request
@@ -114,52 +374,390 @@ impl<T: Send + Sync> ControllerDispatch<T> {
     /// ```rust,no_run
     /// let u8_context = 1;
     /// let guard = BodyGuard;
-    /// let dispatch = ControllerDispatch::new(u8_context);
+    /// let mut dispatch = ControllerDispatch::new(u8_context);
     /// dispatch.add_with_guards(Method::Get, "^/test$", guard.into(), |ctx, req, res| { println!("this will handle Get request done on <your_host>/test")});
     /// ```
-    pub fn add_with_guards<F, R: ToRegex>(&self, method: Method, path: R, guards: RequestGuardCollection, delegate_func: F)
-        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) {
-        self.delegates.write().unwrap().push((method, reg!(path), Some(guards), Box::new(delegate_func)));
+    pub fn add_with_guards<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), Some(Arc::new(guards)), Arc::new(delegate_func), None, None), 0);
+    }
+
+    /// Add a delegate function to handle a particular request, matched before the delegates of a lower priority whatever
+    /// their registration order. Delegates added without a priority have a priority of 0, delegates of the same priority are
+    /// matched in their registration order.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add(Method::GET, "^/users/[^/]+$", |_, _, res| { res.status(StatusCode::OK).body("a user"); });
+    /// dispatch.add_with_priority(Method::GET, "^/users/me$", 10, |_, _, res| { res.status(StatusCode::OK).body("me"); });
+    /// ```
+    pub fn add_with_priority<F, R: ToRegex>(&mut self, method: Method, path: R, priority: i32, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), None, Arc::new(delegate_func), None, None), priority);
+    }
+
+    /// Add a delegate function to handle a particular request, with guards, matched before the delegates of a lower priority
+    /// whatever their registration order
+    pub fn add_with_guards_and_priority<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, priority: i32, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), Some(Arc::new(guards)), Arc::new(delegate_func), None, None), priority);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` which also match `predicate`, like their content type
+    /// or a query parameter, letting several delegates share a method and a path. A request matching the path of a
+    /// delegate but not its predicate is handed to the next delegate matching it, the requests matching none of them being
+    /// handed to the fallback, or answered with `400 Bad Request`.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add_when(Method::POST, "^/users$", RequestPredicate::content_type("application/json"), |_, _, res| {
+    ///     res.status(StatusCode::CREATED).body("from json");
+    /// });
+    /// dispatch.add_when(Method::POST, "^/users$", RequestPredicate::content_type("text/csv"), |_, _, res| {
+    ///     res.status(StatusCode::CREATED).body("from csv");
+    /// });
+    /// dispatch.add_when(Method::GET, "^/users$", RequestPredicate::query("search"), |_, _, res| {
+    ///     res.status(StatusCode::OK).body("search results");
+    /// });
+    /// dispatch.add(Method::GET, "^/users$", |_, _, res| { res.status(StatusCode::OK).body("every user"); });
+    /// ```
+    pub fn add_when<F, R: ToRegex>(&mut self, method: Method, path: R, predicate: RequestPredicate, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), None, Arc::new(delegate_func), Some(predicate), None), 0);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` which also match `predicate`, with guards, see
+    /// `add_when`
+    pub fn add_with_guards_when<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, predicate: RequestPredicate, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), Some(Arc::new(guards)), Arc::new(delegate_func), Some(predicate), None), 0);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` with a response of `media_type`, letting several
+    /// delegates share a method and a path, each producing a representation of its own. The delegate producing the media
+    /// type the client prefers according to its `Accept` header handles the request, and the requests accepting none of
+    /// them are answered with `406 Not Acceptable`. Responses vary on `Accept`, and have the `Content-Type` of the media type
+    /// of their delegate unless it set one.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add_producing(Method::GET, "^/report$", "application/json", |_, _, res| {
+    ///     res.status(StatusCode::OK).body("{\"total\":3}");
+    /// });
+    /// dispatch.add_producing(Method::GET, "^/report$", "text/csv", |_, _, res| {
+    ///     res.status(StatusCode::OK).body("total\n3\n");
+    /// });
+    /// ```
+    pub fn add_producing<F, R: ToRegex>(&mut self, method: Method, path: R, media_type: &str, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push((method, reg!(path), None, Arc::new(delegate_func), None, Some(media_type.to_string())), 0);
+    }
+
+    /// Add a delegate function to handle a particular request, documented by `metadata`, see `RouteMetadata`
+    pub fn add_documented<F, R: ToRegex>(&mut self, method: Method, path: R, metadata: RouteMetadata, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        let reg = reg!(path);
+        self.metadata.entry(reg.as_str().to_string()).or_default().insert(method.clone(), metadata);
+        self.push((method, reg, None, Arc::new(delegate_func), None, None), 0);
+    }
+
+    /// Add a delegate function to handle the requests matching any of `paths`, like the translations of a path
+    ///
+    /// The aliases share the delegate and the name of the first path: labels and deprecations given to the first path
+    /// apply to all of them, see `label` and `deprecate`, and the responses of every alias are reported under the same route.
+    /// The alias which matched a request is given to the delegate by `SyncResponse::route_alias`.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add_aliases(Method::GET, &["^/about$", "^/a-propos$"], |_, _, res| {
+    ///     let french = res.route_alias().is_some_and(|alias| alias.index() == 1);
+    ///     res.status(StatusCode::OK).body(if french { "À propos" } else { "About" });
+    /// });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `paths` is empty.
+    pub fn add_aliases<F, R: ToRegex>(&mut self, method: Method, paths: &[R], delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push_aliases(method, paths, None, Arc::new(delegate_func));
+    }
+
+    /// Add a delegate function to handle the requests matching any of `paths`, the guards validating the requests of every
+    /// alias, see `add_aliases`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `paths` is empty.
+    pub fn add_aliases_with_guards<F, R: ToRegex>(&mut self, method: Method, paths: &[R], guards: RequestGuardCollection, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.push_aliases(method, paths, Some(Arc::new(guards)), Arc::new(delegate_func));
+    }
+
+    fn push_aliases<R: ToRegex>(&mut self, method: Method, paths: &[R], guards: Option<Arc<RequestGuardCollection>>,
+                                delegate_func: Arc<DelegateFunction<T>>) {
+        let patterns: Vec<Regex> = paths.iter().map(|path| reg!(path)).collect();
+        let primary = patterns.first().expect("at least one path to alias").as_str().to_string();
+
+        for (index, pattern) in patterns.into_iter().enumerate() {
+            let alias = RouteAlias { index, pattern: pattern.as_str().to_string(), primary: primary.clone() };
+            self.aliases.entry(alias.pattern.clone()).or_default().insert(method.clone(), alias);
+            self.push((method.clone(), pattern, guards.clone(), delegate_func.clone(), None, None), 0);
+        }
+    }
+
+    /// Add a delegate function to handle a particular request, recovering from its failures according to `policy`
+    pub fn add_with_policy<F, R: ToRegex>(&mut self, method: Method, path: R, policy: RoutePolicy<T>, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync, T: 'static {
+        self.add(method, path, move |ctx: &T, req: &SyncRequest, res: &mut SyncResponse| policy.invoke(&delegate_func, ctx, req, res));
+    }
+
+    /// Add a delegate function to handle a particular request, receiving along with the context a value created by `factory`
+    /// for the request and released once the delegate returned
+    pub fn add_scoped<S, F, R: ToRegex>(&mut self, method: Method, path: R, factory: S, delegate_func: F)
+        where S: 'static + ContextFactory<T>,
+              for<'r, 's, 't0, 't1> F: 'static + Fn(&'r T, &'s mut S::Scoped, &'t0 SyncRequest, &'t1 mut SyncResponse) + Send + Sync {
+        self.add(method, path, move |ctx: &T, req: &SyncRequest, res: &mut SyncResponse| {
+            if let Some(mut scoped) = factory.create(ctx, req, res) {
+                delegate_func(ctx, &mut scoped, req, res);
+                factory.release(ctx, scoped, req, res);
+            }
+        });
+    }
+
+    /// Move the delegates of `other` into this dispatch, after the delegates of the same priority, and its fallback if this
+    /// dispatch has none. The context of `other` is dropped, its delegates are handed the context of this dispatch.
+    ///
+    /// Delegates shadowed by the ones of this dispatch, and a fallback set on both dispatches, are conflicts reported according
+    /// to the conflict policy of this dispatch: the fallback of this dispatch is kept.
+    pub fn merge(&mut self, other: ControllerDispatch<T>) {
+        let ControllerDispatch { delegates, fallback, labels, deprecations, aliases, sitemap, metadata, .. } = other;
+
+        for (pattern, methods) in labels {
+            self.labels.entry(pattern).or_default().extend(methods);
+        }
+
+        for (pattern, methods) in deprecations {
+            self.deprecations.entry(pattern).or_default().extend(methods);
+        }
+
+        for (pattern, methods) in aliases {
+            self.aliases.entry(pattern).or_default().extend(methods);
+        }
+
+        for (pattern, methods) in sitemap {
+            self.sitemap.entry(pattern).or_default().extend(methods);
+        }
+
+        for (pattern, methods) in metadata {
+            self.metadata.entry(pattern).or_default().extend(methods);
+        }
+
+        for (delegate, priority) in delegates.delegates.into_iter().zip(delegates.priorities) {
+            self.push(delegate, priority);
+        }
+
+        match (&self.fallback, fallback) {
+            (&None, fallback) => self.fallback = fallback,
+            (&Some(_), Some(_)) => self.conflict_policy.report("The merged controller has a fallback, which is dropped in favor of the existing one"),
+            (&Some(_), None) => {}
+        }
+    }
+
+    /// Set the function handling the requests matching no delegate, instead of answering them with `405 Method Not Allowed`
+    /// or `400 Bad Request`. Requests rejected by the guards of a delegate are not handed to it.
+    pub fn fallback<F>(&mut self, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.fallback = Some(Box::new(delegate_func));
+    }
+
+    /// Give the delegates of `method` registered under `path` a stable label, reported by `SyncResponse::route_label` and
+    /// used by `MetricsMiddleware`, instead of their route pattern
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add(Method::GET, "^/users/([0-9]+)$", |_, _, res| { res.status(StatusCode::OK); });
+    /// dispatch.label(Method::GET, "^/users/([0-9]+)$", "users.show");
+    /// ```
+    pub fn label<R: ToRegex>(&mut self, method: Method, path: R, label: &str) {
+        let pattern = reg!(path).as_str().to_string();
+        self.labels.entry(pattern).or_default().insert(method, label.to_string());
+    }
+
+    /// Deprecate the delegates of `method` registered under `path`, announcing it on each of their responses, see
+    /// `Deprecation`
+    pub fn deprecate<R: ToRegex>(&mut self, method: Method, path: R, deprecation: Deprecation) {
+        let pattern = reg!(path).as_str().to_string();
+        self.deprecations.entry(pattern).or_default().insert(method, deprecation);
+    }
+
+    /// Describe how the delegates of `method` registered under `path` are listed in the sitemap, see `Sitemap`
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut dispatch = ControllerDispatch::new(());
+    /// dispatch.add(Method::GET, "^/pricing$", |_, _, res| { res.status(StatusCode::OK); });
+    /// dispatch.sitemap(Method::GET, "^/pricing$", SitemapEntry::new().change_frequency(ChangeFrequency::Monthly).priority(0.8));
+    /// ```
+    pub fn sitemap<R: ToRegex>(&mut self, method: Method, path: R, entry: SitemapEntry) {
+        let pattern = reg!(path).as_str().to_string();
+        self.sitemap.entry(pattern).or_default().insert(method, entry);
+    }
+
+    /// Document the delegates of `method` registered under `path`, see `RouteMetadata`
+    pub fn document<R: ToRegex>(&mut self, method: Method, path: R, metadata: RouteMetadata) {
+        let pattern = reg!(path).as_str().to_string();
+        self.metadata.entry(pattern).or_default().insert(method, metadata);
+    }
+
+    /// Returns the pattern `pattern` is an alias of for `method`, the pattern itself when it isn't an alias
+    fn primary<'a>(&'a self, method: &Method, pattern: &'a str) -> &'a str {
+        self.aliases.get(pattern).and_then(|methods| methods.get(method)).map_or(pattern, |alias| alias.primary.as_str())
     }
 
     ///
     pub fn dispatch(&self, req: &SyncRequest, res: &mut SyncResponse) {
-        use std::iter::FromIterator;
-        let delegates_list = self.delegates.read().unwrap();
-        let method = req.method().clone();
+        let started = Instant::now();
+        let table = &self.delegates;
+
+        let index = match table.index.get(req.method()) {
+            Some(index) => index,
+            None => {
+                log_event(Level::Debug, ROUTING_LOG_TARGET, "method not allowed",
+                          format_args!("No delegate handles the method of {} {}", req.method(), req.uri().path()),
+                          &[("method", req.method()), ("path", &req.uri().path())]);
+                match self.fallback {
+                    Some(ref fallback) => fallback(&self.delegate_context, req, res),
+                    None => { res.status(StatusCode::METHOD_NOT_ALLOWED); }
+                }
+                return;
+            }
+        };
+
+        let path = routing_path(req);
+        let path = &*path;
+        let matching = |d: &ControllerDelegate<T>| d.4.as_ref().is_none_or(|predicate| predicate.matches(req));
+        // The index finds the first delegate matching the path, the following ones are only matched against the requests
+        // it doesn't match the predicate of
+        let found = index.find(path).and_then(|position| match table.delegates[position] {
+            ref delegate if matching(delegate) => Some(position),
+            _ => table.delegates[position + 1..].iter()
+                .position(|d| d.0 == *req.method() && d.1.is_match(path) && matching(d))
+                .map(|offset| position + 1 + offset),
+        });
+
+        let (_, reg, op_guards, boxed_func, _, produces) = match found {
+            Some(position) if table.delegates[position].5.is_some() => {
+                // The variants of a route producing different media types are negotiated with the client
+                let variants = table.delegates[position..].iter()
+                    .filter(|d| d.0 == *req.method() && d.5.is_some() && d.1.is_match(path) && matching(d))
+                    .collect::<Vec<_>>();
+                let offered = variants.iter().filter_map(|d| d.5.as_deref()).collect::<Vec<_>>();
+
+                res.header(header::VARY, "Accept");
+                match req.accepts(&offered).and_then(|media_type| offered.iter().position(|offer| *offer == media_type)) {
+                    Some(variant) => variants[variant],
+                    None => {
+                        log_event(Level::Debug, ROUTING_LOG_TARGET, "not acceptable",
+                                  format_args!("No delegate of {} {} produces a media type the client accepts", req.method(), req.uri().path()),
+                                  &[("method", req.method()), ("path", &req.uri().path())]);
+                        res.status(StatusCode::NOT_ACCEPTABLE);
+                        return;
+                    }
+                }
+            }
+            Some(position) => &table.delegates[position],
+            None => {
+                log_event(Level::Debug, ROUTING_LOG_TARGET, "no delegate matched",
+                          format_args!("No delegate route matches {} {}", req.method(), req.uri().path()),
+                          &[("method", req.method()), ("path", &req.uri().path())]);
+                match self.fallback {
+                    Some(ref fallback) => fallback(&self.delegate_context, req, res),
+                    None => { res.status(StatusCode::BAD_REQUEST); }
+                }
+                return;
+            }
+        };
 
-        let retained_delegate = Vec::from_iter(delegates_list.iter().filter(move |x| {
-            x.0 == method
-        }));
+        log_event(Level::Debug, ROUTING_LOG_TARGET, "delegate matched",
+                  format_args!("Routing {} {} to the delegate route {}", req.method(), req.uri().path(), reg.as_str()),
+                  &[("method", req.method()), ("path", &req.uri().path()), ("route", &reg.as_str())]);
 
-        if retained_delegate.len() == 0 {
-            res.status(StatusCode::METHOD_NOT_ALLOWED);
-            return;
+        // Aliases are named after their first pattern
+        let route = self.primary(req.method(), reg.as_str());
+        if let Some(alias) = self.aliases.get(reg.as_str()).and_then(|methods| methods.get(req.method())) {
+            res.extension(alias.clone());
         }
 
-        for del in retained_delegate {
-            let (_, ref reg, ref op_guards, ref boxed_func) = del;
+        let label = self.labels.get(route).and_then(|methods| methods.get(req.method())).map(|label| label.as_str())
+            .or_else(|| self.metadata.get(route).and_then(|methods| methods.get(req.method())).and_then(|metadata| metadata.get_operation_id()));
+        res.extension(RouteLabel(label.unwrap_or(route).to_string()));
+
+        if let Some(deprecation) = self.deprecations.get(route).and_then(|methods| methods.get(req.method())) {
+            deprecation.announce(res);
+        }
 
-            if reg.is_match(req.uri().path()) {
-                if let Some(ref guards) = op_guards {
-                    for guard in guards {
-                        if let RequestContinuation::None = guard.validate(req, res) {
-                            return;
+        record_since(res, Phase::Routing, started);
+
+        if let Some(ref guards) = op_guards {
+            let rejected = profiled(res, || Phase::Guards, |res| {
+                for guard in &**guards {
+                    if let RequestContinuation::None = guard.validate(req, res) {
+                        if log_enabled!(target: GUARD_LOG_TARGET, Level::Debug) {
+                            let name = guard.name();
+                            let status = res.get_status().as_u16();
+                            log_event(Level::Debug, GUARD_LOG_TARGET, "guard rejected",
+                                      format_args!("The guard {} rejected {} {} with {}", name, req.method(), req.uri().path(), status),
+                                      &[("guard", &name), ("method", req.method()), ("path", &req.uri().path()), ("status", &status)]);
                         }
+                        return true;
                     }
                 }
-                boxed_func(&self.delegate_context, req, res);
+                false
+            });
+
+            if rejected {
                 return;
             }
         }
 
-        res.status(StatusCode::BAD_REQUEST);
-    }
-}
+        profiled(res, || Phase::Handler, |res| boxed_func(&self.delegate_context, req, res));
 
-unsafe impl<T> Sync for ControllerDispatch<T> {}
+        if let Some(ref media_type) = *produces {
+            if !res.headers_map().contains_key(header::CONTENT_TYPE) {
+                res.header(header::CONTENT_TYPE, media_type.as_str());
+            }
+        }
+    }
 
-unsafe impl<T> Send for ControllerDispatch<T> {}
+    /// Describe the registered delegates, in their matching order, on behalf of `controller`
+    pub fn routes(&self, controller: &str) -> Vec<RouteInfo> {
+        self.delegates.delegates.iter().map(|(method, reg, op_guards, _, predicate, produces)| {
+            let guards = op_guards.as_ref()
+                .map(|guards| guards.guards.iter().map(|g| g.name()).collect())
+                .unwrap_or_default();
+
+            let mut route = RouteInfo::new(controller, Some(method.clone()), Some(reg.as_str().to_string()), guards);
+            route.name = self.labels.get(self.primary(method, reg.as_str())).and_then(|methods| methods.get(method)).cloned();
+            route.sitemap = self.sitemap.get(reg.as_str()).and_then(|methods| methods.get(method)).cloned();
+            route.predicate = predicate.as_ref().map(|predicate| predicate.to_string());
+            route.produces = produces.clone();
+            route.metadata = self.metadata.get(self.primary(method, reg.as_str())).and_then(|methods| methods.get(method)).cloned();
+            route
+        }).chain(self.fallback.iter().map(|_| RouteInfo::new(controller, None, None, Vec::new()))).collect()
+    }
+}
 
 /// An helper struct embedding a `ControllerDispatch`.
 pub struct BasicController<C> {
@@ -170,6 +768,10 @@ impl<C: Send + Sync> Controller for BasicController<C> {
     fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
         self.dispatch.dispatch(req, res);
     }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.dispatch.routes(&short_type_name(type_name::<Self>()))
+    }
 }
 
 impl<C: Send + Sync> BasicController<C> {
@@ -180,16 +782,22 @@ impl<C: Send + Sync> BasicController<C> {
         }
     }
 
+    /// Set what to do when a delegate is added after a delegate of the same method shadowing it
+    pub fn conflict_policy(mut self, policy: RouteConflictPolicy) -> Self {
+        self.dispatch = self.dispatch.conflict_policy(policy);
+        self
+    }
+
     /// Add a delegate function to handle a particular request
     /// # Example
     ///
     /// ```rust,no_run
     /// let u8_context = 1;
-    /// let u8_controller = BasicController::new(u8_context);
+    /// let mut u8_controller = BasicController::new(u8_context);
     /// u8_controller.add(Method::Get, "^/test$", |ctx, req, res| { println!("this will handle Get request done on <your_host>/test")});
     /// ```
-    pub fn add<F, R: ToRegex>(&self, method: Method, path: R, delegate_func: F)
-        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) {
+    pub fn add<F, R: ToRegex>(&mut self, method: Method, path: R, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
         self.dispatch.add(method, path, delegate_func);
     }
 
@@ -198,13 +806,206 @@ impl<C: Send + Sync> BasicController<C> {
     ///
     /// ```rust,no_run
     /// let u8_context = 1;
-    /// let u8_controller = BasicController::new(u8_context);
+    /// let mut u8_controller = BasicController::new(u8_context);
     /// u8_controller.add(Method::Get, "^/test$", |ctx, req, res| { println!("this will handle Get request done on <your_host>/test")});
     /// ```
-    pub fn add_with_guards<F, R: ToRegex>(&self, method: Method, path: R, guards: RequestGuardCollection, delegate_func: F)
-        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) {
+    pub fn add_with_guards<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
         self.dispatch.add_with_guards(method, path, guards, delegate_func);
     }
+
+    /// Add a delegate function to handle the requests matching any of `paths`, like the translations of a path, see
+    /// `ControllerDispatch::add_aliases`
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut controller = BasicController::new(());
+    /// controller.add_aliases(Method::GET, &["^/about$", "^/a-propos$"], |_, _, res| {
+    ///     let path = res.route_alias().map(|alias| alias.pattern().to_string());
+    ///     res.status(StatusCode::OK).body(format!("{:?}", path));
+    /// });
+    /// ```
+    pub fn add_aliases<F, R: ToRegex>(&mut self, method: Method, paths: &[R], delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_aliases(method, paths, delegate_func);
+    }
+
+    /// Add a delegate function to handle the requests matching any of `paths`, with guards, see
+    /// `ControllerDispatch::add_aliases_with_guards`
+    pub fn add_aliases_with_guards<F, R: ToRegex>(&mut self, method: Method, paths: &[R], guards: RequestGuardCollection, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_aliases_with_guards(method, paths, guards, delegate_func);
+    }
+
+    /// Add a delegate function to handle a particular request, matched before the delegates of a lower priority whatever
+    /// their registration order. Delegates added without a priority have a priority of 0.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut controller = BasicController::new(());
+    /// controller.add(Method::GET, "^/users/[^/]+$", |_, _, res| { res.status(StatusCode::OK).body("a user"); });
+    /// controller.add_with_priority(Method::GET, "^/users/me$", 10, |_, _, res| { res.status(StatusCode::OK).body("me"); });
+    /// ```
+    pub fn add_with_priority<F, R: ToRegex>(&mut self, method: Method, path: R, priority: i32, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_with_priority(method, path, priority, delegate_func);
+    }
+
+    /// Add a delegate function to handle a particular request, with guards, matched before the delegates of a lower priority
+    /// whatever their registration order
+    pub fn add_with_guards_and_priority<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, priority: i32, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_with_guards_and_priority(method, path, guards, priority, delegate_func);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` which also match `predicate`, see
+    /// `ControllerDispatch::add_when`
+    pub fn add_when<F, R: ToRegex>(&mut self, method: Method, path: R, predicate: RequestPredicate, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_when(method, path, predicate, delegate_func);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` which also match `predicate`, with guards, see
+    /// `ControllerDispatch::add_when`
+    pub fn add_with_guards_when<F, R: ToRegex>(&mut self, method: Method, path: R, guards: RequestGuardCollection, predicate: RequestPredicate, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_with_guards_when(method, path, guards, predicate, delegate_func);
+    }
+
+    /// Add a delegate function to handle the requests matching `path` with a response of `media_type`, see
+    /// `ControllerDispatch::add_producing`
+    pub fn add_producing<F, R: ToRegex>(&mut self, method: Method, path: R, media_type: &str, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_producing(method, path, media_type, delegate_func);
+    }
+
+    /// Add a delegate function to handle a particular request, documented by `metadata`, see `RouteMetadata`
+    pub fn add_documented<F, R: ToRegex>(&mut self, method: Method, path: R, metadata: RouteMetadata, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_documented(method, path, metadata, delegate_func);
+    }
+
+    /// Move the delegates of `other` into this controller, after the delegates of the same priority, and its fallback if this
+    /// controller has none, so that the routes of a controller can be defined across modules. The context of `other` is
+    /// dropped, conflicts are reported according to the conflict policy of this controller, see `ControllerDispatch::merge`.
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn user_routes() -> BasicController<()> {
+    ///     let mut controller = BasicController::new(());
+    ///     controller.add(Method::GET, "^/users$", |_, _, res| { res.status(StatusCode::OK); });
+    ///     controller
+    /// }
+    ///
+    /// fn group_routes() -> BasicController<()> {
+    ///     let mut controller = BasicController::new(());
+    ///     controller.add(Method::GET, "^/groups$", |_, _, res| { res.status(StatusCode::OK); });
+    ///     controller
+    /// }
+    ///
+    /// let mut controller = user_routes();
+    /// controller.merge(group_routes());
+    /// ```
+    pub fn merge(&mut self, other: BasicController<C>) {
+        self.dispatch.merge(other.dispatch);
+    }
+
+    /// Set the function handling the requests matching no delegate, instead of answering them with `405 Method Not Allowed`
+    /// or `400 Bad Request`
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut controller = BasicController::new(());
+    /// controller.add(Method::GET, "^/docs/index$", |_, _, res| { res.status(StatusCode::OK).body("<h1>Docs</h1>"); });
+    /// controller.fallback(|_, req, res| {
+    ///     res.status(StatusCode::NOT_FOUND).body(format!("<h1>No page at {}</h1>", req.uri().path()));
+    /// });
+    /// ```
+    pub fn fallback<F>(&mut self, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.dispatch.fallback(delegate_func);
+    }
+
+    /// Document the delegates of `method` registered under `path`, see `ControllerDispatch::document`
+    pub fn document<R: ToRegex>(&mut self, method: Method, path: R, metadata: RouteMetadata) {
+        self.dispatch.document(method, path, metadata);
+    }
+
+    /// Give the delegates of `method` registered under `path` a stable label, see `ControllerDispatch::label`
+    pub fn label<R: ToRegex>(&mut self, method: Method, path: R, label: &str) {
+        self.dispatch.label(method, path, label);
+    }
+
+    /// Deprecate the delegates of `method` registered under `path`, see `ControllerDispatch::deprecate`
+    pub fn deprecate<R: ToRegex>(&mut self, method: Method, path: R, deprecation: Deprecation) {
+        self.dispatch.deprecate(method, path, deprecation);
+    }
+
+    /// Describe how the delegates of `method` registered under `path` are listed in the sitemap, see
+    /// `ControllerDispatch::sitemap`
+    pub fn sitemap<R: ToRegex>(&mut self, method: Method, path: R, entry: SitemapEntry) {
+        self.dispatch.sitemap(method, path, entry);
+    }
+
+    /// Add a delegate function to handle a particular request, recovering from its failures according to `policy`
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use std::time::Duration;
+    /// let mut controller = BasicController::new(());
+    /// let policy = RoutePolicy::new().timeout(Duration::from_secs(1)).retries(1);
+    /// controller.add_with_policy(Method::GET, "^/test$", policy, |_, _, res| { res.status(StatusCode::OK); });
+    /// ```
+    pub fn add_with_policy<F, R: ToRegex>(&mut self, method: Method, path: R, policy: RoutePolicy<C>, delegate_func: F)
+        where for<'r, 's, 't0> F: 'static + Fn(&'r C, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync, C: 'static {
+        self.dispatch.add_with_policy(method, path, policy, delegate_func);
+    }
+
+    /// Add a delegate function to handle a particular request, receiving along with the context a value created by `factory`
+    /// for the request and released once the delegate returned
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// struct Pool;
+    /// struct Connection;
+    ///
+    /// impl Pool {
+    ///     fn checkout(&self) -> Option<Connection> { Some(Connection) }
+    ///     fn checkin(&self, _connection: Connection) {}
+    /// }
+    ///
+    /// struct Checkout;
+    ///
+    /// impl ContextFactory<Pool> for Checkout {
+    ///     type Scoped = Connection;
+    ///
+    ///     fn create(&self, pool: &Pool, _req: &SyncRequest, res: &mut SyncResponse) -> Option<Connection> {
+    ///         let connection = pool.checkout();
+    ///         if connection.is_none() {
+    ///             res.status(StatusCode::SERVICE_UNAVAILABLE);
+    ///         }
+    ///         connection
+    ///     }
+    ///
+    ///     fn release(&self, pool: &Pool, connection: Connection, _req: &SyncRequest, _res: &mut SyncResponse) {
+    ///         pool.checkin(connection);
+    ///     }
+    /// }
+    ///
+    /// let mut controller = BasicController::new(Pool);
+    /// controller.add_scoped(Method::GET, "^/users$", Checkout, |_, _connection, _, res| { res.status(StatusCode::OK); });
+    /// ```
+    pub fn add_scoped<S, F, R: ToRegex>(&mut self, method: Method, path: R, factory: S, delegate_func: F)
+        where S: 'static + ContextFactory<C>,
+              for<'r, 's, 't0, 't1> F: 'static + Fn(&'r C, &'s mut S::Scoped, &'t0 SyncRequest, &'t1 mut SyncResponse) + Send + Sync {
+        self.dispatch.add_scoped(method, path, factory, delegate_func);
+    }
 }
 
 /// RequestGuard ensuring that a request has a body
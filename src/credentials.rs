@@ -0,0 +1,73 @@
+use hmac::Hmac;
+use hmac::KeyInit;
+use hmac::Mac;
+use sha2::Digest;
+use sha2::Sha256;
+use std::fmt::Write;
+use subtle::ConstantTimeEq;
+
+/// Compare two secrets, like API keys or signatures, in a time which only depends on their lengths
+///
+/// Comparing secrets with `==` returns as soon as a byte differs, letting an attacker guess them byte by byte by timing
+/// the responses. Only the lengths of the secrets may leak.
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// assert!(constant_time_eq(b"s3cr3t-k3y", b"s3cr3t-k3y"));
+/// assert!(!constant_time_eq(b"s3cr3t-k3y", b"s3cr3t-k3z"));
+/// ```
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Compare two secret strings in a time which only depends on their lengths, see `constant_time_eq`
+pub fn constant_time_str_eq(a: &str, b: &str) -> bool {
+    constant_time_eq(a.as_bytes(), b.as_bytes())
+}
+
+/// A 128-bit unguessable token in hexadecimal, for session ids, CSRF tokens or OAuth 2.0 states
+///
+/// Tokens are read from the random number generator of the operating system.
+///
+/// # Panics
+///
+/// Panics if the operating system can't provide random bytes, rather than handing out guessable tokens.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 16];
+    ::getrandom::getrandom(&mut bytes).expect("The random number generator of the operating system is unavailable");
+
+    to_hex(&bytes)
+}
+
+/// Returns `bytes` in lowercase hexadecimal
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// Returns the SHA-256 digest of `data`
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Returns the HMAC-SHA256 of `message` keyed with `key`, as defined by RFC 2104, to sign or verify messages with a shared
+/// secret
+///
+/// Signatures received from clients should be compared with `constant_time_eq`.
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// let signature = hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog");
+/// assert_eq!(signature[..4], [0xf7, 0xbc, 0x83, 0xf4]);
+/// ```
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
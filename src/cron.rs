@@ -0,0 +1,190 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// An error raised when parsing an invalid cron expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum CronError {
+    /// The expression doesn't have the five fields minute, hour, day of month, month and day of week
+    FieldCount(usize),
+    /// A field of the expression is invalid or out of its range
+    InvalidField(String),
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CronError::FieldCount(count) => write!(f, "a cron expression has 5 fields, found {}", count),
+            CronError::InvalidField(ref field) => write!(f, "the cron field `{}` is invalid", field),
+        }
+    }
+}
+
+impl ::std::error::Error for CronError {}
+
+/// Years searched for the next occurrence of an expression before giving up, which is only reached by expressions like
+/// `0 0 30 2 *` which never match
+const SEARCHED_YEARS: u64 = 8;
+
+/// A parsed cron expression, matched against UTC time with a precision of one minute
+///
+/// Expressions have the five usual fields: minute (0-59), hour (0-23), day of month (1-31), month (1-12) and day of week
+/// (0-7, both 0 and 7 being Sunday). A field is a comma separated list of `*`, values and ranges like `1-5`, each optionally
+/// followed by a step like `*/15`. As usual, when both the day of month and the day of week are restricted, a day matching
+/// either of them matches. The `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` shortcuts are supported as well.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let every_weekday_morning: CronSchedule = "30 7 * * 1-5".parse().unwrap();
+/// // Monday the 2nd of January 2023 at 7:30
+/// assert_eq!(every_weekday_morning.next_after(1672642800), Some(1672644600));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+fn parse_value(value: &str, min: u32, max: u32, field: &str) -> Result<u32, CronError> {
+    match value.parse::<u32>() {
+        Ok(value) if value >= min && value <= max => Ok(value),
+        _ => Err(CronError::InvalidField(field.to_string())),
+    }
+}
+
+/// Parse a field into a bitmask of its values, and whether it is a bare `*`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), CronError> {
+    let mut mask = 0u64;
+
+    for item in field.split(',') {
+        let (range, step) = match item.find('/') {
+            Some(index) => (&item[..index], parse_value(&item[index + 1..], 1, max, field)?),
+            None => (item, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(index) = range.find('-') {
+            (parse_value(&range[..index], min, max, field)?, parse_value(&range[index + 1..], min, max, field)?)
+        } else {
+            let start = parse_value(range, min, max, field)?;
+            (start, if step > 1 { max } else { start })
+        };
+
+        if start > end {
+            return Err(CronError::InvalidField(field.to_string()));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok((mask, field == "*"))
+}
+
+/// Returns the year, month and day of the `days`th day after the 1st of January 1970
+fn civil_from_days(days: u64) -> (u64, u32, u32) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+impl CronSchedule {
+    fn matches_day(&self, days: u64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // The 1st of January 1970 was a Thursday
+        let weekday = (days + 4) % 7;
+
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+
+        let day_of_month = self.days_of_month & (1 << day) != 0;
+        let day_of_week = self.days_of_week & (1 << weekday) != 0;
+
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+
+    /// Returns the first time matching the expression strictly after `timestamp`, both in seconds since the unix epoch, or
+    /// `None` if the expression never matches
+    pub fn next_after(&self, timestamp: u64) -> Option<u64> {
+        let mut minute = timestamp / 60 + 1;
+        let limit = minute + SEARCHED_YEARS * 366 * 1440;
+
+        while minute < limit {
+            let days = minute / 1440;
+
+            if !self.matches_day(days) {
+                minute = (days + 1) * 1440;
+            } else if self.hours & (1 << (minute / 60 % 24)) == 0 {
+                minute = (minute / 60 + 1) * 60;
+            } else if self.minutes & (1 << (minute % 60)) == 0 {
+                minute += 1;
+            } else {
+                return Some(minute * 60);
+            }
+        }
+
+        None
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, CronError> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(CronError::FieldCount(fields.len()));
+        }
+
+        let (minutes, _) = parse_field(fields[0], 0, 59)?;
+        let (hours, _) = parse_field(fields[1], 0, 23)?;
+        let (days_of_month, any_day_of_month) = parse_field(fields[2], 1, 31)?;
+        let (months, _) = parse_field(fields[3], 1, 12)?;
+        let (mut days_of_week, any_day_of_week) = parse_field(fields[4], 0, 7)?;
+
+        // Sunday is both 0 and 7
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(CronSchedule {
+            minutes,
+            hours,
+            days_of_month,
+            months,
+            days_of_week,
+            any_day_of_month,
+            any_day_of_week,
+        })
+    }
+}
@@ -0,0 +1,158 @@
+use http::*;
+use credentials::constant_time_str_eq;
+use credentials::random_token;
+use form::html_escape;
+use middleware::Middleware;
+use problem::Problem;
+use query::query_pairs;
+use utils::RequestContinuation;
+
+/// The token of a request, set by `CsrfMiddleware` in the extensions of the request and of its response
+#[derive(Clone)]
+pub(crate) struct CsrfToken {
+    pub(crate) token: String,
+    pub(crate) field: String,
+}
+
+impl SyncRequest {
+    /// Returns the CSRF token forms must submit, when `CsrfMiddleware` is applied to the request
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.extensions().get::<CsrfToken>().map(|csrf| csrf.token.as_str())
+    }
+
+    /// Render the hidden input carrying the CSRF token, to embed in the forms of server-rendered pages. Returns an empty
+    /// string when `CsrfMiddleware` isn't applied to the request.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn show_form(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).body(format!(r#"<form method="post">{}<input name="title"></form>"#, req.csrf_input()));
+    /// }
+    /// ```
+    pub fn csrf_input(&self) -> String {
+        match self.extensions().get::<CsrfToken>() {
+            Some(csrf) => format!(r#"<input type="hidden" name="{}" value="{}">"#, html_escape(&csrf.field), html_escape(&csrf.token)),
+            None => String::new(),
+        }
+    }
+}
+
+/// A middleware protecting forms and other state-changing requests against cross-site request forgery, with a
+/// double-submit cookie
+///
+/// Every request is given a token, kept in a `SameSite=Strict` cookie. `POST`, `PUT`, `PATCH` and `DELETE` requests must
+/// send it back, either in the `csrf_token` field of an `application/x-www-form-urlencoded` body or in the `X-CSRF-Token`
+/// header, otherwise they are answered with `403 Forbidden`. Pages embed the token with `SyncRequest::csrf_input`, and
+/// templates rendered with `SyncResponse::render` receive it as their `csrf_token` member. The cookie isn't `HttpOnly`, so
+/// that scripts can copy it into the header.
+///
+/// Routes called by other services, like webhooks, are left out with the exclude list of `MiddlewareStack::apply`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(CsrfMiddleware::new(), vec!("^/"), Some(vec!("^/webhooks")));
+/// ```
+pub struct CsrfMiddleware {
+    cookie_name: String,
+    field_name: String,
+    secure: bool,
+}
+
+impl CsrfMiddleware {
+    /// Create a middleware keeping tokens in the `saphir_csrf` cookie, checked against the `csrf_token` form field
+    pub fn new() -> Self {
+        CsrfMiddleware {
+            cookie_name: "saphir_csrf".to_string(),
+            field_name: "csrf_token".to_string(),
+            secure: false,
+        }
+    }
+
+    /// Set the name of the cookie holding the token
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Set the name of the form field holding the token
+    pub fn field_name(mut self, name: &str) -> Self {
+        self.field_name = name.to_string();
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    fn submitted_token(&self, req: &SyncRequest) -> Option<String> {
+        if let Some(token) = req.headers_map().get("x-csrf-token").and_then(|t| t.to_str().ok()) {
+            return Some(token.trim().to_string());
+        }
+
+        if !req.is_form() {
+            return None;
+        }
+
+        query_pairs(&String::from_utf8_lossy(req.body())).into_iter()
+            .find(|(name, _)| *name == self.field_name)
+            .map(|(_, token)| token)
+    }
+}
+
+impl Default for CsrfMiddleware {
+    fn default() -> Self {
+        CsrfMiddleware::new()
+    }
+}
+
+impl Middleware for CsrfMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let existing = req.cookie(&self.cookie_name)
+            .filter(|token| token.len() == 32 && token.bytes().all(|b| b.is_ascii_hexdigit()))
+            .map(|token| token.to_string());
+
+        let state_changing = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+
+        if state_changing {
+            let valid = match (existing.as_ref(), self.submitted_token(req)) {
+                (Some(expected), Some(submitted)) => constant_time_str_eq(expected, &submitted),
+                _ => false,
+            };
+
+            if !valid {
+                res.problem(&Problem::new(StatusCode::FORBIDDEN).with_detail("Missing or invalid CSRF token"));
+                return RequestContinuation::None;
+            }
+        }
+
+        let token = match existing {
+            Some(token) => token,
+            None => {
+                let token = random_token();
+                let secure = if self.secure { "; Secure" } else { "" };
+                res.header(header::SET_COOKIE, format!("{}={}; Path=/; SameSite=Strict{}", self.cookie_name, token, secure));
+                token
+            }
+        };
+
+        let csrf = CsrfToken {
+            token,
+            field: self.field_name.clone(),
+        };
+        res.extension(csrf.clone());
+        req.extensions_mut().insert(csrf);
+
+        RequestContinuation::Next
+    }
+}
@@ -0,0 +1,55 @@
+use http::*;
+use serde::Serialize;
+use std::thread;
+
+impl SyncResponse {
+    /// Stream `rows` as CSV, along with the `text/csv; charset=utf-8` content type, for exports too large to be held in
+    /// memory
+    ///
+    /// Rows are serialized on a thread of their own and sent to the client as they are produced, in chunks of a few
+    /// kilobytes, see `SyncResponse::stream`. When rows are structs or maps, the first line is a header made of their field
+    /// names. If a row cannot be serialized, the body ends with the rows preceding it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,ignore
+    /// #[derive(Serialize)]
+    /// struct User {
+    ///     id: u64,
+    ///     name: String,
+    /// }
+    ///
+    /// fn export(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let users = (0..1_000_000).map(|id| User { id, name: format!("user {}", id) });
+    ///     res.status(StatusCode::OK).header(header::CONTENT_DISPOSITION, content_disposition("users.csv")).csv(users);
+    /// }
+    /// ```
+    pub fn csv<I, T>(&mut self, rows: I) -> &mut SyncResponse
+        where I: 'static + IntoIterator<Item=T> + Send,
+              I::IntoIter: Send,
+              T: Serialize {
+        self.headers_map_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/csv; charset=utf-8"));
+        let sender = self.stream();
+        let rows = rows.into_iter();
+
+        let spawned = thread::Builder::new().name("saphir-csv".to_string()).spawn(move || {
+            let mut writer = ::csv::Writer::from_writer(sender);
+            for row in rows {
+                if let Err(e) = writer.serialize(row) {
+                    if !e.is_io_error() {
+                        error!("Unable to serialize a row of the response body as csv: {}", e);
+                    }
+                    return;
+                }
+            }
+            let _ = writer.flush();
+        });
+
+        if let Err(e) = spawned {
+            error!("Unable to stream the response body as csv: {}", e);
+            self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+        }
+
+        self
+    }
+}
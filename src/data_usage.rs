@@ -0,0 +1,146 @@
+use http::*;
+use metrics::Metrics;
+use middleware::Middleware;
+use response_size::observe_response_size;
+use utils::RequestContinuation;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+/// Returns the size of a head made of a start line of `start_line` bytes and of `headers`, as written in HTTP/1.1
+fn head_size(start_line: usize, headers: &header::HeaderMap<header::HeaderValue>) -> u64 {
+    let headers: usize = headers.iter().map(|(name, value)| name.as_str().len() + value.len() + 4).sum();
+    (start_line + 2 + headers + 2) as u64
+}
+
+/// Returns the size of the head of a response of `status` with `headers`, as written in HTTP/1.1
+pub(crate) fn response_head_size(status: StatusCode, headers: &header::HeaderMap<header::HeaderValue>) -> u64 {
+    let reason = status.canonical_reason().map_or(0, |reason| reason.len() + 1);
+    head_size("HTTP/1.1 200".len() + reason, headers)
+}
+
+impl SyncRequest {
+    /// Returns the number of bytes the request was received in: its head, as written in HTTP/1.1, and its body as it was
+    /// received. The body of a request left unread by the server is counted from its `Content-Length`, see
+    /// `BodyLimit::buffered`.
+    pub fn received_bytes(&self) -> u64 {
+        let start_line = self.method().as_str().len() + 1 + self.uri().to_string().len() + " HTTP/1.1".len();
+        let body = if self.has_unread_body() {
+            self.headers_map().get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok()).unwrap_or(0)
+        } else {
+            self.raw_body().len() as u64
+        };
+        head_size(start_line, self.headers_map()) + body
+    }
+
+    /// Returns the data exchanged so far on the connection the request was received on, `None` when it wasn't received on a
+    /// connection of the server, like in tests
+    pub fn connection_usage(&self) -> Option<ConnectionUsage> {
+        self.extensions().get::<ConnectionUsage>().cloned()
+    }
+}
+
+/// The data exchanged on a connection of the server, see `SyncRequest::connection_usage`
+///
+/// Heads are counted as written in HTTP/1.1 and bodies as they were received or sent, so the counts approximate the bytes
+/// of the socket, without the framing of chunked bodies nor the compression of HTTP/2 headers.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionUsage {
+    requests: Arc<AtomicU64>,
+    received: Arc<AtomicU64>,
+    sent: Arc<AtomicU64>,
+}
+
+impl ConnectionUsage {
+    /// Returns the number of requests received on the connection
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes received on the connection
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes sent on the connection, once the responses are sent
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_request(&self, received: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.received.fetch_add(received, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_response(&self, sent: u64) {
+        self.sent.fetch_add(sent, Ordering::Relaxed);
+    }
+}
+
+type IdentityExtractor = Box<dyn Fn(&SyncRequest) -> Option<String> + Send + Sync>;
+
+/// A middleware counting the bytes received and sent for every client, to account for the bandwidth of an API by key
+///
+/// Bytes are counted in `http_received_bytes_total` and `http_sent_bytes_total`, labeled by the identity of the client, as
+/// found by the extractor set with `key` or `identity`, typically the user or API key an authentication guard or middleware
+/// inserted in the extensions of the request, or `anonymous`. Heads are counted as written in HTTP/1.1 along with the
+/// bodies, the bytes of streamed bodies once they are sent.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// struct ApiKey {
+///     id: String,
+/// }
+///
+/// let metrics = Metrics::new();
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(DataUsageMiddleware::new(metrics.clone()).identity(|key: &ApiKey| key.id.clone()), vec!("^/api"), None);
+/// ```
+pub struct DataUsageMiddleware {
+    metrics: Metrics,
+    key: IdentityExtractor,
+}
+
+impl DataUsageMiddleware {
+    /// Create a middleware counting the bytes of every client in `metrics`, all of them anonymous
+    pub fn new(metrics: Metrics) -> Self {
+        metrics.describe("http_received_bytes_total", "Bytes received in requests, by client");
+        metrics.describe("http_sent_bytes_total", "Bytes sent in responses, by client");
+        DataUsageMiddleware {
+            metrics,
+            key: Box::new(|_| None),
+        }
+    }
+
+    /// Identify clients with `key`, requests it returns `None` for are anonymous
+    pub fn key<F>(mut self, key: F) -> Self
+        where F: 'static + Fn(&SyncRequest) -> Option<String> + Send + Sync {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Identify clients by the value of type `T` found in the extensions of the request, like the user or API key inserted
+    /// by an authentication guard, requests without one are anonymous
+    pub fn identity<T, F>(self, key: F) -> Self
+        where T: 'static + Send + Sync,
+              F: 'static + Fn(&T) -> String + Send + Sync {
+        self.key(move |req| req.extensions().get::<T>().map(&key))
+    }
+}
+
+impl Middleware for DataUsageMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let client = (self.key)(req).unwrap_or_else(|| "anonymous".to_string());
+        self.metrics.add("http_received_bytes_total", &[("client", &client)], req.received_bytes());
+
+        let head = response_head_size(res.get_status(), res.headers_map());
+        let metrics = self.metrics.clone();
+        observe_response_size(res, move |bytes| metrics.add("http_sent_bytes_total", &[("client", &client)], head + bytes));
+    }
+}
@@ -0,0 +1,35 @@
+use http::header::HeaderValue;
+use http::header::HttpDate;
+use arc_swap::ArcSwapOption;
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+struct CachedDate {
+    second: u64,
+    value: HeaderValue,
+}
+
+static CACHE: ArcSwapOption<CachedDate> = ArcSwapOption::const_empty();
+
+/// Returns the current date formatted for the `Date` header
+///
+/// The formatted value is shared by every thread and refreshed at most once per second, so responses don't format it
+/// themselves.
+pub fn http_date() -> HeaderValue {
+    let now = SystemTime::now();
+    let second = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    if let Some(ref cached) = *CACHE.load() {
+        if cached.second == second {
+            return cached.value.clone();
+        }
+    }
+
+    let value = HeaderValue::from_str(&HttpDate::from(now).to_string()).expect("an http date is a valid header value");
+    CACHE.store(Some(Arc::new(CachedDate {
+        second,
+        value: value.clone(),
+    })));
+    value
+}
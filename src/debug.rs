@@ -0,0 +1,215 @@
+use http::*;
+use controller::Controller;
+use controller::RequestGuardCollection;
+use controller::RouteInfo;
+use route_metadata::RouteMetadata;
+use middleware::MiddlewareStack;
+use router::Router;
+use utils::RequestContinuation;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many requests a `DebugController` remembers unless told otherwise
+pub const DEFAULT_DEBUG_CAPACITY: usize = 100;
+
+/// Summary of a request answered by the server, as listed by the `DebugController`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequestSummary {
+    /// The method of the request
+    pub method: String,
+    /// The path of the request, as received by the server
+    pub path: String,
+    /// The status of the response
+    pub status: u16,
+    /// The label of the route which handled the request, see `SyncResponse::route_label`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    /// The time spent processing the request, in milliseconds
+    pub latency_ms: f64,
+}
+
+/// The last requests answered by the server, oldest first
+#[derive(Clone)]
+pub(crate) struct RequestLog {
+    capacity: usize,
+    requests: Arc<Mutex<VecDeque<RequestSummary>>>,
+}
+
+impl RequestLog {
+    fn new(capacity: usize) -> Self {
+        RequestLog {
+            capacity,
+            requests: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Remember the request of `method` to `path`, forgetting the oldest one once the log is full
+    pub(crate) fn record(&self, method: &Method, path: String, res: &SyncResponse, latency: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let summary = RequestSummary {
+            method: method.to_string(),
+            path,
+            status: res.get_status().as_u16(),
+            route: res.route_label().map(|label| label.to_string()),
+            latency_ms: latency.as_secs() as f64 * 1e3 + latency.subsec_nanos() as f64 * 1e-6,
+        };
+
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if requests.len() == self.capacity {
+            requests.pop_front();
+        }
+        requests.push_back(summary);
+    }
+
+    fn snapshot(&self) -> Vec<RequestSummary> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
+/// A route of the router, as reported by the `DebugController` and the `AdminController`
+#[derive(Serialize)]
+pub(crate) struct RouteReport {
+    method: Option<String>,
+    controller_route: String,
+    pattern: Option<String>,
+    guards: Vec<String>,
+    controller: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<RouteMetadata>,
+}
+
+/// Returns the routes of `router`
+pub(crate) fn route_reports(router: &Router) -> Vec<RouteReport> {
+    router.routes().into_iter().map(|route| RouteReport {
+        method: route.method.map(|method| method.to_string()),
+        controller_route: route.controller_route,
+        pattern: route.pattern,
+        guards: route.guards,
+        controller: route.controller,
+        name: route.name,
+        metadata: route.metadata,
+    }).collect()
+}
+
+#[derive(Serialize)]
+struct MiddlewareReport {
+    name: String,
+    include_path: Vec<String>,
+    exclude_path: Vec<String>,
+}
+
+/// What the server describes of itself to the `DebugController` once built
+#[derive(Default)]
+struct Introspection {
+    prefix: String,
+    routes: Vec<RouteReport>,
+    middlewares: Vec<MiddlewareReport>,
+}
+
+/// A controller exposing what the server is doing, for live troubleshooting: the last requests it answered along with their
+/// status and latency, its routes and its middleware stack
+///
+/// The controller is opt-in, it is registered with `ServerBuilder::debug_endpoint` under a path prefix and answers, as JSON,
+/// `GET <prefix>` with everything, `GET <prefix>/requests` with the last requests, oldest first, `GET <prefix>/routes` with
+/// the route table and `GET <prefix>/middlewares` with the middleware stack. Since it reveals how the application is built
+/// and what its clients request, every request is validated by the guards it is created with before being answered.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+///
+/// let server = Server::builder()
+///     .router(Router::new())
+///     .debug_endpoint("/_debug", DebugController::new(loopback))
+///     .build();
+/// ```
+pub struct DebugController {
+    guards: RequestGuardCollection,
+    requests: RequestLog,
+    introspection: Arc<Mutex<Introspection>>,
+}
+
+impl DebugController {
+    /// Create a controller answering the requests validated by `guards`, which remembers the last `DEFAULT_DEBUG_CAPACITY`
+    /// requests
+    pub fn new<G: Into<RequestGuardCollection>>(guards: G) -> Self {
+        DebugController {
+            guards: guards.into(),
+            requests: RequestLog::new(DEFAULT_DEBUG_CAPACITY),
+            introspection: Arc::new(Mutex::new(Introspection::default())),
+        }
+    }
+
+    /// Set how many requests are remembered
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.requests = RequestLog::new(capacity);
+        self
+    }
+
+    /// Register the controller under `prefix` in `router`, describing the router and `middlewares` once it is part of it.
+    /// Returns the log the server records its requests into.
+    pub(crate) fn register(self, prefix: &str, router: &mut Router, middlewares: &MiddlewareStack) -> RequestLog {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        let requests = self.requests.clone();
+        let introspection = self.introspection.clone();
+        router.add(format!("^{}(/|$)", ::regex::escape(&prefix)), self);
+
+        let mut introspection = introspection.lock().unwrap_or_else(|e| e.into_inner());
+        introspection.prefix = prefix;
+        introspection.routes = route_reports(router);
+        introspection.middlewares = middlewares.middlewares().into_iter().map(|middleware| MiddlewareReport {
+            name: middleware.name,
+            include_path: middleware.include_path,
+            exclude_path: middleware.exclude_path,
+        }).collect();
+
+        requests
+    }
+}
+
+impl Controller for DebugController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        for guard in &self.guards {
+            if let RequestContinuation::None = guard.validate(req, res) {
+                return;
+            }
+        }
+
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, "GET, HEAD");
+            return;
+        }
+
+        let introspection = self.introspection.lock().unwrap_or_else(|e| e.into_inner());
+        let resource = req.uri().path().get(introspection.prefix.len()..).unwrap_or("").trim_end_matches('/');
+        res.header(header::CACHE_CONTROL, "no-store");
+
+        match resource {
+            "" => res.status(StatusCode::OK).json(&json!({
+                "requests": self.requests.snapshot(),
+                "routes": introspection.routes,
+                "middlewares": introspection.middlewares,
+            })),
+            "/requests" => res.status(StatusCode::OK).json(&self.requests.snapshot()),
+            "/routes" => res.status(StatusCode::OK).json(&introspection.routes),
+            "/middlewares" => res.status(StatusCode::OK).json(&introspection.middlewares),
+            _ => res.status(StatusCode::NOT_FOUND),
+        };
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let guards: Vec<String> = (&self.guards).into_iter().map(|guard| guard.name()).collect();
+        vec![Method::GET, Method::HEAD].into_iter()
+            .map(|method| RouteInfo::new("DebugController", Some(method), None, guards.clone()))
+            .collect()
+    }
+}
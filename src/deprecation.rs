@@ -0,0 +1,94 @@
+use http::*;
+use http::header::HttpDate;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The deprecation of a route, announced to its clients with the `Deprecation` header of RFC 9745, and the `Sunset`
+/// header of RFC 8594 once the route has a removal date, see `ControllerDispatch::deprecate`
+///
+/// Requests to deprecated routes are counted by `MetricsMiddleware` in `http_deprecated_requests_total`, by method and
+/// route, telling which clients still have to migrate before the route is removed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let deprecation = Deprecation::new(UNIX_EPOCH + Duration::from_secs(1_767_225_600))
+///     .sunset(UNIX_EPOCH + Duration::from_secs(1_782_864_000))
+///     .documentation("https://example.com/changelog#v1-users")
+///     .successor("/v2/users");
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::GET, "^/v1/users$", |_, _, res| { res.status(StatusCode::OK).body("[]"); });
+/// controller.deprecate(Method::GET, "^/v1/users$", deprecation);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Deprecation {
+    since: SystemTime,
+    sunset: Option<SystemTime>,
+    links: Vec<String>,
+}
+
+impl Deprecation {
+    /// Deprecate a route as of `since`, which may be in the future to announce an upcoming deprecation
+    pub fn new(since: SystemTime) -> Self {
+        Deprecation {
+            since,
+            sunset: None,
+            links: Vec::new(),
+        }
+    }
+
+    /// Announce that the route will stop answering at `sunset`
+    pub fn sunset(mut self, sunset: SystemTime) -> Self {
+        self.sunset = Some(sunset);
+        self
+    }
+
+    /// Link the documentation of the deprecation, like a migration guide
+    pub fn documentation(mut self, url: &str) -> Self {
+        self.links.push(format!("<{}>; rel=\"deprecation\"; type=\"text/html\"", url));
+        self
+    }
+
+    /// Link the route replacing the deprecated one
+    pub fn successor(mut self, url: &str) -> Self {
+        self.links.push(format!("<{}>; rel=\"successor-version\"", url));
+        self
+    }
+
+    /// Add the headers announcing the deprecation to `res`, keeping the `Link` headers it already has
+    pub(crate) fn announce(&self, res: &mut SyncResponse) {
+        let since = self.since.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let headers = res.headers_map_mut();
+        if let Ok(value) = header::HeaderValue::from_str(&format!("@{}", since)) {
+            headers.insert("deprecation", value);
+        }
+
+        if let Some(sunset) = self.sunset {
+            if let Ok(value) = header::HeaderValue::from_str(&HttpDate::from(sunset).to_string()) {
+                headers.insert("sunset", value);
+            }
+        }
+
+        for link in &self.links {
+            if let Ok(value) = header::HeaderValue::from_str(link) {
+                headers.append(header::LINK, value);
+            }
+        }
+
+        res.extension(DeprecatedRoute);
+    }
+}
+
+/// Marks the responses of deprecated routes, for `MetricsMiddleware` to count them
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DeprecatedRoute;
+
+impl SyncResponse {
+    /// Returns true when the request was handled by a deprecated route, see `Deprecation`
+    pub fn is_deprecated_route(&self) -> bool {
+        self.get_extensions().get::<DeprecatedRoute>().is_some()
+    }
+}
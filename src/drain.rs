@@ -0,0 +1,150 @@
+use futures::Future;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The progress of the server draining its connections, as given to the `ServerBuilder::on_drain` hooks while the server
+/// shuts down and answered by the `AdminController`
+///
+/// Operators can tell from it whether the shutdown is stuck on a few long requests or streams, and decide to force the
+/// termination of the process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrainReport {
+    shutdown_age: Option<Duration>,
+    in_flight: usize,
+    streams: usize,
+    oldest_request_age: Option<Duration>,
+}
+
+impl DrainReport {
+    /// Returns true once the server stopped accepting connections and waits for the ones in progress to complete
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_age.is_some()
+    }
+
+    /// Returns the time elapsed since the server started shutting down, if it did
+    pub fn shutdown_age(&self) -> Option<Duration> {
+        self.shutdown_age
+    }
+
+    /// Returns the number of requests in progress, including the streamed responses being sent
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Returns the number of streamed responses being sent, like server-sent events, see `SyncResponse::stream`
+    pub fn streams(&self) -> usize {
+        self.streams
+    }
+
+    /// Returns the time elapsed since the oldest request in progress was received, if any
+    pub fn oldest_request_age(&self) -> Option<Duration> {
+        self.oldest_request_age
+    }
+}
+
+#[derive(Default)]
+struct Requests {
+    next: u64,
+    started: HashMap<u64, (Instant, bool)>,
+}
+
+/// The requests the server is processing, and when it started shutting down
+#[derive(Default)]
+pub(crate) struct InFlight {
+    requests: Mutex<Requests>,
+    shutdown: Mutex<Option<Instant>>,
+}
+
+impl InFlight {
+    /// Track a request until the returned handle is dropped, once its response is sent
+    pub(crate) fn begin(self: &Arc<Self>) -> InFlightRequest {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let id = requests.next;
+        requests.next += 1;
+        requests.started.insert(id, (Instant::now(), false));
+
+        InFlightRequest { in_flight: self.clone(), id }
+    }
+
+    pub(crate) fn report(&self) -> DrainReport {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let shutdown = *self.shutdown.lock().unwrap_or_else(|e| e.into_inner());
+
+        DrainReport {
+            shutdown_age: shutdown.map(|shutdown| shutdown.elapsed()),
+            in_flight: requests.started.len(),
+            streams: requests.started.values().filter(|&&(_, streamed)| streamed).count(),
+            oldest_request_age: requests.started.values().map(|&(started, _)| started).min().map(|started| started.elapsed()),
+        }
+    }
+}
+
+/// A request tracked by `InFlight`, until dropped
+pub(crate) struct InFlightRequest {
+    in_flight: Arc<InFlight>,
+    id: u64,
+}
+
+impl InFlightRequest {
+    /// Mark the request as answered with a streamed response
+    pub(crate) fn streaming(&self) {
+        let mut requests = self.in_flight.requests.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(request) = requests.started.get_mut(&self.id) {
+            request.1 = true;
+        }
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.in_flight.requests.lock().unwrap_or_else(|e| e.into_inner()).started.remove(&self.id);
+    }
+}
+
+/// Stops reporting the draining of the connections once dropped, when the server stopped
+pub(crate) struct DrainWatcher {
+    in_flight: Arc<InFlight>,
+    stop: Option<Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for DrainWatcher {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        // A server failing without shutting down leaves the watcher waiting for a shutdown which will never happen
+        if self.in_flight.shutdown.lock().unwrap_or_else(|e| e.into_inner()).is_some() {
+            if let Some(handle) = self.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Wait for `shutdown` on a thread of its own, then `report` the progress of the draining right away, every `interval` and
+/// a last time once the watcher is dropped
+pub(crate) fn watch_drain<S, R>(in_flight: &Arc<InFlight>, interval: Duration, shutdown: S, report: R) -> DrainWatcher
+    where S: 'static + Future + Send, R: 'static + Fn(&DrainReport) + Send {
+    let (stop, stopped) = channel::<()>();
+    let watched = in_flight.clone();
+
+    let handle = thread::Builder::new().name("saphir-drain".to_string()).spawn(move || {
+        // The server stops when the shutdown completes, whether it resolves or fails
+        let _ = shutdown.wait();
+        *watched.shutdown.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+
+        report(&watched.report());
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            report(&watched.report());
+        }
+        report(&watched.report());
+    });
+
+    DrainWatcher { in_flight: in_flight.clone(), stop: Some(stop), handle: handle.ok() }
+}
@@ -0,0 +1,143 @@
+use http::*;
+use router::routing_path;
+use utils::ToRegex;
+use regex::Regex;
+use arc_swap::ArcSwap;
+use controller::Controller;
+use controller::RouteInfo;
+use route_index::RouteIndex;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// An immutable version of the routes of a `DynamicRouter`
+#[derive(Default)]
+struct RoutesSnapshot {
+    routes: Vec<(Regex, Arc<dyn Controller>)>,
+    index: RouteIndex,
+}
+
+impl RoutesSnapshot {
+    fn new(routes: Vec<(Regex, Arc<dyn Controller>)>) -> Self {
+        let mut index = RouteIndex::new();
+
+        for (position, (route, _)) in routes.iter().enumerate() {
+            index.insert(position, route);
+        }
+
+        RoutesSnapshot {
+            routes,
+            index,
+        }
+    }
+}
+
+/// A controller dispatching requests towards controllers which can be added, removed or replaced while the server is running
+///
+/// Every mutation builds a new snapshot of the routes which atomically replaces the previous one: requests being dispatched
+/// keep using the snapshot they started with, and dispatching never waits for a mutation to complete. Mutations are
+/// serialized with each other, so concurrent changes are never lost.
+///
+/// A `DynamicRouter` is a cheap handle which can be cloned, a clone is typically kept by the application while another one
+/// is added to the `Router` of the server.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let features = DynamicRouter::new();
+///
+/// let mut router = Router::new();
+/// router.add("^/beta", features.clone());
+///
+/// let server = Server::builder().router(router).build();
+///
+/// // Later on, while the server is running
+/// let mut beta = BasicController::new(());
+/// beta.add(Method::GET, "^/beta/search$", |_, _, res| { res.status(StatusCode::OK); });
+/// features.replace("^/beta/search", beta);
+///
+/// features.remove("^/beta/search");
+/// ```
+#[derive(Clone, Default)]
+pub struct DynamicRouter {
+    snapshot: Arc<ArcSwap<RoutesSnapshot>>,
+    mutation: Arc<Mutex<()>>,
+}
+
+impl DynamicRouter {
+    /// Create a router without any route, it answers `404 Not Found` to every request
+    pub fn new() -> Self {
+        DynamicRouter::default()
+    }
+
+    fn mutate<F: FnOnce(&mut Vec<(Regex, Arc<dyn Controller>)>) -> bool>(&self, mutation: F) -> bool {
+        let _guard = self.mutation.lock().unwrap_or_else(|e| e.into_inner());
+        let mut routes = self.snapshot.load().routes.clone();
+
+        let changed = mutation(&mut routes);
+        if changed {
+            self.snapshot.store(Arc::new(RoutesSnapshot::new(routes)));
+        }
+
+        changed
+    }
+
+    /// Add a controller with its route after the existing ones
+    pub fn add<C: 'static + Controller, R: ToRegex>(&self, route: R, controller: C) {
+        let route = reg!(route);
+        let controller: Arc<dyn Controller> = Arc::new(controller);
+
+        self.mutate(move |routes| {
+            routes.push((route, controller));
+            true
+        });
+    }
+
+    /// Replace the controller registered with the exact same route, keeping its position, or add it after the existing ones
+    /// when no such route exists
+    pub fn replace<C: 'static + Controller, R: ToRegex>(&self, route: R, controller: C) {
+        let route = reg!(route);
+        let controller: Arc<dyn Controller> = Arc::new(controller);
+
+        self.mutate(move |routes| {
+            match routes.iter().position(|(r, _)| r.as_str() == route.as_str()) {
+                Some(position) => routes[position].1 = controller,
+                None => routes.push((route, controller)),
+            }
+            true
+        });
+    }
+
+    /// Remove every controller registered with the exact route `route`, returns whether any was removed
+    pub fn remove(&self, route: &str) -> bool {
+        self.mutate(|routes| {
+            let len = routes.len();
+            routes.retain(|(r, _)| r.as_str() != route);
+            routes.len() != len
+        })
+    }
+
+    /// Remove every route
+    pub fn clear(&self) {
+        self.mutate(|routes| {
+            routes.clear();
+            true
+        });
+    }
+}
+
+impl Controller for DynamicRouter {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let snapshot = self.snapshot.load_full();
+
+        if let Some(position) = snapshot.index.find(&routing_path(req)) {
+            snapshot.routes[position].1.handle(req, res);
+        } else {
+            res.status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.snapshot.load().routes.iter().flat_map(|(_, controller)| controller.routes()).collect()
+    }
+}
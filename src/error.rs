@@ -13,6 +13,16 @@ pub enum ServerError {
     InvalidUri(::http_types::uri::InvalidUri),
     /// Unsupported URI Scheme
     UnsupportedUriScheme,
+    /// An IO error, e.g. while binding the listener
+    IoError(::std::io::Error),
+    /// A startup hook failed, or the lifecycle hooks can't be ordered, the server didn't start
+    StartupFailed(String),
+}
+
+impl From<::std::io::Error> for ServerError {
+    fn from(e: ::std::io::Error) -> Self {
+        ServerError::IoError(e)
+    }
 }
 
 impl From<::std::net::AddrParseError> for ServerError {
@@ -48,6 +58,8 @@ impl ::std::error::Error for ServerError {
             ParseError(ref e) => e.description(),
             InvalidUri(ref e) => e.description(),
             UnsupportedUriScheme => "Unsupported URI scheme",
+            IoError(_) => "IO error",
+            StartupFailed(_) => "Startup failed",
         }
     }
 }
@@ -61,6 +73,8 @@ impl ::std::fmt::Display for ServerError {
             ParseError(ref e) => e.fmt(f),
             InvalidUri(ref e) => e.fmt(f),
             UnsupportedUriScheme => write!(f, "Unsupported URI scheme"),
+            IoError(ref e) => e.fmt(f),
+            StartupFailed(ref e) => write!(f, "startup failed: {}", e),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+use http::*;
+use form::html_escape;
+use logging::log_event;
+use logging::HANDLER_LOG_TARGET;
+use profile::Profile;
+use log::Level;
+use std::error::Error;
+use std::fmt::Write;
+
+/// Headers whose value is not shown on developer error pages
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization", "set-cookie"];
+
+/// An error which made a request fail, described on the page answering it under the development `Profile`
+///
+/// The server attaches one to the responses of handlers which panicked and of templates which couldn't be rendered,
+/// handlers attach their own ones with `SyncResponse::internal_error`.
+#[derive(Debug, Clone)]
+pub struct DeveloperError {
+    chain: Vec<String>,
+    backtrace: Option<String>,
+    route: Option<String>,
+}
+
+impl DeveloperError {
+    /// Describe an error with its chain of messages, from the outermost error to its root cause
+    pub fn new(chain: Vec<String>) -> Self {
+        DeveloperError {
+            chain,
+            backtrace: None,
+            route: None,
+        }
+    }
+
+    /// Describe `error` and the errors which caused it
+    pub fn from_error(error: &dyn Error) -> Self {
+        let mut chain = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(cause) = source {
+            chain.push(cause.to_string());
+            source = cause.source();
+        }
+
+        DeveloperError::new(chain)
+    }
+
+    /// Set the backtrace of the error
+    pub fn with_backtrace<B: Into<String>>(mut self, backtrace: B) -> Self {
+        self.backtrace = Some(backtrace.into());
+        self
+    }
+
+    pub(crate) fn with_route(mut self, route: Option<String>) -> Self {
+        self.route = route;
+        self
+    }
+
+    /// Returns the messages of the error and of its causes
+    pub fn chain(&self) -> &[String] {
+        &self.chain
+    }
+
+    /// Returns the backtrace of the error, when it was captured
+    pub fn backtrace(&self) -> Option<&str> {
+        self.backtrace.as_deref()
+    }
+}
+
+impl SyncResponse {
+    /// Answer with `500 Internal Server Error` because of `error`, which is logged. Under the development `Profile`,
+    /// browsers are shown a page describing the error and the causes it was built from.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     match std::fs::read("data.json") {
+    ///         Ok(data) => { res.status(StatusCode::OK).body(data); }
+    ///         Err(e) => { res.internal_error(&e); }
+    ///     }
+    /// }
+    /// ```
+    pub fn internal_error(&mut self, error: &dyn Error) -> &mut SyncResponse {
+        let description = DeveloperError::from_error(error);
+        let message = description.chain.join(": ");
+        log_event(Level::Error, HANDLER_LOG_TARGET, "handler error", format_args!("The handler failed: {}", message),
+                  &[("error", &message)]);
+
+        self.status(StatusCode::INTERNAL_SERVER_ERROR).extension(description)
+    }
+}
+
+/// Replace the server error responses of the development profile with a page describing their error, the route which
+/// matched and the request, when the client prefers HTML. Responses with a body are left alone unless a `DeveloperError`
+/// describes them.
+pub(crate) fn render_developer_page(req: &SyncRequest, res: &mut SyncResponse) {
+    if res.profile() != Some(Profile::Development) || !res.get_status().is_server_error() || *req.method() == Method::HEAD
+        || res.is_streamed() {
+        return;
+    }
+
+    let error = res.get_extensions().get::<DeveloperError>().cloned();
+    if error.is_none() && !res.get_body().is_empty() {
+        return;
+    }
+
+    if req.accepts(&["application/problem+json", "application/json", "text/html"]) != Some("text/html") {
+        return;
+    }
+
+    let status = res.get_status();
+    let error = error.unwrap_or_else(|| DeveloperError::new(vec![format!("The request was answered with {} without a body", status)]));
+    let route = error.route.clone().or_else(|| res.route_label().map(|label| label.to_string()));
+    let page = developer_page(req, status, &error, route.as_deref());
+
+    res.headers_map_mut().remove(header::CONTENT_LENGTH);
+    res.headers_map_mut().insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+    res.headers_map_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/html; charset=utf-8"));
+    res.body(page);
+}
+
+fn developer_page(req: &SyncRequest, status: StatusCode, error: &DeveloperError, route: Option<&str>) -> String {
+    let title = format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or(""));
+    let mut page = String::new();
+
+    let _ = write!(page, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n", html_escape(&title));
+    page.push_str("<style>body{font-family:sans-serif;margin:2em;color:#222}h1{color:#b00}th{text-align:left;padding-right:1em;\
+                   vertical-align:top}pre{background:#f4f4f4;padding:1em;overflow:auto}</style>\n</head>\n<body>\n");
+    let _ = writeln!(page, "<h1>{}</h1>", html_escape(&title));
+
+    page.push_str("<h2>Error</h2>\n<ol>\n");
+    for message in &error.chain {
+        let _ = writeln!(page, "<li>{}</li>", html_escape(message));
+    }
+    page.push_str("</ol>\n");
+
+    let _ = writeln!(page, "<h2>Route</h2>\n<p>{}</p>", html_escape(route.unwrap_or("No route matched the request")));
+
+    page.push_str("<h2>Request</h2>\n<table>\n");
+    let peer = req.peer_addr().map(|addr| addr.to_string()).unwrap_or_else(|| "unknown".to_string());
+    for (name, value) in &[("Method", req.method().to_string()), ("Uri", req.uri().to_string()),
+                           ("Version", format!("{:?}", req.version())), ("Peer", peer)] {
+        let _ = writeln!(page, "<tr><th>{}</th><td>{}</td></tr>", name, html_escape(value));
+    }
+    for (name, value) in req.headers_map().iter() {
+        let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+            "[redacted]".to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).to_string()
+        };
+        let _ = writeln!(page, "<tr><th>{}</th><td>{}</td></tr>", html_escape(name.as_str()), html_escape(&value));
+    }
+    page.push_str("</table>\n");
+
+    if let Some(ref backtrace) = error.backtrace {
+        let _ = writeln!(page, "<h2>Backtrace</h2>\n<pre>{}</pre>", html_escape(backtrace));
+    }
+
+    page.push_str("</body>\n</html>\n");
+    page
+}
@@ -0,0 +1,246 @@
+use http::*;
+use http::header::EntityTag;
+use http::header::HttpDate;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use std::time::SystemTime;
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+fn body_tag(body: &[u8]) -> String {
+    format!("{:x}-{:016x}", body.len(), fnv1a(body))
+}
+
+/// Compute a strong ETag from the bytes of a body
+///
+/// The tag is derived from the length and a 64 bits FNV-1a hash of the body, it is stable across restarts and instances.
+pub fn strong_etag(body: &[u8]) -> EntityTag {
+    EntityTag::strong(body_tag(body))
+}
+
+/// Compute a weak ETag from the bytes of a body, see `strong_etag`
+pub fn weak_etag(body: &[u8]) -> EntityTag {
+    EntityTag::weak(body_tag(body))
+}
+
+fn parse_http_date(value: &header::HeaderValue) -> Option<SystemTime> {
+    value.to_str().ok()
+        .and_then(|v| v.parse::<HttpDate>().ok())
+        .map(SystemTime::from)
+}
+
+/// Evaluate the `If-None-Match` and `If-Modified-Since` preconditions of a request against the current representation of
+/// a resource. Returns `true` when the client's copy is still valid and a `304 Not Modified` can be sent instead.
+///
+/// As mandated by RFC 7232, `If-Modified-Since` is ignored when the request contains `If-None-Match`, and `If-None-Match`
+/// is evaluated using the weak comparison function.
+pub fn is_not_modified(req: &SyncRequest, etag: Option<&EntityTag>, last_modified: Option<SystemTime>) -> bool {
+    let headers = req.headers_map();
+
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        let etag = match etag {
+            Some(etag) => etag,
+            None => return false,
+        };
+
+        return headers.get_all(header::IF_NONE_MATCH).iter().any(|value| {
+            value.to_str().unwrap_or("").split(',').any(|candidate| {
+                let candidate = candidate.trim();
+                candidate == "*" || candidate.parse::<EntityTag>().map(|c| c.weak_eq(etag)).unwrap_or(false)
+            })
+        });
+    }
+
+    if let (Some(since), Some(last_modified)) = (headers.get(header::IF_MODIFIED_SINCE).and_then(parse_http_date), last_modified) {
+        // Http dates have a one second precision
+        return match last_modified.duration_since(since) {
+            Ok(d) => d.as_secs() == 0,
+            Err(_) => true,
+        };
+    }
+
+    false
+}
+
+/// Evaluate the `If-Match` and `If-Unmodified-Since` preconditions of a request against the current representation of a
+/// resource, `exists` telling whether there is one. Returns `false` when the client's copy is outdated and the request must
+/// be answered with `412 Precondition Failed`, see `SyncRequest::check_preconditions`.
+///
+/// As mandated by RFC 7232, `If-Unmodified-Since` is ignored when the request contains `If-Match`, and `If-Match` is
+/// evaluated using the strong comparison function.
+pub fn preconditions_hold(req: &SyncRequest, etag: Option<&EntityTag>, last_modified: Option<SystemTime>, exists: bool) -> bool {
+    let headers = req.headers_map();
+
+    if headers.contains_key(header::IF_MATCH) {
+        return headers.get_all(header::IF_MATCH).iter().any(|value| {
+            value.to_str().unwrap_or("").split(',').any(|candidate| {
+                let candidate = candidate.trim();
+                (candidate == "*" && exists)
+                    || etag.is_some_and(|etag| candidate.parse::<EntityTag>().map(|c| c.strong_eq(etag)).unwrap_or(false))
+            })
+        });
+    }
+
+    match headers.get(header::IF_UNMODIFIED_SINCE).and_then(parse_http_date) {
+        // Http dates have a one second precision
+        Some(since) => last_modified.is_some_and(|last_modified| last_modified.duration_since(since).map_or(true, |d| d.as_secs() == 0)),
+        None => true,
+    }
+}
+
+/// A resource able to report the version of its current representation, for the handlers changing it to check the
+/// preconditions of their requests, see `SyncRequest::check_preconditions`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use saphir::header::EntityTag;
+/// struct Article {
+///     revision: u64,
+/// }
+///
+/// impl Versioned for Article {
+///     fn etag(&self) -> Option<EntityTag> {
+///         Some(EntityTag::strong(self.revision.to_string()))
+///     }
+/// }
+/// ```
+pub trait Versioned {
+    /// Returns the ETag of the current representation of the resource
+    fn etag(&self) -> Option<EntityTag>;
+
+    /// Returns when the resource was last changed, `None` by default
+    fn last_modified(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+impl Versioned for EntityTag {
+    fn etag(&self) -> Option<EntityTag> {
+        Some(self.clone())
+    }
+}
+
+impl SyncRequest {
+    /// Returns `true` when the request may change `resource`, `None` when it doesn't exist, according to its `If-Match` and
+    /// `If-Unmodified-Since` headers. Otherwise answers with `412 Precondition Failed`, along with the current ETag of the
+    /// resource, and returns `false`. Requests without preconditions may always change the resource.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use saphir::header::EntityTag;
+    /// # fn load_revision() -> Option<EntityTag> { None }
+    /// fn update(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let revision = load_revision();
+    ///     if !req.check_preconditions(revision.as_ref(), res) {
+    ///         return;
+    ///     }
+    ///
+    ///     res.status(StatusCode::NO_CONTENT);
+    /// }
+    /// ```
+    pub fn check_preconditions<V: Versioned>(&self, resource: Option<&V>, res: &mut SyncResponse) -> bool {
+        let etag = resource.and_then(|resource| resource.etag());
+        let last_modified = resource.and_then(|resource| resource.last_modified());
+        if preconditions_hold(self, etag.as_ref(), last_modified, resource.is_some()) {
+            return true;
+        }
+
+        res.status(StatusCode::PRECONDITION_FAILED);
+        if let Some(etag) = etag {
+            res.header(header::ETAG, etag.to_string());
+        }
+        false
+    }
+}
+
+/// A middleware answering conditional `GET` and `HEAD` requests with `304 Not Modified`
+///
+/// Once a successful response is computed, its `ETag` and `Last-Modified` headers are evaluated against the request's
+/// `If-None-Match` and `If-Modified-Since` headers, the body is dropped when the client's copy is still valid. By default,
+/// a strong ETag is generated from the body of responses which don't already have one, unless the body is streamed, see
+/// `SyncResponse::stream`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(ConditionalGetMiddleware::new(), vec!("/"), None);
+/// ```
+pub struct ConditionalGetMiddleware {
+    generate: bool,
+    weak: bool,
+}
+
+impl ConditionalGetMiddleware {
+    /// Create a new middleware generating strong ETags
+    pub fn new() -> Self {
+        ConditionalGetMiddleware {
+            generate: true,
+            weak: false,
+        }
+    }
+
+    /// Generate weak ETags instead of strong ones
+    pub fn weak(mut self) -> Self {
+        self.weak = true;
+        self
+    }
+
+    /// Don't generate ETags, only evaluate the ones set by handlers
+    pub fn without_generation(mut self) -> Self {
+        self.generate = false;
+        self
+    }
+}
+
+impl Default for ConditionalGetMiddleware {
+    fn default() -> Self {
+        ConditionalGetMiddleware::new()
+    }
+}
+
+impl Middleware for ConditionalGetMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if (*req.method() != Method::GET && *req.method() != Method::HEAD) || res.get_status() != StatusCode::OK {
+            return;
+        }
+
+        let mut etag = res.headers_map().get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<EntityTag>().ok());
+
+        // Reading a streamed body would wait for the whole of it
+        if etag.is_none() && self.generate && !res.is_streamed() {
+            let body = res.get_body();
+            let generated = if self.weak { weak_etag(&body) } else { strong_etag(&body) };
+            res.header(header::ETAG, generated.to_string());
+            etag = Some(generated);
+        }
+
+        let last_modified = res.headers_map().get(header::LAST_MODIFIED).and_then(parse_http_date);
+
+        if is_not_modified(req, etag.as_ref(), last_modified) {
+            res.status(StatusCode::NOT_MODIFIED);
+            res.headers_map_mut().remove(header::CONTENT_LENGTH);
+            res.body(Vec::<u8>::new());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use futures_cpupool::CpuPool;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// How the requests waiting for a handler thread are shared amongst their senders, see `ServerBuilder::fairness`
+///
+/// By default requests are processed in the order they were received, so a client opening many connections or
+/// multiplexing many requests takes the handler threads from the others. With a fairness, every sender has a queue of its
+/// own, and the handler threads take the next request of each sender in turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Fairness {
+    /// Every connection takes its turn
+    PerConnection,
+    /// Every client, by the address of its peer, takes its turn whatever the number of connections it opened
+    PerClient,
+}
+
+impl fmt::Display for Fairness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Fairness::PerConnection => "per connection",
+            Fairness::PerClient => "per client",
+        })
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Sender {
+    Connection(u64),
+    Client(IpAddr),
+}
+
+#[derive(Default)]
+struct Queues {
+    waiting: HashMap<Sender, VecDeque<Job>>,
+    turns: VecDeque<Sender>,
+    running: usize,
+}
+
+/// Runs the requests on the handler threads, taking the next request of each sender in turn
+pub(crate) struct FairScheduler {
+    fairness: Fairness,
+    pool: CpuPool,
+    threads: usize,
+    queues: Mutex<Queues>,
+}
+
+impl FairScheduler {
+    pub(crate) fn new(fairness: Fairness, pool: CpuPool, threads: usize) -> Arc<Self> {
+        Arc::new(FairScheduler {
+            fairness,
+            pool,
+            threads: threads.max(1),
+            queues: Mutex::new(Queues::default()),
+        })
+    }
+
+    /// Queue `job`, the request received on the connection `connection` from `peer`
+    pub(crate) fn submit<F: 'static + FnOnce() + Send>(self: &Arc<Self>, connection: u64, peer: IpAddr, job: F) {
+        let sender = match self.fairness {
+            Fairness::PerConnection => Sender::Connection(connection),
+            Fairness::PerClient => Sender::Client(peer),
+        };
+
+        {
+            let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+            let queue = queues.waiting.entry(sender).or_default();
+            queue.push_back(Box::new(job));
+            if queue.len() == 1 {
+                queues.turns.push_back(sender);
+            }
+        }
+
+        self.dispatch();
+    }
+
+    /// Run the next jobs of the senders in turn while handler threads are idle
+    fn dispatch(self: &Arc<Self>) {
+        let mut jobs = Vec::new();
+        {
+            let mut queues = self.queues.lock().unwrap_or_else(|e| e.into_inner());
+            while queues.running < self.threads {
+                let sender = match queues.turns.pop_front() {
+                    Some(sender) => sender,
+                    None => break,
+                };
+
+                let (job, remaining) = match queues.waiting.get_mut(&sender) {
+                    Some(queue) => (queue.pop_front(), queue.len()),
+                    None => (None, 0),
+                };
+                if remaining == 0 {
+                    queues.waiting.remove(&sender);
+                } else {
+                    queues.turns.push_back(sender);
+                }
+
+                if let Some(job) = job {
+                    queues.running += 1;
+                    jobs.push(job);
+                }
+            }
+        }
+
+        for job in jobs {
+            let running = Running(self.clone());
+            self.pool.spawn_fn(move || {
+                let _running = running;
+                job();
+                Ok::<(), ()>(())
+            }).forget();
+        }
+    }
+}
+
+/// A job taking a handler thread, which is given to the next one once dropped, even when the job panicked
+struct Running(Arc<FairScheduler>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.queues.lock().unwrap_or_else(|e| e.into_inner()).running -= 1;
+        self.0.dispatch();
+    }
+}
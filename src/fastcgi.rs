@@ -0,0 +1,313 @@
+use http::*;
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+
+const VERSION: u8 = 1;
+
+const BEGIN_REQUEST: u8 = 1;
+const ABORT_REQUEST: u8 = 2;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const DATA: u8 = 8;
+const GET_VALUES: u8 = 9;
+const GET_VALUES_RESULT: u8 = 10;
+const UNKNOWN_TYPE: u8 = 11;
+
+const RESPONDER: u16 = 1;
+const KEEP_CONN: u8 = 1;
+
+const REQUEST_COMPLETE: u8 = 0;
+const CANT_MPX_CONN: u8 = 1;
+const UNKNOWN_ROLE: u8 = 3;
+
+const MAX_CONTENT_LENGTH: usize = 65535;
+
+struct Record {
+    kind: u8,
+    request_id: u16,
+    content: Vec<u8>,
+}
+
+fn read_record<S: Read>(stream: &mut S) -> io::Result<Option<Record>> {
+    let mut header = [0u8; 8];
+
+    match stream.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    if header[0] != VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported FastCGI version"));
+    }
+
+    let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0u8; content_length + header[6] as usize];
+    stream.read_exact(&mut content)?;
+    content.truncate(content_length);
+
+    Ok(Some(Record {
+        kind: header[1],
+        request_id: u16::from_be_bytes([header[2], header[3]]),
+        content,
+    }))
+}
+
+fn write_record<S: Write>(stream: &mut S, kind: u8, request_id: u16, content: &[u8]) -> io::Result<()> {
+    let id = request_id.to_be_bytes();
+    let length = (content.len() as u16).to_be_bytes();
+    let padding = (8 - content.len() % 8) % 8;
+
+    stream.write_all(&[VERSION, kind, id[0], id[1], length[0], length[1], padding as u8, 0])?;
+    stream.write_all(content)?;
+    stream.write_all(&[0u8; 8][..padding])
+}
+
+fn write_end_request<S: Write>(stream: &mut S, request_id: u16, protocol_status: u8) -> io::Result<()> {
+    write_record(stream, END_REQUEST, request_id, &[0, 0, 0, 0, protocol_status, 0, 0, 0])
+}
+
+fn read_length(bytes: &[u8], index: &mut usize) -> Option<usize> {
+    let first = *bytes.get(*index)?;
+
+    if first >> 7 == 0 {
+        *index += 1;
+        Some(first as usize)
+    } else {
+        let length = bytes.get(*index..*index + 4)?;
+        *index += 4;
+        Some((u32::from_be_bytes([length[0], length[1], length[2], length[3]]) & 0x7fff_ffff) as usize)
+    }
+}
+
+fn parse_pairs(bytes: &[u8]) -> HashMap<String, String> {
+    let mut pairs = HashMap::new();
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let (name_length, value_length) = match (read_length(bytes, &mut index), read_length(bytes, &mut index)) {
+            (Some(name), Some(value)) => (name, value),
+            _ => break,
+        };
+
+        let (name, value) = match (bytes.get(index..index + name_length), bytes.get(index + name_length..index + name_length + value_length)) {
+            (Some(name), Some(value)) => (name, value),
+            _ => break,
+        };
+
+        pairs.insert(String::from_utf8_lossy(name).into_owned(), String::from_utf8_lossy(value).into_owned());
+        index += name_length + value_length;
+    }
+
+    pairs
+}
+
+fn write_length(out: &mut Vec<u8>, length: usize) {
+    if length < 128 {
+        out.push(length as u8);
+    } else {
+        out.extend_from_slice(&(length as u32 | 0x8000_0000).to_be_bytes());
+    }
+}
+
+/// Build the request described by the CGI variables of a FastCGI request
+fn build_request(params: &HashMap<String, String>, body: Vec<u8>) -> Result<SyncRequest, ::http_types::Error> {
+    let uri = match params.get("REQUEST_URI") {
+        Some(uri) => uri.clone(),
+        None => {
+            let path = format!("{}{}", params.get("SCRIPT_NAME").map_or("", |s| s.as_str()), params.get("PATH_INFO").map_or("", |s| s.as_str()));
+            match params.get("QUERY_STRING") {
+                Some(query) if !query.is_empty() => format!("{}?{}", path, query),
+                _ => path,
+            }
+        }
+    };
+
+    let mut builder = Request::builder();
+    builder.method(params.get("REQUEST_METHOD").map_or("GET", |m| m.as_str())).uri(if uri.is_empty() { "/" } else { uri.as_str() });
+
+    builder.version(match params.get("SERVER_PROTOCOL").map(|p| p.as_str()) {
+        Some("HTTP/1.0") => Version::HTTP_10,
+        Some("HTTP/2") | Some("HTTP/2.0") => Version::HTTP_2,
+        _ => Version::HTTP_11,
+    });
+
+    for (name, value) in params {
+        let header_name = match name.as_str() {
+            "CONTENT_TYPE" => "content-type".to_string(),
+            "CONTENT_LENGTH" => "content-length".to_string(),
+            name if name.starts_with("HTTP_") => name["HTTP_".len()..].replace('_', "-").to_ascii_lowercase(),
+            _ => continue,
+        };
+
+        if !value.is_empty() {
+            builder.header(header_name.as_str(), value.as_str());
+        }
+    }
+
+    let (parts, _) = builder.body(())?.into_parts();
+    let mut request = SyncRequest::new(parts, body);
+
+    let ip = params.get("REMOTE_ADDR").and_then(|ip| ip.parse::<IpAddr>().ok());
+    let port = params.get("REMOTE_PORT").and_then(|port| port.parse::<u16>().ok()).unwrap_or(0);
+    if let Some(ip) = ip {
+        request.extensions_mut().insert(PeerAddr(SocketAddr::new(ip, port)));
+    }
+
+    Ok(request)
+}
+
+/// Encode `response` the way a CGI script writes its response, a `Status` header followed by the other headers and the body
+fn encode_response(response: &SyncResponse) -> Vec<u8> {
+    let status = response.get_status();
+    let body = response.get_body();
+    let mut out = Vec::with_capacity(body.len() + 256);
+
+    out.extend_from_slice(format!("Status: {} {}\r\n", status.as_u16(), status.canonical_reason().unwrap_or("")).as_bytes());
+
+    for (name, value) in response.headers_map() {
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+
+    if !response.headers_map().contains_key(header::CONTENT_LENGTH) {
+        out.extend_from_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+    }
+
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(&body);
+    out
+}
+
+struct PendingRequest {
+    id: u16,
+    keep_conn: bool,
+    params: Vec<u8>,
+    params_done: bool,
+    body: Vec<u8>,
+}
+
+/// Serve the FastCGI requests of a connection with `process`, until the web server closes it or doesn't ask to keep it open
+///
+/// Requests of a connection are processed one after the other, requests multiplexed on it are refused.
+pub fn handle_connection<S, P>(stream: &mut S, process: P) -> io::Result<()>
+    where S: Read + Write,
+          P: Fn(&mut SyncRequest) -> SyncResponse {
+    let mut pending: Option<PendingRequest> = None;
+
+    while let Some(record) = read_record(stream)? {
+        match record.kind {
+            BEGIN_REQUEST => {
+                if record.content.len() < 3 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid FastCGI begin request"));
+                }
+
+                let role = u16::from_be_bytes([record.content[0], record.content[1]]);
+                let keep_conn = record.content[2] & KEEP_CONN != 0;
+
+                if pending.is_some() {
+                    write_end_request(stream, record.request_id, CANT_MPX_CONN)?;
+                } else if role != RESPONDER {
+                    write_end_request(stream, record.request_id, UNKNOWN_ROLE)?;
+                    if !keep_conn {
+                        return Ok(());
+                    }
+                } else {
+                    pending = Some(PendingRequest {
+                        id: record.request_id,
+                        keep_conn,
+                        params: Vec::new(),
+                        params_done: false,
+                        body: Vec::new(),
+                    });
+                }
+            }
+            ABORT_REQUEST => {
+                if pending.as_ref().map(|p| p.id) == Some(record.request_id) {
+                    let request = pending.take().expect("pending request");
+                    write_end_request(stream, request.id, REQUEST_COMPLETE)?;
+                    if !request.keep_conn {
+                        return Ok(());
+                    }
+                }
+            }
+            PARAMS => {
+                if let Some(ref mut request) = pending {
+                    if request.id == record.request_id {
+                        if record.content.is_empty() {
+                            request.params_done = true;
+                        } else {
+                            request.params.extend_from_slice(&record.content);
+                        }
+                    }
+                }
+            }
+            STDIN => {
+                let complete = match pending {
+                    Some(ref mut request) if request.id == record.request_id => {
+                        request.body.extend_from_slice(&record.content);
+                        record.content.is_empty() && request.params_done
+                    }
+                    _ => false,
+                };
+
+                if complete {
+                    let request = pending.take().expect("pending request");
+                    let params = parse_pairs(&request.params);
+
+                    let output = match build_request(&params, request.body) {
+                        Ok(mut sync_request) => encode_response(&process(&mut sync_request)),
+                        Err(e) => {
+                            warn!("Unable to build the request of a FastCGI request: {}", e);
+                            b"Status: 400 Bad Request\r\ncontent-length: 0\r\n\r\n".to_vec()
+                        }
+                    };
+
+                    for chunk in output.chunks(MAX_CONTENT_LENGTH) {
+                        write_record(stream, STDOUT, request.id, chunk)?;
+                    }
+                    write_record(stream, STDOUT, request.id, &[])?;
+                    write_end_request(stream, request.id, REQUEST_COMPLETE)?;
+                    stream.flush()?;
+
+                    if !request.keep_conn {
+                        return Ok(());
+                    }
+                }
+            }
+            DATA => {}
+            GET_VALUES => {
+                let mut values = Vec::new();
+                for name in parse_pairs(&record.content).keys() {
+                    let value = match name.as_str() {
+                        "FCGI_MAX_CONNS" | "FCGI_MAX_REQS" => "1024",
+                        "FCGI_MPXS_CONNS" => "0",
+                        _ => continue,
+                    };
+
+                    write_length(&mut values, name.len());
+                    write_length(&mut values, value.len());
+                    values.extend_from_slice(name.as_bytes());
+                    values.extend_from_slice(value.as_bytes());
+                }
+
+                write_record(stream, GET_VALUES_RESULT, 0, &values)?;
+                stream.flush()?;
+            }
+            kind => {
+                write_record(stream, UNKNOWN_TYPE, 0, &[kind, 0, 0, 0, 0, 0, 0, 0])?;
+                stream.flush()?;
+            }
+        }
+    }
+
+    Ok(())
+}
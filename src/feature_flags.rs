@@ -0,0 +1,144 @@
+use http::*;
+use arc_swap::ArcSwap;
+use controller::RequestGuard;
+use utils::RequestContinuation;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// The prefix of the environment variables read by `StaticFeatureFlags::from_env`
+pub const FEATURE_ENV_PREFIX: &str = "SAPHIR_FEATURE_";
+
+/// A trait representing where the feature flags of the server come from, like a configuration file or a flag service,
+/// see `ServerBuilder::feature_flags`
+///
+/// Flags are evaluated for a request, so that providers can enable a feature for some users only.
+pub trait FeatureFlagProvider: Send + Sync {
+    /// Returns whether the feature `flag` is enabled for `req`
+    fn is_enabled(&self, flag: &str, req: &SyncRequest) -> bool;
+}
+
+/// The provider registered on the server, inserted in the extensions of every request
+#[derive(Clone)]
+pub(crate) struct SharedFeatureFlags(pub(crate) Arc<dyn FeatureFlagProvider>);
+
+/// The form flag names are compared in, `new-checkout` and `NEW_CHECKOUT` being the same flag
+fn normalize(flag: &str) -> String {
+    flag.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+/// A `FeatureFlagProvider` enabling the same flags for every request, set by the application or read from the environment
+///
+/// A `StaticFeatureFlags` is a cheap handle which can be cloned: flags can be toggled through a clone while the server is
+/// running. Flags which were never set are disabled.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// // SAPHIR_FEATURE_NEW_CHECKOUT=true enables the `new_checkout` flag
+/// let flags = StaticFeatureFlags::from_env().enable("dark_mode");
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::GET, "^/checkout$", |_, req, res| {
+///     if req.feature("new_checkout") {
+///         res.status(StatusCode::OK).body("new checkout");
+///     } else {
+///         res.status(StatusCode::OK).body("checkout");
+///     }
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/", controller);
+/// let server = Server::builder().router(router).feature_flags(flags.clone()).build();
+///
+/// // Later on, while the server is running
+/// flags.set("dark_mode", false);
+/// ```
+#[derive(Clone, Default)]
+pub struct StaticFeatureFlags {
+    flags: Arc<ArcSwap<HashMap<String, bool>>>,
+    mutation: Arc<Mutex<()>>,
+}
+
+impl StaticFeatureFlags {
+    /// Create a provider without any flag enabled
+    pub fn new() -> Self {
+        StaticFeatureFlags::default()
+    }
+
+    /// Create a provider with the flags set by the `SAPHIR_FEATURE_<NAME>` environment variables, enabled by `1`, `true`,
+    /// `on` or `yes` and disabled by any other value
+    pub fn from_env() -> Self {
+        let flags = StaticFeatureFlags::new();
+
+        for (name, value) in env::vars() {
+            if name.starts_with(FEATURE_ENV_PREFIX) && name.len() > FEATURE_ENV_PREFIX.len() {
+                let enabled = ["1", "true", "on", "yes"].iter().any(|truthy| value.trim().eq_ignore_ascii_case(truthy));
+                flags.set(&name[FEATURE_ENV_PREFIX.len()..], enabled);
+            }
+        }
+
+        flags
+    }
+
+    /// Enable `flag`
+    pub fn enable(self, flag: &str) -> Self {
+        self.set(flag, true);
+        self
+    }
+
+    /// Enable or disable `flag`
+    pub fn set(&self, flag: &str, enabled: bool) {
+        let _guard = self.mutation.lock().unwrap_or_else(|e| e.into_inner());
+        let mut flags = HashMap::clone(&self.flags.load());
+        flags.insert(normalize(flag), enabled);
+        self.flags.store(Arc::new(flags));
+    }
+
+    /// Returns whether `flag` is enabled
+    pub fn get(&self, flag: &str) -> bool {
+        self.flags.load().get(&normalize(flag)).cloned().unwrap_or(false)
+    }
+}
+
+impl FeatureFlagProvider for StaticFeatureFlags {
+    fn is_enabled(&self, flag: &str, _req: &SyncRequest) -> bool {
+        self.get(flag)
+    }
+}
+
+impl SyncRequest {
+    /// Returns whether the feature `flag` is enabled for this request by the provider registered with
+    /// `ServerBuilder::feature_flags`, always false without one
+    pub fn feature(&self, flag: &str) -> bool {
+        self.extensions().get::<SharedFeatureFlags>().is_some_and(|flags| flags.0.is_enabled(flag, self))
+    }
+}
+
+/// A guard answering `404 Not Found` while a feature flag is disabled for the request, hiding the routes of unreleased
+/// features, see `SyncRequest::feature`
+pub struct FeatureFlagGuard {
+    flag: String,
+}
+
+impl FeatureFlagGuard {
+    /// Only let the requests `flag` is enabled for through
+    pub fn new<S: Into<String>>(flag: S) -> Self {
+        FeatureFlagGuard {
+            flag: flag.into(),
+        }
+    }
+}
+
+impl RequestGuard for FeatureFlagGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if req.feature(&self.flag) {
+            RequestContinuation::Next
+        } else {
+            res.status(StatusCode::NOT_FOUND);
+            RequestContinuation::None
+        }
+    }
+}
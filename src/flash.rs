@@ -0,0 +1,171 @@
+use http::*;
+use middleware::Middleware;
+use query::percent_decode;
+use query::percent_encode;
+use utils::RequestContinuation;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// The level of a `FlashMessage`, usually rendered as its style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    /// A neutral notice
+    Info,
+    /// An action completed, like a saved form
+    Success,
+    /// Something the user should look at
+    Warning,
+    /// An action failed
+    Error,
+}
+
+/// A message shown once, on the request following the one which set it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlashMessage {
+    /// The level of the message
+    pub level: FlashLevel,
+    /// The text of the message
+    pub message: String,
+}
+
+/// The messages received with a request, cleared once read
+struct IncomingFlashes {
+    messages: Vec<FlashMessage>,
+    read: AtomicBool,
+}
+
+/// The messages set while processing a request, for the next one
+#[derive(Default)]
+struct OutgoingFlashes(Vec<FlashMessage>);
+
+impl SyncRequest {
+    /// Returns the flash messages set by the previous requests with `SyncResponse::flash`, which are cleared by the
+    /// `FlashMiddleware` once this request is answered. Returns nothing when the middleware isn't applied to the request.
+    pub fn flash(&self) -> &[FlashMessage] {
+        match self.extensions().get::<IncomingFlashes>() {
+            Some(flashes) => {
+                flashes.read.store(true, Ordering::SeqCst);
+                &flashes.messages
+            }
+            None => &[],
+        }
+    }
+}
+
+impl SyncResponse {
+    /// Set a message to show on the next request, typically the one following a redirect once a form is submitted. The
+    /// `FlashMiddleware` must be applied to both requests.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn save_profile(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     // Save the submitted profile
+    ///     res.flash(FlashLevel::Success, "Your profile was saved").see_other("/profile");
+    /// }
+    ///
+    /// fn show_profile(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let notices: Vec<&str> = req.flash().iter().map(|flash| flash.message.as_str()).collect();
+    ///     res.status(StatusCode::OK).body(notices.join("\n"));
+    /// }
+    /// ```
+    pub fn flash(&mut self, level: FlashLevel, message: &str) -> &mut SyncResponse {
+        let mut flashes = self.get_extensions_mut().remove::<OutgoingFlashes>().unwrap_or_default();
+        flashes.0.push(FlashMessage {
+            level,
+            message: message.to_string(),
+        });
+        self.extension(flashes)
+    }
+}
+
+/// A middleware carrying the flash messages set with `SyncResponse::flash` to the next request, in a cookie
+///
+/// Messages stay in the cookie until a request reads them with `SyncRequest::flash`, so that requests not showing them,
+/// like the ones of assets, don't make them vanish. The cookie isn't signed: messages are meant to be shown, never
+/// trusted, and must be escaped like any other text. Browsers limit cookies to about 4 KB, flash messages should be short.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(FlashMiddleware::new(), vec!("^/"), None);
+/// ```
+pub struct FlashMiddleware {
+    cookie_name: String,
+    max_age: Duration,
+}
+
+impl FlashMiddleware {
+    /// Create a middleware keeping unread messages for 5 minutes in the `saphir_flash` cookie
+    pub fn new() -> Self {
+        FlashMiddleware {
+            cookie_name: "saphir_flash".to_string(),
+            max_age: Duration::from_secs(5 * 60),
+        }
+    }
+
+    /// Set the name of the cookie holding the messages
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Set for how long unread messages are kept
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    fn cookie(&self, value: &str, max_age: u64) -> String {
+        format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax", self.cookie_name, value, max_age)
+    }
+}
+
+impl Default for FlashMiddleware {
+    fn default() -> Self {
+        FlashMiddleware::new()
+    }
+}
+
+impl Middleware for FlashMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        let messages = req.cookie(&self.cookie_name)
+            .and_then(|value| ::serde_json::from_str::<Vec<FlashMessage>>(&percent_decode(value)).ok())
+            .unwrap_or_default();
+
+        if !messages.is_empty() {
+            req.extensions_mut().insert(IncomingFlashes {
+                messages,
+                read: AtomicBool::new(false),
+            });
+        }
+
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let outgoing = res.get_extensions_mut().remove::<OutgoingFlashes>().map(|flashes| flashes.0).unwrap_or_default();
+        let (mut messages, read) = match req.extensions().get::<IncomingFlashes>() {
+            Some(incoming) if incoming.read.load(Ordering::SeqCst) => (Vec::new(), true),
+            Some(incoming) => (incoming.messages.clone(), false),
+            None => (Vec::new(), false),
+        };
+
+        if !outgoing.is_empty() {
+            messages.extend(outgoing);
+            let value = ::serde_json::to_string(&messages).unwrap_or_default();
+            res.header(header::SET_COOKIE, self.cookie(&percent_encode(&value), self.max_age.as_secs()));
+        } else if read {
+            res.header(header::SET_COOKIE, self.cookie("", 0));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use http::*;
+use csrf::CsrfToken;
+use query::from_query;
+use query::query_pairs;
+use query::percent_encode;
+use serde::de;
+use serde::de::DeserializeOwned;
+use serde::de::value;
+
+impl SyncRequest {
+    /// Returns whether the body of the request is `application/x-www-form-urlencoded`
+    pub fn is_form(&self) -> bool {
+        self.headers_map().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok())
+            .map(|c| c.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("application/x-www-form-urlencoded"))
+            .unwrap_or(false)
+    }
+
+    /// Deserialize the `application/x-www-form-urlencoded` body of the request, the way `from_query` deserializes query
+    /// strings. The CSRF token field checked by `CsrfMiddleware` is left out, so that structures denying unknown fields
+    /// don't have to declare it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use std::collections::HashMap;
+    /// # fn handler(req: &SyncRequest) {
+    /// let fields: HashMap<String, String> = req.body_form().unwrap();
+    /// # }
+    /// ```
+    pub fn body_form<T: DeserializeOwned>(&self) -> Result<T, value::Error> {
+        if !self.is_form() {
+            return Err(de::Error::custom("expected an application/x-www-form-urlencoded body"));
+        }
+
+        let body = String::from_utf8_lossy(self.body());
+        match self.extensions().get::<CsrfToken>() {
+            Some(csrf) => {
+                let fields: Vec<String> = query_pairs(&body).into_iter()
+                    .filter(|(name, _)| *name != csrf.field)
+                    .map(|(name, value)| format!("{}={}", percent_encode(&name), percent_encode(&value)))
+                    .collect();
+                from_query(&fields.join("&"))
+            }
+            None => from_query(&body),
+        }
+    }
+}
+
+/// Escape the characters of `text` which are markup in HTML, for element contents and quoted attribute values
+pub fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,145 @@
+use http::*;
+use maxminddb::Reader;
+use maxminddb::MaxMindDBError;
+use maxminddb::geoip2;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// What is known about the location and network of a client, attached to the request extensions by `GeoIpMiddleware`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// The ISO 3166-1 alpha-2 code of the country of the client, like `CA`
+    pub country: Option<String>,
+    /// The ISO code of the continent of the client, like `NA`
+    pub continent: Option<String>,
+    /// The number of the autonomous system announcing the address of the client
+    pub asn: Option<u32>,
+    /// The organization owning the autonomous system
+    pub as_organization: Option<String>,
+}
+
+/// A trait for the sources `GeoIpMiddleware` finds the location of clients in
+///
+/// The trait is implemented by `MaxMindDatabase`, and by any function taking an address and returning its `GeoInfo`.
+pub trait GeoLookup: Send + Sync {
+    /// Find what is known about `ip`, `None` if nothing is
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo>;
+}
+
+impl<F> GeoLookup for F where F: Fn(IpAddr) -> Option<GeoInfo> + Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        (*self)(ip)
+    }
+}
+
+/// An error raised when a MaxMind database can't be loaded
+#[derive(Debug)]
+pub struct GeoIpError(MaxMindDBError);
+
+impl fmt::Display for GeoIpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to load the MaxMind database: {}", self.0)
+    }
+}
+
+impl ::std::error::Error for GeoIpError {}
+
+/// MaxMind databases, like the GeoLite2 ones, loaded in memory
+pub struct MaxMindDatabase {
+    country: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl MaxMindDatabase {
+    /// Load the country, or city, database at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, GeoIpError> {
+        Ok(MaxMindDatabase {
+            country: Some(Reader::open_readfile(path).map_err(GeoIpError)?),
+            asn: None,
+        })
+    }
+
+    /// Load the ASN database at `path`
+    pub fn open_asn<P: AsRef<Path>>(path: P) -> Result<Self, GeoIpError> {
+        Ok(MaxMindDatabase {
+            country: None,
+            asn: Some(Reader::open_readfile(path).map_err(GeoIpError)?),
+        })
+    }
+
+    /// Also load the ASN database at `path`
+    pub fn with_asn<P: AsRef<Path>>(mut self, path: P) -> Result<Self, GeoIpError> {
+        self.asn = Some(Reader::open_readfile(path).map_err(GeoIpError)?);
+        Ok(self)
+    }
+}
+
+impl GeoLookup for MaxMindDatabase {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo::default();
+
+        if let Some(country) = self.country.as_ref().and_then(|db| db.lookup::<geoip2::Country>(ip).ok()) {
+            info.country = country.country.and_then(|c| c.iso_code).map(str::to_string);
+            info.continent = country.continent.and_then(|c| c.code).map(str::to_string);
+        }
+
+        if let Some(asn) = self.asn.as_ref().and_then(|db| db.lookup::<geoip2::Asn>(ip).ok()) {
+            info.asn = asn.autonomous_system_number;
+            info.as_organization = asn.autonomous_system_organization.map(str::to_string);
+        }
+
+        if info == GeoInfo::default() { None } else { Some(info) }
+    }
+}
+
+/// A middleware looking up the client of every request, as identified by `SyncRequest::client_ip`, and attaching its
+/// `GeoInfo` to the request extensions, see `SyncRequest::geo`
+///
+/// Clients whose address is unknown to the databases get no `GeoInfo`, leaving guards to decide how to treat them.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let database = MaxMindDatabase::open("GeoLite2-Country.mmdb").unwrap()
+///     .with_asn("GeoLite2-ASN.mmdb").unwrap();
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(GeoIpMiddleware::new(database), vec!("/"), None);
+/// ```
+pub struct GeoIpMiddleware {
+    lookup: Box<dyn GeoLookup>,
+}
+
+impl GeoIpMiddleware {
+    /// Create a middleware finding the location of clients in `lookup`
+    pub fn new<L: 'static + GeoLookup>(lookup: L) -> Self {
+        GeoIpMiddleware {
+            lookup: Box::new(lookup),
+        }
+    }
+}
+
+impl Middleware for GeoIpMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        if let Some(info) = req.client_ip().and_then(|ip| self.lookup.lookup(ip)) {
+            req.extensions_mut().insert(info);
+        }
+
+        RequestContinuation::Next
+    }
+}
+
+impl SyncRequest {
+    /// Returns what `GeoIpMiddleware` found about the client of the request
+    pub fn geo(&self) -> Option<&GeoInfo> {
+        self.extensions().get::<GeoInfo>()
+    }
+}
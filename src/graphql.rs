@@ -0,0 +1,301 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use query::query_pairs;
+use serde_json::Value;
+
+/// A GraphQL operation to execute, as sent by a client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphQLRequest {
+    /// The GraphQL document
+    pub query: String,
+    /// The values of the variables of the operation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<Value>,
+    /// The operation of the document to execute, when it has several
+    #[serde(default, rename = "operationName", skip_serializing_if = "Option::is_none")]
+    pub operation_name: Option<String>,
+    /// Protocol extensions, like persisted queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+}
+
+/// A trait representing the execution of GraphQL operations against a schema, like a juniper `RootNode`
+///
+/// Any `Fn(GraphQLRequest, &SyncRequest) -> Value` implements this trait. The returned value is the response document of the
+/// operation, with its `data` and `errors` members.
+pub trait GraphQLExecutor: Send + Sync {
+    /// Execute `operation`, received in `req`. Requests using the `GET` method should only be allowed to run queries.
+    fn execute(&self, operation: GraphQLRequest, req: &SyncRequest) -> Value;
+}
+
+impl<F> GraphQLExecutor for F where F: Fn(GraphQLRequest, &SyncRequest) -> Value + Send + Sync {
+    fn execute(&self, operation: GraphQLRequest, req: &SyncRequest) -> Value {
+        self(operation, req)
+    }
+}
+
+/// Returns a GraphQL response document reporting a single error with `message`
+pub fn graphql_error(message: &str) -> Value {
+    json!({ "errors": [{ "message": message }] })
+}
+
+const GRAPHIQL: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>GraphiQL</title>
+  <link rel="stylesheet" href="https://unpkg.com/graphiql@3/graphiql.min.css" />
+</head>
+<body style="margin: 0;">
+  <div id="graphiql" style="height: 100vh;"></div>
+  <script crossorigin src="https://unpkg.com/react@18/umd/react.production.min.js"></script>
+  <script crossorigin src="https://unpkg.com/react-dom@18/umd/react-dom.production.min.js"></script>
+  <script crossorigin src="https://unpkg.com/graphiql@3/graphiql.min.js"></script>
+  <script>
+    var fetcher = GraphiQL.createFetcher({ url: window.location.pathname });
+    ReactDOM.createRoot(document.getElementById('graphiql')).render(React.createElement(GraphiQL, { fetcher: fetcher }));
+  </script>
+</body>
+</html>
+"#;
+
+/// A controller serving a GraphQL endpoint, and optionally the GraphiQL IDE
+///
+/// Operations are accepted as `POST` requests with an `application/json` body, or an `application/graphql` body holding the
+/// document alone, and as `GET` requests with `query`, `variables` and `operationName` parameters. A JSON array of operations
+/// is executed as a batch and answered with an array of responses. Malformed requests are answered with `400 Bad Request`
+/// and a response document holding the error, executed operations with `200 OK`.
+///
+/// The schema itself is provided by a `GraphQLExecutor`, which can wrap any GraphQL library.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[macro_use] extern crate serde_json;
+/// # extern crate saphir;
+/// # use saphir::*;
+/// # fn main() {
+/// let graphql = GraphQLController::new(|operation: GraphQLRequest, _req: &SyncRequest| {
+///     if operation.query.contains("version") {
+///         json!({ "data": { "version": "1.0" } })
+///     } else {
+///         graphql_error("unknown field")
+///     }
+/// }).graphiql();
+///
+/// let mut router = Router::new();
+/// router.add("^/graphql$", graphql);
+/// # }
+/// ```
+pub struct GraphQLController {
+    executor: Box<dyn GraphQLExecutor>,
+    batching: bool,
+    graphiql: bool,
+}
+
+impl GraphQLController {
+    /// Create a controller executing operations with `executor`, accepting batches
+    pub fn new<E: 'static + GraphQLExecutor>(executor: E) -> Self {
+        GraphQLController {
+            executor: Box::new(executor),
+            batching: true,
+            graphiql: false,
+        }
+    }
+
+    /// Set whether a JSON array of operations is accepted and executed as a batch
+    pub fn batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Serve the GraphiQL IDE to `GET` requests accepting `text/html` without a `query` parameter
+    pub fn graphiql(mut self) -> Self {
+        self.graphiql = true;
+        self
+    }
+
+    fn reject(res: &mut SyncResponse, message: &str) {
+        res.status(StatusCode::BAD_REQUEST)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(graphql_error(message).to_string());
+    }
+
+    fn respond(res: &mut SyncResponse, document: &Value) {
+        res.status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(document.to_string());
+    }
+
+    fn handle_get(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let params = query_pairs(req.uri().query().unwrap_or(""));
+        let param = |name: &str| params.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+
+        let query = match param("query") {
+            Some(query) => query,
+            None => {
+                let accepts_html = req.headers_map().get(header::ACCEPT).and_then(|a| a.to_str().ok()).is_some_and(|a| a.contains("text/html"));
+                if self.graphiql && accepts_html {
+                    res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/html; charset=utf-8").body(GRAPHIQL);
+                } else {
+                    Self::reject(res, "the query parameter is missing");
+                }
+                return;
+            }
+        };
+
+        let variables = match param("variables") {
+            Some(ref variables) if !variables.is_empty() => match ::serde_json::from_str(variables) {
+                Ok(variables) => Some(variables),
+                Err(e) => return Self::reject(res, &format!("the variables are invalid JSON: {}", e)),
+            },
+            _ => None,
+        };
+
+        let operation = GraphQLRequest {
+            query,
+            variables,
+            operation_name: param("operationName").filter(|name| !name.is_empty()),
+            extensions: None,
+        };
+
+        let document = self.executor.execute(operation, req);
+        Self::respond(res, &document);
+    }
+
+    fn handle_post(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let content_type = req.headers_map().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok())
+            .map(|c| c.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if content_type == "application/graphql" {
+            let operation = GraphQLRequest {
+                query: String::from_utf8_lossy(req.body()).into_owned(),
+                variables: None,
+                operation_name: None,
+                extensions: None,
+            };
+
+            let document = self.executor.execute(operation, req);
+            return Self::respond(res, &document);
+        }
+
+        let body: Value = match ::serde_json::from_slice(req.body()) {
+            Ok(body) => body,
+            Err(e) => return Self::reject(res, &format!("the body is invalid JSON: {}", e)),
+        };
+
+        let parse = |operation: Value| ::serde_json::from_value::<GraphQLRequest>(operation).map_err(|e| format!("invalid operation: {}", e));
+
+        match body {
+            Value::Array(operations) => {
+                if !self.batching {
+                    return Self::reject(res, "batched operations are not accepted");
+                }
+
+                let operations = match operations.into_iter().map(parse).collect::<Result<Vec<_>, _>>() {
+                    Ok(operations) => operations,
+                    Err(e) => return Self::reject(res, &e),
+                };
+
+                let documents: Vec<Value> = operations.into_iter().map(|operation| self.executor.execute(operation, req)).collect();
+                Self::respond(res, &Value::Array(documents));
+            }
+            operation => match parse(operation) {
+                Ok(operation) => {
+                    let document = self.executor.execute(operation, req);
+                    Self::respond(res, &document);
+                }
+                Err(e) => Self::reject(res, &e),
+            },
+        }
+    }
+}
+
+impl Controller for GraphQLController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        match *req.method() {
+            Method::GET => self.handle_get(req, res),
+            Method::POST => self.handle_post(req, res),
+            _ => {
+                res.status(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, "GET, POST");
+            }
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        vec![Method::GET, Method::POST].into_iter()
+            .map(|method| RouteInfo::new("GraphQLController", Some(method), None, Vec::new()))
+            .collect()
+    }
+}
+
+mod juniper_schema {
+    use super::*;
+    use juniper::Definition;
+    use juniper::GraphQLType;
+    use juniper::OperationType;
+    use juniper::RootNode;
+    use juniper::parser::parse_document_source;
+    use juniper::http::GraphQLRequest as JuniperRequest;
+
+    /// A `GraphQLExecutor` running operations against a juniper schema, with a context built from each request
+    ///
+    /// Operations sent with the `GET` method are only allowed to run queries.
+    pub struct JuniperSchema<Q, M, S, F> where Q: GraphQLType, M: GraphQLType, S: GraphQLType {
+        root: RootNode<'static, Q, M, S>,
+        context: F,
+    }
+
+    impl<Q, M, S, F> JuniperSchema<Q, M, S, F>
+        where Q: GraphQLType,
+              M: GraphQLType<Context = Q::Context>,
+              S: GraphQLType<Context = Q::Context>,
+              Q::Context: Sized,
+              F: Fn(&SyncRequest) -> Q::Context {
+        /// Create an executor for `root`, calling `context` to build the context of every operation
+        pub fn new(root: RootNode<'static, Q, M, S>, context: F) -> Self {
+            JuniperSchema {
+                root,
+                context,
+            }
+        }
+    }
+
+    impl<Q, M, S, F> GraphQLExecutor for JuniperSchema<Q, M, S, F>
+        where Q: GraphQLType,
+              M: GraphQLType<Context = Q::Context>,
+              S: GraphQLType<Context = Q::Context>,
+              Q::Context: Sized,
+              RootNode<'static, Q, M, S>: Send + Sync,
+              F: Fn(&SyncRequest) -> Q::Context + Send + Sync {
+        fn execute(&self, operation: GraphQLRequest, req: &SyncRequest) -> Value {
+            let operation: JuniperRequest = match ::serde_json::to_value(&operation).and_then(::serde_json::from_value) {
+                Ok(operation) => operation,
+                Err(e) => return graphql_error(&format!("invalid operation: {}", e)),
+            };
+
+            if *req.method() == Method::GET {
+                match parse_document_source(&operation.query, &self.root.schema) {
+                    Ok(document) => {
+                        let mutates = document.iter().any(|definition| match *definition {
+                            Definition::Operation(ref op) => op.item.operation_type != OperationType::Query
+                                && operation.operation_name.as_ref().is_none_or(|name| op.item.name.as_ref().map(|n| n.item) == Some(name.as_str())),
+                            _ => false,
+                        });
+                        if mutates {
+                            return graphql_error("only queries can be sent with the GET method");
+                        }
+                    }
+                    Err(e) => return graphql_error(&e.to_string()),
+                }
+            }
+
+            let context = (self.context)(req);
+            let response = operation.execute_sync(&self.root, &context);
+            ::serde_json::to_value(&response).unwrap_or_else(|e| graphql_error(&e.to_string()))
+        }
+    }
+}
+
+pub use self::juniper_schema::JuniperSchema;
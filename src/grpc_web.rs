@@ -0,0 +1,303 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use std::collections::HashMap;
+use std::fmt;
+
+/// The status codes of the gRPC protocol, sent in the `grpc-status` trailer of every response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrpcCode {
+    /// The call completed successfully
+    Ok = 0,
+    /// The call was cancelled, typically by the caller
+    Cancelled = 1,
+    /// An error which does not fit in any other code
+    Unknown = 2,
+    /// The client specified an invalid argument
+    InvalidArgument = 3,
+    /// The deadline expired before the call could complete
+    DeadlineExceeded = 4,
+    /// A requested entity was not found
+    NotFound = 5,
+    /// The entity the client attempted to create already exists
+    AlreadyExists = 6,
+    /// The caller is not allowed to execute the call
+    PermissionDenied = 7,
+    /// A resource, like a quota, has been exhausted
+    ResourceExhausted = 8,
+    /// The system is not in a state required for the call
+    FailedPrecondition = 9,
+    /// The call was aborted, typically because of a concurrency issue
+    Aborted = 10,
+    /// The call was attempted past the valid range
+    OutOfRange = 11,
+    /// The call is not implemented or not supported
+    Unimplemented = 12,
+    /// An invariant of the system has been broken
+    Internal = 13,
+    /// The service is currently unavailable
+    Unavailable = 14,
+    /// Unrecoverable data loss or corruption
+    DataLoss = 15,
+    /// The request does not have valid authentication credentials
+    Unauthenticated = 16,
+}
+
+/// The outcome of a failed gRPC call, made of a code and a message for the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcStatus {
+    /// The status code of the call
+    pub code: GrpcCode,
+    /// A description of the error, sent in the `grpc-message` trailer
+    pub message: String,
+}
+
+impl GrpcStatus {
+    /// Create a status with `code` and `message`
+    pub fn new<M: Into<String>>(code: GrpcCode, message: M) -> Self {
+        GrpcStatus {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for GrpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl ::std::error::Error for GrpcStatus {}
+
+/// A trait representing the handler of a unary gRPC method
+///
+/// Any `Fn(&SyncRequest, &[u8]) -> Result<Vec<u8>, GrpcStatus>` implements this trait. The handler receives the serialized
+/// request message, unframed, and returns the serialized response message.
+pub trait GrpcHandler: Send + Sync {
+    /// Handle the call made by `req`, carrying `message`
+    fn call(&self, req: &SyncRequest, message: &[u8]) -> Result<Vec<u8>, GrpcStatus>;
+}
+
+impl<F> GrpcHandler for F where F: Fn(&SyncRequest, &[u8]) -> Result<Vec<u8>, GrpcStatus> + Send + Sync {
+    fn call(&self, req: &SyncRequest, message: &[u8]) -> Result<Vec<u8>, GrpcStatus> {
+        self(req, message)
+    }
+}
+
+const DATA_FRAME: u8 = 0x00;
+const COMPRESSED_FLAG: u8 = 0x01;
+const TRAILER_FRAME: u8 = 0x80;
+
+/// The wire format of a gRPC-Web exchange, picked from the request content type and mirrored in the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Binary,
+    Text,
+}
+
+/// Parse the content type of a gRPC-Web request, returning the encoding and the content type of the response. Only the
+/// protobuf message format is supported.
+fn negotiate(req: &SyncRequest) -> Option<(Encoding, &'static str)> {
+    let content_type = req.headers_map().get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok())?;
+    match content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+        "application/grpc-web" | "application/grpc-web+proto" => Some((Encoding::Binary, "application/grpc-web+proto")),
+        "application/grpc-web-text" | "application/grpc-web-text+proto" => Some((Encoding::Text, "application/grpc-web-text+proto")),
+        _ => None,
+    }
+}
+
+/// Decode a `grpc-web-text` body, which may be made of several base64 chunks, each one with its own padding
+fn decode_text(body: &[u8]) -> Result<Vec<u8>, GrpcStatus> {
+    let body: Vec<u8> = body.iter().cloned().filter(|b| !b.is_ascii_whitespace()).collect();
+    let mut decoded = Vec::with_capacity(body.len() * 3 / 4);
+
+    let mut start = 0;
+    for (i, quad) in body.chunks(4).enumerate() {
+        let end = i * 4 + quad.len();
+        if quad.contains(&b'=') || end == body.len() {
+            let chunk = ::base64::decode(&body[start..end])
+                .map_err(|e| GrpcStatus::new(GrpcCode::InvalidArgument, format!("invalid base64 body: {}", e)))?;
+            decoded.extend_from_slice(&chunk);
+            start = end;
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Extract the single message of a unary call from its framed body
+fn unframe(body: &[u8]) -> Result<&[u8], GrpcStatus> {
+    if body.len() < 5 {
+        return Err(GrpcStatus::new(GrpcCode::InvalidArgument, "the request does not contain a message"));
+    }
+
+    if body[0] & COMPRESSED_FLAG != 0 {
+        return Err(GrpcStatus::new(GrpcCode::Unimplemented, "compressed messages are not supported"));
+    }
+
+    let length = ((body[1] as usize) << 24) | ((body[2] as usize) << 16) | ((body[3] as usize) << 8) | body[4] as usize;
+    if body[0] != DATA_FRAME || body.len() - 5 != length {
+        return Err(GrpcStatus::new(GrpcCode::InvalidArgument, "the request must contain a single message"));
+    }
+
+    Ok(&body[5..])
+}
+
+fn frame(flag: u8, payload: &[u8], out: &mut Vec<u8>) {
+    let length = payload.len() as u32;
+    out.push(flag);
+    out.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8]);
+    out.extend_from_slice(payload);
+}
+
+/// Percent-encode a `grpc-message` value, as required by the gRPC protocol for anything outside of printable ASCII
+fn encode_grpc_message(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+
+    for b in message.bytes() {
+        if (0x20..=0x7E).contains(&b) && b != b'%' {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+/// The handler of a unary method, see `GrpcWebController::add_unary`
+type BoxedGrpcHandler = Box<dyn GrpcHandler>;
+
+/// A controller bridging gRPC-Web calls, as made by browser clients, to unary method handlers
+///
+/// Calls are `POST` requests to `/<package>.<Service>/<Method>`, with an `application/grpc-web[+proto]` body or its base64
+/// `application/grpc-web-text[+proto]` variant. The response mirrors the request encoding and always carries a `200 OK`
+/// status: the outcome of the call is sent as `grpc-status` and `grpc-message` in a trailer frame, as well as in the response
+/// headers when the call fails. Requests with another content type are answered with `415 Unsupported Media Type`.
+///
+/// Cross-origin clients need a CORS policy allowing the `content-type`, `x-grpc-web` and `x-user-agent` request headers and
+/// exposing the `grpc-status` and `grpc-message` response headers.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut grpc = GrpcWebController::new();
+/// grpc.add_unary("greeter.Greeter/Echo", |_req: &SyncRequest, message: &[u8]| -> Result<Vec<u8>, GrpcStatus> {
+///     Ok(message.to_vec())
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/greeter\\.Greeter/", grpc);
+/// ```
+pub struct GrpcWebController {
+    methods: HashMap<String, BoxedGrpcHandler>,
+}
+
+impl GrpcWebController {
+    /// Create a controller without any method, every call will fail with `Unimplemented`
+    pub fn new() -> Self {
+        GrpcWebController {
+            methods: HashMap::new(),
+        }
+    }
+
+    /// Add the handler of the unary method `path`, written as `<package>.<Service>/<Method>`
+    pub fn add_unary<P: AsRef<str>, H: 'static + GrpcHandler>(&mut self, path: P, handler: H) -> &mut Self {
+        self.methods.insert(path.as_ref().trim_matches('/').to_string(), Box::new(handler));
+        self
+    }
+
+    /// Add the handler of the unary method `path`, decoding its request and encoding its response as protobuf messages
+    #[cfg(feature = "protobuf")]
+    pub fn add_protobuf<P, Req, Res, F>(&mut self, path: P, handler: F) -> &mut Self
+        where P: AsRef<str>,
+              Req: ::prost::Message + Default,
+              Res: ::prost::Message,
+              F: 'static + Fn(&SyncRequest, Req) -> Result<Res, GrpcStatus> + Send + Sync {
+        self.add_unary(path, move |req: &SyncRequest, message: &[u8]| {
+            let message = Req::decode(message)
+                .map_err(|e| GrpcStatus::new(GrpcCode::InvalidArgument, format!("invalid request message: {}", e)))?;
+            handler(req, message).map(|response| response.encode_to_vec())
+        })
+    }
+
+    /// Find the handler of the method named by the last two segments of `path`
+    fn method(&self, path: &str) -> Option<&dyn GrpcHandler> {
+        let mut segments = path.trim_end_matches('/').rsplitn(3, '/');
+        let method = segments.next()?;
+        let service = segments.next()?;
+        self.methods.get(&format!("{}/{}", service, method)).map(|handler| &**handler)
+    }
+
+    fn call(&self, req: &SyncRequest, encoding: Encoding) -> Result<Vec<u8>, GrpcStatus> {
+        let handler = self.method(req.uri().path())
+            .ok_or_else(|| GrpcStatus::new(GrpcCode::Unimplemented, format!("unknown method {}", req.uri().path())))?;
+
+        let body = match encoding {
+            Encoding::Binary => unframe(req.body())?.to_vec(),
+            Encoding::Text => unframe(&decode_text(req.body())?)?.to_vec(),
+        };
+
+        handler.call(req, &body)
+    }
+}
+
+impl Default for GrpcWebController {
+    fn default() -> Self {
+        GrpcWebController::new()
+    }
+}
+
+impl Controller for GrpcWebController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::POST {
+            res.status(StatusCode::METHOD_NOT_ALLOWED).header(header::ALLOW, "POST");
+            return;
+        }
+
+        let (encoding, content_type) = match negotiate(req) {
+            Some(negotiated) => negotiated,
+            None => {
+                res.status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                return;
+            }
+        };
+
+        let mut body = Vec::new();
+        let status = match self.call(req, encoding) {
+            Ok(message) => {
+                frame(DATA_FRAME, &message, &mut body);
+                GrpcStatus::new(GrpcCode::Ok, "")
+            }
+            Err(status) => {
+                res.header("grpc-status", (status.code as u32).to_string())
+                    .header("grpc-message", encode_grpc_message(&status.message));
+                status
+            }
+        };
+
+        let mut trailers = format!("grpc-status:{}\r\n", status.code as u32);
+        if !status.message.is_empty() {
+            trailers.push_str(&format!("grpc-message:{}\r\n", encode_grpc_message(&status.message)));
+        }
+        frame(TRAILER_FRAME, trailers.as_bytes(), &mut body);
+
+        if encoding == Encoding::Text {
+            body = ::base64::encode(&body).into_bytes();
+        }
+
+        res.status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body);
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let mut paths: Vec<&String> = self.methods.keys().collect();
+        paths.sort();
+        paths.into_iter()
+            .map(|path| RouteInfo::new("GrpcWebController", Some(Method::POST), Some(format!("/{}$", path)), Vec::new()))
+            .collect()
+    }
+}
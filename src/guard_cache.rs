@@ -0,0 +1,158 @@
+use http::*;
+use controller::RequestGuard;
+use response_cache::CachedResponse;
+use utils::RequestContinuation;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+/// A validation remembered by a `CachedGuard`, `None` when the request was allowed through
+struct Validation {
+    rejection: Option<CachedResponse>,
+    expires_at: Instant,
+}
+
+/// A guard remembering the validations of an expensive guard, like a token introspection, so that requests with the same
+/// credentials aren't validated again until the ttl elapses
+///
+/// Validations are remembered under the key returned by `RequestGuard::cache_key`, requests without a key are always
+/// validated. Rejections are remembered too, along with the status, headers and body the guard answered with, unless
+/// `allowed_only` is set. Once the cache holds `max_entries` validations, new ones are not remembered until some expire.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// struct TokenGuard;
+///
+/// impl RequestGuard for TokenGuard {
+///     fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+///         // Call the introspection endpoint of the authorization server
+///         RequestContinuation::Next
+///     }
+///
+///     fn cache_key(&self, req: &SyncRequest) -> Option<String> {
+///         req.headers_map().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+///     }
+/// }
+///
+/// let mut controller = BasicController::new(());
+/// let guard = CachedGuard::new(TokenGuard, Duration::from_secs(60));
+/// controller.add_with_guards(Method::GET, "^/account$", guard.into(), |_, _, res| { res.status(StatusCode::OK); });
+/// ```
+pub struct CachedGuard<G> {
+    guard: G,
+    ttl: Duration,
+    max_entries: usize,
+    allowed_only: bool,
+    validations: Mutex<HashMap<String, Validation>>,
+}
+
+impl<G: RequestGuard> CachedGuard<G> {
+    /// Remember the validations of `guard` for `ttl`, up to 10 000 of them
+    pub fn new(guard: G, ttl: Duration) -> Self {
+        CachedGuard {
+            guard,
+            ttl,
+            max_entries: 10_000,
+            allowed_only: false,
+            validations: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how many validations are remembered at most
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Only remember the requests allowed through, rejected requests are validated again every time
+    pub fn allowed_only(mut self) -> Self {
+        self.allowed_only = true;
+        self
+    }
+
+    /// Forget every validation, like after credentials were revoked
+    pub fn clear(&self) {
+        self.validations.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    fn remember(&self, key: String, rejection: Option<CachedResponse>) {
+        let now = Instant::now();
+        let mut validations = self.validations.lock().unwrap_or_else(|e| e.into_inner());
+
+        if validations.len() >= self.max_entries {
+            validations.retain(|_, validation| validation.expires_at > now);
+            if validations.len() >= self.max_entries {
+                return;
+            }
+        }
+
+        validations.insert(key, Validation {
+            rejection,
+            expires_at: now + self.ttl,
+        });
+    }
+}
+
+impl<G: RequestGuard> RequestGuard for CachedGuard<G> {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let key = match self.guard.cache_key(req) {
+            Some(key) => key,
+            None => return self.guard.validate(req, res),
+        };
+
+        let cached = {
+            let validations = self.validations.lock().unwrap_or_else(|e| e.into_inner());
+            validations.get(&key)
+                .filter(|validation| validation.expires_at > Instant::now())
+                .map(|validation| validation.rejection.clone())
+        };
+
+        match cached {
+            Some(None) => return RequestContinuation::Next,
+            Some(Some(rejection)) => {
+                res.status(rejection.status);
+                for name in rejection.headers.keys() {
+                    res.headers_map_mut().remove(name);
+                }
+                for (name, value) in rejection.headers.iter() {
+                    res.headers_map_mut().append(name.clone(), value.clone());
+                }
+                res.body(rejection.body);
+                return RequestContinuation::None;
+            }
+            None => {}
+        }
+
+        match self.guard.validate(req, res) {
+            RequestContinuation::Next => {
+                self.remember(key, None);
+                RequestContinuation::Next
+            }
+            RequestContinuation::None => {
+                if !self.allowed_only {
+                    self.remember(key, Some(CachedResponse {
+                        status: res.get_status(),
+                        headers: res.headers_map().clone(),
+                        body: res.get_body(),
+                        stored_at: SystemTime::now(),
+                        max_age: self.ttl,
+                    }));
+                }
+                RequestContinuation::None
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        self.guard.name()
+    }
+
+    fn cache_key(&self, req: &SyncRequest) -> Option<String> {
+        self.guard.cache_key(req)
+    }
+}
@@ -0,0 +1,136 @@
+use std::net::TcpListener;
+use futures::sync::oneshot::Sender;
+
+/// Environment variable holding the file descriptor of the listener handed over by a previous process
+#[cfg(unix)]
+const LISTEN_FD_VAR: &str = "SAPHIR_LISTEN_FD";
+
+/// First file descriptor passed by systemd socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Returns the listening sockets inherited from systemd socket activation or from a previous process handing them over, if
+/// any. Descriptors which are not listening TCP sockets are ignored.
+///
+/// The environment variables describing the sockets are removed, so child processes don't inherit them.
+#[cfg(unix)]
+pub fn inherited_listeners() -> Vec<TcpListener> {
+    use std::env;
+    use std::os::unix::io::FromRawFd;
+
+    let handed_over = env::var(LISTEN_FD_VAR).ok()
+        .map(|fds| fds.split(',').filter_map(|fd| fd.trim().parse::<i32>().ok()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    env::remove_var(LISTEN_FD_VAR);
+
+    let activated = match (env::var("LISTEN_PID"), env::var("LISTEN_FDS")) {
+        (Ok(ref pid), Ok(ref fds)) if *pid == ::std::process::id().to_string() => {
+            (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds.parse::<i32>().unwrap_or(0)).collect()
+        }
+        _ => Vec::new(),
+    };
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let fds = if handed_over.is_empty() { activated } else { handed_over };
+    fds.into_iter()
+        .filter(|fd| match is_tcp_listener(*fd) {
+            Ok(true) => true,
+            Ok(false) => {
+                warn!("Ignoring the inherited descriptor {}, it is not a listening TCP socket", fd);
+                false
+            }
+            Err(e) => {
+                warn!("Ignoring the inherited descriptor {}: {}", fd, e);
+                false
+            }
+        })
+        // The descriptor is owned by this process from now on, nothing else in the process refers to it
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listeners() -> Vec<TcpListener> {
+    Vec::new()
+}
+
+/// Returns whether `fd` is an open TCP socket, listening on platforms telling it, without taking its ownership
+#[cfg(unix)]
+fn is_tcp_listener(fd: i32) -> ::std::io::Result<bool> {
+    use socket2::SockRef;
+    use socket2::Type;
+    use std::os::unix::io::BorrowedFd;
+
+    if fd < 0 {
+        return Ok(false);
+    }
+
+    // The descriptor is only borrowed for the checks, an invalid one makes them fail with EBADF
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    let socket = SockRef::from(&fd);
+
+    if socket.r#type()? != Type::STREAM || socket.local_addr()?.as_socket().is_none() {
+        return Ok(false);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "fuchsia"))]
+    {
+        socket.is_listener()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "fuchsia")))]
+    {
+        Ok(true)
+    }
+}
+
+/// Spawn a new instance of the current executable inheriting `listeners` whenever `SIGUSR2` is received. Once the new process
+/// is started, `shutdown` is triggered so this process stops accepting connections and drains the ones in progress.
+///
+/// Every listener is handed over, like the ones bound with `SO_REUSEPORT` by each thread of a thread per core server, so no
+/// socket is closed along with its queued connections when this process exits.
+#[cfg(unix)]
+pub fn handover_on_sigusr2(listeners: &[TcpListener], shutdown: Sender<()>) -> ::std::io::Result<()> {
+    use signal_hook::consts::SIGUSR2;
+    use signal_hook::iterator::Signals;
+    use socket2::SockRef;
+    use std::os::unix::io::AsRawFd;
+    use std::process::Command;
+
+    // The clones outlive the server, so the descriptors are still open when the new process is spawned
+    let listeners = listeners.iter().map(|listener| listener.try_clone()).collect::<::std::io::Result<Vec<_>>>()?;
+    let fds = listeners.iter().map(|listener| listener.as_raw_fd().to_string()).collect::<Vec<_>>().join(",");
+    let mut signals = Signals::new([SIGUSR2])?;
+
+    ::std::thread::spawn(move || {
+        for _ in signals.forever() {
+            let spawned = listeners.iter().try_for_each(|listener| SockRef::from(listener).set_cloexec(false))
+                .and_then(|_| ::std::env::current_exe())
+                .and_then(|exe| Command::new(exe).args(::std::env::args_os().skip(1)).env(LISTEN_FD_VAR, &fds).spawn());
+
+            match spawned {
+                Ok(child) => {
+                    info!("Listeners handed over to process {}, draining connections", child.id());
+                    let _ = shutdown.send(());
+                    return;
+                }
+                Err(e) => {
+                    for listener in &listeners {
+                        let _ = SockRef::from(listener).set_cloexec(true);
+                    }
+                    error!("Unable to hand the listeners over to a new process: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn handover_on_sigusr2(_listeners: &[TcpListener], _shutdown: Sender<()>) -> ::std::io::Result<()> {
+    warn!("Listener handover is only supported on unix platforms");
+    Ok(())
+}
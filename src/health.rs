@@ -0,0 +1,173 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use futures::Future;
+use futures::IntoFuture;
+use futures::future::join_all;
+use std::collections::BTreeMap;
+
+/// Future returned by a health check, resolving to `Ok(())` when the checked dependency is healthy
+pub type HealthCheckFuture = Box<dyn Future<Item=(), Error=String> + Send>;
+
+/// A trait representing a single health check, like a database ping or a dependency lookup
+///
+/// Any `Fn() -> R` where `R` is a `Result<(), String>` or a future resolving to `()` implements this trait.
+pub trait HealthCheck: Send + Sync {
+    /// Start the check. The returned future is awaited by the `HealthController` along with every other check.
+    fn check(&self) -> HealthCheckFuture;
+}
+
+impl<F, R> HealthCheck for F
+    where F: Fn() -> R + Send + Sync,
+          R: IntoFuture<Item=(), Error=String>,
+          R::Future: 'static + Send {
+    fn check(&self) -> HealthCheckFuture {
+        Box::new(self().into_future())
+    }
+}
+
+#[derive(Serialize)]
+struct CheckReport {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    status: &'static str,
+    checks: BTreeMap<String, CheckReport>,
+}
+
+/// A controller answering liveness (`/healthz`) and readiness (`/readyz`) probes
+///
+/// Every check registered for a probe is run concurrently, the probe answers `200 OK` when all of them succeed and
+/// `503 Service Unavailable` otherwise. In both cases the body is a JSON document detailing the outcome of each check. The
+/// readiness probe also fails until the warm-up tasks of the server completed, see `ServerBuilder::warm_up`, and while the
+/// server drains its connections, see `AdminController`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut health = HealthController::new();
+/// health.add_readiness_check("database", || -> Result<(), String> { Ok(()) });
+///
+/// let mut router = Router::new();
+/// router.add("^/(healthz|readyz)$", health);
+/// ```
+pub struct HealthController {
+    liveness_checks: Vec<(String, Box<dyn HealthCheck>)>,
+    readiness_checks: Vec<(String, Box<dyn HealthCheck>)>,
+}
+
+impl HealthController {
+    /// Create a new health controller without any check, both probes will answer `200 OK`
+    pub fn new() -> Self {
+        HealthController {
+            liveness_checks: Vec::new(),
+            readiness_checks: Vec::new(),
+        }
+    }
+
+    /// Add a check evaluated by the liveness probe (`/healthz`)
+    pub fn add_liveness_check<N: Into<String>, C: 'static + HealthCheck>(&mut self, name: N, check: C) -> &mut Self {
+        self.liveness_checks.push((name.into(), Box::new(check)));
+        self
+    }
+
+    /// Add a check evaluated by the readiness probe (`/readyz`)
+    pub fn add_readiness_check<N: Into<String>, C: 'static + HealthCheck>(&mut self, name: N, check: C) -> &mut Self {
+        self.readiness_checks.push((name.into(), Box::new(check)));
+        self
+    }
+
+    /// Run `checks`, failing along with the named `failures` the server already knows of
+    fn evaluate(checks: &[(String, Box<dyn HealthCheck>)], failures: Vec<(String, String)>, res: &mut SyncResponse) {
+        let pending = checks.iter().map(|(name, check)| {
+            let name = name.clone();
+            check.check().then(move |result| Ok::<_, ()>((name, result)))
+        }).collect::<Vec<_>>();
+
+        let results = join_all(pending).wait().unwrap_or_default();
+        let mut healthy = true;
+        let mut report = HealthReport {
+            status: "ok",
+            checks: BTreeMap::new(),
+        };
+
+        for (name, result) in results {
+            let check_report = match result {
+                Ok(_) => CheckReport { status: "ok", message: None },
+                Err(message) => {
+                    healthy = false;
+                    CheckReport { status: "error", message: Some(message) }
+                }
+            };
+
+            report.checks.insert(name, check_report);
+        }
+
+        for (name, message) in failures {
+            healthy = false;
+            report.checks.insert(name, CheckReport { status: "error", message: Some(message) });
+        }
+
+        if !healthy {
+            report.status = "error";
+            res.status(StatusCode::SERVICE_UNAVAILABLE);
+        } else {
+            res.status(StatusCode::OK);
+        }
+
+        let body = ::serde_json::to_vec(&report).unwrap_or_default();
+        res.header(header::CONTENT_TYPE, "application/json")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(body);
+    }
+}
+
+/// Returns why the server isn't ready to receive traffic, besides the readiness checks: the warm-up tasks which didn't
+/// complete, and the draining of its connections
+fn readiness_failures(req: &SyncRequest) -> Vec<(String, String)> {
+    let mut failures: Vec<(String, String)> = req.unfinished_warm_up().into_iter()
+        .map(|(task, reason)| (format!("warm_up.{}", task), reason))
+        .collect();
+
+    if req.is_draining() {
+        failures.push(("draining".to_string(), "the server is draining its connections".to_string()));
+    }
+    failures
+}
+
+impl Default for HealthController {
+    fn default() -> Self {
+        HealthController::new()
+    }
+}
+
+impl Controller for HealthController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED);
+            return;
+        }
+
+        let path = req.uri().path().trim_end_matches('/');
+
+        if path.ends_with("/healthz") {
+            Self::evaluate(&self.liveness_checks, Vec::new(), res);
+        } else if path.ends_with("/readyz") {
+            Self::evaluate(&self.readiness_checks, readiness_failures(req), res);
+        } else {
+            res.status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        ["/healthz$", "/readyz$"].iter().flat_map(|path| {
+            vec![Method::GET, Method::HEAD].into_iter()
+                .map(move |method| RouteInfo::new("HealthController", Some(method), Some(path.to_string()), Vec::new()))
+        }).collect()
+    }
+}
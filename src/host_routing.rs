@@ -0,0 +1,81 @@
+use http::*;
+use regex::Regex;
+
+/// A pattern matching the host of requests, like `{tenant}.example.com`, where each `{name}` placeholder matches a
+/// single label of the host
+pub struct HostPattern {
+    source: String,
+    regex: Regex,
+}
+
+impl HostPattern {
+    /// Compile `pattern`, matched regardless of the case of the host
+    ///
+    /// # Panics
+    ///
+    /// Panics if a placeholder isn't closed, or its name isn't made of alphanumeric characters and underscores.
+    pub fn new(pattern: &str) -> Self {
+        let mut regex = String::from("(?i)^");
+        let mut rest = pattern;
+
+        while let Some(start) = rest.find('{') {
+            regex.push_str(&::regex::escape(&rest[..start]));
+            let end = rest[start..].find('}').map(|end| start + end).expect("the host pattern has an unclosed placeholder");
+            let name = &rest[start + 1..end];
+            assert!(!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+                    "the host pattern has an invalid placeholder {{{}}}", name);
+            regex.push_str(&format!("(?P<{}>[^.]+)", name));
+            rest = &rest[end + 1..];
+        }
+
+        regex.push_str(&::regex::escape(rest));
+        regex.push_str("\\.?$");
+
+        HostPattern {
+            source: pattern.to_string(),
+            regex: Regex::new(&regex).expect("the host pattern compiles to a legitimate regex"),
+        }
+    }
+
+    /// Returns the pattern as it was written
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the values of the placeholders of the pattern when it matches `host`
+    pub fn captures(&self, host: &str) -> Option<HostParams> {
+        let captures = self.regex.captures(host)?;
+
+        Some(HostParams(self.regex.capture_names().flatten().filter_map(|name| {
+            captures.name(name).map(|value| (name.to_string(), value.as_str().to_ascii_lowercase()))
+        }).collect()))
+    }
+}
+
+/// The values of the placeholders of the host pattern matched by a request, see `SyncRequest::host_param`
+#[derive(Clone, Debug, Default)]
+pub struct HostParams(Vec<(String, String)>);
+
+impl SyncRequest {
+    /// Returns the value of the placeholder `name` of the host pattern the request was routed with, lowercased, see
+    /// `Router::add_for_host`
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut controller = BasicController::new(());
+    /// controller.add(Method::GET, "^/dashboard$", |_, req, res| {
+    ///     let tenant = req.host_param("tenant").unwrap_or_default();
+    ///     res.status(StatusCode::OK).body(format!("Dashboard of {}", tenant));
+    /// });
+    ///
+    /// let mut router = Router::new();
+    /// router.add_for_host("{tenant}.example.com", "^/dashboard", controller);
+    /// ```
+    pub fn host_param(&self, name: &str) -> Option<&str> {
+        self.extensions().get::<HostParams>()
+            .and_then(|params| params.0.iter().find(|(param, _)| param == name))
+            .map(|(_, value)| value.as_str())
+    }
+}
@@ -1,4 +1,4 @@
-use http_types::response::Builder as ResponseBuilder;
+use http_types::response::Parts as ResParts;
 use http_types::request::Parts as ReqParts;
 pub use http_types::Extensions;
 pub use hyper::Method;
@@ -21,9 +21,22 @@ use futures::Future;
 use futures::Stream;
 use http_types::HttpTryFrom;
 use std::any::Any;
+use std::net::SocketAddr;
+use date::http_date;
+use problem::Problem;
 
 static EMPTY_BODY: &[u8] = b"";
 
+const RESPONSE_HEADERS_CAPACITY: usize = 8;
+
+/// The address of the client a request was received from, inserted in the request extensions by the server
+#[derive(Debug, Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// The body of a request as it was received, kept once the body is replaced, see `SyncRequest::raw_body`
+#[derive(Debug)]
+struct RawBody(Vec<u8>);
+
 /// A Structure which represent an http request with a fully loaded body
 #[derive(Debug)]
 pub struct SyncRequest {
@@ -45,6 +58,51 @@ impl SyncRequest {
         }
     }
 
+    /// Construct a request from its parts, as the server would once received from `peer_addr`, so guards, handlers or a
+    /// whole `Server`, see `Server::process`, can be fuzzed or property tested without any networking. The version of the
+    /// request is `HTTP/1.1`.
+    pub fn from_parts(method: Method,
+                      uri: Uri,
+                      headers: header::HeaderMap,
+                      body: Vec<u8>,
+                      peer_addr: Option<SocketAddr>,
+                      extensions: Extensions,
+    ) -> SyncRequest {
+        let (mut head, _) = Request::new(()).into_parts();
+        head.method = method;
+        head.uri = uri;
+        head.headers = headers;
+        head.extensions = extensions;
+
+        let mut request = SyncRequest::new(head, body);
+        if let Some(peer_addr) = peer_addr {
+            request.extensions_mut().insert(PeerAddr(peer_addr));
+        }
+        request
+    }
+
+    /// Construct a request from raw bytes, like the input of a fuzzer, failing rather than panicking when the method, the
+    /// uri or a header isn't valid, see `SyncRequest::from_parts`.
+    pub fn from_raw_parts(method: &[u8],
+                          uri: &[u8],
+                          headers: &[(&[u8], &[u8])],
+                          body: &[u8],
+                          peer_addr: Option<SocketAddr>,
+    ) -> Result<SyncRequest, ::http_types::Error> {
+        let method = <Method as HttpTryFrom<&[u8]>>::try_from(method)?;
+        // Bytes which aren't UTF-8 are replaced by a character which is invalid in an uri
+        let uri = <Uri as HttpTryFrom<&str>>::try_from(&String::from_utf8_lossy(uri))?;
+
+        let mut header_map = header::HeaderMap::with_capacity(headers.len());
+        for &(name, value) in headers {
+            let name = <header::HeaderName as HttpTryFrom<&[u8]>>::try_from(name)?;
+            let value = <header::HeaderValue as HttpTryFrom<&[u8]>>::try_from(value)?;
+            header_map.append(name, value);
+        }
+
+        Ok(SyncRequest::from_parts(method, uri, header_map, body.to_vec(), peer_addr, Extensions::new()))
+    }
+
     /// Returns a reference to the associated HTTP method.
     ///
     /// # Examples
@@ -180,13 +238,88 @@ impl SyncRequest {
     pub fn insert_parsed_headers(&mut self, headers: header::Headers) {
         let map: header::HeaderMap = headers.into();
 
-        for header in map {
-            if let (Some(name), value) = header {
-                self.head.headers.insert(name, value);
-            }
+        for name in map.keys() {
+            self.head.headers.remove(name);
+        }
+
+        for (name, value) in map.iter() {
+            self.head.headers.append(name.clone(), value.clone());
         }
     }
 
+    /// Returns every value of the header `key`, in the order they were received.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # fn handler(req: &SyncRequest) {
+    /// for cookie in req.header_values(header::COOKIE) {
+    ///     println!("{:?}", cookie);
+    /// }
+    /// # }
+    /// ```
+    #[inline]
+    pub fn header_values<'a, K: header::AsHeaderName>(&'a self, key: K) -> header::GetAll<'a, header::HeaderValue> {
+        self.head.headers.get_all(key)
+    }
+
+    /// Returns the elements of a comma separated list header like `Accept` or `Cache-Control`, whether they were sent in a
+    /// single field or spread across repeated fields. Values which are not visible ASCII are skipped.
+    pub fn header_list<K: header::AsHeaderName>(&self, key: K) -> Vec<&str> {
+        header_list(&self.head.headers, key)
+    }
+
+    /// Returns the host the request is addressed to, without its port, as sent in the `Host` header or in the authority of
+    /// the uri for requests which don't have one
+    pub fn host(&self) -> Option<&str> {
+        let authority = match self.head.headers.get(header::HOST).and_then(|host| host.to_str().ok()) {
+            Some(host) => host,
+            None => return self.head.uri.authority_part().map(|authority| authority.host()),
+        };
+
+        let host = match authority.find(']') {
+            Some(end) if authority.starts_with('[') => &authority[..=end],
+            _ => authority.split(':').next().unwrap_or(authority),
+        };
+
+        if host.is_empty() { None } else { Some(host) }
+    }
+
+    /// Returns the value of the cookie `name` sent in the `Cookie` headers of the request, the first one when it was sent
+    /// more than once
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.head.headers.get_all(header::COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| {
+                let mut parts = pair.trim().splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(cookie), Some(value)) if cookie == name => Some(value.trim_matches('"')),
+                    _ => None,
+                }
+            })
+            .next()
+    }
+
+    /// Appends a header to the request, without replacing the previous values of the same header.
+    pub fn append_header<K, V>(&mut self, key: K, value: V) -> Result<(), ::http_types::Error>
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        let key = <header::HeaderName as HttpTryFrom<K>>::try_from(key).map_err(Into::into)?;
+        let value = <header::HeaderValue as HttpTryFrom<V>>::try_from(value).map_err(Into::into)?;
+        self.head.headers.append(key, value);
+        Ok(())
+    }
+
+    /// Iterate over every header of the request. Fields are yielded in the order their name first appeared, and the values of
+    /// a repeated field are yielded in the order they were received.
+    #[inline]
+    pub fn headers_iter<'a>(&'a self) -> header::Iter<'a, header::HeaderValue> {
+        self.head.headers.iter()
+    }
+
     /// Returns a reference to the associated extensions.
     ///
     /// # Examples
@@ -218,6 +351,12 @@ impl SyncRequest {
         &mut self.head.extensions
     }
 
+    /// Returns the address of the client which sent the request, or `None` if the request was not received by a `Server`
+    #[inline]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.head.extensions.get::<PeerAddr>().map(|addr| addr.0)
+    }
+
     /// Returns a reference to the associated HTTP body.
     ///
     /// # Examples
@@ -248,6 +387,36 @@ impl SyncRequest {
     pub fn body_mut(&mut self) -> &mut Vec<u8> {
         &mut self.body
     }
+
+    /// Returns the body of the request as it was received, before any middleware replaced it with `replace_body`, like
+    /// `BodyTransformMiddleware` does. Signatures of the body, like the ones of webhooks, are computed over these bytes.
+    ///
+    /// Deserializing the body, with `body_json` or `body_form` for instance, borrows it, so both the raw and the parsed
+    /// body stay available to the handler, the middlewares run after it, and the audit log.
+    pub fn raw_body(&self) -> &[u8] {
+        match self.head.extensions.get::<RawBody>() {
+            Some(raw) => &raw.0,
+            None => &self.body,
+        }
+    }
+
+    /// Replace the body of the request with `body`, keeping the body as it was received for `raw_body`. Middlewares
+    /// rewriting bodies should prefer this method to `body_mut`.
+    pub fn replace_body(&mut self, body: Vec<u8>) {
+        let previous = ::std::mem::replace(&mut self.body, body);
+        if self.head.extensions.get::<RawBody>().is_none() {
+            self.head.extensions.insert(RawBody(previous));
+        }
+    }
+}
+
+fn header_list<K: header::AsHeaderName>(headers: &header::HeaderMap<header::HeaderValue>, key: K) -> Vec<&str> {
+    headers.get_all(key).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect()
 }
 
 /// A trait allowing the implicit conversion of a Hyper::Request into a SyncRequest
@@ -268,17 +437,23 @@ impl LoadBody for Request<Body> {
 
 /// A Structure which represent a fully mutable http response
 pub struct SyncResponse {
-    builder: ResponseBuilder,
+    head: ResParts,
     body: Box<ToBody>,
+    error: Option<::http_types::Error>,
 }
 
 impl SyncResponse {
 
     ///
     pub fn new() -> Self{
+        let (mut head, _) = Response::new(()).into_parts();
+        // Enough room for the headers most responses carry, so they don't grow the map one by one
+        head.headers.reserve(RESPONSE_HEADERS_CAPACITY);
+
         SyncResponse {
-            builder: ResponseBuilder::new(),
+            head,
             body: Box::new(EMPTY_BODY),
+            error: None,
         }
     }
 
@@ -303,10 +478,25 @@ impl SyncResponse {
     pub fn status<T>(&mut self, status: T) -> &mut SyncResponse
         where StatusCode: HttpTryFrom<T>,
     {
-        self.builder.status(status);
+        match HttpTryFrom::try_from(status) {
+            Ok(s) => self.head.status = s,
+            Err(e) => self.error = Some(e.into()),
+        }
         self
     }
 
+    /// Record that building the response failed, `build_response` will return `error`
+    pub(crate) fn invalid(&mut self, error: ::http_types::Error) -> &mut SyncResponse {
+        self.error = Some(error);
+        self
+    }
+
+    /// Returns the HTTP status currently set on this response.
+    #[inline]
+    pub fn get_status(&self) -> StatusCode {
+        self.head.status
+    }
+
     /// Set the HTTP version for this response.
     ///
     /// This function will configure the HTTP version of the `Response` that
@@ -326,10 +516,16 @@ impl SyncResponse {
     ///     .unwrap();
     /// ```
     pub fn version(&mut self, version: Version) -> &mut SyncResponse {
-        self.builder.version(version);
+        self.head.version = version;
         self
     }
 
+    /// Returns the HTTP version currently set on this response.
+    #[inline]
+    pub fn get_version(&self) -> Version {
+        self.head.version
+    }
+
     /// Appends a header to this response builder.
     ///
     /// This function will append the provided key/value as a header to the
@@ -352,23 +548,75 @@ impl SyncResponse {
         where header::HeaderName: HttpTryFrom<K>,
               header::HeaderValue: HttpTryFrom<V>
     {
-        self.builder.header(key, value);
+        match <header::HeaderName as HttpTryFrom<K>>::try_from(key) {
+            Ok(key) => {
+                match <header::HeaderValue as HttpTryFrom<V>>::try_from(value) {
+                    Ok(value) => { self.head.headers.append(key, value); }
+                    Err(e) => self.error = Some(e.into()),
+                }
+            }
+            Err(e) => self.error = Some(e.into()),
+        }
         self
     }
 
+    /// Returns a reference to the header field map of this response.
+    #[inline]
+    pub fn headers_map(&self) -> &header::HeaderMap<header::HeaderValue> {
+        &self.head.headers
+    }
+
+    /// Returns a mutable reference to the header field map of this response.
+    #[inline]
+    pub fn headers_map_mut(&mut self) -> &mut header::HeaderMap<header::HeaderValue> {
+        &mut self.head.headers
+    }
+
     /// A convinient function to constuct the response headers from a Headers struct
     pub fn parsed_header(&mut self, headers: header::Headers) -> &mut SyncResponse {
         let map: header::HeaderMap = headers.into();
 
-        for header in map {
-            if let (Some(name), value) = header {
-                self.builder.header(name, value);
-            }
+        for (name, value) in map.iter() {
+            self.head.headers.append(name.clone(), value.clone());
         }
 
         self
     }
 
+    /// Returns every value of the header `key` set on this response, in the order they were appended.
+    #[inline]
+    pub fn header_values<'a, K: header::AsHeaderName>(&'a self, key: K) -> header::GetAll<'a, header::HeaderValue> {
+        self.head.headers.get_all(key)
+    }
+
+    /// Returns the elements of a comma separated list header set on this response, see `SyncRequest::header_list`.
+    pub fn header_list<K: header::AsHeaderName>(&self, key: K) -> Vec<&str> {
+        header_list(&self.head.headers, key)
+    }
+
+    /// Replace every previous value of the header `key` by `value`.
+    pub fn set_header<K, V>(&mut self, key: K, value: V) -> &mut SyncResponse
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        match <header::HeaderName as HttpTryFrom<K>>::try_from(key) {
+            Ok(key) => {
+                match <header::HeaderValue as HttpTryFrom<V>>::try_from(value) {
+                    Ok(value) => { self.head.headers.insert(key, value); }
+                    Err(e) => self.error = Some(e.into()),
+                }
+            }
+            Err(e) => self.error = Some(e.into()),
+        }
+        self
+    }
+
+    /// Iterate over every header of the response, see `SyncRequest::headers_iter`.
+    #[inline]
+    pub fn headers_iter<'a>(&'a self) -> header::Iter<'a, header::HeaderValue> {
+        self.head.headers.iter()
+    }
+
     /// Adds an extension to this builder
     ///
     /// # Examples
@@ -388,10 +636,22 @@ impl SyncResponse {
     pub fn extension<T>(&mut self, extension: T) -> &mut SyncResponse
         where T: Any + Send + Sync + 'static,
     {
-        self.builder.extension(extension);
+        self.head.extensions.insert(extension);
         self
     }
 
+    /// Returns a reference to the extensions of this response.
+    #[inline]
+    pub fn get_extensions(&self) -> &Extensions {
+        &self.head.extensions
+    }
+
+    /// Returns a mutable reference to the extensions of this response.
+    #[inline]
+    pub fn get_extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.head.extensions
+    }
+
     /// Adds a body to a response
     ///
     /// # Examples
@@ -410,11 +670,108 @@ impl SyncResponse {
         self
     }
 
+    /// Redirect the client to `location` with a `302 Found` status
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    ///
+    /// let mut response = SyncResponse::new();
+    /// response.redirect("/login");
+    /// let response = response.build_response().unwrap();
+    /// ```
+    pub fn redirect<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::FOUND).header(header::LOCATION, location)
+    }
+
+    /// Redirect the client to `location` with a `303 See Other` status, the client will follow it with a `GET` request
+    pub fn see_other<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::SEE_OTHER).header(header::LOCATION, location)
+    }
+
+    /// Redirect the client to `location` with a `301 Moved Permanently` status
+    pub fn moved_permanently<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::MOVED_PERMANENTLY).header(header::LOCATION, location)
+    }
+
+    /// Redirect the client to `location` with a `307 Temporary Redirect` status, the client must keep the request method and body
+    pub fn temporary_redirect<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::TEMPORARY_REDIRECT).header(header::LOCATION, location)
+    }
+
+    /// Redirect the client to `location` with a `308 Permanent Redirect` status, the client must keep the request method and body
+    pub fn permanent_redirect<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::PERMANENT_REDIRECT).header(header::LOCATION, location)
+    }
+
+    /// Set the `204 No Content` status and remove the body of the response
+    pub fn no_content(&mut self) -> &mut SyncResponse {
+        self.status(StatusCode::NO_CONTENT).body(EMPTY_BODY)
+    }
+
+    /// Set the `201 Created` status along with the `Location` of the created resource
+    pub fn created<V>(&mut self, location: V) -> &mut SyncResponse
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.status(StatusCode::CREATED).header(header::LOCATION, location)
+    }
+
+    /// Set `status` and describe the error with an RFC 7807 `application/problem+json` body, see `Problem`
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    ///
+    /// let mut response = SyncResponse::new();
+    /// response.problem_json(StatusCode::FORBIDDEN, "Insufficient credit", "Your balance is 30, but that costs 50.");
+    /// let response = response.build_response().unwrap();
+    /// ```
+    pub fn problem_json(&mut self, status: StatusCode, title: &str, detail: &str) -> &mut SyncResponse {
+        self.problem(&Problem::new(status).with_title(title).with_detail(detail))
+    }
+
+    /// Returns a copy of the bytes currently set as the body of this response.
+    pub fn get_body(&self) -> Vec<u8> {
+        self.body.to_body().concat2().wait().map(|chunk| chunk.to_vec()).unwrap_or_default()
+    }
+
+    /// Take the body out of the response, leaving an empty one, so it can be wrapped
+    pub(crate) fn take_body(&mut self) -> Box<dyn ToBody> {
+        ::std::mem::replace(&mut self.body, Box::new(EMPTY_BODY))
+    }
+
+    /// Replace the body of the response by one taken out of another response, see `take_body`
+    pub(crate) fn put_body(&mut self, body: Box<dyn ToBody>) -> &mut SyncResponse {
+        self.body = body;
+        self
+    }
+
     ///
     pub fn build_response(self) -> Result<Response<Body>, ::http_types::Error> {
-        let SyncResponse { mut builder, body } = self;
-        let b: Body = body.to_body();
-        builder.body(b)
+        let SyncResponse { head, body, error } = self;
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let mut head = head;
+        if !head.headers.contains_key(header::DATE) {
+            head.headers.insert(header::DATE, http_date());
+        }
+
+        Ok(Response::from_parts(head, body.to_body()))
     }
 }
 
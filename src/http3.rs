@@ -0,0 +1,232 @@
+use http::*;
+use error::ServerError;
+use tls::TlsInfo;
+use tls::TlsVersion;
+use saphir_h3::ConnectionInfo;
+use saphir_h3::Closer;
+use saphir_h3::Listener;
+use saphir_h3::Resumption;
+use saphir_h3::TlsSettings;
+use saphir_h3::http as h3_http;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Marks the requests received over HTTP/3, in their extensions
+#[derive(Debug, Clone, Copy)]
+struct Http3Request;
+
+impl SyncRequest {
+    /// Returns whether the request was received by the HTTP/3 listener. Since `Version` has no HTTP/3 variant, such requests
+    /// report `Version::HTTP_2`, the closest protocol.
+    pub fn is_http3(&self) -> bool {
+        self.extensions().get::<Http3Request>().is_some()
+    }
+}
+
+/// The configuration of the experimental HTTP/3 listener, see `ServerBuilder::http3`
+///
+/// HTTP/3 runs over QUIC, on UDP, and always over TLS 1.3: the listener needs a certificate chain and its private key.
+/// Browsers only try HTTP/3 once a response received over TCP advertised it, the server adds an `Alt-Svc` header to these
+/// responses as soon as the listener is bound.
+///
+/// Clients resume their TLS sessions from a cache of 256 sessions by default, see `session_cache` and `session_tickets`.
+/// 0-RTT early data is disabled unless `early_data` opts in to it.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let http3 = Http3Config::from_pem_files("0.0.0.0:443", "cert.pem", "key.pem").unwrap();
+/// let server = Server::builder().router(Router::new()).http3(http3).build();
+/// server.run("http://0.0.0.0:80").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Http3Config {
+    addr: String,
+    cert_chain_pem: Vec<u8>,
+    private_key_pem: Vec<u8>,
+    alt_svc_max_age: Duration,
+    tls: TlsSettings,
+}
+
+impl Http3Config {
+    /// Listen on the UDP `addr`, like `0.0.0.0:443`, with a PEM encoded certificate chain and private key
+    pub fn new<C: Into<Vec<u8>>, K: Into<Vec<u8>>>(addr: &str, cert_chain_pem: C, private_key_pem: K) -> Self {
+        Http3Config {
+            addr: addr.to_string(),
+            cert_chain_pem: cert_chain_pem.into(),
+            private_key_pem: private_key_pem.into(),
+            alt_svc_max_age: Duration::from_secs(24 * 60 * 60),
+            tls: TlsSettings::default(),
+        }
+    }
+
+    /// Listen on the UDP `addr` with the certificate chain and private key read from PEM files
+    pub fn from_pem_files<C: AsRef<Path>, K: AsRef<Path>>(addr: &str, cert_chain: C, private_key: K) -> Result<Self, ServerError> {
+        Ok(Http3Config::new(addr, fs::read(cert_chain)?, fs::read(private_key)?))
+    }
+
+    /// Set for how long clients remember that the server speaks HTTP/3, one day by default
+    pub fn alt_svc_max_age(mut self, max_age: Duration) -> Self {
+        self.alt_svc_max_age = max_age;
+        self
+    }
+
+    /// Keep up to `capacity` TLS sessions in memory, which clients resume with the id they were given
+    pub fn session_cache(mut self, capacity: usize) -> Self {
+        self.tls.resumption = Resumption::Cache { capacity };
+        self
+    }
+
+    /// Keep no TLS session, giving clients tickets holding their session instead, encrypted with a key replaced every
+    /// `rotation`. A ticket remains valid for twice as long, so a stolen key only exposes the sessions of a while.
+    pub fn session_tickets(mut self, rotation: Duration) -> Self {
+        self.tls.resumption = Resumption::Tickets { rotation };
+        self
+    }
+
+    /// Go through a full TLS handshake on every connection, which also rules out early data
+    pub fn no_session_resumption(mut self) -> Self {
+        self.tls.resumption = Resumption::Disabled;
+        self
+    }
+
+    /// Let clients resuming a session from the cache send requests as 0-RTT early data, along with their handshake, which
+    /// sessions resumed with a ticket never do. Early data may be replayed by an attacker: the early requests of methods
+    /// which aren't idempotent are answered with `425 Too Early`, and the idempotent ones are flagged by
+    /// `SyncRequest::is_early_data`.
+    pub fn early_data(mut self) -> Self {
+        self.tls.early_data = true;
+        self
+    }
+
+    pub(crate) fn listen_addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub(crate) fn max_age(&self) -> Duration {
+        self.alt_svc_max_age
+    }
+}
+
+/// A running HTTP/3 listener, see `Server::spawn_http3`. Dropping it closes the listener.
+pub struct Http3Server {
+    addr: SocketAddr,
+    closer: Closer,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Http3Server {
+    /// Returns the UDP address the listener is bound to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `Alt-Svc` value advertising this listener
+    pub(crate) fn alt_svc(&self, max_age: Duration) -> String {
+        format!("h3=\":{}\"; ma={}", self.addr.port(), max_age.as_secs())
+    }
+
+    /// Close the listener and the connections in progress
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.closer.close();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Http3Server {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Bind the listener described by `config` and serve its requests with `process` on a thread of its own
+pub(crate) fn spawn<F>(config: &Http3Config, process: F) -> Result<Http3Server, ServerError>
+    where F: 'static + Fn(&mut SyncRequest) -> SyncResponse + Send + Sync {
+    let addr: SocketAddr = config.addr.parse()?;
+    let listener = Listener::bind_with(addr, &config.cert_chain_pem, &config.private_key_pem, config.tls)?;
+    let addr = listener.local_addr()?;
+    let closer = listener.closer();
+
+    let handle = thread::Builder::new().name("saphir-h3-listener".to_string()).spawn(move || {
+        listener.serve(move |request, connection| {
+            let response = match into_sync_request(request, connection) {
+                Some(mut request) => process(&mut request),
+                None => {
+                    let mut response = SyncResponse::new();
+                    response.status(StatusCode::BAD_REQUEST);
+                    response
+                }
+            };
+            from_sync_response(response)
+        })
+    })?;
+
+    Ok(Http3Server {
+        addr,
+        closer,
+        handle: Some(handle),
+    })
+}
+
+/// Convert an HTTP/3 request into the types of the `http` version saphir is built upon
+fn into_sync_request(request: h3_http::Request<Vec<u8>>, connection: &ConnectionInfo) -> Option<SyncRequest> {
+    let (parts, body) = request.into_parts();
+
+    let mut builder = Request::builder();
+    builder.method(Method::from_bytes(parts.method.as_str().as_bytes()).ok()?)
+        .uri(parts.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/"))
+        .version(Version::HTTP_2);
+
+    if !parts.headers.contains_key(h3_http::header::HOST) {
+        if let Some(authority) = parts.uri.authority() {
+            builder.header(header::HOST, authority.as_str());
+        }
+    }
+
+    for (name, value) in parts.headers.iter() {
+        builder.header(name.as_str(), value.as_bytes());
+    }
+
+    let (mut head, _) = builder.body(()).ok()?.into_parts();
+    head.extensions.insert(PeerAddr(connection.peer_addr));
+    head.extensions.insert(Http3Request);
+    head.extensions.insert(TlsInfo {
+        version: Some(TlsVersion::Tls13),
+        cipher_suite: None,
+        server_name: connection.server_name.clone(),
+        session_reused: None,
+        early_data: connection.early_data,
+    });
+
+    Some(SyncRequest::new(head, body))
+}
+
+/// Convert a response into the types of the `http` version the HTTP/3 listener is built upon
+fn from_sync_response(response: SyncResponse) -> h3_http::Response<Vec<u8>> {
+    let mut builder = h3_http::Response::builder().status(response.get_status().as_u16());
+
+    for (name, value) in response.headers_map().iter() {
+        // Connection specific headers are forbidden in HTTP/3
+        if *name == header::CONNECTION || *name == header::TRANSFER_ENCODING || name.as_str() == "keep-alive" {
+            continue;
+        }
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+
+    builder.body(response.get_body()).unwrap_or_else(|_| {
+        let mut response = h3_http::Response::new(Vec::new());
+        *response.status_mut() = h3_http::StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
@@ -0,0 +1,132 @@
+use http::*;
+use client_ip::SharedTrustedProxies;
+use middleware::Middleware;
+use rewrite::redirect;
+use rewrite::with_query;
+use security_headers::StrictTransportSecurity;
+use utils::RequestContinuation;
+use utils::ToRegex;
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Returns whether `req` was received over https, directly, or through a proxy trusted by the `TrustedProxies` of the
+/// server which tells so with `X-Forwarded-Proto`
+pub(crate) fn is_https(req: &SyncRequest) -> bool {
+    if req.uri().scheme_part().map(|s| s.as_str()) == Some("https") || req.is_secure() {
+        return true;
+    }
+
+    let trusted = match (req.peer_addr(), req.extensions().get::<SharedTrustedProxies>()) {
+        (Some(peer), Some(proxies)) => proxies.0.is_trusted(peer.ip()),
+        _ => false,
+    };
+
+    trusted && req.header_list("x-forwarded-proto").first().is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+/// A middleware redirecting the requests received over plain http to https, and telling the clients to keep using https
+/// with the `Strict-Transport-Security` header
+///
+/// Requests received over http are permanently redirected to the same url with the `https` scheme, on the port mapped to
+/// the one they were received on, or the default https port. A request is considered received over https when its uri
+/// says so, when it was received over TLS, see `SyncRequest::is_secure`, or when a proxy trusted by the `TrustedProxies`
+/// of the server tells so with `X-Forwarded-Proto`. Once paths are `require`d, the other ones are served over both
+/// schemes, like a public landing page next to the account pages.
+///
+/// The HSTS policy is only sent with responses to requests received over https, as browsers ignore it otherwise.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let https = HttpsMiddleware::new()
+///     .map_port(8080, 8443)
+///     .require("^/account")
+///     .require("^/admin")
+///     .hsts(StrictTransportSecurity::new(63_072_000).include_subdomains().preload());
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(https, vec!("/"), None);
+/// ```
+#[derive(Default)]
+pub struct HttpsMiddleware {
+    ports: HashMap<u16, u16>,
+    required: Vec<Regex>,
+    hsts: Option<header::HeaderValue>,
+}
+
+impl HttpsMiddleware {
+    /// Create a middleware redirecting every request received over http to the default https port, without HSTS
+    pub fn new() -> Self {
+        HttpsMiddleware::default()
+    }
+
+    /// Redirect the requests received on the http port `http` to the https port `https`
+    pub fn map_port(mut self, http: u16, https: u16) -> Self {
+        self.ports.insert(http, https);
+        self
+    }
+
+    /// Only redirect the requests whose path matches `pattern`, and the ones of the other `require`d patterns
+    pub fn require<R: ToRegex>(mut self, pattern: R) -> Self {
+        self.required.push(reg!(pattern));
+        self
+    }
+
+    /// Send `hsts` with the responses to the requests received over https
+    pub fn hsts(mut self, hsts: StrictTransportSecurity) -> Self {
+        self.hsts = header::HeaderValue::from_str(&hsts.to_header_value()).ok();
+        self
+    }
+
+    /// Returns the authority of the https url of a request addressed to `host`, mapping its port
+    fn https_authority(&self, host: &str) -> String {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !name.is_empty() && (!name.contains(':') || name.ends_with(']')) => (name, port.parse::<u16>().ok()),
+            _ => (host, None),
+        };
+
+        match port.and_then(|port| self.ports.get(&port)) {
+            Some(&port) if port != 443 => format!("{}:{}", name, port),
+            _ => name.to_string(),
+        }
+    }
+}
+
+impl Middleware for HttpsMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if is_https(req) {
+            return RequestContinuation::Next;
+        }
+
+        if !self.required.is_empty() && !self.required.iter().any(|pattern| pattern.is_match(req.uri().path())) {
+            return RequestContinuation::Next;
+        }
+
+        let host = req.headers_map().get(header::HOST).and_then(|h| h.to_str().ok()).map(|h| h.to_string())
+            .or_else(|| req.uri().authority_part().map(|a| a.as_str().to_string()));
+
+        match host {
+            Some(host) => {
+                let location = with_query(format!("https://{}{}", self.https_authority(&host), req.uri().path()), req.uri());
+                redirect(req, res, location, None);
+            }
+            None => {
+                res.status(StatusCode::BAD_REQUEST);
+            }
+        }
+        RequestContinuation::None
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if let Some(ref hsts) = self.hsts {
+            if is_https(req) {
+                res.headers_map_mut().insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use http::*;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many messages a subscription holds by default before dropping the oldest ones, see `Hub::capacity`
+pub const DEFAULT_SUBSCRIPTION_CAPACITY: usize = 64;
+
+/// How often a subscription streamed to a client checks whether the client is still there while no message comes
+const CLOSED_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A message published to a topic of a `Hub`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HubMessage<T> {
+    /// The topic the message was published to
+    pub topic: String,
+    /// The message itself
+    pub payload: T,
+}
+
+struct Queue<T> {
+    messages: VecDeque<HubMessage<T>>,
+    lagged: u64,
+}
+
+struct Subscriber<T> {
+    queue: Mutex<Queue<T>>,
+    ready: Condvar,
+}
+
+type Topics<T> = Arc<Mutex<HashMap<String, Vec<Weak<Subscriber<T>>>>>>;
+
+/// A topic based broadcast hub, fanning out the messages published by the application to the connections subscribed to
+/// their topic, like streamed responses
+///
+/// Every subscription holds up to `capacity` messages waiting to be received. Publishing never blocks: once a subscription
+/// is full, its oldest message is dropped to make room for the new one, and the subscription is told how many it missed
+/// by `Subscription::lagged`, so a slow client only ever slows itself down. Clones share the same topics, a clone is
+/// typically moved to the code publishing messages.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let hub = Hub::<String>::new();
+///
+/// let mut controller = BasicController::new(hub.clone());
+/// controller.add(Method::GET, "^/events$", |hub, _req, res| {
+///     let subscription = hub.subscribe(vec!["news", "weather"]);
+///     res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/event-stream")
+///         .stream_subscription(subscription, |message| {
+///             format!("event: {}\ndata: {}\n\n", message.topic, message.payload).into_bytes()
+///         });
+/// });
+///
+/// // Later on, from anywhere in the application
+/// hub.publish("news", "Saphir 1.0 is out".to_string());
+/// ```
+pub struct Hub<T> {
+    topics: Topics<T>,
+    capacity: usize,
+}
+
+impl<T> Clone for Hub<T> {
+    fn clone(&self) -> Self {
+        Hub {
+            topics: self.topics.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T: Clone> Default for Hub<T> {
+    fn default() -> Self {
+        Hub::new()
+    }
+}
+
+impl<T: Clone> Hub<T> {
+    /// Create a hub without any subscription
+    pub fn new() -> Self {
+        Hub {
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            capacity: DEFAULT_SUBSCRIPTION_CAPACITY,
+        }
+    }
+
+    /// Set how many messages the subscriptions created from now on hold before dropping the oldest ones, at least one.
+    /// Defaults to `DEFAULT_SUBSCRIPTION_CAPACITY`.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Subscribe to `topics`, the subscription receives the messages published from now on and is removed from the hub
+    /// when dropped
+    pub fn subscribe<I, S>(&self, topics: I) -> Subscription<T>
+        where I: IntoIterator<Item=S>,
+              S: Into<String> {
+        let subscription = Subscription {
+            subscriber: Arc::new(Subscriber {
+                queue: Mutex::new(Queue {
+                    messages: VecDeque::new(),
+                    lagged: 0,
+                }),
+                ready: Condvar::new(),
+            }),
+            hub: self.clone(),
+        };
+
+        for topic in topics {
+            subscription.subscribe(topic);
+        }
+
+        subscription
+    }
+
+    /// Publish `payload` to `topic`, returning the number of subscriptions it was delivered to
+    pub fn publish<S: Into<String>>(&self, topic: S, payload: T) -> usize {
+        let topic = topic.into();
+        let subscribers: Vec<Arc<Subscriber<T>>> = {
+            let mut topics = self.topics.lock().unwrap_or_else(|e| e.into_inner());
+            let subscribers = match topics.get_mut(&topic) {
+                Some(subscribers) => subscribers,
+                None => return 0,
+            };
+            subscribers.retain(|subscriber| subscriber.strong_count() > 0);
+            let live = subscribers.iter().filter_map(|subscriber| subscriber.upgrade()).collect();
+            if subscribers.is_empty() {
+                topics.remove(&topic);
+            }
+            live
+        };
+
+        for subscriber in &subscribers {
+            let mut queue = subscriber.queue.lock().unwrap_or_else(|e| e.into_inner());
+            if queue.messages.len() >= self.capacity {
+                queue.messages.pop_front();
+                queue.lagged += 1;
+            }
+            queue.messages.push_back(HubMessage {
+                topic: topic.clone(),
+                payload: payload.clone(),
+            });
+            subscriber.ready.notify_one();
+        }
+
+        subscribers.len()
+    }
+
+    /// Returns the number of subscriptions to `topic`
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.topics.lock().unwrap_or_else(|e| e.into_inner()).get(topic)
+            .map_or(0, |subscribers| subscribers.iter().filter(|subscriber| subscriber.strong_count() > 0).count())
+    }
+}
+
+/// The messages published to some topics of a `Hub`, returned by `Hub::subscribe`
+pub struct Subscription<T> {
+    subscriber: Arc<Subscriber<T>>,
+    hub: Hub<T>,
+}
+
+impl<T: Clone> Subscription<T> {
+    /// Receive the messages published to `topic` as well
+    pub fn subscribe<S: Into<String>>(&self, topic: S) {
+        let mut topics = self.hub.topics.lock().unwrap_or_else(|e| e.into_inner());
+        let subscribers = topics.entry(topic.into()).or_default();
+        if !subscribers.iter().any(|subscriber| subscriber.as_ptr() == Arc::as_ptr(&self.subscriber)) {
+            subscribers.push(Arc::downgrade(&self.subscriber));
+        }
+    }
+
+    /// Stop receiving the messages published to `topic`, the ones already received are kept
+    pub fn unsubscribe(&self, topic: &str) {
+        let mut topics = self.hub.topics.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(subscribers) = topics.get_mut(topic) {
+            subscribers.retain(|subscriber| subscriber.as_ptr() != Arc::as_ptr(&self.subscriber));
+            if subscribers.is_empty() {
+                topics.remove(topic);
+            }
+        }
+    }
+
+    /// Receive the next message, waiting for at most `timeout` for one to be published
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<HubMessage<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.subscriber.queue.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            if let Some(message) = queue.messages.pop_front() {
+                return Some(message);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            queue = self.subscriber.ready.wait_timeout(queue, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Receive the next message without waiting
+    pub fn try_recv(&self) -> Option<HubMessage<T>> {
+        self.subscriber.queue.lock().unwrap_or_else(|e| e.into_inner()).messages.pop_front()
+    }
+
+    /// Returns the number of messages dropped because the subscription was full since the last call
+    pub fn lagged(&self) -> u64 {
+        let mut queue = self.subscriber.queue.lock().unwrap_or_else(|e| e.into_inner());
+        let lagged = queue.lagged;
+        queue.lagged = 0;
+        lagged
+    }
+}
+
+impl SyncResponse {
+    /// Stream the messages received by `subscription` to the client, each formatted by `format`, like the events of a
+    /// `text/event-stream` body
+    ///
+    /// Messages are sent on a thread of their own, see `SyncResponse::stream`, until the client is gone, which is typically
+    /// noticed while sending it the next messages. A client reading slower than messages are published ends up missing the
+    /// oldest ones, see `Hub`.
+    pub fn stream_subscription<T, F>(&mut self, subscription: Subscription<T>, format: F) -> &mut SyncResponse
+        where T: 'static + Clone + Send,
+              F: 'static + Fn(&HubMessage<T>) -> Vec<u8> + Send {
+        let mut sender = self.stream();
+
+        let spawned = thread::Builder::new().name("saphir-hub".to_string()).spawn(move || {
+            loop {
+                match subscription.recv_timeout(CLOSED_POLL_INTERVAL) {
+                    Some(message) => if sender.send(format(&message)).is_err() {
+                        break;
+                    },
+                    None => if sender.is_closed() {
+                        break;
+                    },
+                }
+            }
+        });
+
+        if let Err(e) = spawned {
+            error!("Unable to stream a hub subscription: {}", e);
+            self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+        }
+
+        self
+    }
+}
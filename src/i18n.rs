@@ -0,0 +1,138 @@
+use http::*;
+use middleware::Middleware;
+use negotiation::parse_quality_list;
+use utils::RequestContinuation;
+
+/// Parse an `Accept-Language` header value into language ranges along with their quality, from the most to the least
+/// preferred. Ranges the client refuses, with a quality of 0, are left out.
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// let ranges = parse_accept_language("fr-CA, fr;q=0.9, en;q=0.8, de;q=0");
+/// assert_eq!(ranges, vec![("fr-CA".to_string(), 1.0), ("fr".to_string(), 0.9), ("en".to_string(), 0.8)]);
+/// ```
+pub fn parse_accept_language(value: &str) -> Vec<(String, f32)> {
+    parse_quality_list(value).into_iter()
+        .filter(|&(ref range, quality)| quality > 0.0 && !range.contains(';'))
+        .collect()
+}
+
+/// Pick the tag of `supported` best matching the language ranges of `accept_language`, as parsed by
+/// `parse_accept_language`
+///
+/// Ranges are tried from the most preferred one. A range matches the tag it is equal to, ignoring case, or the tags it is a
+/// prefix of, `en` matching `en-US`. When no tag matches a range, it is truncated from its end until one does, `en-US`
+/// falling back to `en`. The wildcard `*` matches the first supported tag.
+pub fn negotiate_language<'a>(accept_language: &str, supported: &[&'a str]) -> Option<&'a str> {
+    for (range, _) in parse_accept_language(accept_language) {
+        if range == "*" {
+            return supported.first().cloned();
+        }
+
+        let prefixed = supported.iter().find(|tag| {
+            tag.len() > range.len() && tag[..range.len()].eq_ignore_ascii_case(&range) && tag.as_bytes()[range.len()] == b'-'
+        });
+
+        if let Some(tag) = supported.iter().find(|tag| tag.eq_ignore_ascii_case(&range)).or(prefixed) {
+            return Some(tag);
+        }
+
+        let mut truncated = range.as_str();
+        while let Some(end) = truncated.rfind('-') {
+            truncated = &truncated[..end];
+            if let Some(tag) = supported.iter().find(|tag| tag.eq_ignore_ascii_case(truncated)) {
+                return Some(tag);
+            }
+        }
+    }
+
+    None
+}
+
+/// The locale selected for a request by `LocaleMiddleware`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(pub String);
+
+impl SyncRequest {
+    /// Pick the tag of `supported` preferred by the client according to the `Accept-Language` header of the request, see
+    /// `negotiate_language`. Returns `None` when the header is missing or when no supported tag is acceptable.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let greeting = match req.preferred_language(&["en", "fr"]) {
+    ///         Some("fr") => "Bonjour",
+    ///         _ => "Hello",
+    ///     };
+    ///     res.status(StatusCode::OK).body(greeting);
+    /// }
+    /// ```
+    pub fn preferred_language<'a>(&self, supported: &[&'a str]) -> Option<&'a str> {
+        let accept_language = self.header_list(header::ACCEPT_LANGUAGE).join(",");
+        negotiate_language(&accept_language, supported)
+    }
+
+    /// Returns the locale selected by `LocaleMiddleware`
+    pub fn locale(&self) -> Option<&str> {
+        self.extensions().get::<Locale>().map(|locale| locale.0.as_str())
+    }
+}
+
+/// A middleware selecting the locale of every request amongst the supported ones, according to its `Accept-Language` header
+///
+/// The selected locale is stored in the request extensions, see `SyncRequest::locale`. It is also made available to
+/// templates: `SyncResponse::render` adds it as the `locale` member of object contexts which don't already have one. The
+/// responses are given a `Content-Language` header unless they already have one, and a `Vary: Accept-Language` header.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(LocaleMiddleware::new(vec!["en", "fr", "fr-CA"], "en"), vec!("/"), None);
+/// ```
+pub struct LocaleMiddleware {
+    supported: Vec<String>,
+    default: String,
+}
+
+impl LocaleMiddleware {
+    /// Create a middleware choosing amongst the `supported` locales, selecting `default` when none is acceptable
+    pub fn new<S: Into<String>>(supported: Vec<&str>, default: S) -> Self {
+        LocaleMiddleware {
+            supported: supported.into_iter().map(str::to_string).collect(),
+            default: default.into(),
+        }
+    }
+}
+
+impl Middleware for LocaleMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let locale = {
+            let supported: Vec<&str> = self.supported.iter().map(String::as_str).collect();
+            req.preferred_language(&supported).unwrap_or(&self.default).to_string()
+        };
+
+        req.extensions_mut().insert(Locale(locale.clone()));
+        res.extension(Locale(locale));
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if let Some(locale) = req.locale() {
+            if !res.headers_map().contains_key(header::CONTENT_LANGUAGE) {
+                res.header(header::CONTENT_LANGUAGE, locale);
+            }
+        }
+
+        res.header(header::VARY, "Accept-Language");
+    }
+}
@@ -0,0 +1,278 @@
+use http::*;
+use middleware::Middleware;
+use problem::Problem;
+use response_cache::CachedResponse;
+use utils::RequestContinuation;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// What an `IdempotencyStore` knows about a key
+#[derive(Clone, Debug)]
+pub struct IdempotencyEntry {
+    /// Fingerprint of the request which first used the key, see `IdempotencyMiddleware`
+    pub fingerprint: u64,
+    /// The response to the first request, `None` while it is being processed
+    pub response: Option<CachedResponse>,
+}
+
+/// A trait representing a storage backend for the responses of the `IdempotencyMiddleware`, implement it to share keys
+/// between multiple instances using Redis or any other key-value store.
+///
+/// Reservations must be atomic: when concurrent requests reserve the same key, only one of them may succeed.
+pub trait IdempotencyStore: Send + Sync {
+    /// Reserve `key` for the request with `fingerprint`, returns `None` if the key was free, or what is known about the
+    /// request which already reserved it
+    fn reserve(&self, key: &str, fingerprint: u64) -> Option<IdempotencyEntry>;
+
+    /// Store the response to the request which reserved `key`, keeping it for `response.max_age`
+    fn complete(&self, key: &str, response: CachedResponse);
+
+    /// Free `key` without storing a response, letting the next request with the key be processed
+    fn release(&self, key: &str);
+}
+
+/// An in-memory `IdempotencyStore`, expired responses and stale reservations are purged as new keys are reserved
+pub struct MemoryIdempotencyStore {
+    entries: Mutex<HashMap<String, (IdempotencyEntry, SystemTime)>>,
+    reservation_timeout: Duration,
+}
+
+impl MemoryIdempotencyStore {
+    /// Create an empty store, whose reservations expire after 5 minutes without a response
+    pub fn new() -> Self {
+        MemoryIdempotencyStore {
+            entries: Mutex::new(HashMap::new()),
+            reservation_timeout: Duration::from_secs(300),
+        }
+    }
+
+    /// Free the keys whose request didn't complete within `timeout`, in case the process handling it never released them
+    pub fn reservation_timeout(mut self, timeout: Duration) -> Self {
+        self.reservation_timeout = timeout;
+        self
+    }
+}
+
+impl Default for MemoryIdempotencyStore {
+    fn default() -> Self {
+        MemoryIdempotencyStore::new()
+    }
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    fn reserve(&self, key: &str, fingerprint: u64) -> Option<IdempotencyEntry> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let timeout = self.reservation_timeout;
+        entries.retain(|_, (entry, reserved_at)| match entry.response {
+            Some(ref response) => response.is_fresh(),
+            None => reserved_at.elapsed().map(|elapsed| elapsed < timeout).unwrap_or(true),
+        });
+
+        if let Some((entry, _)) = entries.get(key) {
+            return Some(entry.clone());
+        }
+
+        entries.insert(key.to_string(), (IdempotencyEntry {
+            fingerprint,
+            response: None,
+        }, SystemTime::now()));
+        None
+    }
+
+    fn complete(&self, key: &str, response: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((entry, _)) = entries.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    fn release(&self, key: &str) {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+    }
+}
+
+/// A key reserved by a request, released when dropped before a response is stored, for instance when the handler panics
+struct Reservation {
+    store: Arc<dyn IdempotencyStore>,
+    key: Option<String>,
+}
+
+impl Reservation {
+    fn complete(mut self, response: CachedResponse) {
+        if let Some(key) = self.key.take() {
+            self.store.complete(&key, response);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.store.release(&key);
+        }
+    }
+}
+
+/// The keys reserved by the request of a response, one per `IdempotencyMiddleware` it went through
+struct Reservations(Vec<Reservation>);
+
+/// A middleware implementing the `Idempotency-Key` pattern, letting clients safely retry non-idempotent requests
+///
+/// The first request carrying a key is processed and its response is stored. Retries with the same key are answered with the
+/// stored response, marked with an `Idempotent-Replayed: true` header, without reaching the handler. While the first request
+/// is being processed, duplicates are answered with `409 Conflict`; a key reused for a different request, as told by its
+/// method, path and body, is answered with `422 Unprocessable Entity`.
+///
+/// Server errors and streamed responses are not stored, retrying the request processes it again, and neither are panics: the
+/// key is released when the handler panics. Requests without the header are processed
+/// normally, unless the key is `required`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// use std::time::Duration;
+///
+/// let idempotency = IdempotencyMiddleware::new(MemoryIdempotencyStore::new())
+///     .retention(Duration::from_secs(3600));
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(idempotency, vec!("^/payments"), None);
+/// ```
+pub struct IdempotencyMiddleware {
+    store: Arc<dyn IdempotencyStore>,
+    header: header::HeaderName,
+    methods: Vec<Method>,
+    retention: Duration,
+    required: bool,
+}
+
+impl IdempotencyMiddleware {
+    /// Create a middleware backed by `store`, applying to `POST` and `PATCH` requests and keeping responses for 24 hours
+    pub fn new<S: 'static + IdempotencyStore>(store: S) -> Self {
+        IdempotencyMiddleware {
+            store: Arc::new(store),
+            header: header::HeaderName::from_static("idempotency-key"),
+            methods: vec![Method::POST, Method::PATCH],
+            retention: Duration::from_secs(24 * 3600),
+            required: false,
+        }
+    }
+
+    /// Read the key from the header `name` rather than `Idempotency-Key`
+    pub fn header(mut self, name: header::HeaderName) -> Self {
+        self.header = name;
+        self
+    }
+
+    /// Set the methods the middleware applies to
+    pub fn methods(mut self, methods: Vec<Method>) -> Self {
+        self.methods = methods;
+        self
+    }
+
+    /// Set for how long responses are kept
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Answer requests without a key with `400 Bad Request`
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn fingerprint(req: &SyncRequest) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        req.method().hash(&mut hasher);
+        req.uri().path_and_query().map(|p| p.as_str()).unwrap_or_else(|| req.uri().path()).hash(&mut hasher);
+        req.body().hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl Middleware for IdempotencyMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if !self.methods.contains(req.method()) {
+            return RequestContinuation::Next;
+        }
+
+        let key = match req.headers_map().get(&self.header).and_then(|key| key.to_str().ok()) {
+            Some(key) if !key.trim().is_empty() => key.trim().to_string(),
+            _ if self.required => {
+                res.problem(&Problem::new(StatusCode::BAD_REQUEST).with_detail(format!("The {} header is required", self.header)));
+                return RequestContinuation::None;
+            }
+            _ => return RequestContinuation::Next,
+        };
+
+        let fingerprint = Self::fingerprint(req);
+
+        match self.store.reserve(&key, fingerprint) {
+            None => {
+                let reservation = Reservation { store: self.store.clone(), key: Some(key) };
+                match res.get_extensions_mut().get_mut::<Reservations>() {
+                    Some(reservations) => reservations.0.push(reservation),
+                    None => { res.extension(Reservations(vec![reservation])); }
+                }
+                RequestContinuation::Next
+            }
+            Some(IdempotencyEntry { response: None, .. }) => {
+                res.problem(&Problem::new(StatusCode::CONFLICT)
+                    .with_detail("A request with the same idempotency key is being processed"));
+                RequestContinuation::None
+            }
+            Some(ref entry) if entry.fingerprint != fingerprint => {
+                res.problem(&Problem::new(StatusCode::UNPROCESSABLE_ENTITY)
+                    .with_detail("The idempotency key was already used for a different request"));
+                RequestContinuation::None
+            }
+            Some(IdempotencyEntry { response: Some(cached), .. }) => {
+                res.status(cached.status);
+                for name in cached.headers.keys() {
+                    res.headers_map_mut().remove(name);
+                }
+                for (name, value) in cached.headers.iter() {
+                    res.headers_map_mut().append(name.clone(), value.clone());
+                }
+                res.header("idempotent-replayed", "true");
+                res.body(cached.body);
+                RequestContinuation::None
+            }
+        }
+    }
+
+    fn after(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+        let reservation = match res.get_extensions_mut().get_mut::<Reservations>() {
+            Some(reservations) => match reservations.0.iter().position(|r| Arc::ptr_eq(&r.store, &self.store)) {
+                Some(position) => reservations.0.remove(position),
+                None => return,
+            },
+            None => return,
+        };
+
+        // Dropping the reservation releases the key
+        if res.get_status().is_server_error() || res.is_streamed() {
+            return;
+        }
+
+        reservation.complete(CachedResponse {
+            status: res.get_status(),
+            headers: res.headers_map().clone(),
+            body: res.get_body(),
+            stored_at: SystemTime::now(),
+            max_age: self.retention,
+        });
+    }
+}
@@ -0,0 +1,124 @@
+use http::*;
+use arc_swap::ArcSwap;
+use client_ip::IpNetwork;
+use client_ip::IpNetworkError;
+use controller::RequestGuard;
+use utils::RequestContinuation;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// The ranges of an `IpFilterGuard`
+#[derive(Debug, Clone, Default)]
+struct IpRules {
+    allowed: Vec<IpNetwork>,
+    denied: Vec<IpNetwork>,
+}
+
+impl IpRules {
+    fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.denied.iter().any(|network| network.contains(ip))
+                    && (self.allowed.is_empty() || self.allowed.iter().any(|network| network.contains(ip)))
+            }
+            None => self.allowed.is_empty(),
+        }
+    }
+}
+
+/// A guard rejecting clients by address with `403 Forbidden`, before the handler sees the request
+///
+/// Clients are identified by `SyncRequest::client_ip`, so the guard agrees with the trusted proxies of the server. A client
+/// belonging to a denied range is always rejected; once an allowed range is set, clients outside of every allowed range are
+/// rejected as well.
+///
+/// An `IpFilterGuard` is a cheap handle which can be cloned: ranges can be added or removed through a clone while the server
+/// is running, every guard sharing the same ranges sees the change on its next request.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let filter = IpFilterGuard::new()
+///     .allow("10.0.0.0/8").unwrap()
+///     .deny("10.0.13.0/24").unwrap();
+///
+/// let mut guards = RequestGuardCollection::new();
+/// guards.add(filter.clone());
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::GET, "^/admin$", guards, |_, _, res| { res.status(StatusCode::OK); });
+///
+/// // Later on, while the server is running
+/// filter.add_denied("10.0.42.7".parse().unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub struct IpFilterGuard {
+    rules: Arc<ArcSwap<IpRules>>,
+    mutation: Arc<Mutex<()>>,
+}
+
+impl IpFilterGuard {
+    /// Create a guard without any range, letting every client through
+    pub fn new() -> Self {
+        IpFilterGuard::default()
+    }
+
+    /// Only let the clients of `network`, written in CIDR notation, and of the other allowed ranges through
+    pub fn allow(self, network: &str) -> Result<Self, IpNetworkError> {
+        self.add_allowed(network.parse()?);
+        Ok(self)
+    }
+
+    /// Reject the clients of `network`, written in CIDR notation
+    pub fn deny(self, network: &str) -> Result<Self, IpNetworkError> {
+        self.add_denied(network.parse()?);
+        Ok(self)
+    }
+
+    /// Add an allowed range
+    pub fn add_allowed(&self, network: IpNetwork) {
+        self.mutate(|rules| rules.allowed.push(network));
+    }
+
+    /// Add a denied range
+    pub fn add_denied(&self, network: IpNetwork) {
+        self.mutate(|rules| rules.denied.push(network));
+    }
+
+    /// Remove `network` from the allowed and denied ranges, returns whether it was found
+    pub fn remove(&self, network: &IpNetwork) -> bool {
+        let mut removed = false;
+        self.mutate(|rules| {
+            let count = rules.allowed.len() + rules.denied.len();
+            rules.allowed.retain(|n| n != network);
+            rules.denied.retain(|n| n != network);
+            removed = rules.allowed.len() + rules.denied.len() != count;
+        });
+        removed
+    }
+
+    /// Whether the client `ip` would be let through
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.rules.load().is_allowed(Some(ip))
+    }
+
+    fn mutate<F: FnOnce(&mut IpRules)>(&self, mutation: F) {
+        let _guard = self.mutation.lock().unwrap_or_else(|e| e.into_inner());
+        let mut rules = IpRules::clone(&self.rules.load());
+        mutation(&mut rules);
+        self.rules.store(Arc::new(rules));
+    }
+}
+
+impl RequestGuard for IpFilterGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if self.rules.load().is_allowed(req.client_ip()) {
+            RequestContinuation::Next
+        } else {
+            res.status(StatusCode::FORBIDDEN);
+            RequestContinuation::None
+        }
+    }
+}
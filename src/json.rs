@@ -0,0 +1,44 @@
+use http::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use profiling::Phase;
+use profiling::profiled;
+
+impl SyncRequest {
+    /// Deserialize the body of the request from JSON
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # fn handler(req: &SyncRequest) {
+    /// let values: Vec<u32> = req.body_json().unwrap();
+    /// # }
+    /// ```
+    pub fn body_json<T: DeserializeOwned>(&self) -> Result<T, ::serde_json::Error> {
+        ::serde_json::from_slice(self.body())
+    }
+}
+
+impl SyncResponse {
+    /// Serialize `value` as JSON and set it as the body of the response, along with the `application/json` content type.
+    ///
+    /// If the value cannot be serialized, the server answers with a `500 Internal Server Error`, see `SerializationError`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut response = SyncResponse::new();
+    /// response.json(&vec![1, 2, 3]);
+    /// let response = response.build_response().unwrap();
+    /// ```
+    pub fn json<T: Serialize>(&mut self, value: &T) -> &mut SyncResponse {
+        match profiled(self, || Phase::Serialization, |_| ::serde_json::to_vec(value)) {
+            Ok(body) => {
+                self.header(header::CONTENT_TYPE, "application/json").body(body)
+            }
+            Err(e) => self.serialization_failed("json", e),
+        }
+    }
+}
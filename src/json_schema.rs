@@ -0,0 +1,426 @@
+use http::*;
+use controller::RequestGuard;
+use utils::RequestContinuation;
+use validation::ValidationErrors;
+use validation::ValidationRejection;
+use query::percent_decode;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Keywords whose values are instances rather than subschemas, and must not be searched for patterns
+const LITERAL_KEYWORDS: &[&str] = &["enum", "const", "default", "examples"];
+
+/// An error raised while loading a JSON Schema document
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The document is not valid JSON
+    Parse(::serde_json::Error),
+    /// The document is neither an object nor a boolean schema
+    InvalidSchema,
+    /// A `pattern` or `patternProperties` regular expression is invalid
+    InvalidPattern(String, ::regex::Error),
+}
+
+impl From<io::Error> for SchemaError {
+    fn from(e: io::Error) -> Self {
+        SchemaError::Io(e)
+    }
+}
+
+impl From<::serde_json::Error> for SchemaError {
+    fn from(e: ::serde_json::Error) -> Self {
+        SchemaError::Parse(e)
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::Io(e) => write!(f, "unable to read the schema: {}", e),
+            SchemaError::Parse(e) => write!(f, "unable to parse the schema: {}", e),
+            SchemaError::InvalidSchema => write!(f, "the schema must be an object or a boolean"),
+            SchemaError::InvalidPattern(pattern, e) => write!(f, "invalid pattern {}: {}", pattern, e),
+        }
+    }
+}
+
+impl ::std::error::Error for SchemaError {}
+
+fn escape_pointer(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_type(instance: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => instance.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => instance.is_number(),
+        _ => type_name(instance) == expected,
+    }
+}
+
+/// A JSON Schema document, compiled once and used to validate any number of instances
+///
+/// The validation keywords of drafts 6 to 2020-12 are supported: `type`, `enum`, `const`, the numeric, string, array and
+/// object constraints, the `allOf`, `anyOf`, `oneOf`, `not` and `if`/`then`/`else` combinators, and `$ref` to the
+/// document itself, like `#/definitions/address`. Annotations, including `format`, are ignored.
+pub struct JsonSchema {
+    root: Value,
+    patterns: HashMap<String, Regex>,
+}
+
+impl JsonSchema {
+    /// Compile `schema`, failing if it is malformed or holds an invalid pattern
+    pub fn new(schema: Value) -> Result<Self, SchemaError> {
+        if !schema.is_object() && !schema.is_boolean() {
+            return Err(SchemaError::InvalidSchema);
+        }
+
+        let mut patterns = HashMap::new();
+        Self::compile_patterns(&schema, &mut patterns)?;
+
+        Ok(JsonSchema {
+            root: schema,
+            patterns,
+        })
+    }
+
+    /// Load and compile the schema stored in the JSON file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SchemaError> {
+        let content = fs::read(path)?;
+        Self::new(::serde_json::from_slice(&content)?)
+    }
+
+    fn compile_patterns(schema: &Value, patterns: &mut HashMap<String, Regex>) -> Result<(), SchemaError> {
+        let mut compile = |pattern: &str| -> Result<(), SchemaError> {
+            if !patterns.contains_key(pattern) {
+                let regex = Regex::new(pattern).map_err(|e| SchemaError::InvalidPattern(pattern.to_string(), e))?;
+                patterns.insert(pattern.to_string(), regex);
+            }
+            Ok(())
+        };
+
+        match schema {
+            Value::Object(keywords) => {
+                if let Some(pattern) = keywords.get("pattern").and_then(Value::as_str) {
+                    compile(pattern)?;
+                }
+                if let Some(properties) = keywords.get("patternProperties").and_then(Value::as_object) {
+                    for pattern in properties.keys() {
+                        compile(pattern)?;
+                    }
+                }
+
+                for (keyword, value) in keywords {
+                    if !LITERAL_KEYWORDS.contains(&keyword.as_str()) {
+                        Self::compile_patterns(value, patterns)?;
+                    }
+                }
+            }
+            Value::Array(schemas) => {
+                for schema in schemas {
+                    Self::compile_patterns(schema, patterns)?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Validate `instance`, reporting every failure with the JSON pointer of the invalid value as its field and the
+    /// failed keyword as its code
+    pub fn validate(&self, instance: &Value) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        self.check(&self.root, instance, "", &mut errors);
+        errors.into_result()
+    }
+
+    fn is_valid(&self, schema: &Value, instance: &Value, pointer: &str) -> bool {
+        let mut errors = ValidationErrors::new();
+        self.check(schema, instance, pointer, &mut errors);
+        errors.is_empty()
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        if !reference.starts_with('#') {
+            return None;
+        }
+
+        let pointer = percent_decode(&reference[1..]);
+        if pointer.is_empty() {
+            Some(&self.root)
+        } else {
+            self.root.pointer(&pointer)
+        }
+    }
+
+    fn check(&self, schema: &Value, instance: &Value, pointer: &str, errors: &mut ValidationErrors) {
+        let keywords = match schema {
+            Value::Bool(true) => return,
+            Value::Object(keywords) => keywords,
+            _ => {
+                errors.add(pointer, "false", "no value is allowed");
+                return;
+            }
+        };
+        let keyword = |name: &str| keywords.get(name);
+
+        if let Some(reference) = keyword("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(target, instance, pointer, errors),
+                None => { errors.add(pointer, "$ref", format!("unable to resolve {}", reference)); }
+            }
+        }
+
+        if let Some(expected) = keyword("type") {
+            let valid = match expected {
+                Value::String(expected) => is_type(instance, expected),
+                Value::Array(expected) => expected.iter().filter_map(Value::as_str).any(|t| is_type(instance, t)),
+                _ => true,
+            };
+            if !valid {
+                errors.add(pointer, "type", format!("must be of type {}, not {}", expected, type_name(instance)));
+            }
+        }
+
+        if let Some(allowed) = keyword("enum").and_then(Value::as_array) {
+            errors.check(pointer, allowed.contains(instance), "enum", format!("must be one of {}", Value::Array(allowed.clone())));
+        }
+
+        if let Some(expected) = keyword("const") {
+            errors.check(pointer, instance == expected, "const", format!("must be {}", expected));
+        }
+
+        match instance {
+            Value::Number(number) => self.check_number(number.as_f64().unwrap_or(0.0), keywords, pointer, errors),
+            Value::String(string) => self.check_string(string, keywords, pointer, errors),
+            Value::Array(items) => self.check_array(items, keywords, pointer, errors),
+            Value::Object(members) => self.check_object(members, keywords, pointer, errors),
+            _ => {}
+        }
+
+        if let Some(schemas) = keyword("allOf").and_then(Value::as_array) {
+            for schema in schemas {
+                self.check(schema, instance, pointer, errors);
+            }
+        }
+
+        if let Some(schemas) = keyword("anyOf").and_then(Value::as_array) {
+            let valid = schemas.iter().any(|schema| self.is_valid(schema, instance, pointer));
+            errors.check(pointer, valid, "anyOf", "must match at least one of the anyOf schemas");
+        }
+
+        if let Some(schemas) = keyword("oneOf").and_then(Value::as_array) {
+            let matches = schemas.iter().filter(|schema| self.is_valid(schema, instance, pointer)).count();
+            errors.check(pointer, matches == 1, "oneOf", format!("must match exactly one of the oneOf schemas, matched {}", matches));
+        }
+
+        if let Some(schema) = keyword("not") {
+            errors.check(pointer, !self.is_valid(schema, instance, pointer), "not", "must not match the not schema");
+        }
+
+        if let Some(condition) = keyword("if") {
+            let branch = if self.is_valid(condition, instance, pointer) { keyword("then") } else { keyword("else") };
+            if let Some(schema) = branch {
+                self.check(schema, instance, pointer, errors);
+            }
+        }
+    }
+
+    fn check_number(&self, number: f64, keywords: &::serde_json::Map<String, Value>, pointer: &str, errors: &mut ValidationErrors) {
+        let limit = |name: &str| keywords.get(name).and_then(Value::as_f64);
+
+        if let Some(minimum) = limit("minimum") {
+            errors.check(pointer, number >= minimum, "minimum", format!("must be at least {}", minimum));
+        }
+        if let Some(maximum) = limit("maximum") {
+            errors.check(pointer, number <= maximum, "maximum", format!("must be at most {}", maximum));
+        }
+        if let Some(minimum) = limit("exclusiveMinimum") {
+            errors.check(pointer, number > minimum, "exclusiveMinimum", format!("must be greater than {}", minimum));
+        }
+        if let Some(maximum) = limit("exclusiveMaximum") {
+            errors.check(pointer, number < maximum, "exclusiveMaximum", format!("must be less than {}", maximum));
+        }
+        if let Some(divisor) = limit("multipleOf").filter(|d| *d > 0.0) {
+            let quotient = number / divisor;
+            errors.check(pointer, (quotient - quotient.round()).abs() < 1e-9, "multipleOf", format!("must be a multiple of {}", divisor));
+        }
+    }
+
+    fn check_string(&self, string: &str, keywords: &::serde_json::Map<String, Value>, pointer: &str, errors: &mut ValidationErrors) {
+        let length = string.chars().count() as u64;
+
+        if let Some(min) = keywords.get("minLength").and_then(Value::as_u64) {
+            errors.check(pointer, length >= min, "minLength", format!("must be at least {} characters long", min));
+        }
+        if let Some(max) = keywords.get("maxLength").and_then(Value::as_u64) {
+            errors.check(pointer, length <= max, "maxLength", format!("must be at most {} characters long", max));
+        }
+        if let Some(regex) = keywords.get("pattern").and_then(Value::as_str).and_then(|p| self.patterns.get(p)) {
+            errors.check(pointer, regex.is_match(string), "pattern", format!("must match {}", regex.as_str()));
+        }
+    }
+
+    fn check_array(&self, items: &[Value], keywords: &::serde_json::Map<String, Value>, pointer: &str, errors: &mut ValidationErrors) {
+        let item_pointer = |i: usize| format!("{}/{}", pointer, i);
+
+        // `prefixItems` (2020-12) and the array form of `items` (earlier drafts) validate the items by position
+        let (prefix, rest) = match (keywords.get("prefixItems"), keywords.get("items")) {
+            (Some(Value::Array(prefix)), rest) => (prefix.as_slice(), rest),
+            (_, Some(Value::Array(prefix))) => (prefix.as_slice(), keywords.get("additionalItems")),
+            (_, rest) => (&[][..], rest),
+        };
+
+        for (i, item) in items.iter().enumerate() {
+            if let Some(schema) = prefix.get(i).or(if i >= prefix.len() { rest } else { None }) {
+                self.check(schema, item, &item_pointer(i), errors);
+            }
+        }
+
+        if let Some(min) = keywords.get("minItems").and_then(Value::as_u64) {
+            errors.check(pointer, items.len() as u64 >= min, "minItems", format!("must contain at least {} items", min));
+        }
+        if let Some(max) = keywords.get("maxItems").and_then(Value::as_u64) {
+            errors.check(pointer, items.len() as u64 <= max, "maxItems", format!("must contain at most {} items", max));
+        }
+        if keywords.get("uniqueItems").and_then(Value::as_bool).unwrap_or(false) {
+            let unique = items.iter().enumerate().all(|(i, item)| !items[..i].contains(item));
+            errors.check(pointer, unique, "uniqueItems", "must not contain duplicate items");
+        }
+        if let Some(schema) = keywords.get("contains") {
+            let valid = items.iter().enumerate().any(|(i, item)| self.is_valid(schema, item, &item_pointer(i)));
+            errors.check(pointer, valid, "contains", "must contain an item matching the contains schema");
+        }
+    }
+
+    fn check_object(&self, members: &::serde_json::Map<String, Value>, keywords: &::serde_json::Map<String, Value>, pointer: &str, errors: &mut ValidationErrors) {
+        let properties = keywords.get("properties").and_then(Value::as_object);
+        let pattern_properties = keywords.get("patternProperties").and_then(Value::as_object);
+
+        if let Some(required) = keywords.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !members.contains_key(name) {
+                    errors.add(format!("{}/{}", pointer, escape_pointer(name)), "required", "is required");
+                }
+            }
+        }
+
+        for (name, value) in members {
+            let member_pointer = format!("{}/{}", pointer, escape_pointer(name));
+            let mut matched = false;
+
+            if let Some(schema) = properties.and_then(|p| p.get(name)) {
+                matched = true;
+                self.check(schema, value, &member_pointer, errors);
+            }
+
+            for (pattern, schema) in pattern_properties.into_iter().flat_map(|p| p.iter()) {
+                if self.patterns.get(pattern).is_some_and(|regex| regex.is_match(name)) {
+                    matched = true;
+                    self.check(schema, value, &member_pointer, errors);
+                }
+            }
+
+            if let Some(schema) = keywords.get("propertyNames") {
+                self.check(schema, &Value::String(name.clone()), &member_pointer, errors);
+            }
+
+            if !matched {
+                match keywords.get("additionalProperties") {
+                    Some(Value::Bool(false)) => { errors.add(member_pointer, "additionalProperties", "is not allowed"); }
+                    Some(schema) => self.check(schema, value, &member_pointer, errors),
+                    None => {}
+                }
+            }
+        }
+
+        if let Some(min) = keywords.get("minProperties").and_then(Value::as_u64) {
+            errors.check(pointer, members.len() as u64 >= min, "minProperties", format!("must contain at least {} properties", min));
+        }
+        if let Some(max) = keywords.get("maxProperties").and_then(Value::as_u64) {
+            errors.check(pointer, members.len() as u64 <= max, "maxProperties", format!("must contain at most {} properties", max));
+        }
+    }
+}
+
+/// RequestGuard validating JSON request bodies against a `JsonSchema`
+///
+/// A body which is not valid JSON is rejected with `400 Bad Request`, a body breaking the schema with
+/// `422 Unprocessable Entity`. In both cases the response lists the errors, as described by `ValidationRejection`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[macro_use] extern crate serde_json;
+/// # extern crate saphir;
+/// # use saphir::*;
+/// # fn main() {
+/// let schema = JsonSchema::new(json!({
+///     "type": "object",
+///     "properties": { "name": { "type": "string", "minLength": 1 } },
+///     "required": ["name"]
+/// })).unwrap();
+///
+/// let mut guards = RequestGuardCollection::new();
+/// guards.add(JsonSchemaGuard::new(schema));
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::POST, "^/users$", guards, |_, _, res| { res.status(StatusCode::CREATED); });
+/// # }
+/// ```
+pub struct JsonSchemaGuard {
+    schema: JsonSchema,
+}
+
+impl JsonSchemaGuard {
+    /// Create a guard validating bodies against `schema`
+    pub fn new(schema: JsonSchema) -> Self {
+        JsonSchemaGuard {
+            schema,
+        }
+    }
+
+    /// Create a guard validating bodies against the schema stored in the JSON file at `path`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SchemaError> {
+        JsonSchema::from_file(path).map(Self::new)
+    }
+}
+
+impl RequestGuard for JsonSchemaGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let instance: Value = match req.body_json() {
+            Ok(instance) => instance,
+            Err(e) => {
+                res.rejection(&ValidationRejection::Malformed(e.to_string()));
+                return RequestContinuation::None;
+            }
+        };
+
+        match self.schema.validate(&instance) {
+            Ok(()) => RequestContinuation::Next,
+            Err(errors) => {
+                res.rejection(&ValidationRejection::Invalid(errors));
+                RequestContinuation::None
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use http::*;
+use server::Server;
+use query::percent_encode;
+use futures::Future;
+use futures::Stream;
+use futures::sync::oneshot;
+use hyper::Client;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use tokio::runtime::Runtime;
+
+/// An error raised by the AWS Lambda adapter
+#[derive(Debug)]
+pub enum LambdaError {
+    /// The event is not a valid API Gateway or Application Load Balancer event
+    InvalidEvent(String),
+    /// The Lambda runtime API couldn't be reached, or answered unexpectedly
+    Runtime(String),
+}
+
+impl fmt::Display for LambdaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LambdaError::InvalidEvent(ref e) => write!(f, "invalid lambda event: {}", e),
+            LambdaError::Runtime(ref e) => write!(f, "lambda runtime error: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for LambdaError {}
+
+/// The source of a Lambda event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LambdaEventSource {
+    /// An API Gateway REST API, payload format 1.0
+    ApiGatewayV1,
+    /// An API Gateway HTTP API, payload format 2.0
+    ApiGatewayV2,
+    /// An Application Load Balancer target group
+    ApplicationLoadBalancer,
+}
+
+/// How the response to a Lambda event has to be encoded, as returned by `lambda_request`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LambdaResponseFormat {
+    /// The source of the event
+    pub source: LambdaEventSource,
+    /// Whether the headers are expected as lists of values, which Application Load Balancers expect when the target group
+    /// has multi value headers enabled
+    pub multi_value_headers: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawEvent {
+    version: Option<String>,
+    raw_path: Option<String>,
+    raw_query_string: Option<String>,
+    cookies: Option<Vec<String>>,
+    http_method: Option<String>,
+    path: Option<String>,
+    headers: Option<HashMap<String, String>>,
+    multi_value_headers: Option<HashMap<String, Vec<String>>>,
+    query_string_parameters: Option<HashMap<String, String>>,
+    multi_value_query_string_parameters: Option<HashMap<String, Vec<String>>>,
+    request_context: Option<Value>,
+    body: Option<String>,
+    #[serde(default)]
+    is_base64_encoded: bool,
+}
+
+fn invalid<E: fmt::Display>(e: E) -> LambdaError {
+    LambdaError::InvalidEvent(e.to_string())
+}
+
+/// Rebuild a query string from the parameters of a 1.0 payload, whose values are decoded by API Gateway but not by load
+/// balancers
+fn query_string(event: &RawEvent, encode: bool) -> String {
+    let encode = |value: &str| if encode { percent_encode(value) } else { value.to_string() };
+    let mut pairs: Vec<(String, String)> = match (&event.multi_value_query_string_parameters, &event.query_string_parameters) {
+        (Some(params), _) if !params.is_empty() => params.iter()
+            .flat_map(|(name, values)| values.iter().map(move |value| (name.clone(), value.clone())))
+            .collect(),
+        (_, Some(params)) => params.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+        _ => Vec::new(),
+    };
+
+    // Parameters come in a map, sort them so the same event always gives the same uri
+    pairs.sort();
+    pairs.iter().map(|(name, value)| format!("{}={}", encode(name), encode(value))).collect::<Vec<_>>().join("&")
+}
+
+fn source_ip(context: Option<&Value>, pointer: &str) -> Option<IpAddr> {
+    context.and_then(|c| c.pointer(pointer)).and_then(|ip| ip.as_str()).and_then(|ip| ip.parse().ok())
+}
+
+/// Translate an API Gateway (payload format 1.0 or 2.0) or Application Load Balancer event into the request it carries,
+/// along with the format its response has to be encoded with by `lambda_response`
+///
+/// The source IP address reported by the event is available through `SyncRequest::peer_addr`, with a port of 0.
+pub fn lambda_request(event: &[u8]) -> Result<(SyncRequest, LambdaResponseFormat), LambdaError> {
+    let event: RawEvent = ::serde_json::from_slice(event).map_err(invalid)?;
+    let context = event.request_context.as_ref();
+
+    let source = if event.version.as_deref() == Some("2.0") {
+        LambdaEventSource::ApiGatewayV2
+    } else if context.and_then(|c| c.get("elb")).is_some() {
+        LambdaEventSource::ApplicationLoadBalancer
+    } else {
+        LambdaEventSource::ApiGatewayV1
+    };
+
+    let mut builder = Request::builder();
+    let mut peer_ip = None;
+
+    let (method, path, query) = match source {
+        LambdaEventSource::ApiGatewayV2 => {
+            peer_ip = source_ip(context, "/http/sourceIp");
+            let method = context.and_then(|c| c.pointer("/http/method")).and_then(|m| m.as_str()).unwrap_or("GET").to_string();
+            (method, event.raw_path.clone().unwrap_or_else(|| "/".to_string()), event.raw_query_string.clone().unwrap_or_default())
+        }
+        LambdaEventSource::ApiGatewayV1 | LambdaEventSource::ApplicationLoadBalancer => {
+            if source == LambdaEventSource::ApiGatewayV1 {
+                peer_ip = source_ip(context, "/identity/sourceIp");
+            }
+            let query = query_string(&event, source == LambdaEventSource::ApiGatewayV1);
+            (event.http_method.clone().unwrap_or_else(|| "GET".to_string()), event.path.clone().unwrap_or_else(|| "/".to_string()), query)
+        }
+    };
+
+    let uri = if query.is_empty() { path } else { format!("{}?{}", path, query) };
+    builder.method(method.as_str()).uri(uri.as_str());
+
+    match (&event.multi_value_headers, &event.headers) {
+        (Some(headers), _) if !headers.is_empty() => {
+            for (name, values) in headers {
+                for value in values {
+                    builder.header(name.as_str(), value.as_str());
+                }
+            }
+        }
+        (_, Some(headers)) => {
+            for (name, value) in headers {
+                builder.header(name.as_str(), value.as_str());
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(ref cookies) = event.cookies {
+        if !cookies.is_empty() {
+            builder.header(header::COOKIE, cookies.join("; ").as_str());
+        }
+    }
+
+    let body = match event.body {
+        Some(ref body) if event.is_base64_encoded => ::base64::decode(body).map_err(invalid)?,
+        Some(ref body) => body.clone().into_bytes(),
+        None => Vec::new(),
+    };
+
+    let (parts, _) = builder.body(()).map_err(invalid)?.into_parts();
+    let mut request = SyncRequest::new(parts, body);
+
+    // Load balancers don't report the client address, only the X-Forwarded-For header does
+    let peer_ip = peer_ip.or_else(|| {
+        request.headers_map().get("x-forwarded-for").and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next()).and_then(|ip| ip.trim().parse().ok())
+    });
+
+    if let Some(ip) = peer_ip {
+        request.extensions_mut().insert(PeerAddr(SocketAddr::new(ip, 0)));
+    }
+
+    let format = LambdaResponseFormat {
+        source,
+        multi_value_headers: source == LambdaEventSource::ApiGatewayV1
+            || (source == LambdaEventSource::ApplicationLoadBalancer && event.multi_value_headers.is_some()),
+    };
+
+    Ok((request, format))
+}
+
+/// Encode `response` as the result of a Lambda invocation, in the format expected by the source of the event
+pub fn lambda_response(response: &SyncResponse, format: LambdaResponseFormat) -> Vec<u8> {
+    let status = response.get_status();
+    let body = response.get_body();
+    let (body, is_base64_encoded) = match String::from_utf8(body) {
+        Ok(text) => (text, false),
+        Err(e) => (::base64::encode(e.as_bytes()), true),
+    };
+
+    let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut cookies = Vec::new();
+
+    for (name, value) in response.headers_map() {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+
+        if format.source == LambdaEventSource::ApiGatewayV2 && *name == header::SET_COOKIE {
+            cookies.push(value);
+        } else {
+            headers.entry(name.as_str().to_string()).or_default().push(value);
+        }
+    }
+
+    let mut result = json!({
+        "statusCode": status.as_u16(),
+        "body": body,
+        "isBase64Encoded": is_base64_encoded,
+    });
+
+    if format.multi_value_headers {
+        result["multiValueHeaders"] = json!(headers);
+    } else {
+        let joined: BTreeMap<String, String> = headers.into_iter().map(|(name, values)| (name, values.join(","))).collect();
+        result["headers"] = json!(joined);
+    }
+
+    if format.source == LambdaEventSource::ApiGatewayV2 {
+        result["cookies"] = json!(cookies);
+    }
+
+    if format.source == LambdaEventSource::ApplicationLoadBalancer {
+        result["statusDescription"] = json!(format!("{} {}", status.as_u16(), status.canonical_reason().unwrap_or("")));
+    }
+
+    ::serde_json::to_vec(&result).unwrap_or_default()
+}
+
+/// Process a Lambda event through the middlewares and the router of `server`, returning the encoded response
+pub fn handle_lambda_event(server: &Server, event: &[u8]) -> Result<Vec<u8>, LambdaError> {
+    let (mut request, format) = lambda_request(event)?;
+    let response = server.process(&mut request);
+    Ok(lambda_response(&response, format))
+}
+
+/// Serve the invocations of the current AWS Lambda function with `server`, through the Lambda runtime API, until the runtime
+/// API fails
+///
+/// The address of the runtime API is read from the `AWS_LAMBDA_RUNTIME_API` environment variable, set by Lambda. Events
+/// which can't be translated into a request are reported as invocation errors.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let server = Server::builder().router(Router::new()).build();
+/// run_lambda(&server).unwrap();
+/// ```
+pub fn run_lambda(server: &Server) -> Result<(), LambdaError> {
+    let runtime_api = ::std::env::var("AWS_LAMBDA_RUNTIME_API").map_err(|e| LambdaError::Runtime(format!("AWS_LAMBDA_RUNTIME_API: {}", e)))?;
+    let base = format!("http://{}/2018-06-01/runtime/invocation", runtime_api);
+
+    let runtime = Runtime::new().map_err(|e| LambdaError::Runtime(e.to_string()))?;
+    let client = Client::builder().executor(runtime.executor()).build_http::<Body>();
+
+    let send = |request: Request<Body>| -> Result<(Response<()>, Vec<u8>), LambdaError> {
+        let response = client.request(request).and_then(|res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(move |body| (Response::from_parts(parts, ()), body.to_vec()))
+        });
+
+        oneshot::spawn(response, &runtime.executor()).wait().map_err(|e| LambdaError::Runtime(e.to_string()))
+    };
+
+    loop {
+        let next = Request::get(format!("{}/next", base).as_str()).body(Body::empty()).map_err(|e| LambdaError::Runtime(e.to_string()))?;
+        let (invocation, event) = send(next)?;
+
+        let request_id = invocation.headers().get("lambda-runtime-aws-request-id").and_then(|id| id.to_str().ok())
+            .ok_or_else(|| LambdaError::Runtime("the invocation has no request id".to_string()))?
+            .to_string();
+
+        let result = match handle_lambda_event(server, &event) {
+            Ok(response) => Request::post(format!("{}/{}/response", base, request_id).as_str()).body(Body::from(response)),
+            Err(e) => {
+                warn!("Unable to handle the lambda invocation {}: {}", request_id, e);
+                let error = json!({ "errorMessage": e.to_string(), "errorType": "InvalidEvent" });
+                Request::post(format!("{}/{}/error", base, request_id).as_str()).body(Body::from(error.to_string()))
+            }
+        };
+
+        let (posted, _) = send(result.map_err(|e| LambdaError::Runtime(e.to_string()))?)?;
+        if !posted.status().is_success() {
+            return Err(LambdaError::Runtime(format!("the result of {} was refused with {}", request_id, posted.status())));
+        }
+    }
+}
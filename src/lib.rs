@@ -14,9 +14,69 @@
 #[macro_use]
 extern crate log;
 extern crate futures;
+extern crate bytes;
 extern crate ansi_term;
 extern crate http as http_types;
 extern crate hyperx;
+extern crate arc_swap;
+extern crate tokio;
+extern crate futures_cpupool;
+extern crate socket2;
+extern crate getrandom;
+extern crate encoding_rs;
+extern crate sha2;
+extern crate hmac;
+extern crate subtle;
+extern crate base64;
+extern crate core_affinity;
+#[cfg(unix)]
+extern crate signal_hook;
+#[macro_use]
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "xml")]
+extern crate serde_xml_rs;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "cbor")]
+extern crate serde_cbor;
+#[cfg(feature = "protobuf")]
+extern crate prost;
+#[cfg(feature = "tera")]
+extern crate tera;
+#[cfg(feature = "handlebars")]
+extern crate handlebars;
+#[cfg(feature = "macro")]
+extern crate saphir_macro;
+#[cfg(feature = "openapi")]
+extern crate schemars;
+#[cfg(feature = "oauth2")]
+extern crate hyper_rustls;
+#[cfg(feature = "graphql")]
+extern crate juniper;
+#[cfg(feature = "webdav")]
+extern crate xml as xml_rs;
+#[cfg(feature = "geoip")]
+extern crate maxminddb;
+#[cfg(feature = "password")]
+extern crate argon2;
+#[cfg(feature = "password")]
+extern crate bcrypt;
+#[cfg(feature = "http3")]
+extern crate saphir_h3;
+#[cfg(feature = "toml")]
+extern crate toml;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
+#[cfg(feature = "rust-embed")]
+extern crate rust_embed;
+#[cfg(feature = "csv")]
+extern crate csv;
+#[cfg(feature = "tower")]
+extern crate tower_service;
 pub extern crate regex;
 pub extern crate hyper;
 
@@ -25,21 +85,536 @@ mod utils;
 mod http;
 mod error;
 mod middleware;
+mod predicate;
 mod controller;
+mod async_guard;
+mod guard_cache;
 mod router;
+mod route_index;
+mod host_routing;
+mod dynamic_router;
+mod split;
+mod feature_flags;
+mod body_parser;
+mod malformed;
+mod cancellation;
+mod config_reload;
+mod server_config;
 mod server;
+mod handover;
+mod health;
+mod template;
+mod state;
+mod container;
+mod security_headers;
+mod response_cache;
+mod proxy_cache;
+mod idempotency;
+mod coalescing;
+mod circuit_breaker;
+mod route_policy;
+mod concurrency_limit;
+mod rate_limit;
+mod maintenance;
+mod long_poll;
+mod hub;
+mod proxy;
+mod rewrite;
+mod method_override;
+mod path;
+mod query;
+mod date;
+mod buffer_pool;
+mod request_body;
+mod temp_upload;
+pub mod test;
+mod recording;
+mod logging;
+mod task;
+mod blocking;
+mod drain;
+mod fairness;
+mod cron;
+mod scheduler;
+mod fastcgi;
+mod etag;
+mod json;
+mod ndjson;
+mod negotiation;
+mod i18n;
+mod typed_headers;
+mod problem;
+mod pagination;
+mod sitemap;
+mod route_metadata;
+mod serialization;
+mod preload;
+mod tenancy;
+mod body_transform;
+mod response_size;
+mod response_builder;
+mod transaction;
+mod deprecation;
+mod versioning;
+mod charset;
+mod https;
+mod trailers;
+mod streaming;
+mod validation;
+mod json_schema;
+mod client_ip;
+mod ip_filter;
+mod bot_guard;
+mod signed_request;
+mod webhook_signature;
+mod webhook;
+mod audit;
+mod credentials;
+mod flash;
+mod csrf;
+mod form;
+mod metrics;
+mod profiling;
+mod data_usage;
+mod tls;
+mod body_limits;
+mod profile;
+mod error_page;
+mod debug;
+mod admin;
+mod lifecycle;
+mod assets;
+mod asset_source;
+mod range;
+mod attachment;
+#[cfg(feature = "xml")]
+mod xml;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+#[cfg(feature = "csv")]
+mod csv_response;
+#[cfg(feature = "cbor")]
+mod cbor;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+#[cfg(feature = "openapi")]
+mod openapi;
+#[cfg(feature = "lambda")]
+mod lambda;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc-web")]
+mod grpc_web;
+#[cfg(feature = "webdav")]
+mod webdav;
+#[cfg(feature = "tus")]
+mod tus;
+#[cfg(feature = "geoip")]
+mod geoip;
+#[cfg(feature = "oauth2")]
+mod oauth2;
+#[cfg(feature = "password")]
+mod password;
+#[cfg(feature = "http3")]
+mod http3;
+#[cfg(feature = "client")]
+mod client;
 
 pub use utils::*;
 pub use http::*;
 pub use utils::RequestContinuation;
 pub use middleware::Middleware;
 pub use middleware::MiddlewareStack;
+pub use middleware::MiddlewareInfo;
+pub use middleware::Conditional;
+pub use predicate::RequestPredicate;
 pub use controller::Controller;
 pub use controller::BasicController;
 pub use controller::ControllerDispatch;
 pub use controller::RequestGuard;
 pub use controller::RequestGuardCollection;
+pub use controller::RouteAlias;
+pub use sitemap::Sitemap;
+pub use sitemap::SitemapEntry;
+pub use sitemap::SitemapController;
+pub use sitemap::ChangeFrequency;
+pub use route_metadata::RouteMetadata;
+pub use route_metadata::ExpectedResponse;
 pub use controller::BodyGuard;
+pub use controller::ContextFactory;
+pub use async_guard::AsyncRequestGuard;
+pub use async_guard::GuardFuture;
+pub use async_guard::GuardRejection;
+pub use async_guard::SyncGuard;
+pub use async_guard::AwaitGuard;
+pub use guard_cache::CachedGuard;
+pub use credentials::constant_time_eq;
+pub use credentials::constant_time_str_eq;
+pub use credentials::hmac_sha256;
+pub use flash::FlashLevel;
+pub use flash::FlashMessage;
+pub use flash::FlashMiddleware;
+pub use csrf::CsrfMiddleware;
+pub use form::html_escape;
+pub use metrics::Metrics;
+pub use metrics::MetricsController;
+pub use metrics::MetricsMiddleware;
+pub use metrics::DEFAULT_BUCKETS;
+pub use profiling::Profiler;
+pub use profiling::Phase;
+pub use profiling::RouteProfile;
+pub use profiling::PhaseStats;
+pub use profiling::DEFAULT_PROFILE_WINDOW;
+pub use data_usage::DataUsageMiddleware;
+pub use data_usage::ConnectionUsage;
+pub use tls::TlsInfo;
+pub use tls::TlsVersion;
+pub use tls::TlsGuard;
+pub use tls::TLS_VERSION_HEADER;
+pub use tls::TLS_CIPHER_HEADER;
+pub use tls::TLS_SERVER_NAME_HEADER;
+pub use tls::TLS_SESSION_REUSED_HEADER;
+pub use tls::EARLY_DATA_HEADER;
+pub use body_limits::BodyLimit;
+pub use body_limits::BodyLimits;
+pub use profile::Profile;
+pub use profile::PROFILE_ENV_VAR;
+pub use profile::PRODUCTION_CACHE_MAX_AGE;
+pub use error_page::DeveloperError;
+pub use debug::DebugController;
+pub use debug::RequestSummary;
+pub use debug::DEFAULT_DEBUG_CAPACITY;
+pub use admin::AdminController;
+pub use assets::AssetManifest;
+pub use assets::AssetController;
+pub use assets::IMMUTABLE_CACHE_CONTROL;
+pub use assets::NO_INDEX_MARKER;
+pub use asset_source::AssetSource;
+pub use asset_source::AssetMetadata;
+pub use asset_source::DirectorySource;
+pub use asset_source::MemoryAssets;
+#[cfg(feature = "rust-embed")]
+pub use asset_source::EmbeddedAssets;
+pub use range::parse_byte_ranges;
+pub use range::RangeError;
+pub use range::MAX_RANGES;
+pub use attachment::Attachment;
+pub use attachment::content_disposition;
+pub use controller::RouteInfo;
+pub use controller::RouteConflictPolicy;
 pub use router::Router;
+pub use router::TrailingSlashPolicy;
+pub use path::normalize_path;
+pub use path::PathError;
+pub use query::form_decode;
+pub use query::percent_decode;
+pub use query::percent_encode;
+pub use query::query_pairs;
+pub use query::from_query;
+pub use date::http_date;
+pub use buffer_pool::BufferPoolStats;
+pub use request_body::BodyReader;
+pub use temp_upload::TempUploads;
+pub use temp_upload::TempUpload;
+pub use temp_upload::UploadError;
+pub use recording::RecordingMiddleware;
+pub use recording::RecordedExchange;
+pub use recording::RecordedRequest;
+pub use recording::RecordedResponse;
+pub use recording::RecordedBody;
+pub use recording::ReplayMismatch;
+pub use recording::REDACTED;
+pub use recording::load_recording;
+pub use recording::replay;
+pub use logging::LogFormat;
+pub use logging::set_log_format;
+pub use logging::log_format;
+pub use logging::SERVER_LOG_TARGET;
+pub use logging::ROUTING_LOG_TARGET;
+pub use logging::GUARD_LOG_TARGET;
+pub use logging::HANDLER_LOG_TARGET;
+pub use logging::ACCESS_LOG_TARGET;
+pub use logging::WEBHOOK_LOG_TARGET;
+pub use task::task_queue;
+pub use task::TaskSender;
+pub use task::TaskQueue;
+pub use blocking::BlockingPool;
+pub use blocking::BlockingError;
+pub use drain::DrainReport;
+pub use fairness::Fairness;
+pub use cron::CronSchedule;
+pub use cron::CronError;
+pub use scheduler::Scheduler;
+pub use scheduler::SchedulerHandle;
+pub use scheduler::Schedule;
+pub use scheduler::Job;
+pub use scheduler::JobFuture;
+pub use scheduler::JobStatus;
+pub use dynamic_router::DynamicRouter;
+pub use split::SplitController;
+pub use split::SplitVariant;
+pub use feature_flags::FeatureFlagProvider;
+pub use feature_flags::StaticFeatureFlags;
+pub use feature_flags::FeatureFlagGuard;
+pub use feature_flags::FEATURE_ENV_PREFIX;
+pub use body_parser::BodyDecoder;
+pub use body_parser::BodyParseError;
+pub use malformed::MalformedRequestPolicy;
+pub use malformed::MalformedRequest;
+pub use malformed::MalformedRequestKind;
+pub use cancellation::CancellationToken;
+pub use preload::PreloadMiddleware;
+pub use tenancy::Tenant;
+pub use tenancy::TenantResolver;
+pub use tenancy::TenantMiddleware;
+pub use tenancy::SubdomainTenant;
+pub use tenancy::HeaderTenant;
+pub use tenancy::PathPrefixTenant;
+pub use body_transform::BodyTransform;
+pub use body_transform::BodyTransformer;
+pub use body_transform::BodyTransformMiddleware;
+pub use response_size::RESPONSE_SIZE_BUCKETS;
+pub use response_builder::ResponseBuilder;
+pub use response_builder::ResponseBuildError;
+pub use transaction::Transaction;
+pub use transaction::TransactionPool;
+pub use transaction::TransactionGuard;
+pub use transaction::TransactionMiddleware;
+pub use deprecation::Deprecation;
+pub use versioning::ApiVersioning;
+pub use charset::TextError;
+pub use https::HttpsMiddleware;
 pub use server::Server;
-pub use error::ServerError;
\ No newline at end of file
+pub use server::ServerBuilder;
+pub use server::SaphirService;
+pub use lifecycle::LifecycleHook;
+pub use lifecycle::LifecycleFuture;
+pub use error::ServerError;
+#[cfg(feature = "macro")]
+pub use saphir_macro::controller;
+pub use state::StateMap;
+pub use state::State;
+pub use container::Container;
+pub use container::FromContainer;
+pub use config_reload::ConfigReloader;
+pub use config_reload::Reloadable;
+pub use config_reload::ConfigError;
+pub use config_reload::ReloadTrigger;
+pub use server_config::ServerConfig;
+pub use server_config::LimitsConfig;
+pub use server_config::LimitConfig;
+pub use server_config::LogConfig;
+pub use server_config::Http3Section;
+pub use server_config::EffectiveConfig;
+pub use template::TemplateEngine;
+pub use template::TemplateError;
+#[cfg(feature = "tera")]
+pub use template::TeraEngine;
+#[cfg(feature = "handlebars")]
+pub use template::HandlebarsEngine;
+pub use health::HealthController;
+pub use health::HealthCheck;
+pub use health::HealthCheckFuture;
+pub use security_headers::SecurityHeadersMiddleware;
+pub use security_headers::StrictTransportSecurity;
+pub use security_headers::ContentSecurityPolicy;
+pub use security_headers::FrameOptions;
+pub use security_headers::ReferrerPolicy;
+pub use response_cache::ResponseCacheMiddleware;
+pub use response_cache::CacheStore;
+pub use response_cache::CachedResponse;
+pub use response_cache::MemoryCacheStore;
+pub use proxy_cache::ProxyCache;
+pub use idempotency::IdempotencyMiddleware;
+pub use idempotency::IdempotencyStore;
+pub use idempotency::IdempotencyEntry;
+pub use idempotency::MemoryIdempotencyStore;
+pub use coalescing::CoalescingMiddleware;
+pub use circuit_breaker::CircuitBreakerMiddleware;
+pub use circuit_breaker::CircuitState;
+pub use route_policy::RoutePolicy;
+pub use concurrency_limit::ConcurrencyLimitMiddleware;
+pub use rate_limit::RateLimit;
+pub use rate_limit::RateLimitKey;
+pub use rate_limit::RateLimitMiddleware;
+pub use maintenance::MaintenanceMode;
+pub use long_poll::LongPoll;
+pub use hub::Hub;
+pub use hub::HubMessage;
+pub use hub::Subscription;
+pub use hub::DEFAULT_SUBSCRIPTION_CAPACITY;
+pub use proxy::ProxyController;
+pub use proxy::Upstream;
+pub use proxy::LoadBalancing;
+pub use rewrite::RewriteMiddleware;
+pub use rewrite::TrailingSlash;
+pub use method_override::MethodOverrideMiddleware;
+pub use etag::ConditionalGetMiddleware;
+pub use etag::strong_etag;
+pub use etag::weak_etag;
+pub use etag::is_not_modified;
+pub use etag::preconditions_hold;
+pub use etag::Versioned;
+pub use negotiation::parse_quality_list;
+pub use negotiation::negotiable_media_types;
+pub use i18n::parse_accept_language;
+pub use i18n::negotiate_language;
+pub use i18n::Locale;
+pub use i18n::LocaleMiddleware;
+pub use problem::Problem;
+pub use problem::PROBLEM_CONTENT_TYPE;
+pub use pagination::Pagination;
+pub use pagination::PageRequest;
+pub use pagination::Page;
+pub use pagination::PageRejection;
+pub use serialization::SerializationError;
+pub use trailers::ResponseBody;
+pub use streaming::BodySender;
+pub use streaming::StreamClosed;
+pub use streaming::STREAM_CAPACITY;
+pub use ndjson::NdjsonItems;
+pub use ndjson::NDJSON_CONTENT_TYPE;
+pub use validation::Validate;
+pub use validation::Validated;
+pub use validation::ValidationErrors;
+pub use validation::ValidationRejection;
+pub use validation::FieldError;
+pub use json_schema::JsonSchema;
+pub use json_schema::JsonSchemaGuard;
+pub use json_schema::SchemaError;
+pub use client_ip::IpNetwork;
+pub use client_ip::IpNetworkError;
+pub use client_ip::TrustedProxies;
+pub use client_ip::ForwardedHeader;
+pub use ip_filter::IpFilterGuard;
+pub use bot_guard::BotGuard;
+pub use bot_guard::BotScorer;
+pub use signed_request::SignedRequestGuard;
+pub use signed_request::NonceStore;
+pub use signed_request::MemoryNonceStore;
+pub use webhook_signature::WebhookSignatureGuard;
+pub use webhook_signature::SignatureEncoding;
+pub use webhook::WebhookDispatcher;
+pub use webhook::WebhookHandle;
+pub use webhook::WebhookDelivery;
+pub use webhook::WebhookStore;
+pub use webhook::MemoryWebhookStore;
+pub use webhook::DirectoryWebhookStore;
+pub use audit::AuditEvent;
+pub use audit::AuditOutcome;
+pub use audit::AuditRecord;
+pub use audit::AuditSink;
+pub use audit::AuditLog;
+pub use audit::FileAuditSink;
+pub use audit::SyslogAuditSink;
+pub use audit::HttpAuditSink;
+#[cfg(feature = "protobuf")]
+pub use protobuf::Protobuf;
+#[cfg(feature = "protobuf")]
+pub use protobuf::ProtobufError;
+#[cfg(feature = "protobuf")]
+pub use protobuf::DEFAULT_PROTOBUF_LIMIT;
+#[cfg(feature = "openapi")]
+pub use openapi::OpenApi;
+#[cfg(feature = "openapi")]
+pub use openapi::RouteDoc;
+#[cfg(feature = "openapi")]
+pub use openapi::OpenApiController;
+#[cfg(feature = "lambda")]
+pub use lambda::LambdaError;
+#[cfg(feature = "lambda")]
+pub use lambda::LambdaEventSource;
+#[cfg(feature = "lambda")]
+pub use lambda::LambdaResponseFormat;
+#[cfg(feature = "lambda")]
+pub use lambda::lambda_request;
+#[cfg(feature = "lambda")]
+pub use lambda::lambda_response;
+#[cfg(feature = "lambda")]
+pub use lambda::handle_lambda_event;
+#[cfg(feature = "lambda")]
+pub use lambda::run_lambda;
+#[cfg(feature = "graphql")]
+pub use graphql::GraphQLController;
+#[cfg(feature = "graphql")]
+pub use graphql::GraphQLRequest;
+#[cfg(feature = "graphql")]
+pub use graphql::GraphQLExecutor;
+#[cfg(feature = "graphql")]
+pub use graphql::graphql_error;
+#[cfg(feature = "graphql")]
+pub use graphql::JuniperSchema;
+#[cfg(feature = "grpc-web")]
+pub use grpc_web::GrpcWebController;
+#[cfg(feature = "grpc-web")]
+pub use grpc_web::GrpcHandler;
+#[cfg(feature = "grpc-web")]
+pub use grpc_web::GrpcStatus;
+#[cfg(feature = "grpc-web")]
+pub use grpc_web::GrpcCode;
+#[cfg(feature = "webdav")]
+pub use webdav::WebDavController;
+#[cfg(feature = "webdav")]
+pub use webdav::DavFileSystem;
+#[cfg(feature = "webdav")]
+pub use webdav::DavMetadata;
+#[cfg(feature = "webdav")]
+pub use webdav::LocalFileSystem;
+#[cfg(feature = "tus")]
+pub use tus::TusController;
+#[cfg(feature = "tus")]
+pub use tus::TusUpload;
+#[cfg(feature = "tus")]
+pub use tus::TusStorage;
+#[cfg(feature = "tus")]
+pub use tus::MemoryTusStorage;
+#[cfg(feature = "tus")]
+pub use tus::FileTusStorage;
+#[cfg(feature = "geoip")]
+pub use geoip::GeoIpMiddleware;
+#[cfg(feature = "geoip")]
+pub use geoip::GeoInfo;
+#[cfg(feature = "geoip")]
+pub use geoip::GeoLookup;
+#[cfg(feature = "geoip")]
+pub use geoip::MaxMindDatabase;
+#[cfg(feature = "geoip")]
+pub use geoip::GeoIpError;
+#[cfg(feature = "oauth2")]
+pub use oauth2::OAuth2Client;
+#[cfg(feature = "oauth2")]
+pub use oauth2::OAuth2Config;
+#[cfg(feature = "oauth2")]
+pub use oauth2::OAuth2Controller;
+#[cfg(feature = "oauth2")]
+pub use oauth2::OAuth2Guard;
+#[cfg(feature = "oauth2")]
+pub use oauth2::OAuth2Error;
+#[cfg(feature = "oauth2")]
+pub use oauth2::Identity;
+#[cfg(feature = "oauth2")]
+pub use oauth2::TokenResponse;
+#[cfg(feature = "oauth2")]
+pub use oauth2::TokenExchanger;
+#[cfg(feature = "oauth2")]
+pub use oauth2::HttpTokenExchanger;
+#[cfg(feature = "password")]
+pub use password::hash_password;
+#[cfg(feature = "password")]
+pub use password::verify_password;
+#[cfg(feature = "password")]
+pub use password::password_needs_rehash;
+#[cfg(feature = "password")]
+pub use password::PasswordAlgorithm;
+#[cfg(feature = "password")]
+pub use password::PasswordError;
+#[cfg(feature = "password")]
+pub use password::DEFAULT_BCRYPT_COST;
+#[cfg(feature = "http3")]
+pub use http3::Http3Config;
+#[cfg(feature = "http3")]
+pub use http3::Http3Server;
+#[cfg(feature = "client")]
+pub use client::HttpClient;
+#[cfg(feature = "client")]
+pub use client::ClientError;
@@ -0,0 +1,252 @@
+use http::*;
+use logging::*;
+use log::Level;
+use futures::Future;
+use futures::IntoFuture;
+use futures::future::join_all;
+use tokio::runtime::Runtime;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// Future returned by a lifecycle hook, resolving to `Ok(())` once the hook completed
+pub type LifecycleFuture = Box<dyn Future<Item=(), Error=String> + Send>;
+
+/// A trait representing a task run when the server starts or stops, like connecting to a database or flushing a cache, see
+/// `ServerBuilder::on_startup` and `ServerBuilder::on_shutdown`
+///
+/// Any `Fn() -> R` where `R` is a `Result<(), String>` or a future resolving to `()` implements this trait.
+pub trait LifecycleHook: Send + Sync {
+    /// Start the task. The returned future is awaited along with the other hooks ready to run.
+    fn run(&self) -> LifecycleFuture;
+}
+
+impl<F, R> LifecycleHook for F
+    where F: Fn() -> R + Send + Sync,
+          R: IntoFuture<Item=(), Error=String>,
+          R::Future: 'static + Send {
+    fn run(&self) -> LifecycleFuture {
+        Box::new(self().into_future())
+    }
+}
+
+/// A hook along with its name and the hooks it runs after
+pub(crate) struct NamedHook {
+    name: String,
+    after: Vec<String>,
+    hook: Box<dyn LifecycleHook>,
+}
+
+/// How far a warm-up task is
+#[derive(Debug, Clone, PartialEq)]
+enum WarmUpStatus {
+    Pending,
+    Done,
+    Failed(String),
+}
+
+/// The progress of the warm-up tasks, inserted in the extensions of every request for the readiness probe
+#[derive(Clone)]
+pub(crate) struct WarmUpState(Arc<Mutex<Vec<(String, WarmUpStatus)>>>);
+
+impl WarmUpState {
+    fn set(&self, name: &str, status: WarmUpStatus) {
+        let mut tasks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(task) = tasks.iter_mut().find(|(task, _)| task == name) {
+            task.1 = status;
+        }
+    }
+
+    /// Returns the tasks which didn't complete, along with why
+    fn unfinished(&self) -> Vec<(String, String)> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).iter().filter_map(|(name, status)| match *status {
+            WarmUpStatus::Pending => Some((name.clone(), "warming up".to_string())),
+            WarmUpStatus::Done => None,
+            WarmUpStatus::Failed(ref e) => Some((name.clone(), e.clone())),
+        }).collect()
+    }
+}
+
+impl SyncRequest {
+    /// Returns the warm-up tasks which didn't complete when the request was received, along with why: still running or
+    /// failed. See `ServerBuilder::warm_up`.
+    pub fn unfinished_warm_up(&self) -> Vec<(String, String)> {
+        self.extensions().get::<WarmUpState>().map(|state| state.unfinished()).unwrap_or_default()
+    }
+}
+
+/// The hooks run when the server starts and stops
+#[derive(Default)]
+pub(crate) struct LifecycleHooks {
+    startup: Vec<NamedHook>,
+    shutdown: Vec<NamedHook>,
+    warm_up: Mutex<Vec<NamedHook>>,
+}
+
+impl LifecycleHooks {
+    pub(crate) fn add_startup(&mut self, name: &str, after: &[&str], hook: Box<dyn LifecycleHook>) {
+        self.startup.push(NamedHook { name: name.to_string(), after: after.iter().map(|name| name.to_string()).collect(), hook });
+    }
+
+    pub(crate) fn add_shutdown(&mut self, name: &str, after: &[&str], hook: Box<dyn LifecycleHook>) {
+        self.shutdown.push(NamedHook { name: name.to_string(), after: after.iter().map(|name| name.to_string()).collect(), hook });
+    }
+
+    pub(crate) fn add_warm_up(&mut self, name: &str, after: &[&str], hook: Box<dyn LifecycleHook>) {
+        let warm_up = self.warm_up.get_mut().unwrap_or_else(|e| e.into_inner());
+        warm_up.push(NamedHook { name: name.to_string(), after: after.iter().map(|name| name.to_string()).collect(), hook });
+    }
+
+    /// Returns the progress of the warm-up tasks, all pending, `None` when there isn't any
+    pub(crate) fn warm_up_state(&mut self) -> Option<WarmUpState> {
+        let warm_up = self.warm_up.get_mut().unwrap_or_else(|e| e.into_inner());
+        if warm_up.is_empty() {
+            return None;
+        }
+        Some(WarmUpState(Arc::new(Mutex::new(warm_up.iter().map(|hook| (hook.name.clone(), WarmUpStatus::Pending)).collect()))))
+    }
+
+    /// Run the startup hooks, returning why the server can't start when one of them fails, or when the startup or shutdown
+    /// hooks can't be ordered
+    pub(crate) fn startup(&self) -> Result<(), String> {
+        waves(&self.shutdown, "shutdown")?;
+        waves(&self.warm_up.lock().unwrap_or_else(|e| e.into_inner()), "warm-up")?;
+        if self.startup.is_empty() {
+            return Ok(());
+        }
+
+        let mut runtime = Runtime::new().map_err(|e| format!("unable to start the runtime of the startup hooks: {}", e))?;
+        for wave in waves(&self.startup, "startup")? {
+            for (name, result) in run_wave(&mut runtime, &wave) {
+                if let Err(e) = result {
+                    return Err(format!("the startup hook {} failed: {}", name, e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the warm-up tasks on a background thread, reporting their progress to `state`. Once a task fails, the tasks
+    /// which didn't run yet are skipped.
+    pub(crate) fn start_warm_up(&self, state: WarmUpState) -> Result<(), String> {
+        let tasks = ::std::mem::take(&mut *self.warm_up.lock().unwrap_or_else(|e| e.into_inner()));
+        if tasks.is_empty() {
+            return Ok(());
+        }
+
+        let mut runtime = Runtime::new().map_err(|e| format!("unable to start the runtime of the warm-up tasks: {}", e))?;
+        let started = Instant::now();
+        thread::Builder::new().name("saphir-warm-up".to_string()).spawn(move || {
+            let waves = match waves(&tasks, "warm-up") {
+                Ok(waves) => waves,
+                Err(e) => return tasks.iter().for_each(|task| state.set(&task.name, WarmUpStatus::Failed(e.clone()))),
+            };
+
+            let mut failed = None;
+            for wave in waves {
+                if let Some(ref failed) = failed {
+                    for task in wave {
+                        state.set(&task.name, WarmUpStatus::Failed(format!("skipped since {} failed", failed)));
+                    }
+                    continue;
+                }
+
+                for (name, result) in run_wave(&mut runtime, &wave) {
+                    match result {
+                        Ok(_) => state.set(name, WarmUpStatus::Done),
+                        Err(e) => {
+                            log_event(Level::Error, SERVER_LOG_TARGET, "warm-up failed", format_args!("The warm-up task {} failed: {}", name, e),
+                                      &[("task", &name), ("error", &e)]);
+                            state.set(name, WarmUpStatus::Failed(e));
+                            failed = Some(name.to_string());
+                        }
+                    }
+                }
+            }
+
+            if failed.is_none() {
+                let elapsed = started.elapsed();
+                let duration_ms = elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 * 1e-6;
+                log_event(Level::Info, SERVER_LOG_TARGET, "warmed up", format_args!("Saphir warmed up in {:.3}ms and is ready", duration_ms),
+                          &[("duration_ms", &duration_ms)]);
+            }
+        }).map_err(|e| format!("unable to start the warm-up thread: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Run the shutdown hooks, logging the ones which fail without stopping the others
+    pub(crate) fn shutdown(&self) {
+        if self.shutdown.is_empty() {
+            return;
+        }
+
+        let (mut runtime, waves) = match (Runtime::new(), waves(&self.shutdown, "shutdown")) {
+            (Ok(runtime), Ok(waves)) => (runtime, waves),
+            (Err(e), _) => return log_shutdown_failure("all", &e.to_string()),
+            (_, Err(e)) => return log_shutdown_failure("all", &e),
+        };
+
+        for wave in waves {
+            for (name, result) in run_wave(&mut runtime, &wave) {
+                if let Err(e) = result {
+                    log_shutdown_failure(name, &e);
+                }
+            }
+        }
+    }
+}
+
+fn log_shutdown_failure(name: &str, error: &str) {
+    log_event(Level::Error, SERVER_LOG_TARGET, "shutdown hook failed", format_args!("The shutdown hook {} failed: {}", name, error),
+              &[("hook", &name), ("error", &error)]);
+}
+
+/// Run the hooks of `wave` concurrently, returning their outcome in the order they were registered
+fn run_wave<'a>(runtime: &mut Runtime, wave: &[&'a NamedHook]) -> Vec<(&'a str, Result<(), String>)> {
+    let pending = wave.iter().map(|hook| {
+        let started = Instant::now();
+        let name = hook.name.clone();
+        hook.hook.run().then(move |result| {
+            let elapsed = started.elapsed();
+            let duration_ms = elapsed.as_secs() as f64 * 1e3 + elapsed.subsec_nanos() as f64 * 1e-6;
+            log_event(Level::Debug, SERVER_LOG_TARGET, "lifecycle hook completed",
+                      format_args!("The hook {} completed in {:.3}ms", name, duration_ms), &[("hook", &name), ("duration_ms", &duration_ms)]);
+            Ok::<_, ()>(result)
+        })
+    }).collect::<Vec<_>>();
+
+    let results = runtime.block_on(join_all(pending)).unwrap_or_default();
+    wave.iter().map(|hook| hook.name.as_str()).zip(results).collect()
+}
+
+/// Group `hooks` in waves, each made of the hooks which only run after hooks of the previous waves, in the order they were
+/// registered
+fn waves<'a>(hooks: &'a [NamedHook], stage: &str) -> Result<Vec<Vec<&'a NamedHook>>, String> {
+    for hook in hooks {
+        if let Some(unknown) = hook.after.iter().find(|name| !hooks.iter().any(|other| &other.name == *name)) {
+            return Err(format!("the {} hook {} runs after {}, which isn't a {} hook", stage, hook.name, unknown, stage));
+        }
+    }
+
+    let mut done: Vec<&str> = Vec::new();
+    let mut pending: Vec<&NamedHook> = hooks.iter().collect();
+    let mut waves = Vec::new();
+
+    while !pending.is_empty() {
+        let (ready, blocked): (Vec<&NamedHook>, Vec<&NamedHook>) = pending.into_iter()
+            .partition(|hook| hook.after.iter().all(|name| done.contains(&name.as_str())));
+
+        if ready.is_empty() {
+            let names: Vec<&str> = blocked.iter().map(|hook| hook.name.as_str()).collect();
+            return Err(format!("the {} hooks {} run after each other", stage, names.join(", ")));
+        }
+
+        done.extend(ready.iter().map(|hook| hook.name.as_str()));
+        waves.push(ready);
+        pending = blocked;
+    }
+
+    Ok(waves)
+}
@@ -0,0 +1,74 @@
+use log::Level;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+/// Target of the server lifecycle logs: listening, shutting down and stopping
+pub const SERVER_LOG_TARGET: &str = "saphir::server";
+/// Target of the routing decisions, logged at the debug level
+pub const ROUTING_LOG_TARGET: &str = "saphir::routing";
+/// Target of the requests rejected by a `RequestGuard`, logged at the debug level
+pub const GUARD_LOG_TARGET: &str = "saphir::guard";
+/// Target of the requests answered with a server error, and of the responses which couldn't be built
+pub const HANDLER_LOG_TARGET: &str = "saphir::handler";
+/// Target of the line logged for every processed request
+pub const ACCESS_LOG_TARGET: &str = "saphir::access";
+/// Target of the webhook deliveries of a `WebhookDispatcher`: delivered, retried and abandoned
+pub const WEBHOOK_LOG_TARGET: &str = "saphir::webhook";
+
+static KEY_VALUE: AtomicBool = AtomicBool::new(false);
+
+/// How saphir formats its log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Sentences meant to be read by a human, the default
+    #[default]
+    Text,
+    /// `key=value` pairs meant to be parsed, starting with the `msg` key, like
+    /// `msg="request processed" method=GET path=/users status=200 duration_ms=0.42`
+    KeyValue,
+}
+
+/// Set how saphir formats its log records, for the whole process
+pub fn set_log_format(format: LogFormat) {
+    KEY_VALUE.store(format == LogFormat::KeyValue, Ordering::Relaxed);
+}
+
+/// Returns how saphir formats its log records
+pub fn log_format() -> LogFormat {
+    if KEY_VALUE.load(Ordering::Relaxed) { LogFormat::KeyValue } else { LogFormat::Text }
+}
+
+fn write_value(out: &mut String, value: &str) {
+    if !value.is_empty() && !value.chars().any(|c| c.is_whitespace() || c == '"' || c == '=' || c.is_control()) {
+        out.push_str(value);
+    } else {
+        out.push_str(&format!("{:?}", value));
+    }
+}
+
+/// Log an event to `target` through the `log` facade, as `text` in the text format or as `event` followed by `fields` in the
+/// key-value format. Nothing is formatted when the logger filters the record out.
+pub fn log_event(level: Level, target: &str, event: &str, text: fmt::Arguments, fields: &[(&str, &dyn fmt::Display)]) {
+    if !log_enabled!(target: target, level) {
+        return;
+    }
+
+    match log_format() {
+        LogFormat::Text => log!(target: target, level, "{}", text),
+        LogFormat::KeyValue => {
+            let mut line = String::from("msg=");
+            write_value(&mut line, event);
+
+            for &(key, value) in fields {
+                line.push(' ');
+                line.push_str(key);
+                line.push('=');
+                write_value(&mut line, &value.to_string());
+            }
+
+            log!(target: target, level, "{}", line);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use http::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// A request parked until it is notified
+struct Waiter<T> {
+    value: Mutex<Option<T>>,
+    notified: Condvar,
+}
+
+type Waiters<T> = Arc<Mutex<HashMap<String, Vec<Arc<Waiter<T>>>>>>;
+
+/// The requests of long-poll endpoints waiting for a notification, by key
+///
+/// Handlers park the request they are processing with `wait`, or `respond`, until the application notifies its key with a
+/// value or until the timeout elapses. Clones share the same waiting requests, a clone is typically moved to the code
+/// producing the notifications.
+///
+/// Notifications are only delivered to the requests waiting at the time, clients should poll again right away and say
+/// what they already received, like with the id of the last message, for nothing to be missed in between. Waiting requests
+/// block the thread they are processed on, see `ServerBuilder::handler_threads`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let messages = LongPoll::<String>::new();
+///
+/// let mut controller = BasicController::new(messages.clone());
+/// controller.add(Method::GET, "^/rooms/\\w+/messages$", |messages, req, res| {
+///     let room = req.uri().path().split('/').nth(2).unwrap_or_default().to_string();
+///     messages.respond(room, Duration::from_secs(30), res, |res, message| {
+///         res.status(StatusCode::OK).body(message);
+///     });
+/// });
+///
+/// // Later on, from anywhere in the application
+/// messages.notify("general", "Hello".to_string());
+/// ```
+pub struct LongPoll<T> {
+    waiters: Waiters<T>,
+}
+
+impl<T> Clone for LongPoll<T> {
+    fn clone(&self) -> Self {
+        LongPoll {
+            waiters: self.waiters.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Default for LongPoll<T> {
+    fn default() -> Self {
+        LongPoll::new()
+    }
+}
+
+impl<T: Clone> LongPoll<T> {
+    /// Create a long-poll without any waiting request
+    pub fn new() -> Self {
+        LongPoll {
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Park the current request until `key` is notified, returning the value it was notified with, or `None` once
+    /// `timeout` elapses
+    pub fn wait<K: Into<String>>(&self, key: K, timeout: Duration) -> Option<T> {
+        let key = key.into();
+        let waiter = Arc::new(Waiter {
+            value: Mutex::new(None),
+            notified: Condvar::new(),
+        });
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner()).entry(key.clone()).or_default().push(waiter.clone());
+
+        let deadline = Instant::now() + timeout;
+        let mut value = waiter.value.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if let Some(value) = value.take() {
+                return Some(value);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            value = waiter.notified.wait_timeout(value, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        drop(value);
+
+        let mut waiters = self.waiters.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(parked) = waiters.get_mut(&key) {
+            parked.retain(|parked| !Arc::ptr_eq(parked, &waiter));
+            if parked.is_empty() {
+                waiters.remove(&key);
+            }
+        }
+        drop(waiters);
+
+        // The notification may have come in between the timeout and the removal of the waiter
+        let value = waiter.value.lock().unwrap_or_else(|e| e.into_inner()).take();
+        value
+    }
+
+    /// Park the current request until `key` is notified, then let `respond` answer it with the value it was notified with.
+    /// Requests still waiting once `timeout` elapses are answered with `204 No Content`, telling the client to poll again.
+    pub fn respond<K, F>(&self, key: K, timeout: Duration, res: &mut SyncResponse, respond: F)
+        where K: Into<String>,
+              F: FnOnce(&mut SyncResponse, T) {
+        match self.wait(key, timeout) {
+            Some(value) => respond(res, value),
+            None => { res.status(StatusCode::NO_CONTENT); }
+        }
+    }
+
+    /// Complete every request waiting for `key` with `value`, returning how many there were
+    pub fn notify(&self, key: &str, value: T) -> usize {
+        let parked = self.waiters.lock().unwrap_or_else(|e| e.into_inner()).remove(key).unwrap_or_default();
+
+        for waiter in &parked {
+            *waiter.value.lock().unwrap_or_else(|e| e.into_inner()) = Some(value.clone());
+            waiter.notified.notify_one();
+        }
+
+        parked.len()
+    }
+
+    /// Returns the number of requests waiting for `key`
+    pub fn waiting(&self, key: &str) -> usize {
+        self.waiters.lock().unwrap_or_else(|e| e.into_inner()).get(key).map_or(0, |parked| parked.len())
+    }
+}
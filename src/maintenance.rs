@@ -0,0 +1,152 @@
+use http::*;
+use arc_swap::ArcSwap;
+use client_ip::IpNetwork;
+use client_ip::IpNetworkError;
+use regex::Regex;
+use utils::ToRegex;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Clone)]
+struct MaintenanceState {
+    enabled: bool,
+    allowed_paths: Vec<Regex>,
+    allowed_networks: Vec<IpNetwork>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+    retry_after: Option<Duration>,
+}
+
+/// A switch putting the whole server in maintenance, answering every request with `503 Service Unavailable` without
+/// restarting the process, see `ServerBuilder::maintenance`
+///
+/// Requests whose path matches an allowed path, like a health check, and requests of clients belonging to an allowed
+/// range, like the office network, are still processed. Clients are identified by `SyncRequest::client_ip`. The other
+/// requests get the body and `Retry-After` header set on the switch, or an error page without a body.
+///
+/// A `MaintenanceMode` is a cheap handle which can be cloned: it is typically toggled from a clone, like from an admin
+/// route or a signal handler, while the server is running.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let maintenance = MaintenanceMode::new()
+///     .allow_path("^/health$").unwrap()
+///     .allow_ip("10.0.0.0/8").unwrap()
+///     .body("text/html", "<h1>Back in a few minutes</h1>")
+///     .retry_after(Duration::from_secs(600));
+///
+/// let server = Server::builder().maintenance(maintenance.clone()).build();
+///
+/// // Later on, while the server is running
+/// maintenance.enable();
+/// ```
+#[derive(Clone)]
+pub struct MaintenanceMode {
+    state: Arc<ArcSwap<MaintenanceState>>,
+    mutation: Arc<Mutex<()>>,
+}
+
+impl Default for MaintenanceMode {
+    fn default() -> Self {
+        MaintenanceMode::new()
+    }
+}
+
+impl MaintenanceMode {
+    /// Create a disabled switch, without allowed paths nor ranges
+    pub fn new() -> Self {
+        MaintenanceMode {
+            state: Arc::new(ArcSwap::from_pointee(MaintenanceState {
+                enabled: false,
+                allowed_paths: Vec::new(),
+                allowed_networks: Vec::new(),
+                content_type: None,
+                body: Vec::new(),
+                retry_after: None,
+            })),
+            mutation: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Keep processing the requests whose path matches `path` during maintenance
+    pub fn allow_path<R: ToRegex>(self, path: R) -> Result<Self, ::regex::Error> {
+        let path = path.to_regex()?;
+        self.mutate(|state| state.allowed_paths.push(path));
+        Ok(self)
+    }
+
+    /// Keep processing the requests of the clients of `network`, written in CIDR notation, during maintenance
+    pub fn allow_ip(self, network: &str) -> Result<Self, IpNetworkError> {
+        let network = network.parse()?;
+        self.mutate(|state| state.allowed_networks.push(network));
+        Ok(self)
+    }
+
+    /// Answer the requests rejected during maintenance with `body`, of the type `content_type`
+    pub fn body<C: Into<String>, B: Into<Vec<u8>>>(self, content_type: C, body: B) -> Self {
+        let (content_type, body) = (content_type.into(), body.into());
+        self.mutate(|state| {
+            state.content_type = Some(content_type);
+            state.body = body;
+        });
+        self
+    }
+
+    /// Tell the clients rejected during maintenance to retry after `delay`, rounded up to the second
+    pub fn retry_after(self, delay: Duration) -> Self {
+        self.mutate(|state| state.retry_after = Some(delay));
+        self
+    }
+
+    /// Start rejecting requests
+    pub fn enable(&self) {
+        self.mutate(|state| state.enabled = true);
+    }
+
+    /// Stop rejecting requests
+    pub fn disable(&self) {
+        self.mutate(|state| state.enabled = false);
+    }
+
+    /// Returns whether the server is in maintenance
+    pub fn is_enabled(&self) -> bool {
+        self.state.load().enabled
+    }
+
+    fn mutate<F: FnOnce(&mut MaintenanceState)>(&self, mutation: F) {
+        let _guard = self.mutation.lock().unwrap_or_else(|e| e.into_inner());
+        let mut state = MaintenanceState::clone(&self.state.load());
+        mutation(&mut state);
+        self.state.store(Arc::new(state));
+    }
+
+    /// Answer `req` with `503 Service Unavailable` when the server is in maintenance and the request isn't allowed, returning
+    /// whether it was rejected
+    pub(crate) fn reject(&self, req: &SyncRequest, res: &mut SyncResponse) -> bool {
+        let state = self.state.load();
+        if !state.enabled || state.allowed_paths.iter().any(|path| path.is_match(req.uri().path())) {
+            return false;
+        }
+
+        if let Some(ip) = req.client_ip() {
+            if state.allowed_networks.iter().any(|network| network.contains(ip)) {
+                return false;
+            }
+        }
+
+        res.status(StatusCode::SERVICE_UNAVAILABLE);
+        if let Some(delay) = state.retry_after {
+            let delay = delay.as_secs() + if delay.subsec_nanos() > 0 { 1 } else { 0 };
+            res.header(header::RETRY_AFTER, delay.to_string());
+        }
+        if let Some(ref content_type) = state.content_type {
+            res.header(header::CONTENT_TYPE, content_type.as_str());
+        }
+        res.body(state.body.clone());
+        true
+    }
+}
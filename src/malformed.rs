@@ -0,0 +1,593 @@
+use http::*;
+use bytes::Buf;
+use date::http_date;
+use logging::*;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use log::Level;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::net::TcpListener as AsyncTcpListener;
+use tokio::net::TcpStream;
+use tokio::reactor::Handle;
+use tokio::timer::Delay;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The most bytes of a request kept to tell what is wrong with it
+const MAX_KEPT_HEAD: usize = 16 * 1024;
+
+/// The most clients tracked before the ones which are neither banned nor recently offending are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// What is wrong with a request the server couldn't parse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalformedRequestKind {
+    /// The request line isn't `<method> <target> HTTP/1.x`
+    RequestLine,
+    /// A header is invalid
+    Header,
+    /// The request is encoded with a `Transfer-Encoding` the server doesn't support
+    TransferEncoding,
+    /// The head of the request is too large
+    HeadTooLarge,
+    /// The request couldn't be parsed for another reason
+    Other,
+}
+
+impl fmt::Display for MalformedRequestKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            MalformedRequestKind::RequestLine => "invalid request line",
+            MalformedRequestKind::Header => "invalid header",
+            MalformedRequestKind::TransferEncoding => "unsupported transfer-encoding",
+            MalformedRequestKind::HeadTooLarge => "request head too large",
+            MalformedRequestKind::Other => "unparsable request",
+        })
+    }
+}
+
+/// A request the server couldn't parse, given to the hook of `MalformedRequestPolicy::respond`
+#[derive(Debug, Clone)]
+pub struct MalformedRequest {
+    peer_addr: SocketAddr,
+    kind: MalformedRequestKind,
+    status: StatusCode,
+}
+
+impl MalformedRequest {
+    /// Returns the address of the client which sent the request
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Returns what is wrong with the request
+    pub fn kind(&self) -> MalformedRequestKind {
+        self.kind
+    }
+
+    /// Returns the status the request is answered with by default, `400 Bad Request` or `431 Request Header Fields Too
+    /// Large`
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+type MalformedRequestHook = Arc<dyn Fn(&MalformedRequest) -> SyncResponse + Send + Sync>;
+
+#[derive(Clone)]
+enum Action {
+    Answer,
+    Close,
+    Respond(MalformedRequestHook),
+}
+
+/// How many malformed requests a client may send before it is banned, see `MalformedRequestPolicy::ban`
+#[derive(Debug, Clone, Copy)]
+struct AbuseLimit {
+    threshold: u32,
+    window: Duration,
+    duration: Duration,
+}
+
+/// What the server does with the requests it can't parse: a bad request line, an invalid header or an unsupported
+/// `Transfer-Encoding`, see `ServerBuilder::malformed_requests`
+///
+/// Such requests never reach the middlewares nor the router, and the connection they were sent on is closed once they are
+/// handled. They are always logged to `SERVER_LOG_TARGET`, then answered with an empty `400 Bad Request`, or `431 Request
+/// Header Fields Too Large`, closed without an answer, or answered with a response of their own. Clients sending too many
+/// of them can also be banned for a while, their connections being closed as soon as they are accepted.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let policy = MalformedRequestPolicy::respond(|request| {
+///         let mut res = SyncResponse::new();
+///         res.problem(&Problem::new(request.status()).with_detail(request.kind().to_string()));
+///         res
+///     })
+///     .ban(10, Duration::from_secs(60), Duration::from_secs(15 * 60));
+///
+/// let server = Server::builder()
+///     .router(Router::new())
+///     .malformed_requests(policy)
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct MalformedRequestPolicy {
+    action: Action,
+    abuse: Option<AbuseLimit>,
+}
+
+impl Default for MalformedRequestPolicy {
+    fn default() -> Self {
+        MalformedRequestPolicy::answer()
+    }
+}
+
+impl MalformedRequestPolicy {
+    /// Log malformed requests and answer them with an empty `400 Bad Request`, or `431 Request Header Fields Too Large`
+    pub fn answer() -> Self {
+        MalformedRequestPolicy {
+            action: Action::Answer,
+            abuse: None,
+        }
+    }
+
+    /// Log malformed requests and close their connection without answering them
+    pub fn close() -> Self {
+        MalformedRequestPolicy {
+            action: Action::Close,
+            abuse: None,
+        }
+    }
+
+    /// Log malformed requests and answer them with the response returned by `hook`. The response is sent at once, so its
+    /// body must not be streamed, and along with `Connection: close`.
+    pub fn respond<F>(hook: F) -> Self where F: 'static + Fn(&MalformedRequest) -> SyncResponse + Send + Sync {
+        MalformedRequestPolicy {
+            action: Action::Respond(Arc::new(hook)),
+            abuse: None,
+        }
+    }
+
+    /// Ban the clients, by address, sending `threshold` malformed requests within `window` for `duration`: the connections
+    /// they open in the meantime are closed as soon as they are accepted
+    pub fn ban(mut self, threshold: u32, window: Duration, duration: Duration) -> Self {
+        self.abuse = Some(AbuseLimit { threshold: threshold.max(1), window, duration });
+        self
+    }
+}
+
+/// The offences of a client
+struct Abuse {
+    window_start: Instant,
+    strikes: u32,
+    banned_until: Option<Instant>,
+}
+
+/// The policy of the server, along with the clients it is tracking
+pub(crate) struct MalformedRequests {
+    policy: MalformedRequestPolicy,
+    clients: Mutex<HashMap<IpAddr, Abuse>>,
+}
+
+impl MalformedRequests {
+    pub(crate) fn new(policy: MalformedRequestPolicy) -> Self {
+        MalformedRequests {
+            policy,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns whether `ip` is banned, forgetting its ban once it is over
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        if self.policy.abuse.is_none() {
+            return false;
+        }
+
+        let mut clients = self.clients.lock().expect("malformed request clients");
+        match clients.get(&ip).and_then(|abuse| abuse.banned_until) {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                clients.remove(&ip);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Log `request` and count it toward the abuse score of its client, banning the client once it reaches the threshold
+    fn report(&self, request: &MalformedRequest) {
+        log_event(Level::Info, SERVER_LOG_TARGET, "malformed request",
+                  format_args!("Rejected a malformed request from {}: {}", request.peer_addr, request.kind),
+                  &[("peer_addr", &request.peer_addr), ("kind", &request.kind), ("status", &request.status.as_u16())]);
+
+        let limit = match self.policy.abuse {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().expect("malformed request clients");
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            clients.retain(|_, abuse| abuse.banned_until.is_some_and(|until| until > now) || now.duration_since(abuse.window_start) < limit.window);
+        }
+
+        let abuse = clients.entry(request.peer_addr.ip()).or_insert(Abuse { window_start: now, strikes: 0, banned_until: None });
+        if now.duration_since(abuse.window_start) >= limit.window {
+            abuse.window_start = now;
+            abuse.strikes = 0;
+        }
+
+        abuse.strikes += 1;
+        if abuse.strikes >= limit.threshold {
+            abuse.banned_until = Some(now + limit.duration);
+            abuse.strikes = 0;
+            log_event(Level::Warn, SERVER_LOG_TARGET, "client banned",
+                      format_args!("Banned {} for {}s after {} malformed requests", request.peer_addr.ip(), limit.duration.as_secs(), limit.threshold),
+                      &[("ip", &request.peer_addr.ip()), ("duration_s", &limit.duration.as_secs()), ("threshold", &limit.threshold)]);
+        }
+    }
+
+    /// Returns the bytes replacing the automatic answer of hyper, `None` to send it as is
+    fn replacement(&self, request: &MalformedRequest) -> Option<Vec<u8>> {
+        match self.policy.action {
+            Action::Answer => None,
+            Action::Close => Some(Vec::new()),
+            Action::Respond(ref hook) => Some(serialize_response(hook(request))),
+        }
+    }
+}
+
+/// Write `response` as an HTTP/1.1 response closing its connection
+fn serialize_response(response: SyncResponse) -> Vec<u8> {
+    let status = response.get_status();
+    let body = response.get_body();
+    let mut bytes = format!("HTTP/1.1 {} {}\r\n", status.as_u16(), status.canonical_reason().unwrap_or("")).into_bytes();
+
+    for (name, value) in response.headers_map() {
+        if name == header::CONTENT_LENGTH || name == header::TRANSFER_ENCODING || name == header::CONNECTION {
+            continue;
+        }
+        bytes.extend_from_slice(name.as_str().as_bytes());
+        bytes.extend_from_slice(b": ");
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+    }
+
+    if !response.headers_map().contains_key(header::DATE) {
+        bytes.extend_from_slice(b"date: ");
+        bytes.extend_from_slice(http_date().as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+    }
+
+    bytes.extend_from_slice(format!("content-length: {}\r\nconnection: close\r\n\r\n", body.len()).as_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// Tell what is wrong with a request from the start of its `head`, as it was received
+fn classify(head: &[u8], status: StatusCode) -> MalformedRequestKind {
+    if status == StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE {
+        return MalformedRequestKind::HeadTooLarge;
+    }
+
+    if head.is_empty() {
+        return MalformedRequestKind::Other;
+    }
+
+    let mut lines = head.split(|b| *b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let request_line = lines.next().unwrap_or(&[]);
+    let mut parts = request_line.split(|b| *b == b' ');
+    let valid_request_line = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => {
+            !method.is_empty() && method.iter().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(b))
+                && !target.is_empty() && target.iter().all(|b| b.is_ascii_graphic())
+                && (version == b"HTTP/1.0" || version == b"HTTP/1.1")
+        }
+        _ => false,
+    };
+
+    if !valid_request_line {
+        return MalformedRequestKind::RequestLine;
+    }
+
+    for line in lines.take_while(|line| !line.is_empty()) {
+        let mut field = line.splitn(2, |b| *b == b':');
+        let (name, value) = match (field.next(), field.next()) {
+            (Some(name), Some(value)) => (name, value),
+            _ => continue,
+        };
+
+        if name.eq_ignore_ascii_case(b"transfer-encoding") {
+            let value = String::from_utf8_lossy(value);
+            let last = value.rsplit(',').next().unwrap_or("").trim().to_ascii_lowercase();
+            if last != "chunked" {
+                return MalformedRequestKind::TransferEncoding;
+            }
+        }
+    }
+
+    MalformedRequestKind::Header
+}
+
+/// Returns the status of the response starting at `bytes`, if they start a response
+fn response_status(bytes: &[u8]) -> Option<u16> {
+    if bytes.len() < 12 || !(bytes.starts_with(b"HTTP/1.1 ") || bytes.starts_with(b"HTTP/1.0 ")) {
+        return None;
+    }
+
+    ::std::str::from_utf8(&bytes[9..12]).ok().and_then(|status| status.parse().ok())
+}
+
+/// Counts the requests of a connection handed to the service, which each get a response, unlike the malformed ones
+#[derive(Clone, Default)]
+pub(crate) struct DispatchedRequests(Arc<AtomicU64>);
+
+impl DispatchedRequests {
+    pub(crate) fn dispatch(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// What a connection watched for malformed requests is writing
+enum Writing {
+    /// The responses of hyper or of the service
+    Responses,
+    /// The automatic answer of hyper to a malformed request, as is
+    Answer,
+    /// The replacement of the automatic answer of hyper to a malformed request, and what was written of it
+    Replacement(Vec<u8>, usize),
+    /// Nothing anymore, the connection is about to be closed
+    Discarding,
+}
+
+/// Recognizes the automatic answer hyper gives to the requests it can't parse, since hyper handles them on its own
+///
+/// Every request handed to the service gets a response: a response starting while every dispatched request was answered
+/// is one hyper wrote on its own, a `400` or `431` for a malformed request.
+struct ConnectionWatch {
+    malformed: Arc<MalformedRequests>,
+    dispatched: DispatchedRequests,
+    answered: u64,
+    head: Vec<u8>,
+    writing: Writing,
+}
+
+impl ConnectionWatch {
+    /// Keep the bytes received since the last response, which start the request being parsed
+    fn received(&mut self, bytes: &[u8]) {
+        let kept = bytes.len().min(MAX_KEPT_HEAD.saturating_sub(self.head.len()));
+        self.head.extend_from_slice(&bytes[..kept]);
+    }
+
+    /// Forget the request which was just answered, keeping the ones pipelined after it
+    fn forget_answered(&mut self) {
+        let end = match self.head.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None => return self.head.clear(),
+        };
+
+        let body = ::std::str::from_utf8(&self.head[..end]).ok().and_then(|head| {
+            head.split("\r\n").filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        });
+        let answered = (end + body.unwrap_or(0)).min(self.head.len());
+        self.head.drain(..answered);
+    }
+
+    /// Look at the start of a write: returns whether it starts the response to a request handed to the service, or `None`
+    /// when the write is replaced, being the automatic answer of hyper to a malformed request
+    fn inspect(&mut self, peer_addr: SocketAddr, buf: &[u8]) -> Option<bool> {
+        if let Writing::Responses = self.writing {
+            match response_status(buf) {
+                // hyper answers `Expect: 100-continue` on its own as well
+                Some(100) | None => {}
+                Some(_) if self.answered < self.dispatched.0.load(Ordering::SeqCst) => return Some(true),
+                Some(status @ 400) | Some(status @ 431) if buf.ends_with(b"\r\n\r\n") => {
+                    let status = StatusCode::from_u16(status).expect("a valid status");
+                    let request = MalformedRequest { peer_addr, kind: classify(&self.head, status), status };
+                    self.malformed.report(&request);
+                    self.writing = match self.malformed.replacement(&request) {
+                        Some(replacement) => Writing::Replacement(replacement, 0),
+                        None => Writing::Answer,
+                    };
+                }
+                Some(_) => {}
+            }
+        }
+
+        match self.writing {
+            Writing::Responses | Writing::Answer => Some(false),
+            Writing::Replacement(..) | Writing::Discarding => None,
+        }
+    }
+
+    /// Count the response to a request handed to the service, once it started to be written
+    fn answered(&mut self) {
+        self.answered += 1;
+        self.forget_answered();
+    }
+
+    /// Write the replacement of the automatic answer of hyper, reporting its `len` bytes as written once it is
+    fn replace(&mut self, inner: &mut TcpStream, len: usize) -> io::Result<usize> {
+        if let Writing::Replacement(ref replacement, ref mut offset) = self.writing {
+            while *offset < replacement.len() {
+                *offset += inner.write(&replacement[*offset..])?;
+            }
+        }
+
+        self.writing = Writing::Discarding;
+        Ok(len)
+    }
+}
+
+/// A connection accepted by `Incoming`, watched for malformed requests when the server has a policy for them
+pub(crate) struct GuardedStream {
+    inner: TcpStream,
+    remote_addr: SocketAddr,
+    watch: Option<ConnectionWatch>,
+}
+
+impl GuardedStream {
+    /// Returns the address of the client
+    pub(crate) fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Returns the counter the service of the connection must increment for every request it is handed, if the connection
+    /// is watched
+    pub(crate) fn dispatched(&self) -> Option<DispatchedRequests> {
+        self.watch.as_ref().map(|watch| watch.dispatched.clone())
+    }
+}
+
+impl Read for GuardedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(ref mut watch) = self.watch {
+            watch.received(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl Write for GuardedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.watch {
+            Some(ref mut watch) => match watch.inspect(self.remote_addr, buf) {
+                Some(answer) => {
+                    let written = self.inner.write(buf)?;
+                    if answer {
+                        watch.answered();
+                    }
+                    Ok(written)
+                }
+                None => watch.replace(&mut self.inner, buf.len()),
+            },
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsyncRead for GuardedStream {}
+
+impl AsyncWrite for GuardedStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        AsyncWrite::shutdown(&mut self.inner)
+    }
+
+    /// Keep the vectored writes of the socket, so hyper writes the head and the body of a response at once
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        match self.watch {
+            Some(ref mut watch) => match watch.inspect(self.remote_addr, buf.bytes()) {
+                Some(answer) => {
+                    let written = self.inner.write_buf(buf)?;
+                    if answer && written.is_ready() {
+                        watch.answered();
+                    }
+                    Ok(written)
+                }
+                None => {
+                    match watch.replace(&mut self.inner, buf.remaining()) {
+                        Ok(written) => {
+                            buf.advance(written);
+                            Ok(Async::Ready(written))
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+                        Err(e) => Err(e),
+                    }
+                }
+            },
+            None => self.inner.write_buf(buf),
+        }
+    }
+}
+
+/// The connections accepted by the server, without the ones of banned clients
+pub(crate) struct Incoming {
+    listener: AsyncTcpListener,
+    malformed: Option<Arc<MalformedRequests>>,
+    backoff: Option<Delay>,
+}
+
+impl Incoming {
+    pub(crate) fn new(listener: TcpListener, malformed: Option<Arc<MalformedRequests>>) -> io::Result<Self> {
+        Ok(Incoming {
+            listener: AsyncTcpListener::from_std(listener, &Handle::default())?,
+            malformed,
+            backoff: None,
+        })
+    }
+}
+
+impl Stream for Incoming {
+    type Item = GuardedStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<GuardedStream>, io::Error> {
+        loop {
+            if let Some(ref mut backoff) = self.backoff {
+                if let Ok(Async::NotReady) = backoff.poll() {
+                    return Ok(Async::NotReady);
+                }
+            }
+            self.backoff = None;
+
+            match self.listener.poll_accept() {
+                Ok(Async::Ready((stream, peer_addr))) => {
+                    let malformed = match self.malformed {
+                        Some(ref malformed) if malformed.is_banned(peer_addr.ip()) => {
+                            log_event(Level::Debug, SERVER_LOG_TARGET, "banned client",
+                                      format_args!("Closed the connection of {}, which is banned", peer_addr), &[("peer_addr", &peer_addr)]);
+                            continue;
+                        }
+                        ref malformed => malformed.clone(),
+                    };
+
+                    return Ok(Async::Ready(Some(GuardedStream {
+                        inner: stream,
+                        remote_addr: peer_addr,
+                        watch: malformed.map(|malformed| ConnectionWatch {
+                            malformed,
+                            dispatched: DispatchedRequests::default(),
+                            answered: 0,
+                            head: Vec::new(),
+                            writing: Writing::Responses,
+                        }),
+                    })));
+                }
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                // The client left before its connection was accepted
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionAborted || e.kind() == io::ErrorKind::ConnectionReset => {}
+                // Most likely out of file descriptors, which some connections may give back in a while
+                Err(e) => {
+                    log_event(Level::Error, SERVER_LOG_TARGET, "accept error", format_args!("Unable to accept a connection: {}", e), &[("error", &e)]);
+                    self.backoff = Some(Delay::new(Instant::now() + Duration::from_secs(1)));
+                }
+            }
+        }
+    }
+}
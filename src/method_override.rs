@@ -0,0 +1,97 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+
+/// A middleware letting `POST` requests choose the method they are routed with, for clients like HTML forms which can only
+/// send `GET` and `POST` requests
+///
+/// The method is read from the `X-HTTP-Method-Override` header, or from the `_method` field of an
+/// `application/x-www-form-urlencoded` body. Only methods from the allowed list are honored, `PUT`, `PATCH` and `DELETE` by
+/// default; requests asking for another method keep the `POST` method.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(MethodOverrideMiddleware::new(), vec!("/"), None);
+/// ```
+pub struct MethodOverrideMiddleware {
+    allowed: Vec<Method>,
+    form_field: bool,
+}
+
+impl MethodOverrideMiddleware {
+    /// Create a middleware allowing `POST` requests to be overridden to `PUT`, `PATCH` or `DELETE`
+    pub fn new() -> Self {
+        MethodOverrideMiddleware {
+            allowed: vec![Method::PUT, Method::PATCH, Method::DELETE],
+            form_field: true,
+        }
+    }
+
+    /// Set the methods a request can be overridden to
+    pub fn allowed_methods(mut self, methods: Vec<Method>) -> Self {
+        self.allowed = methods;
+        self
+    }
+
+    /// Only honor the `X-HTTP-Method-Override` header, ignoring the `_method` form field
+    pub fn header_only(mut self) -> Self {
+        self.form_field = false;
+        self
+    }
+
+    fn requested_method(&self, req: &SyncRequest) -> Option<String> {
+        if let Some(method) = req.headers_map().get("x-http-method-override").and_then(|m| m.to_str().ok()) {
+            return Some(method.trim().to_string());
+        }
+
+        if !self.form_field {
+            return None;
+        }
+
+        if !req.is_form() {
+            return None;
+        }
+
+        req.body().split(|b| *b == b'&')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, |b| *b == b'=');
+                match (parts.next(), parts.next()) {
+                    (Some(b"_method"), Some(value)) => ::std::str::from_utf8(value).ok(),
+                    _ => None,
+                }
+            })
+            .next()
+            .map(|method| method.trim().to_string())
+    }
+}
+
+impl Default for MethodOverrideMiddleware {
+    fn default() -> Self {
+        MethodOverrideMiddleware::new()
+    }
+}
+
+impl Middleware for MethodOverrideMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        if *req.method() != Method::POST {
+            return RequestContinuation::Next;
+        }
+
+        let method = self.requested_method(req)
+            .and_then(|m| m.to_ascii_uppercase().parse::<Method>().ok())
+            .filter(|m| self.allowed.contains(m));
+
+        if let Some(method) = method {
+            *req.method_mut() = method;
+        }
+
+        RequestContinuation::Next
+    }
+}
@@ -0,0 +1,304 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use response_size::observe_response_size;
+use response_size::RESPONSE_SIZE_BUCKETS;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Instant;
+
+/// The buckets of histograms registered without buckets of their own, in seconds, suited to request durations
+pub const DEFAULT_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// The label values of a series, sorted by label name
+type Labels = Vec<(String, String)>;
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    help: HashMap<String, String>,
+    buckets: HashMap<String, Vec<f64>>,
+    counters: BTreeMap<String, BTreeMap<Labels, u64>>,
+    histograms: BTreeMap<String, BTreeMap<Labels, Histogram>>,
+}
+
+/// The label of the route which handled a request, set in the extensions of its response by `ControllerDispatch`
+///
+/// Delegates are labeled with their route pattern unless they were given a label with `ControllerDispatch::label`, or an
+/// operation id with `ControllerDispatch::document`, so that labels never contain the values of path parameters.
+#[derive(Debug, Clone)]
+pub(crate) struct RouteLabel(pub(crate) String);
+
+impl SyncResponse {
+    /// Returns the label of the delegate route which handled the request, `None` when no delegate matched it
+    pub fn route_label(&self) -> Option<&str> {
+        self.get_extensions().get::<RouteLabel>().map(|label| label.0.as_str())
+    }
+}
+
+impl SyncRequest {
+    /// Returns the metrics registry of the `MetricsMiddleware` applied to the request, for handlers to record metrics of
+    /// their own
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn checkout(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     if let Some(metrics) = req.metrics() {
+    ///         metrics.increment("orders_total", &[("payment", "card")]);
+    ///         metrics.observe("order_amount_dollars", &[], 42.5);
+    ///     }
+    ///     res.status(StatusCode::CREATED);
+    /// }
+    /// ```
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.extensions().get::<Metrics>()
+    }
+}
+
+/// A registry of counters and histograms, exposed in the Prometheus text format
+///
+/// Series are created the first time they are recorded. Every distinct set of label values is a series of its own, label
+/// values must come from a small set, like a status or a route label, never from raw request paths or identifiers. Clones
+/// share the same series.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let metrics = Metrics::new();
+/// metrics.describe("orders_total", "Orders placed, by payment method");
+/// metrics.histogram("order_amount_dollars", &[10.0, 50.0, 100.0, 500.0]);
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(MetricsMiddleware::new(metrics.clone()), vec!("^/"), Some(vec!("^/metrics$")));
+///
+/// let mut router = Router::new();
+/// router.add("^/metrics$", metrics.controller());
+/// ```
+#[derive(Clone, Default)]
+pub struct Metrics {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Metrics {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    fn registry(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Set the help text of the metric `name`
+    pub fn describe(&self, name: &str, help: &str) -> &Self {
+        self.registry().help.insert(name.to_string(), help.to_string());
+        self
+    }
+
+    /// Set the upper bounds of the buckets of the histogram `name`, in increasing order. Only series recorded afterwards use
+    /// them, histograms should be registered before they are observed.
+    pub fn histogram(&self, name: &str, buckets: &[f64]) -> &Self {
+        self.registry().buckets.insert(name.to_string(), buckets.to_vec());
+        self
+    }
+
+    /// Add 1 to the counter `name` with the given labels
+    pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    /// Add `value` to the counter `name` with the given labels
+    pub fn add(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        let mut registry = self.registry();
+        *registry.counters.entry(name.to_string()).or_default().entry(sorted(labels)).or_insert(0) += value;
+    }
+
+    /// Record `value` in the histogram `name` with the given labels
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let mut registry = self.registry();
+        let buckets = registry.buckets.get(name).cloned().unwrap_or_else(|| DEFAULT_BUCKETS.to_vec());
+        let histogram = registry.histograms.entry(name.to_string()).or_default().entry(sorted(labels)).or_insert_with(|| Histogram {
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        });
+
+        for (count, bound) in histogram.counts.iter_mut().zip(buckets) {
+            if value <= bound {
+                *count += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    /// Returns the value of the counter `name` with the given labels, 0 when it was never incremented
+    pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        self.registry().counters.get(name).and_then(|series| series.get(&sorted(labels)).cloned()).unwrap_or(0)
+    }
+
+    /// Returns how many values the histogram `name` with the given labels recorded, and their sum
+    pub fn histogram_count(&self, name: &str, labels: &[(&str, &str)]) -> (u64, f64) {
+        self.registry().histograms.get(name)
+            .and_then(|series| series.get(&sorted(labels)).map(|histogram| (histogram.count, histogram.sum)))
+            .unwrap_or((0, 0.0))
+    }
+
+    /// Render every series in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let registry = self.registry();
+        let mut text = String::new();
+
+        for (name, series) in &registry.counters {
+            header(&mut text, &registry, name, "counter");
+            for (labels, value) in series {
+                let _ = writeln!(text, "{}{} {}", name, format_labels(labels, None), value);
+            }
+        }
+
+        for (name, series) in &registry.histograms {
+            header(&mut text, &registry, name, "histogram");
+            let buckets = registry.buckets.get(name).map(|buckets| buckets.as_slice()).unwrap_or(DEFAULT_BUCKETS);
+            for (labels, histogram) in series {
+                for (count, bound) in histogram.counts.iter().zip(buckets) {
+                    let _ = writeln!(text, "{}_bucket{} {}", name, format_labels(labels, Some(&bound.to_string())), count);
+                }
+                let _ = writeln!(text, "{}_bucket{} {}", name, format_labels(labels, Some("+Inf")), histogram.count);
+                let _ = writeln!(text, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum);
+                let _ = writeln!(text, "{}_count{} {}", name, format_labels(labels, None), histogram.count);
+            }
+        }
+
+        text
+    }
+
+    /// Create a controller answering `GET` requests with the rendered metrics, for Prometheus to scrape
+    pub fn controller(&self) -> MetricsController {
+        MetricsController { metrics: self.clone() }
+    }
+}
+
+fn sorted(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect();
+    labels.sort();
+    labels
+}
+
+fn header(text: &mut String, registry: &Registry, name: &str, kind: &str) {
+    if let Some(help) = registry.help.get(name) {
+        let _ = writeln!(text, "# HELP {} {}", name, help.replace('\\', "\\\\").replace('\n', "\\n"));
+    }
+    let _ = writeln!(text, "# TYPE {} {}", name, kind);
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// A controller exposing a `Metrics` registry, created with `Metrics::controller`
+pub struct MetricsController {
+    metrics: Metrics,
+}
+
+impl Controller for MetricsController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED);
+            return;
+        }
+
+        res.status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")
+            .body(self.metrics.render());
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        vec![Method::GET, Method::HEAD].into_iter()
+            .map(|method| RouteInfo::new("MetricsController", Some(method), None, Vec::new()))
+            .collect()
+    }
+}
+
+/// When `MetricsMiddleware` started measuring the request
+struct RequestStart(Instant);
+
+/// A middleware counting requests and measuring their duration, labeled by method, route label and status
+///
+/// Requests are counted in `http_requests_total`, their durations recorded in `http_request_duration_seconds` and the
+/// sizes of their response bodies in `http_response_size_bytes`, once they are sent for streamed bodies. The route label is the one of the delegate which handled the request, see `ControllerDispatch::label`, or `unmatched`. The
+/// registry is also made available to handlers with `SyncRequest::metrics`.
+pub struct MetricsMiddleware {
+    metrics: Metrics,
+}
+
+impl MetricsMiddleware {
+    /// Create a middleware recording the requests in `metrics`
+    pub fn new(metrics: Metrics) -> Self {
+        metrics.describe("http_requests_total", "Requests answered, by method, route and status");
+        metrics.describe("http_request_duration_seconds", "Time spent answering requests, by method and route");
+        metrics.describe("http_deprecated_requests_total", "Requests answered by deprecated routes, by method and route");
+        metrics.describe("http_response_size_bytes", "Size of the response bodies, by method and route")
+            .histogram("http_response_size_bytes", RESPONSE_SIZE_BUCKETS);
+        MetricsMiddleware { metrics }
+    }
+}
+
+impl Middleware for MetricsMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        req.extensions_mut().insert(RequestStart(Instant::now()));
+        req.extensions_mut().insert(self.metrics.clone());
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let method = req.method().as_str();
+        let route = res.route_label().unwrap_or("unmatched").to_string();
+        let status = res.get_status().as_u16().to_string();
+        self.metrics.increment("http_requests_total", &[("method", method), ("route", &route), ("status", &status)]);
+
+        if res.is_deprecated_route() {
+            self.metrics.increment("http_deprecated_requests_total", &[("method", method), ("route", &route)]);
+        }
+
+        if let Some(start) = req.extensions().get::<RequestStart>() {
+            let elapsed = start.0.elapsed();
+            let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+            self.metrics.observe("http_request_duration_seconds", &[("method", method), ("route", &route)], seconds);
+        }
+
+        let (metrics, method) = (self.metrics.clone(), method.to_string());
+        observe_response_size(res, move |bytes| {
+            metrics.observe("http_response_size_bytes", &[("method", &method), ("route", &route)], bytes as f64);
+        });
+    }
+}
@@ -2,28 +2,35 @@ use http::*;
 use utils::ToRegex;
 use utils::RequestContinuation;
 use utils::RequestContinuation::*;
+use controller::short_type_name;
 use regex::Regex;
-use std::sync::RwLock;
+use predicate::RequestPredicate;
+use router::routing_path;
+use profiling::Phase;
+use profiling::profiled;
+use std::any::type_name;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 /// Struct representing the layering of middlewares in the server
 pub struct MiddlewareStack {
-    middlewares: RwLock<Vec<(MiddlewareRule, Box<Middleware>)>>
+    middlewares: Vec<(MiddlewareRule, Box<dyn Middleware>)>
 }
 
 impl MiddlewareStack {
     ///
     pub fn new() -> Self {
         MiddlewareStack {
-            middlewares: RwLock::new(Vec::new()),
+            middlewares: Vec::new(),
         }
     }
 
     ///
     pub fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
-        let path = req.uri().path();
+        let path = routing_path(req);
 
-        for &(ref rule, ref middleware) in self.middlewares.read().unwrap().iter() {
-            if rule.validate_path(path) {
+        for (rule, middleware) in self.middlewares.iter() {
+            if rule.validate_path(&path) {
                 if let None = middleware.resolve(req, res) {
                     return None;
                 }
@@ -33,22 +40,201 @@ impl MiddlewareStack {
         Next
     }
 
+    /// Resolve the middleware stack and invoke `handler` if no middleware ceased the request processing. Once the response is
+    /// computed, every middleware which was resolved is given a chance to alter it through `Middleware::after`, in reverse order.
+    ///
+    /// Each middleware is matched against the path of the request as left by the `Middleware::prepare` of the previous ones,
+    /// lowercased when the router is case insensitive, like the routes.
+    pub fn resolve_with<F>(&self, req: &mut SyncRequest, res: &mut SyncResponse, handler: F)
+        where F: FnOnce(&SyncRequest, &mut SyncResponse) {
+        let mut resolved = Vec::new();
+        let mut continuation = Next;
+
+        for (rule, middleware) in self.middlewares.iter() {
+            if rule.validate_path(&routing_path(req)) {
+                resolved.push(middleware);
+                let phase = || Phase::Middleware(middleware.name());
+                if let None = profiled(res, phase, |res| middleware.prepare(req, res)) {
+                    continuation = None;
+                    break;
+                }
+                if let None = profiled(res, phase, |res| middleware.resolve(req, res)) {
+                    continuation = None;
+                    break;
+                }
+            }
+        }
+
+        if let Next = continuation {
+            handler(req, res);
+        }
+
+        for middleware in resolved.iter().rev() {
+            profiled(res, || Phase::Middleware(middleware.name()), |res| middleware.after(req, res));
+        }
+    }
+
     /// Method to apply a new middleware onto the stack where the `include_path` vec are all path affected by the middleware,
     /// and `exclude_path` are exclusion amongst the included paths.
     pub fn apply<M: 'static + Middleware>(&mut self, m: M, include_path: Vec<&str>, exclude_path: Option<Vec<&str>>) {
         let rule = MiddlewareRule::new(include_path, exclude_path);
         let boxed_m = Box::new(m);
 
-        self.middlewares.write().unwrap().push((rule, boxed_m))
+        self.middlewares.push((rule, boxed_m))
+    }
+
+    /// Describe the middlewares of the stack, in their resolution order, used for introspection only
+    pub fn middlewares(&self) -> Vec<MiddlewareInfo> {
+        self.middlewares.iter().map(|(rule, middleware)| {
+            MiddlewareInfo {
+                name: middleware.name(),
+                include_path: rule.included_path.iter().map(|re| re.as_str().to_string()).collect(),
+                exclude_path: rule.excluded_path.iter().flatten().map(|re| re.as_str().to_string()).collect(),
+            }
+        }).collect()
     }
 }
 
+/// Description of a middleware, as returned by `MiddlewareStack::middlewares`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MiddlewareInfo {
+    /// Name of the middleware
+    pub name: String,
+    /// The regular expressions of the paths the middleware is applied to
+    pub include_path: Vec<String>,
+    /// The regular expressions of the paths excluded amongst the included ones
+    pub exclude_path: Vec<String>,
+}
+
 /// The trait a struct need to `impl` to be considered as a middleware
 pub trait Middleware: Send + Sync {
     /// This method will be invoked if the request is targeting an included path, (as defined when "applying" the middleware to the stack)
     /// and doesn't match any exclusion. Returning `RequestContinuation::Next` will allow the request to continue through the stack, and
     /// returning `RequestContinuation::None` will cease the request processing, returning as response the modified `res` param.
     fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation;
+
+    /// This method will be invoked right before `resolve`, with a mutable access to the request. It allows the middleware to
+    /// alter the request, like rewriting its path, before the following middlewares and the router see it. Returning
+    /// `RequestContinuation::None` will cease the request processing, like `resolve`. By default it does nothing.
+    fn prepare(&self, _req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        Next
+    }
+
+    /// This method will be invoked once the response is computed, if `resolve` was previously invoked for the same request,
+    /// whether or not the request was ceased. Middlewares are invoked in the reverse order of their resolution, allowing them
+    /// to inspect or alter the final response. By default it does nothing.
+    fn after(&self, _req: &SyncRequest, _res: &mut SyncResponse) {}
+
+    /// Name of the middleware, used for introspection only
+    fn name(&self) -> String {
+        short_type_name(type_name::<Self>())
+    }
+
+    /// Wrap the middleware so it only runs for the requests matching `predicate`, the others going through as if it
+    /// wasn't applied, see `Conditional`
+    fn when(self, predicate: RequestPredicate) -> Conditional<Self> where Self: Sized {
+        Conditional::new(self, predicate)
+    }
+
+    /// Wrap the middleware so it runs for every request but the ones matching `predicate`, like health checks or
+    /// websocket upgrades
+    fn unless(self, predicate: RequestPredicate) -> Conditional<Self> where Self: Sized {
+        Conditional::new(self, !predicate)
+    }
+}
+
+/// A middleware running only for the requests matching a predicate, created with `Middleware::when` or
+/// `Middleware::unless`
+///
+/// The predicate is evaluated once per request, when the middleware is first reached, so that `prepare`, `resolve`
+/// and `after` are either all invoked or all skipped even if the request is altered in between.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// struct Audit;
+///
+/// impl Middleware for Audit {
+///     fn resolve(&self, req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+///         println!("{} {}", req.method(), req.uri());
+///         RequestContinuation::Next
+///     }
+/// }
+///
+/// let mut stack = MiddlewareStack::new();
+/// stack.apply(Audit.unless(RequestPredicate::path("^/health$") | RequestPredicate::websocket()), vec!["^/"], None);
+/// ```
+pub struct Conditional<M> {
+    middleware: M,
+    predicate: RequestPredicate,
+    id: usize,
+}
+
+/// Whether each conditional middleware runs for a request, by id, kept with its response
+struct ConditionalDecisions(HashMap<usize, bool>);
+
+impl<M: Middleware> Conditional<M> {
+    /// Wrap `middleware` so it only runs for the requests matching `predicate`
+    pub fn new(middleware: M, predicate: RequestPredicate) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        Conditional {
+            middleware,
+            predicate,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the predicate deciding whether the middleware runs
+    pub fn predicate(&self) -> &RequestPredicate {
+        &self.predicate
+    }
+
+    /// Returns whether the middleware runs for `req`, evaluating the predicate the first time only
+    fn applies(&self, req: &SyncRequest, res: &mut SyncResponse) -> bool {
+        if let Some(decision) = res.get_extensions().get::<ConditionalDecisions>().and_then(|d| d.0.get(&self.id)) {
+            return *decision;
+        }
+
+        let decision = self.predicate.matches(req);
+        if res.get_extensions().get::<ConditionalDecisions>().is_none() {
+            res.extension(ConditionalDecisions(HashMap::new()));
+        }
+        if let Some(decisions) = res.get_extensions_mut().get_mut::<ConditionalDecisions>() {
+            decisions.0.insert(self.id, decision);
+        }
+
+        decision
+    }
+}
+
+impl<M: Middleware> Middleware for Conditional<M> {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if self.applies(req, res) {
+            self.middleware.resolve(req, res)
+        } else {
+            Next
+        }
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if self.applies(req, res) {
+            self.middleware.prepare(req, res)
+        } else {
+            Next
+        }
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if self.applies(req, res) {
+            self.middleware.after(req, res)
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{} when {}", self.middleware.name(), self.predicate)
+    }
 }
 
 struct MiddlewareRule {
@@ -0,0 +1,27 @@
+use http::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use profiling::Phase;
+use profiling::profiled;
+
+impl SyncRequest {
+    /// Deserialize the body of the request from MessagePack
+    pub fn body_msgpack<T: DeserializeOwned>(&self) -> Result<T, ::rmp_serde::decode::Error> {
+        ::rmp_serde::from_slice(self.body())
+    }
+}
+
+impl SyncResponse {
+    /// Serialize `value` as MessagePack and set it as the body of the response, along with the `application/msgpack`
+    /// content type. Structs are serialized as maps, keeping field names.
+    ///
+    /// If the value cannot be serialized, the server answers with a `500 Internal Server Error`, see `SerializationError`.
+    pub fn msgpack<T: Serialize>(&mut self, value: &T) -> &mut SyncResponse {
+        match profiled(self, || Phase::Serialization, |_| ::rmp_serde::to_vec_named(value)) {
+            Ok(body) => {
+                self.header(header::CONTENT_TYPE, "application/msgpack").body(body)
+            }
+            Err(e) => self.serialization_failed("msgpack", e),
+        }
+    }
+}
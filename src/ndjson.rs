@@ -0,0 +1,112 @@
+use http::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::thread;
+
+/// The content type of newline delimited JSON bodies, also known as JSON Lines
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// An iterator over the documents of a newline delimited JSON body, returned by `SyncRequest::body_ndjson`
+///
+/// Every line is deserialized on its own when the iterator gets to it, a line failing to deserialize yields an error
+/// without ending the iteration. Blank lines are skipped.
+pub struct NdjsonItems<'a, T> {
+    lines: ::std::slice::Split<'a, u8, fn(&u8) -> bool>,
+    item: PhantomData<fn() -> T>,
+}
+
+fn is_newline(byte: &u8) -> bool {
+    *byte == b'\n'
+}
+
+impl<'a, T: DeserializeOwned> Iterator for NdjsonItems<'a, T> {
+    type Item = Result<T, ::serde_json::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            let line = if line.last() == Some(&b'\r') { &line[..line.len() - 1] } else { line };
+            if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                continue;
+            }
+
+            return Some(::serde_json::from_slice(line));
+        }
+
+        None
+    }
+}
+
+impl SyncRequest {
+    /// Deserialize the body of the request as newline delimited JSON, one document per line
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # fn handler(req: &SyncRequest) {
+    /// for value in req.body_ndjson::<Vec<u32>>() {
+    ///     match value {
+    ///         Ok(value) => println!("{:?}", value),
+    ///         Err(e) => println!("invalid line: {}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn body_ndjson<T: DeserializeOwned>(&self) -> NdjsonItems<'_, T> {
+        NdjsonItems {
+            lines: self.body().split(is_newline as fn(&u8) -> bool),
+            item: PhantomData,
+        }
+    }
+}
+
+impl SyncResponse {
+    /// Stream `items` as newline delimited JSON, along with the `application/x-ndjson` content type
+    ///
+    /// Items are serialized on a thread of their own and every one of them is sent to the client as soon as it is produced,
+    /// see `SyncResponse::stream`, which suits both large exports and live feeds. If an item cannot be serialized, the body
+    /// ends with the items preceding it.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn events(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let events = (0..100).map(|id| vec![id, id * 2]);
+    ///     res.status(StatusCode::OK).ndjson(events);
+    /// }
+    /// ```
+    pub fn ndjson<I, T>(&mut self, items: I) -> &mut SyncResponse
+        where I: 'static + IntoIterator<Item=T> + Send,
+              I::IntoIter: Send,
+              T: Serialize {
+        self.headers_map_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static(NDJSON_CONTENT_TYPE));
+        let mut sender = self.stream();
+        let items = items.into_iter();
+
+        let spawned = thread::Builder::new().name("saphir-ndjson".to_string()).spawn(move || {
+            for item in items {
+                let mut line = match ::serde_json::to_vec(&item) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        error!("Unable to serialize an item of the response body as json: {}", e);
+                        return;
+                    }
+                };
+                line.push(b'\n');
+
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+
+        if let Err(e) = spawned {
+            error!("Unable to stream the response body as ndjson: {}", e);
+            self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+        }
+
+        self
+    }
+}
@@ -0,0 +1,209 @@
+use http::*;
+use serde::Serialize;
+
+/// Parse a header value made of a comma separated list of items with optional quality values, like `Accept`,
+/// `Accept-Language` or `Accept-Encoding`.
+///
+/// Items are returned along with their quality, sorted from the most to the least preferred. Items sharing the same quality
+/// keep their original order. Parameters other than `q` are kept as part of the item.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let items = parse_quality_list("text/html;q=0.8, application/json");
+/// assert_eq!(items, vec![("application/json".to_string(), 1.0), ("text/html".to_string(), 0.8)]);
+/// ```
+pub fn parse_quality_list(value: &str) -> Vec<(String, f32)> {
+    let mut items = Vec::new();
+
+    for item in value.split(',') {
+        let mut params = item.split(';');
+        let mut name = params.next().unwrap_or("").trim().to_string();
+        let mut quality = 1.0;
+
+        if name.is_empty() {
+            continue;
+        }
+
+        for param in params {
+            let param = param.trim();
+            if param.starts_with("q=") || param.starts_with("Q=") {
+                quality = param[2..].trim().parse::<f32>().ok().filter(|q| !q.is_nan()).unwrap_or(0.0).clamp(0.0, 1.0);
+            } else if !param.is_empty() {
+                name.push(';');
+                name.push_str(param);
+            }
+        }
+
+        items.push((name, quality));
+    }
+
+    // sort_by is stable, preserving the order of items with the same quality
+    items.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+    items
+}
+
+fn split_media_type(media_type: &str) -> (String, String) {
+    let essence = media_type.split(';').next().unwrap_or("").trim().to_lowercase();
+    let mut parts = essence.splitn(2, '/');
+    let type_ = parts.next().unwrap_or("").to_string();
+    let subtype = parts.next().unwrap_or("").to_string();
+
+    (type_, subtype)
+}
+
+/// Returns the quality of `media_type` according to the ranges of an `Accept` header, using the most specific matching range
+fn media_type_quality(ranges: &[(String, f32)], media_type: &str) -> f32 {
+    let (type_, subtype) = split_media_type(media_type);
+    let mut best: Option<(u8, f32)> = None;
+
+    for &(ref range, quality) in ranges {
+        let (range_type, range_subtype) = split_media_type(range);
+
+        let specificity = if range_type == type_ && range_subtype == subtype {
+            2
+        } else if range_type == type_ && range_subtype == "*" {
+            1
+        } else if range_type == "*" && range_subtype == "*" {
+            0
+        } else {
+            continue;
+        };
+
+        match best {
+            Some((s, _)) if s >= specificity => {}
+            _ => best = Some((specificity, quality)),
+        }
+    }
+
+    best.map(|(_, q)| q).unwrap_or(0.0)
+}
+
+impl SyncRequest {
+    /// Select the media type the client prefers amongst `offered`, according to the `Accept` header of the request.
+    ///
+    /// Returns `None` when none of the offered types are acceptable. When the request has no `Accept` header, the
+    /// first offered type is returned. Offered types with the same quality are prioritized in their order in `offered`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # fn handler(req: &SyncRequest, res: &mut SyncResponse) {
+    /// match req.accepts(&["application/json", "text/html"]) {
+    ///     Some("text/html") => { res.body("<p>Hello</p>"); }
+    ///     Some(_) => { res.json(&"Hello"); }
+    ///     None => { res.status(StatusCode::NOT_ACCEPTABLE); }
+    /// }
+    /// # }
+    /// ```
+    pub fn accepts<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let accept = self.headers_map().get_all(header::ACCEPT).iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<&str>>()
+            .join(",");
+
+        if accept.trim().is_empty() {
+            return offered.first().cloned();
+        }
+
+        let ranges = parse_quality_list(&accept);
+        let mut best: Option<(&'a str, f32)> = None;
+
+        for media_type in offered {
+            let quality = media_type_quality(&ranges, media_type);
+
+            if quality <= 0.0 {
+                continue;
+            }
+
+            match best {
+                Some((_, q)) if q >= quality => {}
+                _ => best = Some((media_type, quality)),
+            }
+        }
+
+        best.map(|(media_type, _)| media_type)
+    }
+
+    /// Select the content coding the client prefers amongst `offered`, like `br` or `gzip`, according to the
+    /// `Accept-Encoding` header of the request.
+    ///
+    /// Returns `None` when none of the offered codings are acceptable, or when the request has no `Accept-Encoding` header,
+    /// in which case the response should not be encoded. Offered codings with the same quality are prioritized in their
+    /// order in `offered`.
+    pub fn accepts_encoding<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let accept = self.headers_map().get_all(header::ACCEPT_ENCODING).iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<&str>>()
+            .join(",");
+        let codings = parse_quality_list(&accept);
+        let mut best: Option<(&'a str, f32)> = None;
+
+        for coding in offered {
+            let quality = codings.iter().find(|(name, _)| name.eq_ignore_ascii_case(coding))
+                .or_else(|| codings.iter().find(|(name, _)| name == "*"))
+                .map(|&(_, quality)| quality)
+                .unwrap_or(0.0);
+
+            if quality <= 0.0 {
+                continue;
+            }
+
+            match best {
+                Some((_, q)) if q >= quality => {}
+                _ => best = Some((coding, quality)),
+            }
+        }
+
+        best.map(|(coding, _)| coding)
+    }
+}
+
+/// Media types supported by `SyncResponse::negotiate`, in order of preference
+pub fn negotiable_media_types() -> Vec<&'static str> {
+    let mut media_types = vec!["application/json"];
+
+    if cfg!(feature = "xml") {
+        media_types.push("application/xml");
+    }
+
+    if cfg!(feature = "msgpack") {
+        media_types.push("application/msgpack");
+    }
+
+    if cfg!(feature = "cbor") {
+        media_types.push("application/cbor");
+    }
+
+    media_types
+}
+
+impl SyncResponse {
+    /// Serialize `value` in the format preferred by the client according to the `Accept` header of `req`.
+    ///
+    /// The supported formats are listed by `negotiable_media_types`. The response status is set to `406 Not Acceptable`
+    /// when none of them is acceptable.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).negotiate(req, &vec!["a", "b"]);
+    /// }
+    /// ```
+    pub fn negotiate<T: Serialize>(&mut self, req: &SyncRequest, value: &T) -> &mut SyncResponse {
+        match req.accepts(&negotiable_media_types()) {
+            Some("application/json") => self.json(value),
+            #[cfg(feature = "xml")]
+            Some("application/xml") => self.xml(value),
+            #[cfg(feature = "msgpack")]
+            Some("application/msgpack") => self.msgpack(value),
+            #[cfg(feature = "cbor")]
+            Some("application/cbor") => self.cbor(value),
+            _ => self.status(StatusCode::NOT_ACCEPTABLE),
+        }
+    }
+}
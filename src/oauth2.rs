@@ -0,0 +1,608 @@
+use http::*;
+use controller::Controller;
+use controller::RequestGuard;
+use controller::RouteInfo;
+use credentials::random_token;
+use problem::Problem;
+use query::percent_encode;
+use query::query_pairs;
+use utils::RequestContinuation;
+use futures::Future;
+use futures::Stream;
+use futures::sync::oneshot;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::runtime::Runtime;
+
+/// An error raised while completing an authorization-code flow
+#[derive(Debug)]
+pub enum OAuth2Error {
+    /// The token endpoint couldn't be reached, or refused the authorization code
+    Exchange(String),
+    /// The ID token returned by the token endpoint is malformed, expired, or wasn't issued for this login
+    InvalidIdToken(String),
+    /// The token endpoint isn't reached over `https`, which would expose the client secret and the tokens
+    InsecureTokenEndpoint(String),
+}
+
+impl fmt::Display for OAuth2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OAuth2Error::Exchange(ref e) => write!(f, "token exchange failed: {}", e),
+            OAuth2Error::InvalidIdToken(ref e) => write!(f, "invalid id token: {}", e),
+            OAuth2Error::InsecureTokenEndpoint(ref endpoint) => write!(f, "the token endpoint {} isn't reached over https", endpoint),
+        }
+    }
+}
+
+impl ::std::error::Error for OAuth2Error {}
+
+/// The settings of the client registered on an authorization server
+#[derive(Debug, Clone)]
+pub struct OAuth2Config {
+    /// The identifier of the client
+    pub client_id: String,
+    /// The secret of the client, sent to the token endpoint in the exchange form
+    pub client_secret: String,
+    /// The endpoint users are redirected to in order to log in
+    pub authorization_endpoint: String,
+    /// The endpoint authorization codes are exchanged at for tokens, which must be an `https` url
+    pub token_endpoint: String,
+    /// The absolute url of the callback route of the `OAuth2Controller`, as registered on the authorization server
+    pub redirect_uri: String,
+    /// The scopes requested, `openid` by default, which makes the authorization server an OpenID Connect provider
+    pub scopes: Vec<String>,
+    /// The expected `iss` claim of ID tokens, not checked when `None`
+    pub issuer: Option<String>,
+}
+
+impl OAuth2Config {
+    /// Create the settings of a client requesting the `openid` scope
+    pub fn new(client_id: &str, client_secret: &str, authorization_endpoint: &str, token_endpoint: &str, redirect_uri: &str) -> Self {
+        OAuth2Config {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            authorization_endpoint: authorization_endpoint.to_string(),
+            token_endpoint: token_endpoint.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            scopes: vec!["openid".to_string()],
+            issuer: None,
+        }
+    }
+
+    /// Request `scope` along with the previous ones
+    pub fn scope(mut self, scope: &str) -> Self {
+        self.scopes.push(scope.to_string());
+        self
+    }
+
+    /// Only accept ID tokens whose `iss` claim is `issuer`
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer = Some(issuer.to_string());
+        self
+    }
+}
+
+/// The successful answer of a token endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenResponse {
+    /// The access token, to call APIs on behalf of the user
+    pub access_token: String,
+    /// The type of the access token, usually `Bearer`
+    #[serde(default)]
+    pub token_type: String,
+    /// For how many seconds the access token is valid
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// The token to get a new access token once this one expired
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// The OpenID Connect ID token, identifying the user
+    #[serde(default)]
+    pub id_token: Option<String>,
+    /// The scopes granted, when they differ from the requested ones
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+/// Exchanges authorization codes for tokens at the token endpoint
+pub trait TokenExchanger: Send + Sync {
+    /// Post `form` to `token_endpoint` and parse its answer
+    fn exchange(&self, token_endpoint: &str, form: &[(&str, &str)]) -> Result<TokenResponse, OAuth2Error>;
+}
+
+/// The default `TokenExchanger`, posting the exchange form with the hyper client over `https`, the certificate of the
+/// token endpoint being verified against the Mozilla root certificates. Token endpoints which aren't `https` urls are
+/// refused.
+pub struct HttpTokenExchanger {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    runtime: Runtime,
+}
+
+impl HttpTokenExchanger {
+    /// Create an exchanger, with a runtime of its own driving its connections
+    ///
+    /// # Panics
+    ///
+    /// Panics if the runtime can't be started.
+    pub fn new() -> Self {
+        let runtime = Runtime::new().expect("Unable to start the token exchange runtime");
+        let client = Client::builder().executor(runtime.executor()).build(HttpsConnector::new(1));
+
+        HttpTokenExchanger {
+            client,
+            runtime,
+        }
+    }
+}
+
+impl Default for HttpTokenExchanger {
+    fn default() -> Self {
+        HttpTokenExchanger::new()
+    }
+}
+
+impl TokenExchanger for HttpTokenExchanger {
+    fn exchange(&self, token_endpoint: &str, form: &[(&str, &str)]) -> Result<TokenResponse, OAuth2Error> {
+        if !is_https(token_endpoint) {
+            return Err(OAuth2Error::InsecureTokenEndpoint(token_endpoint.to_string()));
+        }
+
+        let body = form.iter().map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value))).collect::<Vec<_>>().join("&");
+        let request = Request::post(token_endpoint)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::from(body))
+            .map_err(exchange_error)?;
+
+        let response = self.client.request(request).and_then(|response| {
+            let (parts, body) = response.into_parts();
+            body.concat2().map(move |body| (parts.status, body.to_vec()))
+        });
+        let (status, body) = oneshot::spawn(response, &self.runtime.executor()).wait().map_err(exchange_error)?;
+
+        if !status.is_success() {
+            return Err(OAuth2Error::Exchange(format!("the token endpoint answered {}: {}", status, String::from_utf8_lossy(&body))));
+        }
+
+        ::serde_json::from_slice(&body).map_err(exchange_error)
+    }
+}
+
+fn exchange_error<E: fmt::Display>(e: E) -> OAuth2Error {
+    OAuth2Error::Exchange(e.to_string())
+}
+
+/// Returns whether `url` is an absolute `https` url
+fn is_https(url: &str) -> bool {
+    url.parse::<Uri>().ok().is_some_and(|uri| uri.scheme_part().is_some_and(|scheme| scheme.as_str().eq_ignore_ascii_case("https")))
+}
+
+/// The user identified at the end of an authorization-code flow
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// The `sub` claim of the ID token, the identifier of the user at the provider
+    pub subject: Option<String>,
+    /// Every claim of the ID token, empty without one
+    pub claims: Map<String, Value>,
+    /// The tokens returned by the token endpoint
+    pub tokens: TokenResponse,
+}
+
+impl Identity {
+    /// Returns the claim `name` of the ID token
+    pub fn claim(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+}
+
+/// A login started by the login route, waiting for the callback
+struct PendingLogin {
+    nonce: String,
+    return_to: String,
+    started_at: Instant,
+}
+
+struct Session {
+    identity: Identity,
+    expires_at: Instant,
+}
+
+/// The logins in progress and the sessions of the logged in users
+#[derive(Default)]
+struct SessionStore {
+    pending: Mutex<HashMap<String, PendingLogin>>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// An OpenID Connect, or plain OAuth 2.0, client logging users in with the authorization-code flow
+///
+/// The `OAuth2Controller` starts logins, redirecting users to the authorization endpoint with a `state` and a `nonce`,
+/// handles the callback by exchanging the code for tokens and opening a session, and logs users out. The `state` is bound
+/// to the browser which started the login by a short-lived cookie, so a callback can't log someone into another account. Sessions are kept in
+/// memory, identified by a cookie; `OAuth2Guard` protects the routes only logged in users may reach, whose identity is
+/// then available through `identity`. Clones share the same sessions.
+///
+/// The ID token is validated against the `nonce` of the login, the client id, its expiry and, when set, the issuer. Its
+/// signature isn't checked: it is received directly from the token endpoint, whose TLS certificate authenticates the
+/// issuer instead, as OpenID Connect allows. The client therefore refuses token endpoints which aren't `https` urls.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let config = OAuth2Config::new("client", "secret", "https://idp.example.com/authorize", "https://idp.example.com/token",
+///                                "https://app.example.com/auth/callback").scope("email");
+/// let oauth2 = OAuth2Client::new(config).expect("an https token endpoint");
+///
+/// let mut controller = BasicController::new(oauth2.clone());
+/// controller.add_with_guards(Method::GET, "^/account$", oauth2.guard().login_redirect("/auth/login").into(), |oauth2, req, res| {
+///     let identity = oauth2.identity(req).expect("the guard let a logged in user through");
+///     res.status(StatusCode::OK).body(format!("Hello {}", identity.subject.unwrap_or_default()));
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/auth", oauth2.controller());
+/// router.add("^/account", controller);
+/// ```
+#[derive(Clone)]
+pub struct OAuth2Client {
+    config: Arc<OAuth2Config>,
+    exchanger: Arc<dyn TokenExchanger>,
+    store: Arc<SessionStore>,
+    cookie_name: String,
+    session_ttl: Duration,
+    login_ttl: Duration,
+}
+
+impl OAuth2Client {
+    /// Create a client exchanging codes with an `HttpTokenExchanger`, whose sessions last 8 hours, and logins 10 minutes.
+    /// Fails when the token endpoint of `config` isn't an `https` url.
+    pub fn new(config: OAuth2Config) -> Result<Self, OAuth2Error> {
+        if !is_https(&config.token_endpoint) {
+            return Err(OAuth2Error::InsecureTokenEndpoint(config.token_endpoint));
+        }
+
+        Ok(OAuth2Client {
+            config: Arc::new(config),
+            exchanger: Arc::new(HttpTokenExchanger::new()),
+            store: Arc::new(SessionStore::default()),
+            cookie_name: "saphir_session".to_string(),
+            session_ttl: Duration::from_secs(8 * 60 * 60),
+            login_ttl: Duration::from_secs(10 * 60),
+        })
+    }
+
+    /// Exchange codes with `exchanger`, like one with a certificate store of its own
+    pub fn token_exchanger<E: 'static + TokenExchanger>(mut self, exchanger: E) -> Self {
+        self.exchanger = Arc::new(exchanger);
+        self
+    }
+
+    /// Set the name of the session cookie, `saphir_session` by default
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Set for how long users stay logged in
+    pub fn session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    /// Set for how long users may take to log in at the authorization server
+    pub fn login_ttl(mut self, ttl: Duration) -> Self {
+        self.login_ttl = ttl;
+        self
+    }
+
+    /// Returns the controller handling the `login`, `callback` and `logout` routes, to register under the prefix of the
+    /// redirect uri
+    pub fn controller(&self) -> OAuth2Controller {
+        OAuth2Controller {
+            client: self.clone(),
+        }
+    }
+
+    /// Returns a guard letting through the requests of logged in users only
+    pub fn guard(&self) -> OAuth2Guard {
+        OAuth2Guard {
+            client: self.clone(),
+            login_redirect: None,
+        }
+    }
+
+    /// Returns the identity of the user who sent `req`, `None` if they aren't logged in
+    pub fn identity(&self, req: &SyncRequest) -> Option<Identity> {
+        let id = req.cookie(&self.cookie_name).filter(|id| !id.is_empty())?;
+        let sessions = self.store.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.get(id).filter(|session| session.expires_at > Instant::now()).map(|session| session.identity.clone())
+    }
+
+    /// Returns the `state` of a new login which ends back at `return_to`, along with the url of the authorization endpoint
+    /// starting it
+    fn start_login(&self, return_to: String) -> (String, String) {
+        let state = random_token();
+        let nonce = random_token();
+
+        let mut query = vec![
+            ("response_type", "code".to_string()),
+            ("client_id", self.config.client_id.clone()),
+            ("redirect_uri", self.config.redirect_uri.clone()),
+            ("scope", self.config.scopes.join(" ")),
+            ("state", state.clone()),
+        ];
+        if self.config.scopes.iter().any(|scope| scope == "openid") {
+            query.push(("nonce", nonce.clone()));
+        }
+
+        {
+            let mut pending = self.store.pending.lock().unwrap_or_else(|e| e.into_inner());
+            let login_ttl = self.login_ttl;
+            pending.retain(|_, login| login.started_at.elapsed() < login_ttl);
+            pending.insert(state.clone(), PendingLogin {
+                nonce,
+                return_to,
+                started_at: Instant::now(),
+            });
+        }
+
+        let separator = if self.config.authorization_endpoint.contains('?') { '&' } else { '?' };
+        let query = query.iter().map(|(name, value)| format!("{}={}", name, percent_encode(value))).collect::<Vec<_>>().join("&");
+        (state, format!("{}{}{}", self.config.authorization_endpoint, separator, query))
+    }
+
+    /// Exchange `code` for the identity of the user, and open their session
+    fn complete_login(&self, code: &str, login: &PendingLogin) -> Result<String, OAuth2Error> {
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("client_secret", self.config.client_secret.as_str()),
+        ];
+        let tokens = self.exchanger.exchange(&self.config.token_endpoint, &form)?;
+
+        let claims = match tokens.id_token {
+            Some(ref id_token) => self.validate_id_token(id_token, &login.nonce)?,
+            None if self.config.scopes.iter().any(|scope| scope == "openid") => {
+                return Err(OAuth2Error::InvalidIdToken("the token endpoint returned no id token".to_string()));
+            }
+            None => Map::new(),
+        };
+
+        let identity = Identity {
+            subject: claims.get("sub").and_then(|sub| sub.as_str()).map(|sub| sub.to_string()),
+            claims,
+            tokens,
+        };
+
+        let id = random_token();
+        let now = Instant::now();
+        let mut sessions = self.store.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(id.clone(), Session {
+            identity,
+            expires_at: now + self.session_ttl,
+        });
+
+        Ok(id)
+    }
+
+    fn validate_id_token(&self, id_token: &str, nonce: &str) -> Result<Map<String, Value>, OAuth2Error> {
+        let invalid = |reason: &str| OAuth2Error::InvalidIdToken(reason.to_string());
+
+        let payload = id_token.split('.').nth(1).ok_or_else(|| invalid("the token is not a JWT"))?;
+        let payload = ::base64::decode_config(payload.trim_end_matches('='), ::base64::URL_SAFE_NO_PAD)
+            .map_err(|_| invalid("the payload is not base64url"))?;
+        let claims: Map<String, Value> = ::serde_json::from_slice(&payload).map_err(|_| invalid("the payload is not a JSON object"))?;
+
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+            return Err(invalid("the nonce doesn't match the login"));
+        }
+
+        let audience = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == &self.config.client_id,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(self.config.client_id.as_str())),
+            _ => false,
+        };
+        if !audience {
+            return Err(invalid("the token wasn't issued for this client"));
+        }
+
+        if let Some(ref issuer) = self.config.issuer {
+            if claims.get("iss").and_then(|v| v.as_str()) != Some(issuer.as_str()) {
+                return Err(invalid("the token wasn't issued by the expected issuer"));
+            }
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        match claims.get("exp").and_then(|v| v.as_u64()) {
+            Some(exp) if exp > now => Ok(claims),
+            Some(_) => Err(invalid("the token expired")),
+            None => Err(invalid("the token has no expiry")),
+        }
+    }
+
+    fn session_cookie(&self, value: &str, max_age: u64) -> String {
+        self.cookie(&self.cookie_name, value, max_age)
+    }
+
+    /// The name of the cookie holding the `state` of the login started by the browser
+    fn login_cookie_name(&self) -> String {
+        format!("{}_login", self.cookie_name)
+    }
+
+    fn login_cookie(&self, value: &str, max_age: u64) -> String {
+        self.cookie(&self.login_cookie_name(), value, max_age)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64) -> String {
+        let secure = if self.config.redirect_uri.starts_with("https:") { "; Secure" } else { "" };
+        format!("{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}", name, value, max_age, secure)
+    }
+}
+
+/// The routes of an `OAuth2Client`: `GET .../login` redirects to the authorization server, accepting a relative
+/// `return_to` url, `GET .../callback` completes the login and `GET` or `POST .../logout` closes the session
+pub struct OAuth2Controller {
+    client: OAuth2Client,
+}
+
+impl OAuth2Controller {
+    fn login(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let return_to = req.uri().query().map(query_pairs).unwrap_or_default().into_iter()
+            .find(|(name, _)| name == "return_to")
+            .map(|(_, value)| value)
+            .filter(|value| is_local(value))
+            .unwrap_or_else(|| "/".to_string());
+
+        let (state, location) = self.client.start_login(return_to);
+        res.header(header::SET_COOKIE, self.client.login_cookie(&state, self.client.login_ttl.as_secs()))
+            .header(header::CACHE_CONTROL, "no-store")
+            .redirect(location);
+    }
+
+    fn callback(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let params: HashMap<String, String> = req.uri().query().map(query_pairs).unwrap_or_default().into_iter().collect();
+
+        // Only the browser which started the login may complete it, a callback forged for another browser isn't consumed
+        let login_cookie = req.cookie(&self.client.login_cookie_name());
+        let login = params.get("state").filter(|state| login_cookie == Some(state.as_str())).and_then(|state| {
+            self.client.store.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(state)
+        });
+        let login = match login {
+            Some(ref login) if login.started_at.elapsed() < self.client.login_ttl => login,
+            _ => {
+                res.problem(&Problem::new(StatusCode::BAD_REQUEST).with_detail("The login is unknown or expired, please log in again"));
+                return;
+            }
+        };
+
+        if let Some(error) = params.get("error") {
+            let detail = params.get("error_description").unwrap_or(error);
+            res.problem(&Problem::new(StatusCode::UNAUTHORIZED).with_detail(detail));
+            return;
+        }
+
+        let code = match params.get("code") {
+            Some(code) => code,
+            None => {
+                res.problem(&Problem::new(StatusCode::BAD_REQUEST).with_detail("The callback has no authorization code"));
+                return;
+            }
+        };
+
+        match self.client.complete_login(code, login) {
+            Ok(session) => {
+                let cookie = self.client.session_cookie(&session, self.client.session_ttl.as_secs());
+                res.header(header::SET_COOKIE, cookie)
+                    .header(header::SET_COOKIE, self.client.login_cookie("", 0))
+                    .header(header::CACHE_CONTROL, "no-store")
+                    .redirect(login.return_to.as_str());
+            }
+            Err(e @ OAuth2Error::Exchange(_)) | Err(e @ OAuth2Error::InsecureTokenEndpoint(_)) => {
+                warn!("Unable to complete a login: {}", e);
+                res.problem(&Problem::new(StatusCode::BAD_GATEWAY).with_detail(e.to_string()));
+            }
+            Err(e) => {
+                warn!("Unable to complete a login: {}", e);
+                res.problem(&Problem::new(StatusCode::UNAUTHORIZED).with_detail(e.to_string()));
+            }
+        }
+    }
+
+    fn logout(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if let Some(id) = req.cookie(&self.client.cookie_name) {
+            self.client.store.sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+        }
+
+        let return_to = req.uri().query().map(query_pairs).unwrap_or_default().into_iter()
+            .find(|(name, _)| name == "return_to")
+            .map(|(_, value)| value)
+            .filter(|value| is_local(value))
+            .unwrap_or_else(|| "/".to_string());
+
+        res.header(header::SET_COOKIE, self.client.session_cookie("", 0)).see_other(return_to.as_str());
+    }
+}
+
+impl Controller for OAuth2Controller {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let path = req.uri().path();
+
+        match *req.method() {
+            Method::GET if path.ends_with("/login") => self.login(req, res),
+            Method::GET if path.ends_with("/callback") => self.callback(req, res),
+            Method::GET | Method::POST if path.ends_with("/logout") => self.logout(req, res),
+            _ if path.ends_with("/login") || path.ends_with("/callback") || path.ends_with("/logout") => {
+                res.status(StatusCode::METHOD_NOT_ALLOWED);
+            }
+            _ => { res.status(StatusCode::NOT_FOUND); }
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        vec![
+            RouteInfo::new("OAuth2Controller", Some(Method::GET), Some("/login$".to_string()), Vec::new()),
+            RouteInfo::new("OAuth2Controller", Some(Method::GET), Some("/callback$".to_string()), Vec::new()),
+            RouteInfo::new("OAuth2Controller", Some(Method::GET), Some("/logout$".to_string()), Vec::new()),
+            RouteInfo::new("OAuth2Controller", Some(Method::POST), Some("/logout$".to_string()), Vec::new()),
+        ]
+    }
+}
+
+/// A guard letting through the requests of the users logged in with an `OAuth2Client`, see `OAuth2Client::guard`
+///
+/// Other requests are answered with `401 Unauthorized`, or redirected to the login route for `GET` requests when a login
+/// redirect is set.
+pub struct OAuth2Guard {
+    client: OAuth2Client,
+    login_redirect: Option<String>,
+}
+
+impl OAuth2Guard {
+    /// Redirect the `GET` requests of users who aren't logged in to `login_path`, coming back to the requested url
+    pub fn login_redirect(mut self, login_path: &str) -> Self {
+        self.login_redirect = Some(login_path.to_string());
+        self
+    }
+}
+
+impl RequestGuard for OAuth2Guard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if self.client.identity(req).is_some() {
+            return RequestContinuation::Next;
+        }
+
+        match self.login_redirect {
+            Some(ref login_path) if *req.method() == Method::GET => {
+                let return_to = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or_else(|| req.uri().path());
+                let separator = if login_path.contains('?') { '&' } else { '?' };
+                res.redirect(format!("{}{}return_to={}", login_path, separator, percent_encode(return_to)));
+            }
+            _ => {
+                res.problem(&Problem::new(StatusCode::UNAUTHORIZED).with_detail("Please log in"));
+            }
+        }
+
+        RequestContinuation::None
+    }
+}
+
+/// Returns whether `url` is a path of this server, which logins may safely return to
+fn is_local(url: &str) -> bool {
+    url.starts_with('/') && !url.starts_with("//") && !url.starts_with("/\\")
+}
@@ -0,0 +1,335 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use router::Router;
+use route_index::path_template;
+use route_metadata::RouteMetadata;
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::gen::SchemaSettings;
+use schemars::schema::Schema;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+type SchemaFn = Box<dyn Fn(&mut SchemaGenerator) -> Schema + Send + Sync>;
+
+fn schema_of<T: JsonSchema>() -> SchemaFn {
+    Box::new(|gen| gen.subschema_for::<T>())
+}
+
+/// Documentation of a single operation, used to complete what is inferred from the registered routes
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate saphir;
+/// # #[macro_use] extern crate schemars;
+/// # use saphir::*;
+/// #[derive(JsonSchema)]
+/// struct User {
+///     name: String,
+/// }
+///
+/// # fn main() {
+/// let doc = RouteDoc::new()
+///     .summary("Fetch a user")
+///     .tag("users")
+///     .response::<User>(200, "The user")
+///     .empty_response(404, "No such user");
+/// # }
+/// ```
+#[derive(Default)]
+pub struct RouteDoc {
+    summary: Option<String>,
+    description: Option<String>,
+    operation_id: Option<String>,
+    tags: Vec<String>,
+    request_body: Option<SchemaFn>,
+    responses: Vec<(u16, String, Option<SchemaFn>)>,
+}
+
+impl RouteDoc {
+    /// Create an empty documentation
+    pub fn new() -> Self {
+        RouteDoc::default()
+    }
+
+    /// Set a short summary of the operation
+    pub fn summary<S: Into<String>>(mut self, summary: S) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Set a verbose description of the operation
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Set the unique identifier of the operation
+    pub fn operation_id<S: Into<String>>(mut self, operation_id: S) -> Self {
+        self.operation_id = Some(operation_id.into());
+        self
+    }
+
+    /// Add a tag grouping the operation with others
+    pub fn tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Document the JSON body the operation expects
+    pub fn request_body<T: JsonSchema>(mut self) -> Self {
+        self.request_body = Some(schema_of::<T>());
+        self
+    }
+
+    /// Document a response with a JSON body
+    pub fn response<T: JsonSchema>(mut self, status: u16, description: &str) -> Self {
+        self.responses.push((status, description.to_string(), Some(schema_of::<T>())));
+        self
+    }
+
+    /// Document a response without body
+    pub fn empty_response(mut self, status: u16, description: &str) -> Self {
+        self.responses.push((status, description.to_string(), None));
+        self
+    }
+}
+
+/// A builder generating an OpenAPI 3 specification from the routes of a `Router`
+///
+/// Every route registered with a method is listed, its path template and path parameters are inferred from its regular
+/// expression, see `Router::routes`, and its summary, description, operation id, tags and responses are the ones of the
+/// `RouteMetadata` it was registered with, if any. Routes can be further documented with a `RouteDoc`, which is where
+/// request and response schemas are declared using `schemars`, its fields taking precedence over the route metadata.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::GET, "^/users/(?P<id>\\d+)$", |_, _, res| { res.status(StatusCode::OK); });
+///
+/// let mut router = Router::new();
+/// router.add("^/users", controller);
+///
+/// let openapi = OpenApi::new("Users", "1.0.0")
+///     .route(Method::GET, "/users/{id}", RouteDoc::new().summary("Fetch a user"));
+///
+/// let docs = openapi.controller(&router);
+/// router.add("^/docs", docs);
+/// ```
+pub struct OpenApi {
+    title: String,
+    version: String,
+    description: Option<String>,
+    docs: Vec<(Method, String, RouteDoc)>,
+}
+
+impl OpenApi {
+    /// Create a new builder for an API named `title` at version `version`
+    pub fn new<T: Into<String>, V: Into<String>>(title: T, version: V) -> Self {
+        OpenApi {
+            title: title.into(),
+            version: version.into(),
+            description: None,
+            docs: Vec::new(),
+        }
+    }
+
+    /// Set the description of the API
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Document the operation `method` on the path template `path`, e.g. `/users/{id}`
+    pub fn route<P: Into<String>>(mut self, method: Method, path: P, doc: RouteDoc) -> Self {
+        self.docs.push((method, path.into(), doc));
+        self
+    }
+
+    /// Generate the specification of the routes of `router`
+    pub fn spec(&self, router: &Router) -> Value {
+        let mut gen = SchemaSettings::openapi3().into_generator();
+        let mut paths = BTreeMap::new();
+
+        for route in router.routes() {
+            let (method, pattern) = match route {
+                RouteInfo { method: Some(method), pattern: Some(pattern), .. } => (method, pattern),
+                _ => continue,
+            };
+
+            let (template, params) = path_template(&pattern);
+            let doc = self.docs.iter().find(|d| d.0 == method && d.1 == template).map(|d| &d.2);
+            let operation = Self::operation(&mut gen, &params, route.metadata.as_ref(), doc);
+
+            paths.entry(template).or_insert_with(Map::new)
+                .insert(method.as_str().to_lowercase(), operation);
+        }
+
+        let mut info = json!({ "title": self.title, "version": self.version });
+        if let Some(ref description) = self.description {
+            info["description"] = json!(description);
+        }
+
+        let schemas = gen.take_definitions();
+
+        json!({
+            "openapi": "3.0.3",
+            "info": info,
+            "paths": paths,
+            "components": { "schemas": schemas },
+        })
+    }
+
+    /// Generate the specification of the routes of `router` and create a controller serving it, see `OpenApiController`
+    pub fn controller(&self, router: &Router) -> OpenApiController {
+        OpenApiController::new(self.spec(router))
+    }
+
+    fn operation(gen: &mut SchemaGenerator, params: &[(String, bool)], metadata: Option<&RouteMetadata>, doc: Option<&RouteDoc>) -> Value {
+        let parameters = params.iter().map(|&(ref name, integer)| json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": { "type": if integer { "integer" } else { "string" } },
+        })).collect::<Vec<_>>();
+
+        let mut operation = json!({
+            "parameters": parameters,
+            "responses": { "default": { "description": "Response" } },
+        });
+
+        if let Some(metadata) = metadata {
+            if let Some(summary) = metadata.get_summary() {
+                operation["summary"] = json!(summary);
+            }
+
+            if let Some(description) = metadata.get_description() {
+                operation["description"] = json!(description);
+            }
+
+            if let Some(operation_id) = metadata.get_operation_id() {
+                operation["operationId"] = json!(operation_id);
+            }
+
+            if !metadata.get_tags().is_empty() {
+                operation["tags"] = json!(metadata.get_tags());
+            }
+
+            if !metadata.get_responses().is_empty() {
+                let responses = metadata.get_responses().iter()
+                    .map(|response| (response.status.to_string(), json!({ "description": response.description })))
+                    .collect::<Map<_, _>>();
+                operation["responses"] = Value::Object(responses);
+            }
+        }
+
+        let doc = match doc {
+            Some(doc) => doc,
+            None => return operation,
+        };
+
+        if let Some(ref summary) = doc.summary {
+            operation["summary"] = json!(summary);
+        }
+
+        if let Some(ref description) = doc.description {
+            operation["description"] = json!(description);
+        }
+
+        if let Some(ref operation_id) = doc.operation_id {
+            operation["operationId"] = json!(operation_id);
+        }
+
+        if !doc.tags.is_empty() {
+            operation["tags"] = json!(doc.tags);
+        }
+
+        if let Some(ref schema) = doc.request_body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema(gen) } },
+            });
+        }
+
+        if !doc.responses.is_empty() {
+            let mut responses = Map::new();
+
+            for &(status, ref description, ref schema) in &doc.responses {
+                let mut response = json!({ "description": description });
+                if let Some(ref schema) = *schema {
+                    response["content"] = json!({ "application/json": { "schema": schema(gen) } });
+                }
+                responses.insert(status.to_string(), response);
+            }
+
+            operation["responses"] = Value::Object(responses);
+        }
+
+        operation
+    }
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>API documentation</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>window.ui = SwaggerUIBundle({ url: {{SPEC_URL}}, dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// A controller serving an OpenAPI specification as `<route>/openapi.json` and a Swagger UI page as `<route>`
+pub struct OpenApiController {
+    spec: Vec<u8>,
+}
+
+impl OpenApiController {
+    /// Create a controller serving `spec`, usually generated by `OpenApi::spec`
+    pub fn new(spec: Value) -> Self {
+        OpenApiController {
+            spec: ::serde_json::to_vec(&spec).unwrap_or_default(),
+        }
+    }
+}
+
+impl Controller for OpenApiController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED);
+            return;
+        }
+
+        let path = req.uri().path().trim_end_matches('/');
+
+        if path.ends_with("/openapi.json") {
+            res.status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(self.spec.clone());
+        } else {
+            // The url is embedded as a json string, with `<` escaped so it cannot close the script element
+            let url = ::serde_json::to_string(&format!("{}/openapi.json", path)).unwrap_or_default().replace('<', "\\u003c");
+            let page = SWAGGER_UI.replace("{{SPEC_URL}}", &url);
+            res.status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                .body(page);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        ["/openapi.json$", "/?$"].iter().flat_map(|path| {
+            vec![Method::GET, Method::HEAD].into_iter()
+                .map(move |method| RouteInfo::new("OpenApiController", Some(method), Some(path.to_string()), Vec::new()))
+        }).collect()
+    }
+}
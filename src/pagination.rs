@@ -0,0 +1,262 @@
+use http::*;
+use problem::Problem;
+use query::percent_encode;
+use query::query_pairs;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+/// The bounds of the pages requested by clients, see `SyncRequest::page_request`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pagination {
+    default_per_page: u64,
+    max_per_page: u64,
+}
+
+impl Pagination {
+    /// Create bounds of 20 items per page by default, and at most 100
+    pub fn new() -> Self {
+        Pagination {
+            default_per_page: 20,
+            max_per_page: 100,
+        }
+    }
+
+    /// Set the number of items of the pages requested without `per_page`
+    pub fn default_per_page(mut self, per_page: u64) -> Self {
+        self.default_per_page = per_page.max(1);
+        self
+    }
+
+    /// Set the number of items a page holds at most, larger pages are reduced to it
+    pub fn max_per_page(mut self, per_page: u64) -> Self {
+        self.max_per_page = per_page.max(1);
+        self
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Pagination::new()
+    }
+}
+
+/// The page of a collection requested by a client, from the `page`, `per_page` and `cursor` parameters of its query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRequest {
+    page: u64,
+    per_page: u64,
+    cursor: Option<String>,
+}
+
+impl PageRequest {
+    /// Returns the number of the page, from 1
+    pub fn page(&self) -> u64 {
+        self.page
+    }
+
+    /// Returns the number of items of the page
+    pub fn per_page(&self) -> u64 {
+        self.per_page
+    }
+
+    /// Returns the cursor the page starts at, for collections paginated by cursor
+    pub fn cursor(&self) -> Option<&str> {
+        self.cursor.as_deref()
+    }
+
+    /// Returns the number of items preceding the page, for collections paginated by offset
+    pub fn offset(&self) -> u64 {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+}
+
+impl SyncRequest {
+    /// Returns the page of a collection requested by the `page`, `per_page` and `cursor` parameters of the query, within
+    /// `pagination`. Pages larger than allowed are reduced to the maximum, while numbers which aren't positive integers
+    /// are rejected, see `PageRejection`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn list(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let request = match req.page_request(&Pagination::new().max_per_page(50)) {
+    ///         Ok(request) => request,
+    ///         Err(rejection) => { res.problem(&rejection.problem()); return; }
+    ///     };
+    ///
+    ///     let users: Vec<String> = (request.offset()..93).take(request.per_page() as usize).map(|id| format!("user {}", id)).collect();
+    ///     res.page(req, &Page::new(users, &request).total(93));
+    /// }
+    /// ```
+    pub fn page_request(&self, pagination: &Pagination) -> Result<PageRequest, PageRejection> {
+        let mut request = PageRequest { page: 1, per_page: pagination.default_per_page.min(pagination.max_per_page), cursor: None };
+
+        for (name, value) in query_pairs(self.uri().query().unwrap_or("")) {
+            let number = || match value.parse::<u64>() {
+                Ok(number) if number > 0 => Ok(number),
+                _ => Err(PageRejection { parameter: name.clone() }),
+            };
+
+            match name.as_str() {
+                "page" => request.page = number()?,
+                "per_page" => request.per_page = number()?.min(pagination.max_per_page),
+                "cursor" if !value.is_empty() => request.cursor = Some(value.clone()),
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+/// Why the page requested by a client was rejected, a number which isn't a positive integer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageRejection {
+    parameter: String,
+}
+
+impl PageRejection {
+    /// Returns the name of the invalid parameter
+    pub fn parameter(&self) -> &str {
+        &self.parameter
+    }
+
+    /// Describe the rejection as a `400 Bad Request` problem document
+    pub fn problem(&self) -> Problem {
+        Problem::new(StatusCode::BAD_REQUEST).with_detail(self.to_string())
+    }
+}
+
+impl fmt::Display for PageRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The {} parameter must be a positive integer", self.parameter)
+    }
+}
+
+impl Error for PageRejection {}
+
+/// A page of a collection, answered by `SyncResponse::page`
+///
+/// The page is serialized as an envelope holding the `items` along with the `page` and `per_page` of the request, the
+/// `total` number of items when it is known and the `next_cursor` of collections paginated by cursor.
+#[derive(Debug, Clone, Serialize)]
+pub struct Page<T> {
+    items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u64>,
+    per_page: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    #[serde(skip)]
+    cursor: bool,
+}
+
+impl<T> Page<T> {
+    /// Create the page of `items` answering `request`
+    pub fn new(items: Vec<T>, request: &PageRequest) -> Self {
+        Page {
+            items,
+            page: if request.cursor.is_some() { None } else { Some(request.page) },
+            per_page: request.per_page,
+            total: None,
+            next_cursor: None,
+            cursor: request.cursor.is_some(),
+        }
+    }
+
+    /// Set the number of items of the whole collection, announcing the last page
+    pub fn total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Set the cursor the next page starts at, for collections paginated by cursor. A page without one is the last.
+    pub fn next_cursor<C: Into<String>>(mut self, cursor: C) -> Self {
+        self.next_cursor = Some(cursor.into());
+        self.page = None;
+        self.cursor = true;
+        self
+    }
+
+    /// Returns the items of the page
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Returns the number of the last page, when the total is known
+    fn last(&self) -> Option<u64> {
+        self.total.map(|total| total.div_ceil(self.per_page).max(1))
+    }
+
+    /// Returns the links to the pages surrounding this one, along with their relation, for the `Link` header
+    fn links(&self, req: &SyncRequest) -> Vec<(&'static str, String)> {
+        let mut links = Vec::new();
+        // The size of the pages is kept when the client asked for it, as reduced to the maximum
+        let sized = query_pairs(req.uri().query().unwrap_or("")).iter().any(|(name, _)| name == "per_page");
+        let link = |param: Option<(&str, String)>| {
+            let per_page = if sized { Some(("per_page", self.per_page.to_string())) } else { None };
+            page_url(req, &per_page.into_iter().chain(param).collect::<Vec<_>>())
+        };
+
+        if self.cursor {
+            links.push(("first", link(None)));
+            if let Some(ref cursor) = self.next_cursor {
+                links.push(("next", link(Some(("cursor", cursor.clone())))));
+            }
+            return links;
+        }
+
+        let page = self.page.unwrap_or(1);
+        let has_next = match self.last() {
+            Some(last) => page < last,
+            None => self.items.len() as u64 >= self.per_page,
+        };
+
+        links.push(("first", link(Some(("page", "1".to_string())))));
+        if page > 1 {
+            let previous = self.last().map_or(page - 1, |last| (page - 1).min(last));
+            links.push(("prev", link(Some(("page", previous.to_string())))));
+        }
+        if has_next {
+            links.push(("next", link(Some(("page", (page + 1).to_string())))));
+        }
+        if let Some(last) = self.last() {
+            links.push(("last", link(Some(("page", last.to_string())))));
+        }
+
+        links
+    }
+}
+
+/// Returns the path and query of `req`, its page parameters replaced by `params`
+fn page_url(req: &SyncRequest, params: &[(&str, String)]) -> String {
+    let pairs = query_pairs(req.uri().query().unwrap_or("")).into_iter()
+        .filter(|(name, _)| name != "page" && name != "per_page" && name != "cursor")
+        .chain(params.iter().map(|(name, value)| (name.to_string(), value.clone())))
+        .map(|(name, value)| format!("{}={}", percent_encode(&name), percent_encode(&value)))
+        .collect::<Vec<_>>();
+
+    if pairs.is_empty() {
+        req.uri().path().to_string()
+    } else {
+        format!("{}?{}", req.uri().path(), pairs.join("&"))
+    }
+}
+
+impl SyncResponse {
+    /// Answer with `page` as JSON, see `Page`, along with a `Link` header to the `first`, `prev`, `next` and `last` pages as
+    /// described by RFC 8288, and the total number of items in the `X-Total-Count` header when it is known
+    pub fn page<T: Serialize>(&mut self, req: &SyncRequest, page: &Page<T>) -> &mut SyncResponse {
+        let links = page.links(req).into_iter().map(|(rel, url)| format!("<{}>; rel=\"{}\"", url, rel)).collect::<Vec<_>>();
+        self.header(header::LINK, links.join(", "));
+        if let Some(total) = page.total {
+            self.header("x-total-count", total.to_string());
+        }
+
+        self.json(page)
+    }
+}
@@ -0,0 +1,109 @@
+use argon2::Argon2;
+use argon2::PasswordHasher;
+use argon2::PasswordVerifier;
+use argon2::password_hash::PasswordHash;
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The default cost of `PasswordAlgorithm::Bcrypt`
+pub const DEFAULT_BCRYPT_COST: u32 = 12;
+
+/// An error raised while hashing or verifying a password
+#[derive(Debug)]
+pub enum PasswordError {
+    /// The stored hash is not an argon2 nor a bcrypt hash in the PHC or modular crypt format
+    InvalidHash(String),
+    /// The password couldn't be hashed, like with a bcrypt cost out of range
+    Hashing(String),
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PasswordError::InvalidHash(ref e) => write!(f, "invalid password hash: {}", e),
+            PasswordError::Hashing(ref e) => write!(f, "unable to hash the password: {}", e),
+        }
+    }
+}
+
+impl ::std::error::Error for PasswordError {}
+
+/// The algorithms passwords are hashed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PasswordAlgorithm {
+    /// Argon2id with the default parameters of the `argon2` crate, the recommended algorithm
+    #[default]
+    Argon2id,
+    /// bcrypt with the given cost, between 4 and 31 and `DEFAULT_BCRYPT_COST` usually, for compatibility with existing user databases. Only the first 72 bytes
+    /// of a password are hashed.
+    Bcrypt(u32),
+}
+
+/// Hash `password` with a random salt, returning a string holding the algorithm, its parameters, the salt and the hash,
+/// to store as is
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// let hash = hash_password("correct horse battery staple", PasswordAlgorithm::Argon2id).unwrap();
+/// assert!(hash.starts_with("$argon2id$"));
+/// assert!(verify_password("correct horse battery staple", &hash).unwrap());
+/// assert!(!verify_password("Tr0ub4dor&3", &hash).unwrap());
+/// ```
+pub fn hash_password(password: &str, algorithm: PasswordAlgorithm) -> Result<String, PasswordError> {
+    match algorithm {
+        PasswordAlgorithm::Argon2id => {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default().hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|e| PasswordError::Hashing(e.to_string()))
+        }
+        PasswordAlgorithm::Bcrypt(cost) => ::bcrypt::hash(password, cost).map_err(|e| PasswordError::Hashing(e.to_string())),
+    }
+}
+
+/// Returns whether `password` matches `hash`, produced by `hash_password` or by any other argon2 or bcrypt implementation.
+/// The comparison takes the same time whatever the password.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, PasswordError> {
+    if is_bcrypt(hash) {
+        return ::bcrypt::verify(password, hash).map_err(|e| PasswordError::InvalidHash(e.to_string()));
+    }
+
+    let parsed = PasswordHash::new(hash).map_err(|e| PasswordError::InvalidHash(e.to_string()))?;
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) => Ok(true),
+        Err(::argon2::password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(PasswordError::InvalidHash(e.to_string())),
+    }
+}
+
+/// Returns whether `hash` should be replaced by a new hash of the password, once verified, because it wasn't produced by
+/// `algorithm` with its current parameters, like a bcrypt hash after moving to argon2id, or a lower bcrypt cost
+pub fn password_needs_rehash(hash: &str, algorithm: PasswordAlgorithm) -> bool {
+    match algorithm {
+        PasswordAlgorithm::Bcrypt(cost) => {
+            !is_bcrypt(hash) || hash.get(4..6).and_then(|c| c.parse::<u32>().ok()).is_none_or(|current| current < cost)
+        }
+        PasswordAlgorithm::Argon2id => {
+            let parsed = match PasswordHash::new(hash) {
+                Ok(parsed) => parsed,
+                Err(_) => return true,
+            };
+            let current = match ::argon2::Params::try_from(&parsed) {
+                Ok(params) => params,
+                Err(_) => return true,
+            };
+            let default = ::argon2::Params::default();
+
+            parsed.algorithm.as_str() != "argon2id" || current.m_cost() < default.m_cost() || current.t_cost() < default.t_cost()
+                || current.p_cost() < default.p_cost()
+        }
+    }
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2x$") || hash.starts_with("$2y$")
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+/// An error raised when a request path can't be normalized safely
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathError {
+    /// The path contains an encoded NUL byte
+    NulByte,
+    /// The path contains a malformed percent-encoding, or percent-encoded bytes which are not valid UTF-8, like overlong
+    /// encodings of `/` or `.`
+    InvalidEncoding,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PathError::NulByte => write!(f, "the path contains a NUL byte"),
+            PathError::InvalidEncoding => write!(f, "the path contains an invalid percent-encoding"),
+        }
+    }
+}
+
+impl ::std::error::Error for PathError {}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'-' || b == b'.' || b == b'_' || b == b'~'
+}
+
+/// Check that the consecutive percent-encoded bytes in `pending` form valid UTF-8, which rules out overlong encodings like
+/// `%C0%AE`
+fn check_encoded(pending: &mut Vec<u8>) -> Result<(), PathError> {
+    if ::std::str::from_utf8(pending).is_err() {
+        return Err(PathError::InvalidEncoding);
+    }
+
+    pending.clear();
+    Ok(())
+}
+
+/// Decode the percent-encoded unreserved characters of `segment`, and uppercase the remaining percent-encodings
+fn normalize_segment(segment: &str) -> Result<String, PathError> {
+    let bytes = segment.as_bytes();
+    let mut normalized = String::with_capacity(segment.len());
+    let mut pending = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            check_encoded(&mut pending)?;
+            normalized.push(bytes[i] as char);
+            i += 1;
+            continue;
+        }
+
+        let value = match (bytes.get(i + 1).cloned().and_then(hex_value), bytes.get(i + 2).cloned().and_then(hex_value)) {
+            (Some(high), Some(low)) => high << 4 | low,
+            _ => return Err(PathError::InvalidEncoding),
+        };
+
+        if value == 0 {
+            return Err(PathError::NulByte);
+        }
+
+        if is_unreserved(value) {
+            normalized.push(value as char);
+        } else {
+            normalized.push_str(&format!("%{:02X}", value));
+        }
+
+        pending.push(value);
+        i += 3;
+    }
+
+    check_encoded(&mut pending)?;
+    Ok(normalized)
+}
+
+/// Normalize a request path so equivalent paths are matched the same way, and can safely be mapped to files
+///
+/// - Percent-encoded unreserved characters are decoded, `/%7Euser` becomes `/~user`, other encodings are kept with uppercase
+///   hexadecimal digits
+/// - Empty segments are collapsed, `/a//b` becomes `/a/b`
+/// - Dot segments are resolved, including encoded ones, `/a/./b/../c` becomes `/a/c`; `..` never goes above the root
+/// - Encoded NUL bytes, malformed encodings and encoded bytes which are not valid UTF-8 are rejected
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// assert_eq!(normalize_path("/static/%2e%2e//etc/./passwd"), Ok("/etc/passwd".to_string()));
+/// assert_eq!(normalize_path("/static/%00.png"), Err(PathError::NulByte));
+/// ```
+pub fn normalize_path(path: &str) -> Result<String, PathError> {
+    let mut segments: Vec<String> = Vec::new();
+    let raw_segments: Vec<&str> = path.split('/').collect();
+    let mut trailing_slash = false;
+
+    for (index, raw) in raw_segments.iter().enumerate() {
+        let is_last = index == raw_segments.len() - 1;
+        let segment = normalize_segment(raw)?;
+
+        match segment.as_str() {
+            "" => {
+                trailing_slash = is_last && index > 0;
+            }
+            "." => {
+                trailing_slash = is_last;
+            }
+            ".." => {
+                segments.pop();
+                trailing_slash = is_last;
+            }
+            _ => {
+                segments.push(segment);
+                trailing_slash = false;
+            }
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+
+    if normalized.is_empty() || trailing_slash {
+        normalized.push('/');
+    }
+
+    Ok(normalized)
+}
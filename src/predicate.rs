@@ -0,0 +1,170 @@
+use http::*;
+use utils::ToRegex;
+use query::query_pairs;
+use std::fmt;
+use std::ops::{BitAnd, BitOr, Not};
+use std::sync::Arc;
+
+type Test = Arc<dyn Fn(&SyncRequest) -> bool + Send + Sync>;
+
+/// A condition on requests, like their method, path, headers, query or content type, see `Middleware::when` and
+/// `ControllerDispatch::add_when`
+///
+/// Predicates are combined with `and`, `or` and negated with `!`, and describe themselves for introspection.
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// let probes = RequestPredicate::path("^/health") | RequestPredicate::websocket();
+/// let writes = RequestPredicate::method(Method::POST) & RequestPredicate::content_type("application/json");
+/// assert_eq!(format!("{}", !probes), "not (path ^/health or websocket upgrade)");
+/// # let _ = writes;
+/// ```
+#[derive(Clone)]
+pub struct RequestPredicate {
+    test: Test,
+    description: String,
+}
+
+impl RequestPredicate {
+    /// Create a predicate from a custom test, described as `description`
+    pub fn new<D, F>(description: D, test: F) -> Self
+        where D: Into<String>, F: 'static + Fn(&SyncRequest) -> bool + Send + Sync {
+        RequestPredicate {
+            test: Arc::new(test),
+            description: description.into(),
+        }
+    }
+
+    /// Match the requests of `method`
+    pub fn method(method: Method) -> Self {
+        let description = format!("method {}", method);
+        RequestPredicate::new(description, move |req| *req.method() == method)
+    }
+
+    /// Match the requests whose path matches the regular expression `path`
+    pub fn path<R: ToRegex>(path: R) -> Self {
+        let path = reg!(path);
+        let description = format!("path {}", path.as_str());
+        RequestPredicate::new(description, move |req| path.is_match(req.uri().path()))
+    }
+
+    /// Match the requests carrying the header `name`
+    pub fn header(name: &'static str) -> Self {
+        RequestPredicate::new(format!("header {}", name), move |req| req.headers_map().contains_key(name))
+    }
+
+    /// Match the requests with a header `name` of `value`, ignoring the case
+    pub fn header_value(name: &'static str, value: &str) -> Self {
+        let value = value.to_string();
+        let description = format!("header {}: {}", name, value);
+        RequestPredicate::new(description, move |req| {
+            req.header_values(name).iter()
+                .any(|v| v.to_str().map(|v| v.trim().eq_ignore_ascii_case(&value)).unwrap_or(false))
+        })
+    }
+
+    /// Match the requests with the query parameter `name`
+    pub fn query(name: &str) -> Self {
+        let name = name.to_string();
+        let description = format!("query {}", name);
+        RequestPredicate::new(description, move |req| {
+            query_pairs(req.uri().query().unwrap_or("")).iter().any(|(param, _)| *param == name)
+        })
+    }
+
+    /// Match the requests with a query parameter `name` of `value`
+    pub fn query_value(name: &str, value: &str) -> Self {
+        let (name, value) = (name.to_string(), value.to_string());
+        let description = format!("query {}={}", name, value);
+        RequestPredicate::new(description, move |req| {
+            query_pairs(req.uri().query().unwrap_or("")).iter().any(|(param, v)| *param == name && *v == value)
+        })
+    }
+
+    /// Match the requests whose body is of `media_type`, ignoring its parameters, like `application/json`. A media type
+    /// ending with `/*`, like `image/*`, matches every subtype.
+    pub fn content_type(media_type: &str) -> Self {
+        let media_type = media_type.to_ascii_lowercase();
+        let description = format!("content type {}", media_type);
+        RequestPredicate::new(description, move |req| {
+            let essence = match req.headers_map().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+                Some(content_type) => content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase(),
+                None => return false,
+            };
+
+            match media_type.strip_suffix("/*") {
+                Some(kind) => essence.split('/').next() == Some(kind),
+                None => essence == media_type,
+            }
+        })
+    }
+
+    /// Match the requests asking to upgrade the connection to a websocket
+    pub fn websocket() -> Self {
+        RequestPredicate::new("websocket upgrade", |req| {
+            req.header_list(header::UPGRADE).iter().any(|protocol| protocol.eq_ignore_ascii_case("websocket"))
+                && req.header_list(header::CONNECTION).iter().any(|option| option.eq_ignore_ascii_case("upgrade"))
+        })
+    }
+
+    /// Match the requests matching both this predicate and `other`
+    pub fn and(self, other: RequestPredicate) -> Self {
+        let description = format!("{} and {}", self.description, other.description);
+        RequestPredicate::new(description, move |req| self.matches(req) && other.matches(req))
+    }
+
+    /// Match the requests matching this predicate or `other`
+    pub fn or(self, other: RequestPredicate) -> Self {
+        let description = format!("{} or {}", self.description, other.description);
+        RequestPredicate::new(description, move |req| self.matches(req) || other.matches(req))
+    }
+
+    /// Returns whether `req` matches the predicate
+    pub fn matches(&self, req: &SyncRequest) -> bool {
+        (self.test)(req)
+    }
+
+    /// Returns the description of the predicate
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl Not for RequestPredicate {
+    type Output = RequestPredicate;
+
+    fn not(self) -> RequestPredicate {
+        let description = format!("not ({})", self.description);
+        RequestPredicate::new(description, move |req| !self.matches(req))
+    }
+}
+
+impl BitAnd for RequestPredicate {
+    type Output = RequestPredicate;
+
+    fn bitand(self, other: RequestPredicate) -> RequestPredicate {
+        self.and(other)
+    }
+}
+
+impl BitOr for RequestPredicate {
+    type Output = RequestPredicate;
+
+    fn bitor(self, other: RequestPredicate) -> RequestPredicate {
+        self.or(other)
+    }
+}
+
+impl fmt::Debug for RequestPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("RequestPredicate").field(&self.description).finish()
+    }
+}
+
+impl fmt::Display for RequestPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
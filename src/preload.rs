@@ -0,0 +1,137 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use regex::Regex;
+
+/// A middleware announcing the stylesheets and scripts referenced by HTML responses as `Link: rel=preload` headers
+///
+/// Once a successful `text/html` response is computed, its body is scanned for `<link rel="stylesheet">`,
+/// `<link rel="preload">`, `<link rel="modulepreload">` and `<script src>` elements referencing resources of the same
+/// origin, and each of them is added as a `Link` header of the response, which browsers preload from. Resources the
+/// handler already announced in a `Link` header are not repeated, and streamed bodies are never scanned.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(PreloadMiddleware::new().limit(8), vec!("/"), None);
+/// ```
+pub struct PreloadMiddleware {
+    limit: usize,
+    element: Regex,
+    attribute: Regex,
+}
+
+impl PreloadMiddleware {
+    /// Create a new middleware announcing up to 16 resources per response
+    pub fn new() -> Self {
+        PreloadMiddleware {
+            limit: 16,
+            element: Regex::new(r"(?i)<(link|script)\b([^>]*)>").expect("valid element regex"),
+            attribute: Regex::new(r#"([a-zA-Z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).expect("valid attribute regex"),
+        }
+    }
+
+    /// Announce up to `limit` resources per response, in the order they appear in the document
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Returns the `Link` header values of the resources referenced by `html`
+    fn links(&self, html: &str) -> Vec<(String, String)> {
+        let mut links = Vec::new();
+
+        for element in self.element.captures_iter(html) {
+            let mut rel = None;
+            let mut href = None;
+            let mut src = None;
+            let mut destination = None;
+            let mut module = false;
+
+            for attribute in self.attribute.captures_iter(&element[2]) {
+                let value = attribute.get(2).or_else(|| attribute.get(3)).or_else(|| attribute.get(4)).map_or("", |v| v.as_str());
+                match attribute[1].to_ascii_lowercase().as_str() {
+                    "rel" => rel = Some(value.to_ascii_lowercase()),
+                    "href" => href = Some(value),
+                    "src" => src = Some(value),
+                    "as" => destination = Some(value.to_ascii_lowercase()),
+                    "type" => module = value.eq_ignore_ascii_case("module"),
+                    _ => {}
+                }
+            }
+
+            let link = if element[1].eq_ignore_ascii_case("script") {
+                src.map(|src| if module {
+                    (src, "rel=modulepreload".to_string())
+                } else {
+                    (src, "rel=preload; as=script".to_string())
+                })
+            } else {
+                let rels: Vec<&str> = rel.as_deref().unwrap_or("").split_whitespace().collect();
+                href.and_then(|href| {
+                    if rels.contains(&"stylesheet") {
+                        Some((href, "rel=preload; as=style".to_string()))
+                    } else if rels.contains(&"modulepreload") {
+                        Some((href, "rel=modulepreload".to_string()))
+                    } else if rels.contains(&"preload") {
+                        destination.as_ref().map(|destination| (href, format!("rel=preload; as={}", destination)))
+                    } else {
+                        None
+                    }
+                })
+            };
+
+            if let Some((target, params)) = link {
+                // Preloading another origin requires a matching `crossorigin` attribute, which the header can't guess
+                if target.starts_with('/') && !target.starts_with("//") && !links.iter().any(|(t, _)| t == target) {
+                    links.push((target.to_string(), params));
+                }
+            }
+        }
+
+        links
+    }
+}
+
+impl Default for PreloadMiddleware {
+    fn default() -> Self {
+        PreloadMiddleware::new()
+    }
+}
+
+/// Returns whether `target` is the target of one of the `Link` header values of `links`
+fn announced<'a, I: IntoIterator<Item = &'a str>>(links: I, target: &str) -> bool {
+    let target = format!("<{}>", target);
+    links.into_iter().any(|value| value.split(',').any(|link| link.trim().starts_with(&target)))
+}
+
+impl Middleware for PreloadMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET || res.get_status() != StatusCode::OK || res.is_streamed() {
+            return;
+        }
+
+        let html = res.headers_map().get(header::CONTENT_TYPE).and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim_start().to_ascii_lowercase().starts_with("text/html"));
+        if !html {
+            return;
+        }
+
+        let body = res.get_body();
+        let links = self.links(&String::from_utf8_lossy(&body));
+
+        let links: Vec<String> = links.into_iter().filter(|(target, _)| {
+            !announced(res.headers_map().get_all(header::LINK).iter().filter_map(|v| v.to_str().ok()), target)
+        }).take(self.limit).map(|(target, params)| format!("<{}>; {}", target, params)).collect();
+
+        for link in links {
+            res.header(header::LINK, link);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use http::*;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+use std::fmt;
+
+/// The media type of a `Problem` document
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// An RFC 7807 problem details document, describing why a request failed
+///
+/// Every error generated by saphir itself, from routing failures to rejected guards and validation errors, is answered
+/// with a problem document. Members other than the standard ones are carried as extensions, like the `errors` listed by a
+/// `ValidationRejection`.
+///
+/// # Example
+///
+/// ```rust
+/// # extern crate saphir;
+/// # use saphir::*;
+/// # fn main() {
+/// let problem = Problem::new(StatusCode::FORBIDDEN)
+///     .with_type("https://example.com/probs/out-of-credit")
+///     .with_title("You do not have enough credit")
+///     .with_detail("Your current balance is 30, but that costs 50.")
+///     .with_instance("/account/12345/msgs/abc")
+///     .with_extension("balance", 30);
+///
+/// let mut response = SyncResponse::new();
+/// response.problem(&problem);
+/// assert_eq!(response.get_status(), StatusCode::FORBIDDEN);
+/// assert_eq!(response.headers_map()[header::CONTENT_TYPE], "application/problem+json");
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    /// A URI reference identifying the problem type, `about:blank` when the status code says it all
+    #[serde(rename = "type", default = "about_blank")]
+    pub problem_type: String,
+    /// A short summary of the problem type
+    #[serde(default)]
+    pub title: String,
+    /// The status code of the response
+    pub status: u16,
+    /// An explanation specific to this occurrence of the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// A URI reference identifying this occurrence of the problem, like the request path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Additional members of the document
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+fn about_blank() -> String {
+    "about:blank".to_string()
+}
+
+impl Problem {
+    /// Create a problem of type `about:blank` for `status`, titled with the reason phrase of the status
+    pub fn new(status: StatusCode) -> Self {
+        Problem {
+            problem_type: about_blank(),
+            title: status.canonical_reason().unwrap_or("").to_string(),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Set the URI identifying the problem type
+    pub fn with_type<T: Into<String>>(mut self, problem_type: T) -> Self {
+        self.problem_type = problem_type.into();
+        self
+    }
+
+    /// Set the summary of the problem type
+    pub fn with_title<T: Into<String>>(mut self, title: T) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// Set the explanation of this occurrence of the problem
+    pub fn with_detail<D: Into<String>>(mut self, detail: D) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /// Set the URI identifying this occurrence of the problem
+    pub fn with_instance<I: Into<String>>(mut self, instance: I) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Add the extension member `name`. Values which can't be represented as JSON are skipped.
+    pub fn with_extension<N: Into<String>, V: Serialize>(mut self, name: N, value: V) -> Self {
+        match ::serde_json::to_value(value) {
+            Ok(value) => { self.extensions.insert(name.into(), value); }
+            Err(e) => warn!("Unable to serialize a problem extension: {}", e),
+        }
+        self
+    }
+
+    /// The status code of the problem, `500 Internal Server Error` if it holds an invalid code
+    pub fn status(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.title)?;
+        if let Some(ref detail) = self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl ::std::error::Error for Problem {}
+
+impl SyncResponse {
+    /// Set the status of `problem` and describe it with an `application/problem+json` body
+    pub fn problem(&mut self, problem: &Problem) -> &mut SyncResponse {
+        match ::serde_json::to_vec(problem) {
+            Ok(body) => self.status(problem.status()).header(header::CONTENT_TYPE, PROBLEM_CONTENT_TYPE).body(body),
+            Err(e) => {
+                error!("Unable to serialize a problem: {}", e);
+                self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new())
+            }
+        }
+    }
+}
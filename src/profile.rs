@@ -0,0 +1,132 @@
+use http::*;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::panic;
+use std::str::FromStr;
+use std::sync::Once;
+use std::time::Duration;
+
+/// The environment variable selecting the profile of servers built without one, see `Profile::from_env`
+pub const PROFILE_ENV_VAR: &str = "SAPHIR_PROFILE";
+
+/// For how long `ResponseCacheMiddleware` caches responses without freshness information under the production profile,
+/// unless it was given a default max age of its own
+pub const PRODUCTION_CACHE_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// A set of defaults suited to where the server runs, see `ServerBuilder::profile`
+///
+/// | | Development | Production |
+/// |---|---|---|
+/// | Server errors | described with their error chain and backtrace, on a page for browsers, see `DeveloperError` | answered with a terse `500`, only logged |
+/// | Templates | reloaded from disk before every render | loaded once |
+/// | Responses without `Cache-Control` | sent with `Cache-Control: no-store` | left as is |
+/// | `ResponseCacheMiddleware` | bypassed | caches responses without freshness information for a minute |
+///
+/// Without a profile, panics are answered with a terse `500`, templates are reloaded in debug builds and responses are
+/// only cached when their freshness is explicit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Profile {
+    /// Verbose errors and no caching, to see changes and problems right away
+    #[serde(rename = "dev", alias = "development")]
+    Development,
+    /// Terse errors and aggressive caching
+    #[serde(rename = "prod", alias = "production")]
+    Production,
+}
+
+impl Profile {
+    /// Returns the profile named by the `SAPHIR_PROFILE` environment variable, `None` when it is unset or invalid
+    pub fn from_env() -> Option<Profile> {
+        let value = env::var(PROFILE_ENV_VAR).ok()?;
+
+        match value.parse() {
+            Ok(profile) => Some(profile),
+            Err(_) => {
+                warn!("Ignoring the invalid profile {} of {}, expected dev or prod", value, PROFILE_ENV_VAR);
+                None
+            }
+        }
+    }
+
+    /// Returns whether this is the development profile
+    pub fn is_development(&self) -> bool {
+        *self == Profile::Development
+    }
+
+    /// Returns whether this is the production profile
+    pub fn is_production(&self) -> bool {
+        *self == Profile::Production
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Profile::Development => "dev",
+            Profile::Production => "prod",
+        })
+    }
+}
+
+impl FromStr for Profile {
+    type Err = ();
+
+    /// Parse `dev` or `development`, and `prod` or `production`, ignoring case
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Development),
+            "prod" | "production" => Ok(Profile::Production),
+            _ => Err(()),
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Returns the profile of the server processing the request, `None` when it has none
+    pub fn profile(&self) -> Option<Profile> {
+        self.extensions().get::<Profile>().cloned()
+    }
+}
+
+impl SyncResponse {
+    /// Returns the profile of the server processing the request, `None` when it has none
+    pub fn profile(&self) -> Option<Profile> {
+        self.get_extensions().get::<Profile>().cloned()
+    }
+}
+
+thread_local!(static LAST_BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) });
+
+static CAPTURE_BACKTRACES: Once = Once::new();
+
+/// Record the backtrace of every panic, for the development profile to describe the panics of the handlers. The panic hook
+/// installed before keeps being invoked.
+pub(crate) fn capture_backtraces() {
+    CAPTURE_BACKTRACES.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::force_capture().to_string();
+            LAST_BACKTRACE.with(|last| *last.borrow_mut() = Some(backtrace));
+            previous(info);
+        }));
+    });
+}
+
+/// Returns the backtrace of the last panic of the current thread, when backtraces are captured
+pub(crate) fn take_backtrace() -> Option<String> {
+    LAST_BACKTRACE.with(|last| last.borrow_mut().take())
+}
+
+/// Returns the message a panic was raised with
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "the handler panicked".to_string()
+    }
+}
@@ -0,0 +1,293 @@
+use http::*;
+use metrics::Metrics;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many of the last requests of each route the percentiles are computed over, unless set with `Profiler::window`
+pub const DEFAULT_PROFILE_WINDOW: usize = 1000;
+
+/// A phase of the processing of a request, timed by the `Profiler`
+///
+/// Phases don't overlap: the time a phase spends in another one, like a handler serializing its response, is only
+/// accounted to the inner one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Preparing the path of the request and matching it against the routes of the router and of the controller
+    Routing,
+    /// Running the `prepare`, `resolve` and `after` of a middleware, named after it
+    Middleware(String),
+    /// Validating the request with the guards of its route
+    Guards,
+    /// Running the controller, or the delegate of the route
+    Handler,
+    /// Serializing the response body with `SyncResponse::json`, `xml`, `msgpack` or `cbor`
+    Serialization,
+}
+
+impl Phase {
+    /// The position of the phase in the reports, the middlewares keeping the order they were first reached in
+    fn rank(&self) -> u8 {
+        match *self {
+            Phase::Routing => 0,
+            Phase::Middleware(_) => 1,
+            Phase::Guards => 2,
+            Phase::Handler => 3,
+            Phase::Serialization => 4,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Phase::Routing => f.write_str("routing"),
+            Phase::Middleware(ref name) => write!(f, "middleware:{}", name),
+            Phase::Guards => f.write_str("guards"),
+            Phase::Handler => f.write_str("handler"),
+            Phase::Serialization => f.write_str("serialization"),
+        }
+    }
+}
+
+/// The time spent in each phase by a request, kept in the extensions of its response while the server profiles requests
+#[derive(Default)]
+pub(crate) struct RequestProfile {
+    phases: Vec<(Phase, Duration)>,
+    recorded: Duration,
+}
+
+impl RequestProfile {
+    fn add(&mut self, phase: Phase, duration: Duration) {
+        match self.phases.iter_mut().find(|(recorded, _)| *recorded == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+        self.recorded += duration;
+    }
+}
+
+/// Run `f`, accounting the time it took to `phase` when the request of `res` is profiled, minus the time of the phases
+/// recorded meanwhile
+pub(crate) fn profiled<T, P, F>(res: &mut SyncResponse, phase: P, f: F) -> T
+    where P: FnOnce() -> Phase,
+          F: FnOnce(&mut SyncResponse) -> T
+{
+    let before = match res.get_extensions().get::<RequestProfile>() {
+        Some(profile) => profile.recorded,
+        None => return f(res),
+    };
+
+    let started = Instant::now();
+    let value = f(res);
+    let elapsed = started.elapsed();
+
+    if let Some(profile) = res.get_extensions_mut().get_mut::<RequestProfile>() {
+        let nested = profile.recorded.saturating_sub(before);
+        profile.add(phase(), elapsed.saturating_sub(nested));
+    }
+
+    value
+}
+
+/// Account the time elapsed since `started` to `phase` when the request of `res` is profiled
+pub(crate) fn record_since(res: &mut SyncResponse, phase: Phase, started: Instant) {
+    if let Some(profile) = res.get_extensions_mut().get_mut::<RequestProfile>() {
+        profile.add(phase, started.elapsed());
+    }
+}
+
+/// The durations of the last requests, and how many were recorded in total
+#[derive(Default)]
+struct Samples {
+    durations: VecDeque<Duration>,
+    count: u64,
+}
+
+impl Samples {
+    fn push(&mut self, duration: Duration, window: usize) {
+        if self.durations.len() >= window {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(duration);
+        self.count += 1;
+    }
+
+    fn stats(&self, phase: String) -> PhaseStats {
+        let mut sorted: Vec<Duration> = self.durations.iter().cloned().collect();
+        sorted.sort();
+
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        // The nearest rank of the percentile `p`
+        let percentile = |p: f64| sorted.get(((p * sorted.len() as f64).ceil() as usize).max(1) - 1).cloned().map_or(0.0, millis);
+        let total: Duration = sorted.iter().sum();
+
+        PhaseStats {
+            phase,
+            count: self.count,
+            mean_ms: if sorted.is_empty() { 0.0 } else { millis(total) / sorted.len() as f64 },
+            p50_ms: percentile(0.5),
+            p90_ms: percentile(0.9),
+            p99_ms: percentile(0.99),
+            max_ms: sorted.last().cloned().map_or(0.0, millis),
+        }
+    }
+}
+
+#[derive(Default)]
+struct RouteSamples {
+    total: Samples,
+    phases: Vec<(Phase, Samples)>,
+}
+
+/// The statistics of a phase over the last requests of a route, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseStats {
+    /// The phase, see `Phase`, or `total` for the whole processing of the requests
+    pub phase: String,
+    /// How many requests went through the phase since the profiler was created or reset
+    pub count: u64,
+    /// The mean duration
+    pub mean_ms: f64,
+    /// The median duration
+    pub p50_ms: f64,
+    /// The 90th percentile of the durations
+    pub p90_ms: f64,
+    /// The 99th percentile of the durations
+    pub p99_ms: f64,
+    /// The longest duration
+    pub max_ms: f64,
+}
+
+/// Where the requests of a route spent their time, see `Profiler::report`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RouteProfile {
+    /// The method of the requests
+    pub method: String,
+    /// The label of the route, see `SyncResponse::route_label`, or `unmatched`
+    pub route: String,
+    /// The time spent processing the requests, from the routing to the last middleware
+    pub total: PhaseStats,
+    /// The time spent in each phase, in the order requests go through them
+    pub phases: Vec<PhaseStats>,
+}
+
+/// Records where requests spend their time, per route: the routing, each middleware, the guards, the handler and the
+/// serialization of the response, to find hot spots without external tooling
+///
+/// Profiling is opt-in, the server only times the requests once the profiler is registered with
+/// `ServerBuilder::profiling`. The percentiles are computed over the last requests of each route, see `Profiler::window`,
+/// and exposed as JSON by the `AdminController` under `<prefix>/profile`. With `Profiler::metrics`, every phase is also
+/// observed in the `http_request_phase_duration_seconds` histogram, labeled by method, route and phase. Clones share
+/// the same samples.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let metrics = Metrics::new();
+/// let profiler = Profiler::new().metrics(metrics.clone());
+/// let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+///
+/// let server = Server::builder()
+///     .router(Router::new())
+///     .profiling(profiler.clone())
+///     .admin_endpoint("/_admin", AdminController::new(loopback).metrics(metrics).profiler(profiler))
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct Profiler {
+    routes: Arc<Mutex<BTreeMap<(String, String), RouteSamples>>>,
+    window: usize,
+    metrics: Option<Metrics>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            routes: Arc::new(Mutex::new(BTreeMap::new())),
+            window: DEFAULT_PROFILE_WINDOW,
+            metrics: None,
+        }
+    }
+}
+
+impl Profiler {
+    /// Create a profiler keeping the last `DEFAULT_PROFILE_WINDOW` requests of each route
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    /// Compute the percentiles over the last `requests` requests of each route
+    pub fn window(mut self, requests: usize) -> Self {
+        self.window = requests.max(1);
+        self
+    }
+
+    /// Also observe the duration of every phase in the `http_request_phase_duration_seconds` histogram of `metrics`
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        metrics.describe("http_request_phase_duration_seconds", "Time spent in each phase of the requests, by method, route and phase");
+        self.metrics = Some(metrics);
+        self
+    }
+
+    fn routes(&self) -> MutexGuard<'_, BTreeMap<(String, String), RouteSamples>> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Record the phases of a request of `method` handled by `route`
+    pub(crate) fn record(&self, method: &Method, route: &str, profile: RequestProfile) {
+        {
+            let mut routes = self.routes();
+            let samples = routes.entry((method.to_string(), route.to_string())).or_default();
+            samples.total.push(profile.recorded, self.window);
+            for (phase, duration) in &profile.phases {
+                match samples.phases.iter_mut().find(|(recorded, _)| recorded == phase) {
+                    Some((_, phase_samples)) => phase_samples.push(*duration, self.window),
+                    None => {
+                        let mut phase_samples = Samples::default();
+                        phase_samples.push(*duration, self.window);
+                        samples.phases.push((phase.clone(), phase_samples));
+                    }
+                }
+            }
+        }
+
+        if let Some(ref metrics) = self.metrics {
+            for (phase, duration) in &profile.phases {
+                let labels = [("method", method.as_str()), ("route", route), ("phase", &phase.to_string())];
+                metrics.observe("http_request_phase_duration_seconds", &labels, duration.as_secs_f64());
+            }
+        }
+    }
+
+    /// Returns where the requests of each route spent their time, the routes spending the most time first
+    pub fn report(&self) -> Vec<RouteProfile> {
+        let routes = self.routes();
+        let mut report: Vec<(Duration, RouteProfile)> = routes.iter().map(|((method, route), samples)| {
+            let mut phases: Vec<&(Phase, Samples)> = samples.phases.iter().collect();
+            phases.sort_by_key(|(phase, _)| phase.rank());
+
+            let spent = samples.total.durations.iter().sum();
+            (spent, RouteProfile {
+                method: method.clone(),
+                route: route.clone(),
+                total: samples.total.stats("total".to_string()),
+                phases: phases.into_iter().map(|(phase, samples)| samples.stats(phase.to_string())).collect(),
+            })
+        }).collect();
+
+        report.sort_by_key(|(spent, _)| ::std::cmp::Reverse(*spent));
+        report.into_iter().map(|(_, profile)| profile).collect()
+    }
+
+    /// Forget every request recorded so far
+    pub fn reset(&self) {
+        self.routes().clear();
+    }
+}
@@ -0,0 +1,125 @@
+use http::*;
+use prost::Message;
+use std::fmt;
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+/// Default maximum size of a protobuf request body, in bytes
+pub const DEFAULT_PROTOBUF_LIMIT: usize = 4 * 1024 * 1024;
+
+/// Errors that can occur while extracting a `Protobuf` message from a request
+#[derive(Debug)]
+pub enum ProtobufError {
+    /// The request content type is not `application/x-protobuf`
+    UnsupportedContentType,
+    /// The request body is larger than the allowed limit
+    PayloadTooLarge,
+    /// The request body is not a valid encoding of the expected message
+    Decode(::prost::DecodeError),
+}
+
+impl ProtobufError {
+    /// The status code a response should carry when the extraction fails
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ProtobufError::UnsupportedContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ProtobufError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ProtobufError::Decode(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for ProtobufError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtobufError::UnsupportedContentType => write!(f, "Unsupported content type, expected application/x-protobuf"),
+            ProtobufError::PayloadTooLarge => write!(f, "Protobuf payload too large"),
+            ProtobufError::Decode(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl ::std::error::Error for ProtobufError {}
+
+fn is_protobuf_content_type(req: &SyncRequest) -> bool {
+    req.headers_map().get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            let essence = v.split(';').next().unwrap_or("").trim().to_lowercase();
+            essence == "application/x-protobuf" || essence == "application/protobuf"
+        })
+        .unwrap_or(false)
+}
+
+/// A protobuf message extracted from a request body or sent as a response body
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[macro_use] extern crate prost;
+/// # extern crate saphir;
+/// # use saphir::*;
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Ping {
+///     #[prost(string, tag = "1")]
+///     message: String,
+/// }
+///
+/// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+///     match Protobuf::<Ping>::from_request(req) {
+///         Ok(ping) => { res.status(StatusCode::OK).protobuf(&*ping); }
+///         Err(e) => { res.status(e.status()); }
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Protobuf<T>(pub T);
+
+impl<T: Message + Default> Protobuf<T> {
+    /// Decode the message from the body of `req`, validating its content type and enforcing `DEFAULT_PROTOBUF_LIMIT`
+    pub fn from_request(req: &SyncRequest) -> Result<Self, ProtobufError> {
+        Self::from_request_with_limit(req, DEFAULT_PROTOBUF_LIMIT)
+    }
+
+    /// Decode the message from the body of `req`, validating its content type and refusing bodies larger than `limit` bytes
+    pub fn from_request_with_limit(req: &SyncRequest, limit: usize) -> Result<Self, ProtobufError> {
+        if !is_protobuf_content_type(req) {
+            return Err(ProtobufError::UnsupportedContentType);
+        }
+
+        if req.body().len() > limit {
+            return Err(ProtobufError::PayloadTooLarge);
+        }
+
+        T::decode(req.body().as_slice()).map(Protobuf).map_err(ProtobufError::Decode)
+    }
+}
+
+impl<T> Protobuf<T> {
+    /// Unwrap the message
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Protobuf<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Protobuf<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl SyncResponse {
+    /// Encode `message` and set it as the body of the response, along with the `application/x-protobuf` content type
+    pub fn protobuf<T: Message>(&mut self, message: &T) -> &mut SyncResponse {
+        self.header(header::CONTENT_TYPE, "application/x-protobuf").body(message.encode_to_vec())
+    }
+}
@@ -0,0 +1,603 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use error::ServerError;
+use proxy_cache::ProxyCache;
+use proxy_cache::answer_cached;
+use proxy_cache::CacheKey;
+use response_cache::CacheControlDirectives;
+use response_cache::CachedResponse;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use futures::sync::oneshot;
+use hyper::Chunk;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use tokio::runtime::Builder as RuntimeBuilder;
+use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Headers describing a single connection, which must not be forwarded by a proxy
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Remove the hop-by-hop headers from `headers`, including the ones listed by the `Connection` header
+pub fn strip_hop_by_hop_headers(headers: &mut header::HeaderMap<header::HeaderValue>) {
+    let listed: Vec<String> = headers.get_all(header::CONNECTION).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS.iter().cloned()) {
+        headers.remove(name);
+    }
+}
+
+/// How a `ProxyController` picks the upstream a request is forwarded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoadBalancing {
+    /// Each upstream is used in turn
+    #[default]
+    RoundRobin,
+    /// The upstream with the fewest requests in progress is used, ties are broken in the order upstreams were added
+    LeastConnections,
+}
+
+/// An upstream server requests can be forwarded to by a `ProxyController`
+///
+/// Upstreams are passively health checked: after `max_fails` consecutive requests failing to get a response, because the
+/// upstream can't be reached or doesn't answer within its timeout, it is not used for `fail_timeout`. When every upstream is
+/// considered unhealthy, they are all tried anyway.
+pub struct Upstream {
+    base_url: Uri,
+    timeout: Option<Duration>,
+    retries: usize,
+    max_fails: usize,
+    fail_timeout: Duration,
+    in_flight: AtomicUsize,
+    failures: AtomicUsize,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    /// Create an `http` upstream at `base_url`, without timeout nor retries, considered unhealthy for 10 seconds after 3
+    /// consecutive failures
+    pub fn new(base_url: &str) -> Result<Self, ServerError> {
+        let base_url: Uri = base_url.parse()?;
+
+        if base_url.scheme_part() != Some(&::http_types::uri::Scheme::HTTP) || base_url.authority_part().is_none() {
+            return Err(ServerError::UnsupportedUriScheme);
+        }
+
+        Ok(Upstream {
+            base_url,
+            timeout: None,
+            retries: 0,
+            max_fails: 3,
+            fail_timeout: Duration::from_secs(10),
+            in_flight: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            down_until: Mutex::new(None),
+        })
+    }
+
+    /// Fail the requests for which the upstream doesn't send the head of its response within `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Forward an idempotent request to another upstream, up to `retries` times, when this upstream fails to answer it
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Consider the upstream unhealthy for `fail_timeout` after `max_fails` consecutive failures. A `max_fails` of 0 disables
+    /// health checking.
+    pub fn max_fails(mut self, max_fails: usize, fail_timeout: Duration) -> Self {
+        self.max_fails = max_fails;
+        self.fail_timeout = fail_timeout;
+        self
+    }
+
+    /// Returns whether the upstream is currently considered healthy
+    pub fn is_healthy(&self) -> bool {
+        match *self.down_until.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    /// Returns the number of requests currently forwarded to the upstream
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    fn succeeded(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+        *self.down_until.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    fn failed(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+
+        if self.max_fails > 0 && failures >= self.max_fails {
+            warn!("Upstream {} failed {} times in a row, not using it for {:?}", self.base_url, failures, self.fail_timeout);
+            self.failures.store(0, Ordering::SeqCst);
+            *self.down_until.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    fn uri(&self, uri: &Uri, strip_prefix: Option<&str>) -> Result<Uri, ::http_types::Error> {
+        let mut path = uri.path();
+
+        // The prefix only matches whole segments, `/api` is removed from `/api/users` but not from `/apiv2/users`
+        if let Some(rest) = strip_prefix.and_then(|prefix| path.strip_prefix(prefix.trim_end_matches('/'))) {
+            if rest.is_empty() || rest.starts_with('/') {
+                path = rest;
+            }
+        }
+
+        let base = self.base_url.path().trim_end_matches('/');
+        let mut target = format!("{}/{}", base, path.trim_start_matches('/'));
+
+        if let Some(query) = uri.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+
+        Uri::builder()
+            .scheme("http")
+            .authority(self.base_url.authority_part().map(|a| a.as_str()).unwrap_or_default())
+            .path_and_query(target.as_str())
+            .build()
+    }
+}
+
+/// Methods which can safely be sent again to another upstream when the first one failed
+pub(crate) fn is_idempotent(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::PUT, Method::DELETE, Method::OPTIONS, Method::TRACE].contains(method)
+}
+
+/// Why a request could not be forwarded to an upstream
+enum ForwardError {
+    Timeout,
+    Unreachable(String),
+}
+
+/// A controller forwarding the requests it handles to upstream servers, making saphir usable as the front of an API gateway
+///
+/// The path and query of the request are appended to the path of the upstream base url, after removing the prefix set with
+/// `strip_prefix`. The `Host` header is rewritten to the authority of the upstream, the original one being forwarded in
+/// `X-Forwarded-Host`, while the address of the client is appended to `X-Forwarded-For`. Hop-by-hop headers are removed in both
+/// directions.
+///
+/// Requests are balanced amongst the upstreams according to the `LoadBalancing` strategy, see `Upstream` for timeouts,
+/// retries and health checks.
+///
+/// Request bodies are forwarded as they were received along with their headers, even when a middleware replaced them, see
+/// `SyncRequest::raw_body`. They are loaded before the controller is invoked, unless the route leaves them unread, see
+/// `BodyLimit::buffered`, in which case they are streamed to the upstream without retries. Response bodies are
+/// streamed to the client as they are received from the upstream. When no upstream answers, `502 Bad Gateway` is returned, or
+/// `504 Gateway Timeout` if the last one tried timed out.
+///
+/// The responses of the upstreams can be cached according to their `Cache-Control` and revalidated with their `ETag`,
+/// see `ProxyCache`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let mut router = Router::new();
+/// router.add("^/api", ProxyController::new("http://127.0.0.1:8080/v1").unwrap().strip_prefix("/api"));
+///
+/// let search = ProxyController::with_upstreams(vec![
+///     Upstream::new("http://10.0.0.1:8080").unwrap().timeout(Duration::from_secs(2)).retries(1),
+///     Upstream::new("http://10.0.0.2:8080").unwrap().timeout(Duration::from_secs(2)).retries(1),
+/// ]).load_balancing(LoadBalancing::LeastConnections);
+/// router.add("^/search", search);
+/// ```
+pub struct ProxyController {
+    upstreams: Vec<Upstream>,
+    load_balancing: LoadBalancing,
+    next: AtomicUsize,
+    strip_prefix: Option<String>,
+    cache: Option<Arc<ProxyCache>>,
+    client: Client<HttpConnector, Body>,
+    runtime: Runtime,
+}
+
+impl ProxyController {
+    /// Create a controller forwarding requests to the `http` upstream `base_url`
+    pub fn new(base_url: &str) -> Result<Self, ServerError> {
+        Ok(ProxyController::with_upstreams(vec![Upstream::new(base_url)?]))
+    }
+
+    /// Create a controller balancing requests amongst `upstreams`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `upstreams` is empty, or if the runtime handling the upstream connections can't be started.
+    pub fn with_upstreams(upstreams: Vec<Upstream>) -> Self {
+        assert!(!upstreams.is_empty(), "A proxy needs at least one upstream");
+
+        let runtime = RuntimeBuilder::new().name_prefix("saphir-proxy-").build().expect("Unable to start the proxy runtime");
+        let client = Client::builder().executor(runtime.executor()).build_http();
+
+        ProxyController {
+            upstreams,
+            load_balancing: LoadBalancing::default(),
+            next: AtomicUsize::new(0),
+            strip_prefix: None,
+            cache: None,
+            client,
+            runtime,
+        }
+    }
+
+    /// Set how upstreams are picked, `LoadBalancing::RoundRobin` by default
+    pub fn load_balancing(mut self, load_balancing: LoadBalancing) -> Self {
+        self.load_balancing = load_balancing;
+        self
+    }
+
+    /// Remove `prefix` from the path of the requests before appending it to the upstream base url
+    pub fn strip_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.strip_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Answer `GET` requests from `cache` while the responses of the upstreams are fresh, revalidating them with
+    /// conditional requests once they are stale, see `ProxyCache`
+    pub fn cache(mut self, cache: ProxyCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Returns the upstreams of the proxy
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    /// Pick the upstream to use amongst the ones not `tried` yet, preferring healthy ones
+    fn select(&self, tried: &[usize]) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.upstreams.len()).filter(|i| !tried.contains(i)).collect();
+        let healthy: Vec<usize> = candidates.iter().cloned().filter(|&i| self.upstreams[i].is_healthy()).collect();
+        let candidates = if healthy.is_empty() { candidates } else { healthy };
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        match self.load_balancing {
+            LoadBalancing::RoundRobin => Some(candidates[self.next.fetch_add(1, Ordering::SeqCst) % candidates.len()]),
+            LoadBalancing::LeastConnections => candidates.into_iter().min_by_key(|&i| self.upstreams[i].in_flight()),
+        }
+    }
+
+    fn upstream_request(&self, req: &SyncRequest, body: Body, upstream: &Upstream) -> Result<Request<Body>, ::http_types::Error> {
+        let mut request = Request::new(body);
+        *request.method_mut() = req.method().clone();
+        *request.uri_mut() = upstream.uri(req.uri(), self.strip_prefix.as_deref())?;
+
+        let headers = request.headers_mut();
+        *headers = req.headers_map().clone();
+        strip_hop_by_hop_headers(headers);
+
+        if let Some(host) = headers.remove(header::HOST) {
+            headers.insert("x-forwarded-host", host);
+        }
+
+        if let Some(authority) = upstream.base_url.authority_part() {
+            headers.insert(header::HOST, header::HeaderValue::from_str(authority.as_str())?);
+        }
+
+        if let Some(peer) = req.peer_addr() {
+            let forwarded_for = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+                Some(previous) => format!("{}, {}", previous, peer.ip()),
+                None => peer.ip().to_string(),
+            };
+            headers.insert("x-forwarded-for", header::HeaderValue::from_str(&forwarded_for)?);
+        }
+
+        headers.insert("x-forwarded-proto", header::HeaderValue::from_static("http"));
+
+        Ok(request)
+    }
+
+    fn forward(&self, request: Request<Body>, upstream: &Upstream) -> Result<Response<Body>, ForwardError> {
+        upstream.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        let response = match upstream.timeout {
+            Some(timeout) => {
+                let response = Timeout::new(self.client.request(request), timeout);
+                oneshot::spawn(response, &self.runtime.executor()).wait().map_err(|e| {
+                    if e.is_elapsed() {
+                        ForwardError::Timeout
+                    } else {
+                        ForwardError::Unreachable(e.to_string())
+                    }
+                })
+            }
+            None => {
+                oneshot::spawn(self.client.request(request), &self.runtime.executor()).wait()
+                    .map_err(|e| ForwardError::Unreachable(e.to_string()))
+            }
+        };
+
+        upstream.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        match response {
+            Ok(_) => upstream.succeeded(),
+            Err(_) => upstream.failed(),
+        }
+
+        response
+    }
+
+    /// Forward `req` to the upstreams until one answers, along with the `validators` of a stored response, if any
+    fn exchange(&self, req: &SyncRequest, validators: Option<&header::HeaderMap>) -> Result<Response<Body>, StatusCode> {
+        // A body left unread by the server is streamed to the first upstream tried, and can't be sent again
+        let mut unread = req.take_unread_body();
+        let retry = is_idempotent(req.method()) && unread.is_none();
+        let mut tried = Vec::with_capacity(1);
+        let mut attempts_left = 1;
+        let mut timed_out = false;
+
+        while attempts_left > 0 {
+            let index = match self.select(&tried) {
+                Some(index) => index,
+                None => break,
+            };
+            tried.push(index);
+            attempts_left -= 1;
+
+            let upstream = &self.upstreams[index];
+            let body = unread.take().unwrap_or_else(|| Body::from(req.raw_body().to_vec()));
+            let request = match self.upstream_request(req, body, upstream) {
+                Ok(request) => with_validators(request, validators),
+                Err(e) => {
+                    warn!("Unable to build the upstream request for {}: {}", req.uri(), e);
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+            };
+
+            match self.forward(request, upstream) {
+                Ok(response) => return Ok(response),
+                Err(ForwardError::Timeout) => {
+                    error!("The upstream {} timed out", upstream.base_url);
+                    timed_out = true;
+                }
+                Err(ForwardError::Unreachable(e)) => {
+                    error!("Unable to reach the upstream {}: {}", upstream.base_url, e);
+                    timed_out = false;
+                }
+            }
+
+            if retry && tried.len() == 1 {
+                attempts_left = upstream.retries;
+            }
+        }
+
+        Err(if timed_out { StatusCode::GATEWAY_TIMEOUT } else { StatusCode::BAD_GATEWAY })
+    }
+
+    fn handle_cached(&self, cache: &Arc<ProxyCache>, req: &SyncRequest, res: &mut SyncResponse) {
+        let (key, cached) = cache.lookup(req);
+        let revalidate = CacheControlDirectives::parse(req.headers_map()).no_cache;
+
+        if let Some(ref cached) = cached {
+            if !revalidate && cached.is_fresh() {
+                answer_cached(res, cached);
+                return;
+            }
+            if !revalidate && cache.serves_stale(cached) {
+                answer_cached(res, cached);
+                self.revalidate_in_background(cache, req, key, cached);
+                return;
+            }
+        }
+
+        let validators = cached.as_ref().map(ProxyCache::validators);
+        let response = match self.exchange(req, validators.as_ref()) {
+            Ok(response) => response,
+            Err(status) => {
+                res.status(status);
+                return;
+            }
+        };
+
+        let (mut parts, body) = response.into_parts();
+        strip_hop_by_hop_headers(&mut parts.headers);
+
+        if let (StatusCode::NOT_MODIFIED, Some(cached)) = (parts.status, cached) {
+            answer_cached(res, &cache.refresh(&key, cached, &parts.headers));
+            return;
+        }
+
+        if cache.freshness(parts.status, &parts.headers).is_none() {
+            res.status(parts.status);
+            *res.headers_map_mut() = parts.headers;
+            res.body(StreamedBody::new(body));
+            return;
+        }
+
+        match body.concat2().wait() {
+            Ok(body) => {
+                cache.store(&key, req.headers_map(), parts.status, parts.headers.clone(), body.to_vec());
+                res.status(parts.status);
+                *res.headers_map_mut() = parts.headers;
+                res.body(body.to_vec());
+            }
+            Err(e) => {
+                error!("Unable to receive the response of the upstream for {}: {}", req.uri(), e);
+                res.status(StatusCode::BAD_GATEWAY);
+            }
+        }
+    }
+
+    /// Revalidate the stale response `cached` on the runtime of the proxy, unless it already is
+    fn revalidate_in_background(&self, cache: &Arc<ProxyCache>, req: &SyncRequest, key: CacheKey, cached: &CachedResponse) {
+        if !cache.begin_revalidation(&key) {
+            return;
+        }
+
+        let upstream = match self.select(&[]) {
+            Some(index) => &self.upstreams[index],
+            None => return cache.end_revalidation(&key),
+        };
+        let request = match self.upstream_request(req, Body::empty(), upstream) {
+            Ok(request) => with_validators(request, Some(&ProxyCache::validators(cached))),
+            Err(_) => return cache.end_revalidation(&key),
+        };
+
+        let cache = cache.clone();
+        let cached = cached.clone();
+        let request_headers = req.headers_map().clone();
+        let store = cache.clone();
+        let response = self.client.request(request).and_then(move |response| {
+            let (mut parts, body) = response.into_parts();
+            strip_hop_by_hop_headers(&mut parts.headers);
+            let keep = parts.status != StatusCode::NOT_MODIFIED && store.freshness(parts.status, &parts.headers).is_some();
+            let body: Box<dyn Future<Item=Vec<u8>, Error=::hyper::Error> + Send> = if keep {
+                Box::new(body.concat2().map(|body| body.to_vec()))
+            } else {
+                Box::new(::futures::future::ok(Vec::new()))
+            };
+            body.map(move |body| (parts, body))
+        });
+        let response: Box<dyn Future<Item=_, Error=String> + Send> = match upstream.timeout {
+            Some(timeout) => Box::new(Timeout::new(response, timeout).map_err(|e| e.to_string())),
+            None => Box::new(response.map_err(|e| e.to_string())),
+        };
+
+        self.runtime.executor().spawn(response.then(move |result| {
+            match result {
+                Ok((parts, _)) if parts.status == StatusCode::NOT_MODIFIED => { cache.refresh(&key, cached, &parts.headers); }
+                Ok((parts, body)) => cache.store(&key, &request_headers, parts.status, parts.headers, body),
+                Err(e) => warn!("Unable to revalidate a cached response in the background: {}", e),
+            }
+            cache.end_revalidation(&key);
+            Ok(())
+        }));
+    }
+}
+
+/// Add the `validators` of a stored response to `request`, replacing the conditions of the client
+fn with_validators(mut request: Request<Body>, validators: Option<&header::HeaderMap>) -> Request<Body> {
+    if let Some(validators) = validators {
+        for name in &[header::IF_NONE_MATCH, header::IF_MODIFIED_SINCE, header::IF_MATCH, header::IF_UNMODIFIED_SINCE] {
+            request.headers_mut().remove(name);
+        }
+        for (name, value) in validators {
+            request.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    request
+}
+
+impl Controller for ProxyController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if let Some(ref cache) = self.cache {
+            if cache.applies(req) {
+                return self.handle_cached(cache, req, res);
+            }
+        }
+
+        match self.exchange(req, None) {
+            Ok(response) => {
+                let (mut parts, body) = response.into_parts();
+                strip_hop_by_hop_headers(&mut parts.headers);
+
+                res.status(parts.status);
+                *res.headers_map_mut() = parts.headers;
+                res.body(StreamedBody::new(body));
+            }
+            Err(status) => {
+                res.status(status);
+            }
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let upstreams: Vec<String> = self.upstreams.iter().map(|u| u.base_url.to_string()).collect();
+        vec![RouteInfo::new(format!("ProxyController({})", upstreams.join(", ")), None, None, Vec::new())]
+    }
+}
+
+/// The body of an upstream response, streamed the first time it is converted
+///
+/// The received chunks are kept while the body is still part of a response, so it can be converted again, by a middleware
+/// reading it for instance, once the stream has been consumed.
+struct StreamedBody {
+    stream: Mutex<Option<Body>>,
+    received: Arc<Mutex<Vec<u8>>>,
+}
+
+impl StreamedBody {
+    fn new(body: Body) -> Self {
+        StreamedBody {
+            stream: Mutex::new(Some(body)),
+            received: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl ToBody for StreamedBody {
+    fn to_body(&self) -> Body {
+        match self.stream.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(stream) => Body::wrap_stream(TeeStream {
+                stream,
+                received: Arc::downgrade(&self.received),
+            }),
+            None => self.received.lock().unwrap_or_else(|e| e.into_inner()).clone().into(),
+        }
+    }
+}
+
+/// A stream of chunks copying each of them into `received` until the body they came from is dropped, so a response streamed
+/// to the client isn't buffered as a whole
+struct TeeStream {
+    stream: Body,
+    received: Weak<Mutex<Vec<u8>>>,
+}
+
+impl Stream for TeeStream {
+    type Item = Chunk;
+    type Error = ::hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::hyper::Error> {
+        let chunk = match self.stream.poll()? {
+            Async::Ready(chunk) => chunk,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+
+        if let (Some(chunk), Some(received)) = (chunk.as_ref(), self.received.upgrade()) {
+            received.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(chunk);
+        }
+
+        Ok(Async::Ready(chunk))
+    }
+}
@@ -0,0 +1,201 @@
+use http::*;
+use response_cache::CacheControlDirectives;
+use response_cache::CacheStore;
+use response_cache::CachedResponse;
+use response_cache::ResponseCacheMiddleware;
+use response_cache::is_cacheable_status;
+use response_cache::vary_headers;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// The headers of a stored response which a `304 Not Modified` from the upstream doesn't update
+const KEPT_HEADERS: &[&str] = &["content-length", "content-encoding", "content-range", "transfer-encoding"];
+
+/// A cache of the responses of the upstreams of a `ProxyController`, see `ProxyController::cache`
+///
+/// `GET` responses are stored for the duration given by the `s-maxage` or `max-age` directive of their `Cache-Control`,
+/// or the default duration when they have none. Responses with an `ETag` or a `Last-Modified` but without freshness
+/// information, or with `no-cache`, are stored all the same to be revalidated: once a response is stale, the request is
+/// forwarded with `If-None-Match` or `If-Modified-Since` and a `304 Not Modified` from the upstream refreshes the stored
+/// response instead of transferring it again. Responses with `no-store`, `private`, `Set-Cookie` or `Vary: *` are never
+/// stored, neither are the ones larger than the maximum body size or without a `Content-Length`.
+///
+/// A stale response is still answered right away for the duration of its `stale-while-revalidate` directive, or the one
+/// configured, while it is revalidated in the background.
+///
+/// Requests sent with `Cache-Control: no-store` bypass the cache, and `Cache-Control: no-cache` forces a revalidation.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let cache = ProxyCache::new(MemoryCacheStore::new(4096))
+///     .default_max_age(Duration::from_secs(10))
+///     .stale_while_revalidate(Duration::from_secs(30));
+///
+/// let mut router = Router::new();
+/// router.add("^/catalog", ProxyController::new("http://127.0.0.1:8080").unwrap().cache(cache));
+/// ```
+pub struct ProxyCache {
+    store: Box<dyn CacheStore>,
+    default_max_age: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    max_body_size: u64,
+    vary_index: RwLock<HashMap<String, Vec<header::HeaderName>>>,
+    revalidating: Mutex<HashSet<String>>,
+}
+
+/// Where a request is looked up in a `ProxyCache`
+pub(crate) struct CacheKey {
+    primary: String,
+    variant: String,
+}
+
+impl ProxyCache {
+    /// Create a cache backed by `store`, for responses of 1 MiB at most. By default, only responses having an explicit
+    /// `max-age` or a validator are stored, and stale responses are always revalidated before being answered.
+    pub fn new<S: 'static + CacheStore>(store: S) -> Self {
+        ProxyCache {
+            store: Box::new(store),
+            default_max_age: None,
+            stale_while_revalidate: None,
+            max_body_size: 1024 * 1024,
+            vary_index: RwLock::new(HashMap::new()),
+            revalidating: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Store responses without freshness information for `max_age`
+    pub fn default_max_age(mut self, max_age: Duration) -> Self {
+        self.default_max_age = Some(max_age);
+        self
+    }
+
+    /// Answer stale responses without a `stale-while-revalidate` directive for `window` after they became stale, while
+    /// they are revalidated in the background
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    /// Only store responses whose body is `bytes` long at most
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Returns whether `req` is answered through the cache
+    pub(crate) fn applies(&self, req: &SyncRequest) -> bool {
+        *req.method() == Method::GET && !CacheControlDirectives::parse(req.headers_map()).no_store
+    }
+
+    /// Returns where `req` is stored, along with the response stored there if any
+    pub(crate) fn lookup(&self, req: &SyncRequest) -> (CacheKey, Option<CachedResponse>) {
+        let primary = ResponseCacheMiddleware::primary_key(req);
+        let variant = match self.vary_index.read().unwrap_or_else(|e| e.into_inner()).get(&primary) {
+            Some(vary) => ResponseCacheMiddleware::variant_key(&primary, vary, req.headers_map()),
+            None => primary.clone(),
+        };
+
+        let cached = self.store.get(&variant);
+        (CacheKey { primary, variant }, cached)
+    }
+
+    /// Returns whether `cached` may be answered while it is revalidated in the background
+    pub(crate) fn serves_stale(&self, cached: &CachedResponse) -> bool {
+        let window = CacheControlDirectives::parse(&cached.headers).stale_while_revalidate.map(Duration::from_secs)
+            .or(self.stale_while_revalidate);
+
+        window.is_some_and(|window| cached.age() < cached.max_age + window)
+    }
+
+    /// Returns for how long a response of `status` with `headers` is fresh, `None` when it can't be stored
+    pub(crate) fn freshness(&self, status: StatusCode, headers: &header::HeaderMap) -> Option<Duration> {
+        if !is_cacheable_status(status) || headers.contains_key(header::SET_COOKIE) || vary_headers(headers).is_none() {
+            return None;
+        }
+
+        let length = headers.get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+        if length.is_none_or(|length| length > self.max_body_size) {
+            return None;
+        }
+
+        let directives = CacheControlDirectives::parse(headers);
+        if directives.no_store || directives.private {
+            return None;
+        }
+        if directives.no_cache {
+            return Some(Duration::from_secs(0));
+        }
+
+        let validated = headers.contains_key(header::ETAG) || headers.contains_key(header::LAST_MODIFIED);
+        directives.s_max_age.or(directives.max_age).map(Duration::from_secs)
+            .or(self.default_max_age)
+            .or(if validated { Some(Duration::from_secs(0)) } else { None })
+    }
+
+    /// Store the response of the upstream to the request with `request_headers` stored under `key`
+    pub(crate) fn store(&self, key: &CacheKey, request_headers: &header::HeaderMap, status: StatusCode, headers: header::HeaderMap, body: Vec<u8>) {
+        let max_age = match self.freshness(status, &headers) {
+            Some(max_age) => max_age,
+            None => return self.store.remove(&key.variant),
+        };
+        let vary = vary_headers(&headers).unwrap_or_default();
+
+        let variant = ResponseCacheMiddleware::variant_key(&key.primary, &vary, request_headers);
+        self.vary_index.write().unwrap_or_else(|e| e.into_inner()).insert(key.primary.clone(), vary);
+        self.store.put(variant, CachedResponse { status, headers, body, stored_at: SystemTime::now(), max_age });
+    }
+
+    /// Refresh `cached` with the headers of a `304 Not Modified` answered by the upstream, and returns it
+    pub(crate) fn refresh(&self, key: &CacheKey, mut cached: CachedResponse, not_modified: &header::HeaderMap) -> CachedResponse {
+        for name in not_modified.keys().filter(|name| !KEPT_HEADERS.contains(&name.as_str())) {
+            cached.headers.remove(name);
+            for value in not_modified.get_all(name).iter() {
+                cached.headers.append(name.clone(), value.clone());
+            }
+        }
+
+        cached.stored_at = SystemTime::now();
+        match self.freshness(cached.status, &cached.headers) {
+            Some(max_age) => {
+                cached.max_age = max_age;
+                self.store.put(key.variant.clone(), cached.clone());
+            }
+            None => self.store.remove(&key.variant),
+        }
+        cached
+    }
+
+    /// Returns the headers asking the upstream whether `cached` is still valid
+    pub(crate) fn validators(cached: &CachedResponse) -> header::HeaderMap {
+        let mut validators = header::HeaderMap::new();
+        if let Some(etag) = cached.headers.get(header::ETAG) {
+            validators.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = cached.headers.get(header::LAST_MODIFIED) {
+            validators.insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        validators
+    }
+
+    /// Mark `key` as being revalidated in the background, returns false when it already is
+    pub(crate) fn begin_revalidation(&self, key: &CacheKey) -> bool {
+        self.revalidating.lock().unwrap_or_else(|e| e.into_inner()).insert(key.variant.clone())
+    }
+
+    pub(crate) fn end_revalidation(&self, key: &CacheKey) {
+        self.revalidating.lock().unwrap_or_else(|e| e.into_inner()).remove(&key.variant);
+    }
+}
+
+/// Answer `res` with the stored response `cached`, along with its `Age`
+pub(crate) fn answer_cached(res: &mut SyncResponse, cached: &CachedResponse) {
+    *res.headers_map_mut() = cached.headers.clone();
+    res.status(cached.status).header(header::AGE, cached.age().as_secs().to_string()).body(cached.body.clone());
+}
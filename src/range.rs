@@ -0,0 +1,174 @@
+use http::*;
+use http::header::HttpDate;
+use credentials::random_token;
+use std::fmt;
+use std::ops::Range;
+use std::time::SystemTime;
+
+/// The most ranges a request may ask for at once, requests asking for more are answered with the whole representation
+pub const MAX_RANGES: usize = 16;
+
+/// An error raised while evaluating the `Range` header of a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    /// The header is not a valid set of byte ranges, it should be ignored
+    Invalid,
+    /// None of the ranges overlap the representation, the request should be answered with
+    /// `416 Range Not Satisfiable`
+    Unsatisfiable,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RangeError::Invalid => write!(f, "invalid byte ranges"),
+            RangeError::Unsatisfiable => write!(f, "unsatisfiable byte ranges"),
+        }
+    }
+}
+
+impl ::std::error::Error for RangeError {}
+
+/// Parse the value of a `Range` header, like `bytes=0-499, -100`, into the ranges of a representation of `len` bytes it
+/// asks for, in their requested order. Ranges running past the end of the representation are shortened, ranges starting
+/// past its end are left out.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// assert_eq!(parse_byte_ranges("bytes=0-9, 20-, -5", 100), Ok(vec![0..10, 20..100, 95..100]));
+/// assert_eq!(parse_byte_ranges("bytes=200-", 100), Err(RangeError::Unsatisfiable));
+/// ```
+pub fn parse_byte_ranges(value: &str, len: u64) -> Result<Vec<Range<u64>>, RangeError> {
+    let value = value.trim();
+    if value.len() < 6 || !value[..6].eq_ignore_ascii_case("bytes=") {
+        return Err(RangeError::Invalid);
+    }
+
+    let mut specs = 0;
+    let mut ranges = Vec::new();
+
+    for spec in value[6..].split(',').map(|spec| spec.trim()).filter(|spec| !spec.is_empty()) {
+        specs += 1;
+        let dash = spec.find('-').ok_or(RangeError::Invalid)?;
+        let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+
+        if first.is_empty() {
+            let suffix = last.parse::<u64>().map_err(|_| RangeError::Invalid)?;
+            if suffix > 0 && len > 0 {
+                ranges.push(len.saturating_sub(suffix)..len);
+            }
+            continue;
+        }
+
+        let first = first.parse::<u64>().map_err(|_| RangeError::Invalid)?;
+        let last = if last.is_empty() { None } else { Some(last.parse::<u64>().map_err(|_| RangeError::Invalid)?) };
+        if last.is_some_and(|last| last < first) {
+            return Err(RangeError::Invalid);
+        }
+
+        if first < len {
+            ranges.push(first..last.map_or(len, |last| last.saturating_add(1).min(len)));
+        }
+    }
+
+    if specs == 0 {
+        Err(RangeError::Invalid)
+    } else if ranges.is_empty() {
+        Err(RangeError::Unsatisfiable)
+    } else {
+        Ok(ranges)
+    }
+}
+
+/// Evaluate the `If-Range` precondition of `req` against the validators of `res`, ranges are only sent when the client's
+/// copy is the current one
+fn if_range_matches(req: &SyncRequest, res: &SyncResponse) -> bool {
+    let if_range = match req.headers_map().get(header::IF_RANGE).and_then(|value| value.to_str().ok()) {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+
+    if if_range.starts_with('"') {
+        res.headers_map().get(header::ETAG).and_then(|etag| etag.to_str().ok()) == Some(if_range)
+    } else {
+        let date = |value: &str| value.parse::<HttpDate>().ok().map(SystemTime::from);
+        let last_modified = res.headers_map().get(header::LAST_MODIFIED).and_then(|value| value.to_str().ok()).and_then(date);
+        last_modified.is_some() && last_modified == date(if_range)
+    }
+}
+
+impl SyncResponse {
+    /// Set `content` as the body of the response, or the parts of it asked for by the `Range` header of `req`
+    ///
+    /// Successful responses to `GET` requests asking for one range are turned into `206 Partial Content` along with the
+    /// range, and the ones asking for several ranges into a `multipart/byteranges` body made of the ranges, each along with
+    /// the content type of the response. Requests asking for ranges starting past the end of `content` are answered with
+    /// `416 Range Not Satisfiable`. The whole content is sent when the `Range` header is invalid, asks for more than
+    /// `MAX_RANGES` ranges, or when the `If-Range` precondition doesn't match the `ETag` or `Last-Modified` of the response.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn video(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let content = std::fs::read("video.mp4").unwrap_or_default();
+    ///     res.status(StatusCode::OK).header(header::CONTENT_TYPE, "video/mp4").ranged_body(req, content);
+    /// }
+    /// ```
+    pub fn ranged_body(&mut self, req: &SyncRequest, content: Vec<u8>) -> &mut SyncResponse {
+        self.headers_map_mut().insert(header::ACCEPT_RANGES, header::HeaderValue::from_static("bytes"));
+        let len = content.len() as u64;
+
+        let ranges = match req.headers_map().get(header::RANGE).and_then(|value| value.to_str().ok()) {
+            Some(range) if *req.method() == Method::GET && self.get_status() == StatusCode::OK && if_range_matches(req, self) => {
+                parse_byte_ranges(range, len)
+            }
+            _ => Err(RangeError::Invalid),
+        };
+
+        match ranges {
+            Ok(ref ranges) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                let part = content[range.start as usize..range.end as usize].to_vec();
+                self.status(StatusCode::PARTIAL_CONTENT).set_length(part.len())
+                    .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end - 1, len))
+                    .body(part)
+            }
+            Ok(ref ranges) if ranges.len() <= MAX_RANGES => {
+                let boundary = random_token();
+                let content_type = self.headers_map().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).map(|value| value.to_string());
+
+                let mut body = Vec::new();
+                for range in ranges {
+                    body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+                    if let Some(ref content_type) = content_type {
+                        body.extend_from_slice(format!("Content-Type: {}\r\n", content_type).as_bytes());
+                    }
+                    body.extend_from_slice(format!("Content-Range: bytes {}-{}/{}\r\n\r\n", range.start, range.end - 1, len).as_bytes());
+                    body.extend_from_slice(&content[range.start as usize..range.end as usize]);
+                    body.extend_from_slice(b"\r\n");
+                }
+                body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+                let multipart = format!("multipart/byteranges; boundary={}", boundary);
+                if let Ok(multipart) = header::HeaderValue::from_str(&multipart) {
+                    self.headers_map_mut().insert(header::CONTENT_TYPE, multipart);
+                }
+                self.status(StatusCode::PARTIAL_CONTENT).set_length(body.len()).body(body)
+            }
+            Err(RangeError::Unsatisfiable) => {
+                self.status(StatusCode::RANGE_NOT_SATISFIABLE).set_length(0)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                    .body(Vec::<u8>::new())
+            }
+            _ => self.set_length(content.len()).body(content),
+        }
+    }
+
+    fn set_length(&mut self, len: usize) -> &mut SyncResponse {
+        self.headers_map_mut().insert(header::CONTENT_LENGTH, header::HeaderValue::from(len as u64));
+        self
+    }
+}
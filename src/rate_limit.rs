@@ -0,0 +1,195 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many windows a `RateLimitMiddleware` keeps before forgetting the expired ones
+const PURGE_THRESHOLD: usize = 1024;
+
+/// A number of requests allowed per window of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The number of requests allowed per window
+    pub limit: u64,
+    /// The duration of a window
+    pub window: Duration,
+}
+
+impl RateLimit {
+    /// Allow `limit` requests every `window`
+    pub fn new(limit: u64, window: Duration) -> Self {
+        RateLimit {
+            limit,
+            window,
+        }
+    }
+}
+
+/// Who a request is counted against by a `RateLimitMiddleware`, and the tier of limits applying to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitKey {
+    /// The identifier requests are counted against, like a user id or an API key
+    pub id: String,
+    /// The tier of limits applying to the identifier, the default limit applies without one
+    pub tier: Option<String>,
+}
+
+impl RateLimitKey {
+    /// Count requests against `id`, with the default limit
+    pub fn new<S: Into<String>>(id: S) -> Self {
+        RateLimitKey {
+            id: id.into(),
+            tier: None,
+        }
+    }
+
+    /// Apply the limit of `tier`
+    pub fn tier<S: Into<String>>(mut self, tier: S) -> Self {
+        self.tier = Some(tier.into());
+        self
+    }
+}
+
+struct Window {
+    started: Instant,
+    length: Duration,
+    count: u64,
+}
+
+type KeyExtractor = Box<dyn Fn(&SyncRequest) -> Option<RateLimitKey> + Send + Sync>;
+
+/// A middleware limiting how many requests every client can make in a window of time, rejecting the excess with
+/// `429 Too Many Requests`
+///
+/// Requests are counted against the identity of their client, as found by the extractor set with `key` or `identity`,
+/// typically the authenticated user or API key an authentication middleware inserted in the extensions of the request.
+/// Anonymous requests are counted against their client address, see `SyncRequest::client_ip`. Every identity may belong to
+/// a tier with limits of its own, like the plans of an API.
+///
+/// Every response is given the `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset` headers, the latter
+/// being the number of seconds until the current window ends, so that clients can pace themselves. Rejected requests are
+/// also given a `Retry-After` header. Windows are fixed and kept in memory, clients are counted separately by every
+/// instance of the server.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// struct ApiKey {
+///     id: String,
+///     plan: String,
+/// }
+///
+/// let limiter = RateLimitMiddleware::new(RateLimit::new(60, Duration::from_secs(60)))
+///     .tier("pro", RateLimit::new(6000, Duration::from_secs(60)))
+///     .identity(|key: &ApiKey| RateLimitKey::new(key.id.clone()).tier(key.plan.clone()));
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(limiter, vec!("^/api"), None);
+/// ```
+pub struct RateLimitMiddleware {
+    default: RateLimit,
+    tiers: HashMap<String, RateLimit>,
+    key: KeyExtractor,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimitMiddleware {
+    /// Create a middleware applying `default` to every client, identified by address
+    pub fn new(default: RateLimit) -> Self {
+        RateLimitMiddleware {
+            default,
+            tiers: HashMap::new(),
+            key: Box::new(|_| None),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Apply `limit` to the identities of the tier `name`
+    pub fn tier<S: Into<String>>(mut self, name: S, limit: RateLimit) -> Self {
+        self.tiers.insert(name.into(), limit);
+        self
+    }
+
+    /// Identify clients with `key`, requests it returns `None` for are identified by address
+    pub fn key<F>(mut self, key: F) -> Self
+        where F: 'static + Fn(&SyncRequest) -> Option<RateLimitKey> + Send + Sync {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Identify clients by the value of type `T` found in the extensions of the request, like the user or API key inserted
+    /// by an authentication middleware, requests without one are identified by address
+    pub fn identity<T, F>(self, key: F) -> Self
+        where T: 'static + Send + Sync,
+              F: 'static + Fn(&T) -> RateLimitKey + Send + Sync {
+        self.key(move |req| req.extensions().get::<T>().map(&key))
+    }
+
+    fn identify(&self, req: &SyncRequest) -> (String, RateLimit) {
+        match (self.key)(req) {
+            Some(key) => {
+                let limit = key.tier.as_ref().and_then(|tier| self.tiers.get(tier)).cloned().unwrap_or(self.default);
+                (format!("id:{}", key.id), limit)
+            }
+            None => {
+                let ip = req.client_ip().map(|ip| ip.to_string()).unwrap_or_default();
+                (format!("ip:{}", ip), self.default)
+            }
+        }
+    }
+
+    /// Count a request against `key`, returning whether it is allowed, the requests remaining and the time until the reset
+    fn hit(&self, key: String, limit: RateLimit) -> (bool, u64, Duration) {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+
+        if windows.len() >= PURGE_THRESHOLD && !windows.contains_key(&key) {
+            windows.retain(|_, window| now < window.started + window.length);
+        }
+
+        let window = windows.entry(key).or_insert(Window {
+            started: now,
+            length: limit.window,
+            count: 0,
+        });
+        if now >= window.started + window.length {
+            window.started = now;
+            window.count = 0;
+        }
+        window.length = limit.window;
+
+        let allowed = window.count < limit.limit;
+        if allowed {
+            window.count += 1;
+        }
+
+        (allowed, limit.limit.saturating_sub(window.count), (window.started + window.length).saturating_duration_since(now))
+    }
+}
+
+impl Middleware for RateLimitMiddleware {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let (key, limit) = self.identify(req);
+        let (allowed, remaining, reset) = self.hit(key, limit);
+        let reset = reset.as_secs() + if reset.subsec_nanos() > 0 { 1 } else { 0 };
+
+        let headers = res.headers_map_mut();
+        headers.insert("x-ratelimit-limit", header::HeaderValue::from(limit.limit));
+        headers.insert("x-ratelimit-remaining", header::HeaderValue::from(remaining));
+        headers.insert("x-ratelimit-reset", header::HeaderValue::from(reset));
+
+        if !allowed {
+            res.status(StatusCode::TOO_MANY_REQUESTS)
+                .header(header::RETRY_AFTER, reset.to_string())
+                .body(Vec::<u8>::new());
+            return RequestContinuation::None;
+        }
+
+        RequestContinuation::Next
+    }
+}
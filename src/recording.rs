@@ -0,0 +1,254 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use test::TestClient;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Value replacing the redacted header values of a recording
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A body of a recorded message, kept as text when it is valid UTF-8
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordedBody {
+    /// A UTF-8 body
+    Text(String),
+    /// Any other body
+    Binary(Vec<u8>),
+}
+
+impl RecordedBody {
+    fn new(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => RecordedBody::Text(text),
+            Err(e) => RecordedBody::Binary(e.into_bytes()),
+        }
+    }
+
+    /// Returns the bytes of the body
+    pub fn as_bytes(&self) -> &[u8] {
+        match *self {
+            RecordedBody::Text(ref text) => text.as_bytes(),
+            RecordedBody::Binary(ref bytes) => bytes,
+        }
+    }
+}
+
+/// A recorded request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    /// Method of the request
+    pub method: String,
+    /// Uri of the request
+    pub uri: String,
+    /// Headers of the request, in the order they were received
+    pub headers: Vec<(String, String)>,
+    /// Body of the request
+    pub body: RecordedBody,
+}
+
+/// A recorded response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    /// Status code of the response
+    pub status: u16,
+    /// Headers of the response
+    pub headers: Vec<(String, String)>,
+    /// Body of the response
+    pub body: RecordedBody,
+}
+
+/// A request and the response it was answered with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    /// The request
+    pub request: RecordedRequest,
+    /// The response
+    pub response: RecordedResponse,
+}
+
+type Redactor = Box<dyn Fn(&mut RecordedExchange) + Send + Sync>;
+
+fn recorded_headers(headers: &header::HeaderMap<header::HeaderValue>, redacted: &[header::HeaderName]) -> Vec<(String, String)> {
+    headers.iter().map(|(name, value)| {
+        let value = if redacted.contains(name) { REDACTED.to_string() } else { String::from_utf8_lossy(value.as_bytes()).into_owned() };
+        (name.as_str().to_string(), value)
+    }).collect()
+}
+
+/// A middleware recording every request along with its response to a file, one JSON document per line
+///
+/// The values of the `Authorization`, `Proxy-Authorization`, `Cookie` and `Set-Cookie` headers are replaced by `[REDACTED]`,
+/// more headers can be redacted with `redact_header` and any other secret, like a password in a body, with `redact`. The
+/// recording can be loaded with `load_recording` and replayed against an application with `replay`.
+///
+/// Recording happens once the response is computed, so the middleware records the request as the following middlewares
+/// and the router saw it, except for its body, recorded as it was received since replaying the request goes through the
+/// middlewares again, see `SyncRequest::raw_body`. It should be applied first to record the responses altered by the other
+/// middlewares. Streamed response bodies are recorded empty, rather than waiting for the whole stream.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let recorder = RecordingMiddleware::new("traffic.jsonl").unwrap()
+///     .redact_header("x-api-key")
+///     .redact(|exchange| if exchange.request.uri.starts_with("/login") {
+///         exchange.request.body = RecordedBody::Text(REDACTED.to_string());
+///     });
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(recorder, vec!("/api"), None);
+/// ```
+pub struct RecordingMiddleware {
+    file: Mutex<File>,
+    redacted_headers: Vec<header::HeaderName>,
+    redactors: Vec<Redactor>,
+}
+
+impl RecordingMiddleware {
+    /// Record to the file at `path`, appending to it if it already exists
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(RecordingMiddleware {
+            file: Mutex::new(file),
+            redacted_headers: vec![header::AUTHORIZATION, header::PROXY_AUTHORIZATION, header::COOKIE, header::SET_COOKIE],
+            redactors: Vec::new(),
+        })
+    }
+
+    /// Replace the values of the request and response header `name` by `[REDACTED]`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is not a valid header name.
+    pub fn redact_header(mut self, name: &str) -> Self {
+        self.redacted_headers.push(name.parse().expect("invalid header name"));
+        self
+    }
+
+    /// Invoke `redactor` with every exchange before it is written, to remove the secrets it may contain
+    pub fn redact<F: 'static + Fn(&mut RecordedExchange) + Send + Sync>(mut self, redactor: F) -> Self {
+        self.redactors.push(Box::new(redactor));
+        self
+    }
+
+    fn record(&self, exchange: &RecordedExchange) -> io::Result<()> {
+        let mut line = ::serde_json::to_vec(exchange)?;
+        line.push(b'\n');
+
+        // A single write per exchange, so concurrent requests never interleave their lines
+        self.file.lock().unwrap_or_else(|e| e.into_inner()).write_all(&line)
+    }
+}
+
+impl Middleware for RecordingMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let mut exchange = RecordedExchange {
+            request: RecordedRequest {
+                method: req.method().to_string(),
+                uri: req.uri().to_string(),
+                headers: recorded_headers(req.headers_map(), &self.redacted_headers),
+                body: RecordedBody::new(req.raw_body().to_vec()),
+            },
+            response: RecordedResponse {
+                status: res.get_status().as_u16(),
+                headers: recorded_headers(res.headers_map(), &self.redacted_headers),
+                body: RecordedBody::new(if res.is_streamed() { Vec::new() } else { res.get_body() }),
+            },
+        };
+
+        for redactor in &self.redactors {
+            redactor(&mut exchange);
+        }
+
+        if let Err(e) = self.record(&exchange) {
+            error!("Unable to record the exchange of {} {}: {}", exchange.request.method, exchange.request.uri, e);
+        }
+    }
+}
+
+/// Load the exchanges recorded by a `RecordingMiddleware` in the file at `path`
+pub fn load_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<RecordedExchange>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut exchanges = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            exchanges.push(::serde_json::from_str(&line)?);
+        }
+    }
+
+    Ok(exchanges)
+}
+
+/// A recorded exchange whose response differs when the request is replayed
+#[derive(Debug)]
+pub struct ReplayMismatch {
+    /// Position of the exchange in the recording
+    pub index: usize,
+    /// The recorded exchange
+    pub expected: RecordedExchange,
+    /// Status code of the response to the replayed request
+    pub status: u16,
+    /// Body of the response to the replayed request
+    pub body: RecordedBody,
+}
+
+/// Replay the recorded requests against the server of `client`, returning the exchanges whose status or body differ from
+/// the recorded response
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use saphir::test::TestClient;
+/// # let server = Server::builder().build();
+/// let exchanges = load_recording("traffic.jsonl").unwrap();
+/// let mismatches = replay(&TestClient::new(server), &exchanges);
+/// assert!(mismatches.is_empty(), "{:#?}", mismatches);
+/// ```
+pub fn replay(client: &TestClient, exchanges: &[RecordedExchange]) -> Vec<ReplayMismatch> {
+    exchanges.iter().enumerate().filter_map(|(index, exchange)| {
+        let method = match exchange.request.method.parse::<Method>() {
+            Ok(method) => method,
+            Err(_) => {
+                warn!("Skipping the recorded request {} with the invalid method {}", index, exchange.request.method);
+                return None;
+            }
+        };
+
+        let mut request = client.request(method, &exchange.request.uri).body(exchange.request.body.as_bytes().to_vec());
+        for (name, value) in &exchange.request.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        let response = request.send();
+        let status = response.get_status().as_u16();
+        let body = response.get_body();
+
+        if status == exchange.response.status && body.as_slice() == exchange.response.body.as_bytes() {
+            None
+        } else {
+            Some(ReplayMismatch {
+                index,
+                expected: exchange.clone(),
+                status,
+                body: RecordedBody::new(body),
+            })
+        }
+    }).collect()
+}
@@ -0,0 +1,138 @@
+use http::*;
+use futures::Stream;
+use futures::stream::Wait;
+use hyper::Chunk;
+use std::error::Error;
+use std::io;
+use std::io::Read;
+use std::sync::Mutex;
+
+/// The body of a request left unread by the server, see `BodyLimit::buffered`
+pub(crate) struct UnreadBody {
+    body: Mutex<Option<Body>>,
+    max_size: Option<usize>,
+}
+
+impl UnreadBody {
+    pub(crate) fn new(body: Body, max_size: Option<usize>) -> Self {
+        UnreadBody {
+            body: Mutex::new(Some(body)),
+            max_size,
+        }
+    }
+}
+
+enum Source {
+    Loaded(io::Cursor<Vec<u8>>),
+    Streamed {
+        chunks: Wait<Body>,
+        chunk: Chunk,
+        offset: usize,
+    },
+    Empty,
+}
+
+/// A reader of the body of a request, returned by `SyncRequest::body_reader`
+///
+/// The body of a request left unread by the server is read from the connection as the reader is, blocking until the client
+/// sends the next bytes. Reading it past its maximum size fails with an `InvalidData` error.
+pub struct BodyReader {
+    source: Source,
+    read: usize,
+    max_size: Option<usize>,
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.source {
+            Source::Loaded(ref mut cursor) => cursor.read(buf)?,
+            Source::Streamed { ref mut chunks, ref mut chunk, ref mut offset } => {
+                while *offset == chunk.len() {
+                    match chunks.next() {
+                        Some(Ok(next)) => {
+                            *chunk = next;
+                            *offset = 0;
+                        }
+                        Some(Err(e)) => return Err(io::Error::other(e)),
+                        None => return Ok(0),
+                    }
+                }
+
+                let read = buf.len().min(chunk.len() - *offset);
+                buf[..read].copy_from_slice(&chunk[*offset..*offset + read]);
+                *offset += read;
+                read
+            }
+            Source::Empty => 0,
+        };
+
+        self.read += read;
+        match self.max_size {
+            Some(max_size) if self.read > max_size => {
+                Err(io::Error::new(io::ErrorKind::InvalidData, format!("the request body exceeds the limit of {} bytes", max_size)))
+            }
+            _ => Ok(read),
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Returns a reader of the body of the request
+    ///
+    /// When the route leaves the bodies unread, see `BodyLimit::buffered`, the body is read from the connection as the
+    /// reader is, and can only be read once: the readers returned afterwards are empty. Otherwise the reader reads a copy of
+    /// the loaded body.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # fn handler(req: &SyncRequest, res: &mut SyncResponse) {
+    /// let mut file = ::std::fs::File::create("/var/lib/backups/latest.tar").unwrap();
+    /// match ::std::io::copy(&mut req.body_reader(), &mut file) {
+    ///     Ok(_) => res.status(StatusCode::CREATED),
+    ///     Err(_) => res.status(StatusCode::BAD_REQUEST),
+    /// };
+    /// # }
+    /// ```
+    pub fn body_reader(&self) -> BodyReader {
+        let source = match self.extensions().get::<UnreadBody>() {
+            Some(unread) => match unread.body.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                Some(body) => Source::Streamed { chunks: body.wait(), chunk: Chunk::default(), offset: 0 },
+                None => Source::Empty,
+            },
+            None => Source::Loaded(io::Cursor::new(self.body().clone())),
+        };
+
+        BodyReader {
+            source,
+            read: 0,
+            max_size: self.extensions().get::<UnreadBody>().and_then(|unread| unread.max_size),
+        }
+    }
+
+    /// Returns true when the body of the request was left unread by the server, and wasn't read since
+    pub fn has_unread_body(&self) -> bool {
+        self.extensions().get::<UnreadBody>().is_some_and(|unread| unread.body.lock().unwrap_or_else(|e| e.into_inner()).is_some())
+    }
+
+    /// Take the body of the request left unread by the server, failing once it grows past its maximum size
+    pub(crate) fn take_unread_body(&self) -> Option<Body> {
+        let unread = self.extensions().get::<UnreadBody>()?;
+        let body = unread.body.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+
+        Some(match unread.max_size {
+            Some(max_size) => {
+                let mut read = 0;
+                Body::wrap_stream(body.map_err(|e| Box::new(e) as Box<dyn Error + Send + Sync>).and_then(move |chunk| {
+                    read += chunk.len();
+                    if read > max_size {
+                        return Err(format!("the request body exceeds the limit of {} bytes", max_size).into());
+                    }
+                    Ok(chunk)
+                }))
+            }
+            None => body,
+        })
+    }
+}
@@ -0,0 +1,249 @@
+use http::*;
+use http_types::HttpTryFrom;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+
+/// A fluent builder of responses, which rejects the responses HTTP doesn't allow once built
+///
+/// Unlike the `SyncResponse` setters, which mutate the response in place, the builder is consumed by each call and checks
+/// the response as a whole in `build`: a body on a `1xx`, `204 No Content` or `304 Not Modified` response, a redirection
+/// without `Location` and an invalid header are reported as a `ResponseBuildError`, rather than silently dropped by the
+/// server or sent as is to confused clients.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// fn show(_: &(), _: &SyncRequest, res: &mut SyncResponse) {
+///     ResponseBuilder::ok()
+///         .header(header::CACHE_CONTROL, "max-age=60")
+///         .json(&vec!["pen", "ink"])
+///         .respond(res)
+///         .expect("a valid response");
+/// }
+///
+/// let moved = ResponseBuilder::moved_permanently().location("/catalog").build().unwrap();
+/// assert!(ResponseBuilder::no_content().text("done").build().is_err());
+/// ```
+pub struct ResponseBuilder {
+    response: SyncResponse,
+    has_body: bool,
+    error: Option<ResponseBuildError>,
+}
+
+impl ResponseBuilder {
+    /// Start building a response of `status`
+    pub fn new(status: StatusCode) -> Self {
+        let mut response = SyncResponse::new();
+        response.status(status);
+
+        ResponseBuilder {
+            response,
+            has_body: false,
+            error: None,
+        }
+    }
+
+    /// Start building a `200 OK` response
+    pub fn ok() -> Self {
+        ResponseBuilder::new(StatusCode::OK)
+    }
+
+    /// Start building a `201 Created` response, which should be given the `Location` of the created resource
+    pub fn created() -> Self {
+        ResponseBuilder::new(StatusCode::CREATED)
+    }
+
+    /// Start building a `202 Accepted` response
+    pub fn accepted() -> Self {
+        ResponseBuilder::new(StatusCode::ACCEPTED)
+    }
+
+    /// Start building a `204 No Content` response, which can't have a body
+    pub fn no_content() -> Self {
+        ResponseBuilder::new(StatusCode::NO_CONTENT)
+    }
+
+    /// Start building a `301 Moved Permanently` response, which must be given a `Location`
+    pub fn moved_permanently() -> Self {
+        ResponseBuilder::new(StatusCode::MOVED_PERMANENTLY)
+    }
+
+    /// Start building a `302 Found` response, which must be given a `Location`
+    pub fn found() -> Self {
+        ResponseBuilder::new(StatusCode::FOUND)
+    }
+
+    /// Start building a `303 See Other` response, which must be given a `Location`
+    pub fn see_other() -> Self {
+        ResponseBuilder::new(StatusCode::SEE_OTHER)
+    }
+
+    /// Start building a `304 Not Modified` response, which can't have a body
+    pub fn not_modified() -> Self {
+        ResponseBuilder::new(StatusCode::NOT_MODIFIED)
+    }
+
+    /// Start building a `307 Temporary Redirect` response, which must be given a `Location`
+    pub fn temporary_redirect() -> Self {
+        ResponseBuilder::new(StatusCode::TEMPORARY_REDIRECT)
+    }
+
+    /// Start building a `308 Permanent Redirect` response, which must be given a `Location`
+    pub fn permanent_redirect() -> Self {
+        ResponseBuilder::new(StatusCode::PERMANENT_REDIRECT)
+    }
+
+    /// Start building a `400 Bad Request` response
+    pub fn bad_request() -> Self {
+        ResponseBuilder::new(StatusCode::BAD_REQUEST)
+    }
+
+    /// Start building a `401 Unauthorized` response
+    pub fn unauthorized() -> Self {
+        ResponseBuilder::new(StatusCode::UNAUTHORIZED)
+    }
+
+    /// Start building a `403 Forbidden` response
+    pub fn forbidden() -> Self {
+        ResponseBuilder::new(StatusCode::FORBIDDEN)
+    }
+
+    /// Start building a `404 Not Found` response
+    pub fn not_found() -> Self {
+        ResponseBuilder::new(StatusCode::NOT_FOUND)
+    }
+
+    /// Start building a `409 Conflict` response
+    pub fn conflict() -> Self {
+        ResponseBuilder::new(StatusCode::CONFLICT)
+    }
+
+    /// Start building a `500 Internal Server Error` response
+    pub fn internal_server_error() -> Self {
+        ResponseBuilder::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Append a header to the response
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        let name: Result<header::HeaderName, ::http_types::Error> = HttpTryFrom::try_from(key).map_err(Into::into);
+        let value: Result<header::HeaderValue, ::http_types::Error> = HttpTryFrom::try_from(value).map_err(Into::into);
+
+        match (name, value) {
+            (Ok(name), Ok(value)) => { self.response.headers_map_mut().append(name, value); }
+            (Err(e), _) | (_, Err(e)) => self.fail(ResponseBuildError::InvalidHeader(e)),
+        }
+        self
+    }
+
+    /// Set the `Location` of the response, replacing the previous one if any
+    pub fn location<V>(mut self, location: V) -> Self
+        where header::HeaderValue: HttpTryFrom<V>
+    {
+        self.response.headers_map_mut().remove(header::LOCATION);
+        self.header(header::LOCATION, location)
+    }
+
+    /// Set the body of the response, leaving its `Content-Type` to the caller
+    pub fn body<B: 'static + ToBody>(mut self, body: B) -> Self {
+        self.response.body(body);
+        self.has_body = true;
+        self
+    }
+
+    /// Set `text` as the body of the response, along with the `text/plain; charset=utf-8` content type
+    pub fn text<S: Into<String>>(self, text: S) -> Self {
+        self.header(header::CONTENT_TYPE, "text/plain; charset=utf-8").body(text.into())
+    }
+
+    /// Serialize `value` as JSON and set it as the body of the response, along with the `application/json` content type
+    pub fn json<T: Serialize>(mut self, value: &T) -> Self {
+        match ::serde_json::to_vec(value) {
+            Ok(body) => self.header(header::CONTENT_TYPE, "application/json").body(body),
+            Err(e) => {
+                self.fail(ResponseBuildError::Serialization(e.to_string()));
+                self
+            }
+        }
+    }
+
+    /// Keep the first error, which the following ones are likely caused by
+    fn fail(&mut self, error: ResponseBuildError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Check the response and return it
+    pub fn build(self) -> Result<SyncResponse, ResponseBuildError> {
+        let ResponseBuilder { response, has_body, error } = self;
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let status = response.get_status();
+        if has_body && (status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED) {
+            return Err(ResponseBuildError::BodyNotAllowed(status));
+        }
+
+        let redirection = status.is_redirection() && status != StatusCode::MULTIPLE_CHOICES && status != StatusCode::NOT_MODIFIED;
+        if redirection && !response.headers_map().contains_key(header::LOCATION) {
+            return Err(ResponseBuildError::MissingLocation(status));
+        }
+
+        Ok(response)
+    }
+
+    /// Check the response and answer `res` with it: the status and the body of `res` are replaced, and the headers of the
+    /// response are appended to the ones of `res`, like the ones set by the middlewares. `res` is left untouched when the
+    /// response is invalid.
+    pub fn respond(self, res: &mut SyncResponse) -> Result<&mut SyncResponse, ResponseBuildError> {
+        let mut response = self.build()?;
+
+        res.status(response.get_status()).put_body(response.take_body());
+        for (name, values) in response.headers_map_mut().drain() {
+            for value in values {
+                res.headers_map_mut().append(name.clone(), value);
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Why a `ResponseBuilder` couldn't build its response
+#[derive(Debug)]
+pub enum ResponseBuildError {
+    /// The status of the response doesn't allow a body, like `204 No Content` or `304 Not Modified`
+    BodyNotAllowed(StatusCode),
+    /// The response redirects the client without telling it where, its `Location` is missing
+    MissingLocation(StatusCode),
+    /// The name or the value of a header is invalid
+    InvalidHeader(::http_types::Error),
+    /// The body couldn't be serialized
+    Serialization(String),
+}
+
+impl fmt::Display for ResponseBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ResponseBuildError::BodyNotAllowed(status) => write!(f, "A {} response can't have a body", status),
+            ResponseBuildError::MissingLocation(status) => write!(f, "A {} response must have a Location", status),
+            ResponseBuildError::InvalidHeader(ref e) => write!(f, "Invalid header: {}", e),
+            ResponseBuildError::Serialization(ref message) => write!(f, "Unable to serialize the response body: {}", message),
+        }
+    }
+}
+
+impl Error for ResponseBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ResponseBuildError::InvalidHeader(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
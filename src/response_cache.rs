@@ -0,0 +1,368 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use profile::Profile;
+use profile::PRODUCTION_CACHE_MAX_AGE;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// A response stored by the `ResponseCacheMiddleware`
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    /// Status of the cached response
+    pub status: StatusCode,
+    /// Headers of the cached response
+    pub headers: header::HeaderMap<header::HeaderValue>,
+    /// Body of the cached response
+    pub body: Vec<u8>,
+    /// When the response was stored
+    pub stored_at: SystemTime,
+    /// For how long the response is considered fresh
+    pub max_age: Duration,
+}
+
+impl CachedResponse {
+    /// Age of the cached response
+    pub fn age(&self) -> Duration {
+        SystemTime::now().duration_since(self.stored_at).unwrap_or_else(|_| Duration::from_secs(0))
+    }
+
+    /// Returns whether or not the cached response can still be served
+    pub fn is_fresh(&self) -> bool {
+        self.age() < self.max_age
+    }
+}
+
+/// A trait representing a storage backend for cached responses, implement it to share a cache between multiple instances
+/// using Redis, memcached or any other key-value store.
+pub trait CacheStore: Send + Sync {
+    /// Retrieve the response stored under `key`
+    fn get(&self, key: &str) -> Option<CachedResponse>;
+
+    /// Store a response under `key`, replacing any previous value
+    fn put(&self, key: String, response: CachedResponse);
+
+    /// Remove the response stored under `key`
+    fn remove(&self, key: &str);
+}
+
+struct LruEntry {
+    response: CachedResponse,
+    tick: u64,
+}
+
+struct LruState {
+    entries: HashMap<String, LruEntry>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+/// An in-memory `CacheStore` evicting the least recently used response when full
+pub struct MemoryCacheStore {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+impl MemoryCacheStore {
+    /// Create a new store holding at most `capacity` responses
+    pub fn new(capacity: usize) -> Self {
+        MemoryCacheStore {
+            capacity,
+            state: Mutex::new(LruState {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    /// Number of responses currently stored
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns whether or not the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CacheStore for MemoryCacheStore {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut state = self.state.lock().unwrap();
+        let LruState { ref mut entries, ref mut recency, ref mut tick } = *state;
+
+        entries.get_mut(key).map(|entry| {
+            *tick += 1;
+            recency.remove(&entry.tick);
+            recency.insert(*tick, key.to_string());
+            entry.tick = *tick;
+            entry.response.clone()
+        })
+    }
+
+    fn put(&self, key: String, response: CachedResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+
+        if let Some(previous) = state.entries.remove(&key) {
+            state.recency.remove(&previous.tick);
+        }
+
+        while state.entries.len() >= self.capacity {
+            let oldest = match state.recency.keys().next() {
+                Some(t) => *t,
+                None => break,
+            };
+
+            if let Some(evicted) = state.recency.remove(&oldest) {
+                state.entries.remove(&evicted);
+            }
+        }
+
+        state.recency.insert(tick, key.clone());
+        state.entries.insert(key, LruEntry { response, tick });
+    }
+
+    fn remove(&self, key: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(entry) = state.entries.remove(key) {
+            state.recency.remove(&entry.tick);
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CacheControlDirectives {
+    pub(crate) no_store: bool,
+    pub(crate) no_cache: bool,
+    pub(crate) private: bool,
+    pub(crate) max_age: Option<u64>,
+    pub(crate) s_max_age: Option<u64>,
+    pub(crate) stale_while_revalidate: Option<u64>,
+}
+
+impl CacheControlDirectives {
+    pub(crate) fn parse(headers: &header::HeaderMap<header::HeaderValue>) -> Self {
+        let mut directives = CacheControlDirectives::default();
+
+        for value in headers.get_all(header::CACHE_CONTROL).iter() {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            for directive in value.split(',') {
+                let mut parts = directive.trim().splitn(2, '=');
+                let name = parts.next().unwrap_or("").to_lowercase();
+                let arg = parts.next().map(|a| a.trim_matches('"'));
+
+                match name.as_str() {
+                    "no-store" => directives.no_store = true,
+                    "no-cache" => directives.no_cache = true,
+                    "private" => directives.private = true,
+                    "max-age" => directives.max_age = arg.and_then(|a| a.parse().ok()),
+                    "s-maxage" => directives.s_max_age = arg.and_then(|a| a.parse().ok()),
+                    "stale-while-revalidate" => directives.stale_while_revalidate = arg.and_then(|a| a.parse().ok()),
+                    _ => {}
+                }
+            }
+        }
+
+        directives
+    }
+}
+
+struct CacheHit;
+
+/// A middleware caching `GET` responses keyed by path, query and the request headers listed in the response's `Vary` header
+///
+/// Requests sent with `Cache-Control: no-store` bypass the cache, and `Cache-Control: no-cache` forces a fresh response.
+/// Responses are stored for the duration given by their `s-maxage` or `max-age` directive, or for the configured default
+/// duration when they have none. Responses with `no-store`, `no-cache`, `private`, `Set-Cookie` or `Vary: *` are never stored,
+/// and neither are streamed responses.
+/// Under the development `Profile` the cache is bypassed, under the production one the default duration is
+/// `PRODUCTION_CACHE_MAX_AGE` unless another one is configured.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let cache = ResponseCacheMiddleware::new(MemoryCacheStore::new(1024));
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(cache, vec!("^/api"), None);
+/// ```
+pub struct ResponseCacheMiddleware {
+    store: Box<dyn CacheStore>,
+    default_max_age: Option<Duration>,
+    vary_index: RwLock<HashMap<String, Vec<header::HeaderName>>>,
+}
+
+impl ResponseCacheMiddleware {
+    /// Create a new cache middleware backed by `store`. By default, only responses having an explicit `max-age` are cached.
+    pub fn new<S: 'static + CacheStore>(store: S) -> Self {
+        ResponseCacheMiddleware {
+            store: Box::new(store),
+            default_max_age: None,
+            vary_index: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Cache responses without freshness information for `max_age`
+    pub fn default_max_age(mut self, max_age: Duration) -> Self {
+        self.default_max_age = Some(max_age);
+        self
+    }
+
+    pub(crate) fn primary_key(req: &SyncRequest) -> String {
+        let path = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or_else(|| req.uri().path());
+        format!("{} {}", req.method(), path)
+    }
+
+    pub(crate) fn variant_key(primary: &str, vary: &[header::HeaderName], headers: &header::HeaderMap) -> String {
+        let mut key = primary.to_string();
+
+        for name in vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            for value in headers.get_all(name).iter() {
+                key.push_str(value.to_str().unwrap_or(""));
+                key.push(',');
+            }
+        }
+
+        key
+    }
+
+    fn key_for(&self, req: &SyncRequest) -> String {
+        let primary = Self::primary_key(req);
+
+        match self.vary_index.read().unwrap().get(&primary) {
+            Some(vary) => Self::variant_key(&primary, vary, req.headers_map()),
+            None => primary,
+        }
+    }
+
+    fn max_age_for(&self, res: &SyncResponse) -> Option<Duration> {
+        let headers = res.headers_map();
+
+        if headers.contains_key(header::SET_COOKIE) {
+            return None;
+        }
+
+        let directives = CacheControlDirectives::parse(headers);
+        if directives.no_store || directives.no_cache || directives.private {
+            return None;
+        }
+
+        let default_max_age = match res.profile() {
+            Some(Profile::Production) => self.default_max_age.or(Some(PRODUCTION_CACHE_MAX_AGE)),
+            _ => self.default_max_age,
+        };
+
+        directives.s_max_age.or(directives.max_age).map(Duration::from_secs).or(default_max_age)
+    }
+}
+
+/// Returns the request headers listed by the `Vary` header of a response, `None` when it varies on everything
+pub(crate) fn vary_headers(headers: &header::HeaderMap) -> Option<Vec<header::HeaderName>> {
+    let mut vary = Vec::new();
+    for value in headers.get_all(header::VARY).iter() {
+        for name in value.to_str().unwrap_or("*").split(',') {
+            let name = name.trim();
+            if name == "*" {
+                return None;
+            }
+
+            if let Ok(name) = name.to_lowercase().parse::<header::HeaderName>() {
+                vary.push(name);
+            }
+        }
+    }
+    Some(vary)
+}
+
+pub(crate) fn is_cacheable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 200 | 203 | 204 | 300 | 301 | 404 | 405 | 410 | 414 | 501)
+}
+
+impl Middleware for ResponseCacheMiddleware {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if *req.method() != Method::GET || req.profile() == Some(Profile::Development) {
+            return RequestContinuation::Next;
+        }
+
+        let directives = CacheControlDirectives::parse(req.headers_map());
+        if directives.no_store || directives.no_cache {
+            return RequestContinuation::Next;
+        }
+
+        let key = self.key_for(req);
+        let cached = match self.store.get(&key) {
+            Some(cached) => cached,
+            None => return RequestContinuation::Next,
+        };
+
+        if !cached.is_fresh() {
+            self.store.remove(&key);
+            return RequestContinuation::Next;
+        }
+
+        res.status(cached.status);
+        for name in cached.headers.keys() {
+            res.headers_map_mut().remove(name);
+        }
+        for (name, value) in cached.headers.iter() {
+            res.headers_map_mut().append(name.clone(), value.clone());
+        }
+        res.header(header::AGE, cached.age().as_secs().to_string());
+        res.extension(CacheHit);
+        res.body(cached.body);
+
+        RequestContinuation::None
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET || req.profile() == Some(Profile::Development) || res.get_extensions().get::<CacheHit>().is_some() {
+            return;
+        }
+
+        if CacheControlDirectives::parse(req.headers_map()).no_store || !is_cacheable_status(res.get_status()) || res.is_streamed() {
+            return;
+        }
+
+        let max_age = match self.max_age_for(res) {
+            Some(max_age) => max_age,
+            None => return,
+        };
+
+        let vary = match vary_headers(res.headers_map()) {
+            Some(vary) => vary,
+            None => return,
+        };
+
+        let primary = Self::primary_key(req);
+        let key = Self::variant_key(&primary, &vary, req.headers_map());
+        self.vary_index.write().unwrap().insert(primary, vary);
+
+        self.store.put(key, CachedResponse {
+            status: res.get_status(),
+            headers: res.headers_map().clone(),
+            body: res.get_body(),
+            stored_at: SystemTime::now(),
+            max_age,
+        });
+    }
+}
@@ -0,0 +1,83 @@
+use http::*;
+use body_transform::BodyTransform;
+use body_transform::transform_streamed;
+use logging::*;
+use log::Level;
+use std::io;
+
+/// The buckets of the `http_response_size_bytes` histogram, in bytes
+pub const RESPONSE_SIZE_BUCKETS: &[f64] = &[100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0, 10_000_000.0, 100_000_000.0];
+
+/// Counts the bytes of a streamed body, reporting them once the body is dropped, whether it was sent entirely or not
+struct CountBytes<F: FnOnce(u64)> {
+    count: u64,
+    report: Option<F>,
+}
+
+impl<F: FnOnce(u64) + Send> BodyTransform for CountBytes<F> {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.count += chunk.len() as u64;
+        Ok(chunk.to_vec())
+    }
+}
+
+impl<F: FnOnce(u64)> Drop for CountBytes<F> {
+    fn drop(&mut self) {
+        if let Some(report) = self.report.take() {
+            report(self.count);
+        }
+    }
+}
+
+/// Fails a streamed body once it exceeds its limit, which aborts the response
+struct LimitBytes {
+    count: u64,
+    max: u64,
+    method: Method,
+    path: String,
+}
+
+impl BodyTransform for LimitBytes {
+    fn chunk(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.count += chunk.len() as u64;
+
+        if self.count > self.max {
+            log_event(Level::Error, HANDLER_LOG_TARGET, "response too large",
+                      format_args!("The streamed response to {} {} exceeded {} bytes and was aborted", self.method, self.path, self.max),
+                      &[("method", &self.method), ("path", &self.path), ("max_size", &self.max)]);
+            return Err(io::Error::other("the response exceeds its maximum size"));
+        }
+
+        Ok(chunk.to_vec())
+    }
+}
+
+/// Invoke `report` with the size of the body of `res`, right away, or once a streamed body is sent
+pub(crate) fn observe_response_size<F: 'static + FnOnce(u64) + Send>(res: &mut SyncResponse, report: F) {
+    if res.is_streamed() {
+        transform_streamed(res, Box::new(CountBytes { count: 0, report: Some(report) }));
+    } else {
+        report(res.get_body().len() as u64);
+    }
+}
+
+/// Answer with `500 Internal Server Error` when the body of `res` exceeds `max` bytes, or abort a streamed body once it does
+pub(crate) fn limit_response_size(req: &SyncRequest, res: &mut SyncResponse, max: u64) {
+    if res.is_streamed() {
+        return transform_streamed(res, Box::new(LimitBytes {
+            count: 0,
+            max,
+            method: req.method().clone(),
+            path: req.uri().path().to_string(),
+        }));
+    }
+
+    let size = res.get_body().len() as u64;
+    if size > max {
+        log_event(Level::Error, HANDLER_LOG_TARGET, "response too large",
+                  format_args!("The response to {} {} is {} bytes, over its maximum of {}", req.method(), req.uri().path(), size, max),
+                  &[("method", req.method()), ("path", &req.uri().path()), ("size", &size), ("max_size", &max)]);
+        res.headers_map_mut().remove(header::CONTENT_LENGTH);
+        res.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,181 @@
+use http::*;
+use https::is_https;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use utils::ToRegex;
+use regex::Regex;
+
+/// What to do with a request matching a rewrite rule
+enum RewriteAction {
+    /// Replace the path of the request, the client doesn't know about it
+    Rewrite(String),
+    /// Redirect the client to the replaced path with the given status
+    Redirect(String, Option<StatusCode>),
+    /// Redirect the client to the same url with the `https` scheme
+    Https,
+}
+
+struct RewriteRule {
+    pattern: Regex,
+    action: RewriteAction,
+}
+
+/// How `RewriteMiddleware::trailing_slash` normalizes paths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailingSlash {
+    /// Redirect `/foo/` to `/foo`
+    Remove,
+    /// Redirect `/foo` to `/foo/`, paths whose last segment contains a dot, like `/app.js`, are left untouched
+    Add,
+}
+
+/// A middleware rewriting the path of requests, or redirecting the client, according to an ordered list of rules
+///
+/// Each rule matches a regular expression against the path of the request, the first matching rule is applied and the following
+/// ones are ignored. Replacements may refer to the groups of the pattern with `$1` or `$name`. When the replacement doesn't
+/// contain a query, the query of the request is kept.
+///
+/// Rewrites alter the request before the following middlewares and the router see it. Redirects answer right away with a
+/// `Location` header; unless a status is given, permanent redirects use `301 Moved Permanently` for `GET` and `HEAD` requests
+/// and `308 Permanent Redirect` for the others, so clients keep the method and the body.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let rewrites = RewriteMiddleware::new()
+///     .enforce_https()
+///     .trailing_slash(TrailingSlash::Remove)
+///     .redirect("^/blog/(?P<slug>[^/]+)$", "/articles/$slug")
+///     .rewrite("^/u/(\\d+)$", "/users/$1");
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(rewrites, vec!("/"), None);
+/// ```
+#[derive(Default)]
+pub struct RewriteMiddleware {
+    rules: Vec<RewriteRule>,
+}
+
+impl RewriteMiddleware {
+    /// Create a middleware without any rule
+    pub fn new() -> Self {
+        RewriteMiddleware::default()
+    }
+
+    fn rule<R: ToRegex>(mut self, pattern: R, action: RewriteAction) -> Self {
+        self.rules.push(RewriteRule {
+            pattern: reg!(pattern),
+            action,
+        });
+        self
+    }
+
+    /// Internally replace the path of the requests matching `pattern` with `replacement`
+    pub fn rewrite<R: ToRegex, S: Into<String>>(self, pattern: R, replacement: S) -> Self {
+        self.rule(pattern, RewriteAction::Rewrite(replacement.into()))
+    }
+
+    /// Permanently redirect the requests matching `pattern` to `replacement`
+    pub fn redirect<R: ToRegex, S: Into<String>>(self, pattern: R, replacement: S) -> Self {
+        self.rule(pattern, RewriteAction::Redirect(replacement.into(), None))
+    }
+
+    /// Redirect the requests matching `pattern` to `replacement` with the redirection `status`
+    pub fn redirect_with<R: ToRegex, S: Into<String>>(self, pattern: R, replacement: S, status: StatusCode) -> Self {
+        self.rule(pattern, RewriteAction::Redirect(replacement.into(), Some(status)))
+    }
+
+    /// Permanently redirect paths ending with a slash to the same path without it, or the opposite
+    pub fn trailing_slash(self, policy: TrailingSlash) -> Self {
+        match policy {
+            TrailingSlash::Remove => self.redirect("^(.+)/$", "$1"),
+            TrailingSlash::Add => self.redirect("^((?:.*/)?[^/.]+)$", "$1/"),
+        }
+    }
+
+    /// Permanently redirect every request to `https`, unless it was received over `https`, directly or through a proxy trusted
+    /// by the `TrustedProxies` of the server which tells so with `X-Forwarded-Proto`
+    pub fn enforce_https(self) -> Self {
+        self.rule("^", RewriteAction::Https)
+    }
+}
+
+/// Append the query of `uri` to `target`, unless `target` has its own query
+pub fn with_query(target: String, uri: &Uri) -> String {
+    match uri.query() {
+        Some(query) if !target.contains('?') => format!("{}?{}", target, query),
+        _ => target,
+    }
+}
+
+/// Redirect the client to `location` with `status`, defaulting to a permanent redirect keeping the method of the request
+pub fn redirect(req: &SyncRequest, res: &mut SyncResponse, location: String, status: Option<StatusCode>) {
+    let status = status.unwrap_or_else(|| match *req.method() {
+        Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+        _ => StatusCode::PERMANENT_REDIRECT,
+    });
+
+    res.status(status).header(header::LOCATION, location).body(Vec::<u8>::new());
+}
+
+impl Middleware for RewriteMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let https = is_https(req);
+        let (rule, target) = {
+            let path = req.uri().path();
+            // Once the request is received over https, the rule enforcing it is satisfied and the following ones apply
+            let applies = |r: &&RewriteRule| r.pattern.is_match(path) && match r.action {
+                RewriteAction::Https => !https,
+                _ => true,
+            };
+            let rule = match self.rules.iter().find(applies) {
+                Some(rule) => rule,
+                None => return RequestContinuation::Next,
+            };
+
+            let target = match rule.action {
+                RewriteAction::Rewrite(ref replacement) | RewriteAction::Redirect(ref replacement, _) => {
+                    rule.pattern.replace(path, replacement.as_str()).into_owned()
+                }
+                RewriteAction::Https => path.to_string(),
+            };
+
+            (rule, with_query(target, req.uri()))
+        };
+
+        match rule.action {
+            RewriteAction::Rewrite(_) => {
+                match target.parse() {
+                    Ok(uri) => *req.uri_mut() = uri,
+                    Err(e) => warn!("Unable to rewrite {} to {}: {}", req.uri(), target, e),
+                }
+                RequestContinuation::Next
+            }
+            RewriteAction::Redirect(_, status) => {
+                redirect(req, res, target, status);
+                RequestContinuation::None
+            }
+            RewriteAction::Https => {
+                let host = req.headers_map().get(header::HOST).and_then(|h| h.to_str().ok()).map(|h| h.to_string())
+                    .or_else(|| req.uri().authority_part().map(|a| a.as_str().to_string()));
+
+                match host {
+                    Some(host) => {
+                        let host = host.rsplit_once(':').filter(|(_, port)| port.bytes().all(|b| b.is_ascii_digit()))
+                            .map_or(host.as_str(), |(host, _)| host);
+                        redirect(req, res, format!("https://{}{}", host, target), None);
+                    }
+                    None => {
+                        res.status(StatusCode::BAD_REQUEST);
+                    }
+                }
+                RequestContinuation::None
+            }
+        }
+    }
+}
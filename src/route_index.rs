@@ -0,0 +1,228 @@
+use regex::Regex;
+
+/// Returns the path matched by a route pattern made only of literal characters, along with whether the pattern is anchored
+/// at the end, or `None` if the pattern is not anchored at the start or contains any other meta character.
+pub fn literal_path(pattern: &str) -> Option<(&str, bool)> {
+    if !pattern.starts_with('^') {
+        return None;
+    }
+
+    let (path, exact) = if pattern.len() > 1 && pattern.ends_with('$') {
+        (&pattern[1..pattern.len() - 1], true)
+    } else {
+        (&pattern[1..], false)
+    };
+
+    if path.contains(|c| "\\.+*?()|[]{}^$".contains(c)) {
+        return None;
+    }
+
+    Some((path, exact))
+}
+
+/// Convert the regular expression of a route into a path template, like `/users/{id}`, along with its path parameters and
+/// whether they are integers.
+///
+/// Named groups become named parameters and other groups or character classes become `param1`, `param2`, etc. This is a
+/// best effort conversion, alternations and optional segments cannot be represented by a path template.
+pub(crate) fn path_template(regex: &str) -> (String, Vec<(String, bool)>) {
+    let regex = regex.trim_start_matches('^').trim_end_matches('$');
+    let chars = regex.chars().collect::<Vec<char>>();
+    let mut template = String::new();
+    let mut params = Vec::new();
+    let mut i = 0;
+
+    let mut push_param = |template: &mut String, name: Option<String>, body: &str| {
+        let name = name.unwrap_or_else(|| format!("param{}", params.len() + 1));
+        let integer = body == "\\d+" || body == "[0-9]+";
+        template.push_str(&format!("{{{}}}", name));
+        params.push((name, integer));
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                template.push(chars[i + 1]);
+                i += 2;
+            }
+            '(' => {
+                let start = i;
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => i += 1,
+                        '(' => depth += 1,
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    i += 1;
+                }
+
+                let group = chars[start + 1..i.min(chars.len())].iter().collect::<String>();
+                let (name, body) = if group.starts_with("?P<") || group.starts_with("?<") {
+                    let group = group.trim_start_matches("?P").trim_start_matches('?');
+                    let end = group.find('>').unwrap_or(group.len());
+                    (Some(group[1..end].to_string()), group[(end + 1).min(group.len())..].to_string())
+                } else {
+                    (None, group.trim_start_matches("?:").to_string())
+                };
+
+                push_param(&mut template, name, &body);
+                i += 1;
+                while i < chars.len() && "*+?".contains(chars[i]) {
+                    i += 1;
+                }
+            }
+            '[' | '.' => {
+                let start = i;
+                if chars[i] == '[' {
+                    while i < chars.len() && chars[i] != ']' {
+                        i += if chars[i] == '\\' { 2 } else { 1 };
+                    }
+                }
+                i += 1;
+                while i < chars.len() && "*+?".contains(chars[i]) {
+                    i += 1;
+                }
+
+                let body = chars[start..i.min(chars.len())].iter().collect::<String>();
+                push_param(&mut template, None, &body);
+            }
+            '*' | '+' | '?' | '|' => i += 1,
+            c => {
+                template.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    if !template.starts_with('/') {
+        template.insert(0, '/');
+    }
+
+    (template, params)
+}
+
+fn common_prefix_len(a: &str, b: &str) -> usize {
+    let mut len = a.bytes().zip(b.bytes()).take_while(|&(a, b)| a == b).count();
+
+    while !a.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    len
+}
+
+/// A compressed prefix tree of paths, keeping the smallest value inserted for each path
+#[derive(Default)]
+struct RadixTree {
+    label: String,
+    value: Option<usize>,
+    children: Vec<RadixTree>,
+}
+
+impl RadixTree {
+    fn insert(&mut self, key: &str, value: usize) {
+        if key.is_empty() {
+            self.value = Some(self.value.map_or(value, |v| v.min(value)));
+            return;
+        }
+
+        for child in &mut self.children {
+            let common = common_prefix_len(&child.label, key);
+
+            if common == 0 {
+                continue;
+            }
+
+            if common < child.label.len() {
+                let split = RadixTree {
+                    label: child.label[common..].to_string(),
+                    value: child.value.take(),
+                    children: ::std::mem::take(&mut child.children),
+                };
+                child.label.truncate(common);
+                child.children.push(split);
+            }
+
+            child.insert(&key[common..], value);
+            return;
+        }
+
+        self.children.push(RadixTree {
+            label: key.to_string(),
+            value: Some(value),
+            children: Vec::new(),
+        });
+    }
+
+    /// Walk the tree along `path`, returns the smallest value of the keys prefixing `path` and the value of `path` itself
+    fn lookup(&self, path: &str) -> (Option<usize>, Option<usize>) {
+        let mut node = self;
+        let mut rest = path;
+        let mut smallest_prefix = node.value;
+
+        while let Some(child) = node.children.iter().find(|c| rest.starts_with(c.label.as_str())) {
+            rest = &rest[child.label.len()..];
+            node = child;
+            smallest_prefix = min(smallest_prefix, node.value);
+        }
+
+        (smallest_prefix, if rest.is_empty() { node.value } else { None })
+    }
+}
+
+fn min(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// An index of route patterns, finding the first pattern matching a path
+///
+/// Patterns made of a literal path, like `^/users$` or `^/api`, are looked up in prefix trees in a time proportional to the
+/// length of the path. Other patterns are matched one by one as regular expressions, stopping as soon as a literal pattern
+/// registered before them is known to match.
+#[derive(Default)]
+pub struct RouteIndex {
+    exact: RadixTree,
+    prefixes: RadixTree,
+    patterns: Vec<(usize, Regex)>,
+}
+
+impl RouteIndex {
+    /// Create an empty index
+    pub fn new() -> Self {
+        RouteIndex::default()
+    }
+
+    /// Add `regex` to the index, identified by `position`. Positions must be inserted in increasing order.
+    pub fn insert(&mut self, position: usize, regex: &Regex) {
+        match literal_path(regex.as_str()) {
+            Some((path, true)) => self.exact.insert(path, position),
+            Some((path, false)) => self.prefixes.insert(path, position),
+            None => self.patterns.push((position, regex.clone())),
+        }
+    }
+
+    /// Returns the position of the first pattern matching `path`
+    pub fn find(&self, path: &str) -> Option<usize> {
+        let (_, exact) = self.exact.lookup(path);
+        let (prefix, _) = self.prefixes.lookup(path);
+        let best = min(exact, prefix);
+
+        let pattern = self.patterns.iter()
+            .take_while(|&&(position, _)| best.is_none_or(|best| position < best))
+            .find(|(_, regex)| regex.is_match(path))
+            .map(|&(position, _)| position);
+
+        min(best, pattern)
+    }
+}
@@ -0,0 +1,106 @@
+/// The documentation of a delegate route, given when it is registered with `ControllerDispatch::add_documented` or
+/// afterwards with `ControllerDispatch::document`
+///
+/// It is reported along with the route by `Router::routes`, completes the operations of the OpenAPI specification, is
+/// listed in the route table of the `DebugController` and the `AdminController`, and its operation id labels the metrics
+/// of the route unless it was given a label of its own.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut controller = BasicController::new(());
+/// let metadata = RouteMetadata::new()
+///     .summary("Fetch a user")
+///     .operation_id("users.show")
+///     .tag("users")
+///     .response(200, "The user")
+///     .response(404, "No such user");
+///
+/// controller.add_documented(Method::GET, "^/users/([0-9]+)$", metadata, |_, _, res| { res.status(StatusCode::OK); });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RouteMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    responses: Vec<ExpectedResponse>,
+}
+
+/// A response a route is documented to answer, see `RouteMetadata::response`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExpectedResponse {
+    /// The status of the response
+    pub status: u16,
+    /// What the response means
+    pub description: String,
+}
+
+impl RouteMetadata {
+    /// Create an empty documentation
+    pub fn new() -> Self {
+        RouteMetadata::default()
+    }
+
+    /// Set a short summary of the route
+    pub fn summary<S: Into<String>>(mut self, summary: S) -> Self {
+        self.summary = Some(summary.into());
+        self
+    }
+
+    /// Set a verbose description of the route
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Set the unique identifier of the route, its `operationId` in the OpenAPI specification. It must only contain
+    /// characters suited to a metrics label.
+    pub fn operation_id<S: Into<String>>(mut self, operation_id: S) -> Self {
+        self.operation_id = Some(operation_id.into());
+        self
+    }
+
+    /// Add a tag grouping the route with others
+    pub fn tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Document a response the route answers with `status`
+    pub fn response<S: Into<String>>(mut self, status: u16, description: S) -> Self {
+        self.responses.push(ExpectedResponse { status, description: description.into() });
+        self
+    }
+
+    /// Returns the summary of the route
+    pub fn get_summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    /// Returns the description of the route
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Returns the unique identifier of the route
+    pub fn get_operation_id(&self) -> Option<&str> {
+        self.operation_id.as_deref()
+    }
+
+    /// Returns the tags of the route
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Returns the responses the route is documented to answer
+    pub fn get_responses(&self) -> &[ExpectedResponse] {
+        &self.responses
+    }
+}
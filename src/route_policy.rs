@@ -0,0 +1,221 @@
+use http::*;
+use logging::*;
+use log::Level;
+use proxy::is_idempotent;
+use cancellation::CancellationToken;
+use concurrency_limit::Limiter;
+use response_size::limit_response_size;
+use std::fmt;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+type Fallback<T> = Box<dyn Fn(&T, &SyncRequest, &mut SyncResponse) + Send + Sync>;
+
+/// How a delegate recovers from failures, set when the delegate is registered with `BasicController::add_with_policy`
+///
+/// An attempt fails when the delegate panics, answers with a server error status, or completes after the timeout. Failed
+/// attempts of idempotent requests are retried, as long as the timeout isn't elapsed, each attempt starting from the
+/// response as it was before the first one. Once every attempt failed, the fallback is invoked on that response; without
+/// fallback, a timed out request is answered with `504 Gateway Timeout`, a panic with `500 Internal Server Error`, and a
+/// server error is left as is.
+///
+/// Delegates run synchronously, so the timeout can't interrupt an attempt in progress: it bounds when attempts may start,
+/// and turns the late ones into failures. It is also set as the deadline of the request, for delegates to give up on their
+/// own, see `SyncRequest::is_cancelled`.
+///
+/// A maximum response size guards against a delegate producing an unbounded body: a larger body is replaced with a
+/// `500 Internal Server Error`, and a streamed body is aborted once it grows over the maximum, leaving the client with
+/// an incomplete response.
+///
+/// A maximum concurrency caps the number of requests the delegate processes at once, like an expensive report limited
+/// to 2 at a time. Excess requests wait for a slot in a bounded queue, or are answered with `429 Too Many Requests` and a
+/// `Retry-After` header. Waiting requests block the thread they are processed on, see `ServerBuilder::handler_threads`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let policy = RoutePolicy::new()
+///     .timeout(Duration::from_secs(2))
+///     .retries(2)
+///     .fallback(|_, _, res| { res.status(StatusCode::OK).body("[]"); });
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_policy(Method::GET, "^/recommendations$", policy, |_, _, res| {
+///     res.status(StatusCode::OK).body("[\"saphir\"]");
+/// });
+/// ```
+pub struct RoutePolicy<T> {
+    timeout: Option<Duration>,
+    retries: usize,
+    fallback: Option<Fallback<T>>,
+    max_response_size: Option<u64>,
+    max_concurrency: Option<usize>,
+    max_queued: usize,
+    max_wait: Duration,
+    limiter: Arc<Limiter>,
+}
+
+/// Why an attempt failed
+enum Failure {
+    Panicked,
+    ServerError,
+    TimedOut,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Panicked => write!(f, "panicked"),
+            Failure::ServerError => write!(f, "server error"),
+            Failure::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+impl<T> RoutePolicy<T> {
+    /// Create a policy without timeout, retries, fallback, maximum response size nor maximum concurrency
+    pub fn new() -> Self {
+        RoutePolicy {
+            timeout: None,
+            retries: 0,
+            fallback: None,
+            max_response_size: None,
+            max_concurrency: None,
+            max_queued: 0,
+            max_wait: Duration::from_secs(0),
+            limiter: Limiter::new(),
+        }
+    }
+
+    /// Fail the attempts completing after `timeout` from the start of the first one
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry failed attempts of `GET`, `HEAD`, `PUT`, `DELETE`, `OPTIONS` and `TRACE` requests up to `retries` times
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Invoke `fallback` to answer the request once every attempt failed
+    pub fn fallback<F>(mut self, fallback: F) -> Self
+        where for<'r, 's, 't0> F: 'static + Fn(&'r T, &'s SyncRequest, &'t0 mut SyncResponse) + Send + Sync {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Answer with `500 Internal Server Error` when the body of the response exceeds `bytes`, or abort it once it does
+    /// when it is streamed
+    pub fn max_response_size(mut self, bytes: u64) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Let the delegate process at most `max_concurrency` requests at once, answering the excess right away with
+    /// `429 Too Many Requests`
+    pub fn max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
+    /// Let up to `max_queued` requests wait for at most `max_wait` when the maximum concurrency is reached
+    pub fn queue(mut self, max_queued: usize, max_wait: Duration) -> Self {
+        self.max_queued = max_queued;
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Invoke `delegate` according to the policy
+    pub(crate) fn invoke<F>(&self, delegate: &F, context: &T, req: &SyncRequest, res: &mut SyncResponse)
+        where F: Fn(&T, &SyncRequest, &mut SyncResponse) {
+        // Dropping the permit once the delegate completed releases the slot
+        let _permit = match self.max_concurrency {
+            Some(max_concurrency) => match self.limiter.acquire(max_concurrency, self.max_queued, self.max_wait) {
+                Some(permit) => Some(permit),
+                None => {
+                    res.status(StatusCode::TOO_MANY_REQUESTS).header(header::RETRY_AFTER, "1").body(Vec::<u8>::new());
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        self.attempt(delegate, context, req, res);
+
+        if let Some(max) = self.max_response_size {
+            limit_response_size(req, res, max);
+        }
+    }
+
+    /// Invoke `delegate` as many times as the timeout and the retries allow, then the fallback if every attempt failed
+    fn attempt<F>(&self, delegate: &F, context: &T, req: &SyncRequest, res: &mut SyncResponse)
+        where F: Fn(&T, &SyncRequest, &mut SyncResponse) {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        if let Some(deadline) = deadline {
+            if let Some(token) = req.extensions().get::<CancellationToken>() {
+                token.set_deadline(deadline);
+            }
+        }
+        let attempts = if is_idempotent(req.method()) { self.retries + 1 } else { 1 };
+        let status = res.get_status();
+        let headers = res.headers_map().clone();
+
+        let mut failure = Failure::TimedOut;
+        for attempt in 0..attempts {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                failure = Failure::TimedOut;
+                break;
+            }
+
+            if attempt > 0 {
+                reset(res, status, &headers);
+            }
+
+            let panicked = catch_unwind(AssertUnwindSafe(|| delegate(context, req, res))).is_err();
+
+            failure = if panicked {
+                Failure::Panicked
+            } else if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                Failure::TimedOut
+            } else if res.get_status().is_server_error() {
+                Failure::ServerError
+            } else {
+                return;
+            };
+
+            log_event(Level::Warn, HANDLER_LOG_TARGET, "attempt failed",
+                      format_args!("Attempt {} of {} at {} {} failed: {}", attempt + 1, attempts, req.method(), req.uri().path(), failure),
+                      &[("method", req.method()), ("path", &req.uri().path()), ("attempt", &(attempt + 1)), ("failure", &failure)]);
+        }
+
+        if let Some(ref fallback) = self.fallback {
+            reset(res, status, &headers);
+            return fallback(context, req, res);
+        }
+
+        match failure {
+            Failure::Panicked => { reset(res, StatusCode::INTERNAL_SERVER_ERROR, &headers); }
+            Failure::TimedOut => { reset(res, StatusCode::GATEWAY_TIMEOUT, &headers); }
+            Failure::ServerError => {}
+        }
+    }
+}
+
+impl<T> Default for RoutePolicy<T> {
+    fn default() -> Self {
+        RoutePolicy::new()
+    }
+}
+
+/// Bring `res` back to `status` and `headers`, without a body
+fn reset(res: &mut SyncResponse, status: StatusCode, headers: &header::HeaderMap<header::HeaderValue>) {
+    *res.headers_map_mut() = headers.clone();
+    res.status(status).body(Vec::<u8>::new());
+}
@@ -3,11 +3,107 @@ use utils::ToRegex;
 use regex::Regex;
 
 use controller::Controller;
+use controller::RouteInfo;
+use controller::RouteConflictPolicy;
+use controller::is_shadowed_by;
+use route_index::RouteIndex;
+use route_index::path_template;
+use query::percent_encode;
+use host_routing::HostPattern;
+use versioning::versioned_route;
+use rewrite::TrailingSlash;
+use rewrite::redirect;
+use rewrite::with_query;
+use logging::*;
+use log::Level;
+use utils::RequestContinuation;
+use path::normalize_path;
+use std::borrow::Cow;
+use profiling::Phase;
+use profiling::profiled;
+use profiling::record_since;
+use std::time::Instant;
+
+/// How a `Router` handles a trailing slash at the end of request paths
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum TrailingSlashPolicy {
+    /// Paths are matched as they are received, `/foo` and `/foo/` are different paths
+    #[default]
+    Strict,
+    /// The trailing slash is removed before matching, so `/foo` and `/foo/` are both matched by routes written without it
+    Ignore,
+    /// Permanently redirect the client so the path ends with a slash, or doesn't, see `TrailingSlash`
+    Redirect(TrailingSlash),
+}
+
+/// Replace the path of the request, keeping its query
+pub fn set_path(req: &mut SyncRequest, path: &str) {
+    let target = with_query(path.to_string(), req.uri());
+    let mut parts = req.uri().clone().into_parts();
+
+    match target.parse() {
+        Ok(path_and_query) => {
+            parts.path_and_query = Some(path_and_query);
+            if let Ok(uri) = Uri::from_parts(parts) {
+                *req.uri_mut() = uri;
+            }
+        }
+        Err(e) => warn!(target: ROUTING_LOG_TARGET, "Unable to set the path of {} to {}: {}", req.uri(), path, e),
+    }
+}
+
+/// Marks the requests prepared by a case insensitive router, whose routes are matched against the lowercased path
+struct CaseInsensitive;
+
+/// Returns the path the routes and the middleware rules are matched against, which is the path of the request, lowercased
+/// when the router is case insensitive. It follows the rewrites of the middlewares.
+pub fn routing_path(req: &SyncRequest) -> Cow<'_, str> {
+    let path = req.uri().path();
+    if req.extensions().get::<CaseInsensitive>().is_some() && path.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(path.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// The routes registered for the hosts matching a pattern, see `Router::add_for_host`
+struct HostRoutes {
+    pattern: HostPattern,
+    routes: Vec<(Regex, Box<dyn Controller>)>,
+    index: RouteIndex,
+}
+
+/// The position of the host routes whose pattern matches the host of a request prepared by the router
+struct MatchedHost(usize);
+
+/// Add `controller` under `route` to `routes`, reporting the route shadowed by a previous one according to `policy`
+fn register(routes: &mut Vec<(Regex, Box<dyn Controller>)>, index: &mut RouteIndex, policy: RouteConflictPolicy, route: Regex,
+            controller: Box<dyn Controller>) {
+    if let Some((previous, _)) = routes.iter().find(|(previous, _)| is_shadowed_by(&route, previous)) {
+        policy.report(&format!("The controller route {} is shadowed by the controller route {} registered before it and will never be reached",
+                               route.as_str(), previous.as_str()));
+    }
+
+    index.insert(routes.len(), &route);
+    routes.push((route, controller))
+}
 
 /// A Struct responsible of dispatching request towards controllers
 pub struct Router {
     ///
-    routes: Vec<(Regex, Box<Controller>)>
+    routes: Vec<(Regex, Box<dyn Controller>)>,
+    /// Index of the routes, to find the controller matching a path
+    index: RouteIndex,
+    /// Routes restricted to the hosts matching a pattern, in their registration order
+    hosts: Vec<HostRoutes>,
+    /// What to do when a controller is shadowed by a previous one
+    conflict_policy: RouteConflictPolicy,
+    /// How the trailing slash of paths is handled
+    trailing_slash: TrailingSlashPolicy,
+    /// Whether routes are matched against the lowercased path
+    case_insensitive: bool,
+    /// Whether paths are normalized before matching
+    normalize_paths: bool,
 }
 
 impl Router {
@@ -15,21 +111,133 @@ impl Router {
     pub fn new() -> Self {
         Router {
             routes: Vec::new(),
+            index: RouteIndex::new(),
+            hosts: Vec::new(),
+            conflict_policy: RouteConflictPolicy::default(),
+            trailing_slash: TrailingSlashPolicy::default(),
+            case_insensitive: false,
+            normalize_paths: true,
         }
     }
 
+    /// Set what to do when a controller is added after a controller whose route shadows it
+    pub fn conflict_policy(mut self, policy: RouteConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Set how the trailing slash of paths is handled, `TrailingSlashPolicy::Strict` by default
+    pub fn trailing_slash(mut self, policy: TrailingSlashPolicy) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Match routes against the lowercased path, so routes written in lowercase match paths regardless of their case. The uri
+    /// of the request is left untouched, handlers still see the path as it was received.
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
+    /// Set whether paths are normalized with `normalize_path` before matching, which is the case by default. Requests whose
+    /// path can't be normalized are answered with `400 Bad Request`.
+    pub fn normalize_paths(mut self, normalize: bool) -> Self {
+        self.normalize_paths = normalize;
+        self
+    }
+
+    /// Apply the path policies of the router to `req`, before the middlewares and the router see it. Returns
+    /// `RequestContinuation::None` when the client is redirected, or the path is rejected, instead. The server invokes it for
+    /// every request.
+    pub fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let mut path = if self.normalize_paths {
+            match normalize_path(req.uri().path()) {
+                Ok(path) => path,
+                Err(e) => {
+                    log_event(Level::Warn, ROUTING_LOG_TARGET, "path rejected", format_args!("Rejected the path {}: {}", req.uri().path(), e),
+                              &[("path", &req.uri().path()), ("error", &e)]);
+                    res.status(StatusCode::BAD_REQUEST);
+                    return RequestContinuation::None;
+                }
+            }
+        } else {
+            req.uri().path().to_string()
+        };
+
+        match self.trailing_slash {
+            TrailingSlashPolicy::Strict => {}
+            TrailingSlashPolicy::Ignore => {
+                if path.len() > 1 && path.ends_with('/') {
+                    let trimmed_len = path.trim_end_matches('/').len().max(1);
+                    path.truncate(trimmed_len);
+                }
+            }
+            TrailingSlashPolicy::Redirect(direction) => {
+                let target = match direction {
+                    TrailingSlash::Remove if path.len() > 1 && path.ends_with('/') => Some(path.trim_end_matches('/').to_string()),
+                    TrailingSlash::Add if !path.ends_with('/') && !path.rsplit('/').next().is_some_and(|s| s.contains('.')) => {
+                        Some(format!("{}/", path))
+                    }
+                    _ => None,
+                };
+
+                if let Some(target) = target {
+                    let target = if target.is_empty() { "/".to_string() } else { target };
+                    let location = with_query(target, req.uri());
+                    log_event(Level::Debug, ROUTING_LOG_TARGET, "trailing slash redirect",
+                              format_args!("Redirecting {} to {}", req.uri().path(), location), &[("path", &req.uri().path()), ("location", &location)]);
+                    redirect(req, res, location, None);
+                    return RequestContinuation::None;
+                }
+            }
+        }
+
+        if path != req.uri().path() {
+            set_path(req, &path);
+        }
+
+        if self.case_insensitive {
+            req.extensions_mut().insert(CaseInsensitive);
+        }
+
+        let matched = req.host().and_then(|host| {
+            self.hosts.iter().enumerate().find_map(|(position, routes)| routes.pattern.captures(host).map(|params| (position, params)))
+        });
+        if let Some((position, params)) = matched {
+            req.extensions_mut().insert(MatchedHost(position));
+            req.extensions_mut().insert(params);
+        }
+
+        RequestContinuation::Next
+    }
+
     ///
     pub fn dispatch(&self, req: &SyncRequest, res: &mut SyncResponse) {
-        let request_path = req.uri().path();
-        let h: Option<(usize, &(Regex, Box<Controller>))> = self.routes.iter().enumerate().find(
-            move |&(_, &(ref re, _))| {
-                re.is_match(request_path)
+        let started = Instant::now();
+        if let Some(&MatchedHost(host)) = req.extensions().get::<MatchedHost>() {
+            let host = &self.hosts[host];
+            if let Some(position) = host.index.find(&routing_path(req)) {
+                let route = host.routes[position].0.as_str();
+                log_event(Level::Debug, ROUTING_LOG_TARGET, "controller matched",
+                          format_args!("Routing {} {} to the controller route {} of the host {}", req.method(), req.uri().path(), route, host.pattern.as_str()),
+                          &[("method", req.method()), ("path", &req.uri().path()), ("controller_route", &route), ("host", &host.pattern.as_str())]);
+                record_since(res, Phase::Routing, started);
+                return profiled(res, || Phase::Handler, |res| host.routes[position].1.handle(req, res));
             }
-        );
+        }
 
-        if let Some((_, &(_, ref controller))) = h {
-            controller.handle(req, res);
+        if let Some(position) = self.index.find(&routing_path(req)) {
+            let route = self.routes[position].0.as_str();
+            log_event(Level::Debug, ROUTING_LOG_TARGET, "controller matched",
+                      format_args!("Routing {} {} to the controller route {}", req.method(), req.uri().path(), route),
+                      &[("method", req.method()), ("path", &req.uri().path()), ("controller_route", &route)]);
+            record_since(res, Phase::Routing, started);
+            profiled(res, || Phase::Handler, |res| self.routes[position].1.handle(req, res));
         } else {
+            log_event(Level::Debug, ROUTING_LOG_TARGET, "no controller matched",
+                      format_args!("No controller route matches {} {}", req.method(), req.uri().path()),
+                      &[("method", req.method()), ("path", &req.uri().path())]);
+            record_since(res, Phase::Routing, started);
             res.status(StatusCode::NOT_FOUND);
         }
     }
@@ -38,7 +246,7 @@ impl Router {
     /// # Example
     /// ```rust,no_run
     /// let u8_context = 1;
-    /// let u8_controller = BasicController::new(u8_context);
+    /// let mut u8_controller = BasicController::new(u8_context);
     /// u8_controller.add(Method::Get, "^/test$", |ctx, req, res| { println!("this will handle Get request done on <your_host>/test")});
     ///
     /// let mut router = Router::new();
@@ -46,6 +254,156 @@ impl Router {
     ///
     /// ```
     pub fn add<C: 'static + Controller, R: ToRegex>(&mut self, route: R, controller: C) {
-        self.routes.push((reg!(route), Box::new(controller)))
+        register(&mut self.routes, &mut self.index, self.conflict_policy, reg!(route), Box::new(controller));
     }
-}
\ No newline at end of file
+
+    /// Add a new controller with its route to the router, only handling the requests whose host matches `host`, a pattern
+    /// like `{tenant}.example.com` where each `{name}` placeholder matches a single label of the host, regardless of its
+    /// case. Handlers read the values of the placeholders with `SyncRequest::host_param`.
+    ///
+    /// The routes of the first host pattern matching the host of a request are tried before the routes added without a
+    /// host, which still handle the paths none of them matches.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut tenants = BasicController::new(());
+    /// tenants.add(Method::GET, "^/$", |_, req, res| {
+    ///     res.status(StatusCode::OK).body(format!("Welcome to {}", req.host_param("tenant").unwrap_or_default()));
+    /// });
+    ///
+    /// let mut router = Router::new();
+    /// router.add_for_host("{tenant}.example.com", "^/", tenants);
+    /// ```
+    pub fn add_for_host<C: 'static + Controller, R: ToRegex>(&mut self, host: &str, route: R, controller: C) {
+        let position = match self.hosts.iter().position(|routes| routes.pattern.as_str().eq_ignore_ascii_case(host)) {
+            Some(position) => position,
+            None => {
+                self.hosts.push(HostRoutes {
+                    pattern: HostPattern::new(host),
+                    routes: Vec::new(),
+                    index: RouteIndex::new(),
+                });
+                self.hosts.len() - 1
+            }
+        };
+
+        let host = &mut self.hosts[position];
+        register(&mut host.routes, &mut host.index, self.conflict_policy, reg!(route), Box::new(controller));
+    }
+
+    /// Add a new controller with its route to the router, under the path prefix of each of `versions`, like `/v1` and `/v2`
+    /// for the versions `1` and `2`, see `ApiVersioning`. The controller declares its routes with the prefix, which it
+    /// can match with `^/v\d+` when it serves several versions.
+    pub fn add_versioned<C: 'static + Controller, R: ToRegex>(&mut self, versions: &[&str], route: R, controller: C) {
+        let route = versioned_route(versions, reg!(route).as_str());
+        self.add(route.as_str(), controller);
+    }
+
+    /// List every route of the registered controllers, in their matching order, the ones restricted to a host first
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let host_routes = self.hosts.iter().flat_map(|host| host.routes.iter().map(move |route| (Some(host.pattern.as_str()), route)));
+        let routes = self.routes.iter().map(|route| (None, route));
+
+        host_routes.chain(routes).flat_map(|(host, (re, controller))| {
+            controller.routes().into_iter().map(move |mut route| {
+                route.host = host.map(|host| host.to_string());
+                route.controller_route = re.as_str().to_string();
+                route
+            })
+        }).collect()
+    }
+
+    /// Returns the path of the route named `name`, see `ControllerDispatch::label`, its path parameters replaced in their
+    /// order by `params`, percent-encoded. Returns `None` when no route has this name, or when the number of parameters
+    /// doesn't match.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # extern crate saphir;
+    /// # use saphir::*;
+    /// # fn main() {
+    /// let mut controller = BasicController::new(());
+    /// controller.add(Method::GET, "^/users/(?P<id>[0-9]+)/posts/([a-z-]+)$", |_, _, res| { res.status(StatusCode::OK); });
+    /// controller.label(Method::GET, "^/users/(?P<id>[0-9]+)/posts/([a-z-]+)$", "posts.show");
+    ///
+    /// let mut router = Router::new();
+    /// router.add("^/users", controller);
+    /// assert_eq!(router.url_for("posts.show", &["42", "hello-world"]).unwrap(), "/users/42/posts/hello-world");
+    /// # }
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[&str]) -> Option<String> {
+        let pattern = self.routes().into_iter().find(|route| route.name.as_ref().is_some_and(|n| n == name))?.pattern?;
+        let (template, names) = path_template(&pattern);
+        if names.len() != params.len() {
+            return None;
+        }
+
+        let mut path = template;
+        for ((name, _), value) in names.iter().zip(params) {
+            path = path.replacen(&format!("{{{}}}", name), &percent_encode(value), 1);
+        }
+
+        Some(path)
+    }
+
+    /// Format the registered routes as a table, one line per route
+    ///
+    /// ```text
+    /// METHOD  CONTROLLER ROUTE  PATTERN         GUARDS     CONTROLLER
+    /// GET     ^/users           ^/users$        -          BasicController<UserContext>
+    /// PUT     ^/users           ^/users/(\d+)$  BodyGuard  BasicController<UserContext>
+    /// ```
+    pub fn route_table(&self) -> String {
+        let mut rows = vec![[
+            "METHOD".to_string(),
+            "CONTROLLER ROUTE".to_string(),
+            "PATTERN".to_string(),
+            "GUARDS".to_string(),
+            "CONTROLLER".to_string(),
+        ]];
+
+        for route in self.routes() {
+            let guards = if route.guards.is_empty() { "-".to_string() } else { route.guards.join(", ") };
+            let mut pattern = route.pattern.unwrap_or_else(|| "*".to_string());
+            if let Some(predicate) = route.predicate {
+                pattern = format!("{} when {}", pattern, predicate);
+            }
+            if let Some(media_type) = route.produces {
+                pattern = format!("{} producing {}", pattern, media_type);
+            }
+
+            rows.push([
+                route.method.as_ref().map(|m| m.to_string()).unwrap_or_else(|| "*".to_string()),
+                match route.host {
+                    Some(host) => format!("{} {}", host, route.controller_route),
+                    None => route.controller_route,
+                },
+                pattern,
+                guards,
+                route.controller,
+            ]);
+        }
+
+        let mut widths = [0; 5];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut table = String::new();
+        for row in &rows {
+            let line = row.iter().zip(widths.iter())
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>()
+                .join("  ");
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+
+        table
+    }
+}
@@ -0,0 +1,277 @@
+use cron::CronSchedule;
+use health::HealthCheck;
+use health::HealthCheckFuture;
+use server::Server;
+use futures::Future;
+use futures::IntoFuture;
+use futures::future::join_all;
+use futures::future::loop_fn;
+use futures::future::Either;
+use futures::future::Loop;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::timer::Delay;
+
+/// Future returned by a scheduled job, resolving to `Ok(())` when the run succeeded
+pub type JobFuture = Box<dyn Future<Item=(), Error=String> + Send>;
+
+/// A trait representing a job run by a `Scheduler`
+///
+/// Any `Fn() -> R` where `R` is a `Result<(), String>` or a future resolving to `()` implements this trait.
+pub trait Job: Send + Sync {
+    /// Start a run of the job. The next run is only scheduled once the returned future completes, so runs never overlap.
+    fn run(&self) -> JobFuture;
+}
+
+impl<F, R> Job for F
+    where F: Fn() -> R + Send + Sync,
+          R: IntoFuture<Item=(), Error=String>,
+          R::Future: 'static + Send {
+    fn run(&self) -> JobFuture {
+        Box::new(self().into_future())
+    }
+}
+
+/// When a job of a `Scheduler` runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    /// Run the job every given interval, the first run happening one interval after the server starts
+    Every(Duration),
+    /// Run the job at the times matching a cron expression, in UTC
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Parse a cron expression, see `CronSchedule`
+    pub fn cron(expression: &str) -> Result<Self, ::cron::CronError> {
+        expression.parse().map(Schedule::Cron)
+    }
+
+    /// Returns how long to wait from now before the next run, `None` when there is no next run
+    fn next_delay(&self) -> Option<Duration> {
+        match *self {
+            Schedule::Every(interval) => Some(interval),
+            Schedule::Cron(ref cron) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                cron.next_after(now.as_secs()).map(|next| Duration::from_secs(next).checked_sub(now).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// The state of a job of a `Scheduler`, as returned by `SchedulerHandle::status`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobStatus {
+    /// Name of the job
+    pub name: String,
+    /// Whether the job runs when its schedule is due
+    pub enabled: bool,
+    /// Number of completed runs
+    pub runs: u64,
+    /// Number of failed runs
+    pub failures: u64,
+    /// When the last run started
+    pub last_run: Option<SystemTime>,
+    /// How long the last run took
+    pub last_duration: Option<Duration>,
+    /// The error of the last run, `None` if it succeeded or if the job never ran
+    pub last_error: Option<String>,
+}
+
+struct ScheduledJob {
+    schedule: Schedule,
+    job: Box<dyn Job>,
+    enabled: AtomicBool,
+    status: Mutex<JobStatus>,
+}
+
+impl ScheduledJob {
+    fn status(&self) -> JobStatus {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        status.enabled = self.enabled.load(Ordering::SeqCst);
+        status
+    }
+
+    fn record(&self, started_at: SystemTime, started: Instant, result: Result<(), String>) {
+        let mut status = self.status.lock().unwrap_or_else(|e| e.into_inner());
+
+        status.runs += 1;
+        status.last_run = Some(started_at);
+        status.last_duration = Some(started.elapsed());
+
+        match result {
+            Ok(()) => status.last_error = None,
+            Err(e) => {
+                warn!("The scheduled job {} failed: {}", status.name, e);
+                status.failures += 1;
+                status.last_error = Some(e);
+            }
+        }
+    }
+
+    /// Run the job forever, according to its schedule
+    fn run(job: Arc<ScheduledJob>) -> Box<dyn Future<Item=(), Error=()> + Send> {
+        Box::new(loop_fn(job, |job| {
+            let delay = match job.schedule.next_delay() {
+                Some(delay) => delay,
+                None => {
+                    warn!("The schedule of the job {} never matches, it won't run", job.status().name);
+                    return Either::A(Ok(Loop::Break(())).into_future());
+                }
+            };
+
+            Either::B(Delay::new(Instant::now() + delay).map_err(|e| error!("Scheduler timer error: {}", e)).and_then(move |_| {
+                if !job.enabled.load(Ordering::SeqCst) {
+                    return Either::A(Ok(Loop::Continue(job)).into_future());
+                }
+
+                let started_at = SystemTime::now();
+                let started = Instant::now();
+                Either::B(job.job.run().then(move |result| {
+                    job.record(started_at, started, result);
+                    Ok(Loop::Continue(job))
+                }))
+            }))
+        }))
+    }
+}
+
+/// Run jobs on a schedule, for the lifetime of a server
+///
+/// Jobs run on the background tasks thread of the server, see `Server::spawn_task`, and can be enabled and disabled at
+/// runtime through a `SchedulerHandle`. The handle also reports the outcome of the last runs, and can be registered as a
+/// check of the `HealthController` to fail when the last run of an enabled job failed.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// use std::time::Duration;
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler.add("purge sessions", Schedule::Every(Duration::from_secs(300)), || -> Result<(), String> { Ok(()) });
+/// scheduler.add("nightly report", Schedule::cron("0 3 * * *").unwrap(), || -> Result<(), String> { Ok(()) });
+///
+/// let mut health = HealthController::new();
+/// health.add_liveness_check("scheduler", scheduler.handle());
+///
+/// let mut router = Router::new();
+/// router.add("^/(healthz|readyz)$", health);
+///
+/// let server = Server::builder().router(router).state(scheduler.handle()).build();
+/// scheduler.spawn_on(&server);
+/// server.run("http://0.0.0.0:12345").unwrap();
+/// ```
+pub struct Scheduler {
+    jobs: Arc<Mutex<Vec<Arc<ScheduledJob>>>>,
+}
+
+impl Scheduler {
+    /// Create a scheduler without any job
+    pub fn new() -> Self {
+        Scheduler {
+            jobs: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Add a job named `name`, enabled, running according to `schedule`
+    pub fn add<N: Into<String>, J: 'static + Job>(&mut self, name: N, schedule: Schedule, job: J) -> &mut Self {
+        let job = ScheduledJob {
+            schedule,
+            job: Box::new(job),
+            enabled: AtomicBool::new(true),
+            status: Mutex::new(JobStatus {
+                name: name.into(),
+                enabled: true,
+                runs: 0,
+                failures: 0,
+                last_run: None,
+                last_duration: None,
+                last_error: None,
+            }),
+        };
+
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::new(job));
+        self
+    }
+
+    /// Returns a handle to enable, disable and inspect the jobs of the scheduler
+    pub fn handle(&self) -> SchedulerHandle {
+        SchedulerHandle {
+            jobs: self.jobs.clone(),
+        }
+    }
+
+    /// Run the jobs as a background task of `server`, starting once it listens and stopping when it shuts down
+    pub fn spawn_on(self, server: &Server) {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        server.spawn_task(move || join_all(jobs.into_iter().map(ScheduledJob::run)).map(|_| ()));
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+/// A handle to the jobs of a `Scheduler`, which can be registered as a state for controllers to use
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    jobs: Arc<Mutex<Vec<Arc<ScheduledJob>>>>,
+}
+
+impl SchedulerHandle {
+    fn find(&self, name: &str) -> Option<Arc<ScheduledJob>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).iter()
+            .find(|job| job.status.lock().unwrap_or_else(|e| e.into_inner()).name == name)
+            .cloned()
+    }
+
+    fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        self.find(name).map(|job| job.enabled.store(enabled, Ordering::SeqCst)).is_some()
+    }
+
+    /// Let the job named `name` run when its schedule is due, returns `false` if there is no such job
+    pub fn enable(&self, name: &str) -> bool {
+        self.set_enabled(name, true)
+    }
+
+    /// Skip the runs of the job named `name` until it is enabled again, returns `false` if there is no such job. A run in
+    /// progress is not interrupted.
+    pub fn disable(&self, name: &str) -> bool {
+        self.set_enabled(name, false)
+    }
+
+    /// Returns the state of the job named `name`
+    pub fn status(&self, name: &str) -> Option<JobStatus> {
+        self.find(name).map(|job| job.status())
+    }
+
+    /// Returns the state of every job, in the order they were added
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).iter().map(|job| job.status()).collect()
+    }
+}
+
+impl HealthCheck for SchedulerHandle {
+    /// Fails when the last run of an enabled job failed
+    fn check(&self) -> HealthCheckFuture {
+        let failures: Vec<String> = self.statuses().into_iter()
+            .filter(|status| status.enabled)
+            .filter_map(|status| status.last_error.as_ref().map(|e| format!("{}: {}", status.name, e)))
+            .collect();
+
+        if failures.is_empty() {
+            Box::new(Ok(()).into_future())
+        } else {
+            Box::new(Err(failures.join(", ")).into_future())
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+
+/// Value of the `X-Frame-Options` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameOptions {
+    /// The page cannot be displayed in a frame
+    Deny,
+    /// The page can only be displayed in a frame on the same origin
+    SameOrigin,
+}
+
+impl FrameOptions {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+/// Value of the `Referrer-Policy` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferrerPolicy {
+    /// no-referrer
+    NoReferrer,
+    /// no-referrer-when-downgrade
+    NoReferrerWhenDowngrade,
+    /// origin
+    Origin,
+    /// origin-when-cross-origin
+    OriginWhenCrossOrigin,
+    /// same-origin
+    SameOrigin,
+    /// strict-origin
+    StrictOrigin,
+    /// strict-origin-when-cross-origin
+    StrictOriginWhenCrossOrigin,
+    /// unsafe-url
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        }
+    }
+}
+
+/// Settings of the `Strict-Transport-Security` header
+#[derive(Clone, Debug)]
+pub struct StrictTransportSecurity {
+    max_age: u64,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl StrictTransportSecurity {
+    /// Create a new HSTS policy telling the client to only use https for `max_age` seconds
+    pub fn new(max_age: u64) -> Self {
+        StrictTransportSecurity {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// Apply the policy to every subdomain of the current host
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Allow the host to be included in the browsers' HSTS preload lists
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+
+    /// Format the policy as a header value
+    pub fn to_header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+
+        if self.preload {
+            value.push_str("; preload");
+        }
+
+        value
+    }
+}
+
+impl Default for StrictTransportSecurity {
+    fn default() -> Self {
+        StrictTransportSecurity::new(31_536_000).include_subdomains()
+    }
+}
+
+/// A typed builder for the `Content-Security-Policy` header
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let csp = ContentSecurityPolicy::new()
+///     .default_src(vec!["'self'"])
+///     .img_src(vec!["'self'", "data:"])
+///     .upgrade_insecure_requests();
+///
+/// assert_eq!(csp.to_header_value(), "default-src 'self'; img-src 'self' data:; upgrade-insecure-requests");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+}
+
+impl ContentSecurityPolicy {
+    /// Create an empty policy
+    pub fn new() -> Self {
+        ContentSecurityPolicy {
+            directives: Vec::new(),
+        }
+    }
+
+    /// Add an arbitrary directive with its sources, replacing any previous value of the same directive
+    pub fn directive<N: Into<String>, S: Into<String>>(mut self, name: N, sources: Vec<S>) -> Self {
+        let name = name.into();
+        let sources: Vec<String> = sources.into_iter().map(|s| s.into()).collect();
+
+        if let Some(existing) = self.directives.iter_mut().find(|d| d.0 == name) {
+            existing.1 = sources;
+            return self;
+        }
+
+        self.directives.push((name, sources));
+        self
+    }
+
+    /// default-src directive
+    pub fn default_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("default-src", sources)
+    }
+
+    /// script-src directive
+    pub fn script_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("script-src", sources)
+    }
+
+    /// style-src directive
+    pub fn style_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("style-src", sources)
+    }
+
+    /// img-src directive
+    pub fn img_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("img-src", sources)
+    }
+
+    /// connect-src directive
+    pub fn connect_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("connect-src", sources)
+    }
+
+    /// font-src directive
+    pub fn font_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("font-src", sources)
+    }
+
+    /// object-src directive
+    pub fn object_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("object-src", sources)
+    }
+
+    /// media-src directive
+    pub fn media_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("media-src", sources)
+    }
+
+    /// frame-src directive
+    pub fn frame_src<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("frame-src", sources)
+    }
+
+    /// frame-ancestors directive
+    pub fn frame_ancestors<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("frame-ancestors", sources)
+    }
+
+    /// base-uri directive
+    pub fn base_uri<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("base-uri", sources)
+    }
+
+    /// form-action directive
+    pub fn form_action<S: Into<String>>(self, sources: Vec<S>) -> Self {
+        self.directive("form-action", sources)
+    }
+
+    /// report-uri directive
+    pub fn report_uri<S: Into<String>>(self, uri: S) -> Self {
+        self.directive("report-uri", vec![uri])
+    }
+
+    /// upgrade-insecure-requests directive
+    pub fn upgrade_insecure_requests(self) -> Self {
+        self.directive("upgrade-insecure-requests", Vec::<String>::new())
+    }
+
+    /// Format the policy as a header value
+    pub fn to_header_value(&self) -> String {
+        self.directives.iter().map(|(name, sources)| {
+            if sources.is_empty() {
+                name.clone()
+            } else {
+                format!("{} {}", name, sources.join(" "))
+            }
+        }).collect::<Vec<String>>().join("; ")
+    }
+}
+
+/// A middleware injecting security related headers on every response
+///
+/// By default, it sets `Strict-Transport-Security: max-age=31536000; includeSubDomains`, `X-Content-Type-Options: nosniff`,
+/// `X-Frame-Options: DENY` and `Referrer-Policy: strict-origin-when-cross-origin`. No `Content-Security-Policy` is sent
+/// unless one is configured.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let security = SecurityHeadersMiddleware::new()
+///     .frame_options(Some(FrameOptions::SameOrigin))
+///     .content_security_policy(ContentSecurityPolicy::new().default_src(vec!["'self'"]));
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(security, vec!("/"), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SecurityHeadersMiddleware {
+    hsts: Option<StrictTransportSecurity>,
+    content_type_options: bool,
+    frame_options: Option<FrameOptions>,
+    referrer_policy: Option<ReferrerPolicy>,
+    csp: Option<ContentSecurityPolicy>,
+    csp_report_only: bool,
+    /// The headers to insert, formatted once when the middleware is configured rather than on every response
+    headers: Vec<(header::HeaderName, header::HeaderValue)>,
+}
+
+impl SecurityHeadersMiddleware {
+    /// Create the middleware with its default headers
+    pub fn new() -> Self {
+        SecurityHeadersMiddleware {
+            hsts: Some(StrictTransportSecurity::default()),
+            content_type_options: true,
+            frame_options: Some(FrameOptions::Deny),
+            referrer_policy: Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+            csp: None,
+            csp_report_only: false,
+            headers: Vec::new(),
+        }.rebuild()
+    }
+
+    /// Set the `Strict-Transport-Security` policy, `None` disables the header
+    pub fn hsts(mut self, hsts: Option<StrictTransportSecurity>) -> Self {
+        self.hsts = hsts;
+        self.rebuild()
+    }
+
+    /// Enable or disable `X-Content-Type-Options: nosniff`
+    pub fn content_type_options(mut self, enabled: bool) -> Self {
+        self.content_type_options = enabled;
+        self.rebuild()
+    }
+
+    /// Set the `X-Frame-Options` value, `None` disables the header
+    pub fn frame_options(mut self, frame_options: Option<FrameOptions>) -> Self {
+        self.frame_options = frame_options;
+        self.rebuild()
+    }
+
+    /// Set the `Referrer-Policy` value, `None` disables the header
+    pub fn referrer_policy(mut self, referrer_policy: Option<ReferrerPolicy>) -> Self {
+        self.referrer_policy = referrer_policy;
+        self.rebuild()
+    }
+
+    /// Set the `Content-Security-Policy` sent with every response
+    pub fn content_security_policy(mut self, csp: ContentSecurityPolicy) -> Self {
+        self.csp = Some(csp);
+        self.csp_report_only = false;
+        self.rebuild()
+    }
+
+    /// Set a `Content-Security-Policy-Report-Only` policy, violations are reported but not enforced by the browser
+    pub fn content_security_policy_report_only(mut self, csp: ContentSecurityPolicy) -> Self {
+        self.csp = Some(csp);
+        self.csp_report_only = true;
+        self.rebuild()
+    }
+
+    fn rebuild(mut self) -> Self {
+        let mut headers = Vec::new();
+
+        if let Some(ref hsts) = self.hsts {
+            headers.push((header::STRICT_TRANSPORT_SECURITY, hsts.to_header_value()));
+        }
+
+        if self.content_type_options {
+            headers.push((header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()));
+        }
+
+        if let Some(frame_options) = self.frame_options {
+            headers.push((header::X_FRAME_OPTIONS, frame_options.as_str().to_string()));
+        }
+
+        if let Some(referrer_policy) = self.referrer_policy {
+            headers.push((header::REFERRER_POLICY, referrer_policy.as_str().to_string()));
+        }
+
+        if let Some(ref csp) = self.csp {
+            let name = if self.csp_report_only { header::CONTENT_SECURITY_POLICY_REPORT_ONLY } else { header::CONTENT_SECURITY_POLICY };
+            headers.push((name, csp.to_header_value()));
+        }
+
+        self.headers = headers.into_iter()
+            .filter_map(|(name, value)| match header::HeaderValue::from_str(&value) {
+                Ok(value) => Some((name, value)),
+                Err(_) => {
+                    warn!("Ignoring the invalid value {:?} of the security header {}", value, name);
+                    None
+                }
+            })
+            .collect();
+        self
+    }
+}
+
+impl Default for SecurityHeadersMiddleware {
+    fn default() -> Self {
+        SecurityHeadersMiddleware::new()
+    }
+}
+
+impl Middleware for SecurityHeadersMiddleware {
+    fn resolve(&self, _req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let headers = res.headers_map_mut();
+        headers.reserve(self.headers.len());
+
+        for (name, value) in &self.headers {
+            headers.insert(name.clone(), value.clone());
+        }
+
+        RequestContinuation::Next
+    }
+}
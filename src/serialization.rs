@@ -0,0 +1,49 @@
+use http::*;
+use std::error::Error;
+use std::fmt;
+
+/// Why the body of a response couldn't be serialized by `SyncResponse::json`, `xml`, `msgpack` or `cbor`
+///
+/// The error is kept with the response, which the server then answers with a `500 Internal Server Error` problem
+/// whatever the handler and the middlewares did to it afterwards, so the client never gets the status or the headers
+/// meant for the body along with an empty or partial one. See `ServerBuilder::on_serialization_error` to answer
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct SerializationError {
+    format: &'static str,
+    message: String,
+}
+
+impl SerializationError {
+    /// Returns the format the body was serialized to, like `json`
+    pub fn format(&self) -> &str {
+        self.format
+    }
+
+    /// Returns the error of the serializer
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to serialize the response body as {}: {}", self.format, self.message)
+    }
+}
+
+impl Error for SerializationError {}
+
+impl SyncResponse {
+    /// Returns why the body of the response couldn't be serialized, if it couldn't
+    pub fn serialization_error(&self) -> Option<&SerializationError> {
+        self.get_extensions().get::<SerializationError>()
+    }
+
+    /// Turn the response into an empty `500 Internal Server Error`, keeping `error` for the server to answer with
+    pub(crate) fn serialization_failed<E: fmt::Display>(&mut self, format: &'static str, error: E) -> &mut SyncResponse {
+        self.extension(SerializationError { format, message: error.to_string() })
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Vec::<u8>::new())
+    }
+}
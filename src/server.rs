@@ -1,29 +1,728 @@
 use hyper::Server as HyperServer;
 use hyper::service::service_fn;
+use hyper::service::make_service_fn;
+use hyper::service::Service;
 use http::*;
-use utils;
+use http_types::HttpTryFrom;
 use error::ServerError;
 use std::sync::Arc;
+use std::sync::Mutex;
 use middleware::MiddlewareStack;
 use router::Router;
+use utils::RequestContinuation;
+use problem::Problem;
+use trailers::ResponseBody;
+use client_ip::TrustedProxies;
+use client_ip::SharedTrustedProxies;
+use template::TemplateEngine;
+use template::RegisteredTemplateEngine;
+use state::StateMap;
+use state::SharedState;
+use container::Container;
+use container::RequestScope;
+use std::collections::HashMap;
+use std::any::Any;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use profile::Profile;
+use profile::capture_backtraces;
+use charset::default_charset;
+use profile::panic_message;
+use profile::take_backtrace;
+use error_page::DeveloperError;
+use error_page::render_developer_page;
+use debug::DebugController;
+use debug::RequestLog;
+use assets::AssetManifest;
+use maintenance::MaintenanceMode;
+use tls::reject_early_data;
+use feature_flags::FeatureFlagProvider;
+use feature_flags::SharedFeatureFlags;
+use body_parser::BodyDecoder;
+use body_parser::BodyDecoders;
+use audit::AuditLog;
+use audit::SharedAuditLog;
+use admin::AdminController;
+use admin::DrainSwitch;
+use admin::close_drained;
+use admin::mark_draining;
+use lifecycle::LifecycleHook;
+use lifecycle::LifecycleHooks;
+use lifecycle::WarmUpState;
+use cancellation::CancelOnDrop;
+use cancellation::CancellationToken;
 use futures::Future;
+use futures_cpupool::CpuPool;
+use blocking::BlockingPool;
+use drain::DrainReport;
+use drain::InFlight;
+use drain::watch_drain;
+use drain::DrainWatcher;
+use fairness::Fairness;
+use fairness::FairScheduler;
+use malformed::DispatchedRequests;
+use malformed::GuardedStream;
+use malformed::Incoming;
+use malformed::MalformedRequestPolicy;
+use malformed::MalformedRequests;
+use profiling::Phase;
+use profiling::Profiler;
+use profiling::RequestProfile;
+use profiling::profiled;
+use server_config::EffectiveConfig;
+use futures_cpupool::Builder as CpuPoolBuilder;
+use tokio::runtime::Builder as RuntimeBuilder;
+use tokio::runtime::current_thread::Runtime as CurrentThreadRuntime;
+use tokio::runtime::current_thread::TaskExecutor;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use futures::future::Shared;
+use futures::sync::oneshot::channel;
+use futures::sync::oneshot::Sender;
+use handover::inherited_listeners;
+use handover::handover_on_sigusr2;
+use buffer_pool::BufferPool;
+use buffer_pool::BufferPoolStats;
+use buffer_pool::load_pooled_body;
+use data_usage::ConnectionUsage;
+use data_usage::response_head_size;
+use serialization::SerializationError;
+use body_limits::BodyLimits;
+use body_limits::BodyRejection;
+use task::PendingTask;
+use task::run_tasks;
+use fastcgi::handle_connection as handle_fastcgi_connection;
+use futures::IntoFuture;
+use logging::*;
+use arc_swap::ArcSwapOption;
+#[cfg(feature = "http3")]
+use http3::Http3Config;
+#[cfg(feature = "http3")]
+use http3::Http3Server;
+use log::Level;
+
+/// Everything a request needs to be processed, shared amongst every connection of the server
+struct ServiceContext {
+    middleware_stack: MiddlewareStack,
+    router: Router,
+    template_engine: Option<RegisteredTemplateEngine>,
+    state: SharedState,
+    log_routes: bool,
+    handler_pool: Option<CpuPool>,
+    scheduler: Option<Arc<FairScheduler>>,
+    buffer_stats: BufferPoolStats,
+    hooks: Arc<Hooks>,
+    problem_details: bool,
+    trusted_proxies: Option<SharedTrustedProxies>,
+    default_headers: header::HeaderMap,
+    alt_svc: ArcSwapOption<header::HeaderValue>,
+    body_limits: BodyLimits,
+    profile: Option<Profile>,
+    request_log: Option<RequestLog>,
+    asset_manifest: Option<AssetManifest>,
+    maintenance: Option<MaintenanceMode>,
+    feature_flags: Option<SharedFeatureFlags>,
+    body_decoders: Option<BodyDecoders>,
+    malformed: Option<Arc<MalformedRequests>>,
+    profiler: Option<Profiler>,
+    audit_log: Option<SharedAuditLog>,
+    draining: Option<DrainSwitch>,
+    warm_up: Option<WarmUpState>,
+    in_flight: Arc<InFlight>,
+    drain_interval: Duration,
+}
+
+type RequestHook = Box<dyn Fn(&SyncRequest) + Send + Sync>;
+type ResponseHook = Box<dyn Fn(&SyncRequest, &SyncResponse) + Send + Sync>;
+type BeforeSendHook = Box<dyn Fn(&SyncRequest, &mut SyncResponse) + Send + Sync>;
+type ConnectionHook = Box<dyn Fn(SocketAddr) + Send + Sync>;
+type SerializationErrorHook = Box<dyn Fn(&SyncRequest, &SerializationError, &mut SyncResponse) + Send + Sync>;
+type DrainHook = Box<dyn Fn(&DrainReport) + Send + Sync>;
+
+/// The lifecycle callbacks registered on the `ServerBuilder`
+#[derive(Default)]
+struct Hooks {
+    on_request: Vec<RequestHook>,
+    on_response: Vec<ResponseHook>,
+    before_send: Vec<BeforeSendHook>,
+    on_connection_open: Vec<ConnectionHook>,
+    on_connection_close: Vec<ConnectionHook>,
+    on_serialization_error: Vec<SerializationErrorHook>,
+    on_drain: Vec<DrainHook>,
+}
+
+/// What the requests received on the same connection share
+struct Connection {
+    id: u64,
+    peer_addr: SocketAddr,
+    buffers: Arc<BufferPool>,
+    hooks: Arc<Hooks>,
+    usage: ConnectionUsage,
+    dispatched: Option<DispatchedRequests>,
+}
+
+impl Connection {
+    fn new(peer_addr: SocketAddr, context: &ServiceContext) -> Self {
+        for hook in &context.hooks.on_connection_open {
+            hook(peer_addr);
+        }
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Connection {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            peer_addr,
+            buffers: Arc::new(BufferPool::new(context.buffer_stats.clone())),
+            hooks: context.hooks.clone(),
+            usage: ConnectionUsage::default(),
+            dispatched: None,
+        }
+    }
+}
+
+impl Drop for Connection {
+    /// Hyper drops the service owning the connection once the connection is closed
+    fn drop(&mut self) {
+        for hook in &self.hooks.on_connection_close {
+            hook(self.peer_addr);
+        }
+
+        let usage = &self.usage;
+        log_event(Level::Debug, SERVER_LOG_TARGET, "connection closed",
+                  format_args!("The connection of {} closed after {} requests, {} bytes received and {} bytes sent", self.peer_addr,
+                               usage.requests(), usage.received(), usage.sent()),
+                  &[("peer_addr", &self.peer_addr), ("requests", &usage.requests()), ("bytes_received", &usage.received()),
+                    ("bytes_sent", &usage.sent())]);
+    }
+}
+
+impl ServiceContext {
+    /// Write the audit records buffered by the sinks of the audit log
+    fn flush_audit_log(&self) {
+        if let Some(ref log) = self.audit_log {
+            log.0.flush();
+        }
+    }
+
+    /// Run the middlewares and the router to compute the response to `request`
+    fn process(&self, request: &mut SyncRequest) -> SyncResponse {
+        let received = self.request_log.as_ref().map(|_| (::std::time::Instant::now(), request.uri().path().to_string()));
+        request.extensions_mut().insert(self.state.clone());
+
+        if request.extensions().get::<CancellationToken>().is_none() {
+            request.extensions_mut().insert(CancellationToken::new());
+        }
+
+        if let Some(ref proxies) = self.trusted_proxies {
+            request.extensions_mut().insert(proxies.clone());
+        }
+
+        if self.state.0.contains::<Container>() {
+            request.extensions_mut().insert(RequestScope::default());
+        }
+
+        if let Some(profile) = self.profile {
+            request.extensions_mut().insert(profile);
+        }
+
+        if let Some(ref flags) = self.feature_flags {
+            request.extensions_mut().insert(flags.clone());
+        }
+
+        if let Some(ref decoders) = self.body_decoders {
+            request.extensions_mut().insert(decoders.clone());
+        }
+
+        if let Some(ref log) = self.audit_log {
+            request.extensions_mut().insert(log.clone());
+        }
+
+        if let Some(ref warm_up) = self.warm_up {
+            request.extensions_mut().insert(warm_up.clone());
+        }
+
+        if self.draining.as_ref().is_some_and(|draining| draining.is_draining()) {
+            mark_draining(request);
+        }
+
+        for hook in &self.hooks.on_request {
+            hook(request);
+        }
+
+        let mut response = self.new_response(request);
+        if self.profiler.is_some() {
+            response.extension(RequestProfile::default());
+        }
+
+        let limit = self.body_limits.resolve(request.uri().path(), request.headers_map());
+        let router = &self.router;
+        let mut request_profile = None;
+        if self.maintenance.as_ref().is_some_and(|maintenance| maintenance.reject(request, &mut response)) {
+            // The request was answered by the maintenance mode
+        } else if reject_early_data(request, &mut response) {
+            // The request could be replayed
+        } else if let Some(rejection) = BodyRejection::of(request, limit) {
+            rejection.describe(&mut response);
+        } else {
+            let dispatched = catch_unwind(AssertUnwindSafe(|| {
+                if let RequestContinuation::Next = profiled(&mut response, || Phase::Routing, |res| router.prepare(request, res)) {
+                    self.middleware_stack.resolve_with(request, &mut response, |req, res| {
+                        router.dispatch(req, res);
+                    });
+                }
+            }));
+
+            // Taken before the response is replaced, along with the route which handled the request
+            request_profile = response.get_extensions_mut().remove::<RequestProfile>()
+                .map(|profile| (profile, response.route_label().unwrap_or("unmatched").to_string()));
+
+            if let Err(payload) = dispatched {
+                let route = response.route_label().map(|label| label.to_string());
+                response = self.new_response(request);
+                self.describe_panic(request, &mut response, &*payload, route);
+            }
+        }
+
+        if let Some(error) = response.serialization_error().cloned() {
+            response = self.new_response(request);
+            self.describe_serialization_error(request, &mut response, &error);
+        }
+
+        render_developer_page(request, &mut response);
+
+        if self.problem_details {
+            describe_error(request, &mut response);
+        }
+
+        for name in self.default_headers.keys() {
+            if !response.headers_map().contains_key(name) {
+                for value in self.default_headers.get_all(name) {
+                    response.headers_map_mut().append(name.clone(), value.clone());
+                }
+            }
+        }
+
+        default_charset(&mut response);
+        close_drained(request, &mut response);
+
+        if self.profile == Some(Profile::Development) && !response.headers_map().contains_key(header::CACHE_CONTROL) {
+            response.headers_map_mut().insert(header::CACHE_CONTROL, header::HeaderValue::from_static("no-store"));
+        }
+
+        if let Some(alt_svc) = self.alt_svc.load_full() {
+            if !response.headers_map().contains_key(header::ALT_SVC) {
+                response.headers_map_mut().insert(header::ALT_SVC, (*alt_svc).clone());
+            }
+        }
+
+        for hook in &self.hooks.before_send {
+            hook(request, &mut response);
+        }
+
+        for hook in &self.hooks.on_response {
+            hook(request, &response);
+        }
+
+        if let (Some(log), Some((started, path))) = (self.request_log.as_ref(), received) {
+            log.record(request.method(), path, &response, started.elapsed());
+        }
+
+        if let (Some(profiler), Some((profile, route))) = (self.profiler.as_ref(), request_profile) {
+            profiler.record(request.method(), &route, profile);
+        }
+
+        response
+    }
+
+    fn new_response(&self, request: &SyncRequest) -> SyncResponse {
+        let mut response = SyncResponse::new();
+
+        if let Some(token) = request.extensions().get::<CancellationToken>() {
+            response.extension(token.clone());
+        }
+
+        if let Some(ref engine) = self.template_engine {
+            response.extension(engine.clone());
+        }
+
+        if let Some(profile) = self.profile {
+            response.extension(profile);
+        }
+
+        if let Some(ref manifest) = self.asset_manifest {
+            response.extension(manifest.clone());
+        }
+
+        response
+    }
+
+    /// Answer a request whose middlewares or handler panicked with `500 Internal Server Error`, described with the message
+    /// and the backtrace of the panic under the development profile
+    fn describe_panic(&self, request: &SyncRequest, response: &mut SyncResponse, payload: &(dyn Any + Send), route: Option<String>) {
+        let message = panic_message(payload);
+        log_event(Level::Error, HANDLER_LOG_TARGET, "handler panicked",
+                  format_args!("Processing {} {} panicked: {}", request.method(), request.uri().path(), message),
+                  &[("method", request.method()), ("path", &request.uri().path()), ("error", &message)]);
+
+        response.status(StatusCode::INTERNAL_SERVER_ERROR);
+        if self.profile == Some(Profile::Development) {
+            let mut problem = Problem::new(StatusCode::INTERNAL_SERVER_ERROR).with_detail(message.as_str()).with_instance(request.uri().path());
+            let mut error = DeveloperError::new(vec![format!("The handler panicked: {}", message)]).with_route(route);
+            if let Some(backtrace) = take_backtrace() {
+                problem = problem.with_extension("backtrace", backtrace.lines().map(|line| line.trim_end()).collect::<Vec<_>>());
+                error = error.with_backtrace(backtrace);
+            }
+            response.problem(&problem).extension(error);
+        }
+    }
+
+    /// Answer in place of a response whose body couldn't be serialized, with a `500 Internal Server Error` given a problem
+    /// document by `describe_error`, unless a hook answers otherwise
+    fn describe_serialization_error(&self, request: &SyncRequest, response: &mut SyncResponse, error: &SerializationError) {
+        log_event(Level::Error, HANDLER_LOG_TARGET, "serialization failed",
+                  format_args!("Unable to answer {} {}: {}", request.method(), request.uri().path(), error),
+                  &[("method", request.method()), ("path", &request.uri().path()), ("format", &error.format()), ("error", &error.message())]);
+
+        response.status(StatusCode::INTERNAL_SERVER_ERROR);
+        if self.profile == Some(Profile::Development) {
+            response.problem(&Problem::new(StatusCode::INTERNAL_SERVER_ERROR).with_detail(error.to_string()).with_instance(request.uri().path()));
+        }
+
+        for hook in &self.hooks.on_serialization_error {
+            hook(request, error, response);
+        }
+    }
+}
+
+/// Returns the `500 Internal Server Error` answering `request` in place of a response which couldn't be built, like one
+/// given an invalid header
+fn invalid_response(request: &SyncRequest) -> Response<Body> {
+    let mut response = SyncResponse::new();
+    response.problem(&Problem::new(StatusCode::INTERNAL_SERVER_ERROR).with_instance(request.uri().path()));
+    response.build_response().unwrap_or_else(|_| {
+        let empty: &[u8] = b"";
+        let mut response = Response::new(empty.into());
+        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+        response
+    })
+}
+
+/// Give error responses left without a body, like the `404 Not Found` of an unknown route, a problem document
+fn describe_error(request: &SyncRequest, response: &mut SyncResponse) {
+    let status = response.get_status();
+    if (!status.is_client_error() && !status.is_server_error()) || *request.method() == Method::HEAD
+        || response.headers_map().contains_key(header::CONTENT_TYPE) || response.is_streamed() || !response.get_body().is_empty() {
+        return;
+    }
+
+    response.problem(&Problem::new(status).with_instance(request.uri().path()));
+}
+
+/// How the server distributes its work amongst threads
+#[derive(Default, Clone)]
+struct Threading {
+    worker_threads: Option<usize>,
+    thread_name: Option<String>,
+    pin_threads: bool,
+    thread_per_core: bool,
+}
 
 /// The http server
 pub struct Server {
-    middleware_stack: Arc<MiddlewareStack>,
-    router: Arc<Router>,
+    context: Arc<ServiceContext>,
+    threading: Threading,
+    inherit_listener: bool,
+    handover: bool,
+    cancel_on_half_close: bool,
+    tasks: Mutex<Vec<PendingTask>>,
+    lifecycle: LifecycleHooks,
+    config: EffectiveConfig,
+    listeners: Mutex<Vec<String>>,
+    startup_banner: bool,
+    #[cfg(feature = "http3")]
+    http3: Option<Http3Config>,
 }
 
 impl Server {
     /// Create a new server from a `Router` and a `MiddlewareStack`
     pub fn new(router: Router, middleware_stack: Option<MiddlewareStack>) -> Self {
-        let middleware_stack = middleware_stack.unwrap_or_else(|| { MiddlewareStack::new() });
-        //let http_service = HyperHttpService::new(router, middleware_stack);
+        let mut builder = Server::builder().router(router);
 
-        Server {
-            middleware_stack: Arc::new(middleware_stack),
-            router: Arc::new(router),
+        if let Some(middleware_stack) = middleware_stack {
+            builder = builder.middleware_stack(middleware_stack);
+        }
+
+        builder.build()
+    }
+
+    /// Create a `ServerBuilder` to configure a new server
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder()
+    ///     .router(Router::new())
+    ///     .middleware_stack(MiddlewareStack::new())
+    ///     .build();
+    /// ```
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Returns the counters of the pools of buffers request bodies are loaded into, see `BufferPoolStats`
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.context.buffer_stats.clone()
+    }
+
+    /// Returns the configuration the server runs with, once its builder, configuration file and defaults are resolved,
+    /// along with the addresses it listens on once it runs, see `EffectiveConfig`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use saphir::*;
+    /// let server = Server::builder().router(Router::new()).worker_threads(2).build();
+    /// let config = server.effective_config();
+    /// assert_eq!(config.worker_threads, 2);
+    /// assert!(config.middlewares.is_empty());
+    /// ```
+    pub fn effective_config(&self) -> EffectiveConfig {
+        let mut config = self.config.clone();
+        config.listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        config
+    }
+
+    /// Process `request` through the middlewares and the router, the way the server processes every request it receives,
+    /// without any connection. See `saphir::test::TestClient` to build the requests.
+    pub fn process(&self, request: &mut SyncRequest) -> SyncResponse {
+        self.context.process(request)
+    }
+
+    /// Serve FastCGI requests on `addr`, like `127.0.0.1:9000`, for web servers which only forward requests over FastCGI.
+    /// Requests go through the same middlewares, router and controllers as when serving HTTP. This method runs until the
+    /// listener fails.
+    ///
+    /// Every connection is served on a thread of its own, and the connection hooks are invoked with the address of the web
+    /// server. Request bodies are not pooled and background tasks are not started.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder().router(Router::new()).build();
+    /// server.run_fastcgi("127.0.0.1:9000").unwrap();
+    /// ```
+    pub fn run_fastcgi(&self, addr: &str) -> Result<(), ServerError> {
+        let addr: SocketAddr = addr.parse()?;
+        self.serve_fastcgi(bind_listener(&addr, false)?)
+    }
+
+    /// Serve FastCGI requests on `listener`, see `run_fastcgi`
+    pub fn serve_fastcgi(&self, listener: TcpListener) -> Result<(), ServerError> {
+        let addr = listener.local_addr()?;
+        self.listeners.lock().unwrap_or_else(|e| e.into_inner()).push(format!("fastcgi://{}", addr));
+        log_event(Level::Info, SERVER_LOG_TARGET, "listening", format_args!("Saphir successfully started and listening for FastCGI on {}", addr),
+                  &[("addr", &addr), ("protocol", &"fastcgi")]);
+
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(target: SERVER_LOG_TARGET, "Unable to accept a FastCGI connection: {}", e);
+                    continue;
+                }
+            };
+
+            let peer_addr = match stream.peer_addr() {
+                Ok(peer_addr) => peer_addr,
+                Err(_) => continue,
+            };
+
+            let context = self.context.clone();
+            thread::Builder::new().name("saphir-fastcgi".to_string()).spawn(move || {
+                let _connection = Connection::new(peer_addr, &context);
+                if let Err(e) = handle_fastcgi_connection(&mut stream, |request| context.process(request)) {
+                    debug!(target: SERVER_LOG_TARGET, "The FastCGI connection of {} failed: {}", peer_addr, e);
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Serve FastCGI requests on the unix socket `listener`, see `run_fastcgi`. The connection hooks are not invoked, since
+    /// unix sockets have no peer address.
+    #[cfg(unix)]
+    pub fn serve_fastcgi_unix(&self, listener: ::std::os::unix::net::UnixListener) -> Result<(), ServerError> {
+        log_event(Level::Info, SERVER_LOG_TARGET, "listening",
+                  format_args!("Saphir successfully started and listening for FastCGI on {:?}", listener.local_addr().ok()),
+                  &[("protocol", &"fastcgi")]);
+
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(target: SERVER_LOG_TARGET, "Unable to accept a FastCGI connection: {}", e);
+                    continue;
+                }
+            };
+
+            let context = self.context.clone();
+            thread::Builder::new().name("saphir-fastcgi".to_string()).spawn(move || {
+                if let Err(e) = handle_fastcgi_connection(&mut stream, |request| context.process(request)) {
+                    debug!(target: SERVER_LOG_TARGET, "A FastCGI connection failed: {}", e);
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns a `hyper` service processing the requests of a connection from `peer_addr` through the middlewares and the
+    /// router, to embed the application into an existing `hyper` server. A service should be created for every connection,
+    /// as the connection hooks and the buffer pools of the server are tied to it.
+    ///
+    /// Background tasks are not started, since the server itself doesn't run.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate hyper;
+    /// # extern crate saphir;
+    /// # use saphir::*;
+    /// use hyper::rt::Future;
+    /// use hyper::server::conn::AddrStream;
+    /// use hyper::service::make_service_fn;
+    /// use std::sync::Arc;
+    ///
+    /// let app = Arc::new(Server::builder().router(Router::new()).build());
+    ///
+    /// let server = hyper::Server::bind(&([127, 0, 0, 1], 3000).into())
+    ///     .serve(make_service_fn(move |conn: &AddrStream| Ok::<_, ServerError>(app.service(conn.remote_addr()))))
+    ///     .map_err(|e| eprintln!("server error: {}", e));
+    ///
+    /// hyper::rt::run(server);
+    /// ```
+    pub fn service(&self, peer_addr: SocketAddr) -> SaphirService {
+        SaphirService {
+            connection: Connection::new(peer_addr, &self.context),
+            context: self.context.clone(),
+        }
+    }
+
+    /// Run the server on a background thread, listening on an ephemeral port of the loopback interface, so end-to-end tests
+    /// can exercise real connections without picking a free port themselves
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder().router(Router::new()).build().spawn_test().unwrap();
+    /// println!("listening on {}", server.url());
+    /// server.shutdown().unwrap();
+    /// ```
+    pub fn spawn_test(self) -> Result<TestServer, ServerError> {
+        self.start()?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let (shutdown_tx, shutdown_rx) = channel();
+
+        let handle = thread::Builder::new().name(format!("saphir-test-{}", addr.port())).spawn(move || {
+            self.serve(listener, &addr, shutdown_rx.map_err(|_| ()))
+        })?;
+
+        Ok(TestServer {
+            addr,
+            shutdown: Some(shutdown_tx),
+            handle: Some(handle),
+        })
+    }
+
+    /// Start serving requests over HTTP/3 on a background thread, on the UDP address of `config`, and advertise the listener
+    /// in the `Alt-Svc` header of the responses. `run` calls it when the builder was given an `Http3Config`, it is only needed
+    /// when connections are served another way, like in tests. The listener is closed when the returned handle is dropped.
+    #[cfg(feature = "http3")]
+    pub fn spawn_http3(&self, config: &Http3Config) -> Result<Http3Server, ServerError> {
+        let context = self.context.clone();
+        let http3 = ::http3::spawn(config, move |request| {
+            let started = ::std::time::Instant::now();
+            let response = context.process(request);
+            let elapsed = started.elapsed();
+            let bytes = if response.is_streamed() { None } else { Some(response.get_body().len() as u64) };
+            log_access(request, response.get_status(), elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 * 1e-6,
+                       request.received_bytes(), bytes);
+            response
+        })?;
+
+        log_event(Level::Info, SERVER_LOG_TARGET, "listening", format_args!("Saphir listening for HTTP/3 on {}", http3.addr()),
+                  &[("addr", &http3.addr())]);
+
+        if let Ok(alt_svc) = header::HeaderValue::from_str(&http3.alt_svc(config.max_age())) {
+            self.context.alt_svc.store(Some(Arc::new(alt_svc)));
+        }
+
+        Ok(http3)
+    }
+
+    /// Run a background task for the lifetime of the server, like a periodic job or a queue consumer, see `task_queue`
+    ///
+    /// `task` is invoked once the server is listening, and the future it returns runs on a thread dedicated to the background
+    /// tasks of the server, so it shouldn't block. The future is cancelled when the server starts shutting down, and the
+    /// server waits for the tasks to be cancelled before returning from `run`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # extern crate futures;
+    /// # extern crate tokio;
+    /// # extern crate saphir;
+    /// # use saphir::*;
+    /// use futures::Stream;
+    /// use std::time::{Duration, Instant};
+    /// use tokio::timer::Interval;
+    ///
+    /// let server = Server::builder().router(Router::new()).build();
+    /// server.spawn_task(|| Interval::new(Instant::now(), Duration::from_secs(60))
+    ///     .map_err(|_| ())
+    ///     .for_each(|_| Ok(println!("purging expired sessions"))));
+    /// server.run("http://0.0.0.0:12345").unwrap();
+    /// ```
+    pub fn spawn_task<F, T>(&self, task: F)
+        where F: 'static + FnOnce() -> T + Send,
+              T: 'static + IntoFuture<Item=(), Error=()>,
+              T::Future: Send {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(move || Box::new(task().into_future())));
+    }
+
+    /// Report the progress of the draining of the connections to the `on_drain` hooks once `shutdown` completes, until the
+    /// returned watcher is dropped
+    fn watch_drain<S>(&self, shutdown: Shared<S>) -> DrainWatcher
+        where S: 'static + Future + Send, S::Item: Send + Sync, S::Error: Send + Sync {
+        let context = self.context.clone();
+        watch_drain(&self.context.in_flight, self.context.drain_interval, shutdown, move |report| {
+            for hook in &context.hooks.on_drain {
+                hook(report);
+            }
+        })
+    }
+
+    /// Start the background tasks registered so far, cancelling them when `shutdown` completes
+    fn start_tasks<S>(&self, shutdown: Shared<S>) -> Result<Option<thread::JoinHandle<()>>, ServerError>
+        where S: 'static + Future + Send, S::Item: Send + Sync, S::Error: Send + Sync {
+        let tasks: Vec<PendingTask> = self.tasks.lock().unwrap_or_else(|e| e.into_inner()).drain(..).collect();
+
+        if tasks.is_empty() {
+            return Ok(None);
         }
+
+        let name = format!("{}-tasks", self.threading.thread_name.as_deref().unwrap_or("saphir"));
+        Ok(Some(run_tasks(tasks, shutdown, name)?))
     }
 
     /// This method will run untill the server terminates, `uri` defines the listener uri.
@@ -36,70 +735,1054 @@ impl Server {
         }
 
         let addr = url.authority_part().expect("The uri passed to launch the server doesn't contain an authority.").as_str().parse()?;
-        let middleware_stack_clone = self.middleware_stack.clone();
-        let router_clone = self.router.clone();
-        let server = HyperServer::bind(&addr)
-            .serve(move || {
-                let middleware_stack_clone_svc = middleware_stack_clone.clone();
-                let router_clone_svc = router_clone.clone();
-                service_fn(move |req| {
-                    http_service(req, &middleware_stack_clone_svc, &router_clone_svc)
-                })
-            })
-            .map_err(|e| error!("server error: {}", e));
-        ;
-        info!("Saphir successfully started and listening on {}", addr);
-        ::hyper::rt::run(server);
+        self.start()?;
+
+        if self.context.log_routes {
+            info!(target: SERVER_LOG_TARGET, "Registered routes:\n{}", self.context.router.route_table());
+        }
+
+        #[cfg(feature = "http3")]
+        let _http3 = match self.http3 {
+            Some(ref config) => Some(self.spawn_http3(config)?),
+            None => None,
+        };
+
+        let mut inherited = if self.inherit_listener { inherited_listeners() } else { Vec::new() };
+
+        for listener in &inherited {
+            let addr = listener.local_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            log_event(Level::Info, SERVER_LOG_TARGET, "using inherited listener", format_args!("Using the inherited listener {}", addr),
+                      &[("addr", &addr)]);
+        }
+
+        {
+            let mut listeners = self.listeners.lock().unwrap_or_else(|e| e.into_inner());
+            listeners.clear();
+            match inherited.first().and_then(|listener| listener.local_addr().ok()) {
+                Some(addr) => listeners.push(format!("http://{} (inherited)", addr)),
+                None => listeners.push(format!("http://{}", addr)),
+            }
+        }
+
+        if self.startup_banner {
+            info!(target: SERVER_LOG_TARGET, "Effective configuration:\n{}", self.effective_config());
+        }
+
+        let (shutdown_tx, shutdown_rx) = channel();
+        // Dropping the sender would trigger the shutdown, it is only handed out when the listener can be handed over
+        let mut shutdown_tx = Some(shutdown_tx);
+
+        if self.threading.thread_per_core {
+            let threads = self.threading.worker_threads.unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+            let listeners = thread_listeners(&addr, inherited, threads)?;
+
+            if self.handover {
+                handover_on_sigusr2(&listeners, shutdown_tx.take().expect("shutdown sender"))?;
+            }
+
+            let result = self.run_thread_per_core(&addr, listeners, shutdown_rx.map(log_shutdown).shared());
+            drop(shutdown_tx);
+            return result;
+        }
+
+        let listener = if inherited.is_empty() { bind_listener(&addr, false)? } else { inherited.remove(0) };
+
+        if self.handover {
+            handover_on_sigusr2(::std::slice::from_ref(&listener), shutdown_tx.take().expect("shutdown sender"))?;
+        }
+
+        let result = self.serve(listener, &addr, shutdown_rx.map_err(|_| ()));
+        drop(shutdown_tx);
+        result
+    }
+
+    /// Accept connections on `listener` until `shutdown` completes, then wait for the connections in progress to complete
+    fn serve<F>(&self, listener: TcpListener, addr: &SocketAddr, shutdown: F) -> Result<(), ServerError>
+        where F: 'static + Future<Item=(), Error=()> + Send {
+        let shutdown = shutdown.map(log_shutdown).shared();
+        let context_clone = self.context.clone();
+        let server = HyperServer::builder(Incoming::new(listener, self.context.malformed.clone())?)
+            .http1_half_close(!self.cancel_on_half_close)
+            .serve(make_service_fn(move |conn: &GuardedStream| {
+                let context_clone_svc = context_clone.clone();
+                let mut connection = Connection::new(conn.remote_addr(), &context_clone);
+                connection.dispatched = conn.dispatched();
+                Ok::<_, ServerError>(service_fn(move |req| {
+                    http_service(req, &connection, &context_clone_svc)
+                }))
+            }))
+            .with_graceful_shutdown(shutdown.clone().then(|_| Ok::<(), ()>(())))
+            .map_err(log_server_error);
+
+        let mut builder = RuntimeBuilder::new();
+
+        if let Some(threads) = self.threading.worker_threads {
+            builder.core_threads(threads);
+        }
+
+        if let Some(ref name) = self.threading.thread_name {
+            builder.name_prefix(format!("{}-worker-", name));
+        }
+
+        if self.threading.pin_threads {
+            let next_core = AtomicUsize::new(0);
+            builder.after_start(move || pin_current_thread(next_core.fetch_add(1, Ordering::SeqCst)));
+        }
+
+        let runtime = builder.build()?;
+
+        log_event(Level::Info, SERVER_LOG_TARGET, "listening", format_args!("Saphir successfully started and listening on {}", addr),
+                  &[("addr", addr)]);
+        let drain = self.watch_drain(shutdown.clone());
+        let tasks = self.start_tasks(shutdown)?;
+        let _ = runtime.block_on_all(server);
+
+        if let Some(tasks) = tasks {
+            let _ = tasks.join();
+        }
+        drop(drain);
+
+        // The connections are closed, no more event can be emitted
+        self.lifecycle.shutdown();
+        self.context.flush_audit_log();
+        log_stopped(addr);
         Ok(())
     }
+
+    /// Run the startup hooks then start the warm-up tasks, logging why the server can't start when a startup hook fails
+    fn start(&self) -> Result<(), ServerError> {
+        let started = self.lifecycle.startup().and_then(|_| match self.context.warm_up {
+            Some(ref state) => self.lifecycle.start_warm_up(state.clone()),
+            None => Ok(()),
+        });
+
+        started.map_err(|e| {
+            log_event(Level::Error, SERVER_LOG_TARGET, "startup failed", format_args!("Saphir failed to start: {}", e), &[("error", &e)]);
+            ServerError::StartupFailed(e)
+        })
+    }
+
+    /// Run one single threaded runtime per listener, each accepting connections on its own thread
+    fn run_thread_per_core<S>(&self, addr: &SocketAddr, listeners: Vec<TcpListener>, shutdown: Shared<S>) -> Result<(), ServerError>
+        where S: 'static + Future<Item=()> + Send, S::Error: Send + Sync {
+        let threads = listeners.len();
+        let name = self.threading.thread_name.clone().unwrap_or_else(|| "saphir".to_string());
+        let mut handles = Vec::with_capacity(threads);
+
+        for (index, listener) in listeners.into_iter().enumerate() {
+            let shutdown = shutdown.clone().map(|_| ()).map_err(|_| ());
+            let context = self.context.clone();
+            let pin_threads = self.threading.pin_threads;
+            let half_close = !self.cancel_on_half_close;
+
+            let handle = thread::Builder::new().name(format!("{}-worker-{}", name, index + 1)).spawn(move || {
+                if pin_threads {
+                    pin_current_thread(index);
+                }
+
+                let mut runtime = match CurrentThreadRuntime::new() {
+                    Ok(runtime) => runtime,
+                    Err(e) => return error!(target: SERVER_LOG_TARGET, "Unable to start the runtime of a worker thread: {}", e),
+                };
+
+                let server = match Incoming::new(listener, context.malformed.clone()) {
+                    Ok(incoming) => HyperServer::builder(incoming),
+                    Err(e) => return error!(target: SERVER_LOG_TARGET, "Unable to listen on a worker thread: {}", e),
+                };
+
+                let server = server.executor(TaskExecutor::current())
+                    .http1_half_close(half_close)
+                    .serve(make_service_fn(move |conn: &GuardedStream| {
+                        let context_svc = context.clone();
+                        let mut connection = Connection::new(conn.remote_addr(), &context);
+                        connection.dispatched = conn.dispatched();
+                        Ok::<_, ServerError>(service_fn(move |req| {
+                            http_service(req, &connection, &context_svc)
+                        }))
+                    }))
+                    .with_graceful_shutdown(shutdown)
+                    .map_err(log_server_error);
+
+                let _ = runtime.block_on(server);
+            })?;
+
+            handles.push(handle);
+        }
+
+        log_event(Level::Info, SERVER_LOG_TARGET, "listening",
+                  format_args!("Saphir successfully started and listening on {} with {} threads", addr, threads),
+                  &[("addr", addr), ("threads", &threads)]);
+
+        let drain = self.watch_drain(shutdown.clone());
+        handles.extend(self.start_tasks(shutdown)?);
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+        drop(drain);
+
+        // The connections are closed, no more event can be emitted
+        self.lifecycle.shutdown();
+        self.context.flush_audit_log();
+        log_stopped(addr);
+        Ok(())
+    }
+}
+
+/// A server running on a background thread for the duration of a test, see `Server::spawn_test`
+///
+/// The server is shut down when this handle is dropped.
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: Option<Sender<()>>,
+    handle: Option<thread::JoinHandle<Result<(), ServerError>>>,
+}
+
+impl TestServer {
+    /// Returns the address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the base url of the server, like `http://127.0.0.1:43567`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Stop accepting connections and wait for the ones in progress to complete
+    pub fn shutdown(mut self) -> Result<(), ServerError> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Result<(), ServerError> {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        match self.handle.take().map(|handle| handle.join()) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => {
+                error!("The test server on {} panicked", self.addr);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
 }
 
-fn http_service(req: Request<Body>, middleware_stack: &Arc<MiddlewareStack>, router: &Arc<Router>)
-                -> Box<Future<Item=Response<Body>, Error=ServerError> + Send> {
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// A `hyper` service processing requests through the middlewares and the router of a `Server`, see `Server::service`
+///
+/// With the `tower` feature, it is a `tower_service::Service` as well, so it can be wrapped in `tower` layers.
+pub struct SaphirService {
+    connection: Connection,
+    context: Arc<ServiceContext>,
+}
+
+impl Service for SaphirService {
+    type ReqBody = Body;
+    type ResBody = ResponseBody;
+    type Error = ServerError;
+    type Future = Box<dyn Future<Item=Response<ResponseBody>, Error=ServerError> + Send>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        http_service(req, &self.connection, &self.context)
+    }
+}
+
+#[cfg(feature = "tower")]
+impl ::tower_service::Service<Request<Body>> for SaphirService {
+    type Response = Response<ResponseBody>;
+    type Error = ServerError;
+    type Future = Box<dyn Future<Item=Response<ResponseBody>, Error=ServerError> + Send>;
+
+    /// Requests are processed on the handler threads, the service is always ready to take one
+    fn poll_ready(&mut self) -> ::futures::Poll<(), ServerError> {
+        Ok(::futures::Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        http_service(req, &self.connection, &self.context)
+    }
+}
+
+/// A builder to configure and create a `Server`
+pub struct ServerBuilder {
+    router: Option<Router>,
+    middleware_stack: Option<MiddlewareStack>,
+    template_engine: Option<Box<dyn TemplateEngine>>,
+    state: StateMap,
+    log_routes: bool,
+    startup_banner: bool,
+    log_format: Option<LogFormat>,
+    hooks: Hooks,
+    handler_threads: Option<usize>,
+    fairness: Option<Fairness>,
+    threading: Threading,
+    inherit_listener: bool,
+    handover: bool,
+    cancel_on_half_close: bool,
+    problem_details: bool,
+    trusted_proxies: Option<TrustedProxies>,
+    default_headers: header::HeaderMap,
+    body_limits: BodyLimits,
+    profile: Option<Profile>,
+    debug_endpoint: Option<(String, DebugController)>,
+    admin_endpoint: Option<(String, AdminController)>,
+    lifecycle: LifecycleHooks,
+    asset_manifest: Option<AssetManifest>,
+    maintenance: Option<MaintenanceMode>,
+    feature_flags: Option<SharedFeatureFlags>,
+    body_decoders: HashMap<String, Arc<dyn BodyDecoder>>,
+    malformed_requests: Option<MalformedRequestPolicy>,
+    profiler: Option<Profiler>,
+    audit_log: Option<AuditLog>,
+    drain_interval: Duration,
+    #[cfg(feature = "http3")]
+    http3: Option<Http3Config>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerBuilder::new()
+    }
+}
+
+impl ServerBuilder {
+    /// Create a new builder, with an empty router and middleware stack
+    pub fn new() -> Self {
+        ServerBuilder {
+            router: None,
+            middleware_stack: None,
+            template_engine: None,
+            state: StateMap::new(),
+            log_routes: false,
+            startup_banner: false,
+            log_format: None,
+            hooks: Hooks::default(),
+            handler_threads: None,
+            fairness: None,
+            threading: Threading::default(),
+            inherit_listener: false,
+            handover: false,
+            cancel_on_half_close: false,
+            problem_details: true,
+            trusted_proxies: None,
+            default_headers: header::HeaderMap::new(),
+            body_limits: BodyLimits::default(),
+            profile: None,
+            debug_endpoint: None,
+            admin_endpoint: None,
+            lifecycle: LifecycleHooks::default(),
+            asset_manifest: None,
+            maintenance: None,
+            feature_flags: None,
+            body_decoders: HashMap::new(),
+            malformed_requests: None,
+            profiler: None,
+            audit_log: None,
+            drain_interval: Duration::from_secs(1),
+            #[cfg(feature = "http3")]
+            http3: None,
+        }
+    }
+
+    /// Set the router dispatching requests towards controllers
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Some(router);
+        self
+    }
+
+    /// Set the middleware stack every request goes through before reaching the router
+    pub fn middleware_stack(mut self, middleware_stack: MiddlewareStack) -> Self {
+        self.middleware_stack = Some(middleware_stack);
+        self
+    }
+
+    /// Register the template engine used by `SyncResponse::render`
+    pub fn template_engine<E: 'static + TemplateEngine>(mut self, engine: E) -> Self {
+        self.template_engine = Some(Box::new(engine));
+        self
+    }
+
+    /// Register an application state, available to guards, middlewares and delegates through `SyncRequest::state`.
+    /// Any number of states can be registered as long as their types differ.
+    pub fn state<T: Any + Send + Sync>(mut self, state: T) -> Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Log the table of registered routes when the server starts, see `Router::route_table`
+    pub fn log_routes(mut self) -> Self {
+        self.log_routes = true;
+        self
+    }
+
+    /// Log a summary of the configuration the server runs with when it starts, its listeners, threads, limits, middlewares
+    /// in order and number of routes, see `Server::effective_config`
+    pub fn startup_banner(mut self) -> Self {
+        self.startup_banner = true;
+        self
+    }
+
+    /// Set how saphir formats its log records, see `set_log_format`. Saphir logs through the `log` facade to the targets
+    /// `saphir::server`, `saphir::routing`, `saphir::guard`, `saphir::handler` and `saphir::access`, which loggers can filter
+    /// on; routing decisions and guard rejections are logged at the debug level.
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = Some(format);
+        self
+    }
+
+    /// Invoke `hook` with every request before the middlewares see it, for cross-cutting concerns like custom metrics which
+    /// don't justify a middleware. Hooks are invoked in the order they are registered, on the thread processing the request.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let open_connections = Arc::new(AtomicUsize::new(0));
+    /// let (opened, closed) = (open_connections.clone(), open_connections.clone());
+    ///
+    /// let server = Server::builder()
+    ///     .on_request(|req| println!("{} {}", req.method(), req.uri()))
+    ///     .on_response(|req, res| println!("{} {} {}", req.method(), req.uri(), res.get_status()))
+    ///     .on_connection_open(move |_| { opened.fetch_add(1, Ordering::Relaxed); })
+    ///     .on_connection_close(move |_| { closed.fetch_sub(1, Ordering::Relaxed); })
+    ///     .build();
+    /// ```
+    pub fn on_request<F: 'static + Fn(&SyncRequest) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.on_request.push(Box::new(hook));
+        self
+    }
+
+    /// Invoke `hook` with every request and its response, once the middlewares and the router are done with them
+    pub fn on_response<F: 'static + Fn(&SyncRequest, &SyncResponse) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.on_response.push(Box::new(hook));
+        self
+    }
+
+    /// Invoke `hook` with every request and its response before the response is sent, letting it change the response, like
+    /// adding headers computed from the request. Hooks are invoked in the order they are registered, after the default
+    /// headers are added and before the `on_response` hooks.
+    pub fn before_send<F: 'static + Fn(&SyncRequest, &mut SyncResponse) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.before_send.push(Box::new(hook));
+        self
+    }
+
+    /// Invoke `hook` in place of the responses whose body couldn't be serialized, see `SerializationError`
+    ///
+    /// The response given to the hook is a fresh `500 Internal Server Error`, which the hook may turn into any other
+    /// answer, like a problem document of its own. Left without a body, it is given a problem document like any other error
+    /// response.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder()
+    ///     .on_serialization_error(|_, error, res| {
+    ///         res.status(StatusCode::SERVICE_UNAVAILABLE).problem(&Problem::new(StatusCode::SERVICE_UNAVAILABLE)
+    ///             .with_detail(format!("The {} export is unavailable", error.format())));
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_serialization_error<F>(mut self, hook: F) -> Self
+        where F: 'static + Fn(&SyncRequest, &SerializationError, &mut SyncResponse) + Send + Sync {
+        self.hooks.on_serialization_error.push(Box::new(hook));
+        self
+    }
+
+    /// Invoke `hook` with the progress of the draining of the connections while the server shuts down: once it stopped
+    /// accepting connections, then every second, see `drain_interval`, and a last time once every connection completed
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder()
+    ///     .router(Router::new())
+    ///     .on_drain(|report| {
+    ///         println!("{} requests left, {} of them streaming, the oldest for {:?}",
+    ///                  report.in_flight(), report.streams(), report.oldest_request_age());
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn on_drain<F: 'static + Fn(&DrainReport) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.on_drain.push(Box::new(hook));
+        self
+    }
+
+    /// Set how often the `on_drain` hooks are invoked while the server shuts down, every second by default
+    pub fn drain_interval(mut self, interval: Duration) -> Self {
+        self.drain_interval = interval;
+        self
+    }
+
+    /// Add the header `name` to every response which doesn't already have it, like `Server` or a version header, including
+    /// the responses of the router and of the middlewares. Calling it again with the same name adds another value. Invalid
+    /// names or values are logged and ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder()
+    ///     .default_header(header::SERVER, "saphir")
+    ///     .default_header("x-api-version", "2")
+    ///     .default_header(header::CACHE_CONTROL, "no-store")
+    ///     .build();
+    /// ```
+    pub fn default_header<K, V>(mut self, name: K, value: V) -> Self
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        let name = match <header::HeaderName as HttpTryFrom<K>>::try_from(name) {
+            Ok(name) => name,
+            Err(_) => {
+                warn!(target: SERVER_LOG_TARGET, "Ignoring a default header with an invalid name");
+                return self;
+            }
+        };
+
+        match <header::HeaderValue as HttpTryFrom<V>>::try_from(value) {
+            Ok(value) => { self.default_headers.append(name, value); }
+            Err(_) => warn!(target: SERVER_LOG_TARGET, "Ignoring the default header {} with an invalid value", name),
+        }
+
+        self
+    }
+
+    /// Invoke `hook` with the address of the peer of every accepted connection
+    pub fn on_connection_open<F: 'static + Fn(SocketAddr) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.on_connection_open.push(Box::new(hook));
+        self
+    }
+
+    /// Invoke `hook` with the address of the peer of every connection, once it is closed
+    pub fn on_connection_close<F: 'static + Fn(SocketAddr) + Send + Sync>(mut self, hook: F) -> Self {
+        self.hooks.on_connection_close.push(Box::new(hook));
+        self
+    }
+
+    /// Run `hook`, named `name`, when the server starts, once the startup hooks named in `after` completed and before any
+    /// connection is accepted. Hooks which don't run after each other run concurrently. When a startup hook fails, the
+    /// server doesn't start and `run` returns `ServerError::StartupFailed`, telling which hook failed.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let server = Server::builder()
+    ///     .router(Router::new())
+    ///     .on_startup("database", &[], || -> Result<(), String> { Ok(()) })
+    ///     .on_startup("cache", &["database"], || -> Result<(), String> { Ok(()) })
+    ///     .on_shutdown("database", &[], || -> Result<(), String> { Ok(()) })
+    ///     .build();
+    /// ```
+    pub fn on_startup<H: 'static + LifecycleHook>(mut self, name: &str, after: &[&str], hook: H) -> Self {
+        self.lifecycle.add_startup(name, after, Box::new(hook));
+        self
+    }
+
+    /// Run `hook`, named `name`, when the server stops, once the connections in progress completed and the shutdown hooks
+    /// named in `after` completed. A failing shutdown hook is logged, the other ones still run.
+    pub fn on_shutdown<H: 'static + LifecycleHook>(mut self, name: &str, after: &[&str], hook: H) -> Self {
+        self.lifecycle.add_shutdown(name, after, Box::new(hook));
+        self
+    }
+
+    /// Run `task`, named `name`, once the server started, after the warm-up tasks named in `after` completed, like compiling
+    /// templates, priming a cache or checking that upstream services are reachable. The server accepts connections
+    /// meanwhile, but the readiness probe of the `HealthController` fails until every warm-up task completed, so that load
+    /// balancers only send traffic to a warmed up server. A failing task keeps the server unready, and the tasks which run
+    /// after it are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let mut health = HealthController::new();
+    /// health.add_readiness_check("database", || -> Result<(), String> { Ok(()) });
+    ///
+    /// let mut router = Router::new();
+    /// router.add("^/(healthz|readyz)$", health);
+    ///
+    /// let server = Server::builder()
+    ///     .router(router)
+    ///     .warm_up("templates", &[], || -> Result<(), String> { Ok(()) })
+    ///     .warm_up("catalog cache", &[], || -> Result<(), String> { Ok(()) })
+    ///     .build();
+    /// ```
+    pub fn warm_up<H: 'static + LifecycleHook>(mut self, name: &str, after: &[&str], task: H) -> Self {
+        self.lifecycle.add_warm_up(name, after, Box::new(task));
+        self
+    }
+
+    /// Set the number of threads accepting connections and processing their io, defaults to the number of cpus
+    pub fn worker_threads(mut self, threads: usize) -> Self {
+        self.threading.worker_threads = Some(threads);
+        self
+    }
+
+    /// Run middlewares and controllers on a pool of `threads` threads, instead of a new thread for every request
+    pub fn handler_threads(mut self, threads: usize) -> Self {
+        self.handler_threads = Some(threads);
+        self
+    }
+
+    /// Share the handler threads amongst the connections or the clients sending requests, rather than processing requests
+    /// in the order they were received, so a client sending many requests at once can't starve the others, see
+    /// `Fairness`. Requests run on a pool of as many threads as cpus unless `handler_threads` sets another number.
+    pub fn fairness(mut self, fairness: Fairness) -> Self {
+        self.fairness = Some(fairness);
+        self
+    }
+
+    /// Offload the work handlers give to `SyncRequest::run_blocking` to `pool`, instead of running it on their thread
+    pub fn blocking_pool(mut self, pool: BlockingPool) -> Self {
+        self.state.insert(pool);
+        self
+    }
+
+    /// Prefix the name of the threads of the server with `name`
+    pub fn thread_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.threading.thread_name = Some(name.into());
+        self
+    }
+
+    /// Pin every worker thread to a cpu core
+    pub fn pin_threads(mut self) -> Self {
+        self.threading.pin_threads = true;
+        self
+    }
+
+    /// Run a single threaded runtime on every worker thread, each accepting connections on its own listener bound with
+    /// `SO_REUSEPORT` so the kernel balances connections amongst them. On platforms without `SO_REUSEPORT`, worker threads
+    /// share the same listener. This avoids any synchronization between worker threads, at the cost of letting a busy thread
+    /// hold connections other threads could have served.
+    pub fn thread_per_core(mut self) -> Self {
+        self.threading.thread_per_core = true;
+        self
+    }
+
+    /// Listen on the socket inherited from systemd socket activation (`LISTEN_FDS`), or handed over by a previous process,
+    /// when there is one instead of binding a new one. Only available on unix platforms.
+    pub fn inherit_listener(mut self) -> Self {
+        self.inherit_listener = true;
+        self
+    }
+
+    /// On `SIGUSR2`, start a new instance of the current executable with the same arguments and hand it the listening socket,
+    /// then stop accepting connections and return from `Server::run` once the ones in progress are completed. This implies
+    /// `inherit_listener` so the new instance picks the socket up, allowing to upgrade the binary without refusing a single
+    /// connection. Only available on unix platforms.
+    pub fn handover_on_sigusr2(mut self) -> Self {
+        self.inherit_listener = true;
+        self.handover = true;
+        self
+    }
+
+    /// Consider a client shutting down its writing side of the connection as gone, cancelling the requests it was waiting
+    /// for instead of still sending their responses. HTTP/1 clients are allowed to half close once their request is sent,
+    /// so this is only suited to servers whose clients never do.
+    pub fn cancel_on_half_close(mut self) -> Self {
+        self.cancel_on_half_close = true;
+        self
+    }
+
+    /// Set whether error responses without a body, like the ones generated by the router, are given an
+    /// `application/problem+json` body describing their status, see `Problem`. Enabled by default.
+    pub fn problem_details(mut self, enabled: bool) -> Self {
+        self.problem_details = enabled;
+        self
+    }
+
+    /// Set the proxies trusted to report the address of the client in forwarding headers, see `SyncRequest::client_ip`.
+    /// Without trusted proxies, the client address is the address of the peer.
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Some(proxies);
+        self
+    }
+
+    /// Set the maximum size of the request bodies and how long they may take to be received, by route and by content type.
+    /// Requests exceeding them are answered with `413 Payload Too Large` or `408 Request Timeout` without going through the
+    /// middlewares. Request bodies are unlimited by default.
+    pub fn body_limits(mut self, limits: BodyLimits) -> Self {
+        self.body_limits = limits;
+        self
+    }
+
+    /// Switch the defaults of the server to the ones of `profile`, see `Profile`. Without a profile, the one named by the
+    /// `SAPHIR_PROFILE` environment variable is used, if any.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let profile = if cfg!(debug_assertions) { Profile::Development } else { Profile::Production };
+    /// let server = Server::builder().router(Router::new()).profile(profile).build();
+    /// ```
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Register `controller` in the router under the path `prefix`, like `/_debug`, and record the requests answered by the
+    /// server for it to list them, see `DebugController`. No request is recorded without a debug endpoint.
+    pub fn debug_endpoint(mut self, prefix: &str, controller: DebugController) -> Self {
+        self.debug_endpoint = Some((prefix.to_string(), controller));
+        self
+    }
+
+    /// Serve the runtime controls of the server under `prefix`, like `/_admin`, see `AdminController`
+    pub fn admin_endpoint(mut self, prefix: &str, controller: AdminController) -> Self {
+        self.admin_endpoint = Some((prefix.to_string(), controller));
+        self
+    }
+
+    /// Register the manifest giving the fingerprinted URLs of the static assets, for `SyncResponse::asset_url` and the
+    /// `asset_url` helper of the template engine, see `AssetManifest`
+    pub fn asset_manifest(mut self, manifest: AssetManifest) -> Self {
+        self.asset_manifest = Some(manifest);
+        self
+    }
+
+    /// Register the switch putting the server in maintenance, see `MaintenanceMode`. It is checked before the middlewares
+    /// and the router, for every request.
+    pub fn maintenance(mut self, maintenance: MaintenanceMode) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Register the provider of the feature flags returned by `SyncRequest::feature`, see `FeatureFlagProvider`
+    pub fn feature_flags<P: 'static + FeatureFlagProvider>(mut self, provider: P) -> Self {
+        self.feature_flags = Some(SharedFeatureFlags(Arc::new(provider)));
+        self
+    }
+
+    /// Decode the request bodies of `media_type`, like `application/yaml`, with `decoder` in `SyncRequest::parse_body`,
+    /// instead of answering them with `415 Unsupported Media Type` or decoding them as the server does by default
+    pub fn body_decoder<D: 'static + BodyDecoder>(mut self, media_type: &str, decoder: D) -> Self {
+        self.body_decoders.insert(media_type.to_ascii_lowercase(), Arc::new(decoder));
+        self
+    }
+
+    /// Handle the requests the server can't parse, like the ones with a bad request line or an invalid header, according to
+    /// `policy` rather than just answering them with `400 Bad Request`, see `MalformedRequestPolicy`
+    pub fn malformed_requests(mut self, policy: MalformedRequestPolicy) -> Self {
+        self.malformed_requests = Some(policy);
+        self
+    }
+
+    /// Time the phases of every request, from the routing to the serialization of its response, and aggregate them per
+    /// route in `profiler`, see `Profiler`
+    pub fn profiling(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Register the audit log keeping the events emitted with `SyncRequest::audit`, see `AuditLog`
+    pub fn audit_log(mut self, log: AuditLog) -> Self {
+        self.audit_log = Some(log);
+        self
+    }
+
+    /// Also serve requests over HTTP/3 when the server runs, on the UDP address of `config`, and advertise it to the clients
+    /// connected over TCP with the `Alt-Svc` header. Both listeners share the router, the middlewares and the hooks.
+    /// Experimental, see `Http3Config`.
+    #[cfg(feature = "http3")]
+    pub fn http3(mut self, config: Http3Config) -> Self {
+        self.http3 = Some(config);
+        self
+    }
+
+    /// Create the server
+    pub fn build(self) -> Server {
+        #[cfg(feature = "http3")]
+        let http3 = self.http3.clone();
+        let ServerBuilder { router, middleware_stack, template_engine, state, log_routes, startup_banner, log_format, hooks, handler_threads, fairness, threading, inherit_listener, handover, cancel_on_half_close, problem_details, trusted_proxies, default_headers, body_limits, profile, debug_endpoint, admin_endpoint, asset_manifest, maintenance, feature_flags, body_decoders, malformed_requests, profiler, audit_log, drain_interval, mut lifecycle, .. } = self;
+
+        if let Some(format) = log_format {
+            set_log_format(format);
+        }
+
+        let profile = profile.or_else(Profile::from_env);
+        if profile == Some(Profile::Development) {
+            capture_backtraces();
+        }
+
+        let template_engine = template_engine.map(|mut engine| {
+            if let Some(profile) = profile {
+                engine.hot_reload(profile.is_development());
+            }
+            if let Some(ref manifest) = asset_manifest {
+                engine.asset_manifest(manifest);
+            }
+            RegisteredTemplateEngine(Arc::from(engine))
+        });
+
+        // Requests are scheduled fairly amongst the threads of a pool, which is sized after the cpus by default
+        let handler_threads = handler_threads.or_else(|| fairness.map(|_| thread::available_parallelism().map(|n| n.get()).unwrap_or(1)));
+        let handler_pool = handler_threads.map(|threads| {
+            let mut pool = CpuPoolBuilder::new();
+            pool.pool_size(threads);
+            pool.name_prefix(format!("{}-handler-", threading.thread_name.as_deref().unwrap_or("saphir")));
+            pool.create()
+        });
+        let scheduler = match (fairness, &handler_pool, handler_threads) {
+            (Some(fairness), Some(pool), Some(threads)) => Some(FairScheduler::new(fairness, pool.clone(), threads)),
+            _ => None,
+        };
+
+        let middleware_stack = middleware_stack.unwrap_or_else(MiddlewareStack::new);
+        let mut router = router.unwrap_or_else(Router::new);
+        let in_flight = Arc::new(InFlight::default());
+        let draining = admin_endpoint.map(|(prefix, controller)| controller.register(&prefix, &mut router, maintenance.clone(), in_flight.clone()));
+        let request_log = debug_endpoint.map(|(prefix, controller)| controller.register(&prefix, &mut router, &middleware_stack));
+
+        let config = EffectiveConfig {
+            listeners: Vec::new(),
+            profile,
+            worker_threads: threading.worker_threads.unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            handler_threads,
+            fairness,
+            thread_per_core: threading.thread_per_core,
+            pin_threads: threading.pin_threads,
+            inherit_listener,
+            handover,
+            problem_details,
+            trusted_proxies: trusted_proxies.as_ref()
+                .map(|proxies| proxies.networks().iter().map(|network| network.to_string()).collect())
+                .unwrap_or_default(),
+            default_headers: default_headers.iter()
+                .map(|(name, value)| (name.to_string(), value.to_str().unwrap_or("").to_string()))
+                .collect(),
+            limits: body_limits.config(),
+            drain_interval_ms: drain_interval.as_millis() as u64,
+            maintenance: maintenance.is_some(),
+            profiling: profiler.is_some(),
+            #[cfg(feature = "http3")]
+            http3: http3.as_ref().map(|config| config.listen_addr().to_string()),
+            #[cfg(not(feature = "http3"))]
+            http3: None,
+            middlewares: middleware_stack.middlewares(),
+            route_count: router.routes().len(),
+        };
+
+        Server {
+            context: Arc::new(ServiceContext {
+                middleware_stack,
+                router,
+                template_engine,
+                state: SharedState(Arc::new(state)),
+                log_routes,
+                handler_pool,
+                scheduler,
+                buffer_stats: BufferPoolStats::default(),
+                hooks: Arc::new(hooks),
+                problem_details,
+                trusted_proxies: trusted_proxies.map(|proxies| SharedTrustedProxies(Arc::new(proxies))),
+                default_headers,
+                alt_svc: ArcSwapOption::const_empty(),
+                body_limits,
+                profile,
+                request_log,
+                asset_manifest,
+                maintenance,
+                feature_flags,
+                body_decoders: if body_decoders.is_empty() { None } else { Some(BodyDecoders(Arc::new(body_decoders))) },
+                malformed: malformed_requests.map(|policy| Arc::new(MalformedRequests::new(policy))),
+                profiler,
+                audit_log: audit_log.map(|log| SharedAuditLog(Arc::new(log))),
+                draining,
+                warm_up: lifecycle.warm_up_state(),
+                in_flight,
+                drain_interval,
+            }),
+            threading,
+            inherit_listener,
+            handover,
+            cancel_on_half_close,
+            tasks: Mutex::new(Vec::new()),
+            lifecycle,
+            config,
+            listeners: Mutex::new(Vec::new()),
+            startup_banner,
+            #[cfg(feature = "http3")]
+            http3,
+        }
+    }
+}
+
+fn http_service(req: Request<Body>, connection: &Connection, context: &Arc<ServiceContext>)
+                -> Box<dyn Future<Item=Response<ResponseBody>, Error=ServerError> + Send> {
     use std::time::Instant;
-    use server::utils::RequestContinuation::*;
-    use futures::sync::oneshot::channel;
-    use std::thread;
+
+    if let Some(ref dispatched) = connection.dispatched {
+        dispatched.dispatch();
+    }
 
     let (tx, rx) = channel();
-    let middleware_stack_c = middleware_stack.clone();
-    let router_c = router.clone();
+    let context_c = context.clone();
 
-    Box::new(req.load_body().map_err(|e| ServerError::from(e)).and_then(move |request| {
-        thread::spawn(move || {
-            let req_iat = Instant::now();
-            let mut response = SyncResponse::new();
+    let peer_addr = connection.peer_addr;
+    let connection_id = connection.id;
+    let buffers = connection.buffers.clone();
+    let usage = connection.usage.clone();
+
+    let token = CancellationToken::new();
+    let limit = context.body_limits.resolve(req.uri().path(), req.headers());
+    Box::new(load_pooled_body(req, buffers.clone(), limit).map_err(ServerError::from).and_then(move |mut request| {
+        request.extensions_mut().insert(PeerAddr(peer_addr));
+        request.extensions_mut().insert(token.clone());
+        let in_flight = context_c.in_flight.begin();
+        let received = request.received_bytes();
+        usage.record_request(received);
+        request.extensions_mut().insert(usage.clone());
 
-            if let Next = middleware_stack_c.resolve(&request, &mut response) {
-                router_c.dispatch(&request, &mut response);
+        let handler_pool = context_c.handler_pool.clone();
+        let scheduler = context_c.scheduler.clone();
+        let process = move || {
+            let req_iat = Instant::now();
+            let response = context_c.process(&mut request);
+            if response.is_streamed() {
+                in_flight.streaming();
             }
 
-            let final_res = response.build_response().unwrap_or_else(|_| {
-                let empty: &[u8] = b"";
-                Response::new(empty.into())
+            let final_res = response.build_response().unwrap_or_else(|e| {
+                log_event(Level::Error, HANDLER_LOG_TARGET, "invalid response",
+                          format_args!("Unable to build the response to {} {}: {}", request.method(), request.uri().path(), e),
+                          &[("method", request.method()), ("path", &request.uri().path()), ("error", &e)]);
+                invalid_response(&request)
             });
 
             let resp_status = final_res.status();
+            let elapsed = req_iat.elapsed();
+            let duration_ms = (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9) * 1000.0;
 
-            let _ = tx.send(final_res);
+            if resp_status.is_server_error() {
+                log_event(Level::Warn, HANDLER_LOG_TARGET, "server error response",
+                          format_args!("{} {} was answered with {}", request.method(), request.uri().path(), resp_status),
+                          &[("method", request.method()), ("path", &request.uri().path()), ("status", &resp_status.as_u16())]);
+            }
 
-            let elapsed = req_iat.elapsed();
+            // Back in the pool before the client can send its next request on the connection
+            buffers.put(::std::mem::take(request.body_mut()));
+            // Logged once the body is sent, the number of bytes written being known by then
+            let head = response_head_size(resp_status, final_res.headers());
+            let response = ResponseBody::with_trailers(final_res).map(|body| body.on_end(move |bytes| {
+                // The request is in flight until its response is sent
+                drop(in_flight);
+                usage.record_response(head + bytes);
+                log_access(&request, resp_status, duration_ms, received, Some(bytes));
+            }));
+            let _ = tx.send(response);
+        };
 
-            use ansi_term::Colour::*;
+        match (scheduler, handler_pool) {
+            (Some(scheduler), _) => scheduler.submit(connection_id, peer_addr.ip(), process),
+            (None, Some(pool)) => pool.spawn_fn(move || {
+                process();
+                Ok::<(), ()>(())
+            }).forget(),
+            (None, None) => {
+                thread::spawn(process);
+            }
+        }
 
-            let status_str = resp_status.to_string();
+        CancelOnDrop::new(rx.map_err(ServerError::from), token)
+    }))
+}
 
-            let status = match resp_status.as_u16() {
-                0...199 => Cyan.paint(status_str),
-                200...299 => Green.paint(status_str),
-                400...599 => Red.paint(status_str),
-                _ => Yellow.paint(status_str),
-            };
+fn log_shutdown(_: ()) {
+    log_event(Level::Info, SERVER_LOG_TARGET, "shutting down",
+              format_args!("Saphir is shutting down, waiting for the connections in progress to complete"), &[]);
+}
 
-            info!("{} {} {} - {:.3}ms", request.method(), request.uri().path(), status, (elapsed.as_secs() as f64
-                + elapsed.subsec_nanos() as f64 * 1e-9) * 1000 as f64);
-        });
+fn log_stopped(addr: &SocketAddr) {
+    log_event(Level::Info, SERVER_LOG_TARGET, "stopped", format_args!("Saphir stopped listening on {}", addr), &[("addr", addr)]);
+}
 
-        rx.map_err(|e| ServerError::from(e))
-    }))
+fn log_server_error(e: ::hyper::Error) {
+    log_event(Level::Error, SERVER_LOG_TARGET, "server error", format_args!("server error: {}", e), &[("error", &e)]);
+}
+
+/// Log the access of `request`, received in `received` bytes and answered with `bytes` bytes of body
+fn log_access(request: &SyncRequest, status: StatusCode, duration_ms: f64, received: u64, bytes: Option<u64>) {
+    let bytes = bytes.map(|bytes| bytes.to_string()).unwrap_or_else(|| "-".to_string());
+
+    if log_format() == LogFormat::KeyValue {
+        let duration_ms = format!("{:.3}", duration_ms);
+        let client_ip = request.client_ip().map(|ip| ip.to_string()).unwrap_or_default();
+        return log_event(Level::Info, ACCESS_LOG_TARGET, "request processed", format_args!(""),
+                         &[("method", request.method()), ("path", &request.uri().path()), ("status", &status.as_u16()),
+                           ("duration_ms", &duration_ms), ("bytes", &bytes), ("bytes_received", &received), ("client_ip", &client_ip)]);
+    }
+
+    use ansi_term::Colour::*;
+
+    let status_str = status.to_string();
+
+    let status = match status.as_u16() {
+        0..=199 => Cyan.paint(status_str),
+        200..=299 => Green.paint(status_str),
+        400..=599 => Red.paint(status_str),
+        _ => Yellow.paint(status_str),
+    };
+
+    info!(target: ACCESS_LOG_TARGET, "{} {} {} {} - {:.3}ms, {} bytes received", request.method(), request.uri().path(), status, bytes,
+          duration_ms, received);
+}
+
+/// Pin the current thread to the `index`th cpu core, wrapping around the number of cores
+fn pin_current_thread(index: usize) {
+    let cores = ::core_affinity::get_core_ids().unwrap_or_default();
+
+    if !cores.is_empty() && !::core_affinity::set_for_current(cores[index % cores.len()]) {
+        warn!(target: SERVER_LOG_TARGET, "Unable to pin thread {:?} to a cpu core", thread::current().name());
+    }
+}
+
+/// Returns one listener per thread of a thread per core server: the `inherited` ones first, then listeners bound to `addr`
+/// with `SO_REUSEPORT` when the first one allows it, or clones of the first one otherwise. Every inherited listener is kept,
+/// even when there are more of them than `threads`, so none is closed along with its queued connections.
+fn thread_listeners(addr: &SocketAddr, mut listeners: Vec<TcpListener>, threads: usize) -> Result<Vec<TcpListener>, ServerError> {
+    if listeners.is_empty() {
+        listeners.push(bind_listener(addr, cfg!(unix))?);
+    }
+
+    #[cfg(unix)]
+    let reuse_port = ::socket2::SockRef::from(&listeners[0]).reuse_port()?;
+    #[cfg(not(unix))]
+    let reuse_port = false;
+
+    while listeners.len() < threads {
+        let listener = if reuse_port { bind_listener(addr, true)? } else { listeners[0].try_clone()? };
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
+}
+
+/// Bind a listener with `SO_REUSEADDR`, and `SO_REUSEPORT` when `reuse_port` is set, allowing other listeners to bind the same
+/// address
+fn bind_listener(addr: &SocketAddr, reuse_port: bool) -> Result<TcpListener, ServerError> {
+    use socket2::{Domain, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(reuse_port)?;
+    socket.bind(&(*addr).into())?;
+    socket.listen(1024)?;
+
+    Ok(socket.into())
 }
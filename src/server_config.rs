@@ -0,0 +1,493 @@
+use body_limits::BodyLimit;
+use body_limits::BodyLimits;
+use client_ip::TrustedProxies;
+use config_reload::ConfigError;
+use fairness::Fairness;
+use logging::LogFormat;
+use middleware::MiddlewareInfo;
+use profile::Profile;
+use server::Server;
+use server::ServerBuilder;
+use log::LevelFilter;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The settings of a server which deployments tune without recompiling, see `Server::from_config`
+///
+/// The configuration is read from a JSON, TOML (feature `toml`) or YAML (feature `yaml`) file, and every setting can be
+/// overridden with environment variables, see `ServerConfig::with_env`. Missing settings keep their default value,
+/// unknown ones are rejected so typos don't go unnoticed.
+///
+/// # Example
+///
+/// ```toml
+/// listen = "http://0.0.0.0:8080"
+/// worker_threads = 4
+/// trusted_proxies = ["10.0.0.0/8"]
+///
+/// [default_headers]
+/// server = "saphir"
+///
+/// [limits]
+/// max_body_size = 65536
+/// body_timeout_ms = 10000
+///
+/// [[limits.content_types]]
+/// content_type = "application/json"
+/// max_body_size = 1048576
+///
+/// [[limits.routes]]
+/// path = "^/videos$"
+/// max_body_size = 104857600
+/// buffered = false
+///
+/// [log]
+/// level = "info"
+/// format = "key_value"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The uri of the HTTP listener, passed to `Server::run`, `http://0.0.0.0:8080` by default
+    pub listen: String,
+    /// `dev` or `prod`, see `ServerBuilder::profile`
+    pub profile: Option<Profile>,
+    /// See `ServerBuilder::worker_threads`
+    pub worker_threads: Option<usize>,
+    /// See `ServerBuilder::handler_threads`
+    pub handler_threads: Option<usize>,
+    /// See `ServerBuilder::thread_name`
+    pub thread_name: Option<String>,
+    /// See `ServerBuilder::thread_per_core`
+    pub thread_per_core: bool,
+    /// See `ServerBuilder::pin_threads`
+    pub pin_threads: bool,
+    /// See `ServerBuilder::inherit_listener`
+    pub inherit_listener: bool,
+    /// See `ServerBuilder::problem_details`, enabled by default
+    pub problem_details: bool,
+    /// The networks trusted to report the address of the client, like `10.0.0.0/8`, see `ServerBuilder::trusted_proxies`
+    pub trusted_proxies: Vec<String>,
+    /// See `ServerBuilder::default_header`
+    pub default_headers: BTreeMap<String, String>,
+    /// See `ServerBuilder::body_limits`
+    pub limits: LimitsConfig,
+    /// How saphir logs
+    pub log: LogConfig,
+    /// The HTTP/3 listener, only available with the `http3` feature
+    pub http3: Option<Http3Section>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: "http://0.0.0.0:8080".to_string(),
+            profile: None,
+            worker_threads: None,
+            handler_threads: None,
+            thread_name: None,
+            thread_per_core: false,
+            pin_threads: false,
+            inherit_listener: false,
+            problem_details: true,
+            trusted_proxies: Vec::new(),
+            default_headers: BTreeMap::new(),
+            limits: LimitsConfig::default(),
+            log: LogConfig::default(),
+            http3: None,
+        }
+    }
+}
+
+/// The limits of the request bodies, see `BodyLimits`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum size of every request body, in bytes
+    pub max_body_size: Option<usize>,
+    /// How long every request body may take to be received, in milliseconds
+    pub body_timeout_ms: Option<u64>,
+    /// The limits of the requests of a content type, like `application/json` or `multipart/*`, in the `content_type` key
+    pub content_types: Vec<LimitConfig>,
+    /// The limits of the requests whose path matches the regex in the `path` key, the first matching one applies
+    pub routes: Vec<LimitConfig>,
+}
+
+/// The limits of the request bodies of a content type or of a route
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitConfig {
+    /// The content type the limits apply to, for the `content_types` limits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The regex of the paths the limits apply to, for the `routes` limits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// The maximum size of the request bodies, in bytes
+    pub max_body_size: Option<usize>,
+    /// How long the request bodies may take to be received, in milliseconds
+    pub body_timeout_ms: Option<u64>,
+    /// Whether the request bodies are loaded before the requests are dispatched, see `BodyLimit::buffered`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buffered: Option<bool>,
+}
+
+impl LimitConfig {
+    fn limit(&self) -> BodyLimit {
+        let mut limit = BodyLimit::new();
+        if let Some(max_size) = self.max_body_size {
+            limit = limit.max_size(max_size);
+        }
+        if let Some(timeout) = self.body_timeout_ms {
+            limit = limit.timeout(Duration::from_millis(timeout));
+        }
+        if let Some(buffered) = self.buffered {
+            limit = limit.buffered(buffered);
+        }
+        limit
+    }
+}
+
+/// How saphir logs
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// The maximum level of the log records, like `info` or `debug`, applied with `log::set_max_level`
+    pub level: Option<String>,
+    /// The format of the log records, `text` or `key_value`, see `LogFormat`
+    pub format: Option<LogFormat>,
+    /// See `ServerBuilder::log_routes`
+    pub log_routes: bool,
+}
+
+/// The HTTP/3 listener, see `Http3Config`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Http3Section {
+    /// The UDP address of the listener, like `0.0.0.0:443`
+    pub listen: String,
+    /// The path of the PEM encoded certificate chain
+    pub cert_chain: PathBuf,
+    /// The path of the PEM encoded private key
+    pub private_key: PathBuf,
+    /// For how long clients remember that the server speaks HTTP/3, in seconds
+    #[serde(default)]
+    pub alt_svc_max_age_secs: Option<u64>,
+}
+
+impl ServerConfig {
+    /// Parse a JSON configuration
+    pub fn from_json(content: &str) -> Result<Self, ConfigError> {
+        Ok(::serde_json::from_str(content)?)
+    }
+
+    /// Parse a TOML configuration
+    #[cfg(feature = "toml")]
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        ::toml::from_str(content).map_err(|e| ConfigError::Syntax(e.to_string()))
+    }
+
+    /// Parse a YAML configuration
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(content: &str) -> Result<Self, ConfigError> {
+        ::serde_yaml::from_str(content).map_err(|e| ConfigError::Syntax(e.to_string()))
+    }
+
+    /// Load the configuration file at `path`, whose format is given by its extension: `.json`, `.toml` or `.yaml`/`.yml`
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)?;
+
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("json") => ServerConfig::from_json(&content),
+            #[cfg(feature = "toml")]
+            Some("toml") => ServerConfig::from_toml(&content),
+            #[cfg(feature = "yaml")]
+            Some("yaml") | Some("yml") => ServerConfig::from_yaml(&content),
+            _ => Err(ConfigError::Syntax(format!("the format of {} is not supported", path.display()))),
+        }
+    }
+
+    /// Override the settings with the environment variables starting with `prefix` followed by `_`
+    ///
+    /// The rest of the name of a variable is the key of the setting in upper case, nested keys being separated by `__`:
+    /// with the `SAPHIR` prefix, `SAPHIR_WORKER_THREADS=8` sets `worker_threads` and `SAPHIR_LOG__LEVEL=debug` sets the
+    /// `level` of `log`. Values are read as JSON when they can be, like `SAPHIR_TRUSTED_PROXIES=["10.0.0.0/8"]`, and as
+    /// strings otherwise.
+    pub fn with_env(self, prefix: &str) -> Result<Self, ConfigError> {
+        let prefix = format!("{}_", prefix);
+        self.with_vars(env::vars().filter_map(|(name, value)| {
+            if name.starts_with(&prefix) {
+                Some((name[prefix.len()..].to_string(), value))
+            } else {
+                None
+            }
+        }))
+    }
+
+    fn with_vars<I: IntoIterator<Item=(String, String)>>(self, vars: I) -> Result<Self, ConfigError> {
+        let mut config = ::serde_json::to_value(&self)?;
+        let mut vars: Vec<(String, String)> = vars.into_iter().collect();
+        vars.sort();
+
+        for (name, raw) in vars {
+            let path: Vec<String> = name.split("__").map(|key| key.to_ascii_lowercase()).collect();
+            let value = ::serde_json::from_str(&raw).unwrap_or_else(|_| Value::String(raw.clone()));
+            let is_string = value.is_string();
+            set(&mut config, &path, value);
+
+            // A string setting whose value looks like a number, like a thread name
+            if !is_string && ::serde_json::from_value::<ServerConfig>(config.clone()).is_err() {
+                set(&mut config, &path, Value::String(raw));
+            }
+        }
+
+        Ok(::serde_json::from_value(config)?)
+    }
+}
+
+fn set(config: &mut Value, path: &[String], value: Value) {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    if !config.is_object() {
+        *config = Value::Object(Default::default());
+    }
+
+    if let Value::Object(ref mut map) = *config {
+        if rest.is_empty() {
+            map.insert(key.clone(), value);
+        } else {
+            set(map.entry(key.clone()).or_insert(Value::Null), rest, value);
+        }
+    }
+}
+
+impl Server {
+    /// Create a `ServerBuilder` configured with `config`, to which the router and the middlewares are then given. The
+    /// log level is applied right away.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// let config = ServerConfig::from_file("server.json").unwrap().with_env("SAPHIR").unwrap();
+    ///
+    /// let server = Server::from_config(&config).unwrap().router(Router::new()).build();
+    /// server.run(&config.listen).unwrap();
+    /// ```
+    pub fn from_config(config: &ServerConfig) -> Result<ServerBuilder, ConfigError> {
+        let mut builder = Server::builder().problem_details(config.problem_details);
+
+        if let Some(profile) = config.profile {
+            builder = builder.profile(profile);
+        }
+
+        if let Some(threads) = config.worker_threads {
+            builder = builder.worker_threads(threads);
+        }
+        if let Some(threads) = config.handler_threads {
+            builder = builder.handler_threads(threads);
+        }
+        if let Some(ref name) = config.thread_name {
+            builder = builder.thread_name(name.as_str());
+        }
+        if config.thread_per_core {
+            builder = builder.thread_per_core();
+        }
+        if config.pin_threads {
+            builder = builder.pin_threads();
+        }
+        if config.inherit_listener {
+            builder = builder.inherit_listener();
+        }
+
+        if !config.trusted_proxies.is_empty() {
+            let mut proxies = TrustedProxies::new();
+            for network in &config.trusted_proxies {
+                proxies = proxies.trust(network).map_err(|e| ConfigError::Invalid(format!("trusted proxy {}: {}", network, e)))?;
+            }
+            builder = builder.trusted_proxies(proxies);
+        }
+
+        for (name, value) in &config.default_headers {
+            let name = ::http_types::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| ConfigError::Invalid(format!("invalid default header name {}", name)))?;
+            let value = ::http_types::header::HeaderValue::from_str(value)
+                .map_err(|_| ConfigError::Invalid(format!("invalid value for the default header {}", name)))?;
+            builder = builder.default_header(name, value);
+        }
+
+        builder = builder.body_limits(config.limits.body_limits()?);
+
+        if let Some(format) = config.log.format {
+            builder = builder.log_format(format);
+        }
+        if config.log.log_routes {
+            builder = builder.log_routes();
+        }
+        if let Some(ref level) = config.log.level {
+            let level: LevelFilter = level.parse().map_err(|_| ConfigError::Invalid(format!("invalid log level {}", level)))?;
+            ::log::set_max_level(level);
+        }
+
+        if let Some(ref http3) = config.http3 {
+            builder = http3.apply(builder)?;
+        }
+
+        Ok(builder)
+    }
+}
+
+impl LimitsConfig {
+    fn body_limits(&self) -> Result<BodyLimits, ConfigError> {
+        let default = LimitConfig {
+            max_body_size: self.max_body_size,
+            body_timeout_ms: self.body_timeout_ms,
+            ..LimitConfig::default()
+        };
+        let mut limits = BodyLimits::new(default.limit());
+
+        for limit in &self.content_types {
+            let content_type = limit.content_type.as_ref()
+                .ok_or_else(|| ConfigError::Invalid("a content type limit has no content_type".to_string()))?;
+            limits = limits.content_type(content_type, limit.limit());
+        }
+
+        for limit in &self.routes {
+            let path = limit.path.as_ref().ok_or_else(|| ConfigError::Invalid("a route limit has no path".to_string()))?;
+            let path = ::regex::Regex::new(path).map_err(|e| ConfigError::Invalid(format!("route limit {}: {}", path, e)))?;
+            limits = limits.route(path, limit.limit());
+        }
+
+        Ok(limits)
+    }
+}
+
+impl Http3Section {
+    #[cfg(feature = "http3")]
+    fn apply(&self, builder: ServerBuilder) -> Result<ServerBuilder, ConfigError> {
+        let mut config = ::http3::Http3Config::new(&self.listen, fs::read(&self.cert_chain)?, fs::read(&self.private_key)?);
+        if let Some(max_age) = self.alt_svc_max_age_secs {
+            config = config.alt_svc_max_age(Duration::from_secs(max_age));
+        }
+
+        Ok(builder.http3(config))
+    }
+
+    #[cfg(not(feature = "http3"))]
+    fn apply(&self, _builder: ServerBuilder) -> Result<ServerBuilder, ConfigError> {
+        Err(ConfigError::Invalid("the http3 listener requires the http3 feature".to_string()))
+    }
+}
+
+/// The configuration a server actually runs with, once its builder, configuration file and defaults are resolved, see
+/// `Server::effective_config`
+///
+/// It tells why a route or a middleware isn't applied: the middlewares are listed in the order requests go through them,
+/// along with the paths they apply to. The configuration serializes to JSON for tooling, and displays as the summary
+/// logged at startup by `ServerBuilder::startup_banner`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EffectiveConfig {
+    /// The addresses the server listens on, like `http://0.0.0.0:8080`, empty until it runs
+    pub listeners: Vec<String>,
+    /// See `ServerBuilder::profile`, also read from the environment
+    pub profile: Option<Profile>,
+    /// The number of threads accepting connections and processing their io
+    pub worker_threads: usize,
+    /// The number of threads running middlewares and controllers, `None` for a new thread per request
+    pub handler_threads: Option<usize>,
+    /// How the handler threads are shared amongst the senders of requests, `None` in the order requests are received
+    pub fairness: Option<Fairness>,
+    /// See `ServerBuilder::thread_per_core`
+    pub thread_per_core: bool,
+    /// See `ServerBuilder::pin_threads`
+    pub pin_threads: bool,
+    /// See `ServerBuilder::inherit_listener`
+    pub inherit_listener: bool,
+    /// See `ServerBuilder::handover_on_sigusr2`
+    pub handover: bool,
+    /// See `ServerBuilder::problem_details`
+    pub problem_details: bool,
+    /// The networks trusted to report the address of the client, see `ServerBuilder::trusted_proxies`
+    pub trusted_proxies: Vec<String>,
+    /// The headers added to every response, see `ServerBuilder::default_header`
+    pub default_headers: BTreeMap<String, String>,
+    /// The limits of the request bodies, see `ServerBuilder::body_limits`
+    pub limits: LimitsConfig,
+    /// How often the draining of the connections is reported while shutting down, in milliseconds
+    pub drain_interval_ms: u64,
+    /// Whether the server can be put in maintenance, see `ServerBuilder::maintenance`
+    pub maintenance: bool,
+    /// Whether the phases of the requests are profiled, see `ServerBuilder::profiling`
+    pub profiling: bool,
+    /// The UDP address of the HTTP/3 listener, see `ServerBuilder::http3`
+    pub http3: Option<String>,
+    /// The middlewares, in the order requests go through them
+    pub middlewares: Vec<MiddlewareInfo>,
+    /// The number of routes of the router, including the ones of the debug and admin endpoints
+    pub route_count: usize,
+}
+
+impl fmt::Display for EffectiveConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let listeners = if self.listeners.is_empty() { "not listening yet".to_string() } else { self.listeners.join(", ") };
+        write!(f, "listeners: {}", listeners)?;
+        if let Some(ref http3) = self.http3 {
+            write!(f, ", HTTP/3 on udp {}", http3)?;
+        }
+
+        write!(f, "\nprofile: {}", self.profile.map_or("none".to_string(), |profile| profile.to_string()))?;
+        write!(f, "\nthreads: {} workers{}{}, ", self.worker_threads,
+               if self.thread_per_core { " (one per core)" } else { "" },
+               if self.pin_threads { " pinned" } else { "" })?;
+        match self.handler_threads {
+            Some(threads) => write!(f, "{} handlers", threads)?,
+            None => f.write_str("a handler thread per request")?,
+        }
+        if let Some(fairness) = self.fairness {
+            write!(f, " shared {}", fairness)?;
+        }
+
+        let limit = |max_size: Option<usize>, timeout: Option<u64>| format!("{} within {}",
+            max_size.map_or("unlimited".to_string(), |size| format!("{} bytes", size)),
+            timeout.map_or("unlimited time".to_string(), |timeout| format!("{}ms", timeout)));
+        write!(f, "\nbody limits: {} by default", limit(self.limits.max_body_size, self.limits.body_timeout_ms))?;
+        for content_type in &self.limits.content_types {
+            write!(f, ", {} for {}", limit(content_type.max_body_size, content_type.body_timeout_ms),
+                   content_type.content_type.as_deref().unwrap_or(""))?;
+        }
+        for route in &self.limits.routes {
+            write!(f, ", {} for {}", limit(route.max_body_size, route.body_timeout_ms), route.path.as_deref().unwrap_or(""))?;
+        }
+
+        if !self.trusted_proxies.is_empty() {
+            write!(f, "\ntrusted proxies: {}", self.trusted_proxies.join(", "))?;
+        }
+        if !self.default_headers.is_empty() {
+            let headers = self.default_headers.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+            write!(f, "\ndefault headers: {}", headers.join(", "))?;
+        }
+
+        write!(f, "\nmiddlewares:")?;
+        if self.middlewares.is_empty() {
+            f.write_str(" none")?;
+        }
+        for (position, middleware) in self.middlewares.iter().enumerate() {
+            write!(f, "\n  {}. {} on {}", position + 1, middleware.name, middleware.include_path.join(", "))?;
+            if !middleware.exclude_path.is_empty() {
+                write!(f, " except {}", middleware.exclude_path.join(", "))?;
+            }
+        }
+
+        write!(f, "\nroutes: {}", self.route_count)
+    }
+}
@@ -0,0 +1,188 @@
+use http::*;
+use controller::RequestGuard;
+use credentials::constant_time_eq;
+use credentials::hmac_sha256;
+use credentials::sha256;
+use credentials::to_hex;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use logging::*;
+use log::Level;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// A trait representing where the nonces of the signed requests already received are remembered, see
+/// `SignedRequestGuard::nonce_store`
+///
+/// Servers sharing a secret behind a load balancer must share their store, like a table of a database or a cache with
+/// expiring keys, for a request replayed to another server to be rejected as well.
+pub trait NonceStore: Send + Sync {
+    /// Remember `nonce` until `expires`, returning `false` when it was already remembered
+    fn insert(&self, nonce: &str, expires: SystemTime) -> bool;
+}
+
+/// A `NonceStore` remembering the nonces in memory, forgetting them once they expire
+#[derive(Default)]
+pub struct MemoryNonceStore {
+    nonces: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl MemoryNonceStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        MemoryNonceStore::default()
+    }
+}
+
+impl NonceStore for MemoryNonceStore {
+    fn insert(&self, nonce: &str, expires: SystemTime) -> bool {
+        let mut nonces = self.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        let now = SystemTime::now();
+        nonces.retain(|_, expires| *expires > now);
+
+        if nonces.contains_key(nonce) {
+            return false;
+        }
+        nonces.insert(nonce.to_string(), expires);
+        true
+    }
+}
+
+/// A guard rejecting with `401 Unauthorized` the requests which aren't signed with a shared secret, or which are replayed,
+/// like the deliveries received by a webhook endpoint
+///
+/// A signed request carries the time it was signed at, in seconds since the Unix epoch, in `X-Signature-Timestamp`, a
+/// unique nonce in `X-Signature-Nonce`, and in `X-Signature` the hexadecimal HMAC-SHA256, keyed with the secret, of the
+/// lines:
+///
+/// ```text
+/// <timestamp>
+/// <nonce>
+/// <METHOD>
+/// <path and query>
+/// <hexadecimal SHA-256 of the body>
+/// ```
+///
+/// joined with `\n`, as computed by `signature`. A request signed more than the tolerance away from the time it is
+/// received, 5 minutes by default, is rejected, as is a nonce which was already received within the tolerance: a captured
+/// request can't be replayed, nor its body tampered with.
+///
+/// The guard can also be applied to a `MiddlewareStack`, to protect every route under a path.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use std::time::Duration;
+/// let guard = SignedRequestGuard::new(b"whsec_5WbX5kEWLlfzsGNjH64I8lOOqUB6e8FH").tolerance(Duration::from_secs(60));
+///
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::POST, "^/hooks/payments$", guard.into(), |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+/// ```
+pub struct SignedRequestGuard {
+    secret: Vec<u8>,
+    tolerance: Duration,
+    nonces: Box<dyn NonceStore>,
+    signature_header: String,
+    timestamp_header: String,
+    nonce_header: String,
+}
+
+impl SignedRequestGuard {
+    /// Create a guard verifying the requests signed with `secret`, remembering their nonces in memory
+    pub fn new(secret: &[u8]) -> Self {
+        SignedRequestGuard {
+            secret: secret.to_vec(),
+            tolerance: Duration::from_secs(300),
+            nonces: Box::new(MemoryNonceStore::new()),
+            signature_header: "x-signature".to_string(),
+            timestamp_header: "x-signature-timestamp".to_string(),
+            nonce_header: "x-signature-nonce".to_string(),
+        }
+    }
+
+    /// Accept the requests signed at most `tolerance` before or after they are received, to allow for clock skew and
+    /// network delays
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Remember the nonces in `store` rather than in memory
+    pub fn nonce_store<S: 'static + NonceStore>(mut self, store: S) -> Self {
+        self.nonces = Box::new(store);
+        self
+    }
+
+    /// Read the signature, the timestamp and the nonce from the headers `signature`, `timestamp` and `nonce`
+    pub fn headers(mut self, signature: &str, timestamp: &str, nonce: &str) -> Self {
+        self.signature_header = signature.to_ascii_lowercase();
+        self.timestamp_header = timestamp.to_ascii_lowercase();
+        self.nonce_header = nonce.to_ascii_lowercase();
+        self
+    }
+
+    /// Returns the hexadecimal signature of the request of `method` to `path_and_query` with `body`, signed at `timestamp`
+    /// with `nonce`, as sent by the clients in `X-Signature`
+    pub fn signature(&self, method: &Method, path_and_query: &str, timestamp: u64, nonce: &str, body: &[u8]) -> String {
+        let payload = format!("{}\n{}\n{}\n{}\n{}", timestamp, nonce, method, path_and_query, to_hex(&sha256(body)));
+        to_hex(&hmac_sha256(&self.secret, payload.as_bytes()))
+    }
+
+    /// Returns why `req` isn't a valid signed request, `None` when it is
+    fn rejection(&self, req: &SyncRequest) -> Option<&'static str> {
+        let header = |name: &str| req.headers_map().get(name).and_then(|value| value.to_str().ok()).map(|value| value.trim());
+        let (signature, timestamp, nonce) = match (header(&self.signature_header), header(&self.timestamp_header), header(&self.nonce_header)) {
+            (Some(signature), Some(timestamp), Some(nonce)) if !nonce.is_empty() => (signature, timestamp, nonce),
+            _ => return Some("the signature, its timestamp or its nonce is missing"),
+        };
+
+        let signed_at = match timestamp.parse::<u64>() {
+            Ok(timestamp) => UNIX_EPOCH + Duration::from_secs(timestamp),
+            Err(_) => return Some("the timestamp is invalid"),
+        };
+
+        let now = SystemTime::now();
+        let skew = now.duration_since(signed_at).or_else(|_| signed_at.duration_since(now)).unwrap_or_default();
+        if skew > self.tolerance {
+            return Some("the request was signed outside of the tolerance");
+        }
+
+        let path = req.uri().path_and_query().map(|path| path.as_str()).unwrap_or_else(|| req.uri().path());
+        let expected = self.signature(req.method(), path, signed_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0), nonce, req.body());
+        if !constant_time_eq(expected.as_bytes(), signature.to_ascii_lowercase().as_bytes()) {
+            return Some("the signature doesn't match");
+        }
+
+        // Only remembered once the signature is valid, so forged requests can't burn the nonces of legitimate ones
+        if !self.nonces.insert(nonce, signed_at + self.tolerance) {
+            return Some("the nonce was already received");
+        }
+
+        None
+    }
+}
+
+impl RequestGuard for SignedRequestGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.rejection(req) {
+            Some(reason) => {
+                log_event(Level::Warn, GUARD_LOG_TARGET, "signed request rejected",
+                          format_args!("Rejected the signed request {} {}: {}", req.method(), req.uri().path(), reason),
+                          &[("method", req.method()), ("path", &req.uri().path()), ("reason", &reason)]);
+                res.status(StatusCode::UNAUTHORIZED);
+                RequestContinuation::None
+            }
+            None => RequestContinuation::Next,
+        }
+    }
+}
+
+impl Middleware for SignedRequestGuard {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        self.validate(req, res)
+    }
+}
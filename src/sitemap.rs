@@ -0,0 +1,265 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use form::html_escape;
+use route_index::literal_path;
+use route_index::path_template;
+use router::Router;
+use std::fmt::Write;
+
+/// How often the page of a route is likely to change, a hint given to crawlers by the sitemap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeFrequency {
+    /// The page changes every time it is requested
+    Always,
+    /// The page changes about every hour
+    Hourly,
+    /// The page changes about every day
+    Daily,
+    /// The page changes about every week
+    Weekly,
+    /// The page changes about every month
+    Monthly,
+    /// The page changes about every year
+    Yearly,
+    /// The page is archived
+    Never,
+}
+
+impl ChangeFrequency {
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeFrequency::Always => "always",
+            ChangeFrequency::Hourly => "hourly",
+            ChangeFrequency::Daily => "daily",
+            ChangeFrequency::Weekly => "weekly",
+            ChangeFrequency::Monthly => "monthly",
+            ChangeFrequency::Yearly => "yearly",
+            ChangeFrequency::Never => "never",
+        }
+    }
+}
+
+/// How a route is listed in the sitemap and in `robots.txt`, given to its delegate with `ControllerDispatch::sitemap`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SitemapEntry {
+    excluded: bool,
+    disallowed: bool,
+    change_frequency: Option<ChangeFrequency>,
+    priority: Option<f32>,
+    paths: Vec<String>,
+}
+
+impl SitemapEntry {
+    /// Create an entry listing the route in the sitemap
+    pub fn new() -> Self {
+        SitemapEntry::default()
+    }
+
+    /// Create an entry leaving the route out of the sitemap
+    pub fn excluded() -> Self {
+        SitemapEntry { excluded: true, ..SitemapEntry::default() }
+    }
+
+    /// Create an entry leaving the route out of the sitemap and asking crawlers not to crawl it in `robots.txt`
+    pub fn disallowed() -> Self {
+        SitemapEntry { excluded: true, disallowed: true, ..SitemapEntry::default() }
+    }
+
+    /// Set how often the page is likely to change
+    pub fn change_frequency(mut self, change_frequency: ChangeFrequency) -> Self {
+        self.change_frequency = Some(change_frequency);
+        self
+    }
+
+    /// Set the priority of the page relative to the other pages of the site, from 0.0 to 1.0, 0.5 when unset
+    pub fn priority(mut self, priority: f32) -> Self {
+        self.priority = Some(priority.clamp(0.0, 1.0));
+        self
+    }
+
+    /// List `path` in place of the route, for routes with path parameters like the page of each product
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+}
+
+/// A generator of the `sitemap.xml` and the `robots.txt` of a site, from the routes of a `Router`
+///
+/// Every `GET` route matching a single path is listed in the sitemap, unless it is annotated otherwise with
+/// `ControllerDispatch::sitemap`, and routes with path parameters are listed by the paths given to their `SitemapEntry`.
+/// Routes restricted to a host are left out, the sitemap being the one of the host of its base URL. Since they are
+/// generated from the routing table, the documents don't list pages which can't be reached.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut products = BasicController::new(());
+/// products.add(Method::GET, "^/products$", |_, _, res| { res.status(StatusCode::OK); });
+/// products.add(Method::GET, "^/products/([a-z-]+)$", |_, _, res| { res.status(StatusCode::OK); });
+/// products.sitemap(Method::GET, "^/products/([a-z-]+)$", SitemapEntry::new().path("/products/saphir").path("/products/ruby"));
+///
+/// let mut admin = BasicController::new(());
+/// admin.add(Method::GET, "^/admin$", |_, _, res| { res.status(StatusCode::OK); });
+/// admin.sitemap(Method::GET, "^/admin$", SitemapEntry::disallowed());
+///
+/// let mut router = Router::new();
+/// router.add("^/products", products);
+/// router.add("^/admin", admin);
+///
+/// let sitemap = Sitemap::new("https://example.com").controller(&router);
+/// router.add("^/(robots\\.txt|sitemap\\.xml)$", sitemap);
+/// ```
+pub struct Sitemap {
+    base_url: String,
+    annotated_only: bool,
+    disallow: Vec<String>,
+}
+
+impl Sitemap {
+    /// Create a generator of the documents of the site at `base_url`, like `https://example.com`
+    pub fn new(base_url: &str) -> Self {
+        Sitemap {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            annotated_only: false,
+            disallow: Vec::new(),
+        }
+    }
+
+    /// Only list the routes annotated with a `SitemapEntry` in the sitemap
+    pub fn annotated_only(mut self) -> Self {
+        self.annotated_only = true;
+        self
+    }
+
+    /// Ask crawlers not to crawl the paths starting with `path` in `robots.txt`, besides the disallowed routes
+    pub fn disallow<P: Into<String>>(mut self, path: P) -> Self {
+        self.disallow.push(path.into());
+        self
+    }
+
+    /// Returns the pages of `routes` listed in the sitemap, along with their entry
+    fn pages(&self, routes: &[RouteInfo]) -> Vec<(String, SitemapEntry)> {
+        let mut pages: Vec<(String, SitemapEntry)> = Vec::new();
+
+        for route in routes.iter().filter(|route| route.method == Some(Method::GET) && route.host.is_none()) {
+            let entry = match route.sitemap {
+                Some(ref entry) if entry.excluded => continue,
+                Some(ref entry) => entry.clone(),
+                None if self.annotated_only => continue,
+                None => SitemapEntry::new(),
+            };
+
+            let paths = match (entry.paths.is_empty(), route.pattern.as_ref().and_then(|pattern| literal_path(pattern))) {
+                (false, _) => entry.paths.clone(),
+                (true, Some((path, true))) => vec![path.to_string()],
+                _ => continue,
+            };
+
+            for path in paths {
+                if !pages.iter().any(|page| page.0 == path) {
+                    pages.push((path, entry.clone()));
+                }
+            }
+        }
+
+        pages
+    }
+
+    /// Generate the `sitemap.xml` of the routes of `router`
+    pub fn xml(&self, router: &Router) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+
+        for (path, entry) in self.pages(&router.routes()) {
+            let _ = write!(xml, "  <url>\n    <loc>{}</loc>\n", html_escape(&format!("{}{}", self.base_url, path)));
+            if let Some(change_frequency) = entry.change_frequency {
+                let _ = writeln!(xml, "    <changefreq>{}</changefreq>", change_frequency.as_str());
+            }
+            if let Some(priority) = entry.priority {
+                let _ = writeln!(xml, "    <priority>{:.1}</priority>", priority);
+            }
+            xml.push_str("  </url>\n");
+        }
+
+        xml.push_str("</urlset>\n");
+        xml
+    }
+
+    /// Generate the `robots.txt` of the routes of `router`, pointing crawlers to the sitemap
+    pub fn robots(&self, router: &Router) -> String {
+        let mut disallowed = self.disallow.clone();
+
+        for route in router.routes() {
+            let (entry, pattern) = match (route.sitemap, route.pattern) {
+                (Some(entry), Some(pattern)) if entry.disallowed => (entry, pattern),
+                _ => continue,
+            };
+
+            // Paths with parameters are disallowed up to their first parameter
+            let paths = if entry.paths.is_empty() {
+                let (template, _) = path_template(&pattern);
+                vec![template.split('{').next().unwrap_or("/").to_string()]
+            } else {
+                entry.paths
+            };
+
+            for path in paths {
+                if !disallowed.contains(&path) {
+                    disallowed.push(path);
+                }
+            }
+        }
+
+        let mut robots = String::from("User-agent: *\n");
+        if disallowed.is_empty() {
+            robots.push_str("Disallow:\n");
+        }
+        for path in disallowed {
+            let _ = writeln!(robots, "Disallow: {}", path);
+        }
+
+        let _ = write!(robots, "\nSitemap: {}/sitemap.xml\n", self.base_url);
+        robots
+    }
+
+    /// Generate the documents of the routes of `router` and create a controller serving them, see `SitemapController`
+    pub fn controller(&self, router: &Router) -> SitemapController {
+        SitemapController {
+            sitemap: self.xml(router),
+            robots: self.robots(router),
+        }
+    }
+}
+
+/// A controller serving a `robots.txt` and a `sitemap.xml`, as `<route>/robots.txt` and `<route>/sitemap.xml`
+pub struct SitemapController {
+    sitemap: String,
+    robots: String,
+}
+
+impl Controller for SitemapController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if *req.method() != Method::GET && *req.method() != Method::HEAD {
+            res.status(StatusCode::METHOD_NOT_ALLOWED);
+            return;
+        }
+
+        let path = req.uri().path();
+        if path.ends_with("/sitemap.xml") {
+            res.status(StatusCode::OK).header(header::CONTENT_TYPE, "application/xml; charset=utf-8").body(self.sitemap.clone());
+        } else if path.ends_with("/robots.txt") {
+            res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain; charset=utf-8").body(self.robots.clone());
+        } else {
+            res.status(StatusCode::NOT_FOUND);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        ["/robots.txt$", "/sitemap.xml$"].iter().flat_map(|path| {
+            vec![Method::GET, Method::HEAD].into_iter()
+                .map(move |method| RouteInfo::new("SitemapController", Some(method), Some(path.to_string()), Vec::new()))
+        }).collect()
+    }
+}
@@ -0,0 +1,170 @@
+use http::*;
+use controller::Controller;
+use controller::RouteInfo;
+use etag::fnv1a;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// The delegate of a `SplitController` a request was sent to, inserted in the extensions of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitVariant {
+    /// The current implementation
+    Stable,
+    /// The new implementation being rolled out
+    Canary,
+}
+
+#[derive(Clone)]
+enum SplitRule {
+    Header(String, String),
+    Cookie(String, String),
+    Feature(String),
+}
+
+impl SplitRule {
+    fn matches(&self, req: &SyncRequest) -> bool {
+        match *self {
+            SplitRule::Header(ref name, ref value) => {
+                req.headers_map().get_all(name.as_str()).iter().any(|v| v.to_str().ok() == Some(value.as_str()))
+            }
+            SplitRule::Cookie(ref name, ref value) => req.cookie(name) == Some(value.as_str()),
+            SplitRule::Feature(ref flag) => req.feature(flag),
+        }
+    }
+}
+
+/// A controller splitting the traffic of a path between two delegates, the stable implementation of the routes and a canary
+/// one, for gradual rollouts
+///
+/// Requests matching one of the header, cookie or feature flag rules, like the ones of testers, always go to the canary.
+/// The others go to the canary by percentage: a client is always sent to the same delegate for a given percentage, as long
+/// as it keeps the same sticky cookie, see `sticky_cookie`, or the same address when there is none. The delegate a request
+/// was sent to is inserted in the extensions of the response as a `SplitVariant`.
+///
+/// A `SplitController` is a cheap handle which can be cloned, a clone is typically kept by the application to change the
+/// percentage while the server is running.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut stable = BasicController::new(());
+/// stable.add(Method::GET, "^/search$", |_, _, res| { res.status(StatusCode::OK).body("stable"); });
+/// let mut canary = BasicController::new(());
+/// canary.add(Method::GET, "^/search$", |_, _, res| { res.status(StatusCode::OK).body("canary"); });
+///
+/// let split = SplitController::new(stable, canary)
+///     .percentage(5)
+///     .header("x-canary", "always")
+///     .sticky_cookie("session");
+///
+/// let mut router = Router::new();
+/// router.add("^/search", split.clone());
+///
+/// // Later on, while the server is running
+/// split.set_percentage(50);
+/// ```
+#[derive(Clone)]
+pub struct SplitController {
+    stable: Arc<dyn Controller>,
+    canary: Arc<dyn Controller>,
+    percentage: Arc<AtomicUsize>,
+    rules: Vec<SplitRule>,
+    sticky_cookie: Option<String>,
+    counter: Arc<AtomicUsize>,
+}
+
+impl SplitController {
+    /// Split the traffic between `stable` and `canary`, sending everything to `stable` until a percentage or a rule is set
+    pub fn new<S: 'static + Controller, C: 'static + Controller>(stable: S, canary: C) -> Self {
+        SplitController {
+            stable: Arc::new(stable),
+            canary: Arc::new(canary),
+            percentage: Arc::new(AtomicUsize::new(0)),
+            rules: Vec::new(),
+            sticky_cookie: None,
+            counter: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Send `percentage` percent of the clients to the canary
+    pub fn percentage(self, percentage: u8) -> Self {
+        self.set_percentage(percentage);
+        self
+    }
+
+    /// Send the requests whose header `name` is `value` to the canary
+    pub fn header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.rules.push(SplitRule::Header(name.into().to_lowercase(), value.into()));
+        self
+    }
+
+    /// Send the requests whose cookie `name` is `value` to the canary
+    pub fn cookie<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.rules.push(SplitRule::Cookie(name.into(), value.into()));
+        self
+    }
+
+    /// Send the requests the feature `flag` is enabled for to the canary, see `SyncRequest::feature`
+    pub fn feature<S: Into<String>>(mut self, flag: S) -> Self {
+        self.rules.push(SplitRule::Feature(flag.into()));
+        self
+    }
+
+    /// Keep the clients with the same value of the cookie `name`, like a session, on the same delegate, rather than the
+    /// clients with the same address
+    pub fn sticky_cookie<S: Into<String>>(mut self, name: S) -> Self {
+        self.sticky_cookie = Some(name.into());
+        self
+    }
+
+    /// Change the percentage of the clients sent to the canary, capped at 100
+    pub fn set_percentage(&self, percentage: u8) {
+        self.percentage.store(percentage.min(100) as usize, Ordering::Relaxed);
+    }
+
+    /// Returns the percentage of the clients sent to the canary
+    pub fn get_percentage(&self) -> u8 {
+        self.percentage.load(Ordering::Relaxed) as u8
+    }
+
+    /// Returns the delegate `req` is sent to
+    pub fn variant(&self, req: &SyncRequest) -> SplitVariant {
+        if self.rules.iter().any(|rule| rule.matches(req)) {
+            return SplitVariant::Canary;
+        }
+
+        let percentage = self.percentage.load(Ordering::Relaxed);
+        if percentage == 0 {
+            return SplitVariant::Stable;
+        }
+
+        let sticky = self.sticky_cookie.as_ref().and_then(|name| req.cookie(name)).map(|value| value.to_string())
+            .or_else(|| req.client_ip().map(|ip| ip.to_string()));
+        let bucket = match sticky {
+            Some(sticky) => (fnv1a(sticky.as_bytes()) % 100) as usize,
+            None => self.counter.fetch_add(1, Ordering::Relaxed) % 100,
+        };
+
+        if bucket < percentage { SplitVariant::Canary } else { SplitVariant::Stable }
+    }
+}
+
+impl Controller for SplitController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let variant = self.variant(req);
+        res.extension(variant);
+
+        match variant {
+            SplitVariant::Stable => self.stable.handle(req, res),
+            SplitVariant::Canary => self.canary.handle(req, res),
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes = self.stable.routes();
+        routes.extend(self.canary.routes());
+        routes
+    }
+}
@@ -0,0 +1,175 @@
+use http::*;
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+
+/// A type map holding the application states registered on the server, see `ServerBuilder::state`
+#[derive(Default)]
+pub struct StateMap {
+    states: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl StateMap {
+    /// Create an empty state map
+    pub fn new() -> Self {
+        StateMap {
+            states: HashMap::new(),
+        }
+    }
+
+    /// Insert a state, replacing any previous state of the same type
+    pub fn insert<T: Any + Send + Sync>(&mut self, state: T) {
+        self.states.insert(TypeId::of::<T>(), Box::new(state));
+    }
+
+    /// Returns the state of type `T`, if any
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.states.get(&TypeId::of::<T>()).and_then(|s| s.downcast_ref::<T>())
+    }
+
+    /// Returns whether or not a state of type `T` was registered
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.states.contains_key(&TypeId::of::<T>())
+    }
+}
+
+/// Handle to the states of the server, inserted in the extensions of every request
+#[derive(Clone)]
+pub struct SharedState(pub Arc<StateMap>);
+
+impl SyncRequest {
+    /// Returns the application state of type `T` registered on the server builder, or for the tenant of the request when
+    /// `TenantMiddleware` found one which has its own
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// struct AppState {
+    ///     name: String,
+    /// }
+    ///
+    /// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let state = req.state::<AppState>().expect("AppState is registered on the server");
+    ///     res.status(StatusCode::OK).body(state.name.clone());
+    /// }
+    ///
+    /// let server = Server::builder()
+    ///     .state(AppState { name: "saphir".to_string() })
+    ///     .build();
+    /// ```
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.tenant().and_then(|tenant| tenant.state::<T>())
+            .or_else(|| self.extensions().get::<SharedState>().and_then(|s| s.0.get::<T>()))
+    }
+
+    /// Returns every application state registered on the server builder
+    pub fn states(&self) -> Option<&StateMap> {
+        self.extensions().get::<SharedState>().map(|s| &*s.0)
+    }
+}
+
+/// A value shared between the requests of a controller, or of the whole server, which handlers may mutate
+///
+/// Controllers only hand their context out as `&T`, a `State` gives it interior mutability behind a read-write lock:
+/// many requests may read the value at once, while a write waits for them and excludes everyone else. A `State` is a
+/// cheap handle, clones share the same value. Keep guards for the shortest time, and never take a write guard while
+/// holding another guard of the same state, which would deadlock. A handler panicking while holding a guard doesn't make
+/// the value unusable, the next requests see it as the handler left it.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// #[derive(Default)]
+/// struct Counters {
+///     visits: u64,
+/// }
+///
+/// let mut controller = BasicController::new(State::new(Counters::default()));
+/// controller.add(Method::POST, "^/visit$", |state, _, res| {
+///     let visits = state.update(|counters| {
+///         counters.visits += 1;
+///         counters.visits
+///     });
+///     res.status(StatusCode::OK).body(visits.to_string());
+/// });
+/// controller.add(Method::GET, "^/visits$", |state, _, res| {
+///     res.status(StatusCode::OK).body(state.read().visits.to_string());
+/// });
+/// ```
+pub struct State<T> {
+    inner: Arc<RwLock<T>>,
+}
+
+impl<T> State<T> {
+    /// Share `value`
+    pub fn new(value: T) -> Self {
+        State {
+            inner: Arc::new(RwLock::new(value)),
+        }
+    }
+
+    /// Lock the value for reading, waiting for the write in progress if any
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Lock the value for writing, waiting for the reads and the write in progress if any
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the result of `f` invoked on the value, locked for reading during the call
+    pub fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&self.read())
+    }
+
+    /// Returns the result of `f` invoked on the value, locked for writing during the call
+    pub fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut self.write())
+    }
+
+    /// Replace the value, returning the previous one
+    pub fn replace(&self, value: T) -> T {
+        ::std::mem::replace(&mut *self.write(), value)
+    }
+}
+
+impl<T: Clone> State<T> {
+    /// Returns a copy of the value
+    pub fn get(&self) -> T {
+        self.read().clone()
+    }
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: Default> Default for State<T> {
+    fn default() -> Self {
+        State::new(T::default())
+    }
+}
+
+impl<T> From<T> for State<T> {
+    fn from(value: T) -> Self {
+        State::new(value)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for State<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("State").field("value", &*self.read()).finish()
+    }
+}
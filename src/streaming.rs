@@ -0,0 +1,191 @@
+use http::*;
+use cancellation::CancellationToken;
+use futures::Async;
+use futures::Poll;
+use futures::Sink;
+use futures::Stream;
+use futures::sink::Wait;
+use futures::sync::mpsc;
+use hyper::Chunk;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Weak;
+
+/// How many chunks a `BodySender` may send ahead of the client before blocking
+pub const STREAM_CAPACITY: usize = 16;
+
+/// Marks a response whose body is streamed through a `BodySender`
+struct Streamed;
+
+/// The error returned by `BodySender::send` once the client is gone, nothing more can be streamed to it
+#[derive(Debug)]
+pub struct StreamClosed;
+
+impl fmt::Display for StreamClosed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The response stream is closed")
+    }
+}
+
+impl Error for StreamClosed {
+    fn description(&self) -> &str {
+        "The response stream is closed"
+    }
+}
+
+/// The sending half of a streamed response body, returned by `SyncResponse::stream`
+///
+/// Every chunk is sent to the client as soon as the response head is, in a chunk of a `Transfer-Encoding: chunked` body on
+/// HTTP/1.1 or in a `DATA` frame on HTTP/2. The body ends when the sender is dropped. Once `STREAM_CAPACITY` chunks are
+/// waiting for the client, `send` blocks until it catches up.
+pub struct BodySender {
+    sink: Wait<mpsc::Sender<Chunk>>,
+    probe: mpsc::Sender<Chunk>,
+}
+
+impl BodySender {
+    /// Send `data` as the next chunk of the body, blocking while the client is too far behind
+    pub fn send<D: Into<Chunk>>(&mut self, data: D) -> Result<(), StreamClosed> {
+        let chunk = data.into();
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        self.sink.send(chunk).map_err(|_| StreamClosed)
+    }
+
+    /// Returns true once the client is gone, without sending anything, for producers waiting for something to send
+    pub fn is_closed(&self) -> bool {
+        self.probe.is_closed()
+    }
+}
+
+impl io::Write for BodySender {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.send(buf.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush().map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))
+    }
+}
+
+impl fmt::Debug for BodySender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BodySender").finish()
+    }
+}
+
+impl SyncResponse {
+    /// Stream the body of the response instead of setting it up front, its length doesn't need to be known
+    ///
+    /// The body is sent with `Transfer-Encoding: chunked` on HTTP/1.1, and in `DATA` frames on HTTP/2, any `Content-Length`
+    /// header is removed. The response head is sent once the handler returns, chunks sent before then wait for it and `send`
+    /// blocks when there are more than `STREAM_CAPACITY` of them, so the sender is typically moved to a thread producing the
+    /// body while the handler returns. Middlewares reading the body with
+    /// `get_body` wait for the stream to end, and the response is then sent with the whole body.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use std::thread;
+    /// fn handler(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let mut sender = res.status(StatusCode::OK).stream();
+    ///     thread::spawn(move || {
+    ///         for line in 0..100 {
+    ///             if sender.send(format!("line {}\n", line)).is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     });
+    /// }
+    /// ```
+    pub fn stream(&mut self) -> BodySender {
+        let (sender, receiver) = mpsc::channel(STREAM_CAPACITY);
+
+        let token = self.get_extensions().get::<CancellationToken>().cloned();
+        self.headers_map_mut().remove(header::CONTENT_LENGTH);
+        self.body(StreamedBody {
+            stream: Mutex::new(Some(receiver)),
+            received: Arc::new(Mutex::new(Vec::new())),
+            token,
+        }).extension(Streamed);
+
+        BodySender {
+            probe: sender.clone(),
+            sink: sender.wait(),
+        }
+    }
+
+    /// Returns true when the body of the response is streamed with `stream`
+    pub fn is_streamed(&self) -> bool {
+        self.get_extensions().get::<Streamed>().is_some()
+    }
+}
+
+/// A body streamed from a `BodySender`, keeping a copy of the chunks read while it is still part of a response, so that
+/// the response can be built after a middleware read its body
+struct StreamedBody {
+    stream: Mutex<Option<mpsc::Receiver<Chunk>>>,
+    received: Arc<Mutex<Vec<u8>>>,
+    token: Option<CancellationToken>,
+}
+
+impl ToBody for StreamedBody {
+    fn to_body(&self) -> Body {
+        match self.stream.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(receiver) => Body::wrap_stream(ReceiverStream {
+                receiver,
+                received: Arc::downgrade(&self.received),
+                token: self.token.clone(),
+                ended: false,
+            }),
+            None => self.received.lock().unwrap_or_else(|e| e.into_inner()).clone().into(),
+        }
+    }
+}
+
+/// The chunks sent by a `BodySender`, copied into `received` until the body they came from is dropped
+///
+/// The stream being dropped before it ended means the client is gone, which disconnects the token of the request.
+struct ReceiverStream {
+    receiver: mpsc::Receiver<Chunk>,
+    received: Weak<Mutex<Vec<u8>>>,
+    token: Option<CancellationToken>,
+    ended: bool,
+}
+
+impl Stream for ReceiverStream {
+    type Item = Chunk;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, io::Error> {
+        // The receiver never fails, its senders being gone ends the stream
+        let chunk = match self.receiver.poll() {
+            Ok(Async::Ready(chunk)) => chunk,
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Err(()) => None,
+        };
+
+        self.ended = chunk.is_none();
+
+        if let (Some(chunk), Some(received)) = (chunk.as_ref(), self.received.upgrade()) {
+            received.lock().unwrap_or_else(|e| e.into_inner()).extend_from_slice(chunk);
+        }
+
+        Ok(Async::Ready(chunk))
+    }
+}
+
+impl Drop for ReceiverStream {
+    fn drop(&mut self) {
+        if let (false, Some(token)) = (self.ended, self.token.as_ref()) {
+            token.disconnect();
+        }
+    }
+}
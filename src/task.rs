@@ -0,0 +1,97 @@
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use futures::future::Shared;
+use futures::sync::mpsc::unbounded;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::sync::mpsc::UnboundedSender;
+use std::io;
+use std::thread;
+use tokio::runtime::current_thread::Runtime as CurrentThreadRuntime;
+
+/// A background task waiting for the server to start, see `Server::spawn_task`
+pub type PendingTask = Box<dyn FnOnce() -> Box<dyn Future<Item=(), Error=()> + Send> + Send>;
+
+/// Run `tasks` on a thread of their own until they complete, or until `shutdown` completes, cancelling the remaining tasks
+pub fn run_tasks<S>(tasks: Vec<PendingTask>, shutdown: Shared<S>, name: String) -> io::Result<thread::JoinHandle<()>>
+    where S: 'static + Future + Send, S::Item: Send + Sync, S::Error: Send + Sync {
+    thread::Builder::new().name(name).spawn(move || {
+        let mut runtime = match CurrentThreadRuntime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => return error!("Unable to start the runtime of the background tasks: {}", e),
+        };
+
+        for task in tasks {
+            let task = task().select(shutdown.clone().then(|_| Ok(()))).map(|_| ()).map_err(|_| ());
+            runtime.spawn(task);
+        }
+
+        let _ = runtime.run();
+    })
+}
+
+/// Create a queue to hand work over to a background task, from controllers, guards or middlewares
+///
+/// The sender is registered as a state of the server, and the queue consumed by a task spawned with `Server::spawn_task`.
+/// Work sent before the server starts is queued until the task consumes it.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate futures;
+/// # extern crate saphir;
+/// # use saphir::*;
+/// use futures::Stream;
+///
+/// let (sender, queue) = task_queue::<String>();
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::POST, "^/emails$", |_, req, res| {
+///     let address = String::from_utf8_lossy(req.body()).into_owned();
+///     match req.state::<TaskSender<String>>().map(|sender| sender.send(address)) {
+///         Some(Ok(())) => res.status(StatusCode::ACCEPTED),
+///         _ => res.status(StatusCode::SERVICE_UNAVAILABLE),
+///     };
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/", controller);
+///
+/// let server = Server::builder().router(router).state(sender).build();
+/// server.spawn_task(move || queue.for_each(|address| {
+///     println!("sending an email to {}", address);
+///     Ok(())
+/// }));
+/// ```
+pub fn task_queue<T>() -> (TaskSender<T>, TaskQueue<T>) {
+    let (sender, receiver) = unbounded();
+    (TaskSender(sender), TaskQueue(receiver))
+}
+
+/// The sending half of a `task_queue`
+pub struct TaskSender<T>(UnboundedSender<T>);
+
+impl<T> Clone for TaskSender<T> {
+    fn clone(&self) -> Self {
+        TaskSender(self.0.clone())
+    }
+}
+
+impl<T> TaskSender<T> {
+    /// Queue `work` for the task, or give it back when the task is gone, like after the server shut down
+    pub fn send(&self, work: T) -> Result<(), T> {
+        self.0.unbounded_send(work).map_err(|e| e.into_inner())
+    }
+}
+
+/// The receiving half of a `task_queue`, a stream of the work sent to the task
+pub struct TaskQueue<T>(UnboundedReceiver<T>);
+
+impl<T> Stream for TaskQueue<T> {
+    type Item = T;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        self.0.poll()
+    }
+}
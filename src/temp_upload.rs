@@ -0,0 +1,245 @@
+use http::*;
+use credentials::random_token;
+use credentials::to_hex;
+use problem::Problem;
+use sha2::Digest;
+use sha2::Sha256;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Where uploads are spooled while they are received, and how large they may grow, see `SyncRequest::spool_body`
+///
+/// Uploads are written to a temporary file as they are read, hashing them on the way, so handlers of large multipart parts,
+/// completed tus uploads or streamed bodies never hold them in memory. The file is removed when the upload fails or is
+/// dropped without being persisted.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// fn upload(uploads: &TempUploads, req: &SyncRequest, res: &mut SyncResponse) {
+///     match req.spool_body(uploads) {
+///         Ok(upload) => {
+///             let digest = upload.sha256();
+///             upload.persist_content_addressed("/var/lib/blobs").unwrap();
+///             res.status(StatusCode::CREATED).header(header::LOCATION, format!("/blobs/{}", digest));
+///         }
+///         Err(e) => { res.problem(&e.problem()); }
+///     }
+/// }
+///
+/// let mut controller = BasicController::new(TempUploads::new().max_size(1024 * 1024 * 1024));
+/// controller.add(Method::POST, "^/blobs$", upload);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TempUploads {
+    dir: PathBuf,
+    max_size: Option<u64>,
+}
+
+impl TempUploads {
+    /// Spool uploads of any size to the temporary directory of the system
+    pub fn new() -> Self {
+        TempUploads {
+            dir: env::temp_dir(),
+            max_size: None,
+        }
+    }
+
+    /// Spool uploads to `dir` rather than the temporary directory of the system, preferably on the file system they are
+    /// persisted to so they are moved rather than copied
+    pub fn dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Reject uploads larger than `bytes` with `UploadError::TooLarge`, as soon as they grow past it
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Spool the content of `reader` to a temporary file. The file is removed when reading or writing fails.
+    pub fn receive<R: Read>(&self, mut reader: R) -> Result<TempUpload, UploadError> {
+        let path = self.dir.join(format!("saphir-upload-{}.part", random_token()));
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&path).map_err(UploadError::Storage)?;
+        // Dropping the upload on the way out of an error removes the file
+        let mut upload = TempUpload { path, len: 0, sha256: [0; 32], persisted: false };
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // A streamed body fails once it grows past the limits of the server
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => return Err(UploadError::TooLarge),
+                Err(e) => return Err(UploadError::Body(e)),
+            };
+
+            upload.len += read as u64;
+            if self.max_size.is_some_and(|max_size| upload.len > max_size) {
+                return Err(UploadError::TooLarge);
+            }
+
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read]).map_err(UploadError::Storage)?;
+        }
+
+        file.sync_all().map_err(UploadError::Storage)?;
+        upload.sha256 = hasher.finalize().into();
+        Ok(upload)
+    }
+}
+
+impl Default for TempUploads {
+    fn default() -> Self {
+        TempUploads::new()
+    }
+}
+
+/// An upload spooled to a temporary file, see `TempUploads`
+///
+/// The file is removed once the upload is dropped, unless it was persisted.
+#[derive(Debug)]
+pub struct TempUpload {
+    path: PathBuf,
+    len: u64,
+    sha256: [u8; 32],
+    persisted: bool,
+}
+
+impl TempUpload {
+    /// Returns the path of the temporary file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the size of the upload, in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true when the upload is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the SHA-256 digest of the upload, in lowercase hexadecimal
+    pub fn sha256(&self) -> String {
+        to_hex(&self.sha256)
+    }
+
+    /// Move the upload to `destination`, replacing the file there if any, and returns its path
+    pub fn persist<P: AsRef<Path>>(mut self, destination: P) -> io::Result<PathBuf> {
+        let destination = destination.as_ref().to_path_buf();
+        if fs::rename(&self.path, &destination).is_err() {
+            // The temporary directory may be on another file system
+            fs::copy(&self.path, &destination)?;
+            let _ = fs::remove_file(&self.path);
+        }
+
+        self.persisted = true;
+        Ok(destination)
+    }
+
+    /// Move the upload to `dir`, named after its SHA-256 digest, and returns its path. An identical upload persisted before
+    /// is kept as it is, the new one being discarded.
+    pub fn persist_content_addressed<P: AsRef<Path>>(self, dir: P) -> io::Result<PathBuf> {
+        let destination = dir.as_ref().join(self.sha256());
+        if destination.exists() {
+            self.discard();
+            return Ok(destination);
+        }
+
+        self.persist(destination)
+    }
+
+    /// Remove the temporary file right away, as dropping the upload does
+    pub fn discard(self) {}
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Why an upload couldn't be spooled, see `TempUploads`
+#[derive(Debug)]
+pub enum UploadError {
+    /// The upload is larger than the maximum size
+    TooLarge,
+    /// The upload couldn't be read from the client
+    Body(io::Error),
+    /// The temporary file couldn't be written
+    Storage(io::Error),
+}
+
+impl UploadError {
+    /// Returns the status answering the request, `413 Payload Too Large` for an upload too large, `400 Bad Request` for one
+    /// which couldn't be read and `500 Internal Server Error` when the temporary file couldn't be written
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            UploadError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::Body(_) => StatusCode::BAD_REQUEST,
+            UploadError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Describe the error as a problem document of its status, without the details of storage failures
+    pub fn problem(&self) -> Problem {
+        match *self {
+            UploadError::Storage(_) => Problem::new(self.status()),
+            _ => Problem::new(self.status()).with_detail(self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UploadError::TooLarge => f.write_str("The upload exceeds the maximum size"),
+            UploadError::Body(ref e) => write!(f, "The upload couldn't be received: {}", e),
+            UploadError::Storage(ref e) => write!(f, "The upload couldn't be stored: {}", e),
+        }
+    }
+}
+
+impl Error for UploadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            UploadError::TooLarge => None,
+            UploadError::Body(ref e) | UploadError::Storage(ref e) => Some(e),
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Spool the body of the request to a temporary file of `uploads`, see `TempUploads`. Bodies announcing a
+    /// `Content-Length` larger than the maximum size are rejected before a single byte is read.
+    ///
+    /// Bodies left unread by the server, see `BodyLimit::buffered`, are read from the connection as they are written.
+    pub fn spool_body(&self, uploads: &TempUploads) -> Result<TempUpload, UploadError> {
+        let announced = self.headers_map().get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if let (Some(announced), Some(max_size)) = (announced, uploads.max_size) {
+            if announced > max_size {
+                return Err(UploadError::TooLarge);
+            }
+        }
+
+        uploads.receive(self.body_reader())
+    }
+}
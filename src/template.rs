@@ -0,0 +1,261 @@
+use http::*;
+use serde::Serialize;
+use serde_json::Value;
+use csrf::CsrfToken;
+use i18n::Locale;
+use problem::Problem;
+use profile::Profile;
+use error_page::DeveloperError;
+use assets::AssetManifest;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+/// An error raised while rendering a template
+#[derive(Debug, Clone)]
+pub struct TemplateError {
+    message: String,
+    causes: Vec<String>,
+}
+
+impl TemplateError {
+    /// Create a new template error
+    pub fn new<M: Into<String>>(message: M) -> Self {
+        TemplateError {
+            message: message.into(),
+            causes: Vec::new(),
+        }
+    }
+
+    /// Create a template error from `error`, keeping the messages of the errors which caused it
+    pub fn from_error(error: &dyn Error) -> Self {
+        let mut causes = Vec::new();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+
+        TemplateError {
+            message: error.to_string(),
+            causes,
+        }
+    }
+
+    /// Returns the messages of the errors which caused this one, from the closest to the root cause
+    pub fn causes(&self) -> &[String] {
+        &self.causes
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for TemplateError {}
+
+/// A trait to plug a template engine into the server, see `ServerBuilder::template_engine`
+pub trait TemplateEngine: Send + Sync {
+    /// Render the template `name` using `context`
+    fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError>;
+
+    /// Set whether templates are reloaded from disk before every render, called when the server is built with a `Profile`.
+    /// Engines which can't reload their templates ignore it.
+    fn hot_reload(&mut self, _enabled: bool) {}
+
+    /// Give the templates an `asset_url` helper returning the URL of an asset fingerprinted according to `manifest`, called
+    /// when the server is built with an `AssetManifest`. Engines without helpers ignore it.
+    fn asset_manifest(&mut self, _manifest: &AssetManifest) {}
+}
+
+/// Handle to the template engine registered on the server, inserted in the extensions of every response
+#[derive(Clone)]
+pub struct RegisteredTemplateEngine(pub Arc<dyn TemplateEngine>);
+
+impl SyncResponse {
+    /// Render the template `name` with the template engine registered on the server, and set the result as the body of the
+    /// response along with the `text/html; charset=utf-8` content type.
+    ///
+    /// If no engine is registered, or if the template cannot be rendered, the response is turned into an empty
+    /// `500 Internal Server Error`, or into a description of the error under the development `Profile`, see
+    /// `DeveloperError`. When `LocaleMiddleware` selected a locale, it is added to object contexts as their `locale` member,
+    /// unless they already have one. The same goes for the token of `CsrfMiddleware`, as their `csrf_token` member.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).render("index.html", &vec!["a", "b"]);
+    /// }
+    /// ```
+    pub fn render<T: Serialize>(&mut self, name: &str, context: &T) -> &mut SyncResponse {
+        let engine = match self.get_extensions().get::<RegisteredTemplateEngine>() {
+            Some(engine) => engine.clone(),
+            None => {
+                error!("Unable to render template {}: no template engine registered on the server", name);
+                return self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+            }
+        };
+
+        let locale = self.get_extensions().get::<Locale>().map(|locale| locale.0.clone());
+        let csrf_token = self.get_extensions().get::<CsrfToken>().map(|csrf| csrf.token.clone());
+        let rendered = ::serde_json::to_value(context)
+            .map_err(|e| TemplateError::new(e.to_string()))
+            .map(|mut context| {
+                if let Some(object) = context.as_object_mut() {
+                    if let Some(locale) = locale {
+                        object.entry("locale").or_insert(Value::String(locale));
+                    }
+                    if let Some(csrf_token) = csrf_token {
+                        object.entry("csrf_token").or_insert(Value::String(csrf_token));
+                    }
+                }
+                context
+            })
+            .and_then(|context| engine.0.render(name, &context));
+
+        match rendered {
+            Ok(body) => {
+                self.header(header::CONTENT_TYPE, "text/html; charset=utf-8").body(body)
+            }
+            Err(e) => {
+                error!("Unable to render template {}: {}", name, e);
+                if self.profile() == Some(Profile::Development) {
+                    let mut chain = vec![format!("Unable to render template {}", name), e.to_string()];
+                    chain.extend(e.causes().iter().cloned());
+                    let problem = Problem::new(StatusCode::INTERNAL_SERVER_ERROR).with_detail(chain.join(": "));
+                    return self.problem(&problem).extension(DeveloperError::new(chain));
+                }
+                self.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tera")]
+mod tera_engine {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+    use tera::Context;
+    use tera::Tera;
+
+    /// A `TemplateEngine` backed by Tera. In debug builds, or under the development `Profile`, templates are reloaded from
+    /// disk before every render.
+    pub struct TeraEngine {
+        tera: RwLock<Tera>,
+        hot_reload: bool,
+    }
+
+    impl TeraEngine {
+        /// Load every template matching `glob`, e.g. `templates/**/*.html`
+        pub fn new(glob: &str) -> Result<Self, TemplateError> {
+            Tera::new(glob).map(TeraEngine::from).map_err(|e| TemplateError::from_error(&e))
+        }
+    }
+
+    impl From<Tera> for TeraEngine {
+        fn from(tera: Tera) -> Self {
+            TeraEngine {
+                tera: RwLock::new(tera),
+                hot_reload: cfg!(debug_assertions),
+            }
+        }
+    }
+
+    impl TemplateEngine for TeraEngine {
+        fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError> {
+            if self.hot_reload {
+                if let Err(e) = self.tera.write().unwrap().full_reload() {
+                    warn!("Unable to reload tera templates: {}", e);
+                }
+            }
+
+            let context = Context::from_value(context.clone()).map_err(|e| TemplateError::from_error(&e))?;
+            self.tera.read().unwrap().render(name, &context).map_err(|e| TemplateError::from_error(&e))
+        }
+
+        fn hot_reload(&mut self, enabled: bool) {
+            self.hot_reload = enabled;
+        }
+
+        /// Register the `asset_url` function, called with the name of the asset as its `name` argument:
+        /// `{{ asset_url(name="app.css") }}`
+        fn asset_manifest(&mut self, manifest: &AssetManifest) {
+            let manifest = manifest.clone();
+            self.tera.write().unwrap().register_function("asset_url", move |args: &HashMap<String, Value>| {
+                match args.get("name").and_then(|name| name.as_str()) {
+                    Some(name) => Ok(Value::String(manifest.url(name))),
+                    None => Err(::tera::Error::msg("asset_url expects the name of an asset as its name argument")),
+                }
+            });
+        }
+    }
+}
+
+#[cfg(feature = "tera")]
+pub use self::tera_engine::TeraEngine;
+
+#[cfg(feature = "handlebars")]
+mod handlebars_engine {
+    use super::*;
+    use handlebars::Context;
+    use handlebars::Handlebars;
+    use handlebars::Helper;
+    use handlebars::HelperDef;
+    use handlebars::RenderContext;
+    use handlebars::RenderError;
+    use handlebars::RenderErrorReason;
+    use handlebars::ScopedJson;
+
+    /// A `TemplateEngine` backed by Handlebars. In debug builds, or under the development `Profile`, dev mode is enabled so
+    /// templates registered from files are reloaded from disk before every render.
+    pub struct HandlebarsEngine {
+        handlebars: Handlebars<'static>,
+    }
+
+    impl HandlebarsEngine {
+        /// Create an engine rendering the templates registered on `handlebars`
+        pub fn new(mut handlebars: Handlebars<'static>) -> Self {
+            if cfg!(debug_assertions) {
+                handlebars.set_dev_mode(true);
+            }
+
+            HandlebarsEngine {
+                handlebars,
+            }
+        }
+    }
+
+    impl TemplateEngine for HandlebarsEngine {
+        fn render(&self, name: &str, context: &Value) -> Result<String, TemplateError> {
+            self.handlebars.render(name, context).map_err(|e| TemplateError::from_error(&e))
+        }
+
+        fn hot_reload(&mut self, enabled: bool) {
+            self.handlebars.set_dev_mode(enabled);
+        }
+
+        /// Register the `asset_url` helper, called with the name of the asset: `{{asset_url "app.css"}}`
+        fn asset_manifest(&mut self, manifest: &AssetManifest) {
+            self.handlebars.register_helper("asset_url", Box::new(AssetUrlHelper(manifest.clone())));
+        }
+    }
+
+    struct AssetUrlHelper(AssetManifest);
+
+    impl HelperDef for AssetUrlHelper {
+        fn call_inner<'reg: 'rc, 'rc>(&self, h: &Helper<'rc>, _: &'reg Handlebars<'reg>, _: &'rc Context,
+                                      _: &mut RenderContext<'reg, 'rc>) -> Result<ScopedJson<'rc>, RenderError> {
+            let name = h.param(0).and_then(|param| param.value().as_str())
+                .ok_or(RenderErrorReason::ParamNotFoundForIndex("asset_url", 0))?;
+            Ok(ScopedJson::Derived(Value::String(self.0.url(name))))
+        }
+    }
+}
+
+#[cfg(feature = "handlebars")]
+pub use self::handlebars_engine::HandlebarsEngine;
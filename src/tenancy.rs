@@ -0,0 +1,230 @@
+use http::*;
+use middleware::Middleware;
+use rewrite::with_query;
+use state::StateMap;
+use utils::RequestContinuation;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The tenant a request is addressed to, attached to the request extensions by `TenantMiddleware`, see
+/// `SyncRequest::tenant`
+#[derive(Clone)]
+pub struct Tenant {
+    id: String,
+    states: Option<Arc<StateMap>>,
+}
+
+impl Tenant {
+    /// Returns the identifier of the tenant, as found by the `TenantResolver`
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the state of type `T` registered for the tenant, if any
+    pub fn state<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.states.as_ref().and_then(|states| states.get::<T>())
+    }
+}
+
+/// A trait for the ways `TenantMiddleware` finds the tenant a request is addressed to
+///
+/// The trait is implemented by `SubdomainTenant`, `HeaderTenant` and `PathPrefixTenant`, and by any function taking a
+/// request and returning the identifier of its tenant.
+pub trait TenantResolver: Send + Sync {
+    /// Find the identifier of the tenant `req` is addressed to, `None` if it can't be told
+    fn resolve(&self, req: &SyncRequest) -> Option<String>;
+
+    /// Returns the path the following middlewares and the router see once `tenant` was found for a request to `path`,
+    /// `None` to leave the path untouched, which is the default
+    fn routed_path(&self, _path: &str, _tenant: &str) -> Option<String> {
+        None
+    }
+}
+
+impl<F> TenantResolver for F where F: Fn(&SyncRequest) -> Option<String> + Send + Sync {
+    fn resolve(&self, req: &SyncRequest) -> Option<String> {
+        (*self)(req)
+    }
+}
+
+/// Find the tenant in the subdomain of the host of the request, like `acme` in `acme.example.com`
+pub struct SubdomainTenant {
+    domain: String,
+}
+
+impl SubdomainTenant {
+    /// Find the tenant in the first label of the hosts directly under `domain`, like `example.com`
+    pub fn new(domain: &str) -> Self {
+        SubdomainTenant {
+            domain: format!(".{}", domain.trim_start_matches('.').to_ascii_lowercase()),
+        }
+    }
+}
+
+impl TenantResolver for SubdomainTenant {
+    fn resolve(&self, req: &SyncRequest) -> Option<String> {
+        let host = req.host()?.trim_end_matches('.').to_ascii_lowercase();
+        let tenant = host.strip_suffix(self.domain.as_str())?;
+
+        if tenant.is_empty() || tenant.contains('.') { None } else { Some(tenant.to_string()) }
+    }
+}
+
+/// Find the tenant in a header of the request, like `X-Tenant-Id`
+pub struct HeaderTenant {
+    name: header::HeaderName,
+}
+
+impl HeaderTenant {
+    /// Find the tenant in the header `name`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn new(name: &str) -> Self {
+        HeaderTenant {
+            name: header::HeaderName::from_bytes(name.as_bytes()).expect("valid tenant header name"),
+        }
+    }
+}
+
+impl TenantResolver for HeaderTenant {
+    fn resolve(&self, req: &SyncRequest) -> Option<String> {
+        let tenant = req.headers_map().get(&self.name)?.to_str().ok()?.trim();
+
+        if tenant.is_empty() { None } else { Some(tenant.to_string()) }
+    }
+}
+
+/// Find the tenant in the first segment of the path of the request, like `acme` in `/acme/users`, which is removed from
+/// the path the router sees, so routes are declared once for every tenant
+#[derive(Default)]
+pub struct PathPrefixTenant;
+
+impl PathPrefixTenant {
+    /// Find the tenant in the first segment of the path
+    pub fn new() -> Self {
+        PathPrefixTenant
+    }
+}
+
+impl TenantResolver for PathPrefixTenant {
+    fn resolve(&self, req: &SyncRequest) -> Option<String> {
+        let segment = req.uri().path().trim_start_matches('/').split('/').next()?;
+
+        if segment.is_empty() { None } else { Some(segment.to_string()) }
+    }
+
+    fn routed_path(&self, path: &str, tenant: &str) -> Option<String> {
+        let rest = path.strip_prefix('/')?.strip_prefix(tenant)?;
+
+        match rest {
+            "" => Some("/".to_string()),
+            _ if rest.starts_with('/') => Some(rest.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A middleware finding the tenant every request is addressed to with a `TenantResolver`, and attaching it to the
+/// request extensions, see `SyncRequest::tenant`
+///
+/// States can be registered for each tenant, like its database pool or its limits: `SyncRequest::state` returns the
+/// state of the tenant of the request over the one of the same type registered on the server, so handlers written for
+/// a single tenant serve them all. Once tenants are registered, requests addressed to other tenants are treated as if
+/// no tenant was found. Those are handed to the following middlewares without a tenant, unless the tenant is `required`,
+/// in which case they are answered with `404 Not Found`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// struct Database {
+///     url: String,
+/// }
+///
+/// let mut acme = StateMap::new();
+/// acme.insert(Database { url: "postgres://db/acme".to_string() });
+///
+/// let tenancy = TenantMiddleware::new(SubdomainTenant::new("example.com"))
+///     .tenant("acme", acme)
+///     .required();
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(tenancy, vec!("/"), None);
+/// ```
+pub struct TenantMiddleware {
+    resolver: Box<dyn TenantResolver>,
+    tenants: HashMap<String, Arc<StateMap>>,
+    required: bool,
+}
+
+impl TenantMiddleware {
+    /// Create a middleware finding the tenant of requests with `resolver`
+    pub fn new<R: 'static + TenantResolver>(resolver: R) -> Self {
+        TenantMiddleware {
+            resolver: Box::new(resolver),
+            tenants: HashMap::new(),
+            required: false,
+        }
+    }
+
+    /// Register the tenant `id` along with its `states`
+    pub fn tenant(mut self, id: &str, states: StateMap) -> Self {
+        self.tenants.insert(id.to_string(), Arc::new(states));
+        self
+    }
+
+    /// Answer the requests whose tenant isn't found with `404 Not Found`
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn find(&self, req: &SyncRequest) -> Option<Tenant> {
+        let id = self.resolver.resolve(req)?;
+
+        if self.tenants.is_empty() {
+            return Some(Tenant { id, states: None });
+        }
+
+        let states = self.tenants.get(&id)?.clone();
+        Some(Tenant { id, states: Some(states) })
+    }
+}
+
+impl Middleware for TenantMiddleware {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        let tenant = match self.find(req) {
+            Some(tenant) => tenant,
+            None if self.required => {
+                res.status(StatusCode::NOT_FOUND);
+                return RequestContinuation::None;
+            }
+            None => return RequestContinuation::Next,
+        };
+
+        if let Some(path) = self.resolver.routed_path(req.uri().path(), tenant.id()) {
+            let target = with_query(path, req.uri());
+            match target.parse() {
+                Ok(uri) => *req.uri_mut() = uri,
+                Err(e) => warn!("Unable to route {} to {}: {}", req.uri(), target, e),
+            }
+        }
+
+        req.extensions_mut().insert(tenant);
+        RequestContinuation::Next
+    }
+}
+
+impl SyncRequest {
+    /// Returns the tenant `TenantMiddleware` found for the request
+    pub fn tenant(&self) -> Option<&Tenant> {
+        self.extensions().get::<Tenant>()
+    }
+}
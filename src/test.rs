@@ -0,0 +1,299 @@
+//! Utilities to test an application without binding a socket
+
+use http::*;
+use http_types::request::Builder as RequestBuilder;
+use http_types::HttpTryFrom;
+use server::Server;
+use serde::Serialize;
+use std::net::SocketAddr;
+
+pub use server::TestServer;
+
+/// A client sending requests to a `Server` in process, through its whole middleware, router and controller pipeline
+///
+/// Requests are processed synchronously on the calling thread and the `SyncResponse` computed for them is returned as is, so
+/// its status, headers and body can be inspected directly.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use saphir::test::TestClient;
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::GET, "^/hello$", |_, _, res| { res.status(StatusCode::OK).body("world"); });
+///
+/// let mut router = Router::new();
+/// router.add("^/", controller);
+///
+/// let client = TestClient::new(Server::builder().router(router).build());
+/// let res = client.get("/hello").header("accept", "text/plain").send();
+///
+/// assert_eq!(res.get_status(), StatusCode::OK);
+/// assert_eq!(res.get_body(), b"world");
+/// ```
+pub struct TestClient {
+    server: Server,
+}
+
+impl TestClient {
+    /// Create a client sending its requests to `server`
+    pub fn new(server: Server) -> Self {
+        TestClient {
+            server,
+        }
+    }
+
+    /// Returns the server the requests are sent to
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Start building a request with `method` towards `uri`
+    pub fn request<'a>(&'a self, method: Method, uri: &str) -> TestRequest<'a> {
+        TestRequest {
+            client: self,
+            request: MockRequest::new(method, uri),
+        }
+    }
+
+    /// Start building a `GET` request towards `uri`
+    pub fn get<'a>(&'a self, uri: &str) -> TestRequest<'a> {
+        self.request(Method::GET, uri)
+    }
+
+    /// Start building a `POST` request towards `uri`
+    pub fn post<'a>(&'a self, uri: &str) -> TestRequest<'a> {
+        self.request(Method::POST, uri)
+    }
+
+    /// Start building a `PUT` request towards `uri`
+    pub fn put<'a>(&'a self, uri: &str) -> TestRequest<'a> {
+        self.request(Method::PUT, uri)
+    }
+
+    /// Start building a `PATCH` request towards `uri`
+    pub fn patch<'a>(&'a self, uri: &str) -> TestRequest<'a> {
+        self.request(Method::PATCH, uri)
+    }
+
+    /// Start building a `DELETE` request towards `uri`
+    pub fn delete<'a>(&'a self, uri: &str) -> TestRequest<'a> {
+        self.request(Method::DELETE, uri)
+    }
+}
+
+/// A builder of `SyncRequest` values, to unit test request guards and middlewares without a server
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// # use saphir::test::MockRequest;
+/// # use saphir::test::MockResponse;
+/// let req = MockRequest::get("/admin").header("authorization", "Bearer token").build();
+/// let mut res = MockResponse::new().header("x-request-id", "42").build();
+///
+/// match SecurityHeadersMiddleware::new().resolve(&req, &mut res) {
+///     RequestContinuation::Next => assert!(res.headers_map().contains_key("x-request-id")),
+///     RequestContinuation::None => panic!("the request was stopped"),
+/// }
+/// ```
+pub struct MockRequest {
+    builder: RequestBuilder,
+    body: Vec<u8>,
+    peer_addr: SocketAddr,
+}
+
+impl MockRequest {
+    /// Start building a request with `method` towards `uri`
+    pub fn new(method: Method, uri: &str) -> Self {
+        let mut builder = Request::builder();
+        builder.method(method).uri(uri);
+
+        MockRequest {
+            builder,
+            body: Vec::new(),
+            peer_addr: ([127, 0, 0, 1], 0).into(),
+        }
+    }
+
+    /// Start building a `GET` request towards `uri`
+    pub fn get(uri: &str) -> Self {
+        Self::new(Method::GET, uri)
+    }
+
+    /// Start building a `POST` request towards `uri`
+    pub fn post(uri: &str) -> Self {
+        Self::new(Method::POST, uri)
+    }
+
+    /// Set the HTTP version of the request, `HTTP/1.1` by default
+    pub fn version(mut self, version: Version) -> Self {
+        self.builder.version(version);
+        self
+    }
+
+    /// Append a header to the request
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        self.builder.header(key, value);
+        self
+    }
+
+    /// Set the body of the request
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Set the body of the request to the JSON representation of `value`, along with its `Content-Type`
+    pub fn json<T: Serialize>(mut self, value: &T) -> Self {
+        self.body = ::serde_json::to_vec(value).expect("the value can't be serialized to JSON");
+        self.header(header::CONTENT_TYPE, "application/json")
+    }
+
+    /// Set the address the request is received from, `127.0.0.1:0` by default
+    pub fn peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.peer_addr = addr;
+        self
+    }
+
+    /// Insert a value in the extensions of the request, like a middleware running before the tested code would
+    pub fn extension<T: 'static + Send + Sync>(mut self, extension: T) -> Self {
+        self.builder.extension(extension);
+        self
+    }
+
+    /// Returns the request as it would be received by the server
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method, uri or a header of the request is invalid.
+    pub fn build(mut self) -> SyncRequest {
+        let (parts, _) = self.builder.body(()).expect("invalid test request").into_parts();
+        let mut request = SyncRequest::new(parts, self.body);
+        request.extensions_mut().insert(PeerAddr(self.peer_addr));
+        request
+    }
+}
+
+/// A builder of `SyncResponse` values, to unit test code receiving a response already altered by a controller or middleware
+pub struct MockResponse {
+    response: SyncResponse,
+}
+
+impl MockResponse {
+    /// Start building an empty response
+    pub fn new() -> Self {
+        MockResponse {
+            response: SyncResponse::new(),
+        }
+    }
+
+    /// Set the status code of the response
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.response.status(status);
+        self
+    }
+
+    /// Append a header to the response
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        self.response.header(key, value);
+        self
+    }
+
+    /// Set the body of the response
+    pub fn body<B: 'static + ToBody>(mut self, body: B) -> Self {
+        self.response.body(body);
+        self
+    }
+
+    /// Insert a value in the extensions of the response
+    pub fn extension<T: 'static + Send + Sync>(mut self, extension: T) -> Self {
+        self.response.extension(extension);
+        self
+    }
+
+    /// Returns the response
+    pub fn build(self) -> SyncResponse {
+        self.response
+    }
+}
+
+impl Default for MockResponse {
+    fn default() -> Self {
+        MockResponse::new()
+    }
+}
+
+/// A request being built by a `TestClient`
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    request: MockRequest,
+}
+
+impl<'a> TestRequest<'a> {
+    /// Append a header to the request
+    pub fn header<K, V>(mut self, key: K, value: V) -> Self
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        self.request = self.request.header(key, value);
+        self
+    }
+
+    /// Set the HTTP version of the request, `HTTP/1.1` by default
+    pub fn version(mut self, version: Version) -> Self {
+        self.request = self.request.version(version);
+        self
+    }
+
+    /// Set the body of the request
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.request = self.request.body(body);
+        self
+    }
+
+    /// Set the body of the request to the JSON representation of `value`, along with its `Content-Type`
+    pub fn json<T: Serialize>(mut self, value: &T) -> Self {
+        self.request = self.request.json(value);
+        self
+    }
+
+    /// Set the address the request is received from, `127.0.0.1:0` by default
+    pub fn peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.request = self.request.peer_addr(addr);
+        self
+    }
+
+    /// Insert a value in the extensions of the request, like a middleware running before the tested ones would
+    pub fn extension<T: 'static + Send + Sync>(mut self, extension: T) -> Self {
+        self.request = self.request.extension(extension);
+        self
+    }
+
+    /// Returns the request as it would be received by the server
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method, uri or a header of the request is invalid.
+    pub fn build(self) -> SyncRequest {
+        self.request.build()
+    }
+
+    /// Process the request and return its response
+    ///
+    /// # Panics
+    ///
+    /// Panics if the method, uri or a header of the request is invalid.
+    pub fn send(self) -> SyncResponse {
+        let client = self.client;
+        let mut request = self.build();
+        client.server.process(&mut request)
+    }
+}
@@ -0,0 +1,234 @@
+use http::*;
+use client_ip::SharedTrustedProxies;
+use controller::RequestGuard;
+use problem::Problem;
+use utils::RequestContinuation;
+use std::fmt;
+use std::str::FromStr;
+
+/// The header a trusted TLS terminating proxy reports the negotiated protocol version in, like nginx's `$ssl_protocol`
+pub const TLS_VERSION_HEADER: &str = "x-ssl-protocol";
+/// The header a trusted TLS terminating proxy reports the negotiated cipher suite in, like nginx's `$ssl_cipher`
+pub const TLS_CIPHER_HEADER: &str = "x-ssl-cipher";
+/// The header a trusted TLS terminating proxy reports the SNI server name in, like nginx's `$ssl_server_name`
+pub const TLS_SERVER_NAME_HEADER: &str = "x-ssl-server-name";
+/// The header a trusted TLS terminating proxy reports whether the TLS session was resumed in, `r` when it was, like nginx's
+/// `$ssl_session_reused`
+pub const TLS_SESSION_REUSED_HEADER: &str = "x-ssl-session-reused";
+/// The header a proxy flags the requests it received as TLS 1.3 early data with, set to `1`, see RFC 8470
+pub const EARLY_DATA_HEADER: &str = "early-data";
+
+/// A version of the SSL/TLS protocol, ordered from the oldest to the most recent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    /// SSL 3.0
+    Ssl3,
+    /// TLS 1.0
+    Tls10,
+    /// TLS 1.1
+    Tls11,
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+impl fmt::Display for TlsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TlsVersion::Ssl3 => "SSLv3",
+            TlsVersion::Tls10 => "TLSv1",
+            TlsVersion::Tls11 => "TLSv1.1",
+            TlsVersion::Tls12 => "TLSv1.2",
+            TlsVersion::Tls13 => "TLSv1.3",
+        })
+    }
+}
+
+impl FromStr for TlsVersion {
+    type Err = ();
+
+    /// Parse the names used by OpenSSL and most proxies, like `TLSv1.2`, as well as `TLS 1.2` or `TLS1.2`
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let normalized: String = s.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_ascii_lowercase();
+
+        match normalized.as_str() {
+            "sslv3" | "ssl3" | "ssl3.0" => Ok(TlsVersion::Ssl3),
+            "tlsv1" | "tlsv1.0" | "tls1" | "tls1.0" => Ok(TlsVersion::Tls10),
+            "tlsv1.1" | "tls1.1" => Ok(TlsVersion::Tls11),
+            "tlsv1.2" | "tls1.2" => Ok(TlsVersion::Tls12),
+            "tlsv1.3" | "tls1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What was negotiated on the TLS connection a request was received over
+///
+/// Saphir only terminates TLS for HTTP/3: otherwise the information is inserted in the request extensions by whatever
+/// accepted the connection, or reported by a TLS terminating proxy trusted by the `TrustedProxies` of the server, in the
+/// `X-SSL-Protocol`, `X-SSL-Cipher`, `X-SSL-Server-Name`, `X-SSL-Session-Reused` and `Early-Data` headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The negotiated protocol version, `None` when unknown
+    pub version: Option<TlsVersion>,
+    /// The negotiated cipher suite, like `ECDHE-RSA-AES128-GCM-SHA256`
+    pub cipher_suite: Option<String>,
+    /// The server name the client asked for with SNI
+    pub server_name: Option<String>,
+    /// Whether the connection resumed a previous TLS session, `None` when unknown
+    pub session_reused: Option<bool>,
+    /// Whether the request was received as 0-RTT early data, which an attacker may replay, see `SyncRequest::is_early_data`
+    pub early_data: bool,
+}
+
+impl SyncRequest {
+    /// Returns what was negotiated on the TLS connection the request was received over, `None` when it wasn't received over
+    /// TLS, or when the proxy which terminated TLS isn't trusted. The HTTP version is available with `SyncRequest::version`.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+    ///     let cipher = req.tls_info().and_then(|tls| tls.cipher_suite).unwrap_or_else(|| "none".to_string());
+    ///     res.status(StatusCode::OK).body(format!("{:?} over {}", req.version(), cipher));
+    /// }
+    /// ```
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        if let Some(tls) = self.extensions().get::<TlsInfo>() {
+            return Some(tls.clone());
+        }
+
+        let peer = self.peer_addr()?.ip();
+        match self.extensions().get::<SharedTrustedProxies>() {
+            Some(proxies) if proxies.0.is_trusted(peer) => {}
+            _ => return None,
+        }
+
+        let header = |name: &str| self.headers_map().get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string());
+        let version = header(TLS_VERSION_HEADER);
+        let cipher_suite = header(TLS_CIPHER_HEADER);
+
+        if version.is_none() && cipher_suite.is_none() {
+            return None;
+        }
+
+        Some(TlsInfo {
+            version: version.and_then(|version| version.parse().ok()),
+            cipher_suite,
+            server_name: header(TLS_SERVER_NAME_HEADER),
+            session_reused: header(TLS_SESSION_REUSED_HEADER).map(|reused| reused == "r"),
+            early_data: header(EARLY_DATA_HEADER).is_some_and(|early_data| early_data == "1"),
+        })
+    }
+
+    /// Returns whether the request was received over TLS, see `SyncRequest::tls_info`
+    pub fn is_secure(&self) -> bool {
+        self.tls_info().is_some()
+    }
+
+    /// Returns whether the request was received as TLS 1.3 early data, along with the handshake resuming a session, by the
+    /// HTTP/3 listener or by a proxy flagging it with `Early-Data: 1`. Early data can be replayed by an attacker, so the
+    /// server answers the early requests of methods which aren't idempotent with `425 Too Early`, which clients retry once
+    /// their handshake completes.
+    ///
+    /// The `Early-Data` header is honored whether the proxy is trusted or not, since it can only get requests rejected.
+    pub fn is_early_data(&self) -> bool {
+        self.extensions().get::<TlsInfo>().is_some_and(|tls| tls.early_data)
+            || self.headers_map().get(EARLY_DATA_HEADER).is_some_and(|early_data| early_data == "1")
+    }
+}
+
+/// Answer `req` with `425 Too Early` when it was received as early data but could change the state of the server if it
+/// were replayed, returning whether it was answered
+pub(crate) fn reject_early_data(req: &SyncRequest, res: &mut SyncResponse) -> bool {
+    if !req.is_early_data() || req.method().is_idempotent() {
+        return false;
+    }
+
+    let status = StatusCode::from_u16(425).expect("a valid status");
+    res.problem(&Problem::new(status).with_detail(format!("A {} request can't be sent as early data, retry it once the TLS handshake completes", req.method())));
+    true
+}
+
+/// A guard only letting through requests received over TLS, optionally with a minimum protocol version and without some
+/// cipher suites, answering the others with `403 Forbidden`
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let guard = TlsGuard::new().min_version(TlsVersion::Tls12).deny_cipher("CBC").deny_cipher("RC4");
+///
+/// let mut admin = BasicController::new(());
+/// admin.add_with_guards(Method::GET, "^/admin", guard.into(), |_, _, res| { res.status(StatusCode::OK); });
+/// ```
+pub struct TlsGuard {
+    min_version: Option<TlsVersion>,
+    denied_ciphers: Vec<String>,
+}
+
+impl TlsGuard {
+    /// Create a guard requiring TLS, of any version and cipher suite
+    pub fn new() -> Self {
+        TlsGuard {
+            min_version: None,
+            denied_ciphers: Vec::new(),
+        }
+    }
+
+    /// Reject connections which negotiated an older version than `version`, or whose version is unknown
+    pub fn min_version(mut self, version: TlsVersion) -> Self {
+        self.min_version = Some(version);
+        self
+    }
+
+    /// Reject cipher suites whose name contains `pattern`, ignoring case, like `CBC` or `3DES`
+    pub fn deny_cipher(mut self, pattern: &str) -> Self {
+        self.denied_ciphers.push(pattern.to_ascii_uppercase());
+        self
+    }
+
+    fn rejection(&self, tls: Option<TlsInfo>) -> Option<String> {
+        let tls = match tls {
+            Some(tls) => tls,
+            None => return Some("This resource is only available over TLS".to_string()),
+        };
+
+        if let Some(min_version) = self.min_version {
+            match tls.version {
+                Some(version) if version >= min_version => {}
+                Some(version) => return Some(format!("{} is not allowed, {} or more recent is required", version, min_version)),
+                None => return Some(format!("{} or more recent is required", min_version)),
+            }
+        }
+
+        if let Some(ref cipher) = tls.cipher_suite {
+            let upper = cipher.to_ascii_uppercase();
+            if self.denied_ciphers.iter().any(|pattern| upper.contains(pattern.as_str())) {
+                return Some(format!("The cipher suite {} is not allowed", cipher));
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for TlsGuard {
+    fn default() -> Self {
+        TlsGuard::new()
+    }
+}
+
+impl RequestGuard for TlsGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.rejection(req.tls_info()) {
+            Some(detail) => {
+                res.problem(&Problem::new(StatusCode::FORBIDDEN).with_detail(detail));
+                RequestContinuation::None
+            }
+            None => RequestContinuation::Next,
+        }
+    }
+}
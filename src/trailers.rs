@@ -0,0 +1,282 @@
+use http::*;
+use http_types::HttpTryFrom;
+use http_types::request::Parts as ReqParts;
+use futures::Async;
+use futures::Future;
+use futures::Poll;
+use futures::Stream;
+use hyper::Chunk;
+use buffer_pool::BufferPool;
+use body_limits::BodyLimit;
+use body_limits::BodyRejection;
+use tokio::timer::Delay;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The trailer fields of a request or of a response, kept in its extensions
+#[derive(Clone, Debug, Default)]
+struct Trailers(header::HeaderMap<header::HeaderValue>);
+
+impl SyncRequest {
+    /// Returns the trailer fields received after the body of the request, `None` if there were none
+    ///
+    /// Hyper only reads trailers of HTTP/2 requests, the trailers of chunked HTTP/1.1 requests are discarded.
+    pub fn trailers(&self) -> Option<&header::HeaderMap<header::HeaderValue>> {
+        self.extensions().get::<Trailers>().map(|trailers| &trailers.0)
+    }
+}
+
+impl SyncResponse {
+    /// Appends a trailer field, sent after the body of the response, like a checksum computed while the body is produced or a
+    /// gRPC status. The field is declared in the `Trailer` header of the response.
+    ///
+    /// Hyper only sends trailers of HTTP/2 responses, they are dropped from HTTP/1.1 responses.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// fn handler(_: &(), _req: &SyncRequest, res: &mut SyncResponse) {
+    ///     res.status(StatusCode::OK).body("payload").trailer("x-checksum", "6f6a9bc1");
+    /// }
+    /// ```
+    pub fn trailer<K, V>(&mut self, key: K, value: V) -> &mut SyncResponse
+        where header::HeaderName: HttpTryFrom<K>,
+              header::HeaderValue: HttpTryFrom<V>
+    {
+        let key = match <header::HeaderName as HttpTryFrom<K>>::try_from(key) {
+            Ok(key) => key,
+            Err(e) => return self.invalid(e.into()),
+        };
+        let value = match <header::HeaderValue as HttpTryFrom<V>>::try_from(value) {
+            Ok(value) => value,
+            Err(e) => return self.invalid(e.into()),
+        };
+
+        let declared = self.headers_map().get_all(header::TRAILER).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|name| name.trim().eq_ignore_ascii_case(key.as_str()));
+        if !declared {
+            self.headers_map_mut().append(header::TRAILER, header::HeaderValue::from(key.clone()));
+        }
+
+        let mut trailers = self.get_extensions_mut().remove::<Trailers>().unwrap_or_default();
+        trailers.0.append(key, value);
+        self.extension(trailers)
+    }
+
+    /// Returns the trailer fields set with `trailer`, `None` if there are none
+    pub fn get_trailers(&self) -> Option<&header::HeaderMap<header::HeaderValue>> {
+        self.get_extensions().get::<Trailers>().map(|trailers| &trailers.0)
+    }
+}
+
+/// The body of the responses of a `Server`, followed by the trailers set with `SyncResponse::trailer`
+pub struct ResponseBody {
+    body: Body,
+    trailers: Option<header::HeaderMap<header::HeaderValue>>,
+    sent: u64,
+    on_end: Option<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl ResponseBody {
+    /// Take the trailers out of the extensions of `response` and move them after its body
+    pub(crate) fn with_trailers(response: Response<Body>) -> Response<ResponseBody> {
+        let (mut parts, body) = response.into_parts();
+        let trailers = parts.extensions.remove::<Trailers>().map(|trailers| trailers.0);
+
+        Response::from_parts(parts, ResponseBody {
+            body,
+            trailers,
+            sent: 0,
+            on_end: None,
+        })
+    }
+
+    /// Invoke `on_end` with the number of bytes of the body sent, once the body is dropped, whether it was sent entirely
+    /// or not
+    pub(crate) fn on_end<F: 'static + FnOnce(u64) + Send>(mut self, on_end: F) -> Self {
+        self.on_end = Some(Box::new(on_end));
+        self
+    }
+
+    /// Returns the number of bytes of the body sent so far
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Returns the trailers which will be sent once the body is
+    pub fn trailers(&self) -> Option<&header::HeaderMap<header::HeaderValue>> {
+        self.trailers.as_ref()
+    }
+}
+
+impl From<Body> for ResponseBody {
+    fn from(body: Body) -> Self {
+        ResponseBody {
+            body,
+            trailers: None,
+            sent: 0,
+            on_end: None,
+        }
+    }
+}
+
+impl Payload for ResponseBody {
+    type Data = Chunk;
+    type Error = ::hyper::Error;
+
+    fn poll_data(&mut self) -> Poll<Option<Chunk>, ::hyper::Error> {
+        let polled = self.body.poll_data();
+        if let Ok(Async::Ready(Some(ref chunk))) = polled {
+            self.sent += chunk.len() as u64;
+        }
+        polled
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<header::HeaderMap<header::HeaderValue>>, ::hyper::Error> {
+        Ok(Async::Ready(self.trailers.take()))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.body.is_end_stream()
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        self.body.content_length()
+    }
+}
+
+impl Drop for ResponseBody {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(self.sent);
+        }
+    }
+}
+
+impl Stream for ResponseBody {
+    type Item = Chunk;
+    type Error = ::hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, ::hyper::Error> {
+        self.poll_data()
+    }
+}
+
+/// Load the body of a request into a buffer of a `BufferPool`, then its trailers
+pub(crate) struct LoadPooledBody {
+    parts: Option<ReqParts>,
+    body: Body,
+    buffer: Option<Vec<u8>>,
+    pool: Arc<BufferPool>,
+    capacity: usize,
+    data_done: bool,
+    max_size: Option<usize>,
+    timeout: Option<(Delay, ::std::time::Duration)>,
+}
+
+impl LoadPooledBody {
+    pub(crate) fn new(req: Request<Body>, pool: Arc<BufferPool>, capacity: usize, limit: BodyLimit) -> Self {
+        let (parts, body) = req.into_parts();
+
+        LoadPooledBody {
+            parts: Some(parts),
+            body,
+            buffer: None,
+            pool,
+            capacity,
+            data_done: false,
+            max_size: limit.get_max_size(),
+            timeout: limit.get_timeout().map(|timeout| (Delay::new(Instant::now() + timeout), timeout)),
+        }
+    }
+
+    /// Complete with the request left without a body, the rejection in its extensions
+    fn reject(&mut self, rejection: BodyRejection) -> Poll<SyncRequest, ::hyper::Error> {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.put(buffer);
+        }
+
+        let parts = self.parts.take().expect("LoadPooledBody polled after completion");
+        let mut request = SyncRequest::new(parts, Vec::new());
+        request.extensions_mut().insert(rejection);
+
+        Ok(Async::Ready(request))
+    }
+
+    fn timed_out(&mut self) -> Option<BodyRejection> {
+        let expired = match self.timeout {
+            Some((ref mut delay, _)) => delay.poll(),
+            None => return None,
+        };
+
+        match expired {
+            Ok(Async::Ready(())) => self.timeout.as_ref().map(|&(_, timeout)| BodyRejection::Timeout(timeout)),
+            Ok(Async::NotReady) => None,
+            // Without a timer, like when the service is driven outside of a runtime, the body is loaded without timeout
+            Err(_) => {
+                self.timeout = None;
+                None
+            }
+        }
+    }
+}
+
+impl Future for LoadPooledBody {
+    type Item = SyncRequest;
+    type Error = ::hyper::Error;
+
+    fn poll(&mut self) -> Poll<SyncRequest, ::hyper::Error> {
+        if let Some(max_size) = self.max_size {
+            let content_length = self.parts.as_ref()
+                .and_then(|parts| parts.headers.get(header::CONTENT_LENGTH))
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+
+            if content_length.is_some_and(|len| len > max_size) {
+                return self.reject(BodyRejection::TooLarge(max_size));
+            }
+        }
+
+        while !self.data_done {
+            match self.body.poll_data()? {
+                Async::NotReady => {
+                    return match self.timed_out() {
+                        Some(rejection) => self.reject(rejection),
+                        None => Ok(Async::NotReady),
+                    };
+                }
+                Async::Ready(Some(chunk)) => {
+                    let (pool, capacity) = (&self.pool, self.capacity);
+                    let buffer = self.buffer.get_or_insert_with(|| pool.take(capacity.max(chunk.len())));
+                    buffer.extend_from_slice(&chunk);
+
+                    if let Some(max_size) = self.max_size.filter(|&max_size| buffer.len() > max_size) {
+                        return self.reject(BodyRejection::TooLarge(max_size));
+                    }
+                }
+                Async::Ready(None) => self.data_done = true,
+            }
+        }
+
+        let trailers = match self.body.poll_trailers()? {
+            Async::Ready(trailers) => trailers,
+            Async::NotReady => {
+                return match self.timed_out() {
+                    Some(rejection) => self.reject(rejection),
+                    None => Ok(Async::NotReady),
+                };
+            }
+        };
+
+        let parts = self.parts.take().expect("LoadPooledBody polled after completion");
+        let mut request = SyncRequest::new(parts, self.buffer.take().unwrap_or_default());
+        if let Some(trailers) = trailers {
+            request.extensions_mut().insert(Trailers(trailers));
+        }
+
+        Ok(Async::Ready(request))
+    }
+}
@@ -0,0 +1,180 @@
+use http::*;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use std::fmt;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// A database transaction begun by a `TransactionPool`, which is either committed or rolled back once the request it was
+/// begun for is answered
+pub trait Transaction: Send + 'static {
+    /// The error of the database
+    type Error: fmt::Display;
+
+    /// Commit the changes made by the transaction
+    fn commit(self) -> Result<(), Self::Error>;
+
+    /// Discard the changes made by the transaction
+    fn rollback(self) -> Result<(), Self::Error>;
+}
+
+/// A pool of connections to a database, beginning the transactions of `TransactionMiddleware`
+pub trait TransactionPool: Send + Sync {
+    /// The transactions begun by the pool
+    type Transaction: Transaction;
+
+    /// Begin a transaction for `req`
+    fn begin(&self, req: &SyncRequest) -> Result<Self::Transaction, <Self::Transaction as Transaction>::Error>;
+}
+
+/// The transaction of a request, attached to its extensions until it is committed or rolled back
+struct PendingTransaction<T: Transaction> {
+    transaction: Mutex<Option<T>>,
+}
+
+impl<T: Transaction> PendingTransaction<T> {
+    fn take(&self) -> Option<T> {
+        self.transaction.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+}
+
+impl<T: Transaction> Drop for PendingTransaction<T> {
+    fn drop(&mut self) {
+        // The request was dropped without being answered by the middleware, like when its handler panicked
+        if let Some(transaction) = self.take() {
+            if let Err(e) = transaction.rollback() {
+                error!("Unable to roll back the transaction of an abandoned request: {}", e);
+            }
+        }
+    }
+}
+
+/// The transaction of a request, borrowed from the request by `SyncRequest::transaction`
+pub struct TransactionGuard<'a, T: Transaction> {
+    guard: MutexGuard<'a, Option<T>>,
+}
+
+impl<'a, T: Transaction> Deref for TransactionGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().expect("the transaction is pending")
+    }
+}
+
+impl<'a, T: Transaction> DerefMut for TransactionGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().expect("the transaction is pending")
+    }
+}
+
+/// A middleware running every request in a database transaction, begun from a `TransactionPool` before the request is
+/// handled, see `SyncRequest::transaction`
+///
+/// The transaction is committed once the response is computed if its status is successful, and rolled back otherwise.
+/// When the commit fails, the response is replaced with `500 Internal Server Error`, so clients are never told a change
+/// was made when it wasn't. The transaction of a request whose handler panicked is rolled back once the request is
+/// dropped. Requests whose transaction can't be begun are answered with `503 Service Unavailable`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// struct Connection;
+/// struct Pool;
+///
+/// impl Transaction for Connection {
+///     type Error = String;
+///
+///     fn commit(self) -> Result<(), String> {
+///         Ok(())
+///     }
+///
+///     fn rollback(self) -> Result<(), String> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl TransactionPool for Pool {
+///     type Transaction = Connection;
+///
+///     fn begin(&self, _req: &SyncRequest) -> Result<Connection, String> {
+///         Ok(Connection)
+///     }
+/// }
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(TransactionMiddleware::new(Pool), vec!("/api"), None);
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::POST, "^/orders$", |_, req, res| {
+///     let _connection = req.transaction::<Connection>().expect("the request has a transaction");
+///     res.status(StatusCode::CREATED);
+/// });
+/// ```
+pub struct TransactionMiddleware<P: TransactionPool> {
+    pool: P,
+}
+
+impl<P: TransactionPool> TransactionMiddleware<P> {
+    /// Create a middleware beginning the transactions of requests from `pool`
+    pub fn new(pool: P) -> Self {
+        TransactionMiddleware {
+            pool,
+        }
+    }
+}
+
+impl<P: TransactionPool> Middleware for TransactionMiddleware<P> {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.pool.begin(req) {
+            Ok(transaction) => {
+                req.extensions_mut().insert(PendingTransaction { transaction: Mutex::new(Some(transaction)) });
+                RequestContinuation::Next
+            }
+            Err(e) => {
+                error!("Unable to begin a transaction for {} {}: {}", req.method(), req.uri(), e);
+                res.status(StatusCode::SERVICE_UNAVAILABLE);
+                RequestContinuation::None
+            }
+        }
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let transaction = match req.extensions().get::<PendingTransaction<P::Transaction>>().and_then(|pending| pending.take()) {
+            Some(transaction) => transaction,
+            None => return,
+        };
+
+        if !res.get_status().is_success() {
+            if let Err(e) = transaction.rollback() {
+                error!("Unable to roll back the transaction of {} {}: {}", req.method(), req.uri(), e);
+            }
+            return;
+        }
+
+        if let Err(e) = transaction.commit() {
+            error!("Unable to commit the transaction of {} {}: {}", req.method(), req.uri(), e);
+            res.headers_map_mut().remove(header::CONTENT_LENGTH);
+            res.status(StatusCode::INTERNAL_SERVER_ERROR).body(Vec::<u8>::new());
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Returns the pending transaction `TransactionMiddleware` begun for the request, of the type of the transactions of
+    /// its pool
+    ///
+    /// The transaction stays borrowed until the returned guard is dropped.
+    pub fn transaction<T: Transaction>(&self) -> Option<TransactionGuard<'_, T>> {
+        let guard = self.extensions().get::<PendingTransaction<T>>()?.transaction.lock().unwrap_or_else(|e| e.into_inner());
+
+        if guard.is_some() { Some(TransactionGuard { guard }) } else { None }
+    }
+}
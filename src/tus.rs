@@ -0,0 +1,490 @@
+use http::*;
+use http::header::HttpDate;
+use controller::Controller;
+use controller::RouteInfo;
+use credentials::random_token;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// An upload received by a `TusController`, in progress or completed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TusUpload {
+    /// The identifier of the upload, the last segment of its URL
+    pub id: String,
+    /// The size of the whole upload, in bytes
+    pub length: u64,
+    /// The number of bytes received so far
+    pub offset: u64,
+    /// The metadata the client attached to the upload, like its file name or type
+    pub metadata: BTreeMap<String, String>,
+    /// When the upload expires if it isn't completed by then
+    pub expires: Option<SystemTime>,
+}
+
+impl TusUpload {
+    /// Returns true once every byte of the upload was received
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+
+    /// Returns true when the upload wasn't completed before its expiration
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        !self.is_complete() && self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// A trait representing where a `TusController` keeps the uploads it receives
+///
+/// Identifiers are generated by the controller and only contain ASCII letters and digits. Storages are shared by the
+/// threads processing requests, the controller making sure a single request appends to an upload at a time.
+pub trait TusStorage: Send + Sync {
+    /// Create the empty `upload`
+    fn create(&self, upload: &TusUpload) -> io::Result<()>;
+
+    /// Returns the upload `id`, failing with `NotFound` when it does not exist
+    fn get(&self, id: &str) -> io::Result<TusUpload>;
+
+    /// Append `data` to the upload `id`, whose offset is `offset`, and returns its new offset
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<u64>;
+
+    /// Read the bytes received for the upload `id`
+    fn read(&self, id: &str) -> io::Result<Vec<u8>>;
+
+    /// Remove the upload `id` and the bytes received for it
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    /// List the uploads, to remove the expired ones
+    fn list(&self) -> io::Result<Vec<TusUpload>>;
+}
+
+/// A `TusStorage` keeping the uploads in memory, for tests and small uploads
+#[derive(Default)]
+pub struct MemoryTusStorage {
+    uploads: Mutex<HashMap<String, (TusUpload, Vec<u8>)>>,
+}
+
+impl MemoryTusStorage {
+    /// Create an empty storage
+    pub fn new() -> Self {
+        MemoryTusStorage::default()
+    }
+}
+
+fn not_found(id: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("the upload {} does not exist", id))
+}
+
+impl TusStorage for MemoryTusStorage {
+    fn create(&self, upload: &TusUpload) -> io::Result<()> {
+        self.uploads.lock().unwrap().insert(upload.id.clone(), (upload.clone(), Vec::new()));
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> io::Result<TusUpload> {
+        self.uploads.lock().unwrap().get(id).map(|(upload, _)| upload.clone()).ok_or_else(|| not_found(id))
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<u64> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let (upload, content) = uploads.get_mut(id).ok_or_else(|| not_found(id))?;
+        content.truncate(offset as usize);
+        content.extend_from_slice(data);
+        upload.offset = content.len() as u64;
+        Ok(upload.offset)
+    }
+
+    fn read(&self, id: &str) -> io::Result<Vec<u8>> {
+        self.uploads.lock().unwrap().get(id).map(|(_, content)| content.clone()).ok_or_else(|| not_found(id))
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.uploads.lock().unwrap().remove(id).map(|_| ()).ok_or_else(|| not_found(id))
+    }
+
+    fn list(&self) -> io::Result<Vec<TusUpload>> {
+        Ok(self.uploads.lock().unwrap().values().map(|(upload, _)| upload.clone()).collect())
+    }
+}
+
+/// A `TusStorage` keeping the uploads in a directory of the local file system, the bytes of an upload `id` in the file
+/// `id` and its description in `id.json`, so uploads survive restarts of the server
+pub struct FileTusStorage {
+    root: PathBuf,
+}
+
+impl FileTusStorage {
+    /// Keep the uploads in the `root` directory, which is created when it doesn't exist
+    pub fn new<P: Into<PathBuf>>(root: P) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+        Ok(FileTusStorage { root })
+    }
+
+    /// Returns the path of the file holding the bytes of the upload `id`, to move it once completed
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}.json", id))
+    }
+
+    fn save(&self, upload: &TusUpload) -> io::Result<()> {
+        let info = ::serde_json::to_vec(upload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // The description is replaced at once, so a crash never leaves it half written
+        let temporary = self.root.join(format!("{}.json.tmp", upload.id));
+        fs::write(&temporary, info)?;
+        fs::rename(temporary, self.info_path(&upload.id))
+    }
+}
+
+impl TusStorage for FileTusStorage {
+    fn create(&self, upload: &TusUpload) -> io::Result<()> {
+        fs::File::create(self.path(&upload.id))?;
+        self.save(upload)
+    }
+
+    fn get(&self, id: &str) -> io::Result<TusUpload> {
+        let info = fs::read(self.info_path(id))?;
+        ::serde_json::from_slice(&info).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn append(&self, id: &str, offset: u64, data: &[u8]) -> io::Result<u64> {
+        let mut upload = self.get(id)?;
+        let mut file = fs::OpenOptions::new().write(true).open(self.path(id))?;
+        // Bytes past the offset were written by a request which failed before its upload was saved
+        file.set_len(offset)?;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_data()?;
+
+        upload.offset = offset + data.len() as u64;
+        self.save(&upload)?;
+        Ok(upload.offset)
+    }
+
+    fn read(&self, id: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(id))
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        fs::remove_file(self.info_path(id))?;
+        match fs::remove_file(self.path(id)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn list(&self) -> io::Result<Vec<TusUpload>> {
+        let mut uploads = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(id) = name.strip_suffix(".json") {
+                match self.get(id) {
+                    Ok(upload) => uploads.push(upload),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(uploads)
+    }
+}
+
+type CompletionHook = Box<dyn Fn(&TusUpload, &dyn TusStorage) + Send + Sync>;
+
+/// A controller receiving uploads with the tus resumable upload protocol 1.0.0, with its creation, expiration and
+/// termination extensions, see https://tus.io/protocols/resumable-upload
+///
+/// Clients create an upload with a `POST` announcing its `Upload-Length`, then send its content with `PATCH` requests
+/// appending to it at the `Upload-Offset` they were told. A connection failing in the middle of an upload only loses the
+/// request in progress: the client asks the offset the server reached with `HEAD` and resumes from there. Uploads which
+/// aren't completed before their expiration are forgotten, see `TusController::remove_expired`.
+///
+/// Every `PATCH` request is subject to the `BodyLimits` of the server, clients being expected to split large uploads in
+/// chunks smaller than the limit.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// use std::time::Duration;
+///
+/// let uploads = TusController::new("/uploads", FileTusStorage::new("/var/lib/uploads").unwrap())
+///     .max_size(10 * 1024 * 1024 * 1024)
+///     .expiration(Duration::from_secs(6 * 60 * 60))
+///     .on_complete(|upload, _storage| println!("received {:?}", upload.metadata.get("filename")));
+///
+/// let mut router = Router::new();
+/// router.add("^/uploads(/|$)", uploads);
+/// ```
+pub struct TusController {
+    prefix: String,
+    storage: Box<dyn TusStorage>,
+    max_size: Option<u64>,
+    expiration: Option<Duration>,
+    on_complete: Vec<CompletionHook>,
+    patching: Mutex<HashSet<String>>,
+}
+
+impl TusController {
+    /// Receive uploads into `storage` under `prefix`, the path the controller is registered under in the router. Uploads
+    /// expire a day after their creation.
+    pub fn new<P: Into<String>, S: 'static + TusStorage>(prefix: P, storage: S) -> Self {
+        TusController {
+            prefix: prefix.into().trim_end_matches('/').to_string(),
+            storage: Box::new(storage),
+            max_size: None,
+            expiration: Some(Duration::from_secs(24 * 60 * 60)),
+            on_complete: Vec::new(),
+            patching: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Reject the uploads larger than `bytes` with `413 Payload Too Large`, the size being announced to the clients
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Set for how long uploads may take to complete once created, or keep them until they complete with `None`
+    pub fn expiration<E: Into<Option<Duration>>>(mut self, expiration: E) -> Self {
+        self.expiration = expiration.into();
+        self
+    }
+
+    /// Invoke `hook` with the upload and the storage holding it once its last byte is received, to process it or move it
+    /// to its final location. The upload is left in the storage, for the hook to remove once it is done with it.
+    pub fn on_complete<F: 'static + Fn(&TusUpload, &dyn TusStorage) + Send + Sync>(mut self, hook: F) -> Self {
+        self.on_complete.push(Box::new(hook));
+        self
+    }
+
+    /// Remove the uploads which expired before completing, and returns how many were removed. Applications call it
+    /// periodically, like from a task registered with `Server::spawn`, since expired uploads are otherwise only removed
+    /// when clients request them.
+    pub fn remove_expired(&self) -> io::Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+        for upload in self.storage.list()? {
+            if upload.is_expired(now) {
+                self.storage.remove(&upload.id)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Returns the identifier of the upload `uri_path` targets, an empty one for the collection of uploads, or `None` when
+    /// the path isn't one of the controller
+    fn upload_id<'a>(&self, uri_path: &'a str) -> Option<&'a str> {
+        let rest = uri_path.strip_prefix(self.prefix.as_str())?;
+        if rest.is_empty() || rest == "/" {
+            return Some("");
+        }
+
+        let id = rest.strip_prefix('/')?.trim_end_matches('/');
+        if !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric()) { Some(id) } else { None }
+    }
+
+    /// Returns the upload `id`, removing it when it expired
+    fn upload(&self, id: &str) -> Result<TusUpload, StatusCode> {
+        let upload = self.storage.get(id).map_err(storage_error)?;
+        if upload.is_expired(SystemTime::now()) {
+            let _ = self.storage.remove(id);
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Ok(upload)
+    }
+
+    fn options(&self, res: &mut SyncResponse) -> Result<(), StatusCode> {
+        res.status(StatusCode::NO_CONTENT).header("tus-version", TUS_VERSION).header("tus-extension", TUS_EXTENSIONS);
+        if let Some(max_size) = self.max_size {
+            res.header("tus-max-size", max_size.to_string());
+        }
+        Ok(())
+    }
+
+    fn create(&self, req: &SyncRequest, res: &mut SyncResponse) -> Result<(), StatusCode> {
+        let length = number_header(req, "upload-length").ok_or(StatusCode::BAD_REQUEST)?;
+        if self.max_size.is_some_and(|max_size| length > max_size) {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        let metadata = match req.headers_map().get("upload-metadata") {
+            Some(metadata) => parse_metadata(metadata.to_str().map_err(|_| StatusCode::BAD_REQUEST)?).ok_or(StatusCode::BAD_REQUEST)?,
+            None => BTreeMap::new(),
+        };
+
+        let upload = TusUpload {
+            id: random_token(),
+            length,
+            offset: 0,
+            metadata,
+            expires: self.expiration.map(|expiration| SystemTime::now() + expiration),
+        };
+        self.storage.create(&upload).map_err(storage_error)?;
+
+        res.status(StatusCode::CREATED).header(header::LOCATION, format!("{}/{}", self.prefix, upload.id));
+        expires_header(res, &upload);
+        if upload.is_complete() {
+            self.complete(&upload);
+        }
+        Ok(())
+    }
+
+    fn head(&self, res: &mut SyncResponse, id: &str) -> Result<(), StatusCode> {
+        let upload = self.upload(id)?;
+
+        res.status(StatusCode::OK)
+            .header(header::CACHE_CONTROL, "no-store")
+            .header("upload-offset", upload.offset.to_string())
+            .header("upload-length", upload.length.to_string());
+        if !upload.metadata.is_empty() {
+            res.header("upload-metadata", format_metadata(&upload.metadata));
+        }
+        expires_header(res, &upload);
+        Ok(())
+    }
+
+    fn patch(&self, req: &SyncRequest, res: &mut SyncResponse, id: &str) -> Result<(), StatusCode> {
+        let content_type = req.headers_map().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok());
+        if content_type.map(|value| value.split(';').next().unwrap_or("").trim()) != Some(OFFSET_CONTENT_TYPE) {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        let offset = number_header(req, "upload-offset").ok_or(StatusCode::BAD_REQUEST)?;
+
+        if !self.patching.lock().unwrap().insert(id.to_string()) {
+            return Err(StatusCode::LOCKED);
+        }
+        let result = self.append(req, res, id, offset);
+        self.patching.lock().unwrap().remove(id);
+        result
+    }
+
+    fn append(&self, req: &SyncRequest, res: &mut SyncResponse, id: &str, offset: u64) -> Result<(), StatusCode> {
+        let mut upload = self.upload(id)?;
+        if offset != upload.offset {
+            return Err(StatusCode::CONFLICT);
+        }
+        if offset + req.body().len() as u64 > upload.length {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        upload.offset = self.storage.append(id, offset, req.body()).map_err(storage_error)?;
+
+        res.status(StatusCode::NO_CONTENT).header("upload-offset", upload.offset.to_string());
+        expires_header(res, &upload);
+        if upload.is_complete() {
+            self.complete(&upload);
+        }
+        Ok(())
+    }
+
+    fn terminate(&self, res: &mut SyncResponse, id: &str) -> Result<(), StatusCode> {
+        self.storage.remove(id).map_err(storage_error)?;
+        res.status(StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    fn complete(&self, upload: &TusUpload) {
+        for hook in &self.on_complete {
+            hook(upload, &*self.storage);
+        }
+    }
+}
+
+impl Controller for TusController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        res.header("tus-resumable", TUS_VERSION);
+
+        let id = match self.upload_id(req.uri().path()) {
+            Some(id) => id,
+            None => {
+                res.status(StatusCode::NOT_FOUND);
+                return;
+            }
+        };
+
+        if *req.method() != Method::OPTIONS && req.headers_map().get("tus-resumable").map(|v| v.as_bytes()) != Some(TUS_VERSION.as_bytes()) {
+            res.status(StatusCode::PRECONDITION_FAILED).header("tus-version", TUS_VERSION);
+            return;
+        }
+
+        let result = match (req.method().as_str(), id.is_empty()) {
+            ("OPTIONS", _) => self.options(res),
+            ("POST", true) => self.create(req, res),
+            ("HEAD", false) => self.head(res, id),
+            ("PATCH", false) => self.patch(req, res, id),
+            ("DELETE", false) => self.terminate(res, id),
+            (_, collection) => {
+                res.header(header::ALLOW, if collection { "OPTIONS, POST" } else { "OPTIONS, HEAD, PATCH, DELETE" });
+                Err(StatusCode::METHOD_NOT_ALLOWED)
+            }
+        };
+
+        if let Err(status) = result {
+            res.status(status);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        [Method::OPTIONS, Method::POST, Method::HEAD, Method::PATCH, Method::DELETE].iter()
+            .map(|method| RouteInfo::new("TusController", Some(method.clone()), None, Vec::new()))
+            .collect()
+    }
+}
+
+fn storage_error(e: io::Error) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        _ => {
+            warn!("The tus storage failed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn number_header(req: &SyncRequest, name: &str) -> Option<u64> {
+    req.headers_map().get(name).and_then(|value| value.to_str().ok()).and_then(|value| value.trim().parse().ok())
+}
+
+fn expires_header(res: &mut SyncResponse, upload: &TusUpload) {
+    if let Some(expires) = upload.expires.filter(|_| !upload.is_complete()) {
+        res.header("upload-expires", HttpDate::from(expires).to_string());
+    }
+}
+
+/// Parse an `Upload-Metadata` header, comma separated keys each followed by its base64 encoded value, if any
+fn parse_metadata(header: &str) -> Option<BTreeMap<String, String>> {
+    let mut metadata = BTreeMap::new();
+    for pair in header.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let mut parts = pair.splitn(2, ' ');
+        let key = parts.next()?.to_string();
+        let value = match parts.next() {
+            Some(value) => String::from_utf8(::base64::decode(value.trim()).ok()?).ok()?,
+            None => String::new(),
+        };
+        metadata.insert(key, value);
+    }
+    Some(metadata)
+}
+
+fn format_metadata(metadata: &BTreeMap<String, String>) -> String {
+    metadata.iter()
+        .map(|(key, value)| if value.is_empty() { key.clone() } else { format!("{} {}", key, ::base64::encode(value.as_bytes())) })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,81 @@
+use http::*;
+use http::header::Header;
+use http::header::HeaderMap;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::header::Headers;
+use http::header::Raw;
+
+fn get_typed<H: Header>(map: &HeaderMap<HeaderValue>) -> Option<H> {
+    let values: Vec<Vec<u8>> = map.get_all(H::header_name()).iter().map(|v| v.as_bytes().to_vec()).collect();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    H::parse_header(&Raw::from(values)).ok()
+}
+
+fn set_typed<H: Header>(map: &mut HeaderMap<HeaderValue>, value: H) {
+    let name = match HeaderName::from_bytes(H::header_name().as_bytes()) {
+        Ok(name) => name,
+        Err(_) => return,
+    };
+
+    let mut headers = Headers::new();
+    headers.set(value);
+    let formatted: HeaderMap = headers.into();
+
+    map.remove(&name);
+    for value in formatted.get_all(&name).iter() {
+        map.append(name.clone(), value.clone());
+    }
+}
+
+impl SyncRequest {
+    /// Parse the header `H` of the request, returns `None` if the header is missing or invalid
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use saphir::header::*;
+    /// # fn handler(req: &SyncRequest) {
+    /// if let Some(ContentType(mime)) = req.typed_header::<ContentType>() {
+    ///     println!("{}", mime);
+    /// }
+    /// # }
+    /// ```
+    pub fn typed_header<H: Header>(&self) -> Option<H> {
+        get_typed(self.headers_map())
+    }
+
+    /// Set the header `H` of the request, replacing any previous value
+    pub fn set_typed_header<H: Header>(&mut self, value: H) {
+        set_typed(self.headers_map_mut(), value);
+    }
+}
+
+impl SyncResponse {
+    /// Parse the header `H` of the response, returns `None` if the header is missing or invalid
+    pub fn typed_header<H: Header>(&self) -> Option<H> {
+        get_typed(self.headers_map())
+    }
+
+    /// Set the header `H` of the response, replacing any previous value
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use saphir::*;
+    /// # use saphir::header::*;
+    ///
+    /// let mut response = SyncResponse::new();
+    /// response.set_typed_header(CacheControl(vec![CacheDirective::NoCache, CacheDirective::MaxAge(86400)]));
+    /// let response = response.build_response().unwrap();
+    /// ```
+    pub fn set_typed_header<H: Header>(&mut self, value: H) -> &mut SyncResponse {
+        set_typed(self.headers_map_mut(), value);
+        self
+    }
+}
@@ -0,0 +1,320 @@
+use http::*;
+use problem::Problem;
+use query::from_query;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::ops::Deref;
+use std::ops::DerefMut;
+
+/// A single failed validation rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    /// Path of the invalid field, like `address.city`, empty when the error is not tied to a field
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub field: String,
+    /// Machine readable identifier of the failed rule, like `length` or `range`
+    pub code: String,
+    /// Human readable description of the failure
+    pub message: String,
+}
+
+/// The errors collected while validating a value, all the failed rules are reported rather than only the first one
+///
+/// # Example
+///
+/// ```rust
+/// # use saphir::*;
+/// struct Signup {
+///     name: String,
+///     age: u32,
+/// }
+///
+/// impl Validate for Signup {
+///     fn validate(&self) -> Result<(), ValidationErrors> {
+///         let mut errors = ValidationErrors::new();
+///         errors.length("name", &self.name, Some(1), Some(64))
+///             .range("age", self.age, Some(18), None);
+///         errors.into_result()
+///     }
+/// }
+///
+/// let errors = Signup { name: String::new(), age: 12 }.validate().unwrap_err();
+/// assert_eq!(errors.errors().len(), 2);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    /// Create an empty collection of errors
+    pub fn new() -> Self {
+        ValidationErrors {
+            errors: Vec::new(),
+        }
+    }
+
+    /// Record that the rule `code` failed for `field`
+    pub fn add<F: Into<String>, C: Into<String>, M: Into<String>>(&mut self, field: F, code: C, message: M) -> &mut Self {
+        self.errors.push(FieldError {
+            field: field.into(),
+            code: code.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Check a custom rule, recording an error with `code` and `message` when `valid` is false
+    pub fn check<C: Into<String>, M: Into<String>>(&mut self, field: &str, valid: bool, code: C, message: M) -> &mut Self {
+        if !valid {
+            self.add(field, code, message);
+        }
+        self
+    }
+
+    /// Check that `value` counts between `min` and `max` characters, both inclusive
+    pub fn length(&mut self, field: &str, value: &str, min: Option<usize>, max: Option<usize>) -> &mut Self {
+        let length = value.chars().count();
+
+        match (min, max) {
+            (Some(min), Some(max)) if length < min || length > max => {
+                self.add(field, "length", format!("must be between {} and {} characters long", min, max))
+            }
+            (Some(min), None) if length < min => self.add(field, "length", format!("must be at least {} characters long", min)),
+            (None, Some(max)) if length > max => self.add(field, "length", format!("must be at most {} characters long", max)),
+            _ => self,
+        }
+    }
+
+    /// Check that `value` lies between `min` and `max`, both inclusive
+    pub fn range<T: PartialOrd + fmt::Display>(&mut self, field: &str, value: T, min: Option<T>, max: Option<T>) -> &mut Self {
+        let below = min.as_ref().is_some_and(|min| value < *min);
+        let above = max.as_ref().is_some_and(|max| value > *max);
+        if !below && !above {
+            return self;
+        }
+
+        match (min, max) {
+            (Some(min), Some(max)) => self.add(field, "range", format!("must be between {} and {}", min, max)),
+            (Some(min), None) => self.add(field, "range", format!("must be at least {}", min)),
+            (None, Some(max)) => self.add(field, "range", format!("must be at most {}", max)),
+            (None, None) => self,
+        }
+    }
+
+    /// Check that `value` matches `pattern`
+    pub fn pattern(&mut self, field: &str, value: &str, pattern: &Regex) -> &mut Self {
+        if !pattern.is_match(value) {
+            self.add(field, "pattern", format!("must match {}", pattern.as_str()));
+        }
+        self
+    }
+
+    /// Merge the outcome of the validation of a nested value, prefixing the fields of its errors with `field`
+    pub fn nested(&mut self, field: &str, result: Result<(), ValidationErrors>) -> &mut Self {
+        if let Err(nested) = result {
+            for mut error in nested.errors {
+                error.field = if error.field.is_empty() { field.to_string() } else { format!("{}.{}", field, error.field) };
+                self.errors.push(error);
+            }
+        }
+        self
+    }
+
+    /// Whether no rule failed
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// The failed rules, in the order they were checked
+    pub fn errors(&self) -> &[FieldError] {
+        &self.errors
+    }
+
+    /// `Ok(())` when no rule failed, `Err(self)` otherwise
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            if error.field.is_empty() {
+                write!(f, "{}", error.message)?;
+            } else {
+                write!(f, "{} {}", error.field, error.message)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ::std::error::Error for ValidationErrors {}
+
+/// A trait for values which can check their own invariants once extracted from a request
+pub trait Validate {
+    /// Check every rule, returning all the failures
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        for (i, item) in self.iter().enumerate() {
+            errors.nested(&format!("[{}]", i), item.validate());
+        }
+        errors.into_result()
+    }
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.as_ref().map_or(Ok(()), Validate::validate)
+    }
+}
+
+/// Errors that can occur while extracting a `Validated` value from a request
+#[derive(Debug)]
+pub enum ValidationRejection {
+    /// The request body or query could not be deserialized
+    Malformed(String),
+    /// The value was deserialized but broke some of its rules
+    Invalid(ValidationErrors),
+}
+
+impl ValidationRejection {
+    /// The status code a response should carry when the extraction fails, `422 Unprocessable Entity` for invalid values
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ValidationRejection::Malformed(_) => StatusCode::BAD_REQUEST,
+            ValidationRejection::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    /// The errors to report, a malformed request is reported as a single error without a field
+    pub fn errors(&self) -> ValidationErrors {
+        match *self {
+            ValidationRejection::Malformed(ref message) => {
+                let mut errors = ValidationErrors::new();
+                errors.add("", "malformed", message.as_str());
+                errors
+            }
+            ValidationRejection::Invalid(ref errors) => errors.clone(),
+        }
+    }
+
+    /// Describe the rejection as a problem document, listing the errors in its `errors` extension member
+    pub fn problem(&self) -> Problem {
+        let detail = match *self {
+            ValidationRejection::Malformed(ref message) => message.clone(),
+            ValidationRejection::Invalid(ref errors) => format!("{} field(s) failed validation", errors.errors().len()),
+        };
+
+        Problem::new(self.status())
+            .with_detail(detail)
+            .with_extension("errors", self.errors().errors())
+    }
+}
+
+impl fmt::Display for ValidationRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValidationRejection::Malformed(ref message) => write!(f, "Malformed request: {}", message),
+            ValidationRejection::Invalid(ref errors) => write!(f, "Invalid request: {}", errors),
+        }
+    }
+}
+
+impl ::std::error::Error for ValidationRejection {}
+
+/// A value extracted from a request and validated
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # #[macro_use] extern crate serde_derive;
+/// # extern crate saphir;
+/// # use saphir::*;
+/// #[derive(Deserialize)]
+/// struct Search {
+///     q: String,
+///     page: Option<u32>,
+/// }
+///
+/// impl Validate for Search {
+///     fn validate(&self) -> Result<(), ValidationErrors> {
+///         let mut errors = ValidationErrors::new();
+///         errors.length("q", &self.q, Some(3), None);
+///         errors.into_result()
+///     }
+/// }
+///
+/// fn handler(_: &(), req: &SyncRequest, res: &mut SyncResponse) {
+///     match Validated::<Search>::from_query(req) {
+///         Ok(search) => { res.status(StatusCode::OK).body(search.q.clone()); }
+///         Err(rejection) => { res.rejection(&rejection); }
+///     }
+/// }
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validated<T>(pub T);
+
+impl<T: DeserializeOwned + Validate> Validated<T> {
+    /// Deserialize the value from the JSON body of `req` and validate it
+    pub fn from_json(req: &SyncRequest) -> Result<Self, ValidationRejection> {
+        let value: T = req.body_json().map_err(|e| ValidationRejection::Malformed(e.to_string()))?;
+        Self::check(value)
+    }
+
+    /// Deserialize the value from the query string of `req` and validate it, see `from_query`
+    pub fn from_query(req: &SyncRequest) -> Result<Self, ValidationRejection> {
+        let value: T = from_query(req.uri().query().unwrap_or("")).map_err(|e| ValidationRejection::Malformed(e.to_string()))?;
+        Self::check(value)
+    }
+
+    /// Deserialize the value from the `application/x-www-form-urlencoded` body of `req` and validate it, see
+    /// `SyncRequest::body_form`
+    pub fn from_form(req: &SyncRequest) -> Result<Self, ValidationRejection> {
+        let value: T = req.body_form().map_err(|e| ValidationRejection::Malformed(e.to_string()))?;
+        Self::check(value)
+    }
+
+    fn check(value: T) -> Result<Self, ValidationRejection> {
+        value.validate().map_err(ValidationRejection::Invalid)?;
+        Ok(Validated(value))
+    }
+}
+
+impl<T> Validated<T> {
+    /// Unwrap the value
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Validated<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Validated<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl SyncResponse {
+    /// Answer with the problem document describing `rejection`, see `ValidationRejection::problem`
+    pub fn rejection(&mut self, rejection: &ValidationRejection) -> &mut SyncResponse {
+        self.problem(&rejection.problem())
+    }
+}
@@ -0,0 +1,174 @@
+use http::*;
+use middleware::Middleware;
+use negotiation::parse_quality_list;
+use router::set_path;
+use rewrite::redirect;
+use rewrite::with_query;
+use utils::RequestContinuation;
+
+/// The version of the API a request is addressed to, attached to the request extensions by `ApiVersioning`
+struct ApiVersion {
+    version: String,
+    /// Whether the version was told by the path, so the response doesn't depend on the `Accept` header
+    from_path: bool,
+}
+
+/// A middleware telling the version of the API each request is addressed to, from the prefix of its path, like `/v2/users`,
+/// or from a parameter of the media types it accepts, like `Accept: application/json; version=2`, see
+/// `SyncRequest::api_version`
+///
+/// The path of a request whose version is told by its `Accept` header is prefixed with the version, so routes are only
+/// declared under versioned paths, grouped with `Router::add_versioned`. Requests telling no version are handed to the
+/// following middlewares untouched, unless a default version is set: their path is then prefixed with the default
+/// version, or the client is redirected to the prefixed path with `redirect_unversioned`. Requests accepting a version
+/// which isn't declared are answered with `406 Not Acceptable`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut users_v1 = BasicController::new(());
+/// users_v1.add(Method::GET, "^/v1/users$", |_, _, res| { res.status(StatusCode::OK).body("[\"alice\"]"); });
+///
+/// let mut users_v2 = BasicController::new(());
+/// users_v2.add(Method::GET, "^/v2/users$", |_, _, res| { res.status(StatusCode::OK).body("{\"users\":[\"alice\"]}"); });
+///
+/// let mut health = BasicController::new(());
+/// health.add(Method::GET, "^/v\\d+/health$", |_, req, res| {
+///     res.status(StatusCode::OK).body(format!("version {}", req.api_version().unwrap_or("?")));
+/// });
+///
+/// let mut router = Router::new();
+/// router.add_versioned(&["1"], "^/users", users_v1);
+/// router.add_versioned(&["2"], "^/users", users_v2);
+/// router.add_versioned(&["1", "2"], "^/health", health);
+///
+/// let mut mid_stack = MiddlewareStack::new();
+/// mid_stack.apply(ApiVersioning::new(&["1", "2"]).default_version("2"), vec!("/"), None);
+/// ```
+pub struct ApiVersioning {
+    versions: Vec<String>,
+    default: Option<String>,
+    redirect_unversioned: bool,
+    parameter: String,
+}
+
+impl ApiVersioning {
+    /// Create a middleware telling the `versions` of the API, like `1` and `2` for paths prefixed with `/v1` and `/v2`
+    pub fn new(versions: &[&str]) -> Self {
+        ApiVersioning {
+            versions: versions.iter().map(|version| version.to_string()).collect(),
+            default: None,
+            redirect_unversioned: false,
+            parameter: "version".to_string(),
+        }
+    }
+
+    /// Route the requests telling no version as if they were addressed to `version`
+    pub fn default_version(mut self, version: &str) -> Self {
+        self.default = Some(version.to_string());
+        self
+    }
+
+    /// Redirect the requests telling no version to the path prefixed with the default version, with
+    /// `307 Temporary Redirect` since the default version changes over time, instead of rewriting their path
+    pub fn redirect_unversioned(mut self) -> Self {
+        self.redirect_unversioned = true;
+        self
+    }
+
+    /// Read the version in the media type parameter `name` of the `Accept` header, `version` by default
+    pub fn media_type_parameter(mut self, name: &str) -> Self {
+        self.parameter = name.to_ascii_lowercase();
+        self
+    }
+
+    /// Returns the declared version prefixing `path`
+    fn path_version(&self, path: &str) -> Option<&str> {
+        let segment = path.strip_prefix("/v")?.split('/').next()?;
+        self.versions.iter().find(|version| version.as_str() == segment).map(|version| version.as_str())
+    }
+
+    /// Returns the versions named by the media type parameter of the `Accept` header of `req`, in their order of preference
+    fn accepted_versions(&self, req: &SyncRequest) -> Vec<String> {
+        let accept = req.header_list(header::ACCEPT.as_str()).join(",");
+
+        parse_quality_list(&accept).into_iter().filter(|(_, quality)| *quality > 0.0).filter_map(|(media_type, _)| {
+            media_type.split(';').skip(1).find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case(&self.parameter) {
+                    Some(value.trim().trim_matches('"').trim_start_matches(['v', 'V']).to_string())
+                } else {
+                    None
+                }
+            })
+        }).collect()
+    }
+}
+
+impl Middleware for ApiVersioning {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+
+    fn prepare(&self, req: &mut SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        if let Some(version) = self.path_version(req.uri().path()) {
+            let version = ApiVersion { version: version.to_string(), from_path: true };
+            req.extensions_mut().insert(version);
+            return RequestContinuation::Next;
+        }
+
+        let accepted = self.accepted_versions(req);
+        let version = if accepted.is_empty() {
+            match self.default {
+                Some(ref version) if self.redirect_unversioned => {
+                    let location = with_query(versioned_path(version, req.uri().path()), req.uri());
+                    redirect(req, res, location, Some(StatusCode::TEMPORARY_REDIRECT));
+                    return RequestContinuation::None;
+                }
+                Some(ref version) => version.clone(),
+                None => return RequestContinuation::Next,
+            }
+        } else {
+            match accepted.into_iter().find(|version| self.versions.contains(version)) {
+                Some(version) => version,
+                None => {
+                    res.status(StatusCode::NOT_ACCEPTABLE);
+                    return RequestContinuation::None;
+                }
+            }
+        };
+
+        let path = versioned_path(&version, req.uri().path());
+        set_path(req, &path);
+        req.extensions_mut().insert(ApiVersion { version, from_path: false });
+        RequestContinuation::Next
+    }
+
+    fn after(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        if !req.extensions().get::<ApiVersion>().is_some_and(|version| version.from_path) {
+            res.header(header::VARY, "Accept");
+        }
+    }
+}
+
+impl SyncRequest {
+    /// Returns the version of the API the request is addressed to, as told by `ApiVersioning`, without its `v` prefix
+    pub fn api_version(&self) -> Option<&str> {
+        self.extensions().get::<ApiVersion>().map(|version| version.version.as_str())
+    }
+}
+
+/// Returns `path` prefixed with `version`
+fn versioned_path(version: &str, path: &str) -> String {
+    match path {
+        "/" => format!("/v{}", version),
+        path => format!("/v{}{}", version, path),
+    }
+}
+
+/// Returns the route matching `route` under the path prefix of each of `versions`, like `^/v(?:1|2)/users` for `^/users`
+pub(crate) fn versioned_route(versions: &[&str], route: &str) -> String {
+    let versions: Vec<String> = versions.iter().map(|version| ::regex::escape(version)).collect();
+    format!("^/v(?:{}){}", versions.join("|"), route.trim_start_matches('^'))
+}
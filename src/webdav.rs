@@ -0,0 +1,867 @@
+use http::*;
+use http::header::HttpDate;
+use controller::Controller;
+use controller::RouteInfo;
+use query::percent_decode;
+use xml_rs::reader::EventReader;
+use xml_rs::reader::XmlEvent;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const DAV_NS: &str = "DAV:";
+const DAV_METHODS: &str = "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+const LIVE_PROPERTIES: &[&str] = &["resourcetype", "getcontentlength", "getlastmodified", "getetag", "supportedlock", "lockdiscovery"];
+const DEFAULT_LOCK_TIMEOUT: u64 = 60 * 60;
+const MAX_LOCK_TIMEOUT: u64 = 7 * 24 * 60 * 60;
+
+/// The metadata of a resource stored by a `DavFileSystem`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DavMetadata {
+    /// Whether the resource is a collection
+    pub is_dir: bool,
+    /// The size of the resource content, in bytes
+    pub len: u64,
+    /// The time of the last modification of the resource, if known
+    pub modified: Option<SystemTime>,
+}
+
+/// A trait representing the storage served by a `WebDavController`
+///
+/// Paths are decoded, `/` separated and relative to the root of the storage, which is `/` itself. They never contain `.`
+/// or `..` segments.
+pub trait DavFileSystem: Send + Sync {
+    /// Return the metadata of the resource at `path`, failing with `NotFound` when it does not exist
+    fn metadata(&self, path: &str) -> io::Result<DavMetadata>;
+
+    /// List the names of the members of the collection at `path`
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>>;
+
+    /// Read the content of the resource at `path`
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    /// Create or replace the resource at `path` with `data`
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Create the collection at `path`, its parent exists
+    fn create_dir(&self, path: &str) -> io::Result<()>;
+
+    /// Remove the resource at `path`, along with its members when it is a collection
+    fn remove(&self, path: &str) -> io::Result<()>;
+
+    /// Copy the resource at `from` and its members to `to`, which does not exist
+    fn copy(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// Move the resource at `from` and its members to `to`, which does not exist
+    fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+}
+
+/// A `DavFileSystem` serving a directory of the local file system
+pub struct LocalFileSystem {
+    root: PathBuf,
+}
+
+impl LocalFileSystem {
+    /// Serve the content of the `root` directory
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalFileSystem {
+            root: root.into(),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let mut resolved = self.root.clone();
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            if segment == "." || segment == ".." || segment.contains('\\') {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the path escapes the root directory"));
+            }
+            resolved.push(segment);
+        }
+        Ok(resolved)
+    }
+
+    fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+        if from.is_dir() {
+            fs::create_dir(to)?;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                Self::copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+            }
+            Ok(())
+        } else {
+            fs::copy(from, to).map(|_| ())
+        }
+    }
+}
+
+impl DavFileSystem for LocalFileSystem {
+    fn metadata(&self, path: &str) -> io::Result<DavMetadata> {
+        let metadata = fs::metadata(self.resolve(path)?)?;
+        Ok(DavMetadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        fs::read_dir(self.resolve(path)?)?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path)?)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        fs::write(self.resolve(path)?, data)
+    }
+
+    fn create_dir(&self, path: &str) -> io::Result<()> {
+        fs::create_dir(self.resolve(path)?)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        let path = self.resolve(path)?;
+        if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn copy(&self, from: &str, to: &str) -> io::Result<()> {
+        Self::copy_recursive(&self.resolve(from)?, &self.resolve(to)?)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        fs::rename(self.resolve(from)?, self.resolve(to)?)
+    }
+}
+
+/// A parsed XML element, with its namespace resolved
+#[derive(Debug, Default)]
+struct Element {
+    namespace: String,
+    name: String,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn parse(body: &[u8]) -> Option<Element> {
+        let mut stack: Vec<Element> = Vec::new();
+
+        for event in EventReader::new(body) {
+            match event.ok()? {
+                XmlEvent::StartElement { name, .. } => stack.push(Element {
+                    namespace: name.namespace.unwrap_or_default(),
+                    name: name.local_name,
+                    ..Element::default()
+                }),
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop()?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Some(element),
+                    }
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn is(&self, name: &str) -> bool {
+        self.namespace == DAV_NS && self.name == name
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.is(name))
+    }
+
+    /// The text content of the element and of its descendants
+    fn inner_text(&self) -> String {
+        self.children.iter().fold(self.text.trim().to_string(), |mut text, child| {
+            text.push_str(&child.inner_text());
+            text
+        })
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn status_line(status: StatusCode) -> String {
+    format!("HTTP/1.1 {} {}", status.as_u16(), status.canonical_reason().unwrap_or(""))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &path[..i],
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
+
+fn is_descendant(path: &str, ancestor: &str) -> bool {
+    if ancestor == "/" {
+        path != "/"
+    } else {
+        path.len() > ancestor.len() && path.starts_with(ancestor) && path.as_bytes()[ancestor.len()] == b'/'
+    }
+}
+
+fn error_status(e: &io::Error) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        io::ErrorKind::AlreadyExists => StatusCode::METHOD_NOT_ALLOWED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn lock_token() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::SeqCst) as u64;
+
+    let random = |salt: u64| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(count);
+        hasher.write_u64(salt);
+        hasher.finish()
+    };
+    let (high, low) = (random(0), random(1));
+
+    format!("urn:uuid:{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}", high >> 32, (high >> 16) & 0xffff, high & 0x0fff,
+            0x8000 | (low >> 48) & 0x3fff, low & 0xffff_ffff_ffff)
+}
+
+/// The dead properties of a resource, by namespace and name
+type DeadProperties = BTreeMap<(String, String), String>;
+
+/// A lock held on a resource, or on a whole tree of resources
+#[derive(Debug, Clone)]
+struct ActiveLock {
+    token: String,
+    root: String,
+    infinite: bool,
+    exclusive: bool,
+    owner: Option<String>,
+    timeout: u64,
+    expires: Instant,
+}
+
+impl ActiveLock {
+    fn covers(&self, path: &str) -> bool {
+        self.root == path || (self.infinite && is_descendant(path, &self.root))
+    }
+
+    fn to_xml(&self, href: &str) -> String {
+        format!("<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{}/></D:lockscope><D:depth>{}</D:depth>\
+                 {}<D:timeout>Second-{}</D:timeout><D:locktoken><D:href>{}</D:href></D:locktoken>\
+                 <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+                if self.exclusive { "exclusive" } else { "shared" },
+                if self.infinite { "infinity" } else { "0" },
+                self.owner.as_ref().map(|o| format!("<D:owner>{}</D:owner>", xml_escape(o))).unwrap_or_default(),
+                self.timeout, xml_escape(&self.token), xml_escape(href))
+    }
+}
+
+/// The properties requested by a `PROPFIND` request
+enum PropFind {
+    AllProp,
+    PropName,
+    Prop(Vec<(String, String)>),
+}
+
+/// A controller serving a `DavFileSystem` over WebDAV (RFC 4918), with class 1 and 2 compliance
+///
+/// Besides `GET`, `HEAD`, `PUT` and `DELETE`, the controller answers the `PROPFIND`, `PROPPATCH`, `MKCOL`, `COPY`, `MOVE`,
+/// `LOCK` and `UNLOCK` methods. Dead properties and write locks are kept in memory, locked resources can only be modified
+/// by requests submitting the lock token in their `If` header.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let dav = WebDavController::new("/dav", LocalFileSystem::new("/srv/dav"));
+///
+/// let mut router = Router::new();
+/// router.add("^/dav(/|$)", dav);
+/// ```
+pub struct WebDavController {
+    prefix: String,
+    fs: Box<dyn DavFileSystem>,
+    properties: RwLock<HashMap<String, DeadProperties>>,
+    locks: Mutex<Vec<ActiveLock>>,
+}
+
+impl WebDavController {
+    /// Serve `fs` under `prefix`, the path the controller is registered under in the router
+    pub fn new<P: Into<String>, F: 'static + DavFileSystem>(prefix: P, fs: F) -> Self {
+        WebDavController {
+            prefix: prefix.into().trim_end_matches('/').to_string(),
+            fs: Box::new(fs),
+            properties: RwLock::new(HashMap::new()),
+            locks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Map the path of a request URI to a resource path, `None` when it is outside of the prefix or malformed
+    fn resource_path(&self, uri_path: &str) -> Option<String> {
+        if !uri_path.starts_with(&self.prefix) {
+            return None;
+        }
+
+        let rest = &uri_path[self.prefix.len()..];
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+
+        let mut path = String::new();
+        for segment in rest.split('/').filter(|s| !s.is_empty()) {
+            let segment = percent_decode(segment);
+            if segment == "." || segment == ".." || segment.contains('/') || segment.contains('\0') {
+                return None;
+            }
+            path.push('/');
+            path.push_str(&segment);
+        }
+
+        if path.is_empty() {
+            path.push('/');
+        }
+        Some(path)
+    }
+
+    fn href(&self, path: &str, is_dir: bool) -> String {
+        let mut href = self.prefix.clone();
+        for segment in path.split('/').filter(|s| !s.is_empty()) {
+            href.push('/');
+            for b in segment.bytes() {
+                if b.is_ascii_alphanumeric() || b"-._~!$'()*+,;=:@".contains(&b) {
+                    href.push(b as char);
+                } else {
+                    href.push_str(&format!("%{:02X}", b));
+                }
+            }
+        }
+        if is_dir || href.is_empty() {
+            href.push('/');
+        }
+        href
+    }
+
+    fn destination(&self, req: &SyncRequest) -> Result<String, StatusCode> {
+        let destination = req.headers_map().get("destination").and_then(|d| d.to_str().ok()).ok_or(StatusCode::BAD_REQUEST)?;
+        let uri = destination.parse::<Uri>().map_err(|_| StatusCode::BAD_REQUEST)?;
+        self.resource_path(uri.path()).ok_or(StatusCode::BAD_GATEWAY)
+    }
+
+    /// The lock tokens submitted in the `If` header of `req`
+    fn submitted_tokens(req: &SyncRequest) -> Vec<String> {
+        req.headers_map().get_all("if").iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split('<').skip(1).filter_map(|part| part.split('>').next()).map(str::to_string).collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// Check that `req` submitted the tokens of the locks protecting `path`, including the ones held on its members when
+    /// `subtree` is set and on its parent collection, whose membership changes, when `membership` is set
+    fn check_locks(&self, req: &SyncRequest, path: &str, subtree: bool, membership: bool) -> Result<(), StatusCode> {
+        let tokens = Self::submitted_tokens(req);
+        let now = Instant::now();
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|lock| lock.expires > now);
+
+        let protected = locks.iter().any(|lock| {
+            let applies = lock.covers(path)
+                || (subtree && is_descendant(&lock.root, path))
+                || (membership && path != "/" && lock.root == parent(path));
+            applies && !tokens.contains(&lock.token)
+        });
+
+        if protected { Err(StatusCode::LOCKED) } else { Ok(()) }
+    }
+
+    fn lock_discovery(&self, path: &str) -> String {
+        let now = Instant::now();
+        self.locks.lock().unwrap().iter()
+            .filter(|lock| lock.expires > now && lock.covers(path))
+            .map(|lock| lock.to_xml(&self.href(&lock.root, false)))
+            .collect()
+    }
+
+    /// Carry the dead properties of `from` and its members over to `to`, removing them from `from` unless `keep_source` is set
+    fn update_properties(&self, from: &str, to: Option<&str>, keep_source: bool) {
+        let mut properties = self.properties.write().unwrap();
+        let affected: Vec<String> = properties.keys().filter(|p| *p == from || is_descendant(p, from)).cloned().collect();
+
+        for path in affected {
+            let props = if keep_source { properties.get(&path).cloned() } else { properties.remove(&path) };
+            if let (Some(props), Some(to)) = (props, to) {
+                properties.insert(format!("{}{}", to.trim_end_matches('/'), &path[from.len()..]), props);
+            }
+        }
+    }
+
+    fn live_property(&self, path: &str, metadata: &DavMetadata, name: &str) -> Option<String> {
+        match name {
+            "resourcetype" => Some(if metadata.is_dir { "<D:collection/>".to_string() } else { String::new() }),
+            "getcontentlength" if !metadata.is_dir => Some(metadata.len.to_string()),
+            "getlastmodified" => metadata.modified.map(|m| HttpDate::from(m).to_string()),
+            "getetag" if !metadata.is_dir => {
+                let modified = metadata.modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+                Some(xml_escape(&format!("\"{:x}-{:x}\"", metadata.len, modified)))
+            }
+            "supportedlock" => Some("<D:lockentry><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>\
+                                     <D:lockentry><D:lockscope><D:shared/></D:lockscope><D:locktype><D:write/></D:locktype></D:lockentry>".to_string()),
+            "lockdiscovery" => Some(self.lock_discovery(path)),
+            _ => None,
+        }
+    }
+
+    fn property_xml(namespace: &str, name: &str, value: Option<&str>) -> String {
+        let open = if namespace == DAV_NS {
+            format!("D:{}", name)
+        } else {
+            format!("{} xmlns=\"{}\"", name, xml_escape(namespace))
+        };
+        let close = if namespace == DAV_NS { format!("D:{}", name) } else { name.to_string() };
+
+        match value {
+            Some(value) if !value.is_empty() => format!("<{}>{}</{}>", open, value, close),
+            _ => format!("<{}/>", open),
+        }
+    }
+
+    fn propstat(props: &str, status: StatusCode) -> String {
+        format!("<D:propstat><D:prop>{}</D:prop><D:status>{}</D:status></D:propstat>", props, status_line(status))
+    }
+
+    fn propfind_response(&self, path: &str, metadata: &DavMetadata, request: &PropFind) -> String {
+        let properties = self.properties.read().unwrap();
+        let dead = properties.get(path);
+        let mut found = String::new();
+        let mut missing = String::new();
+
+        match *request {
+            PropFind::AllProp | PropFind::PropName => {
+                let names_only = matches!(*request, PropFind::PropName);
+                for name in LIVE_PROPERTIES {
+                    if let Some(value) = self.live_property(path, metadata, name) {
+                        found.push_str(&Self::property_xml(DAV_NS, name, if names_only { None } else { Some(&value) }));
+                    }
+                }
+                for ((namespace, name), value) in dead.into_iter().flat_map(|d| d.iter()) {
+                    let value = xml_escape(value);
+                    found.push_str(&Self::property_xml(namespace, name, if names_only { None } else { Some(&value) }));
+                }
+            }
+            PropFind::Prop(ref names) => {
+                for (namespace, name) in names {
+                    let value = if namespace == DAV_NS {
+                        self.live_property(path, metadata, name)
+                    } else {
+                        dead.and_then(|d| d.get(&(namespace.clone(), name.clone()))).map(|v| xml_escape(v))
+                    };
+
+                    match value {
+                        Some(value) => found.push_str(&Self::property_xml(namespace, name, Some(&value))),
+                        None => missing.push_str(&Self::property_xml(namespace, name, None)),
+                    }
+                }
+            }
+        }
+
+        let mut response = format!("<D:response><D:href>{}</D:href>", xml_escape(&self.href(path, metadata.is_dir)));
+        if !found.is_empty() || missing.is_empty() {
+            response.push_str(&Self::propstat(&found, StatusCode::OK));
+        }
+        if !missing.is_empty() {
+            response.push_str(&Self::propstat(&missing, StatusCode::NOT_FOUND));
+        }
+        response.push_str("</D:response>");
+        response
+    }
+
+    fn multistatus(res: &mut SyncResponse, responses: &str) {
+        res.status(StatusCode::MULTI_STATUS)
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>", responses));
+    }
+
+    fn depth(req: &SyncRequest) -> Option<u32> {
+        match req.headers_map().get("depth").and_then(|d| d.to_str().ok()).map(|d| d.trim().to_ascii_lowercase()) {
+            Some(ref depth) if depth == "0" => Some(0),
+            Some(ref depth) if depth == "1" => Some(1),
+            _ => None,
+        }
+    }
+
+    fn propfind(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        let request = if req.body().iter().all(u8::is_ascii_whitespace) {
+            PropFind::AllProp
+        } else {
+            let root = Element::parse(req.body()).filter(|e| e.is("propfind")).ok_or(StatusCode::BAD_REQUEST)?;
+            if let Some(prop) = root.child("prop") {
+                PropFind::Prop(prop.children.iter().map(|c| (c.namespace.clone(), c.name.clone())).collect())
+            } else if root.child("propname").is_some() {
+                PropFind::PropName
+            } else {
+                PropFind::AllProp
+            }
+        };
+
+        let metadata = self.fs.metadata(path).map_err(|e| error_status(&e))?;
+        let max_depth = Self::depth(req);
+
+        let mut responses = String::new();
+        let mut pending = vec![(path.to_string(), metadata, 0)];
+        while let Some((path, metadata, depth)) = pending.pop() {
+            responses.push_str(&self.propfind_response(&path, &metadata, &request));
+
+            if metadata.is_dir && max_depth.is_none_or(|max| depth < max) {
+                let mut names = self.fs.read_dir(&path).map_err(|e| error_status(&e))?;
+                names.sort();
+                for name in names.into_iter().rev() {
+                    let member = join(&path, &name);
+                    if let Ok(metadata) = self.fs.metadata(&member) {
+                        pending.push((member, metadata, depth + 1));
+                    }
+                }
+            }
+        }
+
+        Self::multistatus(res, &responses);
+        Ok(())
+    }
+
+    fn proppatch(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        self.fs.metadata(path).map_err(|e| error_status(&e))?;
+        self.check_locks(req, path, false, false)?;
+
+        let root = Element::parse(req.body()).filter(|e| e.is("propertyupdate")).ok_or(StatusCode::BAD_REQUEST)?;
+        let mut updates = Vec::new();
+        for instruction in root.children.iter().filter(|c| c.is("set") || c.is("remove")) {
+            for prop in instruction.children.iter().filter(|c| c.is("prop")).flat_map(|p| p.children.iter()) {
+                let value = if instruction.is("set") { Some(prop.inner_text()) } else { None };
+                updates.push((prop.namespace.clone(), prop.name.clone(), value));
+            }
+        }
+
+        let protected = updates.iter().any(|(namespace, _, _)| namespace == DAV_NS);
+        let mut props: BTreeMap<StatusCode, String> = BTreeMap::new();
+        for (namespace, name, _) in &updates {
+            let status = match (protected, namespace == DAV_NS) {
+                (false, _) => StatusCode::OK,
+                (true, true) => StatusCode::FORBIDDEN,
+                (true, false) => StatusCode::FAILED_DEPENDENCY,
+            };
+            props.entry(status).or_default().push_str(&Self::property_xml(namespace, name, None));
+        }
+
+        if !protected {
+            let mut properties = self.properties.write().unwrap();
+            let dead = properties.entry(path.to_string()).or_default();
+            for (namespace, name, value) in updates {
+                match value {
+                    Some(value) => { dead.insert((namespace, name), value); }
+                    None => { dead.remove(&(namespace, name)); }
+                }
+            }
+        }
+
+        let is_dir = self.fs.metadata(path).map(|m| m.is_dir).unwrap_or(false);
+        let mut response = format!("<D:response><D:href>{}</D:href>", xml_escape(&self.href(path, is_dir)));
+        for (status, props) in props {
+            response.push_str(&Self::propstat(&props, status));
+        }
+        response.push_str("</D:response>");
+
+        Self::multistatus(res, &response);
+        Ok(())
+    }
+
+    fn get(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        let metadata = self.fs.metadata(path).map_err(|e| error_status(&e))?;
+        if metadata.is_dir {
+            res.header(header::ALLOW, DAV_METHODS.replace(" GET, HEAD,", ""));
+            return Err(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let content = if *req.method() == Method::HEAD { Vec::new() } else { self.fs.read(path).map_err(|e| error_status(&e))? };
+        if let Some(modified) = metadata.modified {
+            res.header(header::LAST_MODIFIED, HttpDate::from(modified).to_string());
+        }
+        res.status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, metadata.len.to_string())
+            .body(content);
+        Ok(())
+    }
+
+    fn put(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        let existing = self.fs.metadata(path).ok();
+        if existing.as_ref().is_some_and(|m| m.is_dir) {
+            return Err(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        if path == "/" || !self.fs.metadata(parent(path)).map(|m| m.is_dir).unwrap_or(false) {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        self.check_locks(req, path, false, existing.is_none())?;
+        self.fs.write(path, req.body()).map_err(|e| error_status(&e))?;
+        res.status(if existing.is_some() { StatusCode::NO_CONTENT } else { StatusCode::CREATED });
+        Ok(())
+    }
+
+    fn delete(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        self.fs.metadata(path).map_err(|e| error_status(&e))?;
+        if path == "/" {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        self.check_locks(req, path, true, true)?;
+        self.fs.remove(path).map_err(|e| error_status(&e))?;
+        self.update_properties(path, None, false);
+        self.locks.lock().unwrap().retain(|lock| !lock.covers(path) && !is_descendant(&lock.root, path));
+        res.status(StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    fn mkcol(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        if !req.body().is_empty() {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        if self.fs.metadata(path).is_ok() {
+            return Err(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        if !self.fs.metadata(parent(path)).map(|m| m.is_dir).unwrap_or(false) {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        self.check_locks(req, path, false, true)?;
+        self.fs.create_dir(path).map_err(|e| error_status(&e))?;
+        res.status(StatusCode::CREATED);
+        Ok(())
+    }
+
+    fn copy_or_move(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str, is_move: bool) -> Result<(), StatusCode> {
+        let metadata = self.fs.metadata(path).map_err(|e| error_status(&e))?;
+        let destination = self.destination(req)?;
+        if destination == path || is_descendant(&destination, path) || (is_move && path == "/") {
+            return Err(StatusCode::FORBIDDEN);
+        }
+        if !self.fs.metadata(parent(&destination)).map(|m| m.is_dir).unwrap_or(false) {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        let overwrite = req.headers_map().get("overwrite").and_then(|o| o.to_str().ok()).is_none_or(|o| !o.trim().eq_ignore_ascii_case("F"));
+        let exists = self.fs.metadata(&destination).is_ok();
+        if exists && !overwrite {
+            return Err(StatusCode::PRECONDITION_FAILED);
+        }
+
+        if is_move {
+            self.check_locks(req, path, true, true)?;
+        }
+        self.check_locks(req, &destination, true, true)?;
+
+        if exists {
+            self.fs.remove(&destination).map_err(|e| error_status(&e))?;
+            self.update_properties(&destination, None, false);
+        }
+
+        if is_move {
+            self.fs.rename(path, &destination).map_err(|e| error_status(&e))?;
+            self.update_properties(path, Some(&destination), false);
+            self.locks.lock().unwrap().retain(|lock| !lock.covers(path) && !is_descendant(&lock.root, path));
+        } else if metadata.is_dir && Self::depth(req) == Some(0) {
+            self.fs.create_dir(&destination).map_err(|e| error_status(&e))?;
+        } else {
+            self.fs.copy(path, &destination).map_err(|e| error_status(&e))?;
+            self.update_properties(path, Some(&destination), true);
+        }
+
+        res.status(if exists { StatusCode::NO_CONTENT } else { StatusCode::CREATED });
+        Ok(())
+    }
+
+    fn lock_timeout(req: &SyncRequest) -> u64 {
+        let timeout = req.headers_map().get("timeout").and_then(|t| t.to_str().ok()).and_then(|t| t.split(',').next()).map(str::trim);
+        match timeout {
+            Some(t) if t.eq_ignore_ascii_case("infinite") => MAX_LOCK_TIMEOUT,
+            Some(t) if t.len() > 7 && t[..7].eq_ignore_ascii_case("second-") => t[7..].parse().unwrap_or(DEFAULT_LOCK_TIMEOUT).min(MAX_LOCK_TIMEOUT),
+            _ => DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    fn lock(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        let timeout = Self::lock_timeout(req);
+
+        if req.body().iter().all(u8::is_ascii_whitespace) {
+            // Refresh of an existing lock, identified by the token submitted in the If header
+            let tokens = Self::submitted_tokens(req);
+            let refreshed = {
+                let mut locks = self.locks.lock().unwrap();
+                let now = Instant::now();
+                locks.iter_mut()
+                    .find(|lock| lock.expires > now && lock.covers(path) && tokens.contains(&lock.token))
+                    .map(|lock| {
+                        lock.timeout = timeout;
+                        lock.expires = now + Duration::from_secs(timeout);
+                        lock.to_xml(&self.href(&lock.root, false))
+                    })
+            };
+
+            let discovery = refreshed.ok_or(StatusCode::PRECONDITION_FAILED)?;
+            res.status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+                .body(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>", discovery));
+            return Ok(());
+        }
+
+        let info = Element::parse(req.body()).filter(|e| e.is("lockinfo")).ok_or(StatusCode::BAD_REQUEST)?;
+        let exclusive = info.child("lockscope").is_none_or(|scope| scope.child("shared").is_none());
+        let infinite = match req.headers_map().get("depth").and_then(|d| d.to_str().ok()).map(str::trim) {
+            Some("0") => false,
+            Some(d) if !d.eq_ignore_ascii_case("infinity") => return Err(StatusCode::BAD_REQUEST),
+            _ => true,
+        };
+
+        let lock = ActiveLock {
+            token: lock_token(),
+            root: path.to_string(),
+            infinite,
+            exclusive,
+            owner: info.child("owner").map(Element::inner_text).filter(|o| !o.is_empty()),
+            timeout,
+            expires: Instant::now() + Duration::from_secs(timeout),
+        };
+
+        {
+            let now = Instant::now();
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|l| l.expires > now);
+            let conflict = locks.iter().any(|l| {
+                (l.covers(path) || (infinite && is_descendant(&l.root, path))) && (exclusive || l.exclusive)
+            });
+            if conflict {
+                return Err(StatusCode::LOCKED);
+            }
+        }
+
+        let created = match self.fs.metadata(path) {
+            Ok(_) => false,
+            Err(_) => {
+                if !self.fs.metadata(parent(path)).map(|m| m.is_dir).unwrap_or(false) {
+                    return Err(StatusCode::CONFLICT);
+                }
+                self.check_locks(req, path, false, true)?;
+                self.fs.write(path, &[]).map_err(|e| error_status(&e))?;
+                true
+            }
+        };
+
+        let is_dir = self.fs.metadata(path).map(|m| m.is_dir).unwrap_or(false);
+        let discovery = lock.to_xml(&self.href(path, is_dir));
+        let token = lock.token.clone();
+        self.locks.lock().unwrap().push(lock);
+
+        res.status(if created { StatusCode::CREATED } else { StatusCode::OK })
+            .header("lock-token", format!("<{}>", token))
+            .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>", discovery));
+        Ok(())
+    }
+
+    fn unlock(&self, req: &SyncRequest, res: &mut SyncResponse, path: &str) -> Result<(), StatusCode> {
+        let token = req.headers_map().get("lock-token").and_then(|t| t.to_str().ok())
+            .map(|t| t.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .ok_or(StatusCode::BAD_REQUEST)?;
+
+        let mut locks = self.locks.lock().unwrap();
+        let count = locks.len();
+        locks.retain(|lock| !(lock.token == token && lock.covers(path)));
+        if locks.len() == count {
+            return Err(StatusCode::CONFLICT);
+        }
+
+        res.status(StatusCode::NO_CONTENT);
+        Ok(())
+    }
+}
+
+impl Controller for WebDavController {
+    fn handle(&self, req: &SyncRequest, res: &mut SyncResponse) {
+        let path = match self.resource_path(req.uri().path()) {
+            Some(path) => path,
+            None => {
+                res.status(StatusCode::BAD_REQUEST);
+                return;
+            }
+        };
+
+        let result = match req.method().as_str() {
+            "OPTIONS" => {
+                res.status(StatusCode::OK).header("dav", "1, 2").header(header::ALLOW, DAV_METHODS);
+                Ok(())
+            }
+            "GET" | "HEAD" => self.get(req, res, &path),
+            "PUT" => self.put(req, res, &path),
+            "DELETE" => self.delete(req, res, &path),
+            "MKCOL" => self.mkcol(req, res, &path),
+            "PROPFIND" => self.propfind(req, res, &path),
+            "PROPPATCH" => self.proppatch(req, res, &path),
+            "COPY" => self.copy_or_move(req, res, &path, false),
+            "MOVE" => self.copy_or_move(req, res, &path, true),
+            "LOCK" => self.lock(req, res, &path),
+            "UNLOCK" => self.unlock(req, res, &path),
+            _ => {
+                res.header(header::ALLOW, DAV_METHODS);
+                Err(StatusCode::METHOD_NOT_ALLOWED)
+            }
+        };
+
+        if let Err(status) = result {
+            res.status(status);
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        DAV_METHODS.split(", ")
+            .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+            .map(|method| RouteInfo::new("WebDavController", Some(method), None, Vec::new()))
+            .collect()
+    }
+}
@@ -0,0 +1,419 @@
+use http::*;
+use credentials::random_token;
+use logging::*;
+use server::Server;
+use webhook_signature::WebhookSignatureGuard;
+use log::Level;
+use futures::Future;
+use futures::IntoFuture;
+use futures::Stream;
+use futures::future::loop_fn;
+use futures::future::Loop;
+use futures::stream;
+use futures::sync::mpsc::unbounded;
+use futures::sync::mpsc::UnboundedReceiver;
+use futures::sync::mpsc::UnboundedSender;
+use hyper::Body;
+use hyper::Client;
+use serde::Serialize;
+use tokio::timer::Delay;
+use tokio::timer::Timeout;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// A webhook waiting to be delivered, as kept by a `WebhookStore`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    /// The unique id of the delivery, sent in `X-Webhook-Id` so receivers can ignore the deliveries retried after they
+    /// were received
+    pub id: String,
+    /// Where the webhook is posted
+    pub url: String,
+    /// The name of the event, sent in `X-Webhook-Event`
+    pub event: String,
+    /// The JSON body of the webhook
+    pub payload: String,
+    /// How many attempts failed so far
+    pub attempts: u32,
+    /// When the next attempt is due, in milliseconds since the Unix epoch
+    pub next_attempt: u64,
+}
+
+/// A trait representing where a `WebhookDispatcher` keeps the deliveries which aren't delivered yet, so they are resumed
+/// once the server restarts
+pub trait WebhookStore: Send + Sync {
+    /// Keep `delivery`, replacing the delivery with the same id
+    fn save(&self, delivery: &WebhookDelivery) -> io::Result<()>;
+
+    /// Forget the delivery `id`, once delivered or abandoned
+    fn remove(&self, id: &str) -> io::Result<()>;
+
+    /// Returns the deliveries kept, resumed when the dispatcher starts
+    fn pending(&self) -> io::Result<Vec<WebhookDelivery>>;
+}
+
+/// A `WebhookStore` keeping the deliveries in memory, which are lost when the process exits
+#[derive(Default)]
+pub struct MemoryWebhookStore {
+    deliveries: Mutex<BTreeMap<String, WebhookDelivery>>,
+}
+
+impl MemoryWebhookStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        MemoryWebhookStore::default()
+    }
+}
+
+impl WebhookStore for MemoryWebhookStore {
+    fn save(&self, delivery: &WebhookDelivery) -> io::Result<()> {
+        self.deliveries.lock().unwrap_or_else(|e| e.into_inner()).insert(delivery.id.clone(), delivery.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.deliveries.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+        Ok(())
+    }
+
+    fn pending(&self) -> io::Result<Vec<WebhookDelivery>> {
+        Ok(self.deliveries.lock().unwrap_or_else(|e| e.into_inner()).values().cloned().collect())
+    }
+}
+
+/// A `WebhookStore` keeping each delivery in a JSON file of a directory
+pub struct DirectoryWebhookStore {
+    directory: PathBuf,
+}
+
+impl DirectoryWebhookStore {
+    /// Keep the deliveries in `directory`, created if it doesn't exist
+    pub fn open<P: AsRef<Path>>(directory: P) -> io::Result<Self> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(DirectoryWebhookStore { directory: directory.as_ref().to_path_buf() })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", id))
+    }
+}
+
+impl WebhookStore for DirectoryWebhookStore {
+    fn save(&self, delivery: &WebhookDelivery) -> io::Result<()> {
+        // Written aside then renamed, so a crash never leaves a truncated delivery behind
+        let partial = self.directory.join(format!("{}.json.partial", delivery.id));
+        fs::write(&partial, ::serde_json::to_vec(delivery).map_err(io::Error::other)?)?;
+        fs::rename(&partial, self.path(&delivery.id))
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn pending(&self) -> io::Result<Vec<WebhookDelivery>> {
+        let mut deliveries = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let delivery = ::serde_json::from_slice(&fs::read(&path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                deliveries.push(delivery);
+            }
+        }
+        Ok(deliveries)
+    }
+}
+
+/// How the deliveries are attempted
+#[derive(Clone)]
+struct Policy {
+    signer: Arc<WebhookSignatureGuard>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    timeout: Duration,
+}
+
+impl Policy {
+    /// Returns how long to wait after the failed attempt `attempts`, doubling after every failure
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.initial_backoff.checked_mul(1 << attempts.saturating_sub(1).min(31)).unwrap_or(self.max_backoff).min(self.max_backoff)
+    }
+}
+
+/// Deliver webhooks enqueued by the application to their receivers, for the lifetime of a server
+///
+/// A webhook is posted as JSON, along with its id in `X-Webhook-Id`, its event in `X-Webhook-Event`, the time of the
+/// attempt in seconds since the Unix epoch in `X-Webhook-Timestamp` and, in `X-Signature`, `sha256=` followed by the
+/// hexadecimal HMAC-SHA256 of `<timestamp>.<body>` keyed with the secret. Receivers verify it with
+/// `WebhookSignatureGuard::new(secret).prefix("sha256=").timestamp("x-webhook-timestamp", tolerance)`.
+///
+/// A delivery is attempted until it is answered with a success status. Attempts which fail, time out, or are answered
+/// with `408 Request Timeout`, `429 Too Many Requests` or a server error are retried after a delay doubling after every
+/// failure, until the maximum number of attempts is reached. Deliveries answered with another client error are abandoned
+/// right away. Every outcome is logged with the `WEBHOOK_LOG_TARGET` target.
+///
+/// Deliveries are kept in a `WebhookStore` until they are delivered or abandoned, in memory by default. With a persistent
+/// store, deliveries still pending when the server stops are resumed by the dispatcher of the next server.
+///
+/// Deliveries run on the background tasks thread of the server, see `Server::spawn_task`, and are enqueued through a
+/// `WebhookHandle`, which can be registered as a state for controllers to use.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// use std::time::Duration;
+///
+/// let dispatcher = WebhookDispatcher::new(b"whsec_5WbX5kEWLlfzsGNjH64I8lOOqUB6e8FH")
+///     .store(DirectoryWebhookStore::open("/var/lib/billing/webhooks").unwrap())
+///     .backoff(Duration::from_secs(10), Duration::from_secs(3600));
+///
+/// let mut controller = BasicController::new(());
+/// controller.add(Method::POST, "^/invoices$", |_, req, res| {
+///     let webhooks = req.state::<WebhookHandle>().unwrap();
+///     match webhooks.enqueue("http://partner.internal/hooks", "invoice.created", &req.body().len()) {
+///         Ok(_) => res.status(StatusCode::CREATED),
+///         Err(_) => res.status(StatusCode::INTERNAL_SERVER_ERROR),
+///     };
+/// });
+///
+/// let mut router = Router::new();
+/// router.add("^/", controller);
+///
+/// let server = Server::builder().router(router).state(dispatcher.handle()).build();
+/// dispatcher.spawn_on(&server);
+/// server.run("http://0.0.0.0:12345").unwrap();
+/// ```
+pub struct WebhookDispatcher {
+    policy: Policy,
+    store: Arc<dyn WebhookStore>,
+    sender: UnboundedSender<WebhookDelivery>,
+    receiver: UnboundedReceiver<WebhookDelivery>,
+}
+
+impl WebhookDispatcher {
+    /// Create a dispatcher signing the webhooks with `secret`, keeping the pending deliveries in memory
+    pub fn new(secret: &[u8]) -> Self {
+        let (sender, receiver) = unbounded();
+        WebhookDispatcher {
+            policy: Policy {
+                signer: Arc::new(WebhookSignatureGuard::new(secret).prefix("sha256=").timestamp("x-webhook-timestamp", Duration::from_secs(300))),
+                max_attempts: 10,
+                initial_backoff: Duration::from_secs(5),
+                max_backoff: Duration::from_secs(3600),
+                timeout: Duration::from_secs(10),
+            },
+            store: Arc::new(MemoryWebhookStore::new()),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Keep the pending deliveries in `store` rather than in memory
+    ///
+    /// Set the store before creating the handles, which enqueue the deliveries in the store the dispatcher had then.
+    pub fn store<S: 'static + WebhookStore>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Abandon a delivery once `attempts` of them failed, 10 by default
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.policy.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait `initial` after the first failed attempt, doubling the delay after every failure up to `max`, 5 seconds and
+    /// an hour by default
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.policy.initial_backoff = initial;
+        self.policy.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Fail the attempts which aren't answered within `timeout`, 10 seconds by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.policy.timeout = timeout;
+        self
+    }
+
+    /// Returns a handle to enqueue webhooks, which are delivered once the dispatcher runs
+    pub fn handle(&self) -> WebhookHandle {
+        WebhookHandle {
+            store: self.store.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Deliver the webhooks as a background task of `server`, starting once it listens with the deliveries left pending by
+    /// the previous server, and stopping when it shuts down
+    pub fn spawn_on(self, server: &Server) {
+        let WebhookDispatcher { policy, store, receiver, .. } = self;
+        server.spawn_task(move || {
+            let resumed = store.pending().unwrap_or_else(|e| {
+                log_event(Level::Error, WEBHOOK_LOG_TARGET, "webhooks not resumed", format_args!("Unable to resume the pending webhooks: {}", e),
+                          &[("error", &e)]);
+                Vec::new()
+            });
+
+            // A delivery enqueued before the server started is both pending in the store and queued
+            let in_flight = Arc::new(Mutex::new(HashSet::new()));
+            let client = Client::builder().build_http::<Body>();
+            stream::iter_ok(resumed).chain(receiver)
+                .filter({
+                    let in_flight = in_flight.clone();
+                    move |delivery| in_flight.lock().unwrap_or_else(|e| e.into_inner()).insert(delivery.id.clone())
+                })
+                .map(move |delivery| {
+                    let (in_flight, id) = (in_flight.clone(), delivery.id.clone());
+                    deliver(client.clone(), policy.clone(), store.clone(), delivery).then(move |_| {
+                        in_flight.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+                        Ok(())
+                    })
+                })
+                .buffer_unordered(usize::MAX)
+                .for_each(|_| Ok(()))
+        });
+    }
+}
+
+/// A handle to enqueue the webhooks delivered by a `WebhookDispatcher`, which can be registered as a state for controllers
+/// to use
+#[derive(Clone)]
+pub struct WebhookHandle {
+    store: Arc<dyn WebhookStore>,
+    sender: UnboundedSender<WebhookDelivery>,
+}
+
+impl WebhookHandle {
+    /// Enqueue the webhook `event` to post `payload` to `url`, returning the id of the delivery once it is kept by the store
+    /// of the dispatcher
+    pub fn enqueue<T: Serialize>(&self, url: &str, event: &str, payload: &T) -> io::Result<String> {
+        let uri = url.parse::<Uri>().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if uri.scheme_part() != Some(&::http_types::uri::Scheme::HTTP) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("webhooks can only be posted to http urls, not {}", url)));
+        }
+
+        let delivery = WebhookDelivery {
+            id: random_token(),
+            url: url.to_string(),
+            event: event.to_string(),
+            payload: ::serde_json::to_string(payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+            attempts: 0,
+            next_attempt: now_ms(),
+        };
+
+        self.store.save(&delivery)?;
+        let id = delivery.id.clone();
+        // Once the dispatcher stopped, the delivery stays in the store for the next one to resume
+        let _ = self.sender.unbounded_send(delivery);
+        Ok(id)
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() * 1000 + d.subsec_millis() as u64).unwrap_or(0)
+}
+
+/// Attempt `delivery` until it is delivered or abandoned
+fn deliver<C>(client: Client<C, Body>, policy: Policy, store: Arc<dyn WebhookStore>, delivery: WebhookDelivery) -> impl Future<Item=(), Error=()>
+    where C: 'static + ::hyper::client::connect::Connect {
+    loop_fn(delivery, move |mut delivery| {
+        let (client, policy, store) = (client.clone(), policy.clone(), store.clone());
+        let due = Instant::now() + Duration::from_millis(delivery.next_attempt.saturating_sub(now_ms()));
+
+        Delay::new(due).map_err(|e| e.to_string()).and_then(move |_| attempt(&client, &policy, &delivery).then(move |result| {
+            match result {
+                Ok(status) => {
+                    log_event(Level::Info, WEBHOOK_LOG_TARGET, "webhook delivered",
+                              format_args!("Delivered the webhook {} {} to {}, answered {}", delivery.event, delivery.id, delivery.url, status),
+                              &[("id", &delivery.id), ("event", &delivery.event), ("url", &delivery.url), ("status", &status.as_u16()),
+                                ("attempts", &(delivery.attempts + 1))]);
+                    forget(&*store, &delivery);
+                    Ok(Loop::Break(()))
+                }
+                Err((e, retry)) => {
+                    delivery.attempts += 1;
+                    if !retry || delivery.attempts >= policy.max_attempts {
+                        log_event(Level::Error, WEBHOOK_LOG_TARGET, "webhook abandoned",
+                                  format_args!("Abandoned the webhook {} {} to {} after {} attempts: {}", delivery.event, delivery.id,
+                                               delivery.url, delivery.attempts, e),
+                                  &[("id", &delivery.id), ("event", &delivery.event), ("url", &delivery.url), ("attempts", &delivery.attempts),
+                                    ("error", &e)]);
+                        forget(&*store, &delivery);
+                        return Ok(Loop::Break(()));
+                    }
+
+                    let backoff = policy.backoff(delivery.attempts);
+                    delivery.next_attempt = now_ms() + backoff.as_secs() * 1000 + backoff.subsec_millis() as u64;
+                    log_event(Level::Warn, WEBHOOK_LOG_TARGET, "webhook retried",
+                              format_args!("Unable to deliver the webhook {} {} to {}, retrying in {:?}: {}", delivery.event, delivery.id,
+                                           delivery.url, backoff, e),
+                              &[("id", &delivery.id), ("event", &delivery.event), ("url", &delivery.url), ("attempts", &delivery.attempts),
+                                ("error", &e)]);
+                    if let Err(e) = store.save(&delivery) {
+                        log_event(Level::Error, WEBHOOK_LOG_TARGET, "webhook not saved",
+                                  format_args!("Unable to save the webhook {}: {}", delivery.id, e), &[("id", &delivery.id), ("error", &e)]);
+                    }
+                    Ok(Loop::Continue(delivery))
+                }
+            }
+        })).map_err(|e| log_event(Level::Error, WEBHOOK_LOG_TARGET, "webhook timer failed", format_args!("Webhook timer error: {}", e),
+                                  &[("error", &e)]))
+    })
+}
+
+/// Post `delivery` once, returning the status it was answered with, or why it failed along with whether to retry
+fn attempt<C>(client: &Client<C, Body>, policy: &Policy, delivery: &WebhookDelivery) -> Box<dyn Future<Item=StatusCode, Error=(String, bool)> + Send>
+    where C: 'static + ::hyper::client::connect::Connect {
+    let timestamp = now_ms() / 1000;
+    let request = Request::post(delivery.url.as_str())
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::USER_AGENT, "saphir-webhook")
+        .header("x-webhook-id", delivery.id.as_str())
+        .header("x-webhook-event", delivery.event.as_str())
+        .header("x-webhook-timestamp", timestamp.to_string().as_str())
+        .header("x-signature", policy.signer.signature(timestamp, delivery.payload.as_bytes()).as_str())
+        .body(Body::from(delivery.payload.clone()));
+
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return Box::new(Err((e.to_string(), false)).into_future()),
+    };
+
+    Box::new(Timeout::new(client.request(request), policy.timeout).then(|result| match result {
+        Ok(res) => {
+            let status = res.status();
+            if status.is_success() {
+                Ok(status)
+            } else {
+                let retry = status.is_server_error() || status == StatusCode::REQUEST_TIMEOUT || status == StatusCode::TOO_MANY_REQUESTS;
+                Err((format!("answered {}", status), retry))
+            }
+        }
+        Err(ref e) if e.is_elapsed() => Err(("timed out".to_string(), true)),
+        Err(e) => Err((e.into_inner().map(|e| e.to_string()).unwrap_or_else(|| "timer error".to_string()), true)),
+    }))
+}
+
+/// Remove `delivery` from `store`, once delivered or abandoned
+fn forget(store: &dyn WebhookStore, delivery: &WebhookDelivery) {
+    if let Err(e) = store.remove(&delivery.id) {
+        log_event(Level::Error, WEBHOOK_LOG_TARGET, "webhook not removed",
+                  format_args!("Unable to remove the webhook {} from the store, it will be delivered again: {}", delivery.id, e),
+                  &[("id", &delivery.id), ("error", &e)]);
+    }
+}
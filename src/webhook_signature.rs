@@ -0,0 +1,250 @@
+use http::*;
+use controller::RequestGuard;
+use credentials::constant_time_eq;
+use credentials::hmac_sha256;
+use credentials::to_hex;
+use middleware::Middleware;
+use utils::RequestContinuation;
+use logging::*;
+use log::Level;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How the signatures of webhooks are written in their header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureEncoding {
+    /// Hexadecimal, in any case
+    Hex,
+    /// Standard base64, padded
+    Base64,
+}
+
+/// How the timestamp of a delivery is sent and signed
+#[derive(Debug, Clone)]
+struct SignedTimestamp {
+    /// The header carrying the timestamp, `None` when it is part of the signature header
+    header: Option<header::HeaderName>,
+    tolerance: Duration,
+    /// What the signed payload starts with, before the timestamp
+    prefix: String,
+    /// What separates the timestamp from the body in the signed payload
+    separator: String,
+}
+
+/// A guard rejecting with `401 Unauthorized` the webhook deliveries whose HMAC-SHA256 signature doesn't match a shared
+/// secret, before the handler sees them
+///
+/// By default, the `X-Signature` header carries the hexadecimal HMAC-SHA256 of the body, keyed with the secret. The
+/// signature is computed over the body as it was received, see `SyncRequest::raw_body`, since re-encoding a parsed body
+/// rarely gives back the same bytes. The constructors `github`, `stripe`, `slack` and `shopify` verify the schemes of
+/// these providers, and the others can be described with `header`, `prefix`, `encoding` and `timestamp`.
+///
+/// When deliveries are timestamped, the timestamp is signed along with the body and a delivery signed more than the
+/// tolerance away from the time it is received is rejected, so a captured delivery can't be replayed later on. Several
+/// secrets can be accepted at once, while rotating them.
+///
+/// The guard can also be applied to a `MiddlewareStack`, to protect every route under a path.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use saphir::*;
+/// let mut controller = BasicController::new(());
+/// controller.add_with_guards(Method::POST, "^/hooks/github$", WebhookSignatureGuard::github(b"It's a Secret to Everybody").into(),
+///                            |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+///
+/// let billing = WebhookSignatureGuard::new(b"new secret").secret(b"previous secret").header("x-billing-signature");
+/// controller.add_with_guards(Method::POST, "^/hooks/billing$", billing.into(), |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+/// ```
+pub struct WebhookSignatureGuard {
+    secrets: Vec<Vec<u8>>,
+    header: header::HeaderName,
+    prefix: String,
+    encoding: SignatureEncoding,
+    timestamp: Option<SignedTimestamp>,
+    /// Whether the timestamp and the signatures are listed in the signature header, as `t=<timestamp>,v1=<signature>`
+    stripe: bool,
+}
+
+impl WebhookSignatureGuard {
+    /// Create a guard verifying the hexadecimal HMAC-SHA256 of the body keyed with `secret`, in the `X-Signature` header
+    pub fn new(secret: &[u8]) -> Self {
+        WebhookSignatureGuard {
+            secrets: vec![secret.to_vec()],
+            header: header::HeaderName::from_static("x-signature"),
+            prefix: String::new(),
+            encoding: SignatureEncoding::Hex,
+            timestamp: None,
+            stripe: false,
+        }
+    }
+
+    /// Create a guard verifying the deliveries of GitHub, signed in `X-Hub-Signature-256` as `sha256=<hex>`
+    pub fn github(secret: &[u8]) -> Self {
+        WebhookSignatureGuard::new(secret).header("x-hub-signature-256").prefix("sha256=")
+    }
+
+    /// Create a guard verifying the deliveries of Stripe, signed in `Stripe-Signature` as `t=<timestamp>,v1=<hex>` over
+    /// `<timestamp>.<body>`, within 5 minutes
+    pub fn stripe(secret: &[u8]) -> Self {
+        let mut guard = WebhookSignatureGuard::new(secret).header("stripe-signature").prefix("v1=");
+        guard.timestamp = Some(SignedTimestamp { header: None, tolerance: Duration::from_secs(300), prefix: String::new(), separator: ".".to_string() });
+        guard.stripe = true;
+        guard
+    }
+
+    /// Create a guard verifying the requests of Slack, signed in `X-Slack-Signature` as `v0=<hex>` over
+    /// `v0:<timestamp>:<body>`, the timestamp being sent in `X-Slack-Request-Timestamp`, within 5 minutes
+    pub fn slack(secret: &[u8]) -> Self {
+        let mut guard = WebhookSignatureGuard::new(secret).header("x-slack-signature").prefix("v0=")
+            .timestamp("x-slack-request-timestamp", Duration::from_secs(300));
+        if let Some(ref mut timestamp) = guard.timestamp {
+            timestamp.prefix = "v0:".to_string();
+            timestamp.separator = ":".to_string();
+        }
+        guard
+    }
+
+    /// Create a guard verifying the deliveries of Shopify, signed in `X-Shopify-Hmac-Sha256` in base64
+    pub fn shopify(secret: &[u8]) -> Self {
+        WebhookSignatureGuard::new(secret).header("x-shopify-hmac-sha256").encoding(SignatureEncoding::Base64)
+    }
+
+    /// Also accept the deliveries signed with `secret`, while the senders switch from a secret to another
+    pub fn secret(mut self, secret: &[u8]) -> Self {
+        self.secrets.push(secret.to_vec());
+        self
+    }
+
+    /// Read the signature from the header `name`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = header::HeaderName::from_bytes(name.as_bytes()).expect("valid signature header name");
+        self
+    }
+
+    /// Expect the signature to be written after `prefix`, like `sha256=`
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Expect the signature to be written in `encoding`, hexadecimal by default
+    pub fn encoding(mut self, encoding: SignatureEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Expect the deliveries to be timestamped, in seconds since the Unix epoch, in the header `name`, the signature being
+    /// computed over `<timestamp>.<body>`, and reject the ones signed more than `tolerance` away from the time they are
+    /// received
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn timestamp(mut self, name: &str, tolerance: Duration) -> Self {
+        let header = header::HeaderName::from_bytes(name.as_bytes()).expect("valid timestamp header name");
+        self.timestamp = Some(SignedTimestamp { header: Some(header), tolerance, prefix: String::new(), separator: ".".to_string() });
+        self
+    }
+
+    /// Returns the signature of `body` sent at `timestamp` with the first secret, as written in the signature header, like
+    /// `sha256=<hex>`, or `t=<timestamp>,v1=<hex>` for Stripe. The timestamp is ignored when deliveries aren't
+    /// timestamped.
+    pub fn signature(&self, timestamp: u64, body: &[u8]) -> String {
+        let signature = format!("{}{}", self.prefix, self.sign(&self.secrets[0], &timestamp.to_string(), body));
+        if self.stripe { format!("t={},{}", timestamp, signature) } else { signature }
+    }
+
+    /// Returns the encoded signature of `body` sent at `timestamp` with `secret`
+    fn sign(&self, secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+        let mac = match self.timestamp {
+            Some(ref signed) => {
+                let mut payload = format!("{}{}{}", signed.prefix, timestamp, signed.separator).into_bytes();
+                payload.extend_from_slice(body);
+                hmac_sha256(secret, &payload)
+            }
+            None => hmac_sha256(secret, body),
+        };
+
+        match self.encoding {
+            SignatureEncoding::Hex => to_hex(&mac),
+            SignatureEncoding::Base64 => ::base64::encode(&mac),
+        }
+    }
+
+    /// Returns why `req` isn't a valid delivery, `None` when it is
+    fn rejection(&self, req: &SyncRequest) -> Option<&'static str> {
+        let value = match req.headers_map().get(&self.header).and_then(|value| value.to_str().ok()) {
+            Some(value) => value,
+            None => return Some("the signature is missing"),
+        };
+
+        let (signatures, timestamp) = if self.stripe {
+            let fields: Vec<&str> = value.split(',').map(|field| field.trim()).collect();
+            let signatures = fields.iter().filter_map(|field| field.strip_prefix(self.prefix.as_str())).collect::<Vec<_>>();
+            (signatures, fields.iter().find_map(|field| field.strip_prefix("t=")))
+        } else {
+            let signatures = value.split([',', ' ']).filter_map(|signature| signature.trim().strip_prefix(self.prefix.as_str()))
+                .filter(|signature| !signature.is_empty()).collect::<Vec<_>>();
+            let timestamp = self.timestamp.as_ref().and_then(|signed| signed.header.as_ref())
+                .and_then(|name| req.headers_map().get(name)).and_then(|value| value.to_str().ok()).map(|value| value.trim());
+            (signatures, timestamp)
+        };
+
+        if signatures.is_empty() {
+            return Some("the signature is missing");
+        }
+
+        let timestamp = match self.timestamp {
+            Some(ref signed) => {
+                let sent_at = match timestamp.and_then(|timestamp| timestamp.parse::<u64>().ok()) {
+                    Some(timestamp) => UNIX_EPOCH + Duration::from_secs(timestamp),
+                    None => return Some("the timestamp is missing or invalid"),
+                };
+
+                let now = SystemTime::now();
+                if now.duration_since(sent_at).or_else(|_| sent_at.duration_since(now)).unwrap_or_default() > signed.tolerance {
+                    return Some("the delivery was signed outside of the tolerance");
+                }
+                timestamp.unwrap_or_default()
+            }
+            None => "",
+        };
+
+        let matches = self.secrets.iter().any(|secret| {
+            let expected = self.sign(secret, timestamp, req.raw_body());
+            signatures.iter().any(|signature| match self.encoding {
+                SignatureEncoding::Hex => constant_time_eq(expected.as_bytes(), signature.to_ascii_lowercase().as_bytes()),
+                SignatureEncoding::Base64 => constant_time_eq(expected.as_bytes(), signature.as_bytes()),
+            })
+        });
+
+        if matches { None } else { Some("the signature doesn't match") }
+    }
+}
+
+impl RequestGuard for WebhookSignatureGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match self.rejection(req) {
+            Some(reason) => {
+                log_event(Level::Warn, GUARD_LOG_TARGET, "webhook rejected",
+                          format_args!("Rejected the webhook {} {}: {}", req.method(), req.uri().path(), reason),
+                          &[("method", req.method()), ("path", &req.uri().path()), ("reason", &reason)]);
+                res.status(StatusCode::UNAUTHORIZED);
+                RequestContinuation::None
+            }
+            None => RequestContinuation::Next,
+        }
+    }
+}
+
+impl Middleware for WebhookSignatureGuard {
+    fn resolve(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        self.validate(req, res)
+    }
+}
@@ -0,0 +1,43 @@
+use http::*;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use profiling::Phase;
+use profiling::profiled;
+
+impl SyncRequest {
+    /// Deserialize the body of the request from XML
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # #[macro_use] extern crate serde_derive;
+    /// # extern crate saphir;
+    /// # use saphir::*;
+    /// #[derive(Deserialize)]
+    /// struct Item {
+    ///     name: String,
+    /// }
+    ///
+    /// # fn handler(req: &SyncRequest) {
+    /// let item: Item = req.body_xml().unwrap();
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn body_xml<T: DeserializeOwned>(&self) -> Result<T, ::serde_xml_rs::Error> {
+        ::serde_xml_rs::from_reader(self.body().as_slice())
+    }
+}
+
+impl SyncResponse {
+    /// Serialize `value` as XML and set it as the body of the response, along with the `application/xml` content type.
+    ///
+    /// If the value cannot be serialized, the server answers with a `500 Internal Server Error`, see `SerializationError`.
+    pub fn xml<T: Serialize>(&mut self, value: &T) -> &mut SyncResponse {
+        match profiled(self, || Phase::Serialization, |_| ::serde_xml_rs::to_string(value)) {
+            Ok(body) => {
+                self.header(header::CONTENT_TYPE, "application/xml").body(body)
+            }
+            Err(e) => self.serialization_failed("xml", e),
+        }
+    }
+}
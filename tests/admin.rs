@@ -0,0 +1,90 @@
+extern crate serde_json;
+extern crate saphir;
+extern crate log;
+
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+fn json(res: &SyncResponse) -> Value {
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/json");
+    serde_json::from_slice(&res.get_body()).unwrap()
+}
+
+#[test]
+fn runtime_controls() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users$", |_, _, res| { res.status(StatusCode::OK).body("[]"); });
+
+    let mut health = HealthController::new();
+    health.add_readiness_check("database", || -> Result<(), String> { Ok(()) });
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+    router.add("^/readyz$", health);
+
+    let metrics = Metrics::new();
+    metrics.increment("jobs_total", &[]);
+
+    let path = ::std::env::temp_dir().join(format!("saphir-admin-{}.json", ::std::process::id()));
+    ::std::fs::write(&path, "{}").unwrap();
+    let reloader = ConfigReloader::<Value>::new(&path).unwrap();
+    let trigger = reloader.trigger();
+    let _ = ::std::fs::remove_file(&path);
+
+    let audited = Arc::new(Mutex::new(Vec::new()));
+    let kept = audited.clone();
+
+    let maintenance = MaintenanceMode::new();
+    let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .maintenance(maintenance.clone())
+        .audit_log(AuditLog::new().sink(move |record: &AuditRecord| { kept.lock().unwrap().push(record.event.action.clone()); Ok(()) }))
+        .admin_endpoint("/_admin", AdminController::new(loopback).metrics(metrics).config_reload(trigger))
+        .build());
+
+    let res = client.get("/_admin/drain").peer_addr(([10, 0, 0, 1], 4000).into()).send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+
+    let overview = json(&client.get("/_admin").send());
+    assert_eq!(overview["maintenance"], false);
+    assert_eq!(overview["draining"], false);
+
+    assert_eq!(json(&client.put("/_admin/log-level").body("debug").send())["level"], "debug");
+    assert_eq!(log::max_level(), log::LevelFilter::Debug);
+    assert_eq!(client.put("/_admin/log-level").body("loud").send().get_status(), StatusCode::BAD_REQUEST);
+
+    assert_eq!(json(&client.put("/_admin/maintenance").send())["enabled"], true);
+    assert!(maintenance.is_enabled());
+    assert_eq!(client.get("/users").send().get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json(&client.delete("/_admin/maintenance").send())["enabled"], false);
+    assert_eq!(client.get("/users").send().get_status(), StatusCode::OK);
+
+    let drain = json(&client.put("/_admin/drain").send());
+    assert_eq!(drain["draining"], true);
+    assert_eq!(drain["shutting_down"], false);
+    assert_eq!(drain["in_flight"], 0);
+    let res = client.get("/users").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONNECTION], "close");
+    let ready = client.get("/readyz").send();
+    assert_eq!(ready.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(String::from_utf8_lossy(&ready.get_body()).contains("draining"));
+    assert_eq!(json(&client.delete("/_admin/drain").send())["draining"], false);
+    assert_eq!(client.get("/readyz").send().get_status(), StatusCode::OK);
+
+    let res = client.get("/_admin/metrics").send();
+    assert!(String::from_utf8_lossy(&res.get_body()).contains("jobs_total 1"));
+
+    let routes = json(&client.get("/_admin/routes").send());
+    assert!(routes.as_array().unwrap().iter().any(|route| route["pattern"] == "^/users$"));
+
+    assert_eq!(client.post("/_admin/reload").send().get_status(), StatusCode::ACCEPTED);
+    assert_eq!(client.post("/_admin/drain").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    assert_eq!(*audited.lock().unwrap(), vec!["admin.log_level", "admin.maintenance", "admin.maintenance", "admin.draining",
+                                              "admin.draining", "admin.config_reload"]);
+}
@@ -0,0 +1,360 @@
+extern crate saphir;
+#[cfg(feature = "handlebars")]
+extern crate handlebars;
+extern crate serde_json;
+
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+fn public_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("saphir-assets-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("css")).unwrap();
+    fs::write(dir.join("css/app.css"), "body { color: red }").unwrap();
+    fs::write(dir.join("app.js"), "console.log('app')").unwrap();
+    dir
+}
+
+struct RecordingEngine(Mutex<Option<AssetManifest>>);
+
+impl TemplateEngine for RecordingEngine {
+    fn render(&self, name: &str, _context: &Value) -> Result<String, TemplateError> {
+        let manifest = self.0.lock().unwrap();
+        Ok(manifest.as_ref().map(|manifest| manifest.url(name)).unwrap_or_default())
+    }
+
+    fn asset_manifest(&mut self, manifest: &AssetManifest) {
+        *self.0.lock().unwrap() = Some(manifest.clone());
+    }
+}
+
+#[test]
+fn scanned_manifest() {
+    let dir = public_dir("scan");
+    let manifest = AssetManifest::scan(&dir).unwrap().prefix("/assets/");
+
+    let fingerprinted = manifest.fingerprinted("css/app.css").unwrap().to_string();
+    assert!(fingerprinted.starts_with("css/app.") && fingerprinted.ends_with(".css"));
+    assert_eq!(manifest.url("/css/app.css"), format!("/assets/{}", fingerprinted));
+    assert_eq!(manifest.url("missing.png"), "/assets/missing.png");
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/page$", |_, _, res| {
+        let url = res.asset_url("app.js");
+        res.status(StatusCode::OK).body(url);
+    });
+    controller.add(Method::GET, "^/template$", |_, _, res| { res.render("app.js", &()); });
+
+    let mut router = Router::new();
+    router.add("^/assets/", AssetController::new(&dir, manifest.clone()));
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .asset_manifest(manifest.clone())
+        .template_engine(RecordingEngine(Mutex::new(None)))
+        .build());
+
+    let res = client.get(&manifest.url("css/app.css")).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/css; charset=utf-8");
+    assert_eq!(res.get_body(), b"body { color: red }".to_vec());
+
+    let res = client.get("/assets/css/app.css").send();
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "no-cache");
+    let etag = res.headers_map()[header::ETAG].to_str().unwrap().to_string();
+    let res = client.get("/assets/css/app.css").header(header::IF_NONE_MATCH, etag.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::NOT_MODIFIED);
+
+    assert!(client.get("/assets/../Cargo.toml").send().get_status().is_client_error());
+    assert_eq!(client.get("/assets/missing.png").send().get_status(), StatusCode::NOT_FOUND);
+
+    let page = client.get("/page").send().get_body();
+    assert_eq!(String::from_utf8(page).unwrap(), manifest.url("app.js"));
+    let page = client.get("/template").send().get_body();
+    assert_eq!(String::from_utf8(page).unwrap(), manifest.url("app.js"));
+
+    // A changed asset gets a new name
+    fs::write(dir.join("app.js"), "console.log('app v2')").unwrap();
+    assert_ne!(AssetManifest::scan(&dir).unwrap().url("app.js"), manifest.url("app.js"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bundler_manifest() {
+    let dir = public_dir("bundler");
+    fs::rename(dir.join("app.js"), dir.join("app.1a2b3c.js")).unwrap();
+    fs::write(dir.join("manifest.json"), r#"{"app.js": "/app.1a2b3c.js"}"#).unwrap();
+    let manifest = AssetManifest::from_file(dir.join("manifest.json")).unwrap();
+    assert_eq!(manifest.url("app.js"), "/app.1a2b3c.js");
+
+    let mut router = Router::new();
+    router.add("^/", AssetController::new(&dir, manifest));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/app.1a2b3c.js").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/javascript; charset=utf-8");
+
+    let res = client.request(Method::HEAD, "/app.1a2b3c.js").send();
+    assert_eq!(res.headers_map()[header::CONTENT_LENGTH], "18");
+    assert!(res.get_body().is_empty());
+    assert_eq!(client.post("/app.1a2b3c.js").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    assert!(matches!(AssetManifest::from_file(dir.join("css/app.css")), Err(ConfigError::Parse(_))));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "handlebars")]
+#[test]
+fn handlebars_helper() {
+    let dir = public_dir("handlebars");
+    let manifest = AssetManifest::scan(&dir).unwrap().prefix("/static");
+
+    let mut handlebars = handlebars::Handlebars::new();
+    handlebars.register_template_string("index", r#"<script src="{{asset_url "app.js"}}"></script>"#).unwrap();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/$", |_, _, res| { res.status(StatusCode::OK).render("index", &()); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .asset_manifest(manifest.clone())
+        .template_engine(HandlebarsEngine::new(handlebars))
+        .build());
+
+    let page = String::from_utf8(client.get("/").send().get_body()).unwrap();
+    assert_eq!(page, format!(r#"<script src="{}"></script>"#, manifest.url("app.js")));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn directory_listing() {
+    let dir = public_dir("listing");
+    fs::write(dir.join("css/my theme.css"), "").unwrap();
+    fs::write(dir.join(".secret"), "").unwrap();
+
+    let mut router = Router::new();
+    router.add("^/static", AssetController::new(&dir, AssetManifest::default().prefix("/static")).directory_listing(true));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/static").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    let page = String::from_utf8(res.get_body()).unwrap();
+    assert!(page.contains("<title>Index of /static/</title>"));
+    assert!(page.contains(r#"<a href="/static/css/">css/</a>"#));
+    assert!(page.contains(r#"<a href="/static/app.js">app.js</a></td><td>18</td>"#));
+    assert!(!page.contains(".secret") && !page.contains("../"));
+
+    let res = client.get("/static/css/").header(header::ACCEPT, "application/json").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/json");
+    let listing: Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(listing["path"], "/static/css/");
+    assert_eq!(listing["entries"][0]["name"], "app.css");
+    assert_eq!(listing["entries"][0]["size"], 19);
+    assert!(listing["entries"][0]["modified"].is_string());
+    assert_eq!(listing["entries"][1]["name"], "my theme.css");
+
+    let page = String::from_utf8(client.get("/static/css").send().get_body()).unwrap();
+    assert!(page.contains(r#"<a href="/static/css/my%20theme.css">my theme.css</a>"#) && page.contains("../"));
+    assert_eq!(client.get("/static/css/my%20theme.css").send().get_status(), StatusCode::OK);
+
+    fs::write(dir.join("css").join(NO_INDEX_MARKER), "").unwrap();
+    assert_eq!(client.get("/static/css/").send().get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(client.get("/static/").send().get_status(), StatusCode::OK);
+
+    let mut router = Router::new();
+    router.add("^/static", AssetController::new(&dir, AssetManifest::default().prefix("/static")));
+    let client = TestClient::new(Server::builder().router(router).build());
+    assert_eq!(client.get("/static/").send().get_status(), StatusCode::NOT_FOUND);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn spa_fallback() {
+    let dir = public_dir("spa");
+    fs::write(dir.join("index.html"), "<div id=\"app\"></div>").unwrap();
+
+    let mut api = BasicController::new(());
+    api.add(Method::GET, "^/api/users$", |_, _, res| { res.status(StatusCode::OK).body("[]"); });
+    let mut router = Router::new();
+    router.add("^/api/users", api);
+    router.add("^/", AssetController::new(&dir, AssetManifest::default()).spa_fallback("/index.html", vec!["^/api/"]));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/users/42/edit").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "no-cache");
+    assert_eq!(res.get_body(), b"<div id=\"app\"></div>".to_vec());
+    assert_eq!(client.get("/").send().get_body(), b"<div id=\"app\"></div>".to_vec());
+
+    assert_eq!(client.get("/app.js").send().get_body(), b"console.log('app')".to_vec());
+    assert_eq!(client.get("/missing.js").send().get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(client.get("/api/users").send().get_body(), b"[]".to_vec());
+    assert_eq!(client.get("/api/orders").send().get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(client.post("/users").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn precompressed() {
+    let dir = public_dir("precompressed");
+    fs::write(dir.join("app.js.gz"), "gzipped").unwrap();
+    fs::write(dir.join("app.js.br"), "brotli").unwrap();
+    let manifest = AssetManifest::scan(&dir).unwrap();
+
+    let mut router = Router::new();
+    router.add("^/", AssetController::new(&dir, manifest.clone()));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/app.js").header(header::ACCEPT_ENCODING, "gzip, deflate, br").send();
+    assert_eq!(res.headers_map()[header::CONTENT_ENCODING], "br");
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/javascript; charset=utf-8");
+    assert_eq!(res.headers_map()[header::VARY], "accept-encoding");
+    assert_eq!(res.get_body(), b"brotli".to_vec());
+
+    let res = client.get(&manifest.url("app.js")).header(header::ACCEPT_ENCODING, "br;q=0, *").send();
+    assert_eq!(res.headers_map()[header::CONTENT_ENCODING], "gzip");
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+    assert_eq!(res.get_body(), b"gzipped".to_vec());
+
+    let res = client.request(Method::HEAD, "/app.js").header(header::ACCEPT_ENCODING, "gzip").send();
+    assert_eq!(res.headers_map()[header::CONTENT_LENGTH], "7");
+
+    let res = client.get("/app.js").send();
+    assert!(res.headers_map().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(res.headers_map()[header::VARY], "accept-encoding");
+    assert_eq!(res.get_body(), b"console.log('app')".to_vec());
+
+    // Files without precompressed siblings don't vary
+    let res = client.get("/css/app.css").header(header::ACCEPT_ENCODING, "gzip").send();
+    assert!(res.headers_map().get(header::CONTENT_ENCODING).is_none());
+    assert!(res.headers_map().get(header::VARY).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn memory_assets() {
+    let mut assets = MemoryAssets::new();
+    assets.add("index.html", &b"<div id=\"app\"></div>"[..]).add("/js/app.js", b"console.log('app')".to_vec());
+    assert!(assets.metadata("js").unwrap().is_dir);
+    assert_eq!(assets.metadata("js/app.js").unwrap().len, 18);
+    assert_eq!(assets.read_dir("").unwrap(), vec!["index.html".to_string(), "js".to_string()]);
+    assert!(assets.metadata("j").is_err());
+
+    let manifest = AssetManifest::scan_source(&assets).unwrap();
+    let fingerprinted = manifest.url("js/app.js");
+    assert!(fingerprinted.starts_with("/js/app.") && fingerprinted.ends_with(".js"));
+
+    let mut router = Router::new();
+    router.add("^/", AssetController::with_source(assets, manifest).directory_listing(true));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get(&fingerprinted).send();
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+    assert_eq!(res.get_body(), b"console.log('app')".to_vec());
+    assert_eq!(client.get("/index.html").send().get_body(), b"<div id=\"app\"></div>".to_vec());
+    assert!(String::from_utf8(client.get("/js").send().get_body()).unwrap().contains(r#"<a href="/js/app.js">app.js</a>"#));
+    assert_eq!(client.get("/missing.css").send().get_status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn byte_ranges() {
+    assert_eq!(parse_byte_ranges("bytes=0-9, 20-, -5", 100), Ok(vec![0..10, 20..100, 95..100]));
+    let tail = 90..100;
+    assert_eq!(parse_byte_ranges("bytes=90-200", 100), Ok(vec![tail]));
+    assert_eq!(parse_byte_ranges("bytes=200-, 300-400", 100), Err(RangeError::Unsatisfiable));
+    assert_eq!(parse_byte_ranges("bytes=9-0", 100), Err(RangeError::Invalid));
+    assert_eq!(parse_byte_ranges("items=0-9", 100), Err(RangeError::Invalid));
+    assert_eq!(parse_byte_ranges("bytes=", 100), Err(RangeError::Invalid));
+
+    let dir = public_dir("ranges");
+    let mut router = Router::new();
+    router.add("^/", AssetController::new(&dir, AssetManifest::default()));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    // console.log('app')
+    let res = client.get("/app.js").header(header::RANGE, "bytes=0-6").send();
+    assert_eq!(res.get_status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers_map()[header::CONTENT_RANGE], "bytes 0-6/18");
+    assert_eq!(res.headers_map()[header::CONTENT_LENGTH], "7");
+    assert_eq!(res.get_body(), b"console".to_vec());
+
+    let res = client.get("/app.js").header(header::RANGE, "bytes=0-6, -5").send();
+    assert_eq!(res.get_status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = res.headers_map()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="));
+    let boundary = &content_type["multipart/byteranges; boundary=".len()..];
+    let body = String::from_utf8(res.get_body()).unwrap();
+    assert_eq!(body, format!("--{b}\r\nContent-Type: text/javascript; charset=utf-8\r\nContent-Range: bytes 0-6/18\r\n\r\nconsole\r\n\
+                              --{b}\r\nContent-Type: text/javascript; charset=utf-8\r\nContent-Range: bytes 13-17/18\r\n\r\napp')\r\n\
+                              --{b}--\r\n", b = boundary));
+    assert_eq!(res.headers_map()[header::CONTENT_LENGTH], body.len().to_string().as_str());
+
+    let res = client.get("/app.js").header(header::RANGE, "bytes=100-").send();
+    assert_eq!(res.get_status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(res.headers_map()[header::CONTENT_RANGE], "bytes */18");
+
+    let res = client.get("/app.js").header(header::RANGE, "bytes=a-b").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(res.get_body().len(), 18);
+
+    let etag = client.get("/app.js").send().headers_map()[header::ETAG].to_str().unwrap().to_string();
+    let res = client.get("/app.js").header(header::RANGE, "bytes=0-6").header(header::IF_RANGE, etag.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::PARTIAL_CONTENT);
+    let res = client.get("/app.js").header(header::RANGE, "bytes=0-6").header(header::IF_RANGE, "\"stale\"").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body().len(), 18);
+
+    let many = (0..=MAX_RANGES).map(|i| format!("{}-{}", i, i)).collect::<Vec<_>>().join(",");
+    assert_eq!(client.get("/app.js").header(header::RANGE, format!("bytes={}", many).as_str()).send().get_status(), StatusCode::OK);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn attachments() {
+    assert_eq!(content_disposition("users.csv"), "attachment; filename=\"users.csv\"");
+    assert_eq!(content_disposition("résumé \"2024\".pdf"),
+               "attachment; filename=\"r_sum_ _2024_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%20%222024%22.pdf");
+
+    let dir = public_dir("attachments");
+    let report = dir.join("css/app.css");
+    let mut controller = BasicController::new(report);
+    controller.add(Method::GET, "^/export$", |_, _, res| { res.status(StatusCode::OK).attachment("id,name\n1,Ada\n", "users.csv"); });
+    controller.add(Method::GET, "^/report$", |report, _, res| {
+        res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain").attachment(report.as_path(), "rapport été.css");
+    });
+    controller.add(Method::GET, "^/missing$", |report, _, res| {
+        res.status(StatusCode::OK).attachment(report.with_extension("pdf"), "missing.pdf");
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/export").send();
+    assert_eq!(res.headers_map()[header::CONTENT_DISPOSITION], "attachment; filename=\"users.csv\"");
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert_eq!(res.get_body(), b"id,name\n1,Ada\n".to_vec());
+
+    let res = client.get("/report").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_DISPOSITION],
+               "attachment; filename=\"rapport _t_.css\"; filename*=UTF-8''rapport%20%C3%A9t%C3%A9.css");
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/plain");
+    assert_eq!(res.get_body(), b"body { color: red }".to_vec());
+
+    let res = client.get("/missing").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+    assert!(res.headers_map().get(header::CONTENT_DISPOSITION).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,48 @@
+extern crate saphir;
+#[macro_use]
+extern crate serde_derive;
+
+use saphir::*;
+use saphir::test::{MockRequest, TestClient};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Item {
+    name: String,
+    quantity: u32,
+}
+
+#[test]
+fn cbor_bodies() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/items$", |_, req, res| {
+        match req.body_cbor::<Item>() {
+            Ok(mut item) => {
+                item.quantity += 1;
+                res.status(StatusCode::CREATED).cbor(&item);
+            }
+            Err(_) => { res.status(StatusCode::BAD_REQUEST); }
+        }
+    });
+    controller.add(Method::GET, "^/negotiated$", |_, req, res| {
+        res.status(StatusCode::OK).negotiate(req, &Item { name: "pen".to_string(), quantity: 2 });
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let mut encoded = SyncResponse::new();
+    encoded.cbor(&Item { name: "pen".to_string(), quantity: 1 });
+
+    let res = client.post("/items").header(header::CONTENT_TYPE, "application/cbor").body(encoded.get_body()).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/cbor");
+    let item: Item = MockRequest::post("/").body(res.get_body()).build().body_cbor().unwrap();
+    assert_eq!(item, Item { name: "pen".to_string(), quantity: 2 });
+
+    assert_eq!(client.post("/items").body(vec![0xc1]).send().get_status(), StatusCode::BAD_REQUEST);
+
+    let res = client.get("/negotiated").header(header::ACCEPT, "application/cbor, application/json;q=0.5").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/cbor");
+    let item: Item = MockRequest::post("/").body(res.get_body()).build().body_cbor().unwrap();
+    assert_eq!(item.quantity, 2);
+}
@@ -0,0 +1,83 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::MockRequest;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Start a server answering a single request with the head of the request it received, after `delay`
+fn upstream(delay: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut head = Vec::new();
+        let mut buf = [0; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => head.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        thread::sleep(delay);
+        let _ = write!(stream, "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n", head.len());
+        let _ = stream.write_all(&head);
+    });
+
+    format!("http://{}/echo", addr)
+}
+
+#[test]
+fn context_propagation() {
+    let client = HttpClient::new().propagate("x-tenant-id");
+    let context = MockRequest::get("/orders")
+        .header("x-request-id", "42")
+        .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+        .header("x-tenant-id", "acme")
+        .header("authorization", "Bearer secret")
+        .build();
+
+    let res = client.get(&context, &upstream(Duration::from_millis(0))).unwrap();
+    let head = String::from_utf8(res.into_body()).unwrap().to_ascii_lowercase();
+    assert!(head.starts_with("get /echo http/1.1"));
+    assert!(head.contains("x-request-id: 42\r\n"));
+    assert!(head.contains("traceparent: 00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01\r\n"));
+    assert!(head.contains("x-tenant-id: acme\r\n"));
+    assert!(!head.contains("authorization"));
+
+    let request = Request::post(upstream(Duration::from_millis(0))).header("x-request-id", "43").body(b"{}".to_vec()).unwrap();
+    let head = String::from_utf8(client.send(&context, request).unwrap().into_body()).unwrap();
+    assert!(head.contains("x-request-id: 43\r\n"));
+    assert!(!head.contains("x-request-id: 42"));
+}
+
+#[test]
+fn context_deadline() {
+    let client = HttpClient::new();
+
+    let token = CancellationToken::new();
+    token.set_deadline(Instant::now() + Duration::from_millis(200));
+    let context = MockRequest::get("/orders").extension(token.clone()).build();
+
+    let started = Instant::now();
+    match client.get(&context, &upstream(Duration::from_secs(5))) {
+        Err(ClientError::Timeout) => {}
+        other => panic!("expected a timeout, got {:?}", other.map(|res| res.status())),
+    }
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    match client.get(&context, &upstream(Duration::from_millis(0))) {
+        Err(ClientError::Cancelled) => {}
+        other => panic!("expected a cancellation, got {:?}", other.map(|res| res.status())),
+    }
+
+    let client = HttpClient::new().timeout(Duration::from_millis(100));
+    let context = MockRequest::get("/orders").build();
+    assert!(matches!(client.get(&context, &upstream(Duration::from_secs(5))), Err(ClientError::Timeout)));
+}
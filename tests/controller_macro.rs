@@ -0,0 +1,43 @@
+extern crate saphir;
+
+use saphir::*;
+
+struct UserController {
+    name: String,
+}
+
+#[controller(prefix = "/users")]
+impl UserController {
+    #[get("/")]
+    fn list(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+        res.status(StatusCode::OK).body(self.name.clone());
+    }
+
+    #[post("/<id>")]
+    fn update(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+        res.status(StatusCode::ACCEPTED);
+    }
+}
+
+fn dispatch(router: &Router, method: Method, uri: &str) -> SyncResponse {
+    let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+    let req = SyncRequest::new(parts, Vec::new());
+    let mut res = SyncResponse::new();
+    router.dispatch(&req, &mut res);
+    res
+}
+
+#[test]
+fn controller_macro() {
+    let mut router = Router::new();
+    UserController { name: "saphir".to_string() }.register(&mut router);
+
+    let res = dispatch(&router, Method::GET, "/users");
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"saphir".to_vec());
+
+    assert_eq!(dispatch(&router, Method::POST, "/users/42").get_status(), StatusCode::ACCEPTED);
+    assert_eq!(dispatch(&router, Method::POST, "/users/42/posts").get_status(), StatusCode::BAD_REQUEST);
+    assert_eq!(dispatch(&router, Method::DELETE, "/users/42").get_status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(dispatch(&router, Method::GET, "/posts").get_status(), StatusCode::NOT_FOUND);
+}
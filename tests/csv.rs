@@ -0,0 +1,47 @@
+extern crate saphir;
+#[macro_use]
+extern crate serde_derive;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+#[derive(Serialize)]
+struct User {
+    id: u64,
+    name: String,
+    admin: bool,
+}
+
+#[test]
+fn csv_export() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users$", |_, _, res| {
+        let users = (1..=3).map(|id| User { id, name: format!("user, {}", id), admin: id == 1 });
+        res.status(StatusCode::OK).csv(users);
+    });
+    controller.add(Method::GET, "^/large$", |_, _, res| {
+        res.status(StatusCode::OK).csv((0..100_000u64).map(|id| (id, id * 2)));
+    });
+    controller.add(Method::GET, "^/empty$", |_, _, res| {
+        res.status(StatusCode::OK).csv(Vec::<User>::new());
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/users").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert!(res.headers_map().get(header::CONTENT_LENGTH).is_none());
+    assert_eq!(String::from_utf8(res.get_body().to_vec()).unwrap(),
+               "id,name,admin\n1,\"user, 1\",true\n2,\"user, 2\",false\n3,\"user, 3\",false\n");
+
+    let res = client.get("/large").send();
+    let body = String::from_utf8(res.get_body().to_vec()).unwrap();
+    assert_eq!(body.lines().count(), 100_000);
+    assert_eq!(body.lines().last(), Some("99999,199998"));
+
+    let res = client.get("/empty").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert!(res.get_body().is_empty());
+}
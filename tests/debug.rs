@@ -0,0 +1,120 @@
+extern crate serde_json;
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+
+fn debug_client(capacity: usize) -> TestClient {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users/(\\d+)$", |_, _, res| { res.status(StatusCode::OK).body("a user"); });
+    controller.add(Method::POST, "^/users$", |_, _, res| { res.status(StatusCode::CREATED); });
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(MetricsMiddleware::new(Metrics::new()), vec!("^/"), Some(vec!("^/_debug")));
+
+    let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+    TestClient::new(Server::builder()
+        .router(router)
+        .middleware_stack(middlewares)
+        .debug_endpoint("/_debug/", DebugController::new(loopback).capacity(capacity))
+        .build())
+}
+
+fn json(res: &SyncResponse) -> Value {
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/json");
+    serde_json::from_slice(&res.get_body()).unwrap()
+}
+
+#[test]
+fn recent_requests() {
+    let client = debug_client(2);
+    client.get("/users/1").send();
+    client.post("/users").send();
+    client.get("/missing").send();
+
+    let requests = json(&client.get("/_debug/requests").send());
+    let requests = requests.as_array().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0]["method"], "POST");
+    assert_eq!(requests[0]["path"], "/users");
+    assert_eq!(requests[0]["status"], 201);
+    assert!(requests[0]["latency_ms"].as_f64().unwrap() >= 0.0);
+    assert_eq!(requests[1]["path"], "/missing");
+    assert_eq!(requests[1]["status"], 404);
+
+    // The request to the debug endpoint is recorded as well
+    let requests = json(&client.get("/_debug/requests").send());
+    assert_eq!(requests[1]["path"], "/_debug/requests");
+}
+
+#[test]
+fn routes_and_middlewares() {
+    let client = debug_client(10);
+
+    let everything = json(&client.get("/_debug").send());
+    assert!(everything["requests"].is_array());
+
+    let routes = json(&client.get("/_debug/routes").send());
+    let routes = routes.as_array().unwrap();
+    assert!(routes.iter().any(|route| route["method"] == "POST" && route["pattern"] == "^/users$"));
+    assert!(routes.iter().any(|route| route["controller"] == "DebugController" && route["guards"][0] == "IpFilterGuard"));
+    assert_eq!(routes, everything["routes"].as_array().unwrap());
+
+    let middlewares = json(&client.get("/_debug/middlewares/").send());
+    assert_eq!(middlewares[0]["name"], "MetricsMiddleware");
+    assert_eq!(middlewares[0]["include_path"][0], "^/");
+    assert_eq!(middlewares[0]["exclude_path"][0], "^/_debug");
+
+    assert_eq!(client.get("/_debug/unknown").send().get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(client.post("/_debug").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[test]
+fn guarded() {
+    let client = debug_client(10);
+
+    let res = client.get("/_debug/requests").peer_addr(([10, 0, 0, 1], 4000).into()).send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+    assert_eq!(client.get("/_debug/requests").send().get_status(), StatusCode::OK);
+}
+
+#[test]
+fn route_metadata() {
+    let mut controller = BasicController::new(());
+    let metadata = RouteMetadata::new().summary("Fetch a user").operation_id("users.show").tag("users").response(404, "No such user");
+    controller.add_documented(Method::GET, "^/users/(\\d+)$", metadata, |_, _, res| { res.status(StatusCode::OK); });
+    controller.add(Method::POST, "^/users$", |_, _, res| { res.status(StatusCode::CREATED); });
+    controller.document(Method::POST, "^/users$", RouteMetadata::new().summary("Create a user"));
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+
+    let metrics = Metrics::new();
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(MetricsMiddleware::new(metrics.clone()), vec!("^/users"), None);
+
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .middleware_stack(middlewares)
+        .debug_endpoint("/_debug", DebugController::new(IpFilterGuard::new().allow("127.0.0.1/32").unwrap()))
+        .build());
+
+    let routes = json(&client.get("/_debug/routes").send());
+    let show = routes.as_array().unwrap().iter().find(|route| route["method"] == "GET" && route["pattern"] == "^/users/(\\d+)$").unwrap();
+    assert_eq!(show["metadata"]["summary"], "Fetch a user");
+    assert_eq!(show["metadata"]["tags"][0], "users");
+    assert_eq!(show["metadata"]["responses"][0]["status"], 404);
+    assert!(show["metadata"].get("description").is_none());
+    let create = routes.as_array().unwrap().iter().find(|route| route["method"] == "POST").unwrap();
+    assert_eq!(create["metadata"]["summary"], "Create a user");
+
+    // The operation id labels the route, the pattern does otherwise
+    assert_eq!(client.get("/users/7").send().route_label(), Some("users.show"));
+    assert_eq!(client.post("/users").send().route_label(), Some("^/users$"));
+    assert_eq!(metrics.counter("http_requests_total", &[("method", "GET"), ("route", "users.show"), ("status", "200")]), 1);
+}
@@ -0,0 +1,37 @@
+#[macro_use]
+extern crate rust_embed;
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+#[derive(RustEmbed)]
+#[folder = "tests/fixtures/public/"]
+struct Public;
+
+#[test]
+fn embedded_assets() {
+    let manifest = AssetManifest::scan_source(&EmbeddedAssets::<Public>::new()).unwrap().prefix("/static");
+    assert!(manifest.url("css/app.css").starts_with("/static/css/app."));
+
+    let mut router = Router::new();
+    router.add("^/static", AssetController::with_source(EmbeddedAssets::<Public>::new(), manifest.clone())
+        .directory_listing(true)
+        .spa_fallback("index.html", Vec::<&str>::new()));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get(&manifest.url("css/app.css")).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], IMMUTABLE_CACHE_CONTROL);
+    assert_eq!(res.get_body(), b"body { margin: 0 }".to_vec());
+
+    let res = client.get("/static/css/app.css").header(header::ACCEPT_ENCODING, "gzip").send();
+    assert_eq!(res.headers_map()[header::CONTENT_ENCODING], "gzip");
+    assert_eq!(res.get_body(), b"gzipped".to_vec());
+
+    let page = String::from_utf8(client.get("/static/").send().get_body()).unwrap();
+    assert!(page.contains(r#"<a href="/static/css/">css/</a>"#));
+    assert!(page.contains(r#"<a href="/static/index.html">index.html</a>"#));
+
+    assert_eq!(client.get("/static/users/42").send().get_body(), b"<div id=\"app\"></div>".to_vec());
+}
@@ -0,0 +1,69 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::net::IpAddr;
+
+struct CountryGuard(&'static str);
+
+impl RequestGuard for CountryGuard {
+    fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+        match req.geo().and_then(|geo| geo.country.as_ref()) {
+            Some(country) if country == self.0 => {
+                res.status(StatusCode::FORBIDDEN);
+                RequestContinuation::None
+            }
+            _ => RequestContinuation::Next,
+        }
+    }
+}
+
+fn lookup(ip: IpAddr) -> Option<GeoInfo> {
+    let country = match ip.to_string().as_str() {
+        "198.51.100.1" => "CA",
+        "203.0.113.7" => "XX",
+        _ => return None,
+    };
+
+    Some(GeoInfo {
+        country: Some(country.to_string()),
+        continent: None,
+        asn: Some(64496),
+        as_organization: Some("Example".to_string()),
+    })
+}
+
+#[test]
+fn geoip_middleware() {
+    let mut guards = RequestGuardCollection::new();
+    guards.add(CountryGuard("XX"));
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::GET, "^/whereami$", guards, |_, req, res| {
+        let geo = req.geo().cloned().unwrap_or_default();
+        res.body(format!("{} {}", geo.country.unwrap_or_default(), geo.asn.unwrap_or(0)));
+    });
+
+    let mut router = Router::new();
+    router.add("^/whereami", controller);
+
+    let mut mid_stack = MiddlewareStack::new();
+    mid_stack.apply(GeoIpMiddleware::new(lookup), vec!("/"), None);
+
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(mid_stack).trusted_proxies(proxies).build());
+
+    let res = client.get("/whereami").peer_addr(([198, 51, 100, 1], 4000).into()).send();
+    assert_eq!(res.get_body(), b"CA 64496".to_vec());
+
+    let res = client.get("/whereami").peer_addr(([10, 0, 0, 1], 4000).into()).header("x-forwarded-for", "198.51.100.1").send();
+    assert_eq!(res.get_body(), b"CA 64496".to_vec());
+
+    let res = client.get("/whereami").peer_addr(([203, 0, 113, 7], 4000).into()).send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+
+    let res = client.get("/whereami").peer_addr(([192, 0, 2, 1], 4000).into()).send();
+    assert_eq!(res.get_body(), b" 0".to_vec());
+
+    assert!(MaxMindDatabase::open("missing.mmdb").is_err());
+}
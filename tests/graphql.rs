@@ -0,0 +1,99 @@
+#[macro_use]
+extern crate serde_json;
+extern crate saphir;
+extern crate juniper;
+
+use juniper::{graphql_object, EmptySubscription, RootNode};
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Context {
+    user: String,
+    counter: AtomicUsize,
+}
+
+impl juniper::Context for Context {}
+
+struct Query;
+
+#[graphql_object(context = Context)]
+impl Query {
+    fn whoami(context: &Context) -> String {
+        context.user.clone()
+    }
+
+    fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+}
+
+struct Mutation;
+
+#[graphql_object(context = Context)]
+impl Mutation {
+    fn increment(context: &Context) -> i32 {
+        context.counter.fetch_add(1, Ordering::SeqCst) as i32 + 1
+    }
+}
+
+fn client() -> TestClient {
+    let root = RootNode::new(Query, Mutation, EmptySubscription::<Context>::new());
+    let schema = JuniperSchema::new(root, |req: &SyncRequest| Context {
+        user: req.headers_map().get("x-user").and_then(|u| u.to_str().ok()).unwrap_or("anonymous").to_string(),
+        counter: AtomicUsize::new(0),
+    });
+
+    let mut router = Router::new();
+    router.add("^/graphql$", GraphQLController::new(schema).graphiql());
+    TestClient::new(Server::builder().router(router).build())
+}
+
+fn document(res: &SyncResponse) -> Value {
+    serde_json::from_slice(&res.get_body()).unwrap()
+}
+
+#[test]
+fn juniper_operations() {
+    let client = client();
+
+    let res = client.post("/graphql").header("x-user", "alice").json(&json!({ "query": "{ whoami }" })).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(document(&res), json!({ "data": { "whoami": "alice" } }));
+
+    let res = client.post("/graphql").json(&json!([
+        { "query": "query Add($a: Int!) { add(a: $a, b: 2) }", "variables": { "a": 40 } },
+        { "query": "mutation { increment }" },
+    ])).send();
+    assert_eq!(document(&res), json!([{ "data": { "add": 42 } }, { "data": { "increment": 1 } }]));
+
+    let res = client.post("/graphql").header(header::CONTENT_TYPE, "application/graphql").body("{ add(a: 1, b: 1) }").send();
+    assert_eq!(document(&res), json!({ "data": { "add": 2 } }));
+
+    let res = client.get("/graphql?query=%7B+add(a%3A+3%2C+b%3A+4)+%7D").send();
+    assert_eq!(document(&res), json!({ "data": { "add": 7 } }));
+
+    let res = client.get("/graphql?query=mutation+%7B+increment+%7D").send();
+    assert_eq!(document(&res)["errors"][0]["message"], "only queries can be sent with the GET method");
+
+    let res = client.post("/graphql").json(&json!({ "query": "{ unknown }" })).send();
+    assert!(document(&res)["errors"].as_array().is_some_and(|errors| !errors.is_empty()));
+}
+
+#[test]
+fn malformed_requests_and_graphiql() {
+    let client = client();
+
+    let res = client.post("/graphql").body("{").send();
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+    assert!(document(&res)["errors"][0]["message"].as_str().unwrap().starts_with("the body is invalid JSON"));
+
+    assert_eq!(client.get("/graphql").send().get_status(), StatusCode::BAD_REQUEST);
+    assert_eq!(client.get("/graphql?query=%7B+add+%7D&variables=%7B").send().get_status(), StatusCode::BAD_REQUEST);
+    assert_eq!(client.delete("/graphql").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+
+    let res = client.get("/graphql").header(header::ACCEPT, "text/html").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert!(String::from_utf8_lossy(&res.get_body()).contains("GraphiQL"));
+}
@@ -0,0 +1,66 @@
+extern crate saphir;
+extern crate base64;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+fn client() -> TestClient {
+    let mut grpc = GrpcWebController::new();
+    grpc.add_unary("echo.Echo/Reverse", |_req: &SyncRequest, message: &[u8]| -> Result<Vec<u8>, GrpcStatus> {
+        if message.is_empty() {
+            return Err(GrpcStatus::new(GrpcCode::InvalidArgument, "empty message: 100%"));
+        }
+        Ok(message.iter().rev().cloned().collect())
+    });
+
+    let mut router = Router::new();
+    router.add("^/echo\\.Echo/", grpc);
+    TestClient::new(Server::builder().router(router).build())
+}
+
+fn framed(flag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![flag];
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn binary_and_text_calls() {
+    let client = client();
+
+    let res = client.post("/echo.Echo/Reverse").header(header::CONTENT_TYPE, "application/grpc-web+proto").body(framed(0, b"abc")).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get(header::CONTENT_TYPE).unwrap(), "application/grpc-web+proto");
+    let mut expected = framed(0, b"cba");
+    expected.extend(framed(0x80, b"grpc-status:0\r\n"));
+    assert_eq!(res.get_body(), expected);
+
+    // Text bodies may be sent as several padded base64 chunks
+    let body = framed(0, b"hello");
+    let text = format!("{}{}", base64::encode(&body[..4]), base64::encode(&body[4..]));
+    let res = client.post("/echo.Echo/Reverse").header(header::CONTENT_TYPE, "application/grpc-web-text").body(text).send();
+    assert_eq!(res.headers_map().get(header::CONTENT_TYPE).unwrap(), "application/grpc-web-text+proto");
+    let decoded = base64::decode(&res.get_body()).unwrap();
+    assert_eq!(&decoded[..10], &framed(0, b"olleh")[..]);
+}
+
+#[test]
+fn failed_calls() {
+    let client = client();
+    let call = |path: &str, body: Vec<u8>| client.post(path).header(header::CONTENT_TYPE, "application/grpc-web").body(body).send();
+
+    let res = call("/echo.Echo/Reverse", framed(0, b""));
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get("grpc-status").unwrap(), "3");
+    assert_eq!(res.headers_map().get("grpc-message").unwrap(), "empty message: 100%25");
+    assert_eq!(res.get_body(), framed(0x80, b"grpc-status:3\r\ngrpc-message:empty message: 100%25\r\n"));
+
+    assert_eq!(call("/echo.Echo/Missing", framed(0, b"abc")).headers_map().get("grpc-status").unwrap(), "12");
+    assert_eq!(call("/echo.Echo/Reverse", framed(1, b"abc")).headers_map().get("grpc-status").unwrap(), "12");
+    assert_eq!(call("/echo.Echo/Reverse", b"abc".to_vec()).headers_map().get("grpc-status").unwrap(), "3");
+
+    let res = client.post("/echo.Echo/Reverse").header(header::CONTENT_TYPE, "application/json").body("{}").send();
+    assert_eq!(res.get_status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(client.get("/echo.Echo/Reverse").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+}
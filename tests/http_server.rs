@@ -38,7 +38,7 @@ fn http_server() {
 
     mid_stack.apply(TestMiddleware {}, vec!("/"), None);
 
-    let basic_test_cont = BasicController::new(TestControllerContext::default());
+    let mut basic_test_cont = BasicController::new(TestControllerContext::default());
 
     basic_test_cont.add(Method::GET, reg!("/"), function_to_receive_any_get_http_call);
     basic_test_cont.add(Method::POST, reg!("/"), |_, _, _| { println!("this was a post request") });
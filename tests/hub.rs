@@ -0,0 +1,96 @@
+extern crate saphir;
+
+use saphir::*;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+fn wait_until<F: Fn() -> bool>(condition: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "the condition was never met");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn publish_and_subscribe() {
+    let hub = Hub::<u32>::new().capacity(2);
+    assert_eq!(hub.publish("news", 0), 0);
+
+    let both = hub.subscribe(vec!["news", "weather"]);
+    let news = hub.subscribe(vec!["news"]);
+    assert_eq!(hub.subscribers("news"), 2);
+    assert_eq!(hub.subscribers("weather"), 1);
+
+    assert_eq!(hub.publish("news", 1), 2);
+    assert_eq!(hub.publish("weather", 2), 1);
+    assert_eq!(both.try_recv(), Some(HubMessage { topic: "news".to_string(), payload: 1 }));
+    assert_eq!(both.try_recv(), Some(HubMessage { topic: "weather".to_string(), payload: 2 }));
+    assert_eq!(both.try_recv(), None);
+
+    hub.publish("news", 3);
+    hub.publish("news", 4);
+    assert_eq!(news.lagged(), 1);
+    assert_eq!(news.lagged(), 0);
+    assert_eq!(news.recv_timeout(Duration::from_millis(10)).map(|message| message.payload), Some(3));
+    assert_eq!(news.recv_timeout(Duration::from_millis(10)).map(|message| message.payload), Some(4));
+    assert_eq!(news.recv_timeout(Duration::from_millis(10)), None);
+
+    let publisher = hub.clone();
+    let published = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        publisher.publish("news", 5)
+    });
+    assert_eq!(news.recv_timeout(Duration::from_secs(5)).map(|message| message.payload), Some(5));
+    assert_eq!(published.join().unwrap(), 2);
+
+    both.unsubscribe("news");
+    both.subscribe("weather");
+    assert_eq!(hub.subscribers("news"), 1);
+    assert_eq!(hub.subscribers("weather"), 1);
+    drop(news);
+    assert_eq!(hub.subscribers("news"), 0);
+    assert_eq!(hub.publish("news", 6), 0);
+}
+
+#[test]
+fn streamed_subscription() {
+    let hub = Hub::<String>::new();
+    let mut controller = BasicController::new(hub.clone());
+    controller.add(Method::GET, "^/events$", |hub, _, res| {
+        let subscription = hub.subscribe(vec!["news"]);
+        res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/event-stream")
+            .stream_subscription(subscription, |message| format!("event: {}\ndata: {}\n\n", message.topic, message.payload).into_bytes());
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    wait_until(|| hub.subscribers("news") == 1);
+
+    let mut response = Vec::new();
+    for headline in &["first", "second"] {
+        hub.publish("news", headline.to_string());
+        let expected = format!("event: news\ndata: {}\n\n", headline);
+        while !String::from_utf8_lossy(&response).contains(&expected) {
+            let mut buffer = [0; 1024];
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(read > 0, "the stream ended early");
+            response.extend_from_slice(&buffer[..read]);
+        }
+    }
+    assert!(String::from_utf8_lossy(&response).contains("text/event-stream"));
+
+    drop(stream);
+    wait_until(|| {
+        hub.publish("news", "missed".to_string());
+        hub.subscribers("news") == 0
+    });
+    server.shutdown().unwrap();
+}
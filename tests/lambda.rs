@@ -0,0 +1,74 @@
+#[macro_use]
+extern crate serde_json;
+extern crate saphir;
+
+use saphir::*;
+use serde_json::Value;
+
+fn server() -> Server {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/users$", |_, req, res| {
+        let query = req.uri().query().unwrap_or("").to_string();
+        let cookie = req.headers_map().get(header::COOKIE).and_then(|c| c.to_str().ok()).unwrap_or("").to_string();
+        let peer = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+
+        res.status(StatusCode::CREATED)
+            .header(header::SET_COOKIE, "session=1")
+            .body(format!("{}|{}|{}|{}", String::from_utf8_lossy(req.body()), query, cookie, peer));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    Server::builder().router(router).build()
+}
+
+fn handle(server: &Server, event: Value) -> Value {
+    serde_json::from_slice(&handle_lambda_event(server, event.to_string().as_bytes()).unwrap()).unwrap()
+}
+
+#[test]
+fn api_gateway_and_alb_events() {
+    let server = server();
+
+    let v2 = handle(&server, json!({
+        "version": "2.0",
+        "rawPath": "/users",
+        "rawQueryString": "page=2",
+        "cookies": ["a=1", "b=2"],
+        "headers": { "content-type": "text/plain" },
+        "requestContext": { "http": { "method": "POST", "sourceIp": "203.0.113.7" } },
+        "body": "aGVsbG8=",
+        "isBase64Encoded": true,
+    }));
+    assert_eq!(v2["statusCode"], 201);
+    assert_eq!(v2["body"], "hello|page=2|a=1; b=2|203.0.113.7");
+    assert_eq!(v2["cookies"], json!(["session=1"]));
+    assert!(v2["headers"].get("set-cookie").is_none());
+
+    let v1 = handle(&server, json!({
+        "httpMethod": "POST",
+        "path": "/users",
+        "multiValueQueryStringParameters": { "name": ["a b"] },
+        "multiValueHeaders": { "cookie": ["a=1"] },
+        "requestContext": { "identity": { "sourceIp": "198.51.100.1" } },
+        "body": "hi",
+        "isBase64Encoded": false,
+    }));
+    assert_eq!(v1["body"], "hi|name=a%20b|a=1|198.51.100.1");
+    assert_eq!(v1["multiValueHeaders"]["set-cookie"], json!(["session=1"]));
+
+    let alb = handle(&server, json!({
+        "httpMethod": "POST",
+        "path": "/users",
+        "queryStringParameters": { "name": "a%20b" },
+        "headers": { "x-forwarded-for": "192.0.2.10, 10.0.0.1" },
+        "requestContext": { "elb": { "targetGroupArn": "arn" } },
+        "body": "",
+        "isBase64Encoded": false,
+    }));
+    assert_eq!(alb["body"], "|name=a%20b||192.0.2.10");
+    assert_eq!(alb["statusDescription"], "201 Created");
+    assert_eq!(alb["headers"]["set-cookie"], "session=1");
+
+    assert!(handle_lambda_event(&server, b"not json").is_err());
+}
@@ -0,0 +1,68 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+fn wait_for_waiters(messages: &LongPoll<String>, key: &str, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while messages.waiting(key) < count {
+        assert!(Instant::now() < deadline, "the requests never started waiting");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn wait_and_notify() {
+    let messages = LongPoll::<String>::new();
+    assert_eq!(messages.notify("general", "nobody".to_string()), 0);
+    assert_eq!(messages.wait("general", Duration::from_millis(20)), None);
+    assert_eq!(messages.waiting("general"), 0);
+
+    let waiters: Vec<_> = (0..3).map(|_| {
+        let messages = messages.clone();
+        thread::spawn(move || messages.wait("general", Duration::from_secs(5)))
+    }).collect();
+    let other = {
+        let messages = messages.clone();
+        thread::spawn(move || messages.wait("random", Duration::from_millis(100)))
+    };
+
+    wait_for_waiters(&messages, "general", 3);
+    assert_eq!(messages.notify("general", "Hello".to_string()), 3);
+    for waiter in waiters {
+        assert_eq!(waiter.join().unwrap(), Some("Hello".to_string()));
+    }
+    assert_eq!(other.join().unwrap(), None);
+    assert_eq!(messages.waiting("random"), 0);
+}
+
+#[test]
+fn long_poll_endpoint() {
+    let messages = LongPoll::<String>::new();
+    let mut controller = BasicController::new(messages.clone());
+    controller.add(Method::GET, "^/rooms/\\w+/messages$", |messages, req, res| {
+        let room = req.uri().path().split('/').nth(2).unwrap_or_default().to_string();
+        messages.respond(room, Duration::from_millis(300), res, |res, message| {
+            res.status(StatusCode::OK).body(message);
+        });
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/rooms/empty/messages").send();
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+
+    let notifier = messages.clone();
+    let notified = thread::spawn(move || {
+        wait_for_waiters(&notifier, "general", 1);
+        notifier.notify("general", "Hello".to_string())
+    });
+    let res = client.get("/rooms/general/messages").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"Hello".to_vec());
+    assert_eq!(notified.join().unwrap(), 1);
+}
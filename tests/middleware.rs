@@ -0,0 +1,1539 @@
+extern crate saphir;
+
+use saphir::*;
+
+fn dispatch(stack: &MiddlewareStack, method: Method, uri: &str, headers: &[(&str, &str)]) -> (SyncResponse, String) {
+    let mut builder = Request::builder();
+    builder.method(method).uri(uri);
+    for &(name, value) in headers {
+        builder.header(name, value);
+    }
+
+    let (parts, _) = builder.body(()).unwrap().into_parts();
+    let mut req = SyncRequest::new(parts, Vec::new());
+    let mut res = SyncResponse::new();
+    let mut routed = String::new();
+
+    stack.resolve_with(&mut req, &mut res, |req, _| routed = req.uri().to_string());
+    (res, routed)
+}
+
+#[test]
+fn rewrite_rules() {
+    let mut stack = MiddlewareStack::new();
+    stack.apply(RewriteMiddleware::new()
+                    .enforce_https()
+                    .trailing_slash(TrailingSlash::Remove)
+                    .rewrite("^/u/(\\d+)$", "/users/$1")
+                    .redirect("^/blog/(?P<slug>[^/]+)$", "/articles/$slug"), vec!("/"), None);
+
+    let (res, _) = dispatch(&stack, Method::GET, "/users", &[("host", "example.com:8080")]);
+    assert_eq!(res.headers_map().get(header::LOCATION).unwrap(), "https://example.com/users");
+
+    // Without a trusted proxy, X-Forwarded-Proto doesn't tell the request was received over https
+    let (res, _) = dispatch(&stack, Method::GET, "/users", &[("host", "example.com"), ("x-forwarded-proto", "https")]);
+    assert_eq!(res.headers_map().get(header::LOCATION).unwrap(), "https://example.com/users");
+
+    let https = [("host", "example.com")];
+
+    let (_, routed) = dispatch(&stack, Method::GET, "https://example.com/u/42?full=1", &https);
+    assert_eq!(routed, "/users/42?full=1");
+
+    let (res, routed) = dispatch(&stack, Method::POST, "https://example.com/users/", &https);
+    assert_eq!(routed, "");
+    assert_eq!(res.get_status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(res.headers_map().get(header::LOCATION).unwrap(), "/users");
+
+    let (res, _) = dispatch(&stack, Method::GET, "https://example.com/blog/hello", &https);
+    assert_eq!(res.get_status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers_map().get(header::LOCATION).unwrap(), "/articles/hello");
+}
+
+#[test]
+fn method_override() {
+    let mut stack = MiddlewareStack::new();
+    stack.apply(MethodOverrideMiddleware::new(), vec!("/"), None);
+
+    let dispatch = |method: Method, headers: &[(&str, &str)], body: &[u8]| {
+        let mut builder = Request::builder();
+        builder.method(method).uri("/users/42");
+        for &(name, value) in headers {
+            builder.header(name, value);
+        }
+
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, body.to_vec());
+        let mut routed = None;
+        stack.resolve_with(&mut req, &mut SyncResponse::new(), |req, _| routed = Some(req.method().clone()));
+        routed.unwrap()
+    };
+
+    assert_eq!(dispatch(Method::POST, &[("x-http-method-override", "delete")], b""), Method::DELETE);
+    assert_eq!(dispatch(Method::POST, &[("content-type", "application/x-www-form-urlencoded")], b"name=a&_method=PUT"), Method::PUT);
+    assert_eq!(dispatch(Method::POST, &[("x-http-method-override", "CONNECT")], b""), Method::POST);
+    assert_eq!(dispatch(Method::GET, &[("x-http-method-override", "DELETE")], b""), Method::GET);
+}
+
+#[test]
+fn mock_builders() {
+    use saphir::test::{MockRequest, MockResponse};
+
+    let is_next = |continuation| match continuation {
+        RequestContinuation::Next => true,
+        RequestContinuation::None => false,
+    };
+
+    assert!(is_next(BodyGuard.validate(&MockRequest::post("/users").body("{}").build(), &mut MockResponse::new().build())));
+    assert!(!is_next(BodyGuard.validate(&MockRequest::post("/users").build(), &mut MockResponse::new().build())));
+
+    let mut req = MockRequest::post("/users/42").header("x-http-method-override", "PATCH").extension(7u8).build();
+    let mut res = MockResponse::new().status(StatusCode::ACCEPTED).header("x-request-id", "42").build();
+    assert!(is_next(MethodOverrideMiddleware::new().prepare(&mut req, &mut res)));
+    assert_eq!(*req.method(), Method::PATCH);
+    assert_eq!(req.extensions().get::<u8>(), Some(&7));
+    assert_eq!(req.peer_addr(), Some(([127, 0, 0, 1], 0).into()));
+    assert_eq!(res.get_status(), StatusCode::ACCEPTED);
+    assert_eq!(res.headers_map().get("x-request-id").unwrap(), "42");
+}
+
+#[test]
+fn content_negotiation() {
+    use saphir::test::MockRequest;
+
+    assert_eq!(parse_quality_list("text/html;q=0.8, application/json, text/*;level=1;q=0.8, a/b;q=nan"),
+               vec![("application/json".to_string(), 1.0), ("text/html".to_string(), 0.8), ("text/*;level=1".to_string(), 0.8),
+                    ("a/b".to_string(), 0.0)]);
+
+    let accepts = |accept: &str, offered: &[&'static str]| MockRequest::get("/").header(header::ACCEPT, accept).build().accepts(offered);
+    assert_eq!(accepts("text/html;q=0.5, application/*", &["text/html", "application/json"]), Some("application/json"));
+    assert_eq!(accepts("*/*;q=0.1, text/html", &["application/json", "text/html"]), Some("text/html"));
+    assert_eq!(accepts("application/*;q=0.9, application/json;q=0", &["application/json"]), None);
+    assert_eq!(MockRequest::get("/").build().accepts(&["text/plain", "text/html"]), Some("text/plain"));
+
+    let req = MockRequest::get("/").header(header::ACCEPT_ENCODING, "gzip;q=0.5, br, *;q=0.1").build();
+    assert_eq!(req.accepts_encoding(&["gzip", "br"]), Some("br"));
+    assert_eq!(req.accepts_encoding(&["zstd"]), Some("zstd"));
+    assert_eq!(MockRequest::get("/").build().accepts_encoding(&["gzip"]), None);
+
+    let mut res = SyncResponse::new();
+    res.negotiate(&MockRequest::get("/").header(header::ACCEPT, "application/json").build(), &vec![1, 2]);
+    assert_eq!(res.get_body(), b"[1,2]".to_vec());
+    let mut res = SyncResponse::new();
+    res.negotiate(&MockRequest::get("/").header(header::ACCEPT, "image/png").build(), &vec![1, 2]);
+    assert_eq!(res.get_status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[test]
+fn locale_negotiation() {
+    let supported = ["en", "fr", "fr-CA"];
+    assert_eq!(negotiate_language("fr-CA;q=0.5, de", &supported), Some("fr-CA"));
+    assert_eq!(negotiate_language("en-GB, fr;q=0.9", &supported), Some("en"));
+    assert_eq!(negotiate_language("FR", &supported), Some("fr"));
+    assert_eq!(negotiate_language("de, *;q=0.1", &supported), Some("en"));
+    assert_eq!(negotiate_language("de, en;q=0", &supported), None);
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(LocaleMiddleware::new(vec!["en", "fr"], "en"), vec!("/"), None);
+
+    let (res, _) = dispatch(&stack, Method::GET, "/", &[("accept-language", "fr-BE, en;q=0.5")]);
+    assert_eq!(res.headers_map().get(header::CONTENT_LANGUAGE).unwrap(), "fr");
+    assert_eq!(res.headers_map().get(header::VARY).unwrap(), "Accept-Language");
+    assert_eq!(res.get_extensions().get::<Locale>(), Some(&Locale("fr".to_string())));
+
+    let (res, _) = dispatch(&stack, Method::GET, "/", &[("accept-language", "de")]);
+    assert_eq!(res.headers_map().get(header::CONTENT_LANGUAGE).unwrap(), "en");
+}
+
+#[test]
+fn idempotency_key() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct SharedStore(Arc<MemoryIdempotencyStore>);
+
+    impl IdempotencyStore for SharedStore {
+        fn reserve(&self, key: &str, fingerprint: u64) -> Option<IdempotencyEntry> { self.0.reserve(key, fingerprint) }
+        fn complete(&self, key: &str, response: CachedResponse) { self.0.complete(key, response) }
+        fn release(&self, key: &str) { self.0.release(key) }
+    }
+
+    let store = Arc::new(MemoryIdempotencyStore::new());
+    let mut stack = MiddlewareStack::new();
+    stack.apply(IdempotencyMiddleware::new(SharedStore(store.clone())), vec!("/"), None);
+
+    let calls = AtomicUsize::new(0);
+    let send = |key: Option<&str>, body: &[u8]| {
+        let mut builder = Request::builder();
+        builder.method(Method::POST).uri("/payments");
+        if let Some(key) = key {
+            builder.header("idempotency-key", key);
+        }
+
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, body.to_vec());
+        let mut res = SyncResponse::new();
+        stack.resolve_with(&mut req, &mut res, |req, res| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            let status = if req.body() == b"fail" { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::CREATED };
+            res.status(status).header("x-call", call.to_string()).body(req.body().clone());
+        });
+        res
+    };
+
+    let res = send(Some("a"), b"42");
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert!(res.headers_map().get("idempotent-replayed").is_none());
+
+    let res = send(Some("a"), b"42");
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(res.headers_map().get("x-call").unwrap(), "0");
+    assert_eq!(res.headers_map().get("idempotent-replayed").unwrap(), "true");
+    assert_eq!(res.get_body(), b"42".to_vec());
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    assert_eq!(send(Some("a"), b"43").get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    assert!(store.reserve("b", 0).is_none());
+    assert_eq!(send(Some("b"), b"").get_status(), StatusCode::CONFLICT);
+
+    assert_eq!(send(Some("c"), b"fail").get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(send(Some("c"), b"fail").headers_map().get("x-call").unwrap(), "2");
+
+    send(None, b"42");
+    send(None, b"42");
+    assert_eq!(calls.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn idempotency_key_release() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let calls = AtomicUsize::new(0);
+    let mut controller = BasicController::new(calls);
+    controller.add(Method::POST, "^/payments$", |calls, _, res| {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+            panic!("payment provider unavailable");
+        }
+        res.status(StatusCode::CREATED);
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(IdempotencyMiddleware::new(MemoryIdempotencyStore::new()), vec!("/"), None);
+    let client = test::TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    // The key of a request whose handler panicked is released, so the retry is processed
+    let send = || client.post("/payments").header("idempotency-key", "a").send().get_status();
+    assert_eq!(send(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(send(), StatusCode::CREATED);
+
+    // Reservations which are never completed nor released expire
+    let store = MemoryIdempotencyStore::new().reservation_timeout(Duration::from_millis(20));
+    assert!(store.reserve("b", 0).is_none());
+    assert!(store.reserve("b", 0).is_some());
+    thread::sleep(Duration::from_millis(40));
+    assert!(store.reserve("b", 0).is_none());
+}
+
+#[test]
+fn streamed_responses() {
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let path = ::std::env::temp_dir().join(format!("saphir-streamed-{}.jsonl", ::std::process::id()));
+    let mut stack = MiddlewareStack::new();
+    stack.apply(RecordingMiddleware::new(&path).unwrap(), vec!("/"), None);
+    stack.apply(ConditionalGetMiddleware::new(), vec!("/"), None);
+    stack.apply(ResponseCacheMiddleware::new(MemoryCacheStore::new(16)), vec!("/"), None);
+    stack.apply(CoalescingMiddleware::new(), vec!("/"), None);
+    stack.apply(IdempotencyMiddleware::new(MemoryIdempotencyStore::new()).methods(vec![Method::GET]), vec!("/"), None);
+    let stack = Arc::new(stack);
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    // The senders are kept open, a middleware reading the body would wait for the end of the stream forever
+    let send = || {
+        let (done_tx, done_rx) = mpsc::channel();
+        let (stack, calls) = (stack.clone(), calls.clone());
+        thread::spawn(move || {
+            let (parts, _) = Request::get("/events").header("idempotency-key", "a").body(()).unwrap().into_parts();
+            let mut req = SyncRequest::new(parts, Vec::new());
+            let mut res = SyncResponse::new();
+            let mut senders = Vec::new();
+            stack.resolve_with(&mut req, &mut res, |_, res| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                senders.push(res.status(StatusCode::OK).stream());
+            });
+            let _ = done_tx.send((res.get_status(), res.headers_map().contains_key(header::ETAG)));
+        });
+        done_rx.recv_timeout(Duration::from_secs(5)).expect("an after hook read the streamed body")
+    };
+
+    // Neither stored nor replayed, so both requests reach the handler
+    assert_eq!(send(), (StatusCode::OK, false));
+    assert_eq!(send(), (StatusCode::OK, false));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    let recording = load_recording(&path).unwrap();
+    assert_eq!(recording.len(), 2);
+    assert!(recording[0].response.body.as_bytes().is_empty());
+    let _ = ::std::fs::remove_file(&path);
+}
+
+#[test]
+fn request_coalescing() {
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(CoalescingMiddleware::new(), vec!("/"), None);
+    let stack = Arc::new(stack);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(4));
+
+    let threads: Vec<_> = (0..4).map(|i| {
+        let (stack, calls, barrier) = (stack.clone(), calls.clone(), barrier.clone());
+        thread::spawn(move || {
+            let user = if i == 3 { "bob" } else { "alice" };
+            let (parts, _) = Request::get("/reports?year=2018").header("authorization", user).body(()).unwrap().into_parts();
+            let mut req = SyncRequest::new(parts, Vec::new());
+            let mut res = SyncResponse::new();
+
+            barrier.wait();
+            stack.resolve_with(&mut req, &mut res, |req, res| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(300));
+                res.status(StatusCode::OK).body(req.headers_map()[header::AUTHORIZATION].as_bytes().to_vec());
+            });
+            (user, res.get_body())
+        })
+    }).collect();
+
+    for thread in threads {
+        let (user, body) = thread.join().unwrap();
+        assert_eq!(body, user.as_bytes().to_vec());
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn circuit_breaker() {
+    use std::cell::Cell;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(CircuitBreakerMiddleware::new().route("^/search").failure_threshold(0.5, 4).open_duration(Duration::from_millis(200)),
+                vec!("/"), None);
+
+    let upstream_status = Cell::new(StatusCode::BAD_GATEWAY);
+    let calls = Cell::new(0);
+    let send = |path: &str| {
+        let (parts, _) = Request::get(path).body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, Vec::new());
+        let mut res = SyncResponse::new();
+        stack.resolve_with(&mut req, &mut res, |_, res| {
+            calls.set(calls.get() + 1);
+            res.status(upstream_status.get());
+        });
+        res
+    };
+
+    assert_eq!(send("/search?q=a").get_status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(send("/search?q=b").get_status(), StatusCode::BAD_GATEWAY);
+    upstream_status.set(StatusCode::OK);
+    assert_eq!(send("/search/advanced").get_status(), StatusCode::OK);
+    upstream_status.set(StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(send("/search").get_status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(calls.get(), 4);
+
+    let res = send("/search");
+    assert_eq!(res.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers_map().get(header::RETRY_AFTER).unwrap(), "1");
+    assert_eq!(calls.get(), 4);
+
+    upstream_status.set(StatusCode::OK);
+    assert_eq!(send("/users").get_status(), StatusCode::OK);
+
+    thread::sleep(Duration::from_millis(250));
+    upstream_status.set(StatusCode::BAD_GATEWAY);
+    assert_eq!(send("/search").get_status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(send("/search").get_status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    thread::sleep(Duration::from_millis(250));
+    upstream_status.set(StatusCode::OK);
+    assert_eq!(send("/search").get_status(), StatusCode::OK);
+    assert_eq!(send("/search").get_status(), StatusCode::OK);
+    assert_eq!(calls.get(), 8);
+}
+
+#[test]
+fn flash_messages() {
+    use saphir::test::TestClient;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/profile$", |_, _, res| {
+        res.flash(FlashLevel::Success, "Your profile was saved").flash(FlashLevel::Warning, "Your email isn't verified").see_other("/profile");
+    });
+    controller.add(Method::GET, "^/profile$", |_, req, res| {
+        let notices: Vec<String> = req.flash().iter().map(|flash| format!("{:?}: {}", flash.level, flash.message)).collect();
+        res.status(StatusCode::OK).body(notices.join("\n"));
+    });
+    controller.add(Method::GET, "^/style.css$", |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(FlashMiddleware::new(), vec!("^/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let res = client.post("/profile").send();
+    assert_eq!(res.get_status(), StatusCode::SEE_OTHER);
+    let cookie = res.headers_map()[header::SET_COOKIE].to_str().unwrap().to_string();
+    assert!(cookie.starts_with("saphir_flash=") && cookie.contains("Max-Age=300"));
+    let flash = cookie.split(';').next().unwrap().to_string();
+
+    // Requests which don't read the messages leave them in place
+    let res = client.get("/style.css").header(header::COOKIE, flash.as_str()).send();
+    assert!(!res.headers_map().contains_key(header::SET_COOKIE));
+
+    let res = client.get("/profile").header(header::COOKIE, format!("session=42; {}", flash)).send();
+    assert_eq!(res.get_body(), b"Success: Your profile was saved\nWarning: Your email isn't verified".to_vec());
+    assert!(res.headers_map()[header::SET_COOKIE].to_str().unwrap().starts_with("saphir_flash=; Path=/; Max-Age=0"));
+
+    assert!(client.get("/profile").send().get_body().is_empty());
+}
+
+#[test]
+fn request_metrics() {
+    use saphir::test::TestClient;
+
+    let metrics = Metrics::new();
+    metrics.histogram("order_amount_dollars", &[10.0, 100.0]);
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users/([0-9]+)$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.label(Method::GET, "^/users/([0-9]+)$", "users.show");
+    controller.add(Method::POST, "^/orders$", |_, req, res| {
+        let metrics = req.metrics().unwrap();
+        metrics.increment("orders_total", &[("payment", "card")]);
+        metrics.observe("order_amount_dollars", &[], 42.5);
+        res.status(StatusCode::CREATED);
+    });
+
+    let mut router = Router::new();
+    router.add("^/metrics$", metrics.controller());
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(MetricsMiddleware::new(metrics.clone()), vec!("^/"), Some(vec!("^/metrics$")));
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    client.get("/users/1").send();
+    client.get("/users/2").send();
+    client.post("/orders").send();
+    client.get("/missing").send();
+
+    assert_eq!(metrics.counter("http_requests_total", &[("method", "GET"), ("route", "users.show"), ("status", "200")]), 2);
+    assert_eq!(metrics.counter("http_requests_total", &[("status", "201"), ("route", "^/orders$"), ("method", "POST")]), 1);
+    assert_eq!(metrics.counter("http_requests_total", &[("method", "GET"), ("route", "unmatched"), ("status", "400")]), 1);
+    assert_eq!(metrics.histogram_count("http_request_duration_seconds", &[("method", "GET"), ("route", "users.show")]).0, 2);
+    assert_eq!(metrics.counter("orders_total", &[("payment", "card")]), 1);
+
+    let res = client.get("/metrics").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/plain; version=0.0.4; charset=utf-8");
+    let text = String::from_utf8(res.get_body()).unwrap();
+    assert!(text.contains("# HELP http_requests_total Requests answered, by method, route and status\n# TYPE http_requests_total counter\n"));
+    assert!(text.contains("http_requests_total{method=\"GET\",route=\"users.show\",status=\"200\"} 2\n"));
+    assert!(text.contains("orders_total{payment=\"card\"} 1\n"));
+    assert!(text.contains("# TYPE order_amount_dollars histogram\norder_amount_dollars_bucket{le=\"10\"} 0\norder_amount_dollars_bucket{le=\"100\"} 1\n\
+                           order_amount_dollars_bucket{le=\"+Inf\"} 1\norder_amount_dollars_sum 42.5\norder_amount_dollars_count 1\n"));
+
+    // The scrapes themselves are excluded from the middleware
+    assert_eq!(metrics.counter("http_requests_total", &[("method", "GET"), ("route", "unmatched"), ("status", "200")]), 0);
+}
+
+#[test]
+fn rate_limit_by_identity() {
+    use std::time::Duration;
+
+    struct ApiKey {
+        id: String,
+        plan: String,
+    }
+
+    struct ApiKeyMiddleware;
+
+    impl Middleware for ApiKeyMiddleware {
+        fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+            let key = req.headers_map().get("x-api-key").and_then(|key| key.to_str().ok()).map(|key| key.to_string());
+            if let Some(key) = key {
+                let plan = if key.starts_with("pro-") { "pro" } else { "free" };
+                req.extensions_mut().insert(ApiKey { id: key, plan: plan.to_string() });
+            }
+            RequestContinuation::Next
+        }
+
+        fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+            RequestContinuation::Next
+        }
+    }
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(ApiKeyMiddleware, vec!("/"), None);
+    stack.apply(RateLimitMiddleware::new(RateLimit::new(1, Duration::from_secs(60)))
+                    .tier("free", RateLimit::new(2, Duration::from_secs(60)))
+                    .tier("pro", RateLimit::new(5, Duration::from_millis(200)))
+                    .identity(|key: &ApiKey| RateLimitKey::new(key.id.clone()).tier(key.plan.clone())), vec!("/"), None);
+
+    let (res, routed) = dispatch(&stack, Method::GET, "/items", &[]);
+    assert_eq!(routed, "/items");
+    assert_eq!(res.headers_map()["x-ratelimit-limit"], "1");
+    assert_eq!(res.headers_map()["x-ratelimit-remaining"], "0");
+    assert_eq!(res.headers_map()["x-ratelimit-reset"], "60");
+    let (res, routed) = dispatch(&stack, Method::GET, "/items", &[]);
+    assert_eq!(routed, "");
+    assert_eq!(res.get_status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers_map()[header::RETRY_AFTER], "60");
+
+    let free = [("x-api-key", "free-1")];
+    assert_eq!(dispatch(&stack, Method::GET, "/items", &free).0.headers_map()["x-ratelimit-remaining"], "1");
+    assert_eq!(dispatch(&stack, Method::GET, "/items", &free).0.headers_map()["x-ratelimit-remaining"], "0");
+    assert_eq!(dispatch(&stack, Method::GET, "/items", &free).0.get_status(), StatusCode::TOO_MANY_REQUESTS);
+    let (_, routed) = dispatch(&stack, Method::GET, "/items", &[("x-api-key", "free-2")]);
+    assert_eq!(routed, "/items");
+
+    let pro = [("x-api-key", "pro-1")];
+    for remaining in (0..5).rev() {
+        let (res, _) = dispatch(&stack, Method::GET, "/items", &pro);
+        assert_eq!(res.headers_map()["x-ratelimit-limit"], "5");
+        assert_eq!(res.headers_map()["x-ratelimit-remaining"], remaining.to_string().as_str());
+        assert_eq!(res.headers_map()["x-ratelimit-reset"], "1");
+    }
+    assert_eq!(dispatch(&stack, Method::GET, "/items", &pro).0.get_status(), StatusCode::TOO_MANY_REQUESTS);
+    std::thread::sleep(Duration::from_millis(250));
+    let (res, routed) = dispatch(&stack, Method::GET, "/items", &pro);
+    assert_eq!(routed, "/items");
+    assert_eq!(res.headers_map()["x-ratelimit-remaining"], "4");
+}
+
+#[test]
+fn security_headers() {
+    let mut stack = MiddlewareStack::new();
+    stack.apply(SecurityHeadersMiddleware::new(), vec!("/"), None);
+
+    let (res, _) = dispatch(&stack, Method::GET, "/", &[]);
+    assert_eq!(res.headers_map().get(header::STRICT_TRANSPORT_SECURITY).unwrap(), "max-age=31536000; includeSubDomains");
+    assert_eq!(res.headers_map().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+    assert_eq!(res.headers_map().get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+    assert_eq!(res.headers_map().get(header::REFERRER_POLICY).unwrap(), "strict-origin-when-cross-origin");
+    assert!(res.headers_map().get(header::CONTENT_SECURITY_POLICY).is_none());
+
+    let csp = ContentSecurityPolicy::new()
+        .default_src(vec!["'self'"])
+        .img_src(vec!["'self'", "data:"])
+        .upgrade_insecure_requests();
+    assert_eq!(csp.to_header_value(), "default-src 'self'; img-src 'self' data:; upgrade-insecure-requests");
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(SecurityHeadersMiddleware::new()
+                    .hsts(Some(StrictTransportSecurity::new(600).preload()))
+                    .frame_options(Some(FrameOptions::SameOrigin))
+                    .referrer_policy(None)
+                    .content_type_options(false)
+                    .content_security_policy_report_only(csp), vec!("/"), None);
+
+    let (res, _) = dispatch(&stack, Method::GET, "/", &[]);
+    assert_eq!(res.headers_map().get(header::STRICT_TRANSPORT_SECURITY).unwrap(), "max-age=600; preload");
+    assert_eq!(res.headers_map().get(header::X_FRAME_OPTIONS).unwrap(), "SAMEORIGIN");
+    assert!(res.headers_map().get(header::REFERRER_POLICY).is_none());
+    assert!(res.headers_map().get(header::X_CONTENT_TYPE_OPTIONS).is_none());
+    assert!(res.headers_map().get(header::CONTENT_SECURITY_POLICY).is_none());
+    assert_eq!(res.headers_map().get(header::CONTENT_SECURITY_POLICY_REPORT_ONLY).unwrap(),
+               "default-src 'self'; img-src 'self' data:; upgrade-insecure-requests");
+}
+
+#[test]
+fn response_cache() {
+    use std::cell::Cell;
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(ResponseCacheMiddleware::new(MemoryCacheStore::new(16)), vec!("/"), None);
+
+    let calls = Cell::new(0);
+    let send = |uri: &str, headers: &[(&str, &str)]| {
+        let mut builder = Request::builder();
+        builder.method(Method::GET).uri(uri);
+        for &(name, value) in headers {
+            builder.header(name, value);
+        }
+
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, Vec::new());
+        let mut res = SyncResponse::new();
+        stack.resolve_with(&mut req, &mut res, |req, res| {
+            calls.set(calls.get() + 1);
+            let language = req.headers_map().get(header::ACCEPT_LANGUAGE).map(|l| l.to_str().unwrap().to_string()).unwrap_or_default();
+            res.status(StatusCode::OK).header(header::VARY, "Accept-Language");
+            match req.uri().path() {
+                "/private" => res.header(header::CACHE_CONTROL, "private, max-age=60"),
+                "/cookie" => res.header(header::CACHE_CONTROL, "max-age=60").header(header::SET_COOKIE, "id=1"),
+                _ => res.header(header::CACHE_CONTROL, "max-age=60"),
+            }.body(format!("{} {}", language, calls.get()));
+        });
+        res
+    };
+
+    let res = send("/greeting", &[("accept-language", "fr")]);
+    assert_eq!(res.get_body(), b"fr 1".to_vec());
+    let res = send("/greeting", &[("accept-language", "fr")]);
+    assert_eq!(res.get_body(), b"fr 1".to_vec());
+    assert!(res.headers_map().contains_key(header::AGE));
+
+    assert_eq!(send("/greeting", &[("accept-language", "en")]).get_body(), b"en 2".to_vec());
+    assert_eq!(send("/greeting", &[("accept-language", "en")]).get_body(), b"en 2".to_vec());
+    assert_eq!(send("/greeting", &[("accept-language", "fr")]).get_body(), b"fr 1".to_vec());
+    assert_eq!(send("/greeting?page=2", &[("accept-language", "fr")]).get_body(), b"fr 3".to_vec());
+
+    assert_eq!(send("/greeting", &[("accept-language", "fr"), ("cache-control", "no-cache")]).get_body(), b"fr 4".to_vec());
+    assert_eq!(send("/private", &[]).get_body(), b" 5".to_vec());
+    assert_eq!(send("/private", &[]).get_body(), b" 6".to_vec());
+    assert_eq!(send("/cookie", &[]).get_body(), b" 7".to_vec());
+    assert_eq!(send("/cookie", &[]).get_body(), b" 8".to_vec());
+
+    let store = MemoryCacheStore::new(2);
+    let cached = |body: &[u8]| CachedResponse {
+        status: StatusCode::OK,
+        headers: header::HeaderMap::new(),
+        body: body.to_vec(),
+        stored_at: std::time::SystemTime::now(),
+        max_age: std::time::Duration::from_secs(60),
+    };
+    store.put("a".to_string(), cached(b"a"));
+    store.put("b".to_string(), cached(b"b"));
+    assert!(store.get("a").is_some());
+    store.put("c".to_string(), cached(b"c"));
+    assert_eq!(store.len(), 2);
+    assert!(store.get("b").is_none());
+    assert_eq!(store.get("a").unwrap().body, b"a".to_vec());
+}
+
+#[test]
+fn conditional_get() {
+    let mut stack = MiddlewareStack::new();
+    stack.apply(ConditionalGetMiddleware::new(), vec!("/"), None);
+
+    let send = |method: Method, uri: &str, headers: &[(&str, &str)]| {
+        let mut builder = Request::builder();
+        builder.method(method).uri(uri);
+        for &(name, value) in headers {
+            builder.header(name, value);
+        }
+
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, Vec::new());
+        let mut res = SyncResponse::new();
+        stack.resolve_with(&mut req, &mut res, |req, res| {
+            if req.uri().path() == "/dated" {
+                res.header(header::LAST_MODIFIED, "Wed, 21 Oct 2015 07:28:00 GMT");
+            }
+            res.status(StatusCode::OK).body("hello");
+        });
+        res
+    };
+
+    let etag = strong_etag(b"hello").to_string();
+    assert_eq!(etag, weak_etag(b"hello").to_string().trim_start_matches("W/"));
+
+    let res = send(Method::GET, "/", &[]);
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get(header::ETAG).unwrap(), etag.as_str());
+
+    let res = send(Method::GET, "/", &[("if-none-match", &format!("\"other\", W/{}", etag))]);
+    assert_eq!(res.get_status(), StatusCode::NOT_MODIFIED);
+    assert!(res.get_body().is_empty());
+
+    assert_eq!(send(Method::HEAD, "/", &[("if-none-match", "*")]).get_status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(send(Method::GET, "/", &[("if-none-match", "\"other\"")]).get_status(), StatusCode::OK);
+    assert_eq!(send(Method::POST, "/", &[("if-none-match", "*")]).get_status(), StatusCode::OK);
+
+    assert_eq!(send(Method::GET, "/dated", &[("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT")]).get_status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(send(Method::GET, "/dated", &[("if-modified-since", "Tue, 20 Oct 2015 07:28:00 GMT")]).get_status(), StatusCode::OK);
+    // If-None-Match takes precedence over If-Modified-Since
+    let res = send(Method::GET, "/dated", &[("if-none-match", "\"other\""), ("if-modified-since", "Wed, 21 Oct 2015 07:28:00 GMT")]);
+    assert_eq!(res.get_status(), StatusCode::OK);
+}
+
+#[test]
+fn preconditions() {
+    use saphir::header::EntityTag;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    struct Article {
+        revision: u64,
+    }
+
+    impl Versioned for Article {
+        fn etag(&self) -> Option<EntityTag> {
+            Some(EntityTag::strong(self.revision.to_string()))
+        }
+
+        fn last_modified(&self) -> Option<::std::time::SystemTime> {
+            // Wed, 21 Oct 2015 07:28:00 GMT
+            Some(UNIX_EPOCH + Duration::from_secs(1_445_412_480))
+        }
+    }
+
+    let check = |article: Option<&Article>, headers: &[(&str, &str)]| {
+        let mut builder = Request::builder();
+        builder.method(Method::PUT).uri("/articles/1");
+        for &(name, value) in headers {
+            builder.header(name, value);
+        }
+
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        let allowed = SyncRequest::new(parts, Vec::new()).check_preconditions(article, &mut res);
+        (allowed, res.get_status(), res.headers_map().get(header::ETAG).map(|etag| etag.to_str().unwrap().to_string()))
+    };
+
+    let article = Article { revision: 3 };
+    assert_eq!(check(Some(&article), &[]), (true, StatusCode::OK, None));
+    assert!(check(Some(&article), &[("if-match", "\"2\", \"3\"")]).0);
+    assert_eq!(check(Some(&article), &[("if-match", "\"2\"")]), (false, StatusCode::PRECONDITION_FAILED, Some("\"3\"".to_string())));
+    // If-Match uses the strong comparison
+    assert!(!check(Some(&article), &[("if-match", "W/\"3\"")]).0);
+    assert!(check(Some(&article), &[("if-match", "*")]).0);
+    assert!(!check(None, &[("if-match", "*")]).0);
+
+    assert!(check(Some(&article), &[("if-unmodified-since", "Wed, 21 Oct 2015 07:28:00 GMT")]).0);
+    assert!(!check(Some(&article), &[("if-unmodified-since", "Tue, 20 Oct 2015 07:28:00 GMT")]).0);
+    // If-Match takes precedence over If-Unmodified-Since
+    assert!(check(Some(&article), &[("if-match", "\"3\""), ("if-unmodified-since", "Tue, 20 Oct 2015 07:28:00 GMT")]).0);
+
+    let (parts, _) = Request::builder().method(Method::DELETE).header("if-match", "\"v1\"").body(()).unwrap().into_parts();
+    let tag = EntityTag::strong("v1".to_string());
+    assert!(SyncRequest::new(parts, Vec::new()).check_preconditions(Some(&tag), &mut SyncResponse::new()));
+}
+
+#[test]
+fn preload_links() {
+    use saphir::test::TestClient;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/page$", |_, _, res| {
+        res.header(header::LINK, "</app.js>; rel=preload; as=script");
+        res.header(header::CONTENT_TYPE, "text/html; charset=utf-8").status(StatusCode::OK).body(concat!(
+            "<html><head><link rel=\"stylesheet\" href=\"/style.css\"><link rel='preload' href='/font.woff2' as='font'>",
+            "<link rel=icon href=/favicon.ico><link rel=\"stylesheet\" href=\"https://cdn.example.com/lib.css\">",
+            "<script src=\"/app.js\"></script><SCRIPT type=\"module\" SRC=\"/main.mjs\"></SCRIPT></head></html>",
+        ));
+    });
+    controller.add(Method::GET, "^/data$", |_, _, res| {
+        res.status(StatusCode::OK).body("<link rel=\"stylesheet\" href=\"/style.css\">");
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(PreloadMiddleware::new().limit(2), vec!("/"), None);
+
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+    let res = client.get("/page").send();
+    let links: Vec<_> = res.headers_map().get_all(header::LINK).iter().map(|link| link.to_str().unwrap().to_string()).collect();
+    assert_eq!(links, vec![
+        "</app.js>; rel=preload; as=script",
+        "</style.css>; rel=preload; as=style",
+        "</font.woff2>; rel=preload; as=font",
+    ]);
+
+    assert!(client.get("/data").send().headers_map().get(header::LINK).is_none());
+}
+
+#[test]
+fn tenancy() {
+    use saphir::test::TestClient;
+
+    struct Plan(&'static str);
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/plan$", |_, req, res| {
+        let tenant = req.tenant().map_or("none", |tenant| tenant.id());
+        let plan = req.state::<Plan>().map_or("none", |plan| plan.0);
+        res.status(StatusCode::OK).body(format!("{} {} {}", tenant, plan, req.uri()));
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let mut acme = StateMap::new();
+    acme.insert(Plan("enterprise"));
+    let mut stack = MiddlewareStack::new();
+    stack.apply(TenantMiddleware::new(SubdomainTenant::new("example.com")).tenant("acme", acme).tenant("globex", StateMap::new()).required(),
+                vec!("/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).state(Plan("free")).build());
+
+    let plan = |host: &str| {
+        let res = client.get("/plan").header(header::HOST, host).send();
+        (res.get_status(), String::from_utf8(res.get_body()).unwrap())
+    };
+    assert_eq!(plan("ACME.example.com:8080"), (StatusCode::OK, "acme enterprise /plan".to_string()));
+    assert_eq!(plan("globex.example.com"), (StatusCode::OK, "globex free /plan".to_string()));
+    assert_eq!(plan("initech.example.com").0, StatusCode::NOT_FOUND);
+    assert_eq!(plan("a.acme.example.com").0, StatusCode::NOT_FOUND);
+    assert_eq!(plan("example.com").0, StatusCode::NOT_FOUND);
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(TenantMiddleware::new(PathPrefixTenant::new()), vec!("/"), None);
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/(plan)?$", |_, req, res| {
+        res.status(StatusCode::OK).body(format!("{} {}", req.tenant().map_or("none", |tenant| tenant.id()), req.uri()));
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+    assert_eq!(client.get("/acme/plan?page=2").send().get_body(), b"acme /plan?page=2".to_vec());
+    assert_eq!(client.get("/acme").send().get_body(), b"acme /".to_vec());
+    assert_eq!(client.get("/").send().get_body(), b"none /".to_vec());
+
+    let (parts, _) = Request::get("/").header("x-tenant-id", " acme ").body(()).unwrap().into_parts();
+    let req = SyncRequest::new(parts, Vec::new());
+    assert_eq!(HeaderTenant::new("X-Tenant-Id").resolve(&req), Some("acme".to_string()));
+    assert_eq!((|_: &SyncRequest| Some("fixed".to_string())).resolve(&req), Some("fixed".to_string()));
+}
+
+#[test]
+fn body_transformers() {
+    use saphir::test::TestClient;
+    use std::io;
+
+    // Requests must be prefixed with `a:`, responses are wrapped in `<a>` elements
+    struct Framing;
+
+    struct Frame {
+        started: bool,
+    }
+
+    impl BodyTransform for Frame {
+        fn chunk(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+            let mut framed = if self.started { Vec::new() } else { b"<a>".to_vec() };
+            self.started = true;
+            framed.extend_from_slice(chunk);
+            Ok(framed)
+        }
+
+        fn finish(&mut self) -> io::Result<Vec<u8>> {
+            Ok(b"</a>".to_vec())
+        }
+    }
+
+    impl BodyTransformer for Framing {
+        fn request(&self, req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+            if *req.method() == Method::GET {
+                return None;
+            }
+
+            Some(Box::new(|chunk: &[u8]| match chunk.strip_prefix(b"a:") {
+                Some(body) => Ok(body.to_vec()),
+                None => Err(io::Error::new(io::ErrorKind::InvalidData, "missing prefix")),
+            }))
+        }
+
+        fn response(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+            Some(Box::new(Frame { started: false }))
+        }
+    }
+
+    // Request bodies are lowercased, and response bodies uppercased
+    struct Case;
+
+    impl BodyTransformer for Case {
+        fn request(&self, _req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+            Some(Box::new(|chunk: &[u8]| Ok(chunk.to_ascii_lowercase())))
+        }
+
+        fn response(&self, req: &SyncRequest, res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+            res.header("x-transformed", req.uri().path());
+            Some(Box::new(|chunk: &[u8]| Ok(chunk.to_ascii_uppercase())))
+        }
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        res.status(StatusCode::OK).body(format!("{} world", String::from_utf8_lossy(req.body())));
+    });
+    controller.add(Method::GET, "^/stream$", |_, _, res| {
+        let mut sender = res.status(StatusCode::OK).stream();
+        sender.send("one ").unwrap();
+        sender.send("two").unwrap();
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(BodyTransformMiddleware::new(Framing), vec!("/"), None);
+    stack.apply(BodyTransformMiddleware::new(Case), vec!("/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let res = client.post("/echo").body("a:HELLO").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get("x-transformed").unwrap(), "/echo");
+    assert_eq!(res.get_body(), b"<a>HELLO WORLD</a>".to_vec());
+
+    assert_eq!(client.get("/stream").send().get_body(), b"<a>ONE TWO</a>".to_vec());
+    assert_eq!(client.post("/echo").body("hello").send().get_status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn response_size() {
+    use saphir::test::TestClient;
+
+    let metrics = Metrics::new();
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/page$", |_, _, res| { res.status(StatusCode::OK).body(vec![b'a'; 1500]); });
+    controller.add(Method::GET, "^/stream$", |_, _, res| {
+        let mut sender = res.status(StatusCode::OK).stream();
+        sender.send(vec![b'a'; 200]).unwrap();
+        sender.send(vec![b'a'; 50]).unwrap();
+    });
+    controller.add_with_policy(Method::GET, "^/limited$", RoutePolicy::new().max_response_size(1000), |_, _, res| {
+        res.status(StatusCode::OK).body(vec![b'a'; 1500]);
+    });
+    controller.label(Method::GET, "^/page$", "page");
+    controller.label(Method::GET, "^/stream$", "stream");
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(MetricsMiddleware::new(metrics.clone()), vec!("^/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    assert_eq!(client.get("/page").send().get_body().len(), 1500);
+    assert_eq!(metrics.histogram_count("http_response_size_bytes", &[("method", "GET"), ("route", "page")]), (1, 1500.0));
+
+    // Streamed bodies are measured once they are sent
+    let res = client.get("/stream").send();
+    assert_eq!(metrics.histogram_count("http_response_size_bytes", &[("method", "GET"), ("route", "stream")]), (0, 0.0));
+    assert_eq!(res.get_body().len(), 250);
+    drop(res);
+    assert_eq!(metrics.histogram_count("http_response_size_bytes", &[("method", "GET"), ("route", "stream")]), (1, 250.0));
+    assert!(metrics.render().contains("http_response_size_bytes_bucket{method=\"GET\",route=\"stream\",le=\"1000\"} 1\n"));
+
+    let res = client.get("/limited").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!res.get_body().starts_with(b"aaa"));
+}
+
+#[test]
+fn transactions() {
+    use saphir::test::TestClient;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    struct Connection {
+        log: Arc<Mutex<Vec<String>>>,
+        statements: Vec<String>,
+        fail_commit: bool,
+    }
+
+    impl Transaction for Connection {
+        type Error = String;
+
+        fn commit(self) -> Result<(), String> {
+            if self.fail_commit {
+                return Err("serialization failure".to_string());
+            }
+            self.log.lock().unwrap().push(format!("commit {}", self.statements.join(", ")));
+            Ok(())
+        }
+
+        fn rollback(self) -> Result<(), String> {
+            self.log.lock().unwrap().push(format!("rollback {}", self.statements.join(", ")));
+            Ok(())
+        }
+    }
+
+    struct Pool {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TransactionPool for Pool {
+        type Transaction = Connection;
+
+        fn begin(&self, req: &SyncRequest) -> Result<Connection, String> {
+            match req.headers_map().get("x-database").and_then(|v| v.to_str().ok()) {
+                Some("down") => Err("connection refused".to_string()),
+                database => Ok(Connection {
+                    log: self.log.clone(),
+                    statements: Vec::new(),
+                    fail_commit: database == Some("conflict"),
+                }),
+            }
+        }
+    }
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/orders$", |_, req, res| {
+        req.transaction::<Connection>().unwrap().statements.push("insert".to_string());
+        let status = req.headers_map().get("x-status").and_then(|v| v.to_str().ok()).unwrap_or("200");
+        res.status(StatusCode::from_bytes(status.as_bytes()).unwrap()).body("done");
+    });
+    controller.add(Method::POST, "^/panic$", |_, req, _| {
+        req.transaction::<Connection>().unwrap().statements.push("delete".to_string());
+        panic!("the handler failed");
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(TransactionMiddleware::new(Pool { log: log.clone() }), vec!("^/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    assert_eq!(client.post("/orders").header("x-status", "201").send().get_status(), StatusCode::CREATED);
+    assert_eq!(log.lock().unwrap().drain(..).collect::<Vec<_>>(), vec!["commit insert"]);
+
+    assert_eq!(client.post("/orders").header("x-status", "409").send().get_status(), StatusCode::CONFLICT);
+    assert_eq!(log.lock().unwrap().drain(..).collect::<Vec<_>>(), vec!["rollback insert"]);
+
+    assert_eq!(client.post("/panic").send().get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(log.lock().unwrap().drain(..).collect::<Vec<_>>(), vec!["rollback delete"]);
+
+    let res = client.post("/orders").header("x-database", "conflict").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!res.get_body().starts_with(b"done"));
+    assert!(log.lock().unwrap().is_empty());
+
+    assert_eq!(client.post("/orders").header("x-database", "down").send().get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn deprecated_routes() {
+    use saphir::test::TestClient;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let metrics = Metrics::new();
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/v1/users$", |_, _, res| { res.status(StatusCode::OK).header("link", "</v1/users?page=2>; rel=\"next\""); });
+    controller.add(Method::POST, "^/v1/users$", |_, _, res| { res.status(StatusCode::CREATED); });
+    controller.add(Method::GET, "^/v2/users$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.label(Method::GET, "^/v1/users$", "users.v1");
+    controller.deprecate(Method::GET, "^/v1/users$", Deprecation::new(UNIX_EPOCH + Duration::from_secs(1_688_169_599))
+        .sunset(UNIX_EPOCH + Duration::from_secs(1_782_864_000))
+        .documentation("https://example.com/deprecations/v1")
+        .successor("/v2/users"));
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(MetricsMiddleware::new(metrics.clone()), vec!("^/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let res = client.get("/v1/users").send();
+    assert!(res.is_deprecated_route());
+    assert_eq!(res.headers_map()["deprecation"], "@1688169599");
+    assert_eq!(res.headers_map()["sunset"], "Wed, 01 Jul 2026 00:00:00 GMT");
+    let links: Vec<_> = res.headers_map().get_all("link").iter().map(|v| v.to_str().unwrap()).collect();
+    assert_eq!(links, vec![
+        "<https://example.com/deprecations/v1>; rel=\"deprecation\"; type=\"text/html\"",
+        "</v2/users>; rel=\"successor-version\"",
+        "</v1/users?page=2>; rel=\"next\"",
+    ]);
+
+    // Only the deprecated method of the route is announced
+    let res = client.post("/v1/users").send();
+    assert!(!res.is_deprecated_route());
+    assert!(!res.headers_map().contains_key("deprecation"));
+    assert!(!client.get("/v2/users").send().headers_map().contains_key("deprecation"));
+
+    client.get("/v1/users").send();
+    assert_eq!(metrics.counter("http_deprecated_requests_total", &[("method", "GET"), ("route", "users.v1")]), 2);
+    assert_eq!(metrics.counter("http_deprecated_requests_total", &[("method", "POST"), ("route", "^/v1/users$")]), 0);
+}
+
+#[test]
+fn https_enforcement() {
+    use saphir::test::TestClient;
+    use std::net::SocketAddr;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/", |_, _, res| { res.status(StatusCode::OK).body("page"); });
+    controller.add(Method::POST, "^/account/password$", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(HttpsMiddleware::new()
+        .map_port(8080, 8443)
+        .map_port(80, 443)
+        .require("^/account")
+        .hsts(StrictTransportSecurity::new(63_072_000).include_subdomains().preload()), vec!("^/"), None);
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).trusted_proxies(proxies).build());
+
+    let proxy: SocketAddr = "10.0.0.2:50000".parse().unwrap();
+    let stranger: SocketAddr = "192.0.2.7:50000".parse().unwrap();
+    let send = |method: Method, path: &str, host: &str, peer: SocketAddr, proto: Option<&str>| {
+        let mut req = client.request(method, path).header(header::HOST, host).peer_addr(peer);
+        if let Some(proto) = proto {
+            req = req.header("x-forwarded-proto", proto);
+        }
+        req.send()
+    };
+
+    let res = send(Method::GET, "/account/settings?tab=2", "example.com:8080", stranger, None);
+    assert_eq!(res.get_status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(res.headers_map()[header::LOCATION], "https://example.com:8443/account/settings?tab=2");
+    assert!(!res.headers_map().contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+    let res = send(Method::POST, "/account/password", "example.com", stranger, None);
+    assert_eq!(res.get_status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(res.headers_map()[header::LOCATION], "https://example.com/account/password");
+    assert_eq!(send(Method::GET, "/account", "[::1]:80", stranger, None).headers_map()[header::LOCATION], "https://[::1]/account");
+    assert_eq!(send(Method::GET, "/account", "example.com:9000", stranger, None).headers_map()[header::LOCATION], "https://example.com/account");
+
+    // Only trusted proxies can tell the request was received over https
+    assert_eq!(send(Method::GET, "/account", "example.com", stranger, Some("https")).get_status(), StatusCode::MOVED_PERMANENTLY);
+    let res = send(Method::GET, "/account", "example.com", proxy, Some("https"));
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::STRICT_TRANSPORT_SECURITY], "max-age=63072000; includeSubDomains; preload");
+
+    // Paths which aren't required are served over both schemes
+    let res = send(Method::GET, "/about", "example.com", stranger, None);
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert!(!res.headers_map().contains_key(header::STRICT_TRANSPORT_SECURITY));
+}
+
+#[test]
+fn bot_guard() {
+    use saphir::test::TestClient;
+
+    let mut controller = BasicController::new(());
+    let guard = BotGuard::new()
+        .deny_user_agent("curl|scrapy")
+        .browser_headers()
+        .honeypot("website")
+        .scorer(|req: &SyncRequest| req.headers_map().get("x-score").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()), 0.8);
+    controller.add_with_guards(Method::POST, "^/signup$", guard.into(), |_, _, res| { res.status(StatusCode::CREATED); });
+    controller.add(Method::GET, "^/hidden$", |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(BotGuard::new().deny_user_agent("scrapy").status(StatusCode::NOT_FOUND), vec!("^/hidden"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let signup = |user_agent: &str, languages: Option<&str>, score: Option<&str>, body: &str| {
+        let mut req = client.post("/signup")
+            .header(header::USER_AGENT, user_agent)
+            .header(header::ACCEPT, "text/html")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body);
+        if let Some(languages) = languages {
+            req = req.header(header::ACCEPT_LANGUAGE, languages);
+        }
+        if let Some(score) = score {
+            req = req.header("x-score", score);
+        }
+        req.send().get_status()
+    };
+
+    let browser = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+    assert_eq!(signup(browser, Some("fr-CA"), None, "email=a%40b.c&website="), StatusCode::CREATED);
+    assert_eq!(signup(browser, Some("fr-CA"), Some("0.2"), "email=a%40b.c"), StatusCode::CREATED);
+    assert_eq!(signup("curl/8.5.0", Some("fr-CA"), None, "email=a%40b.c"), StatusCode::FORBIDDEN);
+    assert_eq!(signup("Scrapy/2.11", Some("fr-CA"), None, "email=a%40b.c"), StatusCode::FORBIDDEN);
+    assert_eq!(signup(browser, None, None, "email=a%40b.c"), StatusCode::FORBIDDEN);
+    assert_eq!(signup(browser, Some("fr-CA"), None, "email=a%40b.c&website=http%3A%2F%2Fspam.example"), StatusCode::FORBIDDEN);
+    assert_eq!(signup(browser, Some("fr-CA"), Some("0.8"), "email=a%40b.c"), StatusCode::FORBIDDEN);
+
+    assert_eq!(client.get("/hidden").header(header::USER_AGENT, "Scrapy/2.11").send().get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(client.get("/hidden").send().get_status(), StatusCode::OK);
+}
+
+#[test]
+fn signed_requests() {
+    use saphir::test::TestClient;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let secret = b"whsec_test";
+    let signer = SignedRequestGuard::new(secret);
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::POST, "^/hooks$", SignedRequestGuard::new(secret).into(), |_, req, res| {
+        res.status(StatusCode::OK).body(req.body().clone());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let deliver = |timestamp: u64, nonce: &str, signed_body: &str, body: &str| {
+        let signature = signer.signature(&Method::POST, "/hooks?source=billing", timestamp, nonce, signed_body.as_bytes());
+        client.post("/hooks?source=billing")
+            .header("x-signature", signature.as_str())
+            .header("x-signature-timestamp", timestamp.to_string().as_str())
+            .header("x-signature-nonce", nonce)
+            .body(body.to_string())
+            .send()
+            .get_status()
+    };
+
+    assert_eq!(deliver(now, "n-1", "{\"paid\":true}", "{\"paid\":true}"), StatusCode::OK);
+    // Replayed
+    assert_eq!(deliver(now, "n-1", "{\"paid\":true}", "{\"paid\":true}"), StatusCode::UNAUTHORIZED);
+    // Tampered with
+    assert_eq!(deliver(now, "n-2", "{\"paid\":true}", "{\"paid\":false}"), StatusCode::UNAUTHORIZED);
+    // Signed too long ago, or by another secret
+    assert_eq!(deliver(now - 600, "n-3", "{}", "{}"), StatusCode::UNAUTHORIZED);
+    let forged = SignedRequestGuard::new(b"guess").signature(&Method::POST, "/hooks?source=billing", now, "n-4", b"{}");
+    let res = client.post("/hooks?source=billing").header("x-signature", forged.as_str())
+        .header("x-signature-timestamp", now.to_string().as_str()).header("x-signature-nonce", "n-4").body("{}").send();
+    assert_eq!(res.get_status(), StatusCode::UNAUTHORIZED);
+    // The nonce of a forged request is still usable
+    assert_eq!(deliver(now, "n-4", "{}", "{}"), StatusCode::OK);
+    assert_eq!(client.post("/hooks").body("{}").send().get_status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn webhook_signatures() {
+    use saphir::test::TestClient;
+    use std::io;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    // Request bodies are lowercased before reaching the guards
+    struct Lowercase;
+
+    impl BodyTransformer for Lowercase {
+        fn request(&self, _req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+            Some(Box::new(|chunk: &[u8]| -> io::Result<Vec<u8>> { Ok(chunk.to_ascii_lowercase()) }))
+        }
+
+        fn response(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+            None
+        }
+    }
+
+    let secret = b"It's a Secret to Everybody";
+    let mut controller = BasicController::new(());
+    let echo = |_: &(), req: &SyncRequest, res: &mut SyncResponse| {
+        res.status(StatusCode::OK).body(req.body().clone());
+    };
+    controller.add_with_guards(Method::POST, "^/github$", WebhookSignatureGuard::github(b"rotated").secret(secret).into(), echo);
+    controller.add_with_guards(Method::POST, "^/stripe$", WebhookSignatureGuard::stripe(secret).into(), echo);
+    controller.add_with_guards(Method::POST, "^/slack$", WebhookSignatureGuard::slack(secret).into(), echo);
+    controller.add_with_guards(Method::POST, "^/shopify$", WebhookSignatureGuard::shopify(secret).into(), echo);
+    let custom = WebhookSignatureGuard::new(secret).header("x-custom").timestamp("x-custom-timestamp", Duration::from_secs(60));
+    controller.add_with_guards(Method::POST, "^/custom$", custom.into(), echo);
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(BodyTransformMiddleware::new(Lowercase), vec!("/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    // The known signature of the GitHub documentation, verified over the body as it was received
+    let body = "Hello, World!";
+    let signature = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+    assert_eq!(WebhookSignatureGuard::github(secret).signature(0, body.as_bytes()), signature);
+    let res = client.post("/github").header("x-hub-signature-256", signature).body(body).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"hello, world!".to_vec());
+    assert_eq!(client.post("/github").header("x-hub-signature-256", signature).body("Hello, World?").send().get_status(),
+               StatusCode::UNAUTHORIZED);
+    assert_eq!(client.post("/github").body(body).send().get_status(), StatusCode::UNAUTHORIZED);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let stripe = WebhookSignatureGuard::stripe(secret);
+    let deliver = |timestamp: u64| {
+        client.post("/stripe").header("stripe-signature", stripe.signature(timestamp, b"{}").as_str()).body("{}").send().get_status()
+    };
+    assert_eq!(deliver(now), StatusCode::OK);
+    assert_eq!(deliver(now - 600), StatusCode::UNAUTHORIZED);
+
+    let slack = WebhookSignatureGuard::slack(secret).signature(now, b"token=x");
+    assert!(slack.starts_with("v0="));
+    let res = client.post("/slack").header("x-slack-signature", slack.as_str())
+        .header("x-slack-request-timestamp", now.to_string().as_str()).body("token=x").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    let res = client.post("/slack").header("x-slack-signature", slack.as_str())
+        .header("x-slack-request-timestamp", (now + 1).to_string().as_str()).body("token=x").send();
+    assert_eq!(res.get_status(), StatusCode::UNAUTHORIZED);
+
+    let shopify = WebhookSignatureGuard::shopify(secret).signature(0, b"{}");
+    assert!(shopify.ends_with('='));
+    assert_eq!(client.post("/shopify").header("x-shopify-hmac-sha256", shopify.as_str()).body("{}").send().get_status(), StatusCode::OK);
+
+    let custom = WebhookSignatureGuard::new(secret).timestamp("x-custom-timestamp", Duration::from_secs(60)).signature(now, b"{}");
+    let res = client.post("/custom").header("x-custom", format!("bogus, {}", custom).as_str())
+        .header("x-custom-timestamp", now.to_string().as_str()).body("{}").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(client.post("/custom").header("x-custom", custom.as_str()).body("{}").send().get_status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn serialization_errors() {
+    use saphir::test::TestClient;
+    use std::collections::HashMap;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/stats$", |_, _, res| {
+        // JSON objects can't have tuples as keys
+        let stats: HashMap<(u8, u8), u8> = vec![((1, 2), 3)].into_iter().collect();
+        res.json(&stats).status(StatusCode::CREATED).header("x-total", "1");
+    });
+    let mut router = Router::new();
+    router.add("^/stats$", controller);
+
+    let client = TestClient::new(Server::builder().router(router).build());
+    let res = client.get("/stats").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    assert!(!res.headers_map().contains_key("x-total"));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/stats$", |_, _, res| {
+        let stats: HashMap<(u8, u8), u8> = vec![((1, 2), 3)].into_iter().collect();
+        res.json(&stats);
+    });
+    let mut router = Router::new();
+    router.add("^/stats$", controller);
+
+    let server = Server::builder().router(router).on_serialization_error(|_, error, res| {
+        res.status(StatusCode::SERVICE_UNAVAILABLE).body(format!("{} unavailable", error.format()));
+    });
+    let res = TestClient::new(server.build()).get("/stats").send();
+    assert_eq!(res.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.get_body(), b"json unavailable".to_vec());
+}
+
+#[test]
+fn pagination() {
+    use saphir::test::TestClient;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users$", |_, req, res| {
+        let request = match req.page_request(&Pagination::new().default_per_page(10).max_per_page(20)) {
+            Ok(request) => request,
+            Err(rejection) => { res.problem(&rejection.problem()); return; }
+        };
+
+        let users: Vec<u64> = (request.offset()..45).take(request.per_page() as usize).collect();
+        res.page(req, &Page::new(users, &request).total(45));
+    });
+    controller.add(Method::GET, "^/events$", |_, req, res| {
+        let request = req.page_request(&Pagination::new()).unwrap();
+        let start: u64 = request.cursor().map_or(0, |cursor| cursor.parse().unwrap());
+        let page = Page::new(vec![start, start + 1], &request);
+        res.page(req, &if start == 0 { page.next_cursor("2") } else { page });
+    });
+    let mut router = Router::new();
+    router.add("^/(users|events)$", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/users?sort=name&page=2").send();
+    assert_eq!(res.headers_map()[header::LINK], concat!("</users?sort=name&page=1>; rel=\"first\", </users?sort=name&page=1>; rel=\"prev\", ",
+                                                       "</users?sort=name&page=3>; rel=\"next\", </users?sort=name&page=5>; rel=\"last\""));
+    assert_eq!(res.headers_map()["x-total-count"], "45");
+    let body: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(body, serde_json::json!({ "items": [10, 11, 12, 13, 14, 15, 16, 17, 18, 19], "page": 2, "per_page": 10, "total": 45 }));
+
+    // Larger pages are reduced to the maximum
+    let res = client.get("/users?per_page=100&page=3").send();
+    assert_eq!(res.headers_map()[header::LINK], "</users?per_page=20&page=1>; rel=\"first\", </users?per_page=20&page=2>; rel=\"prev\", </users?per_page=20&page=3>; rel=\"last\"");
+    assert_eq!(client.get("/users?page=0").send().get_status(), StatusCode::BAD_REQUEST);
+    assert_eq!(client.get("/users?per_page=ten").send().get_status(), StatusCode::BAD_REQUEST);
+
+    let res = client.get("/events").send();
+    assert_eq!(res.headers_map()[header::LINK], "</events>; rel=\"first\", </events?cursor=2>; rel=\"next\"");
+    let body: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(body, serde_json::json!({ "items": [0, 1], "per_page": 20, "next_cursor": "2" }));
+    assert_eq!(client.get("/events?cursor=2").send().headers_map()[header::LINK], "</events>; rel=\"first\"");
+}
+
+#[test]
+fn conditional_middlewares() {
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn prepare(&self, req: &mut SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+            if self.0 == "legacy" {
+                *req.uri_mut() = "/current".parse().unwrap();
+            }
+            RequestContinuation::Next
+        }
+
+        fn resolve(&self, _req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+            res.header(format!("x-{}", self.0).as_str(), "resolved");
+            RequestContinuation::Next
+        }
+
+        fn after(&self, _req: &SyncRequest, res: &mut SyncResponse) {
+            res.header(format!("x-{}-after", self.0).as_str(), "done");
+        }
+    }
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(Tag("audit").unless(RequestPredicate::path("^/health$") | RequestPredicate::websocket()), vec!("/"), None);
+    stack.apply(Tag("json").when(RequestPredicate::method(Method::POST) & RequestPredicate::content_type("application/*")), vec!("/"), None);
+    stack.apply(Tag("legacy").when(RequestPredicate::path("^/legacy$")), vec!("/"), None);
+    assert_eq!(stack.middlewares()[2].name, "Tag when path ^/legacy$");
+
+    let (res, routed) = dispatch(&stack, Method::GET, "/users", &[]);
+    assert_eq!(routed, "/users");
+    assert!(res.headers_map().contains_key("x-audit") && res.headers_map().contains_key("x-audit-after"));
+    assert!(!res.headers_map().contains_key("x-json"));
+
+    let (res, _) = dispatch(&stack, Method::GET, "/health", &[]);
+    assert!(!res.headers_map().contains_key("x-audit") && !res.headers_map().contains_key("x-audit-after"));
+    let (res, _) = dispatch(&stack, Method::GET, "/chat", &[("upgrade", "websocket"), ("connection", "keep-alive, Upgrade")]);
+    assert!(!res.headers_map().contains_key("x-audit"));
+
+    let (res, _) = dispatch(&stack, Method::POST, "/users", &[("content-type", "application/json; charset=utf-8")]);
+    assert!(res.headers_map().contains_key("x-json") && res.headers_map().contains_key("x-json-after"));
+    let (res, _) = dispatch(&stack, Method::POST, "/users", &[("content-type", "text/plain")]);
+    assert!(!res.headers_map().contains_key("x-json"));
+
+    // The decision holds for the whole request, though the middleware rewrote its path
+    let (res, routed) = dispatch(&stack, Method::GET, "/legacy", &[]);
+    assert_eq!(routed, "/current");
+    assert!(res.headers_map().contains_key("x-legacy") && res.headers_map().contains_key("x-legacy-after"));
+}
+
+#[test]
+fn blocking_work() {
+    use saphir::test::TestClient;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/work$", |_, req, res| {
+        match req.run_blocking(|| thread::current().name().unwrap_or("").to_string()) {
+            Ok(name) => res.status(StatusCode::OK).body(name),
+            Err(e) => res.problem(&e.problem()),
+        };
+    });
+    let mut router = Router::new();
+    router.add("^/work$", controller);
+    let client = TestClient::new(Server::builder().router(router).blocking_pool(BlockingPool::new(2).name_prefix("reports-")).build());
+    assert!(String::from_utf8(client.get("/work").send().get_body()).unwrap().starts_with("reports-"));
+
+    // Work beyond the threads and the queue of the pool is rejected right away
+    let pool = BlockingPool::new(1).queue_limit(0);
+    let (release, released) = channel::<()>();
+    let busy = pool.clone();
+    let running = thread::spawn(move || busy.run(None, move || released.recv().is_ok()));
+    while pool.in_use() == 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(pool.run(None, || 1), Err(BlockingError::Saturated));
+    assert_eq!(BlockingError::Saturated.status(), StatusCode::SERVICE_UNAVAILABLE);
+    release.send(()).unwrap();
+    assert_eq!(running.join().unwrap(), Ok(true));
+    assert_eq!(pool.run(None, || 1), Ok(1));
+
+    let pool = BlockingPool::new(1).timeout(Duration::from_millis(10));
+    assert_eq!(pool.run(None, || thread::sleep(Duration::from_millis(200))), Err(BlockingError::TimedOut));
+    assert_eq!(BlockingPool::new(1).run(None, || -> u8 { panic!("corrupted image") }), Err(BlockingError::Panicked));
+}
+
+#[test]
+fn temp_uploads() {
+    use saphir::test::TestClient;
+    use std::fs;
+    use std::io;
+
+    let root = std::env::temp_dir().join(format!("saphir-uploads-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("spool")).unwrap();
+    fs::create_dir_all(root.join("blobs")).unwrap();
+
+    let uploads = TempUploads::new().dir(root.join("spool")).max_size(16);
+    let mut controller = BasicController::new((uploads, root.join("blobs")));
+    controller.add(Method::POST, "^/blobs$", |(uploads, blobs), req, res| {
+        match req.spool_body(uploads) {
+            Ok(upload) => {
+                let body = format!("{} {}", upload.len(), upload.sha256());
+                upload.persist_content_addressed(blobs).unwrap();
+                res.status(StatusCode::CREATED).body(body)
+            }
+            Err(e) => res.problem(&e.problem()),
+        };
+    });
+    let mut router = Router::new();
+    router.add("^/blobs$", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let digest = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+    for _ in 0..2 {
+        let res = client.post("/blobs").body("hello world").send();
+        assert_eq!(res.get_status(), StatusCode::CREATED);
+        assert_eq!(res.get_body(), format!("11 {}", digest).into_bytes());
+    }
+    assert_eq!(fs::read(root.join("blobs").join(digest)).unwrap(), b"hello world".to_vec());
+    assert_eq!(fs::read_dir(root.join("blobs")).unwrap().count(), 1);
+
+    let res = client.post("/blobs").body("a body larger than the limit").send();
+    assert_eq!(res.get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let res = client.post("/blobs").header("content-length", "1000").body("short").send();
+    assert_eq!(res.get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Uploads which fail or aren't persisted leave nothing behind
+    struct Failing(usize);
+    impl io::Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+            }
+            self.0 -= 1;
+            buf[0] = b'a';
+            Ok(1)
+        }
+    }
+    let uploads = TempUploads::new().dir(root.join("spool"));
+    let failed = uploads.receive(Failing(3)).unwrap_err();
+    assert_eq!(failed.status(), StatusCode::BAD_REQUEST);
+    let upload = uploads.receive(&b"discarded"[..]).unwrap();
+    assert!(upload.path().exists());
+    upload.discard();
+    assert_eq!(fs::read_dir(root.join("spool")).unwrap().count(), 0);
+
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn case_insensitive_middleware_rules() {
+    struct Deny;
+
+    impl Middleware for Deny {
+        fn resolve(&self, _req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+            res.status(StatusCode::FORBIDDEN);
+            RequestContinuation::None
+        }
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/admin/users$", |_, _, res| { res.status(StatusCode::OK).body("secrets"); });
+    controller.add(Method::GET, "^/public$", |_, _, res| { res.status(StatusCode::OK).body("hello"); });
+
+    let mut router = Router::new().case_insensitive();
+    router.add("^/", controller);
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(RewriteMiddleware::new().rewrite("^/backoffice$", "/Admin/Users"), vec!("^/"), None);
+    stack.apply(Deny, vec!("^/admin"), None);
+
+    let client = test::TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+    for path in &["/admin/users", "/ADMIN/users", "/Admin/Users", "/backoffice"] {
+        let res = client.get(path).send();
+        assert_eq!(res.get_status(), StatusCode::FORBIDDEN, "{} went through the middleware", path);
+    }
+    assert_eq!(client.get("/PUBLIC").send().get_status(), StatusCode::OK);
+}
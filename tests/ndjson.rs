@@ -0,0 +1,34 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+#[test]
+fn ndjson_roundtrip() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/double$", |_, req, res| {
+        let (values, errors): (Vec<_>, Vec<_>) = req.body_ndjson::<Vec<u32>>().partition(|value| value.is_ok());
+        let doubled: Vec<Vec<u32>> = values.into_iter()
+            .map(|value| value.unwrap().into_iter().map(|n| n * 2).collect())
+            .collect();
+        res.status(StatusCode::OK).header("x-invalid-lines", errors.len().to_string()).ndjson(doubled);
+    });
+    controller.add(Method::GET, "^/feed$", |_, _, res| {
+        res.status(StatusCode::OK).ndjson((0..10_000u32).map(|id| (id, format!("event {}", id))));
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.post("/double").body("[1, 2]\r\n\n  \n[3]\nnot json\n[]").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], NDJSON_CONTENT_TYPE);
+    assert_eq!(res.headers_map()["x-invalid-lines"], "1");
+    assert!(res.headers_map().get(header::CONTENT_LENGTH).is_none());
+    assert_eq!(res.get_body(), b"[2,4]\n[6]\n[]\n".to_vec());
+
+    let res = client.get("/feed").send();
+    let body = String::from_utf8(res.get_body().to_vec()).unwrap();
+    assert_eq!(body.lines().count(), 10_000);
+    assert_eq!(body.lines().last(), Some("[9999,\"event 9999\"]"));
+}
@@ -0,0 +1,160 @@
+extern crate base64;
+extern crate saphir;
+extern crate serde_json;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+fn query_param(url: &str, name: &str) -> String {
+    let query = url.split_once('?').unwrap().1;
+    query_pairs(query).into_iter().find(|(n, _)| n == name).map(|(_, v)| v).unwrap()
+}
+
+fn id_token(claims: &str) -> String {
+    format!("eyJhbGciOiJSUzI1NiJ9.{}.c2lnbmF0dXJl", base64::encode_config(claims, base64::URL_SAFE_NO_PAD))
+}
+
+/// Posts the exchange forms to the provider in process, standing for its token endpoint served over https
+struct InProcessExchanger(TestClient);
+
+impl TokenExchanger for InProcessExchanger {
+    fn exchange(&self, token_endpoint: &str, form: &[(&str, &str)]) -> Result<TokenResponse, OAuth2Error> {
+        assert_eq!(token_endpoint, "https://idp/token");
+        let body = form.iter().map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value))).collect::<Vec<_>>().join("&");
+        let res = self.0.post("/token").header(header::CONTENT_TYPE, "application/x-www-form-urlencoded").body(body).send();
+
+        if !res.get_status().is_success() {
+            return Err(OAuth2Error::Exchange(format!("the token endpoint answered {}", res.get_status())));
+        }
+        serde_json::from_slice(&res.get_body()).map_err(|e| OAuth2Error::Exchange(e.to_string()))
+    }
+}
+
+#[test]
+fn authorization_code_flow() {
+    // The nonce the provider embeds in the next ID token, and the forms posted to its token endpoint
+    let nonce = Arc::new(Mutex::new(String::new()));
+    let forms = Arc::new(Mutex::new(Vec::new()));
+
+    let mut provider = BasicController::new((nonce.clone(), forms.clone()));
+    provider.add(Method::POST, "^/token$", |(nonce, forms), req, res| {
+        let form: Vec<(String, String)> = query_pairs(&String::from_utf8_lossy(req.body()));
+        let code = form.iter().find(|(name, _)| name == "code").map(|(_, code)| code.clone()).unwrap_or_default();
+        forms.lock().unwrap().push(form);
+
+        if code != "valid-code" {
+            res.status(StatusCode::BAD_REQUEST).body(r#"{"error":"invalid_grant"}"#);
+            return;
+        }
+
+        let claims = format!(r#"{{"iss":"http://idp","sub":"alice","aud":"app","exp":4102444800,"nonce":"{}","email":"alice@example.com"}}"#,
+                             nonce.lock().unwrap());
+        res.status(StatusCode::OK).body(format!(r#"{{"access_token":"at","token_type":"Bearer","expires_in":3600,"id_token":"{}"}}"#,
+                                                id_token(&claims)));
+    });
+    let mut provider_router = Router::new();
+    provider_router.add("^/", provider);
+    let provider = TestClient::new(Server::builder().router(provider_router).build());
+
+    let config = OAuth2Config::new("app", "secret", "http://idp/authorize", "https://idp/token", "http://app/auth/callback")
+        .scope("email")
+        .issuer("http://idp");
+    let oauth2 = OAuth2Client::new(config).unwrap().token_exchanger(InProcessExchanger(provider));
+
+    let mut controller = BasicController::new(oauth2.clone());
+    controller.add_with_guards(Method::GET, "^/account", oauth2.guard().login_redirect("/auth/login").into(), |oauth2, req, res| {
+        let identity = oauth2.identity(req).unwrap();
+        res.status(StatusCode::OK).body(format!("{} {}", identity.subject.unwrap(), identity.claims["email"].as_str().unwrap()));
+    });
+    controller.add_with_guards(Method::POST, "^/account$", oauth2.guard().login_redirect("/auth/login").into(), |_, _, res| {
+        res.status(StatusCode::OK);
+    });
+
+    let mut router = Router::new();
+    router.add("^/auth", oauth2.controller());
+    router.add("^/account", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    // Anonymous users are sent to the login route, which sends them to the provider
+    let res = client.get("/account?tab=profile").send();
+    assert_eq!(res.get_status(), StatusCode::FOUND);
+    assert_eq!(res.headers_map()[header::LOCATION], "/auth/login?return_to=%2Faccount%3Ftab%3Dprofile");
+    assert_eq!(client.post("/account").send().get_status(), StatusCode::UNAUTHORIZED);
+
+    // The login returns the url of the provider, and the cookie binding its state to the browser
+    let login = |uri: &str| {
+        let res = client.get(uri).send();
+        let authorize = res.headers_map()[header::LOCATION].to_str().unwrap().to_string();
+        let cookie = res.headers_map()[header::SET_COOKIE].to_str().unwrap().split(';').next().unwrap().to_string();
+        (authorize, cookie)
+    };
+    let callback = |query: String, cookie: &str| client.get(&format!("/auth/callback?{}", query)).header(header::COOKIE, cookie).send();
+
+    let (authorize, login_cookie) = login("/auth/login?return_to=%2Faccount%3Ftab%3Dprofile");
+    assert_eq!(login_cookie, format!("saphir_session_login={}", query_param(&authorize, "state")));
+    assert!(authorize.starts_with("http://idp/authorize?response_type=code&client_id=app"));
+    assert_eq!(query_param(&authorize, "redirect_uri"), "http://app/auth/callback");
+    assert_eq!(query_param(&authorize, "scope"), "openid email");
+    let state = query_param(&authorize, "state");
+    *nonce.lock().unwrap() = query_param(&authorize, "nonce");
+
+    // Unknown states, states of another browser and refused codes don't open sessions
+    assert_eq!(callback("code=valid-code&state=forged".to_string(), "saphir_session_login=forged").get_status(), StatusCode::BAD_REQUEST);
+    assert_eq!(client.get(&format!("/auth/callback?code=valid-code&state={}", state)).send().get_status(), StatusCode::BAD_REQUEST);
+    let res = callback(format!("code=stolen-code&state={}", state), &login_cookie);
+    assert_eq!(res.get_status(), StatusCode::BAD_GATEWAY);
+    assert!(!res.headers_map().contains_key(header::SET_COOKIE));
+
+    // A state is only valid once
+    let res = callback(format!("code=valid-code&state={}", state), &login_cookie);
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+
+    let (authorize, login_cookie) = login("/auth/login?return_to=%2Faccount%3Ftab%3Dprofile");
+    *nonce.lock().unwrap() = query_param(&authorize, "nonce");
+    let res = callback(format!("code=valid-code&state={}", query_param(&authorize, "state")), &login_cookie);
+    assert_eq!(res.get_status(), StatusCode::FOUND);
+    assert_eq!(res.headers_map()[header::LOCATION], "/account?tab=profile");
+    let cookie = res.headers_map()[header::SET_COOKIE].to_str().unwrap().to_string();
+    assert!(cookie.contains("HttpOnly"));
+    let session = cookie.split(';').next().unwrap().to_string();
+    assert!(res.headers_map().get_all(header::SET_COOKIE).iter().any(|c| c.to_str().unwrap().starts_with("saphir_session_login=;")));
+
+    let form = forms.lock().unwrap().last().unwrap().clone();
+    assert!(form.contains(&("grant_type".to_string(), "authorization_code".to_string())));
+    assert!(form.contains(&("client_secret".to_string(), "secret".to_string())));
+
+    let res = client.get("/account").header(header::COOKIE, session.as_str()).send();
+    assert_eq!(res.get_body(), b"alice alice@example.com".to_vec());
+
+    // A login whose nonce doesn't match the ID token fails
+    let (authorize, login_cookie) = login("/auth/login");
+    let res = callback(format!("code=valid-code&state={}", query_param(&authorize, "state")), &login_cookie);
+    assert_eq!(res.get_status(), StatusCode::UNAUTHORIZED);
+
+    // Logins never return to other hosts
+    let (authorize, login_cookie) = login("/auth/login?return_to=%2F%2Fevil.example.com");
+    *nonce.lock().unwrap() = query_param(&authorize, "nonce");
+    let res = callback(format!("code=valid-code&state={}", query_param(&authorize, "state")), &login_cookie);
+    assert_eq!(res.headers_map()[header::LOCATION], "/");
+
+    let res = client.post("/auth/logout").header(header::COOKIE, session.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::SEE_OTHER);
+    assert!(res.headers_map()[header::SET_COOKIE].to_str().unwrap().contains("Max-Age=0"));
+    assert_eq!(client.get("/account").header(header::COOKIE, session.as_str()).send().get_status(), StatusCode::FOUND);
+}
+
+#[test]
+fn insecure_token_endpoints() {
+    let config = OAuth2Config::new("app", "secret", "https://idp/authorize", "http://idp/token", "https://app/auth/callback");
+    match OAuth2Client::new(config) {
+        Err(OAuth2Error::InsecureTokenEndpoint(endpoint)) => assert_eq!(endpoint, "http://idp/token"),
+        _ => panic!("the client secret would be sent in cleartext"),
+    }
+
+    match HttpTokenExchanger::new().exchange("http://idp/token", &[("client_secret", "secret")]) {
+        Err(OAuth2Error::InsecureTokenEndpoint(_)) => {}
+        _ => panic!("the form was posted in cleartext"),
+    }
+}
@@ -0,0 +1,74 @@
+extern crate saphir;
+#[macro_use]
+extern crate schemars;
+
+use saphir::*;
+
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+struct User {
+    name: String,
+}
+
+#[test]
+fn openapi_spec() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users$", |_, _, _| {});
+    controller.add(Method::PUT, "^/users/(?P<id>\\d+)$", |_, _, _| {});
+    controller.add(Method::GET, "^/users/([^/]+)/posts$", |_, _, _| {});
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+
+    let openapi = OpenApi::new("Users", "1.0.0")
+        .route(Method::PUT, "/users/{id}", RouteDoc::new()
+            .summary("Update a user")
+            .request_body::<User>()
+            .response::<User>(200, "The updated user"));
+
+    let spec = openapi.spec(&router);
+
+    assert_eq!(spec["openapi"], "3.0.3");
+    assert!(spec["paths"]["/users"]["get"].is_object());
+    assert_eq!(spec["paths"]["/users/{param1}/posts"]["get"]["parameters"][0]["name"], "param1");
+
+    let update = &spec["paths"]["/users/{id}"]["put"];
+    assert_eq!(update["summary"], "Update a user");
+    assert_eq!(update["parameters"][0]["schema"]["type"], "integer");
+    assert_eq!(update["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/User");
+    assert!(spec["components"]["schemas"]["User"].is_object());
+}
+
+#[test]
+fn route_metadata() {
+    let mut controller = BasicController::new(());
+    controller.add_documented(Method::GET, "^/users/(?P<id>\\d+)$", RouteMetadata::new()
+        .summary("Fetch a user")
+        .description("Fetch a user by its identifier")
+        .operation_id("users.show")
+        .tag("users")
+        .response(200, "The user")
+        .response(404, "No such user"), |_, _, _| {});
+    controller.add_documented(Method::DELETE, "^/users/(?P<id>\\d+)$", RouteMetadata::new().summary("Delete a user").tag("users"), |_, _, _| {});
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+
+    let spec = OpenApi::new("Users", "1.0.0")
+        .route(Method::DELETE, "/users/{id}", RouteDoc::new().summary("Remove a user").empty_response(204, "Removed"))
+        .spec(&router);
+
+    let show = &spec["paths"]["/users/{id}"]["get"];
+    assert_eq!(show["summary"], "Fetch a user");
+    assert_eq!(show["description"], "Fetch a user by its identifier");
+    assert_eq!(show["operationId"], "users.show");
+    assert_eq!(show["tags"][0], "users");
+    assert_eq!(show["responses"]["404"]["description"], "No such user");
+    assert!(show["responses"].get("default").is_none());
+
+    // The documentation given to the generator takes precedence over the metadata of the route
+    let delete = &spec["paths"]["/users/{id}"]["delete"];
+    assert_eq!(delete["summary"], "Remove a user");
+    assert_eq!(delete["tags"][0], "users");
+    assert_eq!(delete["responses"]["204"]["description"], "Removed");
+}
@@ -0,0 +1,36 @@
+extern crate saphir;
+
+use saphir::*;
+
+#[test]
+fn password_hashing() {
+    let argon2 = hash_password("correct horse battery staple", PasswordAlgorithm::Argon2id).unwrap();
+    assert!(argon2.starts_with("$argon2id$"));
+    assert_ne!(argon2, hash_password("correct horse battery staple", PasswordAlgorithm::Argon2id).unwrap());
+    assert!(verify_password("correct horse battery staple", &argon2).unwrap());
+    assert!(!verify_password("correct horse battery stapler", &argon2).unwrap());
+
+    let bcrypt = hash_password("correct horse battery staple", PasswordAlgorithm::Bcrypt(4)).unwrap();
+    assert!(bcrypt.starts_with("$2b$04$"));
+    assert!(verify_password("correct horse battery staple", &bcrypt).unwrap());
+    assert!(!verify_password("Tr0ub4dor&3", &bcrypt).unwrap());
+
+    assert!(verify_password("password", "plain text").is_err());
+    assert!(hash_password("password", PasswordAlgorithm::Bcrypt(40)).is_err());
+
+    assert!(!password_needs_rehash(&argon2, PasswordAlgorithm::Argon2id));
+    assert!(password_needs_rehash(&bcrypt, PasswordAlgorithm::Argon2id));
+    assert!(!password_needs_rehash(&bcrypt, PasswordAlgorithm::Bcrypt(4)));
+    assert!(password_needs_rehash(&bcrypt, PasswordAlgorithm::Bcrypt(DEFAULT_BCRYPT_COST)));
+    assert!(password_needs_rehash(&argon2, PasswordAlgorithm::Bcrypt(4)));
+}
+
+#[test]
+fn constant_time_comparison() {
+    assert!(constant_time_eq(b"", b""));
+    assert!(constant_time_eq(b"api-key", b"api-key"));
+    assert!(!constant_time_eq(b"api-key", b"api-kez"));
+    assert!(!constant_time_eq(b"api-key", b"api-key-longer"));
+    assert!(constant_time_str_eq("token", "token"));
+    assert!(!constant_time_str_eq("token", "Token"));
+}
@@ -0,0 +1,161 @@
+#[macro_use]
+extern crate serde_json;
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+struct BrokenEngine(Arc<AtomicBool>);
+
+impl TemplateEngine for BrokenEngine {
+    fn render(&self, name: &str, _context: &Value) -> Result<String, TemplateError> {
+        Err(TemplateError::new(format!("{} has a syntax error", name)))
+    }
+
+    fn hot_reload(&mut self, enabled: bool) {
+        self.0.store(enabled, Ordering::SeqCst);
+    }
+}
+
+fn profiled_client(profile: Option<Profile>, hot_reload: Arc<AtomicBool>, calls: Arc<AtomicUsize>) -> TestClient {
+    let mut controller = BasicController::new(calls);
+    controller.add(Method::GET, "^/panic$", |_, _, _| panic!("the handler is broken"));
+    controller.add(Method::GET, "^/template$", |_, _, res| { res.render("index.html", &json!({})); });
+    controller.add(Method::GET, "^/internal$", |_, _, res| {
+        let error = std::io::Error::new(std::io::ErrorKind::NotFound, "data.json is missing");
+        res.internal_error(&error);
+    });
+    controller.add(Method::GET, "^/counted$", |calls, req, res| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        res.status(StatusCode::OK).body(req.profile().map(|profile| profile.to_string()).unwrap_or_default());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(ResponseCacheMiddleware::new(MemoryCacheStore::new(16)), vec!("^/counted"), None);
+
+    let mut builder = Server::builder().router(router).middleware_stack(middlewares).template_engine(BrokenEngine(hot_reload));
+    if let Some(profile) = profile {
+        builder = builder.profile(profile);
+    }
+    TestClient::new(builder.build())
+}
+
+fn problem(res: &SyncResponse) -> Value {
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    serde_json::from_slice(&res.get_body()).unwrap()
+}
+
+#[test]
+fn development() {
+    let (hot_reload, calls) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+    let client = profiled_client(Some(Profile::Development), hot_reload.clone(), calls.clone());
+    assert!(hot_reload.load(Ordering::SeqCst));
+
+    let res = client.get("/panic").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = problem(&res);
+    assert_eq!(body["detail"], "the handler is broken");
+    assert!(!body["backtrace"].as_array().unwrap().is_empty());
+
+    let res = client.get("/template").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(problem(&res)["detail"].as_str().unwrap().contains("index.html has a syntax error"));
+
+    let res = client.get("/counted").send();
+    assert_eq!(res.get_body(), b"dev".to_vec());
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "no-store");
+    client.get("/counted").send();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn production() {
+    let (hot_reload, calls) = (Arc::new(AtomicBool::new(true)), Arc::new(AtomicUsize::new(0)));
+    let client = profiled_client(Some(Profile::Production), hot_reload.clone(), calls.clone());
+    assert!(!hot_reload.load(Ordering::SeqCst));
+
+    let res = client.get("/panic").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = problem(&res);
+    assert!(body.get("detail").is_none());
+    assert!(body.get("backtrace").is_none());
+
+    let res = client.get("/template").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(problem(&res).get("detail").is_none());
+
+    let res = client.get("/counted").send();
+    assert_eq!(res.get_body(), b"prod".to_vec());
+    assert!(res.headers_map().get(header::CACHE_CONTROL).is_none());
+    let res = client.get("/counted").send();
+    assert!(res.headers_map().contains_key(header::AGE));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn without_profile_and_from_env() {
+    let (hot_reload, calls) = (Arc::new(AtomicBool::new(true)), Arc::new(AtomicUsize::new(0)));
+    let client = profiled_client(None, hot_reload.clone(), calls.clone());
+    assert!(hot_reload.load(Ordering::SeqCst));
+
+    let res = client.get("/panic").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(problem(&res).get("detail").is_none());
+
+    client.get("/counted").send();
+    client.get("/counted").send();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    env::set_var(PROFILE_ENV_VAR, "Development");
+    let client = profiled_client(None, hot_reload, calls);
+    env::remove_var(PROFILE_ENV_VAR);
+    assert_eq!(client.get("/counted").send().get_body(), b"dev".to_vec());
+
+    assert_eq!("prod".parse::<Profile>(), Ok(Profile::Production));
+    assert!("staging".parse::<Profile>().is_err());
+}
+
+#[test]
+fn developer_error_pages() {
+    let (hot_reload, calls) = (Arc::new(AtomicBool::new(false)), Arc::new(AtomicUsize::new(0)));
+    let client = profiled_client(Some(Profile::Development), hot_reload.clone(), calls.clone());
+    let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+
+    let res = client.get("/panic?page=1").header(header::ACCEPT, html).header(header::AUTHORIZATION, "Bearer secret").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    let page = String::from_utf8(res.get_body()).unwrap();
+    assert!(page.contains("<h1>500 Internal Server Error</h1>"));
+    assert!(page.contains("The handler panicked: the handler is broken"));
+    assert!(page.contains("^/panic$"));
+    assert!(page.contains("/panic?page=1"));
+    assert!(page.contains("<h2>Backtrace</h2>"));
+    assert!(page.contains("[redacted]") && !page.contains("secret"));
+
+    let page = String::from_utf8(client.get("/template").header(header::ACCEPT, html).send().get_body()).unwrap();
+    assert!(page.contains("<li>Unable to render template index.html</li>"));
+    assert!(page.contains("<li>index.html has a syntax error</li>"));
+
+    let res = client.get("/internal").header(header::ACCEPT, html).send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let page = String::from_utf8(res.get_body()).unwrap();
+    assert!(page.contains("<li>data.json is missing</li>"));
+    assert!(!page.contains("<h2>Backtrace</h2>"));
+
+    // Clients preferring JSON keep the problem document
+    let res = client.get("/internal").header(header::ACCEPT, "application/json").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+
+    let client = profiled_client(Some(Profile::Production), hot_reload, calls);
+    let res = client.get("/internal").header(header::ACCEPT, html).send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    assert!(!String::from_utf8(res.get_body()).unwrap().contains("data.json"));
+}
@@ -0,0 +1,112 @@
+extern crate serde_json;
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use serde_json::Value;
+use std::thread::sleep;
+use std::time::Duration;
+
+struct Slow;
+
+impl Middleware for Slow {
+    fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        sleep(Duration::from_millis(10));
+        RequestContinuation::Next
+    }
+}
+
+struct Allow;
+
+impl RequestGuard for Allow {
+    fn validate(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+        RequestContinuation::Next
+    }
+}
+
+fn profile(res: &SyncResponse) -> Value {
+    assert_eq!(res.get_status(), StatusCode::OK);
+    serde_json::from_slice(&res.get_body()).unwrap()
+}
+
+#[test]
+fn phases_per_route() {
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::GET, "^/orders$", Allow.into(), |_, _, res| {
+        sleep(Duration::from_millis(20));
+        res.status(StatusCode::OK).json(&vec!["pen"; 1000]);
+    });
+
+    let mut router = Router::new();
+    router.add("^/orders", controller);
+
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(Slow, vec!("^/orders"), None);
+
+    let metrics = Metrics::new();
+    let profiler = Profiler::new().window(2).metrics(metrics.clone());
+    let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .middleware_stack(middlewares)
+        .profiling(profiler.clone())
+        .admin_endpoint("/_admin", AdminController::new(loopback).metrics(metrics.clone()).profiler(profiler.clone()))
+        .build());
+
+    for _ in 0..3 {
+        assert_eq!(client.get("/orders").send().get_status(), StatusCode::OK);
+    }
+    assert_eq!(client.get("/missing").send().get_status(), StatusCode::NOT_FOUND);
+
+    let report = profile(&client.get("/_admin/profile").send());
+    let routes = report.as_array().unwrap();
+    assert_eq!(routes.len(), 2);
+
+    // The slowest route comes first
+    let orders = &routes[0];
+    assert_eq!(orders["method"], "GET");
+    assert_eq!(orders["route"], "^/orders$");
+    assert_eq!(orders["total"]["count"], 3);
+    assert!(orders["total"]["p50_ms"].as_f64().unwrap() >= 30.0);
+
+    let phases: Vec<&str> = orders["phases"].as_array().unwrap().iter().map(|phase| phase["phase"].as_str().unwrap()).collect();
+    assert_eq!(phases, vec!["routing", "middleware:Slow", "guards", "handler", "serialization"]);
+    let handler = &orders["phases"][3];
+    assert_eq!(handler["count"], 3);
+    assert!(handler["p99_ms"].as_f64().unwrap() >= 20.0);
+    assert!(handler["max_ms"].as_f64().unwrap() >= handler["p50_ms"].as_f64().unwrap());
+    let slow = orders["phases"][1]["p50_ms"].as_f64().unwrap();
+    assert!((10.0..20.0).contains(&slow), "the middleware took {}ms", slow);
+
+    assert_eq!(routes[1]["route"], "unmatched");
+    assert_eq!(routes[1]["phases"][0]["phase"], "routing");
+
+    assert_eq!(profiler.report()[0].total.count, 3);
+    assert_eq!(metrics.histogram_count("http_request_phase_duration_seconds", &[("method", "GET"), ("route", "^/orders$"), ("phase", "handler")]).0, 3);
+
+    // The reset forgets the previous requests, only the request resetting the profile is recorded afterwards
+    profile(&client.delete("/_admin/profile").send());
+    let routes: Vec<String> = profiler.report().into_iter().map(|route| route.route).collect();
+    assert!(!routes.iter().any(|route| route == "^/orders$"));
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/orders$", |_, _, res| { res.status(StatusCode::OK).json(&vec!["pen"]); });
+
+    let mut router = Router::new();
+    router.add("^/orders", controller);
+
+    let profiler = Profiler::new();
+    let loopback = IpFilterGuard::new().allow("127.0.0.1/32").unwrap();
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .admin_endpoint("/_admin", AdminController::new(loopback).profiler(profiler.clone()))
+        .build());
+
+    assert_eq!(client.get("/orders").send().get_status(), StatusCode::OK);
+    assert!(profiler.report().is_empty());
+    assert_eq!(profile(&client.get("/_admin/profile").send()), Value::Array(Vec::new()));
+    assert!(!client.server().effective_config().profiling);
+}
@@ -0,0 +1,43 @@
+extern crate saphir;
+extern crate prost;
+
+use prost::Message;
+use saphir::*;
+use saphir::test::TestClient;
+
+#[derive(Clone, PartialEq, Message)]
+struct Ping {
+    #[prost(string, tag = "1")]
+    message: String,
+    #[prost(uint32, tag = "2")]
+    count: u32,
+}
+
+#[test]
+fn protobuf_messages() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/ping$", |_, req, res| {
+        match Protobuf::<Ping>::from_request_with_limit(req, 64) {
+            Ok(mut ping) => {
+                ping.count += 1;
+                res.status(StatusCode::OK).protobuf(&*ping);
+            }
+            Err(e) => { res.status(e.status()); }
+        }
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let ping = Ping { message: "hello".to_string(), count: 1 };
+    let res = client.post("/ping").header(header::CONTENT_TYPE, "application/x-protobuf").body(ping.encode_to_vec()).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/x-protobuf");
+    assert_eq!(Ping::decode(res.get_body().as_slice()).unwrap(), Ping { message: "hello".to_string(), count: 2 });
+
+    let send = |content_type: &str, body: Vec<u8>| client.post("/ping").header(header::CONTENT_TYPE, content_type).body(body).send().get_status();
+    assert_eq!(send("application/json", ping.encode_to_vec()), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(send("application/protobuf", vec![0x0a, 0xff]), StatusCode::BAD_REQUEST);
+    let large = Ping { message: "a".repeat(100), count: 1 };
+    assert_eq!(send("application/x-protobuf", large.encode_to_vec()), StatusCode::PAYLOAD_TOO_LARGE);
+}
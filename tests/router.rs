@@ -0,0 +1,959 @@
+extern crate futures;
+extern crate saphir;
+
+use saphir::*;
+
+#[test]
+fn route_introspection() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/$", |_, _, _| {});
+    controller.add_with_guards(Method::PUT, "^/(\\d+)$", BodyGuard.into(), |_, _, _| {});
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+    router.add("^/health", HealthController::new());
+
+    let routes = router.routes();
+    assert_eq!(routes.len(), 6);
+    assert_eq!(routes[0].controller_route, "^/users");
+    assert_eq!(routes[0].controller, "BasicController<()>");
+    assert_eq!(routes[0].method, Some(Method::GET));
+    assert_eq!(routes[1].pattern, Some("^/(\\d+)$".to_string()));
+    assert_eq!(routes[1].guards, vec!["BodyGuard".to_string()]);
+    assert_eq!(routes[2].controller, "HealthController");
+
+    let table = router.route_table();
+    assert_eq!(table.lines().count(), 7);
+    assert!(table.lines().nth(2).unwrap().starts_with("PUT     ^/users"));
+}
+
+#[test]
+fn route_conflicts() {
+    let mut controller = BasicController::new(()).conflict_policy(RouteConflictPolicy::Panic);
+    controller.add(Method::GET, "^/users/(\\d+)$", |_, _, _| {});
+    controller.add(Method::POST, "^/users/42$", |_, _, _| {});
+    controller.add(Method::GET, "^/users/me$", |_, _, _| {});
+
+    let shadowed = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        controller.add(Method::GET, "^/users/42$", |_, _, _| {});
+    }));
+    assert!(shadowed.is_err());
+
+    let mut router = Router::new().conflict_policy(RouteConflictPolicy::Panic);
+    router.add("^/api", BasicController::new(()));
+
+    let shadowed = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(move || {
+        router.add("^/api", BasicController::new(()));
+    }));
+    assert!(shadowed.is_err());
+}
+
+#[test]
+fn dispatch_order() {
+    let mut controller = BasicController::new(()).conflict_policy(RouteConflictPolicy::Ignore);
+    controller.add(Method::GET, "^/files/(.+)$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.add(Method::GET, "^/files/index$", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+    controller.add(Method::GET, "^/static", |_, _, res| { res.status(StatusCode::ACCEPTED); });
+    controller.add(Method::GET, "^/static/favicon.ico$", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+    controller.add(Method::GET, "^/about$", |_, _, res| { res.status(StatusCode::CREATED); });
+    controller.add(Method::GET, "^/ab", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res.get_status()
+    };
+
+    assert_eq!(dispatch("/files/index"), StatusCode::OK);
+    assert_eq!(dispatch("/static/favicon.ico"), StatusCode::ACCEPTED);
+    assert_eq!(dispatch("/about"), StatusCode::CREATED);
+    assert_eq!(dispatch("/abc"), StatusCode::NO_CONTENT);
+    assert_eq!(dispatch("/unknown"), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn concurrent_dispatch() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ControllerDispatch<AtomicUsize>>();
+    assert_send_sync::<BasicController<AtomicUsize>>();
+    assert_send_sync::<Router>();
+
+    let mut controller = BasicController::new(AtomicUsize::new(0));
+    controller.add(Method::GET, "^/count$", |count, _, res| {
+        count.fetch_add(1, Ordering::SeqCst);
+        res.status(StatusCode::OK);
+    });
+    controller.add(Method::GET, "^/total$", |count, _, res| {
+        res.status(StatusCode::OK).body(count.load(Ordering::SeqCst).to_string());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let router = Arc::new(router);
+
+    let dispatch = |router: &Router, uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    let threads: Vec<_> = (0..8).map(|_| {
+        let router = router.clone();
+        thread::spawn(move || {
+            for _ in 0..100 {
+                assert_eq!(dispatch(&router, "/count").get_status(), StatusCode::OK);
+            }
+        })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(dispatch(&router, "/total").get_body(), b"800".to_vec());
+}
+
+#[test]
+fn dynamic_routes() {
+    let features = DynamicRouter::new();
+    let mut router = Router::new();
+    router.add("^/beta", features.clone());
+
+    let dispatch = |uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res.get_status()
+    };
+
+    let controller = |status: StatusCode| {
+        let mut controller = BasicController::new(status);
+        controller.add(Method::GET, "^/beta/search$", |status, _, res| { res.status(*status); });
+        controller
+    };
+
+    assert_eq!(dispatch("/beta/search"), StatusCode::NOT_FOUND);
+
+    features.add("^/beta/search", controller(StatusCode::OK));
+    assert_eq!(dispatch("/beta/search"), StatusCode::OK);
+
+    features.replace("^/beta/search", controller(StatusCode::ACCEPTED));
+    assert_eq!(dispatch("/beta/search"), StatusCode::ACCEPTED);
+    assert_eq!(router.routes().len(), 1);
+
+    assert!(features.remove("^/beta/search"));
+    assert!(!features.remove("^/beta/search"));
+    assert_eq!(dispatch("/beta/search"), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn path_policies() {
+    let prepare = |router: &Router, method: Method, uri: &str| {
+        let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+        let mut req = SyncRequest::new(parts, Vec::new());
+        let mut res = SyncResponse::new();
+        let continuation = router.prepare(&mut req, &mut res);
+        let location = res.headers_map().get(header::LOCATION).map(|l| l.to_str().unwrap().to_string());
+
+        match continuation {
+            RequestContinuation::Next => (req.uri().to_string(), None),
+            RequestContinuation::None => (res.get_status().to_string(), location),
+        }
+    };
+
+    let router = Router::new().trailing_slash(TrailingSlashPolicy::Ignore).case_insensitive();
+    assert_eq!(prepare(&router, Method::GET, "/Users/?page=2"), ("/Users?page=2".to_string(), None));
+    assert_eq!(prepare(&router, Method::GET, "/"), ("/".to_string(), None));
+
+    let router = Router::new().trailing_slash(TrailingSlashPolicy::Redirect(TrailingSlash::Remove));
+    assert_eq!(prepare(&router, Method::GET, "/users/?page=2"), ("301 Moved Permanently".to_string(), Some("/users?page=2".to_string())));
+    assert_eq!(prepare(&router, Method::POST, "/users/"), ("308 Permanent Redirect".to_string(), Some("/users".to_string())));
+
+    let router = Router::new().trailing_slash(TrailingSlashPolicy::Redirect(TrailingSlash::Add));
+    assert_eq!(prepare(&router, Method::GET, "/docs"), ("301 Moved Permanently".to_string(), Some("/docs/".to_string())));
+    assert_eq!(prepare(&router, Method::GET, "/app.js"), ("/app.js".to_string(), None));
+}
+
+#[test]
+fn case_insensitive_routing() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/users/(\\w+)$", |_, req, res| { res.body(req.uri().path().to_string()); });
+
+    let mut router = Router::new().case_insensitive();
+    router.add("^/users", controller);
+    let client = test::TestClient::new(Server::builder().router(router).build());
+
+    // Routes match regardless of the case, while handlers see the path as it was received
+    let res = client.get("/USERS/MixedCase").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"/USERS/MixedCase".to_vec());
+}
+
+#[test]
+fn subdomain_routing() {
+    let mut tenants = BasicController::new(());
+    tenants.add(Method::GET, "^/dashboard$", |_, req, res| {
+        res.body(format!("{} of {}", req.uri().path(), req.host_param("tenant").unwrap()));
+    });
+    let mut api = BasicController::new(());
+    api.add(Method::GET, "^/dashboard$", |_, req, res| {
+        res.body(format!("api {} {}", req.host_param("version").unwrap(), req.host_param("tenant").is_none()));
+    });
+    let mut site = BasicController::new(());
+    site.add(Method::GET, "^/(dashboard|about)$", |_, req, res| { res.body(format!("site {}", req.uri().path())); });
+
+    let mut router = Router::new();
+    router.add_for_host("api-{version}.example.com", "^/", api);
+    router.add_for_host("{tenant}.example.com", "^/dashboard", tenants);
+    router.add("^/", site);
+    assert_eq!(router.routes().iter().map(|route| route.host.clone()).collect::<Vec<_>>(),
+               vec![Some("api-{version}.example.com".to_string()), Some("{tenant}.example.com".to_string()), None]);
+
+    let client = test::TestClient::new(Server::builder().router(router).build());
+    let get = |path: &str, host: &str| String::from_utf8(client.get(path).header(header::HOST, host).send().get_body()).unwrap();
+
+    assert_eq!(get("/dashboard", "Acme.Example.com:8080"), "/dashboard of acme");
+    assert_eq!(get("/dashboard", "api-v2.example.com"), "api v2 true");
+    // Paths the routes of the host don't handle fall back to the routes without a host
+    assert_eq!(get("/about", "acme.example.com"), "site /about");
+    assert_eq!(get("/dashboard", "a.b.example.com"), "site /dashboard");
+    assert_eq!(get("/dashboard", "example.com"), "site /dashboard");
+}
+
+#[test]
+fn path_normalization() {
+    assert_eq!(normalize_path("/a//b/./c/../%7Euser/"), Ok("/a/b/~user/".to_string()));
+    assert_eq!(normalize_path("/static/%2e%2e/%2E%2e/../etc/passwd"), Ok("/etc/passwd".to_string()));
+    assert_eq!(normalize_path("/files/a%2fb%c3%a9"), Ok("/files/a%2Fb%C3%A9".to_string()));
+    assert_eq!(normalize_path("/.."), Ok("/".to_string()));
+    assert_eq!(normalize_path("/static/%00.png"), Err(PathError::NulByte));
+    assert_eq!(normalize_path("/static/%c0%ae%c0%ae/passwd"), Err(PathError::InvalidEncoding));
+    assert_eq!(normalize_path("/static/%zz"), Err(PathError::InvalidEncoding));
+
+    let router = Router::new();
+    let (parts, _) = Request::builder().uri("/static/%c0%ae%c0%ae/passwd").body(()).unwrap().into_parts();
+    let mut res = SyncResponse::new();
+    router.prepare(&mut SyncRequest::new(parts, Vec::new()), &mut res);
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn route_policies() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    let mut controller = BasicController::new(AtomicUsize::new(0));
+    controller.add_with_policy(Method::GET, "^/flaky$", RoutePolicy::new().retries(2), |attempts, _, res| {
+        let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+        res.header("x-attempt", attempt.to_string());
+        res.status(if attempt % 3 == 2 { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE });
+    });
+    controller.add_with_policy(Method::POST, "^/flaky$", RoutePolicy::new().retries(2), |_, _, res| {
+        res.status(StatusCode::BAD_GATEWAY);
+    });
+    controller.add_with_policy(Method::GET, "^/slow$", RoutePolicy::new().timeout(Duration::from_millis(50)).retries(3), |_, _, res| {
+        thread::sleep(Duration::from_millis(80));
+        res.status(StatusCode::OK);
+    });
+    let fallback = RoutePolicy::new().fallback(|_, _, res| { res.status(StatusCode::OK).body("cached"); });
+    controller.add_with_policy(Method::GET, "^/broken$", fallback, |_, _, _| panic!("the delegate is broken"));
+    controller.add_with_policy(Method::GET, "^/panic$", RoutePolicy::new(), |_, _, _| panic!("the delegate is broken"));
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |method: Method, uri: &str| {
+        let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    let res = dispatch(Method::GET, "/flaky");
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get_all("x-attempt").iter().count(), 1);
+    assert_eq!(res.headers_map()["x-attempt"], "2");
+
+    assert_eq!(dispatch(Method::POST, "/flaky").get_status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(dispatch(Method::GET, "/slow").get_status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(dispatch(Method::GET, "/broken").get_body(), b"cached".to_vec());
+    assert_eq!(dispatch(Method::GET, "/panic").get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn route_concurrency() {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut controller = BasicController::new(());
+    let policy = RoutePolicy::new().max_concurrency(1).queue(1, Duration::from_secs(5));
+    controller.add_with_policy(Method::GET, "^/report$", policy, |_, _, res| {
+        thread::sleep(Duration::from_millis(300));
+        res.status(StatusCode::OK);
+    });
+    controller.add(Method::GET, "^/health$", |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let router = Arc::new(router);
+
+    let dispatch = |router: &Router, uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    let requests = (0..2).map(|_| {
+        let router = router.clone();
+        let request = thread::spawn(move || dispatch(&router, "/report").get_status());
+        thread::sleep(Duration::from_millis(50));
+        request
+    }).collect::<Vec<_>>();
+
+    // The first request holds the slot and the second waits for it, leaving no room for a third
+    let res = dispatch(&router, "/report");
+    assert_eq!(res.get_status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers_map()[header::RETRY_AFTER], "1");
+    assert_eq!(dispatch(&router, "/health").get_status(), StatusCode::OK);
+
+    for request in requests {
+        assert_eq!(request.join().unwrap(), StatusCode::OK);
+    }
+    assert_eq!(dispatch(&router, "/report").get_status(), StatusCode::OK);
+}
+
+#[test]
+fn request_predicates() {
+    let mut controller = BasicController::new(());
+    controller.add_when(Method::POST, "^/users$", RequestPredicate::content_type("application/json"), |_, _, res| {
+        res.status(StatusCode::CREATED).body("json");
+    });
+    controller.add_when(Method::POST, "^/users$", RequestPredicate::content_type("text/csv"), |_, _, res| {
+        res.status(StatusCode::CREATED).body("csv");
+    });
+    controller.add_when(Method::GET, "^/users$", RequestPredicate::query_value("format", "csv"), |_, _, res| {
+        res.status(StatusCode::OK).body("csv export");
+    });
+    controller.add(Method::GET, "^/users$", |_, _, res| { res.status(StatusCode::OK).body("users"); });
+
+    let mut router = Router::new();
+    router.add("^/users$", controller);
+    assert_eq!(router.routes()[0].predicate, Some("content type application/json".to_string()));
+    assert!(router.route_table().contains("^/users$ when query format=csv"));
+
+    let dispatch = |method: Method, uri: &str, content_type: &str| {
+        let (parts, _) = Request::builder().method(method).uri(uri).header("content-type", content_type).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        (res.get_status(), String::from_utf8(res.get_body()).unwrap())
+    };
+
+    assert_eq!(dispatch(Method::POST, "/users", "application/json; charset=utf-8"), (StatusCode::CREATED, "json".to_string()));
+    assert_eq!(dispatch(Method::POST, "/users", "text/csv"), (StatusCode::CREATED, "csv".to_string()));
+    assert_eq!(dispatch(Method::POST, "/users", "application/xml").0, StatusCode::BAD_REQUEST);
+    assert_eq!(dispatch(Method::GET, "/users?format=csv", ""), (StatusCode::OK, "csv export".to_string()));
+    assert_eq!(dispatch(Method::GET, "/users?format=json", ""), (StatusCode::OK, "users".to_string()));
+}
+
+#[test]
+fn media_type_variants() {
+    let mut controller = BasicController::new(());
+    controller.add_producing(Method::GET, "^/report$", "application/json", |_, _, res| {
+        res.status(StatusCode::OK).body("{\"total\":3}");
+    });
+    controller.add_producing(Method::GET, "^/report$", "text/csv", |_, _, res| {
+        res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/csv; charset=utf-8").body("total\n3\n");
+    });
+
+    let mut router = Router::new();
+    router.add("^/report$", controller);
+    assert!(router.route_table().contains("^/report$ producing text/csv"));
+
+    let dispatch = |accept: &str| {
+        let (parts, _) = Request::builder().uri("/report").header("accept", accept).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    let res = dispatch("text/csv;q=0.9, application/json;q=0.5");
+    assert_eq!(res.get_body(), b"total\n3\n".to_vec());
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert_eq!(res.headers_map()[header::VARY], "Accept");
+
+    let res = dispatch("application/*");
+    assert_eq!(res.get_body(), b"{\"total\":3}".to_vec());
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(dispatch("").headers_map()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(dispatch("image/png").get_status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[test]
+fn shared_controller_state() {
+    use std::thread;
+
+    #[derive(Default)]
+    struct Counters {
+        visits: u64,
+    }
+
+    let state = State::new(Counters::default());
+    let mut controller = BasicController::new(state.clone());
+    controller.add(Method::POST, "^/visit$", |state, _, res| {
+        let visits = state.update(|counters| {
+            counters.visits += 1;
+            counters.visits
+        });
+        res.status(StatusCode::OK).body(visits.to_string());
+    });
+    controller.add(Method::GET, "^/visits$", |state, _, res| {
+        res.status(StatusCode::OK).body(state.read().visits.to_string());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |method: Method, uri: &str| {
+        let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    let threads: Vec<_> = (0..4).map(|_| {
+        let state = state.clone();
+        thread::spawn(move || for _ in 0..25 { state.write().visits += 1; })
+    }).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(dispatch(Method::POST, "/visit").get_body(), b"101".to_vec());
+    assert_eq!(dispatch(Method::GET, "/visits").get_body(), b"101".to_vec());
+    assert_eq!(state.replace(Counters::default()).visits, 101);
+    assert_eq!(state.with(|counters| counters.visits), 0);
+}
+
+#[test]
+fn scoped_context_values() {
+    struct Pool {
+        connections: State<Vec<usize>>,
+        committed: State<Vec<usize>>,
+    }
+
+    struct Checkout;
+
+    impl ContextFactory<Pool> for Checkout {
+        type Scoped = usize;
+
+        fn create(&self, pool: &Pool, _req: &SyncRequest, res: &mut SyncResponse) -> Option<usize> {
+            let connection = pool.connections.write().pop();
+            if connection.is_none() {
+                res.status(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            connection
+        }
+
+        fn release(&self, pool: &Pool, connection: usize, _req: &SyncRequest, res: &mut SyncResponse) {
+            if res.get_status().is_success() {
+                pool.committed.write().push(connection);
+            }
+            pool.connections.write().push(connection);
+        }
+    }
+
+    let pool = Pool {
+        connections: State::new(vec![1]),
+        committed: State::new(Vec::new()),
+    };
+    let (connections, committed) = (pool.connections.clone(), pool.committed.clone());
+
+    let mut controller = BasicController::new(pool);
+    controller.add_scoped(Method::GET, "^/ok$", Checkout, |pool, connection, _, res| {
+        assert!(pool.connections.read().is_empty());
+        res.status(StatusCode::OK).body(connection.to_string());
+    });
+    controller.add_scoped(Method::GET, "^/fail$", Checkout, |_, _, _, res| { res.status(StatusCode::CONFLICT); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    assert_eq!(dispatch("/ok").get_body(), b"1".to_vec());
+    assert_eq!(dispatch("/fail").get_status(), StatusCode::CONFLICT);
+    assert_eq!(connections.get(), vec![1]);
+    assert_eq!(committed.get(), vec![1]);
+
+    connections.write().clear();
+    assert_eq!(dispatch("/ok").get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(committed.get(), vec![1]);
+}
+
+#[test]
+fn delegate_priorities() {
+    let mut controller = BasicController::new(()).conflict_policy(RouteConflictPolicy::Panic);
+    controller.add(Method::GET, "^/files/(.+)$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.add_with_priority(Method::GET, "^/files/index$", 10, |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+    controller.add(Method::GET, "^/static", |_, _, res| { res.status(StatusCode::ACCEPTED); });
+    controller.add_with_guards_and_priority(Method::GET, "^/static/private$", BodyGuard.into(), 5, |_, _, res| { res.status(StatusCode::CREATED); });
+    controller.add_with_priority(Method::GET, "^/(about|contact)$", -1, |_, _, res| { res.status(StatusCode::ALREADY_REPORTED); });
+    controller.add(Method::GET, "^/about$", |_, _, res| { res.status(StatusCode::IM_USED); });
+
+    let routes: Vec<_> = controller.routes().into_iter().map(|route| route.pattern.unwrap()).collect();
+    assert_eq!(routes, vec!["^/files/index$", "^/static/private$", "^/files/(.+)$", "^/static", "^/about$", "^/(about|contact)$"]);
+
+    let shadowed = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        controller.add_with_priority(Method::GET, "^/files/index$", 10, |_, _, _| {});
+    }));
+    assert!(shadowed.is_err());
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, b"body".to_vec()), &mut res);
+        res.get_status()
+    };
+
+    assert_eq!(dispatch("/files/index"), StatusCode::NO_CONTENT);
+    assert_eq!(dispatch("/files/readme"), StatusCode::OK);
+    assert_eq!(dispatch("/static/private"), StatusCode::CREATED);
+    assert_eq!(dispatch("/static/public"), StatusCode::ACCEPTED);
+    assert_eq!(dispatch("/about"), StatusCode::IM_USED);
+    assert_eq!(dispatch("/contact"), StatusCode::ALREADY_REPORTED);
+}
+
+#[test]
+fn controller_fallback() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/docs/index$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.add_with_guards(Method::POST, "^/docs/search$", BodyGuard.into(), |_, _, res| { res.status(StatusCode::OK); });
+    controller.fallback(|_, req, res| {
+        res.status(StatusCode::NOT_FOUND).body(format!("No page at {} {}", req.method(), req.uri().path()));
+    });
+
+    let routes = controller.routes();
+    assert_eq!(routes.len(), 3);
+    assert_eq!((routes[2].method.clone(), routes[2].pattern.clone()), (None, None));
+
+    let mut router = Router::new();
+    router.add("^/docs", controller);
+
+    let dispatch = |method: Method, uri: &str| {
+        let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    assert_eq!(dispatch(Method::GET, "/docs/index").get_status(), StatusCode::OK);
+    assert_eq!(dispatch(Method::GET, "/docs/missing").get_body(), b"No page at GET /docs/missing".to_vec());
+    assert_eq!(dispatch(Method::DELETE, "/docs/index").get_body(), b"No page at DELETE /docs/index".to_vec());
+    assert!(dispatch(Method::POST, "/docs/search").get_body().is_empty());
+}
+
+#[test]
+fn controller_merging() {
+    let mut users = BasicController::new(1).conflict_policy(RouteConflictPolicy::Panic);
+    users.add(Method::GET, "^/users$", |ctx, _, res| { res.status(StatusCode::OK).body(ctx.to_string()); });
+    users.add(Method::GET, "^/users/(.+)$", |_, _, res| { res.status(StatusCode::NOT_FOUND); });
+
+    let mut groups = BasicController::new(2);
+    groups.add(Method::GET, "^/groups$", |ctx, _, res| { res.status(StatusCode::OK).body(ctx.to_string()); });
+    groups.add_with_priority(Method::GET, "^/groups/admins$", 1, |_, _, res| { res.status(StatusCode::FORBIDDEN); });
+    groups.fallback(|_, _, res| { res.status(StatusCode::NOT_FOUND).body("fallback"); });
+
+    users.merge(groups);
+    let routes: Vec<_> = users.routes().into_iter().map(|route| route.pattern).collect();
+    assert_eq!(routes, vec![Some("^/groups/admins$".to_string()), Some("^/users$".to_string()), Some("^/users/(.+)$".to_string()),
+                            Some("^/groups$".to_string()), None]);
+
+    let mut shadowed = BasicController::new(3);
+    shadowed.add(Method::GET, "^/users$", |_, _, _| {});
+    let conflict = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| users.merge(shadowed)));
+    assert!(conflict.is_err());
+
+    let mut users = BasicController::new(1);
+    users.add(Method::GET, "^/users$", |ctx, _, res| { res.status(StatusCode::OK).body(ctx.to_string()); });
+    let mut groups = BasicController::new(2);
+    groups.add(Method::GET, "^/groups$", |ctx, _, res| { res.status(StatusCode::OK).body(ctx.to_string()); });
+    groups.fallback(|_, _, res| { res.status(StatusCode::NOT_FOUND).body("fallback"); });
+    users.merge(groups);
+
+    let mut router = Router::new();
+    router.add("^/", users);
+
+    let dispatch = |uri: &str| {
+        let (parts, _) = Request::builder().uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res.get_body()
+    };
+
+    assert_eq!(dispatch("/users"), b"1".to_vec());
+    assert_eq!(dispatch("/groups"), b"1".to_vec());
+    assert_eq!(dispatch("/roles"), b"fallback".to_vec());
+}
+
+#[test]
+fn async_guards() {
+    use futures::Future;
+    use futures::future;
+    use futures::sync::oneshot;
+    use std::thread;
+    use std::time::Duration;
+
+    struct TokenGuard;
+
+    impl AsyncRequestGuard for TokenGuard {
+        fn validate(&self, req: &SyncRequest, _res: &mut SyncResponse) -> GuardFuture {
+            let token = req.headers_map().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+            let (tx, rx) = oneshot::channel();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                let _ = tx.send(token.is_some_and(|token| token == "Bearer valid"));
+            });
+
+            Box::new(rx.then(|active| match active {
+                Ok(true) => future::ok(()),
+                _ => future::err(Problem::new(StatusCode::UNAUTHORIZED).with_detail("The token is not active").into()),
+            }))
+        }
+    }
+
+    let mut guards = RequestGuardCollection::new();
+    guards.add(AwaitGuard(SyncGuard(BodyGuard)));
+    guards.add(AwaitGuard(TokenGuard));
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::POST, "^/account$", guards, |_, _, res| { res.status(StatusCode::OK); });
+
+    let routes = controller.routes();
+    assert_eq!(routes[0].guards, vec!["BodyGuard".to_string(), "TokenGuard".to_string()]);
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |token: &str, body: &[u8]| {
+        let (parts, _) = Request::builder().method(Method::POST).uri("/account").header(header::AUTHORIZATION, token)
+            .body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        res.status(StatusCode::BAD_REQUEST);
+        router.dispatch(&SyncRequest::new(parts, body.to_vec()), &mut res);
+        res
+    };
+
+    assert_eq!(dispatch("Bearer valid", b"{}").get_status(), StatusCode::OK);
+    assert_eq!(dispatch("Bearer valid", b"").get_status(), StatusCode::BAD_REQUEST);
+    let res = dispatch("Bearer expired", b"{}");
+    assert_eq!(res.get_status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/problem+json");
+}
+
+#[test]
+fn cached_guards() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    struct TokenGuard(Arc<AtomicUsize>);
+
+    impl RequestGuard for TokenGuard {
+        fn validate(&self, req: &SyncRequest, res: &mut SyncResponse) -> RequestContinuation {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match req.headers_map().get(header::AUTHORIZATION) {
+                Some(token) if token == "Bearer valid" => RequestContinuation::Next,
+                _ => {
+                    res.status(StatusCode::UNAUTHORIZED).header(header::WWW_AUTHENTICATE, "Bearer").body("inactive token");
+                    RequestContinuation::None
+                }
+            }
+        }
+
+        fn cache_key(&self, req: &SyncRequest) -> Option<String> {
+            req.headers_map().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+        }
+    }
+
+    let validations = Arc::new(AtomicUsize::new(0));
+    let guard = CachedGuard::new(TokenGuard(validations.clone()), Duration::from_millis(100));
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::GET, "^/account$", guard.into(), |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |token: Option<&str>| {
+        let mut builder = Request::builder();
+        builder.uri("/account");
+        if let Some(token) = token {
+            builder.header(header::AUTHORIZATION, token);
+        }
+        let (parts, _) = builder.body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, Vec::new()), &mut res);
+        res
+    };
+
+    for _ in 0..3 {
+        assert_eq!(dispatch(Some("Bearer valid")).get_status(), StatusCode::OK);
+        let res = dispatch(Some("Bearer expired"));
+        assert_eq!(res.get_status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers_map()[header::WWW_AUTHENTICATE], "Bearer");
+        assert_eq!(res.get_body(), b"inactive token".to_vec());
+    }
+    assert_eq!(validations.load(Ordering::SeqCst), 2);
+
+    dispatch(None);
+    dispatch(None);
+    assert_eq!(validations.load(Ordering::SeqCst), 4);
+
+    thread::sleep(Duration::from_millis(150));
+    assert_eq!(dispatch(Some("Bearer valid")).get_status(), StatusCode::OK);
+    assert_eq!(validations.load(Ordering::SeqCst), 5);
+}
+
+#[test]
+fn canary_split() {
+    use saphir::test::TestClient;
+
+    let controller = |body: &'static str| {
+        let mut controller = BasicController::new(body);
+        controller.add(Method::GET, "^/search$", |body, _, res| { res.status(StatusCode::OK).body(*body); });
+        controller
+    };
+
+    let split = SplitController::new(controller("stable"), controller("canary"))
+        .header("X-Canary", "always")
+        .cookie("beta", "1")
+        .sticky_cookie("session");
+    let mut router = Router::new();
+    router.add("^/search", split.clone());
+    assert_eq!(router.routes().len(), 2);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let from = |ip: u8| client.get("/search").peer_addr(([192, 0, 2, ip], 4000).into());
+    let body = |res: SyncResponse| String::from_utf8(res.get_body()).unwrap();
+
+    assert_eq!(split.get_percentage(), 0);
+    assert!((0..50).all(|ip| body(from(ip).send()) == "stable"));
+    assert_eq!(body(from(1).header("x-canary", "always").send()), "canary");
+    assert_eq!(body(from(1).header("x-canary", "never").send()), "stable");
+    assert_eq!(body(from(1).header(header::COOKIE, "theme=dark; beta=1").send()), "canary");
+
+    split.set_percentage(30);
+    let canaries: Vec<u8> = (0..200).filter(|ip| body(from(*ip).send()) == "canary").collect();
+    assert!(canaries.len() > 20 && canaries.len() < 100, "{} canaries", canaries.len());
+    assert!(canaries.iter().all(|ip| body(from(*ip).send()) == "canary"));
+
+    let session = |id: u32| from(1).header(header::COOKIE, format!("session={}", id)).send();
+    let sessions: Vec<u32> = (0..200).filter(|id| body(session(*id)) == "canary").collect();
+    assert!(sessions.len() > 20 && sessions.len() < 100, "{} canaries", sessions.len());
+    assert!(sessions.iter().all(|id| body(session(*id)) == "canary"));
+
+    let res = from(1).header("x-canary", "always").send();
+    assert_eq!(res.get_extensions().get::<SplitVariant>(), Some(&SplitVariant::Canary));
+
+    split.set_percentage(200);
+    assert_eq!(split.get_percentage(), 100);
+    assert!((0..50).all(|ip| body(from(ip).send()) == "canary"));
+}
+
+#[test]
+fn feature_flags() {
+    use saphir::test::TestClient;
+
+    struct BetaTesters;
+
+    impl FeatureFlagProvider for BetaTesters {
+        fn is_enabled(&self, flag: &str, req: &SyncRequest) -> bool {
+            flag == "new_checkout" && req.cookie("user") == Some("tester")
+        }
+    }
+
+    let controller = |body: &'static str| {
+        let mut controller = BasicController::new(body);
+        controller.add(Method::GET, "^/checkout$", |body, _, res| { res.status(StatusCode::OK).body(*body); });
+        controller
+    };
+
+    let mut guards = RequestGuardCollection::new();
+    guards.add(FeatureFlagGuard::new("wishlist"));
+    let mut wishlist = BasicController::new(());
+    wishlist.add_with_guards(Method::GET, "^/wishlist$", guards, |_, req, res| {
+        res.status(StatusCode::OK).body(req.feature("new_checkout").to_string());
+    });
+
+    let flags = StaticFeatureFlags::new().enable("new-checkout");
+    assert!(flags.get("NEW_CHECKOUT"));
+    assert!(!flags.get("wishlist"));
+
+    let mut router = Router::new();
+    router.add("^/checkout", SplitController::new(controller("old"), controller("new")).feature("new_checkout"));
+    router.add("^/wishlist", wishlist);
+    let client = TestClient::new(Server::builder().router(router).feature_flags(flags.clone()).build());
+    let body = |res: SyncResponse| String::from_utf8(res.get_body()).unwrap();
+
+    assert_eq!(body(client.get("/checkout").send()), "new");
+    assert_eq!(client.get("/wishlist").send().get_status(), StatusCode::NOT_FOUND);
+
+    flags.set("wishlist", true);
+    flags.set("new_checkout", false);
+    assert_eq!(body(client.get("/checkout").send()), "old");
+    assert_eq!(body(client.get("/wishlist").send()), "false");
+
+    let mut router = Router::new();
+    router.add("^/checkout", SplitController::new(controller("old"), controller("new")).feature("new_checkout"));
+    let client = TestClient::new(Server::builder().router(router).feature_flags(BetaTesters).build());
+    assert_eq!(body(client.get("/checkout").header(header::COOKIE, "user=tester").send()), "new");
+    assert_eq!(body(client.get("/checkout").header(header::COOKIE, "user=someone").send()), "old");
+
+    std::env::set_var("SAPHIR_FEATURE_DARK_MODE", "On");
+    std::env::set_var("SAPHIR_FEATURE_LEGACY_SEARCH", "0");
+    let flags = StaticFeatureFlags::from_env();
+    assert!(flags.get("dark_mode"));
+    assert!(!flags.get("legacy_search"));
+}
+
+#[test]
+fn api_versioning() {
+    let mut users_v1 = BasicController::new(());
+    users_v1.add(Method::GET, "^/v1/users$", |_, req, res| { res.status(StatusCode::OK).body(format!("v1 users {}", req.api_version().unwrap())); });
+    let mut users_v2 = BasicController::new(());
+    users_v2.add(Method::GET, "^/v2/users$", |_, req, res| { res.status(StatusCode::OK).body(format!("v2 users {}", req.api_version().unwrap())); });
+    let mut health = BasicController::new(());
+    health.add(Method::GET, "^/v\\d+/health$", |_, req, res| { res.status(StatusCode::OK).body(format!("healthy {}", req.uri())); });
+
+    let mut router = Router::new();
+    router.add_versioned(&["1"], "^/users", users_v1);
+    router.add_versioned(&["2"], "^/users", users_v2);
+    router.add_versioned(&["1", "2"], "^/health", health);
+    assert_eq!(router.routes().iter().map(|route| route.controller_route.as_str()).collect::<Vec<_>>(),
+               vec!["^/v(?:1)/users", "^/v(?:2)/users", "^/v(?:1|2)/health"]);
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(ApiVersioning::new(&["1", "2"]).default_version("2"), vec!("/"), None);
+    let client = test::TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+    let get = |path: &str, accept: &str| {
+        let res = client.get(path).header(header::ACCEPT, accept).send();
+        (res.get_status(), String::from_utf8(res.get_body()).unwrap(), res.headers_map().contains_key(header::VARY))
+    };
+
+    assert_eq!(get("/v1/users", "*/*"), (StatusCode::OK, "v1 users 1".to_string(), false));
+    assert_eq!(get("/users", "application/json; version=1"), (StatusCode::OK, "v1 users 1".to_string(), true));
+    assert_eq!(get("/users", "application/json; version=\"v1\"; q=0.5, application/json; version=3"), (StatusCode::OK, "v1 users 1".to_string(), true));
+    assert_eq!(get("/users", "application/json"), (StatusCode::OK, "v2 users 2".to_string(), true));
+    assert_eq!(get("/health?deep=true", "*/*"), (StatusCode::OK, "healthy /v2/health?deep=true".to_string(), true));
+    assert_eq!(get("/users", "application/json; version=3").0, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(get("/v3/users", "*/*").0, StatusCode::NOT_FOUND);
+
+    let mut router = Router::new();
+    router.add_versioned(&["1"], "^/users", BasicController::new(()));
+    let mut stack = MiddlewareStack::new();
+    stack.apply(ApiVersioning::new(&["1"]).default_version("1").redirect_unversioned(), vec!("/"), None);
+    let client = test::TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let res = client.get("/users?page=2").send();
+    assert_eq!(res.get_status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(res.headers_map()[header::LOCATION], "/v1/users?page=2");
+}
+
+#[test]
+fn route_aliases() {
+    let mut controller = BasicController::new(()).conflict_policy(RouteConflictPolicy::Panic);
+    controller.add_aliases_with_guards(Method::POST, &["^/about$", "^/a-propos$", "^/uber-uns$"], BodyGuard.into(), |_, _, res| {
+        let alias = res.route_alias().map(|alias| (alias.index(), alias.pattern().to_string())).unwrap();
+        res.status(StatusCode::OK).body(format!("{} {}", alias.0, alias.1));
+    });
+    controller.add(Method::GET, "^/about$", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+    controller.label(Method::POST, "^/about$", "pages.about");
+    assert_eq!(controller.routes().len(), 4);
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let dispatch = |method: Method, uri: &str, body: &[u8]| {
+        let (parts, _) = Request::builder().method(method).uri(uri).body(()).unwrap().into_parts();
+        let mut res = SyncResponse::new();
+        router.dispatch(&SyncRequest::new(parts, body.to_vec()), &mut res);
+        res
+    };
+
+    let res = dispatch(Method::POST, "/a-propos", b"body");
+    assert_eq!(res.get_body(), b"1 ^/a-propos$".to_vec());
+    assert_eq!(res.route_label(), Some("pages.about"));
+    assert_eq!(dispatch(Method::POST, "/uber-uns", b"body").route_label(), Some("pages.about"));
+    assert_eq!(dispatch(Method::POST, "/about", b"body").get_body(), b"0 ^/about$".to_vec());
+    // The guards apply to every alias
+    assert!(dispatch(Method::POST, "/uber-uns", b"").get_body().is_empty());
+
+    let res = dispatch(Method::GET, "/about", b"");
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    assert!(res.route_alias().is_none());
+}
+
+#[test]
+fn sitemap() {
+    let mut pages = BasicController::new(());
+    pages.add(Method::GET, "^/$", |_, _, res| { res.status(StatusCode::OK); });
+    pages.add(Method::POST, "^/contact$", |_, _, res| { res.status(StatusCode::OK); });
+    pages.add_aliases(Method::GET, &["^/about$", "^/a-propos$"], |_, _, res| { res.status(StatusCode::OK); });
+    pages.sitemap(Method::GET, "^/about$", SitemapEntry::new().change_frequency(ChangeFrequency::Yearly).priority(0.8));
+    pages.add(Method::GET, "^/drafts$", |_, _, res| { res.status(StatusCode::OK); });
+    pages.sitemap(Method::GET, "^/drafts$", SitemapEntry::excluded());
+
+    let mut products = BasicController::new(());
+    products.add(Method::GET, "^/products/(?P<slug>[a-z-]+)$", |_, _, res| { res.status(StatusCode::OK); });
+    products.label(Method::GET, "^/products/(?P<slug>[a-z-]+)$", "products.show");
+    products.sitemap(Method::GET, "^/products/(?P<slug>[a-z-]+)$", SitemapEntry::new().path("/products/saphir?color=blue&size=m"));
+    products.add(Method::GET, "^/products/([0-9]+)/edit$", |_, _, res| { res.status(StatusCode::OK); });
+    products.sitemap(Method::GET, "^/products/([0-9]+)/edit$", SitemapEntry::disallowed());
+
+    let mut router = Router::new();
+    router.add("^/products", products);
+    router.add("^/", pages);
+
+    assert_eq!(router.url_for("products.show", &["blue shoes"]).unwrap(), "/products/blue%20shoes");
+    assert!(router.url_for("products.show", &[]).is_none());
+    assert!(router.url_for("products.index", &[]).is_none());
+
+    let sitemap = Sitemap::new("https://example.com/");
+    let xml = sitemap.xml(&router);
+    let locs: Vec<&str> = xml.lines().filter_map(|line| line.trim().strip_prefix("<loc>")).collect();
+    assert_eq!(locs, vec!["https://example.com/products/saphir?color=blue&amp;size=m</loc>", "https://example.com/</loc>",
+                          "https://example.com/about</loc>", "https://example.com/a-propos</loc>"]);
+    assert!(xml.contains("<loc>https://example.com/about</loc>\n    <changefreq>yearly</changefreq>\n    <priority>0.8</priority>"));
+
+    let robots = sitemap.disallow("/private/").robots(&router);
+    assert_eq!(robots, "User-agent: *\nDisallow: /private/\nDisallow: /products/\n\nSitemap: https://example.com/sitemap.xml\n");
+
+    let controller = Sitemap::new("https://example.com").annotated_only().controller(&router);
+    let mut router = Router::new();
+    router.add("^/(robots\\.txt|sitemap\\.xml)$", controller);
+    let client = test::TestClient::new(Server::builder().router(router).build());
+    let res = client.get("/sitemap.xml").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/xml; charset=utf-8");
+    assert_eq!(String::from_utf8(res.get_body()).unwrap().matches("<url>").count(), 2);
+    assert!(String::from_utf8(client.get("/robots.txt").send().get_body()).unwrap().starts_with("User-agent: *\n"));
+}
@@ -0,0 +1,63 @@
+extern crate saphir;
+extern crate futures;
+
+use futures::Future;
+use saphir::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn cron_expressions() {
+    // Sunday the 1st of January 2023 at midnight
+    let new_year = 1672531200;
+    let next = |expression: &str, after: u64| expression.parse::<CronSchedule>().unwrap().next_after(after);
+
+    assert_eq!(next("*/15 * * * *", new_year), Some(new_year + 15 * 60));
+    assert_eq!(next("30 7 * * 1-5", new_year), Some(new_year + 86400 + 7 * 3600 + 30 * 60));
+    assert_eq!(next("0 0 * * 7", new_year), Some(new_year + 7 * 86400));
+    assert_eq!(next("0 12 15 * 1", new_year), Some(new_year + 86400 + 12 * 3600));
+    assert_eq!(next("@monthly", new_year), Some(1675209600));
+    assert_eq!(next("0 0 30 2 *", new_year), None);
+
+    assert_eq!("* * * *".parse::<CronSchedule>(), Err(CronError::FieldCount(4)));
+    assert_eq!("60 * * * *".parse::<CronSchedule>(), Err(CronError::InvalidField("60".to_string())));
+    assert_eq!("5-1 * * * *".parse::<CronSchedule>(), Err(CronError::InvalidField("5-1".to_string())));
+}
+
+#[test]
+fn scheduled_jobs() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counted = runs.clone();
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add("count", Schedule::Every(Duration::from_millis(10)), move || -> Result<(), String> {
+        counted.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    });
+    scheduler.add("fail", Schedule::Every(Duration::from_millis(10)), || -> Result<(), String> { Err("unreachable".to_string()) });
+
+    let handle = scheduler.handle();
+    let server = Server::builder().build();
+    scheduler.spawn_on(&server);
+    let server = server.spawn_test().unwrap();
+
+    while handle.status("fail").unwrap().runs == 0 || runs.load(Ordering::SeqCst) == 0 {
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(handle.status("fail").unwrap().last_error, Some("unreachable".to_string()));
+    assert!(handle.check().wait().is_err());
+
+    assert!(handle.disable("fail"));
+    assert!(!handle.disable("missing"));
+    assert!(handle.check().wait().is_ok());
+
+    assert!(handle.disable("count"));
+    thread::sleep(Duration::from_millis(30));
+    let disabled_runs = runs.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(runs.load(Ordering::SeqCst), disabled_runs);
+
+    server.shutdown().unwrap();
+}
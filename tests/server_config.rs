@@ -0,0 +1,173 @@
+extern crate saphir;
+extern crate serde_json;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::env;
+use std::fs;
+
+#[test]
+fn toml_and_yaml() {
+    let toml = ServerConfig::from_toml(r#"
+        listen = "http://127.0.0.1:9000"
+        worker_threads = 4
+        trusted_proxies = ["10.0.0.0/8"]
+
+        [default_headers]
+        server = "saphir"
+
+        [limits]
+        max_body_size = 16
+
+        [[limits.content_types]]
+        content_type = "application/json"
+        max_body_size = 64
+
+        [[limits.routes]]
+        path = "^/upload$"
+        max_body_size = 256
+        body_timeout_ms = 30000
+
+        [log]
+        format = "key_value"
+    "#).unwrap();
+
+    let yaml = ServerConfig::from_yaml(r#"
+        listen: http://127.0.0.1:9000
+        worker_threads: 4
+        trusted_proxies: ["10.0.0.0/8"]
+        default_headers:
+          server: saphir
+        limits:
+          max_body_size: 16
+          content_types:
+            - content_type: application/json
+              max_body_size: 64
+          routes:
+            - path: ^/upload$
+              max_body_size: 256
+              body_timeout_ms: 30000
+        log:
+          format: key_value
+    "#).unwrap();
+
+    assert_eq!(toml, yaml);
+    assert_eq!(toml.listen, "http://127.0.0.1:9000");
+    assert_eq!(toml.worker_threads, Some(4));
+    assert_eq!(toml.limits.routes[0].body_timeout_ms, Some(30000));
+    assert_eq!(toml.log.format, Some(LogFormat::KeyValue));
+    assert!(toml.problem_details);
+    assert_eq!(toml.handler_threads, None);
+
+    assert!(ServerConfig::from_toml("listn = \"http://0.0.0.0:80\"").is_err());
+    assert!(ServerConfig::from_yaml("worker_threads: many").is_err());
+}
+
+#[test]
+fn from_file_and_env() {
+    let path = env::temp_dir().join(format!("saphir-config-{}.toml", std::process::id()));
+    fs::write(&path, "listen = \"http://127.0.0.1:9000\"\n[log]\nlevel = \"info\"\n").unwrap();
+    let config = ServerConfig::from_file(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(config.log.level.as_deref(), Some("info"));
+    assert!(matches!(ServerConfig::from_file("server.ini"), Err(ConfigError::Io(_))));
+
+    env::set_var("SAPHIR_TEST_WORKER_THREADS", "8");
+    env::set_var("SAPHIR_TEST_THREAD_NAME", "2024");
+    env::set_var("SAPHIR_TEST_LOG__LEVEL", "debug");
+    env::set_var("SAPHIR_TEST_LIMITS__MAX_BODY_SIZE", "1024");
+    env::set_var("SAPHIR_TEST_TRUSTED_PROXIES", r#"["10.0.0.0/8", "192.168.0.0/16"]"#);
+    let config = config.with_env("SAPHIR_TEST").unwrap();
+    assert_eq!(config.listen, "http://127.0.0.1:9000");
+    assert_eq!(config.worker_threads, Some(8));
+    assert_eq!(config.thread_name.as_deref(), Some("2024"));
+    assert_eq!(config.log.level.as_deref(), Some("debug"));
+    assert_eq!(config.limits.max_body_size, Some(1024));
+    assert_eq!(config.trusted_proxies.len(), 2);
+
+    env::set_var("SAPHIR_INVALID_WORKER_THREADS", "many");
+    assert!(ServerConfig::default().with_env("SAPHIR_INVALID").is_err());
+}
+
+#[test]
+fn server_from_config() {
+    let config = ServerConfig::from_json(r#"{
+        "default_headers": {"x-api-version": "2"},
+        "limits": {"max_body_size": 8, "routes": [{"path": "^/upload$", "max_body_size": 64}]}
+    }"#).unwrap();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/(echo|upload)$", |_, req, res| {
+        res.status(StatusCode::OK).body(req.body().clone());
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let client = TestClient::new(Server::from_config(&config).unwrap().router(router).build());
+
+    let res = client.post("/echo").body("12345678").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()["x-api-version"], "2");
+    assert_eq!(client.post("/echo").body("123456789").send().get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(client.post("/upload").body("123456789").send().get_status(), StatusCode::OK);
+
+    let invalid = ServerConfig::from_json(r#"{"trusted_proxies": ["not a network"]}"#).unwrap();
+    assert!(matches!(Server::from_config(&invalid), Err(ConfigError::Invalid(_))));
+    let invalid = ServerConfig::from_json(r#"{"limits": {"routes": [{"max_body_size": 1}]}}"#).unwrap();
+    assert!(matches!(Server::from_config(&invalid), Err(ConfigError::Invalid(_))));
+    let invalid = ServerConfig::from_json(r#"{"log": {"level": "loud"}}"#).unwrap();
+    assert!(matches!(Server::from_config(&invalid), Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn effective_config() {
+    struct Audit;
+
+    impl Middleware for Audit {
+        fn resolve(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> RequestContinuation {
+            RequestContinuation::Next
+        }
+    }
+
+    let mut stack = MiddlewareStack::new();
+    stack.apply(Audit, vec!["^/api"], Some(vec!["^/api/health"]));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/api/users$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.add(Method::POST, "^/api/users$", |_, _, res| { res.status(StatusCode::CREATED); });
+    let mut router = Router::new();
+    router.add("^/api", controller);
+
+    let config = ServerConfig::from_json(r#"{
+        "worker_threads": 3,
+        "handler_threads": 2,
+        "trusted_proxies": ["10.0.0.0/8"],
+        "default_headers": {"server": "saphir"},
+        "limits": {"max_body_size": 8, "routes": [{"path": "^/upload$", "max_body_size": 64, "buffered": false}]}
+    }"#).unwrap();
+    let server = Server::from_config(&config).unwrap().router(router).middleware_stack(stack).startup_banner().build();
+
+    let effective = server.effective_config();
+    assert!(effective.listeners.is_empty());
+    assert_eq!(effective.worker_threads, 3);
+    assert_eq!(effective.handler_threads, Some(2));
+    assert_eq!(effective.trusted_proxies, vec!["10.0.0.0/8".to_string()]);
+    assert_eq!(effective.default_headers["server"], "saphir");
+    assert_eq!(effective.limits.max_body_size, Some(8));
+    assert_eq!(effective.limits.routes[0].path.as_deref(), Some("^/upload$"));
+    assert_eq!(effective.limits.routes[0].buffered, Some(false));
+    assert_eq!(effective.middlewares.len(), 1);
+    assert_eq!(effective.middlewares[0].include_path, vec!["^/api".to_string()]);
+    assert_eq!(effective.middlewares[0].exclude_path, vec!["^/api/health".to_string()]);
+    assert_eq!(effective.route_count, 2);
+
+    let json = serde_json::to_value(&effective).unwrap();
+    assert_eq!(json["worker_threads"], 3);
+    assert_eq!(json["middlewares"][0]["exclude_path"][0], "^/api/health");
+
+    let summary = effective.to_string();
+    assert!(summary.contains("threads: 3 workers, 2 handlers"), "{}", summary);
+    assert!(summary.contains("8 bytes within unlimited time by default, 64 bytes within unlimited time for ^/upload$"), "{}", summary);
+    assert!(summary.contains("1. ") && summary.contains("on ^/api except ^/api/health"), "{}", summary);
+    assert!(summary.ends_with("routes: 2"), "{}", summary);
+}
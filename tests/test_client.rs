@@ -0,0 +1,2195 @@
+#[macro_use]
+extern crate serde_json;
+extern crate saphir;
+extern crate log;
+extern crate futures;
+extern crate hyper;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+#[test]
+fn in_process_dispatch() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        res.status(StatusCode::CREATED).body(req.body().clone());
+    });
+    controller.add(Method::GET, "^/peer$", |_, req, res| {
+        res.body(req.peer_addr().map(|addr| addr.to_string()).unwrap_or_default());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(SecurityHeadersMiddleware::new(), vec!("/"), None);
+
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(middlewares).build());
+
+    let res = client.post("/echo").json(&json!({"name": "saphir"})).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(res.get_body(), br#"{"name":"saphir"}"#.to_vec());
+    assert_eq!(res.headers_map().get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+
+    let res = client.get("/peer").peer_addr(([10, 0, 0, 1], 4000).into()).send();
+    assert_eq!(res.get_body(), b"10.0.0.1:4000".to_vec());
+
+    assert_eq!(client.delete("/echo").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(client.post("/api/../echo").body("normalized").send().get_body(), b"normalized".to_vec());
+}
+
+#[test]
+fn raw_requests() {
+    use std::net::SocketAddr;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/items/(?P<id>[0-9]+)$", |_, req, res| {
+        let tenant = req.extensions().get::<&str>().cloned().unwrap_or("none");
+        res.status(StatusCode::OK).body(format!("{} {}", tenant, req.peer_addr().map(|addr| addr.to_string()).unwrap_or_default()));
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build();
+
+    let peer: SocketAddr = "10.0.0.7:4242".parse().unwrap();
+    let mut extensions = Extensions::new();
+    extensions.insert("acme");
+    let mut headers = header::HeaderMap::new();
+    headers.insert(header::ACCEPT, header::HeaderValue::from_static("text/plain"));
+    let mut req = SyncRequest::from_parts(Method::GET, "/items/12".parse().unwrap(), headers, Vec::new(), Some(peer), extensions);
+    assert_eq!(req.version(), Version::HTTP_11);
+    let res = server.process(&mut req);
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"acme 10.0.0.7:4242");
+
+    // Arbitrary bytes are either rejected or dispatched without panicking
+    let methods: &[&[u8]] = &[b"GET", b"POST", b"G ET", b"", b"\xff"];
+    let uris: &[&[u8]] = &[b"/items/12", b"/items/x", b"*", b"/\xc3\x28", b"", b"http://h/items/3?q=1"];
+    let headers: &[&[(&[u8], &[u8])]] = &[&[], &[(b"accept", b"*/*"), (b"accept", b"text/plain")], &[(b"bad name", b"v")], &[(b"x", b"\n")]];
+    let mut dispatched = 0;
+    for method in methods {
+        for uri in uris {
+            for headers in headers {
+                match SyncRequest::from_raw_parts(method, uri, headers, b"\x00\x01", None) {
+                    Ok(mut req) => {
+                        assert_eq!(req.peer_addr(), None);
+                        assert_eq!(req.body(), b"\x00\x01");
+                        server.process(&mut req);
+                        dispatched += 1;
+                    }
+                    Err(e) => assert!(!e.to_string().is_empty()),
+                }
+            }
+        }
+    }
+    assert_eq!(dispatched, 2 * 4 * 2);
+
+    let req = SyncRequest::from_raw_parts(b"GET", b"/items/3", &[(b"accept", b"*/*"), (b"accept", b"text/plain")], b"", Some(peer)).unwrap();
+    assert_eq!(req.headers_map().get_all(header::ACCEPT).iter().count(), 2);
+    assert_eq!(req.peer_addr(), Some(peer));
+}
+
+#[test]
+fn ephemeral_server() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/ping$", |_, _, res| { res.body("pong"); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+    assert!(server.url().starts_with("http://127.0.0.1:"));
+
+    // Both requests are answered on the same keep-alive connection
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    for _ in 0..2 {
+        stream.write_all(b"GET /ping HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut buffer = [0; 512];
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..read]).ends_with("\r\n\r\npong"));
+    }
+
+    drop(stream);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn lifecycle_hooks() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/ping$", |_, _, res| { res.body("pong"); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    // Opened and closed connections, then received requests and sent responses
+    let counters: Arc<Vec<AtomicUsize>> = Arc::new((0..4).map(|_| AtomicUsize::new(0)).collect());
+    let count = |index: usize| {
+        let counters = counters.clone();
+        move || { counters[index].fetch_add(1, Ordering::SeqCst); }
+    };
+    let (opened, closed, requested, responded) = (count(0), count(1), count(2), count(3));
+
+    let server = Server::builder()
+        .router(router)
+        .on_connection_open(move |_| opened())
+        .on_connection_close(move |_| closed())
+        .on_request(move |_| requested())
+        .on_response(move |_, res| if res.get_status() == StatusCode::OK { responded() })
+        .build()
+        .spawn_test()
+        .unwrap();
+
+    // Two requests on a single keep-alive connection
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    for _ in 0..2 {
+        stream.write_all(b"GET /ping HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut buffer = [0; 512];
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..read]).ends_with("\r\n\r\npong"));
+    }
+
+    drop(stream);
+    server.shutdown().unwrap();
+
+    let counts: Vec<usize> = counters.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+    assert_eq!(counts, vec![1, 1, 2, 2]);
+}
+
+#[test]
+fn startup_and_shutdown_hooks() {
+    use futures::future::{lazy, Future};
+    use std::sync::{Arc, Mutex};
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let hook = |events: &Arc<Mutex<Vec<&'static str>>>, name: &'static str| {
+        let events = events.clone();
+        move || -> Result<(), String> { events.lock().unwrap().push(name); Ok(()) }
+    };
+    let connect = events.clone();
+
+    let server = Server::builder()
+        .router(Router::new())
+        .on_startup("cache", &["database"], hook(&events, "warm cache"))
+        .on_startup("database", &[], move || {
+            let connect = connect.clone();
+            lazy(move || { connect.lock().unwrap().push("connect database"); Ok(()) }).map(|_| ())
+        })
+        .on_shutdown("database", &["cache"], hook(&events, "disconnect database"))
+        .on_shutdown("cache", &[], hook(&events, "flush cache"))
+        .build()
+        .spawn_test()
+        .unwrap();
+
+    assert_eq!(*events.lock().unwrap(), vec!["connect database", "warm cache"]);
+    server.shutdown().unwrap();
+    assert_eq!(*events.lock().unwrap(), vec!["connect database", "warm cache", "flush cache", "disconnect database"]);
+
+    let failing = Server::builder()
+        .on_startup("database", &[], || -> Result<(), String> { Err("connection refused".to_string()) })
+        .on_startup("cache", &["database"], hook(&events, "unreachable"))
+        .build()
+        .spawn_test();
+    match failing {
+        Err(ServerError::StartupFailed(e)) => assert_eq!(e, "the startup hook database failed: connection refused"),
+        _ => panic!("the server started"),
+    }
+
+    let cyclic = Server::builder()
+        .on_startup("a", &["b"], hook(&events, "a"))
+        .on_startup("b", &["a"], hook(&events, "b"))
+        .build()
+        .spawn_test();
+    match cyclic {
+        Err(ServerError::StartupFailed(e)) => assert_eq!(e, "the startup hooks a, b run after each other"),
+        _ => panic!("the server started"),
+    }
+    assert_eq!(events.lock().unwrap().len(), 4);
+}
+
+#[test]
+fn warm_up() {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::{mpsc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let readiness = |addr: SocketAddr| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /readyz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let server = |tasks: Vec<(&'static str, &'static [&'static str], Result<(), String>)>, gate: mpsc::Receiver<()>| {
+        let mut health = HealthController::new();
+        health.add_readiness_check("database", || -> Result<(), String> { Ok(()) });
+        let mut router = Router::new();
+        router.add("^/(healthz|readyz)$", health);
+
+        let gate = Mutex::new(gate);
+        let mut builder = Server::builder().router(router)
+            .warm_up("gate", &[], move || -> Result<(), String> { gate.lock().unwrap().recv().map_err(|e| e.to_string()) });
+        for (name, after, result) in tasks {
+            builder = builder.warm_up(name, after, move || result.clone());
+        }
+        builder.build().spawn_test().unwrap()
+    };
+
+    let (open, gate) = mpsc::channel();
+    let warming = server(vec![("templates", &[], Ok(())), ("cache", &["gate", "templates"], Ok(()))], gate);
+    let response = readiness(warming.addr());
+    assert!(response.starts_with("HTTP/1.1 503"));
+    assert!(response.contains(r#""warm_up.gate":{"status":"error","message":"warming up"}"#));
+
+    open.send(()).unwrap();
+    let started = Instant::now();
+    while !readiness(warming.addr()).starts_with("HTTP/1.1 200") {
+        assert!(started.elapsed() < Duration::from_secs(5), "the server didn't warm up");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let (open, gate) = mpsc::channel();
+    open.send(()).unwrap();
+    let failing = server(vec![("upstream", &[], Err("connection refused".to_string())), ("cache", &["upstream"], Ok(()))], gate);
+    let started = Instant::now();
+    let mut response = readiness(failing.addr());
+    while !response.contains("connection refused") || response.contains("warming up") {
+        assert!(started.elapsed() < Duration::from_secs(5), "the warm-up didn't fail");
+        thread::sleep(Duration::from_millis(10));
+        response = readiness(failing.addr());
+    }
+    assert!(response.starts_with("HTTP/1.1 503"));
+    assert!(response.contains(r#""warm_up.cache":{"status":"error","message":"skipped since upstream failed"}"#));
+}
+
+#[test]
+fn record_and_replay() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let path = ::std::env::temp_dir().join(format!("saphir-recording-{}.jsonl", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let build = |calls: Arc<AtomicUsize>, recorder: Option<RecordingMiddleware>| {
+        let mut controller = BasicController::new(calls);
+        controller.add(Method::POST, "^/count$", |calls, req, res| {
+            let count = calls.fetch_add(1, Ordering::SeqCst);
+            res.body(format!("{}:{}", String::from_utf8_lossy(req.body()), count));
+        });
+
+        let mut router = Router::new();
+        router.add("^/", controller);
+
+        let mut middlewares = MiddlewareStack::new();
+        if let Some(recorder) = recorder {
+            middlewares.apply(recorder, vec!("/"), None);
+        }
+
+        TestClient::new(Server::builder().router(router).middleware_stack(middlewares).build())
+    };
+
+    let recorder = RecordingMiddleware::new(&path).unwrap()
+        .redact(|exchange| exchange.request.headers.retain(|(name, _)| name != "x-trace"));
+    let client = build(calls.clone(), Some(recorder));
+    client.post("/count").header("authorization", "Bearer secret").header("x-trace", "1").body("a").send();
+    client.post("/count").body("b").send();
+
+    let exchanges = load_recording(&path).unwrap();
+    let _ = ::std::fs::remove_file(&path);
+    assert_eq!(exchanges.len(), 2);
+    assert_eq!(exchanges[0].request.headers, vec![("authorization".to_string(), REDACTED.to_string())]);
+    assert_eq!(exchanges[1].response.body, RecordedBody::Text("b:1".to_string()));
+
+    assert!(replay(&build(Arc::new(AtomicUsize::new(0)), None), &exchanges).is_empty());
+
+    let mismatches = replay(&build(Arc::new(AtomicUsize::new(1)), None), &exchanges);
+    assert_eq!(mismatches.len(), 2);
+    assert_eq!(mismatches[0].body, RecordedBody::Text("a:1".to_string()));
+}
+
+#[test]
+fn structured_logging() {
+    use std::sync::Mutex;
+
+    struct Capture(Mutex<Vec<(String, String)>>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target().starts_with("saphir::")
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push((record.target().to_string(), record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: Capture = Capture(Mutex::new(Vec::new()));
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+
+    let mut guards = RequestGuardCollection::new();
+    guards.add(BodyGuard);
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::POST, "^/users$", guards, |_, _, res| { res.status(StatusCode::CREATED); });
+
+    let mut router = Router::new();
+    router.add("^/users", controller);
+
+    let client = TestClient::new(Server::builder().router(router).log_format(LogFormat::KeyValue).build());
+    client.post("/users").send();
+
+    let records = LOGGER.0.lock().unwrap();
+    assert!(records.contains(&(ROUTING_LOG_TARGET.to_string(),
+                               r#"msg="delegate matched" method=POST path=/users route=^/users$"#.to_string())));
+    assert!(records.contains(&(GUARD_LOG_TARGET.to_string(),
+                               r#"msg="guard rejected" guard=BodyGuard method=POST path=/users status=200"#.to_string())));
+}
+
+#[test]
+fn background_tasks() {
+    use futures::Stream;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+
+    let (sender, queue) = task_queue::<String>();
+    let (processed_tx, processed_rx) = mpsc::channel();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/jobs$", |_, req, res| {
+        let job = String::from_utf8_lossy(req.body()).into_owned();
+        match req.state::<TaskSender<String>>().unwrap().send(job) {
+            Ok(()) => res.status(StatusCode::ACCEPTED),
+            Err(_) => res.status(StatusCode::SERVICE_UNAVAILABLE),
+        };
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).state(sender.clone()).build();
+    server.spawn_task(move || queue.for_each(move |job| {
+        processed_tx.send(job).unwrap();
+        Ok(())
+    }));
+    // A task which never completes on its own, cancelled by the shutdown
+    server.spawn_task(futures::future::empty::<(), ()>);
+
+    let server = server.spawn_test().unwrap();
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"POST /jobs HTTP/1.1\r\nHost: test\r\nContent-Length: 7\r\n\r\nreindex").unwrap();
+    let mut buffer = [0; 512];
+    let read = stream.read(&mut buffer).unwrap();
+    assert!(String::from_utf8_lossy(&buffer[..read]).starts_with("HTTP/1.1 202"));
+    assert_eq!(processed_rx.recv().unwrap(), "reindex");
+
+    drop(stream);
+    server.shutdown().unwrap();
+    assert_eq!(sender.send("late".to_string()), Err("late".to_string()));
+}
+
+#[test]
+fn hyper_service() {
+    use futures::{Future, Stream};
+    use hyper::service::Service;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        let peer = req.peer_addr().unwrap();
+        res.body(format!("{} from {}", String::from_utf8_lossy(req.body()), peer));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build();
+    let mut service = server.service(([10, 0, 0, 2], 5000).into());
+
+    let request = Request::post("/echo").body(Body::from("hello")).unwrap();
+    let response = service.call(request).wait().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().concat2().wait().unwrap();
+    assert_eq!(&body[..], b"hello from 10.0.0.2:5000");
+}
+
+#[test]
+fn fastcgi_listener() {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    fn record(kind: u8, content: &[u8]) -> Vec<u8> {
+        let mut record = vec![1, kind, 0, 1, (content.len() >> 8) as u8, content.len() as u8, 0, 0];
+        record.extend_from_slice(content);
+        record
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        let peer = req.peer_addr().unwrap();
+        res.header("x-peer", peer.to_string()).body(format!("{}?{}", String::from_utf8_lossy(req.body()), req.uri().query().unwrap_or("")));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || server.serve_fastcgi(listener));
+
+    let mut params = Vec::new();
+    for &(name, value) in &[("REQUEST_METHOD", "POST"), ("REQUEST_URI", "/echo?a=1"), ("REMOTE_ADDR", "192.0.2.1"),
+                            ("REMOTE_PORT", "4242"), ("CONTENT_LENGTH", "5"), ("HTTP_X_TRACE", "1")] {
+        params.push(name.len() as u8);
+        params.push(value.len() as u8);
+        params.extend_from_slice(name.as_bytes());
+        params.extend_from_slice(value.as_bytes());
+    }
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(&record(1, &[0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+    stream.write_all(&record(4, &params)).unwrap();
+    stream.write_all(&record(4, &[])).unwrap();
+    stream.write_all(&record(5, b"hello")).unwrap();
+    stream.write_all(&record(5, &[])).unwrap();
+
+    // Without FCGI_KEEP_CONN the connection is closed once the request is answered
+    let mut output = Vec::new();
+    stream.read_to_end(&mut output).unwrap();
+
+    let mut stdout = Vec::new();
+    let mut index = 0;
+    let mut end_request = None;
+    while index + 8 <= output.len() {
+        let length = ((output[index + 4] as usize) << 8) | output[index + 5] as usize;
+        let content = &output[index + 8..index + 8 + length];
+        match output[index + 1] {
+            6 => stdout.extend_from_slice(content),
+            3 => end_request = Some(content.to_vec()),
+            _ => {}
+        }
+        index += 8 + length + output[index + 6] as usize;
+    }
+
+    let stdout = String::from_utf8(stdout).unwrap();
+    assert!(stdout.starts_with("Status: 200 OK\r\n"));
+    assert!(stdout.contains("x-peer: 192.0.2.1:4242\r\n"));
+    assert!(stdout.ends_with("\r\n\r\nhello?a=1"));
+    assert_eq!(end_request, Some(vec![0; 8]));
+}
+
+#[test]
+fn problem_details() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/api/teapot$", |_, _, res| { res.status(StatusCode::IM_A_TEAPOT); });
+    controller.add(Method::GET, "^/api/custom$", |_, _, res| { res.status(StatusCode::CONFLICT).body("taken"); });
+
+    let mut router = Router::new();
+    router.add("^/api", controller);
+
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/missing").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers_map().get(header::CONTENT_TYPE).unwrap(), PROBLEM_CONTENT_TYPE);
+    let problem: Problem = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem, Problem::new(StatusCode::NOT_FOUND).with_instance("/missing"));
+
+    let res = client.delete("/api/teapot").send();
+    let problem: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem, json!({ "type": "about:blank", "title": "Method Not Allowed", "status": 405, "instance": "/api/teapot" }));
+
+    assert_eq!(client.get("/api/teapot").send().get_status(), StatusCode::IM_A_TEAPOT);
+    assert_eq!(client.get("/api/custom").send().get_body(), b"taken".to_vec());
+
+    let mut router = Router::new();
+    router.add("^/api", BasicController::new(()));
+    let client = TestClient::new(Server::builder().router(router).problem_details(false).build());
+    assert!(client.get("/missing").send().get_body().is_empty());
+}
+
+#[test]
+fn default_headers() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/api/items$", |_, _, res| { res.status(StatusCode::OK).body("[]"); });
+    controller.add(Method::GET, "^/api/feed$", |_, _, res| {
+        res.status(StatusCode::OK).header(header::CACHE_CONTROL, "max-age=60");
+    });
+
+    let mut router = Router::new();
+    router.add("^/api", controller);
+
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .default_header(header::SERVER, "saphir")
+        .default_header(header::CACHE_CONTROL, "no-store")
+        .default_header(header::VARY, "accept")
+        .default_header(header::VARY, "accept-language")
+        .default_header("x-api-version", "invalid\nvalue")
+        .before_send(|req, res| { res.header("x-request-path", req.uri().path()); })
+        .build());
+
+    let res = client.get("/api/items").send();
+    assert_eq!(res.headers_map()[header::SERVER], "saphir");
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "no-store");
+    assert_eq!(res.headers_map().get_all(header::VARY).iter().collect::<Vec<_>>(), vec!["accept", "accept-language"]);
+    assert!(!res.headers_map().contains_key("x-api-version"));
+    assert_eq!(res.headers_map()["x-request-path"], "/api/items");
+
+    // Headers set by the handlers are kept, and responses of the router get the default headers too
+    let res = client.get("/api/feed").send();
+    assert_eq!(res.headers_map().get_all(header::CACHE_CONTROL).iter().collect::<Vec<_>>(), vec!["max-age=60"]);
+    let res = client.get("/missing").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+    assert_eq!(res.headers_map()[header::SERVER], "saphir");
+}
+
+#[test]
+fn client_ip() {
+    fn router() -> Router {
+        let mut controller = BasicController::new(());
+        controller.add(Method::GET, "^/ip$", |_, req, res| {
+            res.body(req.client_ip().map(|ip| ip.to_string()).unwrap_or_default());
+        });
+
+        let mut router = Router::new();
+        router.add("^/ip", controller);
+        router
+    }
+
+    let network: IpNetwork = "10.0.0.0/8".parse().unwrap();
+    assert!(network.contains("10.1.2.3".parse().unwrap()));
+    assert!(network.contains("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(!network.contains("11.0.0.1".parse().unwrap()));
+    assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+
+    let client = TestClient::new(Server::builder().router(router()).build());
+    let res = client.get("/ip").peer_addr(([10, 0, 0, 1], 4000).into()).header("x-forwarded-for", "203.0.113.7").send();
+    assert_eq!(res.get_body(), b"10.0.0.1".to_vec());
+
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap().trust("fd00::/8").unwrap();
+    let client = TestClient::new(Server::builder().router(router()).trusted_proxies(proxies).build());
+
+    let res = client.get("/ip").peer_addr(([10, 0, 0, 1], 4000).into())
+        .header("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2").send();
+    assert_eq!(res.get_body(), b"203.0.113.7".to_vec());
+
+    let res = client.get("/ip").peer_addr(([10, 0, 0, 1], 4000).into())
+        .header("forwarded", "for=\"[2001:db8::1]:4711\", for=10.0.0.3;proto=https").send();
+    assert_eq!(res.get_body(), b"2001:db8::1".to_vec());
+
+    let res = client.get("/ip").peer_addr(([10, 0, 0, 1], 4000).into()).header("x-forwarded-for", "10.0.0.9").send();
+    assert_eq!(res.get_body(), b"10.0.0.9".to_vec());
+
+    let res = client.get("/ip").peer_addr(([192, 0, 2, 8], 4000).into()).header("x-forwarded-for", "203.0.113.7").send();
+    assert_eq!(res.get_body(), b"192.0.2.8".to_vec());
+}
+
+#[test]
+fn tls_info() {
+    let guard = TlsGuard::new().min_version(TlsVersion::Tls12).deny_cipher("cbc");
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/info$", |_, req, res| {
+        let tls = req.tls_info();
+        res.body(format!("{:?} {:?}", req.version(), tls.map(|tls| (tls.version, tls.cipher_suite, tls.server_name))));
+    });
+    controller.add_with_guards(Method::GET, "^/admin$", guard.into(), |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+    let client = TestClient::new(Server::builder().router(router).trusted_proxies(proxies).build());
+
+    let proxied = |path: &str, version: &str, cipher: &str| client.get(path).peer_addr(([10, 0, 0, 1], 4000).into())
+        .header(TLS_VERSION_HEADER, version).header(TLS_CIPHER_HEADER, cipher).header(TLS_SERVER_NAME_HEADER, "app.example.com");
+
+    let res = proxied("/info", "TLSv1.3", "TLS_AES_128_GCM_SHA256").send();
+    assert_eq!(res.get_body(), br#"HTTP/1.1 Some((Some(Tls13), Some("TLS_AES_128_GCM_SHA256"), Some("app.example.com")))"#.to_vec());
+    assert_eq!(proxied("/admin", "TLSv1.2", "ECDHE-RSA-AES128-GCM-SHA256").send().get_status(), StatusCode::OK);
+
+    let res = proxied("/admin", "TLSv1.1", "ECDHE-RSA-AES128-GCM-SHA256").send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+    let problem: Problem = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem.detail.unwrap(), "TLSv1.1 is not allowed, TLSv1.2 or more recent is required");
+    assert_eq!(proxied("/admin", "TLSv1.2", "ECDHE-RSA-AES128-CBC-SHA").send().get_status(), StatusCode::FORBIDDEN);
+
+    // Untrusted peers can't claim TLS, plain connections aren't secure
+    let res = client.get("/info").peer_addr(([192, 0, 2, 8], 4000).into()).header(TLS_VERSION_HEADER, "TLSv1.3").send();
+    assert_eq!(res.get_body(), b"HTTP/1.1 None".to_vec());
+    assert_eq!(client.get("/admin").send().get_status(), StatusCode::FORBIDDEN);
+
+    // Servers terminating TLS themselves insert the negotiated parameters in the request extensions
+    let tls = TlsInfo { version: Some(TlsVersion::Tls13), cipher_suite: None, server_name: None, session_reused: None, early_data: false };
+    assert_eq!(client.get("/admin").extension(tls).send().get_status(), StatusCode::OK);
+    assert_eq!("TLS 1.2".parse(), Ok(TlsVersion::Tls12));
+}
+
+#[test]
+fn early_data() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/orders$", |_, req, res| {
+        let reused = req.tls_info().and_then(|tls| tls.session_reused);
+        res.status(StatusCode::OK).body(format!("{} {:?}", req.is_early_data(), reused));
+    });
+    controller.add(Method::POST, "^/orders$", |_, _, res| { res.status(StatusCode::CREATED); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+    let client = TestClient::new(Server::builder().router(router).trusted_proxies(proxies).build());
+
+    let proxied = |method: Method| client.request(method, "/orders").peer_addr(([10, 0, 0, 1], 4000).into())
+        .header(TLS_VERSION_HEADER, "TLSv1.3").header(TLS_SESSION_REUSED_HEADER, "r").header(EARLY_DATA_HEADER, "1");
+
+    let res = proxied(Method::GET).send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.get_body(), b"true Some(true)".to_vec());
+
+    // A replayed order would be placed twice
+    let res = proxied(Method::POST).send();
+    assert_eq!(res.get_status().as_u16(), 425);
+    let problem: Problem = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem.detail.unwrap(), "A POST request can't be sent as early data, retry it once the TLS handshake completes");
+
+    assert_eq!(client.post("/orders").send().get_status(), StatusCode::CREATED);
+    let tls = TlsInfo { version: Some(TlsVersion::Tls13), cipher_suite: None, server_name: None, session_reused: Some(true), early_data: true };
+    assert_eq!(client.post("/orders").extension(tls).send().get_status().as_u16(), 425);
+}
+
+#[test]
+fn ip_filter() {
+    let filter = IpFilterGuard::new().allow("10.0.0.0/8").unwrap().deny("10.0.13.0/24").unwrap();
+
+    let mut guards = RequestGuardCollection::new();
+    guards.add(filter.clone());
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::GET, "^/admin$", guards, |_, _, res| { res.status(StatusCode::OK); });
+
+    let mut router = Router::new();
+    router.add("^/admin", controller);
+
+    let proxies = TrustedProxies::new().trust("192.168.0.1").unwrap();
+    let client = TestClient::new(Server::builder().router(router).trusted_proxies(proxies).build());
+    let status = |ip: [u8; 4]| client.get("/admin").peer_addr((ip, 4000).into()).send().get_status();
+
+    assert_eq!(status([10, 0, 0, 1]), StatusCode::OK);
+    assert_eq!(status([10, 0, 13, 5]), StatusCode::FORBIDDEN);
+    assert_eq!(status([172, 16, 0, 1]), StatusCode::FORBIDDEN);
+
+    let res = client.get("/admin").peer_addr(([192, 168, 0, 1], 4000).into()).header("x-forwarded-for", "10.0.42.7").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+
+    filter.add_denied("10.0.42.7".parse().unwrap());
+    assert!(!filter.is_allowed("10.0.42.7".parse().unwrap()));
+    let res = client.get("/admin").peer_addr(([192, 168, 0, 1], 4000).into()).header("x-forwarded-for", "10.0.42.7").send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+
+    assert!(filter.remove(&"10.0.13.0/24".parse().unwrap()));
+    assert_eq!(status([10, 0, 13, 5]), StatusCode::OK);
+}
+
+#[test]
+fn http2_trailers() {
+    extern crate tokio;
+    use futures::{Async, Poll};
+    use hyper::body::Payload;
+    use hyper::{Chunk, Client};
+
+    use saphir::header::HeaderMap;
+
+    struct WithTrailers(Option<Chunk>, Option<HeaderMap>);
+
+    impl Payload for WithTrailers {
+        type Data = Chunk;
+        type Error = hyper::Error;
+
+        fn poll_data(&mut self) -> Poll<Option<Chunk>, hyper::Error> {
+            Ok(Async::Ready(self.0.take()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, hyper::Error> {
+            Ok(Async::Ready(self.1.take()))
+        }
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/upload$", |_, req, res| {
+        let checksum = req.trailers().and_then(|t| t.get("x-checksum")).map(|c| c.to_str().unwrap().to_string());
+        res.status(StatusCode::OK).body(req.body().clone()).trailer("x-checksum", checksum.unwrap_or_default().as_str());
+        assert_eq!(res.headers_map()[header::TRAILER], "x-checksum");
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut trailers = HeaderMap::new();
+    trailers.insert("x-checksum", "2a".parse().unwrap());
+    let request = Request::post(format!("{}/upload", server.url()))
+        .body(WithTrailers(Some(Chunk::from("data")), Some(trailers)))
+        .unwrap();
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let client: Client<_, WithTrailers> = Client::builder().http2_only(true).build_http();
+    let response = runtime.block_on(client.request(request)).unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut body = response.into_body();
+    let mut data = Vec::new();
+    let trailers = runtime.block_on(futures::future::poll_fn(move || {
+        loop {
+            match body.poll_data()? {
+                Async::Ready(Some(chunk)) => data.extend_from_slice(&chunk),
+                Async::Ready(None) => break,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+        assert_eq!(data, b"data".to_vec());
+        body.poll_trailers()
+    })).unwrap().unwrap();
+    assert_eq!(trailers["x-checksum"], "2a");
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn chunked_streaming() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/lines$", |_, _, res| {
+        let mut sender = res.status(StatusCode::OK).header(header::CONTENT_LENGTH, "3").stream();
+        assert!(res.is_streamed());
+        thread::spawn(move || {
+            for line in 0..3 {
+                sender.send(format!("line {}\n", line)).unwrap();
+            }
+        });
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /lines HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+
+    let mut response = Vec::new();
+    let mut buffer = [0; 512];
+    while !response.ends_with(b"0\r\n\r\n") {
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(read > 0);
+        response.extend_from_slice(&buffer[..read]);
+    }
+
+    let response = String::from_utf8(response).unwrap().to_lowercase();
+    let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap());
+    assert!(head.contains("transfer-encoding: chunked"));
+    assert!(!head.contains("content-length"));
+    let lines: String = body.split("\r\n").filter(|part| part.starts_with("line")).collect();
+    assert_eq!(lines, "line 0\nline 1\nline 2\n");
+
+    drop(stream);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn body_limits() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    fn server() -> Server {
+        let mut controller = BasicController::new(());
+        controller.add(Method::POST, "^/(items|upload)$", |_, req, res| {
+            res.status(StatusCode::CREATED).body(req.body().len().to_string());
+        });
+
+        let mut router = Router::new();
+        router.add("^/", controller);
+
+        let limits = BodyLimits::new(BodyLimit::new().max_size(16).timeout(Duration::from_millis(200)))
+            .content_type("application/json", BodyLimit::new().max_size(64))
+            .content_type("multipart/*", BodyLimit::new().max_size(128))
+            .route("^/upload$", BodyLimit::new().max_size(256));
+        Server::builder().router(router).body_limits(limits).build()
+    }
+
+    let client = TestClient::new(server());
+    let send = |uri: &str, content_type: &str, len: usize| {
+        client.post(uri).header(header::CONTENT_TYPE, content_type).body(vec![b'a'; len]).send()
+    };
+    assert_eq!(send("/items", "text/plain", 16).get_status(), StatusCode::CREATED);
+    assert_eq!(send("/items", "text/plain", 17).get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(send("/items", "application/json; charset=utf-8", 64).get_status(), StatusCode::CREATED);
+    assert_eq!(send("/items", "application/json", 65).get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(send("/items", "multipart/form-data; boundary=x", 128).get_status(), StatusCode::CREATED);
+    assert_eq!(send("/upload", "application/json", 256).get_status(), StatusCode::CREATED);
+
+    let res = send("/upload", "application/json", 257);
+    assert_eq!(res.get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], PROBLEM_CONTENT_TYPE);
+    assert!(String::from_utf8_lossy(&res.get_body()).contains("256 bytes"));
+
+    let server = server().spawn_test().unwrap();
+    let exchange = |request: &[u8]| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(request).unwrap();
+        let mut buffer = [0; 512];
+        let read = stream.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..read]).to_string()
+    };
+
+    // Rejected from its Content-Length, without waiting for the body
+    assert!(exchange(b"POST /items HTTP/1.1\r\nHost: test\r\nContent-Length: 100000\r\n\r\n").starts_with("HTTP/1.1 413"));
+    // Rejected once the chunks received grow past the limit
+    assert!(exchange(b"POST /items HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\n\r\n20\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n")
+        .starts_with("HTTP/1.1 413"));
+    // The rest of the body never comes
+    assert!(exchange(b"POST /items HTTP/1.1\r\nHost: test\r\nContent-Length: 10\r\n\r\nabc").starts_with("HTTP/1.1 408"));
+    assert!(exchange(b"POST /items HTTP/1.1\r\nHost: test\r\nContent-Length: 3\r\n\r\nabc").starts_with("HTTP/1.1 201"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn maintenance_mode() {
+    use std::time::Duration;
+
+    let maintenance = MaintenanceMode::new()
+        .allow_path("^/health$").unwrap()
+        .allow_ip("10.0.0.0/8").unwrap()
+        .body("text/plain", "Back soon")
+        .retry_after(Duration::from_millis(1500));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/(items|health)$", |_, _, res| { res.status(StatusCode::OK).body("ok"); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).maintenance(maintenance.clone()).build());
+    let get = |path: &str, ip: [u8; 4]| client.get(path).peer_addr((ip, 4000).into()).send();
+
+    assert!(!maintenance.is_enabled());
+    assert_eq!(get("/items", [192, 0, 2, 8]).get_status(), StatusCode::OK);
+
+    maintenance.enable();
+    let res = get("/items", [192, 0, 2, 8]);
+    assert_eq!(res.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers_map()[header::RETRY_AFTER], "2");
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+    assert_eq!(res.get_body(), b"Back soon".to_vec());
+    assert_eq!(get("/health", [192, 0, 2, 8]).get_status(), StatusCode::OK);
+    assert_eq!(get("/items", [10, 1, 2, 3]).get_status(), StatusCode::OK);
+
+    maintenance.disable();
+    assert_eq!(get("/items", [192, 0, 2, 8]).get_body(), b"ok".to_vec());
+}
+
+#[test]
+fn request_cancellation() {
+    use saphir::test::MockRequest;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let (cancelled_tx, cancelled_rx) = mpsc::channel();
+    let mut controller = BasicController::new(Mutex::new(cancelled_tx));
+    controller.add(Method::GET, "^/slow$", |cancelled, req, res| {
+        let started = Instant::now();
+        while !req.is_cancelled() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        cancelled.lock().unwrap().send(req.is_cancelled()).unwrap();
+        res.status(StatusCode::OK);
+    });
+    controller.add_with_policy(Method::GET, "^/deadline$", RoutePolicy::new().timeout(Duration::from_millis(100)), |_, req, res| {
+        let deadline = req.deadline().expect("the policy sets a deadline");
+        assert!(!req.is_cancelled());
+        assert!(req.cancellation_token().remaining().unwrap() <= Duration::from_millis(100));
+        thread::sleep(deadline - Instant::now());
+        assert!(req.is_cancelled());
+        res.status(StatusCode::OK);
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).cancel_on_half_close().build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    drop(stream);
+    assert_eq!(cancelled_rx.recv_timeout(Duration::from_secs(10)), Ok(true));
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /deadline HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 504");
+
+    let req = MockRequest::get("/").build();
+    assert!(!req.is_cancelled());
+    assert_eq!(req.deadline(), None);
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn half_close() {
+    use std::io::{Read, Write};
+    use std::net::{Shutdown, TcpStream};
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/ok$", |_, _, res| {
+        res.status(StatusCode::OK).body("ok");
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /ok HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("ok"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn client_disconnect() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let (gone_tx, gone_rx) = mpsc::channel();
+    let mut controller = BasicController::new(Mutex::new(gone_tx));
+    controller.add(Method::GET, "^/slow$", |gone, _, res| {
+        let started = Instant::now();
+        while !res.is_client_gone() && started.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        gone.lock().unwrap().send(res.is_client_gone()).unwrap();
+        res.status(StatusCode::OK);
+    });
+    controller.add(Method::GET, "^/events$", |gone, req, res| {
+        let token = req.cancellation_token();
+        let gone = gone.lock().unwrap().clone();
+        let mut sender = res.status(StatusCode::OK).stream();
+        thread::spawn(move || {
+            let started = Instant::now();
+            while !token.is_client_gone() && started.elapsed() < Duration::from_secs(5) {
+                let _ = sender.send("data: tick\n\n");
+                thread::sleep(Duration::from_millis(10));
+            }
+            gone.send(token.is_client_gone()).unwrap();
+        });
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).cancel_on_half_close().build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    drop(stream);
+    assert_eq!(gone_rx.recv_timeout(Duration::from_secs(10)), Ok(true));
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /events HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    let mut response = [0; 12];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"HTTP/1.1 200");
+    drop(stream);
+    assert_eq!(gone_rx.recv_timeout(Duration::from_secs(10)), Ok(true));
+
+    assert!(!SyncResponse::new().is_client_gone());
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn health_probes() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let ready = Arc::new(AtomicBool::new(false));
+    let ready_c = ready.clone();
+    let mut health = HealthController::new();
+    health.add_liveness_check("process", || -> Result<(), String> { Ok(()) });
+    health.add_readiness_check("database", move || -> Result<(), String> {
+        if ready_c.load(Ordering::SeqCst) { Ok(()) } else { Err("not connected".to_string()) }
+    });
+
+    let mut router = Router::new();
+    router.add("^/(healthz|readyz)$", health);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.get("/healthz").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map().get(header::CACHE_CONTROL).unwrap(), "no-store");
+    let report: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(report, json!({"status": "ok", "checks": {"process": {"status": "ok"}}}));
+
+    let res = client.get("/readyz").send();
+    assert_eq!(res.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    let report: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(report, json!({"status": "error", "checks": {"database": {"status": "error", "message": "not connected"}}}));
+
+    ready.store(true, Ordering::SeqCst);
+    assert_eq!(client.get("/readyz").send().get_status(), StatusCode::OK);
+    assert_eq!(client.post("/readyz").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[test]
+fn template_rendering() {
+    struct Greeter;
+
+    impl TemplateEngine for Greeter {
+        fn render(&self, name: &str, context: &serde_json::Value) -> Result<String, TemplateError> {
+            match name {
+                "hello.html" => Ok(format!("<p>Hello {}</p>", context["name"].as_str().unwrap_or("?"))),
+                _ => Err(TemplateError::new(format!("no template named {}", name))),
+            }
+        }
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/hello$", |_, _, res| {
+        res.status(StatusCode::OK).render("hello.html", &json!({"name": "saphir"}));
+    });
+    controller.add(Method::GET, "^/missing$", |_, _, res| {
+        res.status(StatusCode::OK).render("missing.html", &json!({}));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).template_engine(Greeter).build());
+
+    let res = client.get("/hello").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(res.get_body(), b"<p>Hello saphir</p>".to_vec());
+    assert_eq!(client.get("/missing").send().get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let mut res = SyncResponse::new();
+    res.render("hello.html", &json!({}));
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[test]
+fn response_helpers() {
+    let mut res = SyncResponse::new();
+    res.redirect("/login");
+    assert_eq!(res.get_status(), StatusCode::FOUND);
+    assert_eq!(res.headers_map()[header::LOCATION], "/login");
+
+    let statuses = [
+        (SyncResponse::new().see_other("/a").get_status(), StatusCode::SEE_OTHER),
+        (SyncResponse::new().moved_permanently("/a").get_status(), StatusCode::MOVED_PERMANENTLY),
+        (SyncResponse::new().temporary_redirect("/a").get_status(), StatusCode::TEMPORARY_REDIRECT),
+        (SyncResponse::new().permanent_redirect("/a").get_status(), StatusCode::PERMANENT_REDIRECT),
+        (SyncResponse::new().created("/items/1").get_status(), StatusCode::CREATED),
+    ];
+    for (status, expected) in statuses.iter() {
+        assert_eq!(status, expected);
+    }
+
+    let mut res = SyncResponse::new();
+    res.body("ignored").no_content();
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    assert!(res.get_body().is_empty());
+
+    let mut res = SyncResponse::new();
+    res.problem_json(StatusCode::CONFLICT, "Conflict", "The item already exists");
+    assert_eq!(res.get_status(), StatusCode::CONFLICT);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/problem+json");
+    let problem: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem, json!({"type": "about:blank", "title": "Conflict", "status": 409, "detail": "The item already exists"}));
+}
+
+#[test]
+fn typed_headers() {
+    use saphir::header::{CacheControl, CacheDirective, ContentLength, ContentType};
+    use saphir::test::MockRequest;
+
+    let mut req = MockRequest::post("/").header(header::CONTENT_TYPE, "application/json").build();
+    let ContentType(mime) = req.typed_header::<ContentType>().unwrap();
+    assert_eq!(mime.to_string(), "application/json");
+    assert!(req.typed_header::<ContentLength>().is_none());
+
+    req.set_typed_header(ContentLength(42));
+    assert_eq!(req.headers_map()[header::CONTENT_LENGTH], "42");
+    assert_eq!(req.typed_header::<ContentLength>(), Some(ContentLength(42)));
+
+    let mut res = SyncResponse::new();
+    res.header(header::CACHE_CONTROL, "private")
+        .set_typed_header(CacheControl(vec![CacheDirective::NoCache, CacheDirective::MaxAge(60)]));
+    assert_eq!(res.header_values(header::CACHE_CONTROL).iter().count(), 1);
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "no-cache, max-age=60");
+    assert_eq!(res.typed_header::<CacheControl>(), Some(CacheControl(vec![CacheDirective::NoCache, CacheDirective::MaxAge(60)])));
+}
+
+#[test]
+fn multi_value_headers() {
+    use saphir::header::{Headers, SetCookie};
+    use saphir::test::MockRequest;
+
+    let mut req = MockRequest::get("/")
+        .header(header::ACCEPT, "text/html, application/json")
+        .header(header::ACCEPT, "text/plain,,")
+        .build();
+    assert_eq!(req.header_values(header::ACCEPT).iter().count(), 2);
+    assert_eq!(req.header_list(header::ACCEPT), vec!["text/html", "application/json", "text/plain"]);
+
+    req.append_header("x-trace", "a").unwrap();
+    req.append_header("x-trace", "b").unwrap();
+    assert!(req.append_header("x trace", "c").is_err());
+    let traces: Vec<_> = req.headers_iter().filter(|&(name, _)| name == "x-trace").map(|(_, value)| value.clone()).collect();
+    assert_eq!(traces, vec!["a", "b"]);
+
+    let mut parsed = Headers::new();
+    parsed.set(SetCookie(vec!["a=1".to_string(), "b=2".to_string()]));
+    req.insert_parsed_headers(parsed.clone());
+    assert_eq!(req.header_values(header::SET_COOKIE).iter().count(), 2);
+
+    let mut res = SyncResponse::new();
+    res.parsed_header(parsed);
+    assert_eq!(res.header_values(header::SET_COOKIE).iter().count(), 2);
+    res.header(header::VARY, "Accept").header(header::VARY, "Accept-Language");
+    assert_eq!(res.header_list(header::VARY), vec!["Accept", "Accept-Language"]);
+    res.set_header(header::VARY, "*");
+    assert_eq!(res.header_list(header::VARY), vec!["*"]);
+    assert_eq!(res.headers_iter().count(), 3);
+}
+
+#[test]
+fn application_state() {
+    struct AppName(&'static str);
+    struct Visits(std::sync::atomic::AtomicUsize);
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/name$", |_, req, res| {
+        let visits = req.state::<Visits>().unwrap().0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+        let name = req.state::<AppName>().map(|name| name.0).unwrap_or("none");
+        assert!(req.state::<String>().is_none());
+        assert!(req.states().unwrap().contains::<AppName>());
+        res.status(StatusCode::OK).body(format!("{} {}", name, visits));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder()
+        .router(router)
+        .state(AppName("first"))
+        .state(AppName("saphir"))
+        .state(Visits(std::sync::atomic::AtomicUsize::new(0)))
+        .build());
+
+    assert_eq!(client.get("/name").send().get_body(), b"saphir 1".to_vec());
+    assert_eq!(client.get("/name").send().get_body(), b"saphir 2".to_vec());
+    assert!(saphir::test::MockRequest::get("/").build().state::<AppName>().is_none());
+}
+
+#[test]
+fn service_container() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Config(&'static str);
+    struct Connection(usize, Arc<Config>);
+
+    let created = Arc::new(AtomicUsize::new(0));
+    let created_c = created.clone();
+    let mut container = Container::new();
+    container.register_singleton(Config("db://local"));
+    container.register_factory(move |c: &Container| Connection(created_c.fetch_add(1, Ordering::SeqCst), c.resolve().unwrap()));
+
+    assert!(container.is_registered::<Connection>());
+    assert!(!container.is_registered::<String>());
+    assert_eq!(container.resolve::<Connection>().unwrap().0, 0);
+    assert_eq!(container.resolve::<Connection>().unwrap().0, 1);
+    assert!(Arc::ptr_eq(&container.resolve::<Config>().unwrap(), &container.resolve::<Config>().unwrap()));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/connection$", |_, req, res| {
+        let first = req.resolve::<Connection>().unwrap();
+        let second = req.resolve::<Connection>().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        res.status(StatusCode::OK).body(format!("{} {}", first.0, (first.1).0));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).state(container).build());
+
+    assert_eq!(client.get("/connection").send().get_body(), b"2 db://local".to_vec());
+    assert_eq!(client.get("/connection").send().get_body(), b"3 db://local".to_vec());
+    assert_eq!(created.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn config_reload() {
+    use std::fs;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    let path = ::std::env::temp_dir().join(format!("saphir-reload-{}.json", ::std::process::id()));
+    fs::write(&path, r#"{"limit": 1}"#).unwrap();
+
+    let (reloaded_tx, reloaded_rx) = mpsc::channel();
+    let reloaded_tx = Mutex::new(reloaded_tx);
+    let reloader = ConfigReloader::<serde_json::Value>::new(&path).unwrap()
+        .poll_interval(Duration::from_millis(20))
+        .on_reload(move |config| reloaded_tx.lock().unwrap().send(config["limit"].as_u64()).unwrap());
+    let config = reloader.config();
+    assert_eq!(config.get()["limit"], 1);
+
+    reloader.spawn();
+    assert_eq!(reloaded_rx.recv_timeout(Duration::from_secs(5)), Ok(Some(1)));
+
+    ::std::thread::sleep(Duration::from_millis(50));
+    fs::write(&path, r#"{"limit": 2}"#).unwrap();
+    assert_eq!(reloaded_rx.recv_timeout(Duration::from_secs(5)), Ok(Some(2)));
+    assert_eq!(config.get()["limit"], 2);
+
+    ::std::thread::sleep(Duration::from_millis(50));
+    fs::write(&path, "{").unwrap();
+    assert!(reloaded_rx.recv_timeout(Duration::from_millis(300)).is_err());
+    assert_eq!(config.get()["limit"], 2);
+
+    assert!(ConfigReloader::<serde_json::Value>::new(path.with_extension("missing")).is_err());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn thread_options() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/thread$", |_, _, res| {
+        res.body(::std::thread::current().name().unwrap_or("").to_string());
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder()
+        .router(router)
+        .worker_threads(2)
+        .handler_threads(2)
+        .thread_name("api")
+        .build()
+        .spawn_test()
+        .unwrap();
+
+    for _ in 0..4 {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(b"GET /thread HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let name = response.rsplit("\r\n\r\n").next().unwrap();
+        assert!(name.starts_with("api-handler-"), "handled on {:?}", name);
+    }
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn concurrency_limit() {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let mut controller = BasicController::new((Mutex::new(entered_tx), Mutex::new(release_rx)));
+    controller.add(Method::GET, "^/slow$", |channels, _, res| {
+        channels.0.lock().unwrap().send(()).unwrap();
+        channels.1.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+        res.status(StatusCode::OK);
+    });
+    controller.add(Method::GET, "^/fast$", |_, _, res| { res.status(StatusCode::OK); });
+    controller.add(Method::GET, "^/panic$", |_, _, _| panic!("handler failure"));
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(ConcurrencyLimitMiddleware::new(1).queue(1, Duration::from_millis(200)).retry_after(Duration::from_millis(1500)),
+                      vec!("/"), None);
+    let client = Arc::new(TestClient::new(Server::builder().router(router).middleware_stack(middlewares).build()));
+
+    // A panicking handler releases its slot
+    assert_eq!(client.get("/panic").send().get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(client.get("/fast").send().get_status(), StatusCode::OK);
+
+    let slow_client = client.clone();
+    let slow = thread::spawn(move || slow_client.get("/slow").send().get_status());
+    entered_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    // Queued for 200ms, then shed
+    let res = client.get("/fast").send();
+    assert_eq!(res.get_status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers_map()[header::RETRY_AFTER], "2");
+
+    // Queued until the slow request completes
+    let queued_client = client.clone();
+    let queued = thread::spawn(move || queued_client.get("/fast").send().get_status());
+    thread::sleep(Duration::from_millis(50));
+    release_tx.send(()).unwrap();
+    assert_eq!(slow.join().unwrap(), StatusCode::OK);
+    assert_eq!(queued.join().unwrap(), StatusCode::OK);
+    assert_eq!(client.get("/fast").send().get_status(), StatusCode::OK);
+}
+
+#[cfg(unix)]
+#[test]
+fn inherited_listeners() {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut connected = TcpStream::connect(peer.local_addr().unwrap()).unwrap();
+    let file = File::open("/dev/null").unwrap();
+
+    // Only the listening socket is adopted, the connected socket and the file are left alone
+    ::std::env::set_var("SAPHIR_LISTEN_FD", format!("{},{},{}", connected.as_raw_fd(), listener.into_raw_fd(), file.as_raw_fd()));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/ok$", |_, _, res| { res.status(StatusCode::OK); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).inherit_listener().thread_per_core().worker_threads(2).build();
+    thread::spawn(move || server.run("http://127.0.0.1:0"));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /ok HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    connected.write_all(b"still open").unwrap();
+    let mut received = [0; 10];
+    peer.accept().unwrap().0.read_exact(&mut received).unwrap();
+    assert_eq!(&received, b"still open");
+    assert!(file.metadata().is_ok());
+}
+
+#[test]
+fn reverse_proxy() {
+    let mut upstream = BasicController::new(());
+    upstream.add(Method::GET, "^/", |_, req, res| {
+        let forwarded = req.headers_map().get("x-forwarded-host").and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+        res.body(format!("{} {}", req.uri(), forwarded));
+    });
+    let mut router = Router::new();
+    router.add("^/", upstream);
+    let upstream = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut router = Router::new();
+    router.add("^/", ProxyController::new(&format!("{}/v1", upstream.url())).unwrap().strip_prefix("/api"));
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let body = |path: &str| String::from_utf8(client.get(path).header("host", "gateway").send().get_body()).unwrap();
+    assert_eq!(body("/api/users?page=2"), "/v1/users?page=2 gateway");
+    assert_eq!(body("/api"), "/v1/ gateway");
+    // The prefix is only stripped on a segment boundary
+    assert_eq!(body("/apiv2/users"), "/v1/apiv2/users gateway");
+
+    upstream.shutdown().unwrap();
+}
+
+#[test]
+fn proxy_load_balancing() {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    let mut upstream = BasicController::new(());
+    upstream.add(Method::GET, "^/", |_, _, res| { res.body("live"); });
+    upstream.add(Method::POST, "^/", |_, _, res| { res.body("live"); });
+    let mut router = Router::new();
+    router.add("^/", upstream);
+    let live = Server::builder().router(router).build().spawn_test().unwrap();
+
+    // Nothing listens on the address of a dropped listener
+    let dead = format!("http://{}", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+
+    let proxy = ProxyController::with_upstreams(vec![
+        Upstream::new(&dead).unwrap().retries(1).max_fails(1, Duration::from_secs(60)),
+        Upstream::new(&live.url()).unwrap().retries(1),
+    ]);
+    let mut router = Router::new();
+    router.add("^/balanced", proxy);
+    router.add("^/unreachable", ProxyController::new(&dead).unwrap());
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    // The idempotent request is retried on the live upstream, which then takes the requests that can't be retried
+    assert_eq!(client.get("/balanced").send().get_body(), b"live".to_vec());
+    for _ in 0..3 {
+        let res = client.post("/balanced").send();
+        assert_eq!(res.get_status(), StatusCode::OK);
+    }
+
+    assert_eq!(client.get("/unreachable").send().get_status(), StatusCode::BAD_GATEWAY);
+
+    live.shutdown().unwrap();
+}
+
+#[test]
+fn proxy_cache() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    let hits = Arc::new(AtomicUsize::new(0));
+    let mut upstream = BasicController::new(hits.clone());
+    upstream.add(Method::GET, "^/fresh$", |hits, _, res| {
+        hits.fetch_add(1, Ordering::SeqCst);
+        res.header(header::CACHE_CONTROL, "max-age=60").body("fresh");
+    });
+    upstream.add(Method::GET, "^/tagged$", |hits, req, res| {
+        hits.fetch_add(1, Ordering::SeqCst);
+        res.header(header::ETAG, "\"v1\"");
+        if req.headers_map().get(header::IF_NONE_MATCH).map(|v| v.as_bytes()) == Some(&b"\"v1\""[..]) {
+            res.status(StatusCode::NOT_MODIFIED).header(header::CACHE_CONTROL, "max-age=60");
+        } else {
+            res.body("tagged");
+        }
+    });
+    upstream.add(Method::GET, "^/stale$", |hits, _, res| {
+        let hit = hits.fetch_add(1, Ordering::SeqCst);
+        res.header(header::CACHE_CONTROL, "max-age=0, stale-while-revalidate=60").body(format!("version {}", hit));
+    });
+    let mut router = Router::new();
+    router.add("^/", upstream);
+    let upstream = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut router = Router::new();
+    router.add("^/", ProxyController::new(&upstream.url()).unwrap().cache(ProxyCache::new(MemoryCacheStore::new(16))));
+    let client = TestClient::new(Server::builder().router(router).build());
+    let body = |res: &SyncResponse| String::from_utf8(res.get_body()).unwrap();
+
+    assert_eq!(body(&client.get("/fresh").send()), "fresh");
+    let res = client.get("/fresh").send();
+    assert_eq!((body(&res), hits.load(Ordering::SeqCst)), ("fresh".to_string(), 1));
+    assert!(res.headers_map().contains_key(header::AGE));
+    client.get("/fresh").header(header::CACHE_CONTROL, "no-store").send();
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // Responses with a validator are revalidated, the upstream answering 304 without a body
+    assert_eq!(body(&client.get("/tagged").send()), "tagged");
+    let res = client.get("/tagged").send();
+    assert_eq!((res.get_status(), body(&res), hits.load(Ordering::SeqCst)), (StatusCode::OK, "tagged".to_string(), 4));
+    client.get("/tagged").send();
+    assert_eq!(hits.load(Ordering::SeqCst), 4);
+    client.get("/tagged").header(header::CACHE_CONTROL, "no-cache").send();
+    assert_eq!(hits.load(Ordering::SeqCst), 5);
+
+    // Stale responses are answered while they are revalidated in the background
+    assert_eq!(body(&client.get("/stale").send()), "version 5");
+    assert_eq!(body(&client.get("/stale").send()), "version 5");
+    let started = Instant::now();
+    while body(&client.get("/stale").send()) != "version 6" {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    upstream.shutdown().unwrap();
+}
+
+#[test]
+fn date_header() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/ok$", |_, _, res| { res.status(StatusCode::OK); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /ok HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    // A single Date header, formatted like the cached one
+    let dates: Vec<&str> = response.lines().filter(|l| l.to_ascii_lowercase().starts_with("date:")).collect();
+    assert_eq!(dates.len(), 1, "{}", response);
+    let date = http_date();
+    assert_eq!(dates[0][6..].trim().len(), date.len());
+    assert!(date.to_str().unwrap().ends_with(" GMT"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn pooled_request_bodies() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| { res.body(req.body().clone()); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build();
+    let stats = server.buffer_pool_stats();
+    let server = server.spawn_test().unwrap();
+
+    // Every request of the connection after the first one reuses a buffer, without seeing the bodies loaded before
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    for body in &["a longer first body", "second", "third"] {
+        let request = format!("POST /echo HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut buffer = [0; 512];
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..read]).ends_with(&format!("\r\n\r\n{}", body)));
+    }
+
+    assert_eq!((stats.misses(), stats.hits()), (1, 2));
+    assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+    drop(stream);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn streamed_response_limit() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    let mut controller = BasicController::new(());
+    controller.add_with_policy(Method::GET, "^/stream$", RoutePolicy::new().max_response_size(1000), |_, _, res| {
+        let mut sender = res.status(StatusCode::OK).stream();
+        thread::spawn(move || {
+            for _ in 0..3 {
+                let _ = sender.send(vec![b'a'; 400]);
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /stream HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    let response = String::from_utf8(response).unwrap();
+
+    // The head is sent before the body exceeds the limit, the client is left with an incomplete body
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+    assert_eq!(response.matches(&"a".repeat(400)).count(), 2);
+    assert!(!response.ends_with("0\r\n\r\n"));
+
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn text_charsets() {
+    use saphir::test::MockRequest;
+
+    let text = |content_type: &str, body: &[u8]| {
+        MockRequest::post("/").header(header::CONTENT_TYPE, content_type).body(body.to_vec()).build().body_text().map(|text| text.into_owned())
+    };
+
+    assert_eq!(text("text/plain", "crème brûlée".as_bytes()), Ok("crème brûlée".to_string()));
+    assert_eq!(text("text/plain; charset=\"ISO-8859-1\"", b"cr\xe8me br\xfbl\xe9e"), Ok("crème brûlée".to_string()));
+    assert_eq!(text("text/plain;charset=shift_jis", b"\x93\xfa\x96\x7b"), Ok("日本".to_string()));
+    assert_eq!(text("text/plain; charset=iso-8859-1", b"\xef\xbb\xbfcr\xc3\xa8me"), Ok("crème".to_string()));
+    assert_eq!(text("text/plain", b"cr\xe8me"), Err(TextError::Malformed("UTF-8")));
+    assert_eq!(text("text/plain; charset=klingon", b"tlhIngan"), Err(TextError::UnsupportedCharset("klingon".to_string())));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/plain$", |_, _, res| { res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain").body("crème"); });
+    controller.add(Method::GET, "^/latin1$", |_, _, res| { res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/plain").body(b"cr\xe8me".to_vec()); });
+    controller.add(Method::GET, "^/csv$", |_, _, res| { res.status(StatusCode::OK).header(header::CONTENT_TYPE, "text/csv; charset=windows-1252").body("a,b"); });
+    controller.add(Method::GET, "^/json$", |_, _, res| { res.status(StatusCode::OK).json(&"crème"); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+    let content_type = |path: &str| client.get(path).send().headers_map()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+
+    assert_eq!(content_type("/plain"), "text/plain; charset=utf-8");
+    assert_eq!(content_type("/latin1"), "text/plain");
+    assert_eq!(content_type("/csv"), "text/csv; charset=windows-1252");
+    assert_eq!(content_type("/json"), "application/json");
+}
+
+#[test]
+fn audit_log() {
+    use std::io::{Read, Write};
+    use std::net::{TcpStream, UdpSocket};
+    use std::sync::{Arc, Mutex};
+
+    let path = ::std::env::temp_dir().join(format!("saphir-audit-{}.jsonl", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let kept = records.clone();
+
+    let posted = Arc::new(Mutex::new(Vec::new()));
+    let mut events = BasicController::new(posted.clone());
+    events.add(Method::POST, "^/events$", |posted, req, res| {
+        posted.lock().unwrap().push(String::from_utf8_lossy(req.body()).into_owned());
+        res.status(StatusCode::ACCEPTED);
+    });
+    let mut collector_router = Router::new();
+    collector_router.add("^/", events);
+    let http_collector = Server::builder().router(collector_router).build().spawn_test().unwrap();
+
+    let audit = AuditLog::new()
+        .sink(move |record: &AuditRecord| { kept.lock().unwrap().push(record.clone()); Ok(()) })
+        .sink(FileAuditSink::open(&path).unwrap())
+        .sink(SyslogAuditSink::connect(collector.local_addr().unwrap(), "billing").unwrap())
+        .sink(HttpAuditSink::new(&format!("{}/events", http_collector.url())).unwrap().batch_size(10));
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::DELETE, "^/invoices/\\d+$", |_, req, res| {
+        let event = AuditEvent::new("alice", "invoice.delete", req.uri().path());
+        if req.header_list("x-role").first() == Some(&"admin") {
+            req.audit(event);
+            res.status(StatusCode::NO_CONTENT);
+        } else {
+            req.audit(event.outcome(AuditOutcome::Denied).detail("reason", "not an administrator"));
+            res.status(StatusCode::FORBIDDEN);
+        }
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let server = Server::builder().router(router).audit_log(audit).build().spawn_test().unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"DELETE /invoices/7 HTTP/1.1\r\nHost: test\r\nX-Request-Id: r-1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"));
+
+    let mut datagram = [0; 1024];
+    let read = collector.recv(&mut datagram).unwrap();
+    let message = String::from_utf8_lossy(&datagram[..read]).into_owned();
+    assert!(message.starts_with("<84>1 - - billing - audit - {"));
+    assert!(posted.lock().unwrap().is_empty());
+
+    // Buffered records are written once the server is shut down
+    server.shutdown().unwrap();
+    let written = ::std::fs::read_to_string(&path).unwrap();
+    let _ = ::std::fs::remove_file(&path);
+    let record: AuditRecord = serde_json::from_str(written.trim()).unwrap();
+
+    assert_eq!(records.lock().unwrap().clone(), vec![record.clone()]);
+    assert_eq!(posted.lock().unwrap().clone(), vec![written]);
+    assert_eq!(record.event.outcome, AuditOutcome::Denied);
+    assert_eq!(record.event.resource, "/invoices/7");
+    assert_eq!(record.event.details["reason"], "not an administrator");
+    assert_eq!((record.method.as_str(), record.client_ip.as_deref()), ("DELETE", Some("127.0.0.1")));
+    assert_eq!(record.request_id.as_deref(), Some("r-1"));
+}
+
+#[test]
+fn webhook_dispatcher() {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let secret = b"whsec_test";
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut hooks = BasicController::new(received.clone());
+    let verified = WebhookSignatureGuard::new(secret).prefix("sha256=").timestamp("x-webhook-timestamp", Duration::from_secs(60));
+    hooks.add_with_guards(Method::POST, "^/hooks$", verified.into(), |received, req, res| {
+        let mut received = received.lock().unwrap();
+        let event = req.header_list("x-webhook-event").first().map(|event| event.to_string()).unwrap_or_default();
+        received.push((event.clone(), String::from_utf8_lossy(req.body()).into_owned()));
+        // Every first attempt of an order fails, and refunds are refused
+        match event.as_str() {
+            "order.created" if received.iter().filter(|(received, _)| *received == event).count() == 1 => res.status(StatusCode::SERVICE_UNAVAILABLE),
+            "order.refunded" => res.status(StatusCode::GONE),
+            _ => res.status(StatusCode::NO_CONTENT),
+        };
+    });
+    let mut receiver_router = Router::new();
+    receiver_router.add("^/", hooks);
+    let receiver = Server::builder().router(receiver_router).build().spawn_test().unwrap();
+    let url = format!("{}/hooks", receiver.url());
+
+    let directory = ::std::env::temp_dir().join(format!("saphir-webhooks-{}", ::std::process::id()));
+    let _ = ::std::fs::remove_dir_all(&directory);
+    let dispatcher = || WebhookDispatcher::new(secret).store(DirectoryWebhookStore::open(&directory).unwrap())
+        .backoff(Duration::from_millis(20), Duration::from_millis(100));
+
+    // Enqueued while no dispatcher runs, the deliveries are kept for the next one
+    let stopped = dispatcher();
+    stopped.handle().enqueue(&url, "order.created", &json!({"order": 1})).unwrap();
+    stopped.handle().enqueue(&url, "order.refunded", &json!({"order": 1})).unwrap();
+    assert!(stopped.handle().enqueue("ftp://partner/hooks", "order.created", &json!({})).is_err());
+    drop(stopped);
+    assert_eq!(DirectoryWebhookStore::open(&directory).unwrap().pending().unwrap().len(), 2);
+
+    let dispatcher = dispatcher();
+    let handle = dispatcher.handle();
+    let server = Server::builder().build();
+    dispatcher.spawn_on(&server);
+    let server = server.spawn_test().unwrap();
+    handle.enqueue(&url, "order.paid", &json!({"order": 1})).unwrap();
+
+    let started = Instant::now();
+    while !DirectoryWebhookStore::open(&directory).unwrap().pending().unwrap().is_empty() && started.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    server.shutdown().unwrap();
+    let _ = ::std::fs::remove_dir_all(&directory);
+
+    let mut received = received.lock().unwrap().clone();
+    received.sort();
+    assert_eq!(received, vec![
+        ("order.created".to_string(), "{\"order\":1}".to_string()),
+        ("order.created".to_string(), "{\"order\":1}".to_string()),
+        ("order.paid".to_string(), "{\"order\":1}".to_string()),
+        ("order.refunded".to_string(), "{\"order\":1}".to_string()),
+    ]);
+}
+
+#[test]
+fn raw_body() {
+    use std::io;
+
+    // Request bodies are lowercased before reaching the handlers
+    struct Lowercase;
+
+    impl BodyTransformer for Lowercase {
+        fn request(&self, _req: &SyncRequest) -> Option<Box<dyn BodyTransform>> {
+            Some(Box::new(|chunk: &[u8]| -> io::Result<Vec<u8>> { Ok(chunk.to_ascii_lowercase()) }))
+        }
+
+        fn response(&self, _req: &SyncRequest, _res: &mut SyncResponse) -> Option<Box<dyn BodyTransform>> {
+            None
+        }
+    }
+
+    let mut upstream = BasicController::new(());
+    upstream.add(Method::POST, "^/", |_, req, res| { res.body(req.body().clone()); });
+    let mut router = Router::new();
+    router.add("^/", upstream);
+    let upstream = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/parsed$", |_, req, res| {
+        let value: serde_json::Value = req.body_json().unwrap();
+        res.body(format!("{} {}", value["name"], String::from_utf8_lossy(req.raw_body())));
+    });
+    let mut router = Router::new();
+    router.add("^/parsed$", controller);
+    router.add("^/api", ProxyController::new(&upstream.url()).unwrap().strip_prefix("/api"));
+
+    let path = ::std::env::temp_dir().join(format!("saphir-raw-body-{}.jsonl", ::std::process::id()));
+    let _ = ::std::fs::remove_file(&path);
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(RecordingMiddleware::new(&path).unwrap(), vec!("/"), None);
+    middlewares.apply(BodyTransformMiddleware::new(Lowercase), vec!("/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(middlewares).build());
+
+    assert_eq!(client.post("/parsed").body(r#"{"name":"Saphir"}"#).send().get_body(), br#""saphir" {"name":"Saphir"}"#.to_vec());
+    // Proxied as received, along with its content length
+    assert_eq!(client.post("/api/echo").body("Saphir").send().get_body(), b"Saphir".to_vec());
+
+    let exchanges = load_recording(&path).unwrap();
+    let _ = ::std::fs::remove_file(&path);
+    assert_eq!(exchanges[0].request.body, RecordedBody::Text(r#"{"name":"Saphir"}"#.to_string()));
+    upstream.shutdown().unwrap();
+}
+
+#[test]
+fn unbuffered_bodies() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut upstream = BasicController::new(());
+    upstream.add(Method::POST, "^/", |_, req, res| { res.body(req.body().clone()); });
+    let mut router = Router::new();
+    router.add("^/", upstream);
+    let upstream = Server::builder().router(router).build().spawn_test().unwrap();
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/upload$", |_, req, res| {
+        let loaded = req.body().len();
+        let mut received = Vec::new();
+        match req.body_reader().read_to_end(&mut received) {
+            Ok(read) => res.status(StatusCode::CREATED).body(format!("{} {} {}", loaded, read, req.has_unread_body())),
+            Err(e) => res.status(StatusCode::PAYLOAD_TOO_LARGE).body(e.to_string()),
+        };
+    });
+    let mut router = Router::new();
+    router.add("^/upload$", controller);
+    router.add("^/api", ProxyController::new(&upstream.url()).unwrap().strip_prefix("/api"));
+
+    let limits = BodyLimits::new(BodyLimit::new().max_size(16))
+        .route("^/upload$", BodyLimit::new().buffered(false))
+        .route("^/api", BodyLimit::new().max_size(1024).buffered(false));
+    let server = Server::builder().router(router).body_limits(limits).build().spawn_test().unwrap();
+    let exchange = |request: &[u8]| {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = exchange(b"POST /upload HTTP/1.1\r\nHost: test\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n8\r\nefghijkl\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 201") && response.ends_with("0 12 false"), "{}", response);
+    // The limit still applies while the body is read
+    let response = exchange(b"POST /upload HTTP/1.1\r\nHost: test\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n14\r\naaaaaaaaaaaaaaaaaaaa\r\n0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 413") && response.ends_with("16 bytes"), "{}", response);
+    let response = exchange(b"POST /upload HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: 100000\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+
+    let response = exchange(b"POST /api/echo HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: 32\r\n\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    assert!(response.starts_with("HTTP/1.1 200") && response.ends_with(&"a".repeat(32)), "{}", response);
+
+    server.shutdown().unwrap();
+    upstream.shutdown().unwrap();
+}
+
+#[test]
+fn data_usage() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/usage$", |_, req, res| {
+        let usage = req.connection_usage().unwrap();
+        res.body(format!("{} {} {}", usage.requests(), usage.received(), req.received_bytes()));
+    });
+    let mut router = Router::new();
+    router.add("^/usage$", controller);
+
+    let metrics = Metrics::new();
+    let mut middlewares = MiddlewareStack::new();
+    let key = |req: &SyncRequest| req.headers_map().get("x-api-key").and_then(|key| key.to_str().ok()).map(|key| key.to_string());
+    middlewares.apply(DataUsageMiddleware::new(metrics.clone()).key(key), vec!("/"), None);
+    let server = Server::builder().router(router).middleware_stack(middlewares).build().spawn_test().unwrap();
+
+    // Two requests on the same connection, of 50 and 69 bytes
+    let mut stream = TcpStream::connect(server.addr()).unwrap();
+    stream.write_all(b"GET /usage HTTP/1.1\r\nHost: test\r\nX-Api-Key: k1\r\n\r\n").unwrap();
+    stream.write_all(b"GET /usage HTTP/1.1\r\nHost: test\r\nX-Api-Key: k1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.contains("1 50 50") && response.ends_with("2 119 69"), "{}", response);
+
+    assert_eq!(metrics.counter("http_received_bytes_total", &[("client", "k1")]), 119);
+    assert!(metrics.counter("http_sent_bytes_total", &[("client", "k1")]) > 0);
+    assert_eq!(metrics.counter("http_received_bytes_total", &[("client", "anonymous")]), 0);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn drain_reports() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    let (entered_tx, entered_rx) = mpsc::channel();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let mut controller = BasicController::new((Mutex::new(entered_tx), Mutex::new(release_rx)));
+    controller.add(Method::GET, "^/slow$", |channels, _, res| {
+        channels.0.lock().unwrap().send(()).unwrap();
+        channels.1.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+        res.status(StatusCode::OK).body("done");
+    });
+    let mut router = Router::new();
+    router.add("^/slow$", controller);
+
+    let (reports_tx, reports) = mpsc::channel();
+    let reports_tx = Mutex::new(reports_tx);
+    let server = Server::builder()
+        .router(router)
+        .drain_interval(Duration::from_millis(50))
+        .on_drain(move |report| { let _ = reports_tx.lock().unwrap().send(report.clone()); })
+        .build()
+        .spawn_test()
+        .unwrap();
+
+    let addr = server.addr();
+    let request = thread::spawn(move || {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    });
+    entered_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    let shutdown = thread::spawn(move || server.shutdown());
+    let report = reports.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(report.is_shutting_down());
+    assert_eq!((report.in_flight(), report.streams()), (1, 0));
+    assert!(report.oldest_request_age().is_some());
+
+    release_tx.send(()).unwrap();
+    assert!(request.join().unwrap().ends_with("done"));
+    shutdown.join().unwrap().unwrap();
+    let last = reports.try_iter().last().unwrap();
+    assert_eq!(last.in_flight(), 0);
+    assert_eq!(last.oldest_request_age(), None);
+}
+
+#[test]
+fn fairness() {
+    extern crate socket2;
+    use std::io::{Read, Write};
+    use std::net::{IpAddr, SocketAddr, TcpStream};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let mut controller = BasicController::new((order.clone(), Mutex::new(release_rx)));
+    controller.add(Method::GET, "^/work$", |state, req, res| {
+        let first = {
+            let mut order = state.0.lock().unwrap();
+            order.push(req.peer_addr().unwrap().ip());
+            order.len() == 1
+        };
+        // The first request holds the only handler thread until the others are queued
+        if first {
+            state.1.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        res.status(StatusCode::OK).body("done");
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).handler_threads(1).fairness(Fairness::PerClient).build().spawn_test().unwrap();
+    let addr = server.addr();
+
+    let request = move |from: &str| {
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None).unwrap();
+        socket.bind(&SocketAddr::new(from.parse().unwrap(), 0).into()).unwrap();
+        socket.connect(&addr.into()).unwrap();
+        let mut stream: TcpStream = socket.into();
+        stream.write_all(b"GET /work HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        let requested = thread::spawn(move || {
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        thread::sleep(Duration::from_millis(100));
+        requested
+    };
+
+    // A client opening three connections doesn't delay the request of another one behind all of them
+    let requests = vec![request("127.0.0.1"), request("127.0.0.1"), request("127.0.0.1"), request("127.0.0.2")];
+    release_tx.send(()).unwrap();
+    for requested in requests {
+        assert!(requested.join().unwrap().ends_with("done"));
+    }
+
+    let a: IpAddr = "127.0.0.1".parse().unwrap();
+    let b: IpAddr = "127.0.0.2".parse().unwrap();
+    assert_eq!(*order.lock().unwrap(), vec![a, a, b, a]);
+    server.shutdown().unwrap();
+}
+
+#[test]
+fn parse_body() {
+    use std::collections::HashMap;
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/orders$", |_, req, res| {
+        match req.parse_body::<HashMap<String, String>>() {
+            Ok(order) => { res.status(StatusCode::CREATED).body(format!("{} {}", order["quantity"], order["item"])); }
+            Err(e) => { e.answer(req, res); }
+        }
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let headers_decoder = |body: &[u8]| {
+        let mut fields = serde_json::Map::new();
+        for line in String::from_utf8_lossy(body).lines() {
+            let mut field = line.splitn(2, ':');
+            match (field.next(), field.next()) {
+                (Some(name), Some(value)) => { fields.insert(name.trim().to_string(), json!(value.trim())); }
+                _ => return Err(format!("invalid line {:?}", line)),
+            }
+        }
+        Ok(serde_json::Value::Object(fields))
+    };
+    let client = TestClient::new(Server::builder().router(router).body_decoder("Text/X-Fields", headers_decoder).build());
+
+    let res = client.post("/orders").json(&json!({"item": "pen", "quantity": "2"})).send();
+    assert_eq!((res.get_status(), res.get_body()), (StatusCode::CREATED, b"2 pen".to_vec()));
+
+    let res = client.post("/orders").header(header::CONTENT_TYPE, "application/vnd.order+json; charset=utf-8").body(r#"{"item":"ink","quantity":"1"}"#).send();
+    assert_eq!(res.get_body(), b"1 ink");
+
+    let res = client.post("/orders").header(header::CONTENT_TYPE, "application/x-www-form-urlencoded").body("item=cap&quantity=3").send();
+    assert_eq!(res.get_body(), b"3 cap");
+
+    let res = client.post("/orders").header(header::CONTENT_TYPE, "text/x-fields").body("item: nib\nquantity: 5").send();
+    assert_eq!(res.get_body(), b"5 nib");
+
+    let res = client.post("/orders").header(header::CONTENT_TYPE, "text/x-fields").body("item").send();
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8_lossy(&res.get_body()).contains("invalid line"));
+
+    let res = client.post("/orders").json(&json!({"item": 3})).send();
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+
+    let res = client.post("/orders").header(header::CONTENT_TYPE, "text/csv").body("pen,2").send();
+    assert_eq!(res.get_status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(res.headers_map()["accept-post"].to_str().unwrap().starts_with("text/x-fields, application/json, application/x-www-form-urlencoded"));
+    let problem: serde_json::Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(problem["supported"][0], "text/x-fields");
+
+    let res = client.post("/orders").body("pen").send();
+    assert_eq!(res.get_status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let req = saphir::test::MockRequest::post("/").header(header::CONTENT_TYPE, "text/x-fields").build();
+    assert_eq!(req.parse_body::<HashMap<String, String>>().unwrap_err().status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[test]
+fn response_builder() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/items$", |_, _, res| {
+        ResponseBuilder::ok().header(header::CACHE_CONTROL, "max-age=60").json(&json!(["pen", "ink"])).respond(res).unwrap();
+    });
+    controller.add(Method::GET, "^/old$", |_, _, res| {
+        if let Err(e) = ResponseBuilder::moved_permanently().respond(res) {
+            res.status(StatusCode::INTERNAL_SERVER_ERROR).body(e.to_string());
+        }
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let mut middlewares = MiddlewareStack::new();
+    middlewares.apply(SecurityHeadersMiddleware::new(), vec!("/"), None);
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(middlewares).build());
+
+    let res = client.get("/items").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(res.headers_map()[header::CACHE_CONTROL], "max-age=60");
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/json");
+    assert_eq!(res.get_body(), br#"["pen","ink"]"#.to_vec());
+    // The headers set by the middlewares before the handler are kept
+    assert!(res.headers_map().contains_key("x-content-type-options"));
+
+    let res = client.get("/old").send();
+    assert_eq!(res.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.get_body(), b"A 301 Moved Permanently response must have a Location".to_vec());
+
+    let moved = ResponseBuilder::moved_permanently().location("/a").location("/b").build().unwrap();
+    assert_eq!(moved.headers_map().get_all(header::LOCATION).iter().collect::<Vec<_>>(), vec!["/b"]);
+    assert!(ResponseBuilder::new(StatusCode::MULTIPLE_CHOICES).text("pick one").build().is_ok());
+    assert!(ResponseBuilder::no_content().build().is_ok());
+
+    match ResponseBuilder::not_modified().header(header::ETAG, "\"v1\"").text("stale").build() {
+        Err(ResponseBuildError::BodyNotAllowed(status)) => assert_eq!(status, StatusCode::NOT_MODIFIED),
+        other => panic!("unexpected {:?}", other.map(|res| res.get_status())),
+    }
+    match ResponseBuilder::created().header("bad header", "v").location("/items/1").build() {
+        Err(ResponseBuildError::InvalidHeader(_)) => {}
+        other => panic!("unexpected {:?}", other.map(|res| res.get_status())),
+    }
+}
+
+#[test]
+fn malformed_requests() {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    fn exchange(addr: SocketAddr, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let _ = stream.write_all(request);
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        String::from_utf8_lossy(&response).to_lowercase()
+    }
+
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/invalid$", |_, _, res| { res.status(StatusCode::BAD_REQUEST).body("handler"); });
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let policy = MalformedRequestPolicy::respond(|request| {
+            let mut res = SyncResponse::new();
+            res.status(request.status()).header(header::CONTENT_TYPE, "text/plain").body(request.kind().to_string());
+            res
+        })
+        .ban(3, Duration::from_secs(60), Duration::from_secs(60));
+    let server = Server::builder().router(router).malformed_requests(policy).build().spawn_test().unwrap();
+
+    // The requests answered by the handlers with 400 are left alone
+    let response = exchange(server.addr(), b"GET /invalid HTTP/1.1\r\nHost: test\r\n\r\nNOT HTTP\r\n\r\n");
+    let (first, second) = response.split_at(response.find("handler").unwrap() + "handler".len());
+    assert!(first.starts_with("http/1.1 400 bad request"));
+    assert!(second.starts_with("http/1.1 400 bad request"));
+    assert!(second.contains("connection: close"));
+    assert!(second.ends_with("\r\n\r\ninvalid request line"));
+
+    let response = exchange(server.addr(), b"POST /invalid HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: gzip\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\nunsupported transfer-encoding"));
+
+    // The third malformed request bans the client, though it is still answered
+    let response = exchange(server.addr(), b"GET /invalid HTTP/1.1\r\nHost: test\r\nNo colon\r\n\r\n");
+    assert!(response.ends_with("\r\n\r\ninvalid header"));
+    assert_eq!(exchange(server.addr(), b"GET /invalid HTTP/1.1\r\nHost: test\r\n\r\n"), "");
+    server.shutdown().unwrap();
+
+    let server = Server::builder().router(Router::new()).malformed_requests(MalformedRequestPolicy::close()).build().spawn_test().unwrap();
+    assert_eq!(exchange(server.addr(), b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").lines().next(), Some("http/1.1 404 not found"));
+    assert_eq!(exchange(server.addr(), b"GET /\x01 HTTP/1.1\r\n\r\n"), "");
+    server.shutdown().unwrap();
+}
@@ -0,0 +1,52 @@
+extern crate futures;
+extern crate saphir;
+extern crate tower_service;
+
+use futures::{Async, Future, Poll, Stream};
+use saphir::*;
+use tower_service::Service;
+
+/// A service wrapping another one, the way a `tower` layer does, counting the requests going through it
+struct Counted<S> {
+    inner: S,
+    count: usize,
+}
+
+impl<S: Service<Request<Body>>> Service<Request<Body>> for Counted<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), S::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> S::Future {
+        self.count += 1;
+        self.inner.call(req)
+    }
+}
+
+#[test]
+fn tower_service() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/echo$", |_, req, res| {
+        let peer = req.peer_addr().unwrap();
+        res.body(format!("{} from {}", String::from_utf8_lossy(req.body()), peer));
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+
+    let server = Server::builder().router(router).build();
+    let mut service = Counted { inner: server.service(([10, 0, 0, 2], 5000).into()), count: 0 };
+
+    assert_eq!(service.poll_ready().unwrap(), Async::Ready(()));
+    let request = Request::post("/echo").body(Body::from("hello")).unwrap();
+    let response = service.call(request).wait().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(service.count, 1);
+
+    let body = response.into_body().concat2().wait().unwrap();
+    assert_eq!(&body[..], b"hello from 10.0.0.2:5000");
+}
@@ -0,0 +1,130 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::fs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+fn header(res: &SyncResponse, name: &str) -> String {
+    res.headers_map().get(name).map(|value| value.to_str().unwrap().to_string()).unwrap_or_default()
+}
+
+#[test]
+fn resumable_uploads() {
+    let completed = Arc::new(Mutex::new(Vec::new()));
+    let completed_c = completed.clone();
+    let uploads = TusController::new("/files", MemoryTusStorage::new())
+        .max_size(1024)
+        .on_complete(move |upload, storage| {
+            completed_c.lock().unwrap().push((upload.metadata["filename"].clone(), storage.read(&upload.id).unwrap()));
+        });
+
+    let mut router = Router::new();
+    router.add("^/files(/|$)", uploads);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.request(Method::OPTIONS, "/files").send();
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&res, "tus-version"), "1.0.0");
+    assert_eq!(header(&res, "tus-max-size"), "1024");
+
+    assert_eq!(client.post("/files").header("upload-length", "11").send().get_status(), StatusCode::PRECONDITION_FAILED);
+    let res = client.post("/files").header("tus-resumable", "1.0.0").header("upload-length", "2048").send();
+    assert_eq!(res.get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // "hello.txt" and "text/plain" in base64
+    let res = client.post("/files")
+        .header("tus-resumable", "1.0.0")
+        .header("upload-length", "11")
+        .header("upload-metadata", "filename aGVsbG8udHh0,filetype dGV4dC9wbGFpbg==,public")
+        .send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(header(&res, "tus-resumable"), "1.0.0");
+    assert!(!header(&res, "upload-expires").is_empty());
+    let location = header(&res, "location");
+    assert!(location.starts_with("/files/"));
+
+    let patch = |offset: &str, chunk: &str| {
+        client.patch(&location)
+            .header("tus-resumable", "1.0.0")
+            .header("content-type", "application/offset+octet-stream")
+            .header("upload-offset", offset)
+            .body(chunk)
+            .send()
+    };
+
+    let res = patch("0", "hello ");
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&res, "upload-offset"), "6");
+
+    // A client which lost the answer resumes from the offset the server reached
+    assert_eq!(patch("0", "hello ").get_status(), StatusCode::CONFLICT);
+    let res = client.request(Method::HEAD, &location).header("tus-resumable", "1.0.0").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert_eq!(header(&res, "upload-offset"), "6");
+    assert_eq!(header(&res, "upload-length"), "11");
+    assert_eq!(header(&res, "cache-control"), "no-store");
+    assert_eq!(header(&res, "upload-metadata"), "filename aGVsbG8udHh0,filetype dGV4dC9wbGFpbg==,public");
+
+    assert_eq!(patch("6", "world and more").get_status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let res = client.patch(&location)
+        .header("tus-resumable", "1.0.0")
+        .header("upload-offset", "6")
+        .body("world")
+        .send();
+    assert_eq!(res.get_status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let res = patch("6", "world");
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    assert_eq!(header(&res, "upload-offset"), "11");
+    assert!(header(&res, "upload-expires").is_empty());
+    assert_eq!(*completed.lock().unwrap(), vec![("hello.txt".to_string(), b"hello world".to_vec())]);
+
+    let res = client.delete(&location).header("tus-resumable", "1.0.0").send();
+    assert_eq!(res.get_status(), StatusCode::NO_CONTENT);
+    let res = client.request(Method::HEAD, &location).header("tus-resumable", "1.0.0").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+    let res = client.request(Method::HEAD, "/files/../secret").header("tus-resumable", "1.0.0").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn expired_uploads() {
+    let root = std::env::temp_dir().join(format!("saphir-tus-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    let uploads = TusController::new("/files", FileTusStorage::new(&root).unwrap()).expiration(Duration::from_millis(0));
+    let storage = FileTusStorage::new(&root).unwrap();
+    let upload = TusUpload {
+        id: "expired".to_string(),
+        length: 4,
+        offset: 0,
+        metadata: Default::default(),
+        expires: Some(std::time::SystemTime::now()),
+    };
+    storage.create(&upload).unwrap();
+    assert_eq!(storage.append("expired", 0, b"ab").unwrap(), 2);
+    assert_eq!(storage.get("expired").unwrap().offset, 2);
+    assert_eq!(storage.read("expired").unwrap(), b"ab".to_vec());
+
+    let completed = TusUpload { id: "completed".to_string(), length: 0, ..upload };
+    storage.create(&completed).unwrap();
+
+    assert_eq!(uploads.remove_expired().unwrap(), 1);
+    assert!(storage.get("expired").is_err());
+    assert!(!storage.path("expired").exists());
+    assert!(storage.get("completed").is_ok());
+
+    let mut router = Router::new();
+    router.add("^/files(/|$)", uploads);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.post("/files").header("tus-resumable", "1.0.0").header("upload-length", "4").send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    let res = client.request(Method::HEAD, &header(&res, "location")).header("tus-resumable", "1.0.0").send();
+    assert_eq!(res.get_status(), StatusCode::NOT_FOUND);
+
+    let _ = fs::remove_dir_all(&root);
+}
@@ -0,0 +1,238 @@
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate saphir;
+
+use saphir::*;
+use saphir::regex::Regex;
+use saphir::test::TestClient;
+use serde_json::Value;
+
+#[derive(Deserialize)]
+struct Address {
+    city: String,
+}
+
+impl Validate for Address {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.length("city", &self.city, Some(1), None);
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+struct Signup {
+    name: String,
+    email: String,
+    age: u32,
+    addresses: Vec<Address>,
+}
+
+impl Validate for Signup {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let email = Regex::new("^[^@]+@[^@]+$").unwrap();
+        let mut errors = ValidationErrors::new();
+        errors.length("name", &self.name, Some(2), Some(32))
+            .pattern("email", &self.email, &email)
+            .range("age", self.age, Some(18), Some(130))
+            .check("name", self.name != "admin", "reserved", "is reserved")
+            .nested("addresses", self.addresses.validate());
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+struct Page {
+    number: u32,
+    size: Option<u32>,
+    verbose: bool,
+}
+
+impl Validate for Page {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.range("size", self.size.unwrap_or(10), Some(1), Some(100));
+        errors.into_result()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Comment {
+    author: String,
+    text: String,
+}
+
+impl Validate for Comment {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.length("text", &self.text, Some(1), Some(280));
+        errors.into_result()
+    }
+}
+
+fn client() -> TestClient {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/signup$", |_, req, res| {
+        match Validated::<Signup>::from_json(req) {
+            Ok(signup) => { res.status(StatusCode::CREATED).body(signup.name.clone()); }
+            Err(rejection) => { res.rejection(&rejection); }
+        }
+    });
+    controller.add(Method::GET, "^/pages$", |_, req, res| {
+        match Validated::<Page>::from_query(req) {
+            Ok(page) => { res.body(format!("{} {:?} {}", page.number, page.size, page.verbose)); }
+            Err(rejection) => { res.rejection(&rejection); }
+        }
+    });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    TestClient::new(Server::builder().router(router).build())
+}
+
+#[test]
+fn validated_json_bodies() {
+    let client = client();
+
+    let res = client.post("/signup").json(&json!({ "name": "jane", "email": "jane@example.com", "age": 30, "addresses": [] })).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+
+    let res = client.post("/signup").json(&json!({
+        "name": "admin", "email": "nope", "age": 12, "addresses": [{ "city": "Montreal" }, { "city": "" }]
+    })).send();
+    assert_eq!(res.get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(res.headers_map().get(header::CONTENT_TYPE).unwrap(), PROBLEM_CONTENT_TYPE);
+    assert_eq!(body, json!({
+        "type": "about:blank",
+        "title": "Unprocessable Entity",
+        "status": 422,
+        "detail": "4 field(s) failed validation",
+        "errors": [
+            { "field": "email", "code": "pattern", "message": "must match ^[^@]+@[^@]+$" },
+            { "field": "age", "code": "range", "message": "must be between 18 and 130" },
+            { "field": "name", "code": "reserved", "message": "is reserved" },
+            { "field": "addresses.[1].city", "code": "length", "message": "must be at least 1 characters long" },
+        ]
+    }));
+
+    let res = client.post("/signup").body("{").send();
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+    let body: Value = serde_json::from_slice(&res.get_body()).unwrap();
+    assert_eq!(body["errors"][0]["code"], "malformed");
+}
+
+#[test]
+fn validated_queries() {
+    let client = client();
+
+    assert_eq!(client.get("/pages?number=2&size=&verbose=true").send().get_body(), b"2 None true".to_vec());
+    assert_eq!(client.get("/pages?number=2&size=50&verbose=false").send().get_body(), b"2 Some(50) false".to_vec());
+    assert_eq!(client.get("/pages?number=2&size=500&verbose=false").send().get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(client.get("/pages?number=two&verbose=false").send().get_status(), StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn csrf_protected_forms() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::GET, "^/comments/new$", |_, req, res| {
+        res.status(StatusCode::OK).body(format!(r#"<form method="post" action="/comments">{}</form>"#, req.csrf_input()));
+    });
+    controller.add(Method::POST, "^/comments$", |_, req, res| {
+        match Validated::<Comment>::from_form(req) {
+            Ok(comment) => { res.status(StatusCode::CREATED).body(format!("{}: {}", comment.author, comment.text)); }
+            Err(rejection) => { res.rejection(&rejection); }
+        }
+    });
+    controller.add(Method::POST, "^/webhooks$", |_, _, res| { res.status(StatusCode::NO_CONTENT); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let mut stack = MiddlewareStack::new();
+    stack.apply(CsrfMiddleware::new(), vec!("^/"), Some(vec!("^/webhooks")));
+    let client = TestClient::new(Server::builder().router(router).middleware_stack(stack).build());
+
+    let res = client.get("/comments/new").send();
+    let cookie = res.headers_map()[header::SET_COOKIE].to_str().unwrap().to_string();
+    assert!(cookie.contains("SameSite=Strict") && !cookie.contains("HttpOnly"));
+    let cookie = cookie.split(';').next().unwrap().to_string();
+    let token = cookie.trim_start_matches("saphir_csrf=").to_string();
+    assert_eq!(token.len(), 32);
+    assert_eq!(res.get_body(), format!(r#"<form method="post" action="/comments"><input type="hidden" name="csrf_token" value="{}"></form>"#, token).into_bytes());
+
+    // Known tokens aren't replaced
+    let res = client.get("/comments/new").header(header::COOKIE, cookie.as_str()).send();
+    assert!(!res.headers_map().contains_key(header::SET_COOKIE));
+
+    let form = |body: String| client.post("/comments").header(header::CONTENT_TYPE, "application/x-www-form-urlencoded").body(body);
+
+    let res = form(format!("author=Jane+Doe&text=Hello%2C+world&csrf_token={}", token)).header(header::COOKIE, cookie.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(res.get_body(), b"Jane Doe: Hello, world".to_vec());
+
+    let res = form("author=Jane&text=".to_string()).header(header::COOKIE, cookie.as_str()).header("X-CSRF-Token", token.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Forged requests can't read the cookie to submit its token
+    assert_eq!(form("author=Eve&text=Hi".to_string()).header(header::COOKIE, cookie.as_str()).send().get_status(), StatusCode::FORBIDDEN);
+    let res = form("author=Eve&text=Hi&csrf_token=00000000000000000000000000000000".to_string()).header(header::COOKIE, cookie.as_str()).send();
+    assert_eq!(res.get_status(), StatusCode::FORBIDDEN);
+    assert_eq!(form(format!("author=Eve&text=Hi&csrf_token={}", token)).send().get_status(), StatusCode::FORBIDDEN);
+
+    let res = client.post("/comments").header(header::COOKIE, cookie.as_str()).header("X-CSRF-Token", token.as_str()).body("{}").send();
+    assert_eq!(res.get_status(), StatusCode::BAD_REQUEST);
+
+    assert_eq!(client.post("/webhooks").send().get_status(), StatusCode::NO_CONTENT);
+}
+
+#[test]
+fn json_schema_guard() {
+    let schema = JsonSchema::new(json!({
+        "type": "object",
+        "definitions": {
+            "tag": { "type": "string", "pattern": "^[a-z]+$" }
+        },
+        "properties": {
+            "name": { "type": "string", "minLength": 2 },
+            "age": { "type": "integer", "minimum": 0 },
+            "tags": { "type": "array", "items": { "$ref": "#/definitions/tag" }, "uniqueItems": true },
+            "role": { "enum": ["admin", "user"] }
+        },
+        "required": ["name", "role"],
+        "additionalProperties": false
+    })).unwrap();
+
+    let mut guards = RequestGuardCollection::new();
+    guards.add(JsonSchemaGuard::new(schema));
+
+    let mut controller = BasicController::new(());
+    controller.add_with_guards(Method::POST, "^/users$", guards, |_, _, res| { res.status(StatusCode::CREATED); });
+
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.post("/users").json(&json!({ "name": "jane", "age": 30, "tags": ["a", "b"], "role": "user" })).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+
+    let res = client.post("/users").json(&json!({ "name": "j", "age": 1.5, "tags": ["a", "B", "a"], "extra": true })).send();
+    assert_eq!(res.get_status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = serde_json::from_slice(&res.get_body()).unwrap();
+    let errors: Vec<(&str, &str)> = body["errors"].as_array().unwrap().iter()
+        .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(errors, vec![
+        ("/role", "required"),
+        ("/age", "type"),
+        ("/extra", "additionalProperties"),
+        ("/name", "minLength"),
+        ("/tags/1", "pattern"),
+        ("/tags", "uniqueItems"),
+    ]);
+
+    assert_eq!(client.post("/users").body("not json").send().get_status(), StatusCode::BAD_REQUEST);
+    assert!(JsonSchema::new(json!({ "pattern": "(" })).is_err());
+}
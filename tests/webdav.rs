@@ -0,0 +1,103 @@
+extern crate saphir;
+
+use saphir::*;
+use saphir::test::TestClient;
+use std::fs;
+
+fn client(name: &str) -> TestClient {
+    let root = std::env::temp_dir().join(format!("saphir-webdav-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+
+    let mut router = Router::new();
+    router.add("^/dav(/|$)", WebDavController::new("/dav", LocalFileSystem::new(root)));
+    TestClient::new(Server::builder().router(router).build())
+}
+
+fn method(name: &str) -> Method {
+    Method::from_bytes(name.as_bytes()).unwrap()
+}
+
+fn body(res: &SyncResponse) -> String {
+    String::from_utf8_lossy(&res.get_body()).into_owned()
+}
+
+#[test]
+fn collections_and_properties() {
+    let client = client("props");
+
+    assert_eq!(client.request(method("MKCOL"), "/dav/docs").send().get_status(), StatusCode::CREATED);
+    assert_eq!(client.request(method("MKCOL"), "/dav/docs").send().get_status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(client.request(method("MKCOL"), "/dav/missing/docs").send().get_status(), StatusCode::CONFLICT);
+    assert_eq!(client.put("/dav/docs/a%20b.txt").body("hello").send().get_status(), StatusCode::CREATED);
+    assert_eq!(client.get("/dav/docs/a%20b.txt").send().get_body(), b"hello".to_vec());
+
+    let res = client.request(method("PROPFIND"), "/dav/docs").header("depth", "1").send();
+    assert_eq!(res.get_status(), StatusCode::MULTI_STATUS);
+    let listing = body(&res);
+    assert!(listing.contains("<D:href>/dav/docs/</D:href>"));
+    assert!(listing.contains("<D:resourcetype><D:collection/></D:resourcetype>"));
+    assert!(listing.contains("<D:href>/dav/docs/a%20b.txt</D:href>"));
+    assert!(listing.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+    let res = client.request(method("PROPPATCH"), "/dav/docs/a%20b.txt").body(r#"<?xml version="1.0"?>
+        <D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:example"><D:set><D:prop><Z:author>Jane &amp; co</Z:author></D:prop></D:set></D:propertyupdate>"#).send();
+    assert_eq!(res.get_status(), StatusCode::MULTI_STATUS);
+    assert!(body(&res).contains("HTTP/1.1 200 OK"));
+
+    let res = client.request(method("PROPPATCH"), "/dav/docs/a%20b.txt").body(r#"<D:propertyupdate xmlns:D="DAV:" xmlns:Z="urn:example">
+        <D:set><D:prop><D:getetag>x</D:getetag><Z:title>t</Z:title></D:prop></D:set></D:propertyupdate>"#).send();
+    assert!(body(&res).contains("HTTP/1.1 403 Forbidden"));
+    assert!(body(&res).contains("HTTP/1.1 424 Failed Dependency"));
+
+    let res = client.request(method("COPY"), "/dav/docs/a%20b.txt").header("destination", "http://localhost/dav/copy.txt").send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+
+    let res = client.request(method("PROPFIND"), "/dav/copy.txt").header("depth", "0")
+        .body(r#"<D:propfind xmlns:D="DAV:"><D:prop><author xmlns="urn:example"/><title xmlns="urn:example"/></D:prop></D:propfind>"#).send();
+    let props = body(&res);
+    assert!(props.contains(r#"<author xmlns="urn:example">Jane &amp; co</author>"#));
+    assert!(props.contains(r#"<title xmlns="urn:example"/></D:prop><D:status>HTTP/1.1 404 Not Found"#));
+
+    let res = client.request(method("MOVE"), "/dav/copy.txt").header("destination", "/dav/docs/a%20b.txt").header("overwrite", "F").send();
+    assert_eq!(res.get_status(), StatusCode::PRECONDITION_FAILED);
+    let res = client.request(method("MOVE"), "/dav/copy.txt").header("destination", "/dav/docs/moved.txt").send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(client.get("/dav/copy.txt").send().get_status(), StatusCode::NOT_FOUND);
+
+    assert_eq!(client.delete("/dav/docs").send().get_status(), StatusCode::NO_CONTENT);
+    assert_eq!(client.request(method("PROPFIND"), "/dav/docs").send().get_status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn locks() {
+    let client = client("locks");
+    let lockinfo = r#"<D:lockinfo xmlns:D="DAV:"><D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>
+        <D:owner><D:href>mailto:jane@example.com</D:href></D:owner></D:lockinfo>"#;
+
+    let res = client.request(method("LOCK"), "/dav/file.txt").header("timeout", "Second-60").body(lockinfo).send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    let token = res.headers_map().get("lock-token").unwrap().to_str().unwrap().to_string();
+    assert!(body(&res).contains("<D:owner>mailto:jane@example.com</D:owner><D:timeout>Second-60</D:timeout>"));
+
+    assert_eq!(client.request(method("LOCK"), "/dav/file.txt").body(lockinfo).send().get_status(), StatusCode::LOCKED);
+    assert_eq!(client.put("/dav/file.txt").body("hello").send().get_status(), StatusCode::LOCKED);
+    assert_eq!(client.delete("/dav/file.txt").send().get_status(), StatusCode::LOCKED);
+
+    let condition = format!("({})", token);
+    assert_eq!(client.put("/dav/file.txt").header("if", condition.as_str()).body("hello").send().get_status(), StatusCode::NO_CONTENT);
+
+    let res = client.request(method("PROPFIND"), "/dav/file.txt").header("depth", "0").send();
+    assert!(body(&res).contains(&format!("<D:locktoken><D:href>{}</D:href></D:locktoken>", token.trim_matches(|c| c == '<' || c == '>'))));
+
+    let res = client.request(method("LOCK"), "/dav/file.txt").header("if", condition.as_str()).header("timeout", "Second-120").send();
+    assert_eq!(res.get_status(), StatusCode::OK);
+    assert!(body(&res).contains("Second-120"));
+
+    assert_eq!(client.request(method("UNLOCK"), "/dav/file.txt").header("lock-token", "<urn:uuid:unknown>").send().get_status(), StatusCode::CONFLICT);
+    assert_eq!(client.request(method("UNLOCK"), "/dav/file.txt").header("lock-token", token.as_str()).send().get_status(), StatusCode::NO_CONTENT);
+    assert_eq!(client.delete("/dav/file.txt").send().get_status(), StatusCode::NO_CONTENT);
+
+    let res = client.request(Method::OPTIONS, "/dav/").send();
+    assert_eq!(res.headers_map().get("dav").unwrap(), "1, 2");
+}
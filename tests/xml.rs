@@ -0,0 +1,45 @@
+extern crate saphir;
+#[macro_use]
+extern crate serde_derive;
+
+use saphir::*;
+use saphir::test::TestClient;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Item {
+    name: String,
+    quantity: u32,
+}
+
+#[test]
+fn xml_bodies() {
+    let mut controller = BasicController::new(());
+    controller.add(Method::POST, "^/items$", |_, req, res| {
+        match req.body_xml::<Item>() {
+            Ok(mut item) => {
+                item.quantity += 1;
+                res.status(StatusCode::CREATED).xml(&item);
+            }
+            Err(_) => { res.status(StatusCode::BAD_REQUEST); }
+        }
+    });
+    controller.add(Method::GET, "^/negotiated$", |_, req, res| {
+        res.status(StatusCode::OK).negotiate(req, &Item { name: "pen".to_string(), quantity: 2 });
+    });
+    let mut router = Router::new();
+    router.add("^/", controller);
+    let client = TestClient::new(Server::builder().router(router).build());
+
+    let res = client.post("/items").body("<Item><name>pen</name><quantity>1</quantity></Item>").send();
+    assert_eq!(res.get_status(), StatusCode::CREATED);
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/xml");
+    let item: Item = SyncRequest::new(Request::new(()).into_parts().0, res.get_body()).body_xml().unwrap();
+    assert_eq!(item, Item { name: "pen".to_string(), quantity: 2 });
+
+    assert_eq!(client.post("/items").body("<Item>").send().get_status(), StatusCode::BAD_REQUEST);
+
+    let res = client.get("/negotiated").header(header::ACCEPT, "application/xml, application/json;q=0.5").send();
+    assert_eq!(res.headers_map()[header::CONTENT_TYPE], "application/xml");
+    let res = client.get("/negotiated").header(header::ACCEPT, "application/json").send();
+    assert_eq!(res.get_body(), br#"{"name":"pen","quantity":2}"#.to_vec());
+}